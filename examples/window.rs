@@ -1,6 +1,7 @@
 use gag::Redirect;
 use mudterm::error::{Error, Result};
 use mudterm::proto::{Parser, Element};
+use mudterm::ui::caps::TermCaps;
 use mudterm::ui::buffer::{Buffer, BufferVec};
 use mudterm::ui::layout::Rect;
 use mudterm::ui::line::{Line, RawLine, RawLines};
@@ -105,7 +106,7 @@ fn run6() -> Result<()> {
     let _stderr_redirect = Redirect::stderr(debuglog)
         .map_err(|e| Error::RuntimeError(format!("Redirect stderr error {}", e)))?;
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     let (width, height) = termion::terminal_size()?;
     let flowarea = Rect {
         x: 1,
@@ -144,7 +145,7 @@ fn run5() -> Result<()> {
     };
 
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     // let mut buf = String::new();
     let (width, height) = termion::terminal_size()?;
     let flowarea = Rect {
@@ -162,7 +163,7 @@ fn run5() -> Result<()> {
     }
 
     terminal.render_widget(&mut flow, flowarea)?;
-    // let (cursor_x, cursor_y) = cmdbar.cursor_pos(area, true);
+    // let (cursor_x, cursor_y) = cmdbar.cursor_pos(area);
     terminal.flush(vec![flowarea])?;
     terminal.set_cursor(1, height)?;
 
@@ -199,7 +200,7 @@ fn run4() -> Result<()> {
         .unwrap();
 
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     // let mut buf = String::new();
     let (width, height) = termion::terminal_size()?;
     let mut cmdbar = CmdBar::new('.', true, 200);
@@ -210,7 +211,7 @@ fn run4() -> Result<()> {
         height: 3,
     };
     terminal.render_widget(&mut cmdbar, area)?;
-    let (cursor_x, cursor_y) = cmdbar.cursor_pos(area, true);
+    let (cursor_x, cursor_y) = cmdbar.cursor_pos(area);
     terminal.flush(vec![Rect {
         x: 1,
         y: 1,
@@ -233,7 +234,7 @@ fn run4() -> Result<()> {
         }
         terminal.render_widget(&mut cmdbar, area)?;
         terminal.flush(vec![area])?;
-        let (cursor_x, cursor_y) = cmdbar.cursor_pos(area, true);
+        let (cursor_x, cursor_y) = cmdbar.cursor_pos(area);
         terminal.set_cursor(cursor_x, cursor_y)?;
     }
     Ok(())
@@ -247,7 +248,7 @@ fn run3() -> Result<()> {
         .map_err(|e| Error::RuntimeError(format!("Redirect stderr error {}", e)))?;
 
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     // let mut buf = String::new();
     let (width, height) = termion::terminal_size()?;
 
@@ -333,7 +334,7 @@ fn run2() -> Result<()> {
         .map_err(|e| Error::RuntimeError(format!("Redirect stderr error {}", e)))?;
 
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     // let mut buf = String::new();
     let (width, height) = termion::terminal_size()?;
 
@@ -391,7 +392,7 @@ fn run1() -> Result<()> {
         .map_err(|e| Error::RuntimeError(format!("Redirect stderr error {}", e)))?;
 
     let stdin = stdin();
-    let mut terminal = Terminal::init()?;
+    let mut terminal = Terminal::init(TermCaps::detect())?;
     let (width, height) = terminal_size()?;
     let mut buf = String::new();
     // let mut lines = RawLines::unbounded();
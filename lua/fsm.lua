@@ -0,0 +1,161 @@
+-- 有限状态机，用于编写任务脚本
+--
+-- 用法：
+-- local m = fsm.new{
+--     name="quest",
+--     initial="idle",
+--     states={
+--         idle={
+--             events={
+--                 {pattern="^你接受了任务", to="walking"},
+--             },
+--         },
+--         walking={
+--             on_enter=function(m) Send("north") end,
+--             on_exit=function(m) end,
+--             timeout=10,
+--             on_timeout="idle",
+--             events={
+--                 {pattern="^你到达了(.*)$", action=function(m, line, wildcards)
+--                     return "done"
+--                 end},
+--             },
+--         },
+--         done={},
+--     },
+-- }
+-- m:start()
+--
+-- 每个状态的事件触发器属于独立的触发器组"fsm-<name>-<state>"，
+-- 仅在状态机处于该状态时开启。
+fsm = {}
+
+local Machine = {}
+Machine.__index = Machine
+
+local state_group = function(m, state)
+    return "fsm-" .. m.name .. "-" .. state
+end
+
+local create_event = function(m, state, idx, event)
+    assert(type(event.pattern) == "string", "pattern of fsm event must be string")
+    assert(event.to or event.action, "fsm event must have either to or action")
+    if event.to then
+        assert(m.states[event.to], "unknown target state " .. tostring(event.to))
+    end
+    local name = state_group(m, state) .. "-" .. idx
+    local callback = function(_, line, wildcards, styles)
+        -- 禁用组后仍可能收到同一批次的文本，此处再次校验
+        if m.state ~= state then
+            return
+        end
+        local to = event.to
+        if event.action then
+            local ret = event.action(m, line, wildcards, styles)
+            if type(ret) == "string" then
+                to = ret
+            end
+        end
+        if to and m.state == state then
+            m:transit(to)
+        end
+    end
+    CreateTrigger(name, state_group(m, state), event.pattern, 0, event.match_lines or 1, callback)
+    table.insert(m.triggers, name)
+end
+
+-- 创建状态机
+-- 参数：
+-- name：名称，不可为空，用于生成触发器组名
+-- initial：初始状态，不可为空
+-- states：状态表，键为状态名，值可包含：
+--     on_enter：进入状态时调用，参数为状态机及transit传入的额外参数
+--     on_exit：离开状态时调用，参数为状态机
+--     timeout：超时时间，单位为秒
+--     on_timeout：超时后切换的状态名，或回调函数（返回状态名则切换）
+--     events：事件列表，每项包含pattern、match_lines（可选），以及
+--             to（目标状态）或action（回调，返回状态名则切换）
+function fsm.new(args)
+    assert(type(args.name) == "string", "name of fsm must be string")
+    assert(type(args.states) == "table", "states of fsm must be table")
+    assert(args.states[args.initial], "initial state of fsm is invalid")
+    local m = setmetatable({
+        name=args.name,
+        initial=args.initial,
+        states=args.states,
+        state=nil,
+        triggers={},
+        -- 每次状态切换递增，用于使过期的超时回调失效
+        epoch=0,
+    }, Machine)
+    for state, def in pairs(m.states) do
+        for idx, event in ipairs(def.events or {}) do
+            create_event(m, state, idx, event)
+        end
+        EnableTriggerGroup(state_group(m, state), false)
+    end
+    return m
+end
+
+-- 进入初始状态
+function Machine:start(...)
+    self:transit(self.initial, ...)
+end
+
+-- 切换到指定状态，额外参数传入on_enter
+function Machine:transit(to, ...)
+    local def = assert(self.states[to], "unknown state " .. tostring(to))
+    self:leave()
+    self.state = to
+    EnableTriggerGroup(state_group(self, to), true)
+    if def.timeout then
+        local epoch = self.epoch
+        DoAfter(def.timeout * 1000, function()
+            if self.epoch == epoch and self.state == to then
+                self:on_timeout(def)
+            end
+        end)
+    end
+    if def.on_enter then
+        def.on_enter(self, ...)
+    end
+end
+
+function Machine:on_timeout(def)
+    local to = def.on_timeout
+    if type(to) == "function" then
+        to = to(self)
+    end
+    if type(to) == "string" then
+        self:transit(to)
+    end
+end
+
+-- 离开当前状态，调用on_exit并禁用该状态的触发器
+function Machine:leave()
+    self.epoch = self.epoch + 1
+    local from = self.state
+    if not from then
+        return
+    end
+    self.state = nil
+    EnableTriggerGroup(state_group(self, from), false)
+    local def = self.states[from]
+    if def.on_exit then
+        def.on_exit(self)
+    end
+end
+
+-- 停止状态机
+function Machine:stop()
+    self:leave()
+end
+
+-- 停止状态机并删除其所有触发器
+function Machine:destroy()
+    self:leave()
+    for _, name in ipairs(self.triggers) do
+        DeleteTrigger(name)
+    end
+    self.triggers = {}
+end
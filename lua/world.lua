@@ -2,8 +2,8 @@ world = {}
 
 local wrap_trigger_callback = function(callback)
     local wrapped = coroutine.wrap(callback)
-    return function(name, line, wildcards, styles)
-        return wrapped(name, line, wildcards, styles)
+    return function(name, line, wildcards, styles, ctx)
+        return wrapped(name, line, wildcards, styles, ctx)
     end
 end
 
@@ -20,9 +20,12 @@ local create_trigger = function(args)
     if not args.match_lines then
         args.match_lines = 1
     end
+    if args.context then
+        args.flags = args.flags + trigger_flag.Context
+    end
 
     local callback = wrap_trigger_callback(args.callback)
-    CreateTrigger(args.name, args.group, args.pattern, args.flags, args.match_lines, callback)
+    CreateTrigger(args.name, args.group, args.pattern, args.flags, args.match_lines, callback, args.style)
 end
 
 -- 创建触发器
@@ -39,7 +42,16 @@ end
 --       3) wildcards，正则捕获序列，实现为lua table，可使用数字
 --          或字符串下标进行取值。
 --       4) styles，文本格式，用于判断文本的颜色和特殊格式，仅支
---          持单行模式，多行模式下为空。
+--          持单行模式，多行模式下为空。其captures字段按捕获序号
+--          或组名给出各捕获区域中占比最多的格式（fg、bg、modifier）。
+--       5) ctx，执行上下文，仅当context为true时传入，包含lineno（行号）、
+--          time（毫秒时间戳）、source（world或prompt）、raw（原始文本）、
+--          text（整行文本）及labels（MXP标签片段）。
+-- context：是否向回调传入执行上下文，默认为false
+-- style：样式条件，仅单行匹配可用，匹配区域中占比最多的格式满足条件时才触发，
+--        如{fg="yellow", bg="black", modifier=1, capture="who"}，各项均可省略，
+--        颜色名称同styles中的fg、bg，modifier为必须包含的格式，capture为检查的
+--        捕获序号或组名，默认为整个匹配
 function world.create_trigger(args)
     args.flags = 0
     create_trigger(args)
@@ -52,6 +64,13 @@ function world.create_oneshot_trigger(args)
     create_trigger(args)
 end
 
+-- 创建提示符触发器
+-- 对未以换行结尾的文本（如提示符、问题）进行匹配，同一行只触发一次
+function world.create_prompt_trigger(args)
+    args.flags = trigger_flag.Prompt
+    create_trigger(args)
+end
+
 -- 删除触发器
 -- 参数name: 名称，不可为空
 function world.delete_trigger(name)
@@ -63,9 +82,19 @@ end
 -- 参数：
 -- 1. name，组名，不可为空
 -- 2. enabled，true开启/false禁用，默认为true
-function world.enable_trigger_group(group, enabled)
+-- 3. opts，可选，开启时的限制：ttl存活秒数，matches匹配次数，到期后自动禁用
+function world.enable_trigger_group(group, enabled, opts)
     enabled = enabled or true
-    EnableTriggerGroup(group, enabled)
+    EnableTriggerGroup(group, enabled, opts)
+end
+
+-- 观察触发器组，匹配时仅统计命中次数并保留最近的文本样本，不执行回调，可在#stats中查看
+-- 参数：
+-- 1. name，组名，不可为空
+-- 2. observe，true开启/false关闭，默认为true
+-- 3. samples，保留的样本数，默认为5
+function world.observe_trigger_group(group, observe, samples)
+    ObserveTriggerGroup(group, observe ~= false, samples)
 end
 
 local wrap_mxp_trigger_callback = function(callback)
@@ -191,6 +220,7 @@ end
 -- name：名称，不传值则自动生成唯一id
 -- group：组名，默认为"default"
 -- tick_time：周期时间，单位为秒
+-- fixed_delay：为true时下一周期从回调执行完毕时开始计时，默认按固定频率调度
 -- func：匹配成功的回调函数，不可为空，函数内部可使用协程相关指令，
 --       即，该函数传入后将包装为协程进行调用。
 --       该函数接受4个参数，按顺序为：
@@ -202,6 +232,9 @@ function world.create_timer(args)
     assert(type(args.tick_time) == "number", "tick time of timer must be number")
     args.tick_in_millis = args.tick_time * 1000
     args.flags = 0
+    if args.fixed_delay then
+        args.flags = timer_flag.FixedDelay
+    end
     create_timer(args)
 end
 
@@ -1,10 +1,14 @@
+use crate::conf::Config;
 use crate::error::Result;
-use crate::event::{Event, EventHandler, NextStep, QuitHandler};
+use crate::i18n;
+use crate::event::{Event, EventHandler, NextStep, QuitHandler, Sessions};
 use crate::proto::cli::Packet;
-use crate::runtime::{Engine, EngineAction, RuntimeOutput, RuntimeOutputHandler};
+use crate::runtime::bundle;
+use crate::runtime::{EngineAction, RuntimeOutput, RuntimeOutputHandler};
 use crate::signal;
 use crate::ui::line::Lines;
-use crate::ui::{Screen, UIEvent};
+use crate::ui::view::ScreenView;
+use crate::ui::{self, Screen, UIEvent, UISender};
 use crate::userinput;
 use crossbeam_channel::{unbounded, Sender};
 use std::{io, thread};
@@ -30,10 +34,13 @@ pub fn start_signal_handle(evttx: Sender<Event>) -> thread::JoinHandle<()> {
 /// 启动UI渲染的后台线程
 pub fn start_ui_handle(
     evttx: Sender<Event>,
-) -> Result<(Sender<UIEvent>, thread::JoinHandle<()>)> {
-    let (uitx, uirx) = unbounded::<UIEvent>();
+    config: &Config,
+    view: ScreenView,
+) -> Result<(UISender, thread::JoinHandle<()>)> {
+    let (uitx, uirx) = ui::ui_channel(ui::UI_OUTPUT_CAPACITY);
+    let config = config.clone();
     let handle = thread::spawn(move || {
-        let mut screen = match Screen::init(evttx.clone()) {
+        let mut screen = match Screen::init(evttx.clone(), &config, view) {
             Ok(screen) => screen,
             Err(e) => {
                 log::error!("failed to initialize screen {}", e);
@@ -110,18 +117,20 @@ pub fn start_from_server_handle(
 }
 
 pub struct Client {
-    uitx: Sender<UIEvent>,
+    uitx: UISender,
     srvtx: Sender<Packet>,
+    evttx: Sender<Event>,
 }
 
 impl Client {
-    pub fn new(uitx: Sender<UIEvent>, srvtx: Sender<Packet>) -> Self {
-        Self { uitx, srvtx }
+    pub fn new(uitx: UISender, srvtx: Sender<Packet>, evttx: Sender<Event>) -> Self {
+        Self { uitx, srvtx, evttx }
     }
 }
 
 impl EventHandler for Client {
-    fn on_event(&mut self, evt: Event, engine: &mut Engine) -> Result<NextStep> {
+    fn on_event(&mut self, evt: Event, sessions: &mut Sessions) -> Result<NextStep> {
+        let engine = sessions.active();
         match evt {
             Event::Quit => return Ok(NextStep::Quit),
             // 以下事件发送给UI线程处理
@@ -131,6 +140,9 @@ impl EventHandler for Client {
             Event::TerminalMouse(m) => {
                 self.uitx.send(UIEvent::Mouse(m))?;
             }
+            Event::PasteChoice(choices) => {
+                self.uitx.send(UIEvent::PasteChoice(choices))?;
+            }
             Event::WindowResize => {
                 self.uitx.send(UIEvent::WindowResize)?;
             }
@@ -141,6 +153,21 @@ impl EventHandler for Client {
             Event::UserOutput(output) => {
                 engine.push(EngineAction::ExecuteUserOutput(output));
             }
+            Event::KeyRead(key) => {
+                engine.push(EngineAction::KeyRead(key));
+            }
+            Event::MenuChosen(idx) => {
+                engine.push(EngineAction::MenuChosen(idx));
+            }
+            Event::ReplInput(code) => {
+                engine.push(EngineAction::EvalRepl(code));
+            }
+            Event::ReplCancel => {
+                engine.push(EngineAction::CancelRepl);
+            }
+            Event::BundleFetched(url, res) => {
+                engine.push(EngineAction::BundleFetched(url, res));
+            }
             Event::Timer(timer) => {
                 engine.push(EngineAction::ExecuteTimer(timer));
             }
@@ -155,11 +182,19 @@ impl EventHandler for Client {
             // client模式不支持客户端连接
             Event::NewClient(..)
             | Event::ClientAuthFail
-            | Event::ClientAuthSuccess(_)
+            | Event::ClientAuthSuccess(..)
             | Event::ClientDisconnect
             | Event::TelnetBytes(_)
-            | Event::WorldBytes(_)
-            | Event::WorldDisconnected => {
+            | Event::WorldBytes(..)
+            | Event::WorldProtocols(_)
+            | Event::WorldGmcp(_)
+            | Event::WorldMsdp(_)
+            | Event::WorldProbe(_)
+            | Event::WorldDisconnected(_)
+            | Event::WorldWriteError(..)
+            | Event::WorldReconnected(..)
+            | Event::WorldReconnectFailed(..)
+            | Event::Session(..) => {
                 unreachable!("standalone mode does not support event {:?}", evt);
             }
         }
@@ -168,14 +203,49 @@ impl EventHandler for Client {
 }
 
 impl RuntimeOutputHandler for Client {
-    fn on_runtime_output(&mut self, output: RuntimeOutput) -> Result<NextStep> {
+    fn on_runtime_output(&mut self, _: usize, output: RuntimeOutput, _: &mut Sessions) -> Result<NextStep> {
         match output {
             RuntimeOutput::ToServer(bs) => {
                 self.srvtx
                     .send(Packet::Text(String::from_utf8(bs).unwrap()))?;
             }
-            RuntimeOutput::ToUI(_, styled) => {
-                self.uitx.send(UIEvent::Lines(styled))?;
+            RuntimeOutput::ToUI(_, styled, lineno) => {
+                self.uitx.send(UIEvent::NumberedLines(lineno, styled))?;
+            }
+            RuntimeOutput::ToWindow(target, styled) => {
+                self.uitx.send(UIEvent::Window(target, styled))?;
+            }
+            RuntimeOutput::ToStatus(lines) => {
+                self.uitx.send(UIEvent::Status(lines))?;
+            }
+            RuntimeOutput::ReadKey(prompt) => {
+                self.uitx.send(UIEvent::ReadKey(prompt))?;
+            }
+            RuntimeOutput::ShowMenu(title, items) => {
+                self.uitx.send(UIEvent::Menu(title, items))?;
+            }
+            RuntimeOutput::FlashCmd(cmd) => {
+                self.uitx.send(UIEvent::FlashCmd(cmd))?;
+            }
+            RuntimeOutput::SecretInput(secret) => {
+                self.uitx.send(UIEvent::SecretInput(secret))?;
+            }
+            RuntimeOutput::ToRepl(lines) => {
+                self.uitx.send(UIEvent::Repl(lines))?;
+            }
+            RuntimeOutput::UpdateTerm(term) => {
+                self.uitx.send(UIEvent::UpdateTerm(term))?;
+            }
+            // 服务器连接由mudterm服务器维护
+            RuntimeOutput::Reconnect => log::warn!("reconnect is not supported in client mode"),
+            RuntimeOutput::FetchBundle(url) => {
+                let evttx = self.evttx.clone();
+                bundle::spawn_fetch(url, move |evt| {
+                    let _ = evttx.send(evt);
+                });
+            }
+            RuntimeOutput::ManageSession(_) => {
+                self.uitx.send(UIEvent::Lines(Lines::fmt_err(i18n::tr("session.unsupported"))))?;
             }
         }
         Ok(NextStep::Run)
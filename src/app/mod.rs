@@ -4,42 +4,35 @@ pub mod standalone;
 
 use crate::auth;
 use crate::conf::Config;
+use crate::datadir::DataDir;
 use crate::error::Result;
-use crate::event::EventLoop;
+use crate::event::{self, EventLoop};
 use crate::runtime::Engine;
 use client::{Client, QuitClient};
 use crossbeam_channel::unbounded;
 use server::{QuitServer, Server};
 use standalone::{QuitStandalone, Standalone};
-use std::fs::File;
 use std::net::{TcpListener, TcpStream};
-use std::time::Duration;
+use std::sync::Arc;
 
 /// standalone app
 pub fn standalone(config: Config) -> Result<()> {
     let (evttx, evtrx) = unbounded();
     // let world_addr = config.world.addr.clone();
-    let serverlog = File::create(&config.server.log_file)?;
+    let data_dir = DataDir::new(&config);
+    data_dir.create_all()?;
 
     // 1. init runtime
     log::info!("initilizing runtime with config");
     let mut engine = Engine::new(&config);
-    engine.set_logger(serverlog);
+    engine.open_log(&config.server.log_file)?;
     engine.init()?;
 
-    // 2. connect to mud
+    // 2. connect to mud and start io threads
+    // 世界连接及定时器的事件标记为首个会话
     log::info!("connecting to world {}", &config.world.addr);
-    let (from_mud, to_mud) = {
-        let from_mud = server::connect_world(&config.world.addr, Duration::from_secs(3))?;
-        let to_mud = from_mud.try_clone()?;
-        (from_mud, to_mud)
-    };
-
-    // 3. start io threads
-    log::info!("starting thread handling message to mud server");
-    let worldtx = server::start_to_mud_handle(evttx.clone(), to_mud);
-    log::info!("starting thread handling message from mud server");
-    server::start_from_mud_handle(evttx.clone(), from_mud);
+    let sesstx = event::session_channel(evttx.clone(), 0);
+    let world = server::WorldLink::connect(&config, sesstx.clone())?;
 
     // 4. start userinput thread
     log::info!("starting thread handling keyboard and mouse events");
@@ -51,14 +44,15 @@ pub fn standalone(config: Config) -> Result<()> {
 
     // 6. start ui thread
     log::info!("starting thread handling user interface");
-    let (uitx, uihandle) = client::start_ui_handle(evttx.clone())?;
+    let (uitx, uihandle) = client::start_ui_handle(evttx.clone(), &config, engine.screen_view())?;
 
     // 7. start timer thread
     log::info!("starting thread handling timer");
-    let _ = engine.spawn_timer(evttx); 
+    let _ = engine.spawn_timer(sesstx);
+    let _ = engine.spawn_exporter()?;
 
     // 8. run event loop on main thread
-    let standalone_handler = Standalone::new(uitx, worldtx);
+    let standalone_handler = Standalone::new(uitx, world, config, evttx);
     let quit_handler = QuitStandalone::new(uihandle);
     let eventloop = EventLoop::new(engine, evtrx, standalone_handler, quit_handler);
     eventloop.run()?;
@@ -71,12 +65,13 @@ pub fn client(config: Config) -> Result<()> {
     let (evttx, evtrx) = unbounded();
     let server_addr = config.client.server_addr.clone();
     let server_pass = config.client.server_pass.clone();
-    let clientlog = File::create(&config.client.log_file)?;
+    let data_dir = DataDir::new(&config);
+    data_dir.create_all()?;
 
     // 1. init runtime
     log::info!("initilizing runtime with config");
     let mut engine = Engine::new(&config);
-    engine.set_logger(clientlog);
+    engine.open_log(&config.client.log_file)?;
     engine.init()?;
 
     // 2. connect to server
@@ -104,14 +99,14 @@ pub fn client(config: Config) -> Result<()> {
 
     // 6. start ui thread
     log::info!("starting thread handling user interface");
-    let (uitx, uihandle) = client::start_ui_handle(evttx.clone())?;
+    let (uitx, uihandle) = client::start_ui_handle(evttx.clone(), &config, engine.screen_view())?;
 
     // 7. start timer thread
     log::info!("starting thread handling timer");
-    let _ = engine.spawn_timer(evttx); 
+    let _ = engine.spawn_timer(evttx.clone());
 
     // 8. run event loop on main thread
-    let client_handler = Client::new(uitx, srvtx);
+    let client_handler = Client::new(uitx, srvtx, evttx);
     let quit_handler = QuitClient::new(uihandle);
     let eventloop = EventLoop::new(engine, evtrx, client_handler, quit_handler);
     eventloop.run()?;
@@ -122,43 +117,42 @@ pub fn client(config: Config) -> Result<()> {
 /// server app
 pub fn server(config: Config) -> Result<()> {
     let (evttx, evtrx) = unbounded();
-    let world_addr = config.world.addr.clone();
-    let server_port = config.server.port;
     let pass = config.server.pass.clone();
     let init_max_lines = config.server.client_init_max_lines;
-    let serverlog = File::create(&config.server.log_file)?;
+    let data_dir = DataDir::new(&config);
+    data_dir.create_all()?;
 
     // 1. init runtime
     log::info!("initilizing runtime with config");
     let mut engine = Engine::new(&config);
-    engine.set_logger(serverlog);
+    engine.open_log(&config.server.log_file)?;
     engine.init()?;
 
     // 2. connect to mud
-    log::info!("connecting to mud server {:?}", world_addr);
-    let (from_mud, to_mud) = {
-        let from_mud = TcpStream::connect(world_addr)?;
-        let to_mud = from_mud.try_clone()?;
-        (from_mud, to_mud)
-    };
+    log::info!("connecting to mud server {:?}", &config.world.addr);
+    let world = server::WorldLink::connect(&config, evttx.clone())?;
 
     // 3. start server thread
-    log::info!("start thread to bind local port {}", server_port);
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", config.server.port))?;
-    server::start_server_listener_handle(listener, evttx.clone());
-
-    // 4. start io threads for mud communication
-    log::info!("starting thread handling message to mud server");
-    let worldtx = server::start_to_mud_handle(evttx.clone(), to_mud);
-    log::info!("starting thread handling message from mud server");
-    server::start_from_mud_handle(evttx.clone(), from_mud);
-
-    // 5. start timer thread
+    let allow = config
+        .server
+        .allow
+        .iter()
+        .map(|s| server::Cidr::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    let allow = Arc::new(allow);
+    for addr in server::listen_addrs(&config.server)? {
+        log::info!("start thread to bind local address {}", addr);
+        let listener = TcpListener::bind(addr)?;
+        server::start_server_listener_handle(listener, allow.clone(), evttx.clone());
+    }
+
+    // 4. start timer thread
     log::info!("starting thread handling timer");
     let _ = engine.spawn_timer(evttx.clone()); 
+    let _ = engine.spawn_exporter()?;
 
-    // 6. run event loop on main thread
-    let server_handler = Server::new(evttx, worldtx, pass, init_max_lines);
+    // 5. run event loop on main thread
+    let server_handler = Server::new(evttx, world, pass, init_max_lines);
     let eventloop = EventLoop::new(engine, evtrx, server_handler, QuitServer);
     eventloop.run()?;
 
@@ -1,13 +1,17 @@
 use crate::auth;
+use crate::conf;
 use crate::error::{Error, Result};
-use crate::event::{Event, EventHandler, NextStep, QuitHandler};
-use crate::proto::cli::Packet;
+use crate::event::{Event, EventHandler, NextStep, QuitHandler, Sessions};
+use crate::proto::cli::{Packet, CAP_ZLIB};
+use crate::i18n;
+use crate::runtime::bundle;
 use crate::runtime::{Engine, EngineAction, RuntimeOutput, RuntimeOutputHandler};
 use crate::telnet::{Outbound, Telnet, TelnetEvent};
-use crate::ui::line::RawLines;
+use crate::ui::line::{Line, Lines, RawLines};
 use crate::ui::UserOutput;
 use crossbeam_channel::{unbounded, Sender};
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 use std::{io, thread};
 
@@ -24,10 +28,16 @@ pub fn connect_world(world_addr: &str, timeout: Duration) -> Result<TcpStream> {
     )))
 }
 
-/// 启动线程接收MUD消息
-pub fn start_from_mud_handle(evttx: Sender<Event>, from_mud: impl io::Read + Send + 'static) {
+/// 启动线程接收MUD消息，conn为连接序号，附带在断开事件中
+pub fn start_from_mud_handle(
+    evttx: Sender<Event>,
+    from_mud: impl io::Read + Send + 'static,
+    config: &conf::Protocol,
+    conn: u32,
+) {
+    let config = config.clone();
     thread::spawn(move || {
-        let mut telnet = Telnet::new(from_mud, 4096);
+        let mut telnet = Telnet::new(from_mud, 4096, &config);
         loop {
             match telnet.recv() {
                 Err(e) => {
@@ -36,7 +46,7 @@ pub fn start_from_mud_handle(evttx: Sender<Event>, from_mud: impl io::Read + Sen
                 }
                 Ok(TelnetEvent::Disconnected) => {
                     // once disconnected, stop the thread
-                    let _ = evttx.send(Event::WorldDisconnected);
+                    let _ = evttx.send(Event::WorldDisconnected(conn));
                     break;
                 }
                 Ok(TelnetEvent::Empty) => (),
@@ -46,17 +56,34 @@ pub fn start_from_mud_handle(evttx: Sender<Event>, from_mud: impl io::Read + Sen
                 }
                 Ok(TelnetEvent::Text(bs)) => {
                     log::trace!("TelnetDataReceive[len={}]", bs.len());
-                    evttx.send(Event::WorldBytes(bs)).unwrap();
+                    evttx.send(Event::WorldBytes(conn, bs)).unwrap();
+                }
+                Ok(TelnetEvent::Protocols(protocols)) => {
+                    log::debug!("negotiated protocols {:?}", protocols);
+                    evttx.send(Event::WorldProtocols(protocols)).unwrap();
+                }
+                Ok(TelnetEvent::Gmcp(bs)) => {
+                    log::trace!("TelnetGmcp[len={}]", bs.len());
+                    evttx.send(Event::WorldGmcp(bs)).unwrap();
+                }
+                Ok(TelnetEvent::Msdp(bs)) => {
+                    log::trace!("TelnetMsdp[len={}]", bs.len());
+                    evttx.send(Event::WorldMsdp(bs)).unwrap();
+                }
+                Ok(TelnetEvent::Probe(report)) => {
+                    log::debug!("connection probe {:?}", report);
+                    evttx.send(Event::WorldProbe(report)).unwrap();
                 }
             }
         }
     });
 }
 
-/// 启动线程发送MUD消息
+/// 启动线程发送MUD消息，conn为连接序号，附带在断开及写入失败事件中
 pub fn start_to_mud_handle(
     evttx: Sender<Event>,
     to_mud: impl io::Write + Send + 'static,
+    conn: u32,
 ) -> Sender<Vec<u8>> {
     let (tx, rx) = unbounded::<Vec<u8>>();
     thread::spawn(move || {
@@ -65,13 +92,16 @@ pub fn start_to_mud_handle(
             match rx.recv() {
                 Err(e) => {
                     log::error!("channel receive outbound message error {}", e);
-                    let _ = evttx.send(Event::WorldDisconnected);
+                    let _ = evttx.send(Event::WorldDisconnected(conn));
                     return;
                 }
                 Ok(bs) => {
                     log::trace!("send bytes to mud[len={}]", bs.len());
                     if let Err(e) = outbound.send(bs) {
                         log::error!("send server error: {}", e);
+                        // 连接已不可用，通知事件循环后退出
+                        let _ = evttx.send(Event::WorldWriteError(conn, e.to_string()));
+                        return;
                     }
                 }
             }
@@ -80,8 +110,328 @@ pub fn start_to_mud_handle(
     tx
 }
 
-/// 启动线程监听本地端口
-pub fn start_server_listener_handle(listener: TcpListener, evttx: Sender<Event>) {
+// 连接服务器的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 重连间隔，从初始间隔开始每次翻倍，不超过最大间隔
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    // 最大重连次数，0表示不限制
+    max_attempts: u32,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, max_attempts: u32) -> Self {
+        Self {
+            initial,
+            max,
+            max_attempts,
+            attempts: 0,
+        }
+    }
+
+    /// 下一次重连前的等待时间，超过最大重连次数时返回None
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_attempts > 0 && self.attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .initial
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(self.max, |d| d.min(self.max));
+        self.attempts += 1;
+        Some(delay)
+    }
+
+    /// 已进行的重连次数
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+/// 与MUD服务器的连接，断开后按退避间隔自动重连
+///
+/// 每个连接及每次重连尝试都有序号，已被替换的连接及过期的重连结果产生的事件被忽略
+pub struct WorldLink {
+    addr: String,
+    protocol: conf::Protocol,
+    write_timeout: Option<Duration>,
+    evttx: Sender<Event>,
+    worldtx: Sender<Vec<u8>>,
+    // 当前连接，用于主动断开
+    stream: Option<TcpStream>,
+    conn: u32,
+    attempt: u32,
+    // 首次连接尚在后台进行
+    connecting: bool,
+    auto: bool,
+    backoff: Backoff,
+}
+
+impl WorldLink {
+    /// 连接服务器并启动读写线程
+    pub fn connect(config: &conf::Config, evttx: Sender<Event>) -> Result<Self> {
+        let world = &config.world;
+        let stream = connect_world(&world.addr, CONNECT_TIMEOUT)?;
+        let worldtx = start_world_handles(&evttx, &stream, &config.protocol, world.write_timeout(), 0)?;
+        Ok(Self {
+            addr: world.addr.to_owned(),
+            protocol: config.protocol.clone(),
+            write_timeout: world.write_timeout(),
+            evttx,
+            worldtx,
+            stream: Some(stream),
+            conn: 0,
+            attempt: 0,
+            connecting: false,
+            auto: world.reconnect,
+            backoff: Backoff::new(
+                Duration::from_secs(world.reconnect_initial_secs),
+                Duration::from_secs(world.reconnect_max_secs),
+                world.reconnect_max_attempts,
+            ),
+        })
+    }
+
+    /// 在后台线程中连接服务器，结果以重连事件返回，期间发送的数据被丢弃
+    pub fn open(config: &conf::Config, evttx: Sender<Event>) -> Self {
+        let world = &config.world;
+        let (worldtx, _) = unbounded();
+        spawn_reconnect(evttx.clone(), world.addr.clone(), Duration::from_secs(0), 1);
+        Self {
+            addr: world.addr.to_owned(),
+            protocol: config.protocol.clone(),
+            write_timeout: world.write_timeout(),
+            evttx,
+            worldtx,
+            stream: None,
+            conn: 0,
+            attempt: 1,
+            connecting: true,
+            auto: world.reconnect,
+            backoff: Backoff::new(
+                Duration::from_secs(world.reconnect_initial_secs),
+                Duration::from_secs(world.reconnect_max_secs),
+                world.reconnect_max_attempts,
+            ),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// 是否为当前连接，已断开或被替换的连接的数据应丢弃
+    pub fn is_current(&self, conn: u32) -> bool {
+        conn == self.conn && self.stream.is_some()
+    }
+
+    /// 发送数据，断线期间丢弃
+    pub fn send(&self, bs: Vec<u8>) {
+        if self.stream.is_none() || self.worldtx.send(bs).is_err() {
+            log::warn!("world not connected, outgoing bytes dropped");
+        }
+    }
+
+    /// 连接断开时调用，提示用户并安排重连，返回是否仍在重连
+    ///
+    /// 已被替换的连接或已处理过的断开返回None
+    pub fn lost(&mut self, conn: u32, engine: &mut Engine) -> Option<bool> {
+        if conn != self.conn {
+            return None;
+        }
+        let stream = self.stream.take()?;
+        // 写入失败时连接可能仍然存活，主动断开使读线程退出
+        let _ = stream.shutdown(Shutdown::Both);
+        engine.push(EngineAction::WorldDisconnected);
+        Some(self.schedule(engine))
+    }
+
+    /// 立即重连，已连接时先断开当前连接
+    pub fn reconnect_now(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+            // 忽略当前连接之后产生的断开事件
+            self.conn += 1;
+        }
+        self.backoff.reset();
+        self.attempt += 1;
+        spawn_reconnect(self.evttx.clone(), self.addr.clone(), Duration::from_secs(0), self.attempt);
+    }
+
+    /// 重连成功时调用，过期的结果直接丢弃
+    pub fn reconnected(&mut self, attempt: u32, stream: TcpStream, engine: &mut Engine) -> Result<()> {
+        if attempt != self.attempt || self.stream.is_some() {
+            return Ok(());
+        }
+        self.conn += 1;
+        self.worldtx = start_world_handles(&self.evttx, &stream, &self.protocol, self.write_timeout, self.conn)?;
+        self.stream = Some(stream);
+        self.backoff.reset();
+        let key = if self.connecting { "world.connected" } else { "world.reconnected" };
+        self.connecting = false;
+        send_note(engine, i18n::tr(key));
+        engine.push(EngineAction::WorldConnected);
+        Ok(())
+    }
+
+    /// 重连失败时调用，返回是否仍在重连，过期的结果返回None
+    pub fn reconnect_failed(&mut self, attempt: u32, err: &str, engine: &mut Engine) -> Option<bool> {
+        if attempt != self.attempt || self.stream.is_some() {
+            return None;
+        }
+        // 首次连接失败时不自动重连，地址可能有误
+        if self.connecting {
+            self.connecting = false;
+            let err_lines = Lines::fmt_err(i18n::trf("world.connect_failed", &[&err]));
+            for err_line in err_lines.into_vec() {
+                engine.push(EngineAction::SendLineToUI(err_line, None));
+            }
+            return Some(false);
+        }
+        send_note(engine, i18n::trf("world.reconnect_failed", &[&err]));
+        Some(self.schedule(engine))
+    }
+
+    // 按退避间隔安排下一次重连，关闭自动重连或超过最大次数时提示并返回false
+    fn schedule(&mut self, engine: &mut Engine) -> bool {
+        let delay = match self.backoff.next_delay().filter(|_| self.auto) {
+            Some(delay) => delay,
+            None => {
+                let err_lines = Lines::fmt_err(i18n::tr("world.disconnected"));
+                for err_line in err_lines.into_vec() {
+                    engine.push(EngineAction::SendLineToUI(err_line, None));
+                }
+                return false;
+            }
+        };
+        self.attempt += 1;
+        send_note(
+            engine,
+            i18n::trf("world.reconnect_in", &[&delay.as_secs(), &self.backoff.attempts()]),
+        );
+        spawn_reconnect(self.evttx.clone(), self.addr.clone(), delay, self.attempt);
+        true
+    }
+}
+
+fn send_note(engine: &mut Engine, text: String) {
+    engine.push(EngineAction::SendLineToUI(Line::fmt_note(text), None));
+}
+
+// 为连接启动读写线程，返回发送通道
+fn start_world_handles(
+    evttx: &Sender<Event>,
+    stream: &TcpStream,
+    protocol: &conf::Protocol,
+    write_timeout: Option<Duration>,
+    conn: u32,
+) -> Result<Sender<Vec<u8>>> {
+    let to_mud = stream.try_clone()?;
+    // 避免连接停滞时写线程无限阻塞
+    to_mud.set_write_timeout(write_timeout)?;
+    let worldtx = start_to_mud_handle(evttx.clone(), to_mud, conn);
+    start_from_mud_handle(evttx.clone(), stream.try_clone()?, protocol, conn);
+    Ok(worldtx)
+}
+
+// 等待指定时间后在后台线程中重连，结果以事件返回
+fn spawn_reconnect(evttx: Sender<Event>, addr: String, delay: Duration, attempt: u32) {
+    thread::spawn(move || {
+        thread::sleep(delay);
+        log::info!("reconnecting to world {}, attempt {}", addr, attempt);
+        let evt = match connect_world(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => Event::WorldReconnected(attempt, stream),
+            Err(e) => Event::WorldReconnectFailed(attempt, e.to_string()),
+        };
+        let _ = evttx.send(evt);
+    });
+}
+
+/// 客户端网段，如192.168.1.0/24或fe80::/10，不带前缀长度时表示单个地址
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || Error::ParseError(format!("invalid cidr {}", s));
+        let (addr, prefix) = match s.find('/') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4映射的IPv6地址按IPv4处理，以便监听::时匹配IPv4网段
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(u32::from(net) as u128, u32::from(ip) as u128, 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+// 比较两个地址的前prefix位
+fn prefix_eq(a: u128, b: u128, bits: u8, prefix: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (a >> shift) == (b >> shift)
+}
+
+/// 根据配置生成所有监听地址
+pub fn listen_addrs(config: &conf::Server) -> Result<Vec<SocketAddr>> {
+    let ip: IpAddr = config
+        .bind
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| Error::ParseError(format!("invalid bind address {}", config.bind)))?;
+    let mut ports = vec![config.port];
+    for port in &config.extra_ports {
+        if !ports.contains(port) {
+            ports.push(*port);
+        }
+    }
+    Ok(ports.into_iter().map(|port| SocketAddr::new(ip, port)).collect())
+}
+
+/// 启动线程监听本地端口，不在允许网段内的连接直接关闭
+pub fn start_server_listener_handle(
+    listener: TcpListener,
+    allow: Arc<Vec<Cidr>>,
+    evttx: Sender<Event>,
+) {
     thread::spawn(move || loop {
         let (conn, addr) = match listener.accept() {
             Err(e) => {
@@ -91,6 +441,11 @@ pub fn start_server_listener_handle(listener: TcpListener, evttx: Sender<Event>)
             }
             Ok((conn, addr)) => (conn, addr),
         };
+        if !allow.is_empty() && !allow.iter().any(|c| c.contains(addr.ip())) {
+            log::warn!("reject client with addr {:?} not in allowlist", addr);
+            drop(conn);
+            continue;
+        }
         log::info!("accept new client with addr {:?}", addr);
         evttx.send(Event::NewClient(conn, addr)).unwrap();
     });
@@ -101,15 +456,15 @@ fn start_to_client_handle(conn: TcpStream, pass: String, evttx: Sender<Event>) -
     let (tx, rx) = unbounded::<Packet>();
     thread::spawn(move || {
         // do authentication first
-        let mut conn = match auth::server_auth(conn, &pass) {
+        let (mut conn, caps) = match auth::server_auth(conn, &pass) {
             Err(_) => {
                 let _ = evttx.send(Event::ClientAuthFail);
                 return;
             }
-            Ok(conn) => conn,
+            Ok(auth) => auth,
         };
         let conn_recv = conn.try_clone().unwrap();
-        evttx.send(Event::ClientAuthSuccess(conn_recv)).unwrap();
+        evttx.send(Event::ClientAuthSuccess(conn_recv, caps)).unwrap();
         // proxy messages to client
         loop {
             match rx.recv() {
@@ -155,7 +510,7 @@ fn start_from_client_handle(mut conn: TcpStream, evttx: Sender<Event>) {
 /// server app
 pub struct Server {
     evttx: Sender<Event>,
-    worldtx: Sender<Vec<u8>>,
+    world: WorldLink,
     pass: String,
     buffer: RawLines,
     to_cli: Option<(Sender<Packet>, SocketAddr)>,
@@ -164,14 +519,14 @@ pub struct Server {
 impl Server {
     pub fn new(
         evttx: Sender<Event>,
-        worldtx: Sender<Vec<u8>>,
+        world: WorldLink,
         pass: String,
         init_max_lines: usize,
     ) -> Self {
         let buffer = RawLines::with_capacity(init_max_lines);
         Self {
             evttx,
-            worldtx,
+            world,
             pass,
             buffer,
             to_cli: None,
@@ -180,7 +535,8 @@ impl Server {
 }
 
 impl EventHandler for Server {
-    fn on_event(&mut self, evt: Event, engine: &mut Engine) -> Result<NextStep> {
+    fn on_event(&mut self, evt: Event, sessions: &mut Sessions) -> Result<NextStep> {
+        let engine = sessions.active();
         match evt {
             Event::NewClient(conn, addr) => {
                 log::info!("client connected from {:?}", addr);
@@ -198,12 +554,18 @@ impl EventHandler for Server {
                 log::info!("client auth failed");
                 self.to_cli.take();
             }
-            Event::ClientAuthSuccess(mut conn) => {
+            Event::ClientAuthSuccess(mut conn, caps) => {
                 log::info!("client auth succeeded, starting thread to handle incoming messages");
                 let lines = self.buffer.to_vec();
                 // todo: separate multiple batch
                 let pkt = Packet::Lines(lines);
-                if let Err(e) = pkt.write_to(&mut conn) {
+                // 客户端支持时压缩初始同步的批量文本
+                let res = if caps & CAP_ZLIB != 0 {
+                    pkt.write_compressed(&mut conn)
+                } else {
+                    pkt.write_to(&mut conn)
+                };
+                if let Err(e) = res {
                     log::error!("channel send client style text error {}", e);
                     // maybe client disconnected, discard this connection
                     return Ok(NextStep::Run);
@@ -216,11 +578,27 @@ impl EventHandler for Server {
             }
             // 直接发送给MUD
             Event::TelnetBytes(bs) => {
-                self.worldtx.send(bs)?;
+                self.world.send(bs);
             }
             // 以下事件交给运行时处理
-            Event::WorldBytes(bs) => {
-                engine.push(EngineAction::ParseWorldBytes(bs));
+            Event::WorldBytes(conn, bs) => {
+                if self.world.is_current(conn) {
+                    engine.push(EngineAction::ParseWorldBytes(bs));
+                } else {
+                    log::debug!("bytes of stale connection {} dropped", conn);
+                }
+            }
+            Event::WorldProtocols(protocols) => {
+                engine.push(EngineAction::UpdateProtocols(protocols));
+            }
+            Event::WorldGmcp(bs) => {
+                engine.push(EngineAction::ReceiveGmcp(bs));
+            }
+            Event::WorldMsdp(bs) => {
+                engine.push(EngineAction::ReceiveMsdp(bs));
+            }
+            Event::WorldProbe(report) => {
+                engine.push(EngineAction::UpdateProbe(report));
             }
             Event::UserOutput(output) => {
                 engine.push(EngineAction::ExecuteUserOutput(output));
@@ -228,14 +606,44 @@ impl EventHandler for Server {
             Event::Timer(timer) => {
                 engine.push(EngineAction::ExecuteTimer(timer));
             }
-            Event::WorldDisconnected => {
-                log::warn!("world down or disconnected, shutdown server");
-                return Ok(NextStep::Quit);
+            Event::BundleFetched(url, res) => {
+                engine.push(EngineAction::BundleFetched(url, res));
+            }
+            // 无法重连时关闭服务器
+            Event::WorldDisconnected(conn) => {
+                log::warn!("world down or disconnected");
+                if self.world.lost(conn, engine) == Some(false) {
+                    log::warn!("reconnect disabled or exhausted, shutdown server");
+                    return Ok(NextStep::Quit);
+                }
+            }
+            Event::WorldWriteError(conn, e) => {
+                log::warn!("failed to write to world: {}", e);
+                if self.world.lost(conn, engine) == Some(false) {
+                    log::warn!("reconnect disabled or exhausted, shutdown server");
+                    return Ok(NextStep::Quit);
+                }
+            }
+            Event::WorldReconnected(attempt, stream) => {
+                self.world.reconnected(attempt, stream, engine)?;
+            }
+            Event::WorldReconnectFailed(attempt, e) => {
+                log::warn!("reconnect to world failed: {}", e);
+                if self.world.reconnect_failed(attempt, &e, engine) == Some(false) {
+                    log::warn!("reconnect exhausted, shutdown server");
+                    return Ok(NextStep::Quit);
+                }
             }
             Event::Quit
             | Event::LinesFromServer(_)
             | Event::TerminalKey(_)
             | Event::TerminalMouse(_)
+            | Event::PasteChoice(_)
+            | Event::KeyRead(_)
+            | Event::MenuChosen(_)
+            | Event::ReplInput(_)
+            | Event::ReplCancel
+            | Event::Session(..)
             | Event::WindowResize
             | Event::ServerDown => unreachable!("standalone mode does not support event {:?}", evt),
         }
@@ -244,12 +652,20 @@ impl EventHandler for Server {
 }
 
 impl RuntimeOutputHandler for Server {
-    fn on_runtime_output(&mut self, output: RuntimeOutput) -> Result<NextStep> {
+    fn on_runtime_output(&mut self, _: usize, output: RuntimeOutput, _: &mut Sessions) -> Result<NextStep> {
         match output {
             RuntimeOutput::ToServer(bs) => {
-                self.worldtx.send(bs)?;
+                self.world.send(bs);
             }
-            RuntimeOutput::ToUI(raw, _) => {
+            RuntimeOutput::Reconnect => self.world.reconnect_now(),
+            RuntimeOutput::ManageSession(_) => log::warn!("multiple sessions are not supported in server mode"),
+            RuntimeOutput::FetchBundle(url) => {
+                let evttx = self.evttx.clone();
+                bundle::spawn_fetch(url, move |evt| {
+                    let _ = evttx.send(evt);
+                });
+            }
+            RuntimeOutput::ToUI(raw, ..) => {
                 if let Some((clitx, _)) = self.to_cli.as_mut() {
                     let lines = raw.into_vec();
                     if let Err(e) = clitx.send(Packet::Lines(lines)) {
@@ -257,6 +673,15 @@ impl RuntimeOutputHandler for Server {
                     }
                 }
             }
+            // 客户端根据原始文本自行解析状态栏及路由
+            RuntimeOutput::ToStatus(_) | RuntimeOutput::ToWindow(..) => (),
+            // 服务器没有界面，等待按键的脚本只能超时，菜单不会弹出
+            RuntimeOutput::ReadKey(_)
+            | RuntimeOutput::ShowMenu(..)
+            | RuntimeOutput::FlashCmd(_)
+            | RuntimeOutput::SecretInput(_)
+            | RuntimeOutput::ToRepl(_)
+            | RuntimeOutput::UpdateTerm(_) => (),
         }
         Ok(NextStep::Run)
     }
@@ -267,3 +692,67 @@ pub struct QuitServer;
 impl QuitHandler for QuitServer {
     fn on_quit(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let lan = Cidr::parse("192.168.1.0/24").unwrap();
+        assert!(lan.contains("192.168.1.20".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.20".parse().unwrap()));
+        // 监听::时IPv4客户端地址为映射地址
+        assert!(lan.contains("::ffff:192.168.1.20".parse().unwrap()));
+        let host = Cidr::parse("::1").unwrap();
+        assert!(host.contains("::1".parse().unwrap()));
+        assert!(!host.contains("127.0.0.1".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn test_to_mud_write_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let to_mud = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // 对端不读取数据，发送缓冲区写满后阻塞
+        let (_peer, _) = listener.accept().unwrap();
+        to_mud.set_write_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (evttx, evtrx) = unbounded();
+        let worldtx = start_to_mud_handle(evttx, to_mud, 0);
+        worldtx.send(vec![b'a'; 64 * 1024 * 1024]).unwrap();
+        match evtrx.recv_timeout(Duration::from_secs(10)).unwrap() {
+            Event::WorldWriteError(0, e) => assert!(e.contains("timed out")),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(10), 5);
+        let delays: Vec<u64> = std::iter::from_fn(|| backoff.next_delay()).map(|d| d.as_secs()).collect();
+        assert_eq!(vec![2, 4, 8, 10, 10], delays);
+        assert_eq!(5, backoff.attempts());
+        backoff.reset();
+        assert_eq!(Some(Duration::from_secs(2)), backoff.next_delay());
+        // 不限制次数时间隔保持在最大值
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60), 0);
+        let last = (0..40).filter_map(|_| backoff.next_delay()).last();
+        assert_eq!(Some(Duration::from_secs(60)), last);
+    }
+
+    #[test]
+    fn test_listen_addrs() {
+        let config = conf::Server {
+            bind: "::".to_owned(),
+            extra_ports: vec![9681, 9680],
+            ..conf::Server::default()
+        };
+        let addrs = listen_addrs(&config).unwrap();
+        assert_eq!(
+            vec!["[::]:9680".parse::<SocketAddr>().unwrap(), "[::]:9681".parse().unwrap()],
+            addrs
+        );
+    }
+}
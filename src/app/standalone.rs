@@ -1,31 +1,148 @@
+use crate::app::server::WorldLink;
+use crate::conf::Config;
+use crate::datadir::{self, DataDir};
 use crate::error::Result;
-use crate::event::{Event, EventHandler, NextStep, QuitHandler};
-use crate::runtime::{Engine, EngineAction, RuntimeOutput, RuntimeOutputHandler};
-use crate::ui::line::Lines;
-use crate::ui::UIEvent;
+use crate::event::{self, Event, EventHandler, NextStep, QuitHandler, Sessions};
+use crate::i18n;
+use crate::runtime::bundle;
+use crate::runtime::{Engine, EngineAction, RuntimeOutput, RuntimeOutputHandler, SessionCmd};
+use crate::ui::line::{Line, Lines};
+use crate::ui::{UIEvent, UISender};
 use crossbeam_channel::Sender;
 use std::thread;
 
 /// standalone app, directly connect to mud world
 /// and render UI
+///
+/// 可同时连接多个世界，每个会话有独立的运行时及连接，序号与Sessions中一致
 pub struct Standalone {
-    uitx: Sender<UIEvent>,
-    worldtx: Sender<Vec<u8>>,
+    uitx: UISender,
+    worlds: Vec<WorldLink>,
+    // 各会话的名称及地址
+    names: Vec<(String, String)>,
+    config: Config,
+    evttx: Sender<Event>,
 }
 
 impl Standalone {
-    pub fn new(uitx: Sender<UIEvent>, worldtx: Sender<Vec<u8>>) -> Self {
-        Self { uitx, worldtx }
+    pub fn new(uitx: UISender, world: WorldLink, config: Config, evttx: Sender<Event>) -> Self {
+        let name = session_name(&config);
+        let addr = config.world.addr.clone();
+        Self {
+            uitx,
+            worlds: vec![world],
+            names: vec![(name, addr)],
+            config,
+            evttx,
+        }
+    }
+
+    fn note(&self, msg: impl Into<String>) -> Result<()> {
+        self.uitx.send(UIEvent::Line(Line::fmt_note(msg)))?;
+        Ok(())
+    }
+
+    fn manage_session(&mut self, cmd: SessionCmd, sessions: &mut Sessions) -> Result<()> {
+        match cmd {
+            SessionCmd::List => {
+                self.note(i18n::tr("session.title"))?;
+                for (id, (name, addr)) in self.names.iter().enumerate() {
+                    let marker = if id == sessions.active_id() { "*" } else { " " };
+                    let state = if self.worlds[id].is_connected() {
+                        i18n::tr("session.connected")
+                    } else {
+                        i18n::tr("session.disconnected")
+                    };
+                    self.note(format!("{} {} {} ({}) {}", marker, id + 1, name, addr, state))?;
+                }
+            }
+            SessionCmd::Switch(n) => {
+                if n == 0 || !sessions.switch(n - 1) {
+                    self.uitx
+                        .send(UIEvent::Lines(Lines::fmt_err(i18n::trf("session.invalid", &[&n]))))?;
+                    return Ok(());
+                }
+                self.switched(n - 1, sessions)?;
+            }
+            // 连接在后台进行，结果以该会话的重连事件返回
+            SessionCmd::Open(name, addr) => match self.open_session(&name, &addr) {
+                Ok(engine) => {
+                    let id = sessions.add(engine);
+                    self.names.push((name, addr));
+                    sessions.switch(id);
+                    self.switched(id, sessions)?;
+                }
+                Err(e) => {
+                    log::error!("failed to open session {} at {}: {}", name, addr, e);
+                    self.uitx.send(UIEvent::Lines(Lines::fmt_err(e.to_string())))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // 通知界面切换主窗格，并以该会话的屏幕内容供其脚本读取
+    fn switched(&self, id: usize, sessions: &mut Sessions) -> Result<()> {
+        let view = match sessions.get(id) {
+            Some(engine) => engine.screen_view(),
+            None => return Ok(()),
+        };
+        self.uitx.send(UIEvent::SwitchSession(id, view))?;
+        self.note(i18n::trf("session.switched", &[&(id + 1), &self.names[id].0]))
+    }
+
+    // 以新的世界名称及地址初始化运行时并在后台连接，脚本及日志使用该世界的数据目录
+    fn open_session(&mut self, name: &str, addr: &str) -> Result<Engine> {
+        // 名称用作数据目录名，不能指向数据目录之外
+        datadir::check_name(name)?;
+        let mut config = self.config.clone();
+        config.world.name = name.to_owned();
+        config.world.addr = addr.to_owned();
+        let data_dir = DataDir::new(&config);
+        data_dir.create_all()?;
+        let mut engine = Engine::new(&config);
+        engine.open_log(&config.server.log_file)?;
+        engine.init()?;
+        let sesstx = event::session_channel(self.evttx.clone(), self.worlds.len());
+        // 连接成功前命令进入离线队列或被丢弃
+        engine.push(EngineAction::WorldDisconnected);
+        let world = WorldLink::open(&config, sesstx.clone());
+        let _ = engine.spawn_timer(sesstx);
+        // 绝对路径或套接字可能与其他会话冲突，不影响会话本身
+        if let Err(e) = engine.spawn_exporter() {
+            log::warn!("failed to export variables of session {}: {}", name, e);
+        }
+        self.worlds.push(world);
+        Ok(engine)
+    }
+}
+
+/// 会话名称，未配置世界名称时使用地址
+pub fn session_name(config: &Config) -> String {
+    if config.world.name.is_empty() {
+        config.world.addr.clone()
+    } else {
+        config.world.name.clone()
     }
 }
 
 impl EventHandler for Standalone {
-    fn on_event(&mut self, evt: Event, engine: &mut Engine) -> Result<NextStep> {
+    fn on_event(&mut self, evt: Event, sessions: &mut Sessions) -> Result<NextStep> {
+        // 世界连接及定时器的事件属于其会话，其余事件交给当前会话
+        let (id, evt) = match evt {
+            Event::Session(id, evt) => (id, *evt),
+            evt => (sessions.active_id(), evt),
+        };
+        let engine = match sessions.get(id) {
+            Some(engine) => engine,
+            None => return Ok(NextStep::Skip),
+        };
+        let world = &mut self.worlds[id];
         match evt {
             Event::Quit => return Ok(NextStep::Quit),
             // 直接发送给MUD
             Event::TelnetBytes(bs) => {
-                self.worldtx.send(bs)?;
+                world.send(bs);
             }
             // 以下事件发送给UI线程处理
             Event::TerminalKey(k) => {
@@ -34,23 +151,69 @@ impl EventHandler for Standalone {
             Event::TerminalMouse(m) => {
                 self.uitx.send(UIEvent::Mouse(m))?;
             }
+            Event::PasteChoice(choices) => {
+                self.uitx.send(UIEvent::PasteChoice(choices))?;
+            }
             Event::WindowResize => {
                 self.uitx.send(UIEvent::WindowResize)?;
             }
             // 以下事件交给运行时处理
-            Event::WorldBytes(bs) => {
-                engine.push(EngineAction::ParseWorldBytes(bs));
+            Event::WorldBytes(conn, bs) => {
+                if world.is_current(conn) {
+                    engine.push(EngineAction::ParseWorldBytes(bs));
+                } else {
+                    log::debug!("bytes of stale connection {} dropped", conn);
+                }
+            }
+            Event::WorldProtocols(protocols) => {
+                engine.push(EngineAction::UpdateProtocols(protocols));
+            }
+            Event::WorldGmcp(bs) => {
+                engine.push(EngineAction::ReceiveGmcp(bs));
+            }
+            Event::WorldMsdp(bs) => {
+                engine.push(EngineAction::ReceiveMsdp(bs));
+            }
+            Event::WorldProbe(report) => {
+                engine.push(EngineAction::UpdateProbe(report));
             }
             Event::UserOutput(output) => {
                 engine.push(EngineAction::ExecuteUserOutput(output));
             }
-            Event::WorldDisconnected => {
+            Event::KeyRead(key) => {
+                engine.push(EngineAction::KeyRead(key));
+            }
+            Event::MenuChosen(idx) => {
+                engine.push(EngineAction::MenuChosen(idx));
+            }
+            Event::ReplInput(code) => {
+                engine.push(EngineAction::EvalRepl(code));
+            }
+            Event::ReplCancel => {
+                engine.push(EngineAction::CancelRepl);
+            }
+            Event::BundleFetched(url, res) => {
+                engine.push(EngineAction::BundleFetched(url, res));
+            }
+            // 断开后自动重连，无法重连时保留界面供查看
+            Event::WorldDisconnected(conn) => {
                 log::error!("world down or not reachable");
-                // 向用户提示退出
-                let err_lines = Lines::fmt_err("与服务器断开了连接，请关闭并重新连接");
+                world.lost(conn, engine);
+            }
+            Event::WorldWriteError(conn, e) => {
+                log::error!("failed to write to world: {}", e);
+                let err_lines = Lines::fmt_err(i18n::trf("err.world_write", &[&e]));
                 for err_line in err_lines.into_vec() {
                     engine.push(EngineAction::SendLineToUI(err_line, None));
                 }
+                world.lost(conn, engine);
+            }
+            Event::WorldReconnected(attempt, stream) => {
+                world.reconnected(attempt, stream, engine)?;
+            }
+            Event::WorldReconnectFailed(attempt, e) => {
+                log::warn!("reconnect to world failed: {}", e);
+                world.reconnect_failed(attempt, &e, engine);
             }
             Event::Timer(task) => {
                 engine.push(EngineAction::ExecuteTimer(task));
@@ -58,23 +221,65 @@ impl EventHandler for Standalone {
             // standalone模式不支持客户端连接，待增强
             Event::NewClient(..)
             | Event::ClientAuthFail
-            | Event::ClientAuthSuccess(_)
+            | Event::ClientAuthSuccess(..)
             | Event::ClientDisconnect
             | Event::LinesFromServer(_)
-            | Event::ServerDown => unreachable!("standalone mode does not support event {:?}", evt),
+            | Event::ServerDown
+            | Event::Session(..) => unreachable!("standalone mode does not support event {:?}", evt),
         }
         Ok(NextStep::Run)
     }
 }
 
 impl RuntimeOutputHandler for Standalone {
-    fn on_runtime_output(&mut self, output: RuntimeOutput) -> Result<NextStep> {
+    fn on_runtime_output(&mut self, session: usize, output: RuntimeOutput, sessions: &mut Sessions) -> Result<NextStep> {
+        let active = session == sessions.active_id();
         match output {
             RuntimeOutput::ToServer(bs) => {
-                self.worldtx.send(bs)?;
+                self.worlds[session].send(bs);
+            }
+            RuntimeOutput::Reconnect => self.worlds[session].reconnect_now(),
+            RuntimeOutput::ManageSession(cmd) => self.manage_session(cmd, sessions)?,
+            // 下载结果交回发起下载的会话
+            RuntimeOutput::FetchBundle(url) => {
+                let evttx = self.evttx.clone();
+                bundle::spawn_fetch(url, move |evt| {
+                    let _ = evttx.send(Event::Session(session, Box::new(evt)));
+                });
+            }
+            // 后台会话的输出保存在其窗格中，切换后可见
+            RuntimeOutput::ToUI(_, styled, lineno) if !active => {
+                self.uitx.send(UIEvent::SessionLines(session, lineno, styled))?;
+            }
+            RuntimeOutput::ToUI(_, styled, lineno) => {
+                self.uitx.send(UIEvent::NumberedLines(lineno, styled))?;
+            }
+            RuntimeOutput::ToRepl(lines) => {
+                self.uitx.send(UIEvent::Repl(lines))?;
+            }
+            // 界面设置由各会话共用
+            RuntimeOutput::UpdateTerm(term) => {
+                self.uitx.send(UIEvent::UpdateTerm(term))?;
+            }
+            // 以下输出仅在当前会话时显示
+            _ if !active => log::debug!("output of background session {} dropped", session),
+            RuntimeOutput::ToWindow(target, styled) => {
+                self.uitx.send(UIEvent::Window(target, styled))?;
             }
-            RuntimeOutput::ToUI(_, styled) => {
-                self.uitx.send(UIEvent::Lines(styled))?;
+            RuntimeOutput::ToStatus(lines) => {
+                self.uitx.send(UIEvent::Status(lines))?;
+            }
+            RuntimeOutput::ReadKey(prompt) => {
+                self.uitx.send(UIEvent::ReadKey(prompt))?;
+            }
+            RuntimeOutput::ShowMenu(title, items) => {
+                self.uitx.send(UIEvent::Menu(title, items))?;
+            }
+            RuntimeOutput::FlashCmd(cmd) => {
+                self.uitx.send(UIEvent::FlashCmd(cmd))?;
+            }
+            RuntimeOutput::SecretInput(secret) => {
+                self.uitx.send(UIEvent::SecretInput(secret))?;
             }
         }
         Ok(NextStep::Run)
@@ -96,3 +301,59 @@ impl QuitHandler for QuitStandalone {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use crate::ui;
+    use crossbeam_channel::unbounded;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    #[test]
+    fn test_standalone_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let tmp = TempDir::new("sessions");
+        let mut config = Config::default();
+        config.world.addr = addr.clone();
+        config.world.data_dir = tmp.path_string();
+        let (evttx, evtrx) = unbounded();
+        let world = WorldLink::connect(&config, event::session_channel(evttx.clone(), 0)).unwrap();
+        let (uitx, uirx) = ui::ui_channel(16);
+        let mut standalone = Standalone::new(uitx, world, config.clone(), evttx);
+        let mut sessions = Sessions::new(Engine::new(&config));
+
+        // 打开会话时不等待连接，立即切换并附带新会话的屏幕内容
+        standalone
+            .manage_session(SessionCmd::Open("second".to_owned(), addr), &mut sessions)
+            .unwrap();
+        assert_eq!(1, sessions.active_id());
+        assert!(!standalone.worlds[1].is_connected());
+        match uirx.recv().unwrap() {
+            UIEvent::SwitchSession(1, _) => (),
+            other => panic!("unexpected ui event {:?}", other),
+        }
+        // 连接结果以新会话的事件返回
+        let evt = loop {
+            match evtrx.recv_timeout(Duration::from_secs(10)).unwrap() {
+                Event::Session(1, evt) => break *evt,
+                _ => continue,
+            }
+        };
+        assert!(matches!(evt, Event::WorldReconnected(1, _)));
+        standalone.on_event(Event::Session(1, Box::new(evt)), &mut sessions).unwrap();
+        assert!(standalone.worlds[1].is_connected());
+
+        standalone.manage_session(SessionCmd::Switch(1), &mut sessions).unwrap();
+        assert_eq!(0, sessions.active_id());
+        match uirx.recv().unwrap() {
+            UIEvent::SwitchSession(0, _) => (),
+            other => panic!("unexpected ui event {:?}", other),
+        }
+        // 不存在的会话不切换
+        standalone.manage_session(SessionCmd::Switch(3), &mut sessions).unwrap();
+        assert_eq!(0, sessions.active_id());
+    }
+}
@@ -1,26 +1,41 @@
 use crate::error::{Error, Result};
-use crate::proto::cli::Packet;
+use crate::proto::cli::{Packet, CAP_ZLIB};
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
 use rand::RngCore;
 use std::net::TcpStream;
 use std::time::Duration;
 
-pub fn server_auth(mut conn: TcpStream, pass: &str) -> Result<TcpStream> {
+// 认证种子的长度，服务端在种子之后附加一个字节声明支持的能力
+const SEED_LEN: usize = 20;
+
+/// 服务端认证，返回连接及客户端声明的能力
+///
+/// 旧版本客户端对包括能力字节在内的整个请求计算认证码，且不声明能力
+pub fn server_auth(mut conn: TcpStream, pass: &str) -> Result<(TcpStream, u8)> {
     let orig_read_timeout = conn.read_timeout()?;
     let orig_write_timeout = conn.write_timeout()?;
     // reduce socket timeout to 5 seconds
     conn.set_read_timeout(Some(Duration::from_secs(5)))?;
     conn.set_write_timeout(Some(Duration::from_secs(5)))?;
-    // send auth request with random seed
-    let (seed, secret) = gen_secret(pass.as_bytes())?;
+    // send auth request with random seed and server capabilities
+    let (mut seed, secret) = gen_secret(pass.as_bytes())?;
+    seed.push(CAP_ZLIB);
+    let legacy_secret = calc_secret(pass.as_bytes(), &seed)?;
     let auth_req = Packet::AuthReq(seed);
     auth_req.write_to(&mut conn)?;
     // receive response and check
     let auth_resp = Packet::read_from(&mut conn)?;
     let mut auth_success = false;
+    let mut caps = 0;
     if let Packet::AuthResp(resp) = auth_resp {
-        auth_success = resp == secret;
+        if resp == legacy_secret {
+            auth_success = true;
+        } else if resp.len() == secret.len() + 1 && resp[..secret.len()] == secret[..] {
+            // 认证码之后的一个字节为客户端能力，仅接受服务端声明过的能力
+            auth_success = true;
+            caps = resp[secret.len()] & CAP_ZLIB;
+        }
     }
     if !auth_success {
         let _ = Packet::Err(String::from("authentication failed")).write_to(&mut conn);
@@ -31,10 +46,11 @@ pub fn server_auth(mut conn: TcpStream, pass: &str) -> Result<TcpStream> {
     // reset socket timeout
     conn.set_read_timeout(orig_read_timeout)?;
     conn.set_write_timeout(orig_write_timeout)?;
-    log::debug!("server auth succeeds");
-    Ok(conn)
+    log::debug!("server auth succeeds, client caps={:#x}", caps);
+    Ok((conn, caps))
 }
 
+/// 客户端认证，仅在服务端声明支持时才声明客户端能力，兼容旧版本服务端
 pub fn client_auth(mut conn: TcpStream, pass: &str) -> Result<TcpStream> {
     let orig_read_timeout = conn.read_timeout()?;
     let orig_write_timeout = conn.write_timeout()?;
@@ -45,7 +61,14 @@ pub fn client_auth(mut conn: TcpStream, pass: &str) -> Result<TcpStream> {
     let auth_req = Packet::read_from(&mut conn)?;
     if let Packet::AuthReq(req) = auth_req {
         // send resp to server
-        let resp = calc_secret(pass.as_bytes(), &req)?;
+        let resp = match req.get(SEED_LEN) {
+            Some(server_caps) => {
+                let mut resp = calc_secret(pass.as_bytes(), &req[..SEED_LEN])?;
+                resp.push(server_caps & CAP_ZLIB);
+                resp
+            }
+            None => calc_secret(pass.as_bytes(), &req)?,
+        };
         Packet::AuthResp(resp).write_to(&mut conn)?;
         // receive ok/err
         let msg = Packet::read_from(&mut conn)?;
@@ -61,7 +84,7 @@ pub fn client_auth(mut conn: TcpStream, pass: &str) -> Result<TcpStream> {
 }
 
 pub fn gen_secret(pass: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
-    let mut seed = vec![0u8; 20];
+    let mut seed = vec![0u8; SEED_LEN];
     rand::thread_rng().fill_bytes(&mut seed);
     let secret = calc_secret(pass, &seed[..])?;
     Ok((seed, secret))
@@ -98,3 +121,42 @@ pub fn calc_secret(password: &[u8], seed: &[u8]) -> Result<Vec<u8>> {
         .collect();
     Ok(rst)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // 启动服务端认证，返回客户端连接及服务端认证结果
+    fn auth_pair(pass: &'static str) -> (TcpStream, thread::JoinHandle<Result<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (conn, _) = listener.accept()?;
+            server_auth(conn, pass).map(|(_, caps)| caps)
+        });
+        (TcpStream::connect(addr).unwrap(), handle)
+    }
+
+    #[test]
+    fn test_auth_caps() {
+        let (conn, handle) = auth_pair("pass");
+        client_auth(conn, "pass").unwrap();
+        assert_eq!(CAP_ZLIB, handle.join().unwrap().unwrap());
+
+        // 旧版本客户端对整个请求计算认证码，不声明能力
+        let (mut conn, handle) = auth_pair("pass");
+        let req = match Packet::read_from(&mut conn).unwrap() {
+            Packet::AuthReq(req) => req,
+            other => panic!("unexpected packet {:?}", other),
+        };
+        Packet::AuthResp(calc_secret(b"pass", &req).unwrap()).write_to(&mut conn).unwrap();
+        assert_eq!(Packet::Ok, Packet::read_from(&mut conn).unwrap());
+        assert_eq!(0, handle.join().unwrap().unwrap());
+
+        let (conn, handle) = auth_pair("pass");
+        assert!(client_auth(conn, "wrong").is_err());
+        assert!(handle.join().unwrap().is_err());
+    }
+}
@@ -1,7 +1,12 @@
 use gag::Redirect;
 use mudterm::app;
-use mudterm::conf::{CmdOpts, Config, Mode};
+use mudterm::conf::{CmdOpts, Config, Mode, SubCmd};
+use mudterm::crash;
+use mudterm::datadir::DataDir;
 use mudterm::error::{Error, Result};
+use mudterm::health;
+use mudterm::i18n;
+use mudterm::logview;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -21,11 +26,33 @@ fn main() -> Result<()> {
         let mut f = File::open(&cmdopts.conf_file)?;
         let mut toml_str = String::new();
         f.read_to_string(&mut toml_str)?;
-        toml::from_str(&toml_str)?
+        let mut config: Config = toml::from_str(&toml_str)?;
+        config.conf_file = cmdopts.conf_file.to_owned();
+        config
     };
 
+    i18n::set_lang(config.runtime.lang);
+
+    let data_dir = DataDir::new(&config);
+    data_dir.create_all()?;
+
+    if let Some(SubCmd::Logs { serve, port }) = cmdopts.cmd {
+        return logs(&config, &data_dir, serve, port);
+    }
+
+    // 启动检查，存在错误时显示诊断信息并退出
+    let diags = health::check_all(&config, &data_dir);
+    if health::has_error(&diags) {
+        println!("{}", health::render(&diags));
+        let n = diags
+            .iter()
+            .filter(|d| d.severity == health::Severity::Error)
+            .count();
+        return Err(Error::HealthCheckError(n));
+    }
+
     // redirect stderr to file
-    let debuglog = File::create(&config.server.debug_file)?;
+    let debuglog = File::create(data_dir.log_path(&config.server.debug_file))?;
     let _stderr_redirect = Redirect::stderr(debuglog).unwrap();
     let verbosity = match &cmdopts.log_level[..] {
         "error" => 0,
@@ -42,11 +69,44 @@ fn main() -> Result<()> {
         .init()
         .unwrap();
 
+    for d in &diags {
+        log::warn!("startup check {}: {}", d.check, d.message);
+    }
+
+    // 任意线程panic时生成崩溃报告，并恢复终端
+    crash::init(&config, data_dir.log_path(""));
+    crash::install_panic_hook();
+    mudterm::ui::terminal::install_panic_hook();
+
     log::info!("starting mudterm in {:?} mode", config.mode);
 
-    match config.mode {
+    let res = match config.mode {
         Mode::Standalone => app::standalone(config),
         Mode::Server => app::server(config),
         Mode::Client => app::client(config),
+    };
+    if let Err(e) = &res {
+        crash::report(&format!("fatal error: {}", e));
+    }
+    res
+}
+
+// 日志文件所在的目录，即当前模式下会话日志的上级目录
+fn logs(config: &Config, data_dir: &DataDir, serve: bool, port: u16) -> Result<()> {
+    let log_file = match config.mode {
+        Mode::Client => &config.client.log_file,
+        Mode::Standalone | Mode::Server => &config.server.log_file,
+    };
+    let log_path = data_dir.log_path(log_file);
+    let dir = match log_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => std::path::PathBuf::from("."),
+    };
+    if serve {
+        return logview::serve(&dir, port);
+    }
+    for file in logview::index(&dir)? {
+        println!("{}  {:>10}  {}", logview::datetime(file.modified), file.size, file.name);
     }
+    Ok(())
 }
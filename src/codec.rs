@@ -1,9 +1,11 @@
+use crate::conf::EncodeFallback;
 use crate::error::{Error, Result};
 use encoding::codec::simpchinese::GB18030_ENCODING;
 use encoding::codec::tradchinese::BigFive2003Encoding;
 use encoding::codec::utf_8::UTF8Decoder;
 use encoding::types::{CodecError, RawDecoder};
 use encoding::{EncoderTrap, Encoding};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Codec {
@@ -70,6 +72,122 @@ impl Encoder {
     pub fn switch_codec(&mut self, code: Codec) {
         self.0 = code;
     }
+
+    /// 服务器编码能否表示该字符
+    ///
+    /// 多数服务器仅支持GBK，因此GB18030中的四字节字符视为不支持
+    pub fn supports(&self, c: char) -> bool {
+        if c.is_ascii() {
+            return true;
+        }
+        let mut buf = [0u8; 4];
+        let s: &str = c.encode_utf8(&mut buf);
+        match self.0 {
+            Codec::Utf8 => true,
+            Codec::Gb18030 => matches!(
+                GB18030_ENCODING.encode(s, EncoderTrap::Strict),
+                Ok(bs) if bs.len() <= 2
+            ),
+            Codec::Big5 => BigFive2003Encoding.encode(s, EncoderTrap::Strict).is_ok(),
+        }
+    }
+}
+
+/// 发送前处理服务器编码无法表示的字符
+#[derive(Debug, Clone)]
+pub struct Fallback {
+    mode: EncodeFallback,
+    // 用户配置的转写对照表
+    table: HashMap<char, String>,
+}
+
+impl Fallback {
+    pub fn new(mode: EncodeFallback, table: &HashMap<String, String>) -> Self {
+        let table = table
+            .iter()
+            .filter_map(|(k, v)| {
+                let mut chars = k.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some((c, v.to_owned())),
+                    _ => {
+                        log::warn!("ignore translit key {} with multiple chars", k);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { mode, table }
+    }
+
+    /// 替换或转写无法编码的字符，拒绝发送时返回这些字符
+    pub fn apply(&self, encoder: &Encoder, input: String) -> std::result::Result<String, Vec<char>> {
+        if input.chars().all(|c| encoder.supports(c)) {
+            return Ok(input);
+        }
+        let mut output = String::with_capacity(input.len());
+        let mut rejected = vec![];
+        for c in input.chars() {
+            if encoder.supports(c) {
+                output.push(c);
+                continue;
+            }
+            match self.mode {
+                EncodeFallback::Replace => output.push('?'),
+                EncodeFallback::Translit => match self.translit(c) {
+                    Some(s) if s.chars().all(|c| encoder.supports(c)) => output.push_str(s),
+                    _ => output.push('?'),
+                },
+                EncodeFallback::Reject => {
+                    if !rejected.contains(&c) {
+                        rejected.push(c);
+                    }
+                }
+            }
+        }
+        if rejected.is_empty() {
+            Ok(output)
+        } else {
+            Err(rejected)
+        }
+    }
+
+    fn translit(&self, c: char) -> Option<&str> {
+        if let Some(s) = self.table.get(&c) {
+            return Some(s);
+        }
+        let s = match c {
+            '\u{a0}' | '\u{2002}'..='\u{200a}' => " ",
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => "-",
+            '\u{2018}' | '\u{2019}' | '\u{201a}' => "'",
+            '\u{201c}' | '\u{201d}' | '\u{201e}' => "\"",
+            '\u{2022}' => "*",
+            '\u{2026}' => "...",
+            '\u{2764}' | '\u{2665}' => "<3",
+            '\u{1f44d}' => "(y)",
+            '\u{1f600}'..='\u{1f60f}' | '\u{1f642}' | '\u{263a}' => ":)",
+            '\u{1f610}'..='\u{1f61f}' | '\u{1f641}' | '\u{2639}' => ":(",
+            // 带变音符号的拉丁字母转写为基本字母
+            'À'..='Å' => "A",
+            'à'..='å' => "a",
+            'Ç' => "C",
+            'ç' => "c",
+            'È'..='Ë' => "E",
+            'è'..='ë' => "e",
+            'Ì'..='Ï' => "I",
+            'ì'..='ï' => "i",
+            'Ñ' => "N",
+            'ñ' => "n",
+            'Ò'..='Ö' | 'Ø' => "O",
+            'ò'..='ö' | 'ø' => "o",
+            'Ù'..='Ü' => "U",
+            'ù'..='ü' => "u",
+            'Ý' => "Y",
+            'ý' | 'ÿ' => "y",
+            'ß' => "ss",
+            _ => return None,
+        };
+        Some(s)
+    }
 }
 
 pub struct MudCodec {
@@ -127,6 +245,25 @@ mod tests {
         assert_eq!(3, pos.unwrap());
     }
 
+    #[test]
+    fn test_encode_fallback() {
+        let mut encoder = Encoder::default();
+        let mut table = HashMap::new();
+        table.insert("说".to_owned(), "shuo".to_owned());
+        let replace = Fallback::new(EncodeFallback::Replace, &table);
+        let translit = Fallback::new(EncodeFallback::Translit, &table);
+        let reject = Fallback::new(EncodeFallback::Reject, &table);
+        assert!(encoder.supports('张'));
+        assert!(!encoder.supports('😀'));
+        assert_eq!(Ok("say ?".to_owned()), replace.apply(&encoder, "say 😀".to_owned()));
+        assert_eq!(Ok("say :) ?".to_owned()), translit.apply(&encoder, "say 😀 🐉".to_owned()));
+        assert_eq!(Err(vec!['😀', '🐉']), reject.apply(&encoder, "😀🐉😀".to_owned()));
+        assert_eq!(Ok("张三".to_owned()), reject.apply(&encoder, "张三".to_owned()));
+        // 简体字无法以Big5编码
+        encoder.switch_codec(Codec::Big5);
+        assert_eq!(Ok("shuo".to_owned()), translit.apply(&encoder, "说".to_owned()));
+    }
+
     #[test]
     fn test_gb18030() {
         let mut mc = MudCodec::new();
@@ -1,4 +1,7 @@
+use crate::i18n::Lang;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -9,18 +12,60 @@ pub struct Config {
     pub server: Server,
     pub client: Client,
     pub runtime: Runtime,
+    pub term: Term,
+    pub protocol: Protocol,
+    pub routes: Vec<Route>,
+    // 行分类的正则列表，如chat = ["^【闲聊】"]，供行路由及脚本共用
+    pub classify: BTreeMap<String, Vec<String>>,
+    pub prompt: Prompt,
+    pub media: Media,
+    pub export: Export,
+    pub log: Log,
+    pub trigger: Vec<SendRule>,
+    pub alias: Vec<SendRule>,
+    // 配置文件路径，由命令行参数指定，供#set保存设置
+    #[serde(skip)]
+    pub conf_file: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct World {
     pub addr: String,
+    // 世界名称，用于区分数据目录
+    pub name: String,
+    // 数据目录根路径，为空时使用XDG数据目录
+    pub data_dir: String,
+    // 向服务器写入的超时秒数，0表示不限制
+    pub write_timeout_secs: u64,
+    // 断线后自动重连，间隔从初始秒数开始每次翻倍，不超过最大秒数
+    pub reconnect: bool,
+    pub reconnect_initial_secs: u64,
+    pub reconnect_max_secs: u64,
+    // 最大连续重连次数，0表示不限制
+    pub reconnect_max_attempts: u32,
+}
+
+impl World {
+    pub fn write_timeout(&self) -> Option<Duration> {
+        if self.write_timeout_secs == 0 {
+            return None;
+        }
+        Some(Duration::from_secs(self.write_timeout_secs))
+    }
 }
 
 impl Default for World {
     fn default() -> Self {
         Self {
             addr: String::from("mud.pkuxkx.net:8080"),
+            name: String::new(),
+            data_dir: String::new(),
+            write_timeout_secs: 10,
+            reconnect: true,
+            reconnect_initial_secs: 2,
+            reconnect_max_secs: 120,
+            reconnect_max_attempts: 0,
         }
     }
 }
@@ -28,7 +73,13 @@ impl Default for World {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Server {
+    // 监听地址，可为IPv4或IPv6地址，如"::"
+    pub bind: String,
     pub port: u16,
+    // 额外的监听端口
+    pub extra_ports: Vec<u16>,
+    // 允许连接的客户端网段（CIDR），为空时不限制，在认证前检查
+    pub allow: Vec<String>,
     pub log_file: String,
     pub debug_file: String,
     pub client_init_max_lines: usize,
@@ -38,7 +89,10 @@ pub struct Server {
 impl Default for Server {
     fn default() -> Self {
         Self {
+            bind: String::from("0.0.0.0"),
             port: 9680,
+            extra_ports: vec![],
+            allow: vec![],
             log_file: String::from("server.log"),
             debug_file: String::from("debug.log"),
             client_init_max_lines: 100,
@@ -70,29 +124,562 @@ impl Default for Client {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Runtime {
-    // pub echo_cmd: bool,
+    // 界面语言，zh-CN或en-US
+    pub lang: Lang,
+    // 是否将发送的命令回显到界面，以空格开头的命令不回显
+    pub echo_cmd: bool,
+    pub echo_prefix: String,
+    pub echo_color: String,
+    // 回显是否写入日志
+    pub echo_log: bool,
+    // 回显合并到之前未结束的提示符行，如“hp> kill rat”，合并时不加前缀；
+    // 关闭时回显另起一行。配置了prompt时仅合并匹配提示符的行
+    pub echo_merge: bool,
     pub cmd_delim: char,
+    // 命令中的等待标记，如"look;#wait 500;n"在发送look后等待500毫秒再执行其余命令，为空时关闭
+    pub wait_token: String,
     pub send_empty_cmd: bool,
+    // 命令行为空时按回车重发上一条命令，脚本可设置禁止重发的命令
+    pub repeat_last_on_empty: bool,
+    // 别名嵌套调用的最大深度，超过后停止展开并提示
+    pub max_alias_depth: usize,
     pub init_script: String,
+    // 额外的初始化脚本，在init_script之后按顺序加载
+    pub init_scripts: Vec<String>,
+    // 自动加载目录，位于世界的scripts目录，其中的*.lua在初始化脚本之后按文件名顺序加载，
+    // 单个文件出错不影响其他文件，可通过#reload scripts重新加载
+    pub autoload_dir: String,
+    // 触发器、别名及定时器的定义文件，位于世界的scripts目录，TOML或JSON格式，可通过#reload重新加载
+    pub def_files: Vec<String>,
+    pub map_db: String,
+    // 启动时加载全部地图数据，关闭时按区域延迟加载，适用于非常大的地图数据库
+    pub map_preload: bool,
+    // 延迟加载时缓存的区域数
+    pub map_zone_cache: usize,
+    // 重复命令保护
+    pub dup_guard: DupGuard,
+    // 从提示符中解析服务器命令队列长度，控制命令发送节奏
+    pub queue_tag: QueueTag,
+    // 服务器编码无法表示的字符（如GBK中的emoji）的处理方式
+    pub encode_fallback: EncodeFallback,
+    // 单独的回车（包括"\r\0"）的处理方式
+    pub newline: NewlineMode,
+    // 世界文本每个周期的处理上限
+    pub frame: Frame,
+    // 转写对照表，如{"😀" = ":)", "啰" = "luo"}，优先于内置对照表
+    pub encode_translit: HashMap<String, String>,
+    // 系统剪贴板复制及粘贴命令，如"xclip -selection clipboard"，为空时不同步
+    pub clipboard_copy_cmd: String,
+    pub clipboard_paste_cmd: String,
+    // 保留的文本处理轨迹条数，为0时不记录
+    pub trace_capacity: usize,
+    // 发送hp、score、skills等命令时自动解析返回的状态界面
+    pub status_parser: bool,
+    // 世界变量的持久化文件，位于世界的state目录，为空时不保存
+    pub vars_file: String,
+    // 全局变量的持久化文件，位于数据目录根下，各世界共享，为空时不保存
+    pub global_vars_file: String,
+    // 变量修改日志同步至磁盘的最小间隔，断电时最多丢失该间隔内的修改
+    pub vars_sync_ms: u64,
+    // 断线期间暂存用户命令的文件，位于世界的state目录，重连并登录后发送，为空时直接丢弃
+    pub offline_queue: String,
+}
+
+impl Runtime {
+    /// 按加载顺序排列的所有初始化脚本
+    pub fn all_init_scripts(&self) -> Vec<String> {
+        std::iter::once(&self.init_script)
+            .chain(self.init_scripts.iter())
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect()
+    }
 }
 
 impl Default for Runtime {
     fn default() -> Self {
         Self {
-            // echo_cmd: false,
+            lang: Lang::default(),
+            echo_cmd: false,
+            echo_prefix: String::from("> "),
+            echo_color: String::from("yellow"),
+            echo_log: false,
+            echo_merge: false,
             cmd_delim: ';',
+            wait_token: String::from("#wait"),
             send_empty_cmd: false,
+            repeat_last_on_empty: false,
+            max_alias_depth: 10,
             init_script: String::new(),
+            init_scripts: Vec::new(),
+            autoload_dir: String::from("scripts.d"),
+            def_files: Vec::new(),
+            map_db: String::new(),
+            map_preload: true,
+            map_zone_cache: 32,
+            dup_guard: DupGuard::default(),
+            queue_tag: QueueTag::default(),
+            encode_fallback: EncodeFallback::Replace,
+            newline: NewlineMode::Keep,
+            frame: Frame::default(),
+            encode_translit: HashMap::new(),
+            clipboard_copy_cmd: String::new(),
+            clipboard_paste_cmd: String::new(),
+            trace_capacity: 200,
+            status_parser: false,
+            vars_file: String::new(),
+            global_vars_file: String::new(),
+            vars_sync_ms: 1000,
+            offline_queue: String::new(),
+        }
+    }
+}
+
+/// 世界文本的处理上限
+///
+/// 服务器一次输出大量文本时，每个周期只处理有限的行，
+/// 剩余的行在后续周期处理，期间仍能及时响应用户输入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Frame {
+    pub tick_ms: u64,
+    // 每个周期最多处理的行数及字节数，为0时不限制
+    pub max_lines: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            tick_ms: 50,
+            max_lines: 500,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// 重复命令保护
+///
+/// 在指定间隔内再次输入完全相同的命令时，拦截或要求确认，
+/// 匹配白名单的命令不受限制
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DupGuard {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    pub action: DupAction,
+    pub whitelist: Vec<String>,
+}
+
+impl Default for DupGuard {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 500,
+            action: DupAction::Confirm,
+            whitelist: vec![],
+        }
+    }
+}
+
+/// 服务器命令队列标记
+///
+/// 部分服务器在提示符中显示尚未执行的命令数，如“[队列:3]>”，
+/// 通过正则及捕获组提取该数值，队列满时暂存后续命令，直到服务器报告队列缩短
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueTag {
+    // 为空时关闭
+    pub pattern: String,
+    // 队列长度所在的捕获组
+    pub group: usize,
+    // 保存队列长度的变量名
+    pub var: String,
+    // 服务器队列的最大长度，达到后暂停发送
+    pub max_depth: usize,
+}
+
+impl Default for QueueTag {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            group: 1,
+            var: String::from("queue_depth"),
+            max_depth: 10,
+        }
+    }
+}
+
+/// 提示符解析
+///
+/// 用带命名捕获组的正则匹配提示符，如“hp:(?P<hp>\d+)/(?P<hp_max>\d+)”，
+/// 匹配后各组的值写入同名变量，并按格式更新状态栏，无需编写触发器
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prompt {
+    // 匹配提示符（未以换行结束的行）的正则，为空时关闭
+    pub pattern: String,
+    // 变量名前缀，如“prompt.”，为空时直接使用组名
+    pub var_prefix: String,
+    // 状态栏格式，{组名}替换为捕获的值，为空时不更新状态栏；
+    // 未开启服务器状态栏时占用命令栏上方一行，开启时不显示
+    pub status: String,
+}
+
+/// 服务器发送的MSP声音及音乐指令，如“!!SOUND(hit.wav V=80)”
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Media {
+    // 从文本中删除指令，仅含指令的行不显示
+    pub gag: bool,
+    // 播放命令，通过环境变量MUD_MEDIA_FILE及MUD_MEDIA_VOLUME获取文件路径及音量，
+    // 如“paplay \"$MUD_MEDIA_FILE\"”，为空时不播放
+    pub play_cmd: String,
+    // 声音文件目录，位于世界的state目录，指令中的类型为其子目录
+    pub sound_dir: String,
+}
+
+impl Default for Media {
+    fn default() -> Self {
+        Self {
+            gag: true,
+            play_cmd: String::new(),
+            sound_dir: String::from("sounds"),
         }
     }
 }
 
+/// 定期导出变量，供外部程序（如直播软件的叠加层）显示状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Export {
+    // 导出目标，为位于世界state目录的JSON文件，或以“unix:”开头的本地套接字路径，
+    // 为空时不导出
+    pub target: String,
+    // 导出的变量名，以*结尾时按前缀匹配，如“hp*”
+    pub vars: Vec<String>,
+    // 检查变量变化的间隔，内容变化时才写入
+    pub interval_ms: u64,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            target: String::new(),
+            vars: Vec::new(),
+            interval_ms: 1000,
+        }
+    }
+}
+
+/// 世界文本日志的格式及轮转
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Log {
+    // 去除ANSI转义序列，保存为纯文本
+    pub plain: bool,
+    // 每行开头添加本地时间
+    pub timestamp: bool,
+    // 每次启动使用新文件，文件名附加启动时间
+    pub session: bool,
+    pub rotate: LogRotate,
+    // 按大小轮转时单个文件的最大字节数
+    pub max_size: u64,
+    // 保留的历史文件数，为0时不删除
+    pub keep: usize,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            plain: false,
+            timestamp: false,
+            session: false,
+            rotate: LogRotate::None,
+            max_size: 10 * 1024 * 1024,
+            keep: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LogRotate {
+    // 不轮转
+    #[serde(rename = "none")]
+    None,
+    // 超过max_size时轮转
+    #[serde(rename = "size")]
+    Size,
+    // 日期变化时轮转
+    #[serde(rename = "daily")]
+    Daily,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DupAction {
+    // 直接丢弃重复命令
+    #[serde(rename = "suppress")]
+    Suppress,
+    // 暂存重复命令，输入#confirm后发送
+    #[serde(rename = "confirm")]
+    Confirm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EncodeFallback {
+    // 替换为?
+    #[serde(rename = "replace")]
+    Replace,
+    // 按对照表转写，无对应时替换为?
+    #[serde(rename = "translit")]
+    Translit,
+    // 拒绝发送整条命令，并提示无法编码的字符
+    #[serde(rename = "reject")]
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NewlineMode {
+    // 不做处理
+    #[serde(rename = "keep")]
+    Keep,
+    // 删除单独的回车
+    #[serde(rename = "strip")]
+    Strip,
+    // 单独的回车视为换行
+    #[serde(rename = "break")]
+    Break,
+    // 单独的回车后的文本覆盖当前行，用于服务器刷新进度等
+    #[serde(rename = "overwrite")]
+    Overwrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Term {
+    // 分屏时上方窗格显示路由到该窗口的文本
+    pub chat_window: String,
+    // 分屏时上方窗格的高度
+    pub chat_height: u16,
+    // 界面主题，键为样式角色：base、border、cmdbar、script、flow、gutter、menu
+    pub theme: HashMap<String, ThemeStyle>,
+    // 超出宽度的行折行显示，关闭时截断
+    pub wrap: bool,
+    // 主窗格中每个新行开头显示收到时的本地时间
+    pub timestamps: bool,
+    // 翻阅历史时有新文本到达则回到最新的行
+    pub follow: bool,
+    // 折行处于不短于该长度的连续ASCII串（如链接）中间时，行尾显示连字符，0表示关闭
+    pub hyphen_after: usize,
+    // 朗读命令，设置后每个完整的行以纯文本写入该命令的标准输入，供读屏或TTS程序使用
+    pub announce_cmd: String,
+    // 朗读的类别：world、chat、system，为空时全部朗读
+    pub announce_categories: Vec<String>,
+    // 每秒最多朗读的行数，超出的行被丢弃，0表示不限制
+    pub announce_rate: u32,
+    // 服务器状态栏的最大行数，大于0时将光标定位绘制的文本显示在命令栏上方，0表示关闭
+    pub server_status_rows: u16,
+    // 界面布局，终端大小变化时重新计算
+    pub layout: Layout,
+    // 歧义宽度字符（如·、±、─）按两列计算，终端使用等宽中文字体时应关闭，可按F4切换
+    pub cjk_width: bool,
+    // 向上翻阅历史时底部继续显示最新文本的行数，0表示不分屏
+    pub scroll_live_rows: u16,
+    // 持久化命令历史的文件，位于状态目录下，为空时不保存
+    pub history_file: String,
+    // 保留的历史命令数
+    pub history_size: usize,
+    // REPL窗格的输入历史文件，与命令历史分开保存，为空时不保存
+    pub repl_history_file: String,
+    // 鼠标上报：auto按终端能力开启，off时不捕获鼠标，可使用终端原生的选择及复制，
+    // 滚轮翻阅可由PageUp/PageDown及Alt-k/Alt-j代替
+    pub mouse: MouseMode,
+}
+
+impl Default for Term {
+    fn default() -> Self {
+        Self {
+            chat_window: String::from("chat"),
+            chat_height: 8,
+            theme: HashMap::new(),
+            wrap: true,
+            timestamps: false,
+            follow: false,
+            hyphen_after: 0,
+            announce_cmd: String::new(),
+            announce_categories: vec![],
+            announce_rate: 5,
+            server_status_rows: 0,
+            layout: Layout::default(),
+            cjk_width: true,
+            scroll_live_rows: 5,
+            history_file: String::new(),
+            history_size: 200,
+            repl_history_file: String::from("repl_history.jsonl"),
+            mouse: MouseMode::Auto,
+        }
+    }
+}
+
+/// 界面布局，空间不足时依次缩小状态栏、聊天窗格及留白
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    // 命令区高度，含上下边框，至少3行
+    pub cmd_height: u16,
+    // 主窗格及聊天窗格左右两侧的留白列数
+    pub margin_left: u16,
+    pub margin_right: u16,
+    // 大于0时分屏的聊天窗格以该宽度显示在主窗格右侧，0表示显示在上方
+    pub chat_side_width: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            cmd_height: 3,
+            margin_left: 0,
+            margin_right: 0,
+            chat_side_width: 0,
+        }
+    }
+}
+
+/// Telnet协议选项的协商方式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Protocol {
+    pub mxp: Negotiate,
+    pub mccp: Negotiate,
+    pub gmcp: Negotiate,
+    pub msdp: Negotiate,
+    pub naws: Negotiate,
+    pub echo: Negotiate,
+    // NAWS报告的窗口大小
+    pub naws_width: u16,
+    pub naws_height: u16,
+    // GMCP启用后通过Core.Supports.Set请求的包及版本，如"Char 1"
+    pub gmcp_supports: Vec<String>,
+    // MSDP启用后通过REPORT请求服务器在变化时发送的变量
+    pub msdp_reports: Vec<String>,
+    // 连接后测量延迟及包大小的秒数，0表示不测量
+    pub probe_secs: u64,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self {
+            mxp: Negotiate::Auto,
+            mccp: Negotiate::Auto,
+            gmcp: Negotiate::Auto,
+            msdp: Negotiate::Auto,
+            naws: Negotiate::Auto,
+            echo: Negotiate::Auto,
+            naws_width: 80,
+            naws_height: 24,
+            gmcp_supports: vec!["Char 1".to_owned(), "Room 1".to_owned(), "Comm 1".to_owned()],
+            msdp_reports: ["HEALTH", "HEALTH_MAX", "MANA", "MANA_MAX", "ROOM"].iter().map(|s| (*s).to_owned()).collect(),
+            probe_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Negotiate {
+    // 服务器提出时接受
+    #[serde(rename = "auto")]
+    Auto,
+    // 连接后主动请求
+    #[serde(rename = "on")]
+    On,
+    // 始终拒绝
+    #[serde(rename = "off")]
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MouseMode {
+    // 终端支持时开启
+    #[serde(rename = "auto")]
+    Auto,
+    // 始终开启
+    #[serde(rename = "on")]
+    On,
+    // 始终关闭
+    #[serde(rename = "off")]
+    Off,
+}
+
+/// 主题中单个角色的样式，未设置的颜色继承自上级角色
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeStyle {
+    pub fg: String,
+    pub bg: String,
+}
+
+/// 服务器文本路由规则，按配置顺序匹配，先于触发器执行
+///
+/// 匹配条件为pattern或class之一，class为[classify]中定义的行分类
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub class: String,
+    pub action: RouteAction,
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RouteAction {
+    #[serde(rename = "window")]
+    Window,
+    #[serde(rename = "log")]
+    Log,
+    #[serde(rename = "gag")]
+    Gag,
+}
+
+/// 配置文件中定义的触发器或别名，匹配后发送命令
+///
+/// send中支持%1..%9及%<name>引用捕获的文本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendRule {
+    #[serde(default)]
+    pub name: String,
+    pub pattern: String,
+    pub send: String,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
 pub struct CmdOpts {
     #[structopt(short, long, default_value = "mud.toml")]
     pub conf_file: String,
     #[structopt(short, long, default_value = "info")]
     pub log_level: String,
+    #[structopt(subcommand)]
+    pub cmd: Option<SubCmd>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub enum SubCmd {
+    /// 列出日志目录中的文件，指定--serve时在本机端口提供网页浏览及搜索
+    Logs {
+        #[structopt(long)]
+        serve: bool,
+        #[structopt(short, long, default_value = "8080")]
+        port: u16,
+    },
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -129,4 +716,63 @@ mod tests {
         let s = toml::to_string(&m).unwrap();
         println!("{}", s);
     }
+
+    #[test]
+    fn test_toml_deserialize_routes() {
+        let s = r#"
+        [[routes]]
+        pattern = "^【闲聊】"
+        action = "window"
+        target = "chat"
+
+        [[routes]]
+        pattern = "^你对.*发起了攻击"
+        action = "gag"
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(2, config.routes.len());
+        assert_eq!(RouteAction::Window, config.routes[0].action);
+        assert_eq!("chat", config.routes[0].target);
+        assert_eq!(RouteAction::Gag, config.routes[1].action);
+        assert!(config.routes[1].target.is_empty());
+    }
+
+    #[test]
+    fn test_toml_deserialize_dup_guard() {
+        let s = r#"
+        [runtime.dup_guard]
+        enabled = true
+        action = "suppress"
+        whitelist = ["^(n|s|e|w)$"]
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        let guard = &config.runtime.dup_guard;
+        assert!(guard.enabled);
+        assert_eq!(500, guard.interval_ms);
+        assert_eq!(DupAction::Suppress, guard.action);
+        assert_eq!(1, guard.whitelist.len());
+    }
+
+    #[test]
+    fn test_toml_deserialize_send_rules() {
+        let s = r#"
+        [[trigger]]
+        pattern = "^(.*)走了过来。$"
+        send = "hi %1"
+
+        [[alias]]
+        name = "kk"
+        pattern = "^kk (.*)$"
+        send = "kill %1;perform"
+        group = "fight"
+        enabled = false
+        "#;
+        let config: Config = toml::from_str(s).unwrap();
+        assert_eq!(1, config.trigger.len());
+        assert!(config.trigger[0].enabled);
+        assert!(config.trigger[0].name.is_empty());
+        assert_eq!(1, config.alias.len());
+        assert_eq!("fight", config.alias[0].group);
+        assert!(!config.alias[0].enabled);
+    }
 }
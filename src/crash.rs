@@ -0,0 +1,191 @@
+use crate::conf::Config;
+use crate::error::Result;
+use crate::runtime::trace::Trace;
+use lazy_static::lazy_static;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 报告中保留的最近原始行数及处理轨迹数
+const MAX_LINES: usize = 200;
+const MAX_TRACES: usize = 50;
+// 键名包含以下内容的配置项视为敏感信息
+const SECRET_KEYS: &[&str] = &["pass", "secret", "token"];
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // 报告所在目录，为空时使用当前目录
+    dir: PathBuf,
+    // 已隐去敏感信息的配置
+    config: String,
+    lines: VecDeque<String>,
+    traces: VecDeque<Trace>,
+}
+
+/// 设置报告目录及配置，配置中的密码等敏感信息被替换
+pub fn init(config: &Config, dir: PathBuf) {
+    let config = redacted_config(config);
+    let mut state = lock();
+    state.dir = dir;
+    state.config = config;
+}
+
+/// 记录服务器原始文本及处理轨迹
+///
+/// 由各引擎缓冲后批量写入，避免每行都获取全局锁；轨迹应已隐去命令文本
+pub fn record(lines: impl IntoIterator<Item = String>, traces: impl IntoIterator<Item = Trace>) {
+    let mut state = lock();
+    for line in lines {
+        if state.lines.len() >= MAX_LINES {
+            state.lines.pop_front();
+        }
+        state.lines.push_back(line);
+    }
+    for trace in traces {
+        if state.traces.len() >= MAX_TRACES {
+            state.traces.pop_front();
+        }
+        state.traces.push_back(trace);
+    }
+}
+
+/// 生成崩溃报告并写入文件，返回文件路径
+pub fn write_report(reason: &str) -> Result<PathBuf> {
+    let (path, report) = {
+        let state = lock();
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let path = state.dir.join(format!("crash-{}.txt", time));
+        (path, render(&state, time, reason))
+    };
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// 安装panic钩子，任意线程panic时生成崩溃报告并显示其路径
+///
+/// 应在恢复终端的钩子之前安装，使报告路径显示在恢复后的终端上
+pub fn install_panic_hook() {
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let reason = format!("panicked: {}\n\n{}", info, Backtrace::force_capture());
+        report(&reason);
+        prev(info);
+    }));
+}
+
+/// 生成崩溃报告并提示用户，用于无法恢复的错误
+pub fn report(reason: &str) {
+    let mut out = io::stdout();
+    match write_report(reason) {
+        Ok(path) => {
+            log::error!("crash report written to {}", path.display());
+            let _ = writeln!(out, "crash report written to {}", path.display());
+        }
+        Err(e) => {
+            log::error!("write crash report error {}", e);
+            let _ = writeln!(out, "failed to write crash report: {}", e);
+        }
+    }
+    let _ = out.flush();
+}
+
+// 钩子中可能在持有锁的线程panic，忽略锁中毒
+fn lock() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn render(state: &State, time: u128, reason: &str) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "mudterm crash report");
+    let _ = writeln!(s, "time: {}", time);
+    let _ = writeln!(
+        s,
+        "version: mudterm {} ({}-{})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let _ = writeln!(s, "reason: {}", reason);
+    let _ = writeln!(s, "\n== config ==\n{}", state.config);
+    let _ = writeln!(s, "== trace ==");
+    for trace in &state.traces {
+        for line in trace.format() {
+            let _ = writeln!(s, "{}", line);
+        }
+    }
+    let _ = writeln!(s, "\n== lines ==");
+    for line in &state.lines {
+        let _ = writeln!(s, "{}", line.trim_end_matches(&['\r', '\n'][..]));
+    }
+    s
+}
+
+// 序列化配置，并替换敏感项的值
+fn redacted_config(config: &Config) -> String {
+    let mut value = match toml::Value::try_from(config) {
+        Ok(value) => value,
+        Err(e) => return format!("<serialize config error {}>", e),
+    };
+    redact(&mut value);
+    toml::to_string(&value).unwrap_or_else(|e| format!("<serialize config error {}>", e))
+}
+
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_KEYS.iter().any(|k| key.contains(k)) {
+                    if let toml::Value::String(s) = value {
+                        if !s.is_empty() {
+                            *s = "<redacted>".to_owned();
+                        }
+                    }
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_crash_report() {
+        let mut config = Config::default();
+        config.server.pass = "hunter2".to_owned();
+        config.client.server_pass = "hunter3".to_owned();
+        let dir = TempDir::new("crash");
+        init(&config, dir.path().to_path_buf());
+        record((0..MAX_LINES + 5).map(|i| format!("line {}\r\n", i)), vec![]);
+        let path = write_report("test failure").unwrap();
+        let report = fs::read_to_string(&path).unwrap();
+        assert!(path.starts_with(dir.path()));
+        assert!(report.contains("reason: test failure"));
+        assert!(report.contains(env!("CARGO_PKG_VERSION")));
+        assert!(report.contains("<redacted>"));
+        assert!(!report.contains("hunter2") && !report.contains("hunter3"));
+        // 仅保留最近的行
+        assert!(!report.contains("\nline 4\n"));
+        assert!(report.contains(&format!("line {}\n", MAX_LINES + 4)));
+    }
+}
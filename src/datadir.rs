@@ -0,0 +1,135 @@
+use crate::conf::Config;
+use crate::error::{Error, Result};
+use crate::i18n;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 世界数据目录
+///
+/// 每个世界（以world.name区分）拥有独立的目录：
+/// <data_dir>/<world.name>/{logs,scripts,state}
+/// 未配置data_dir时遵循XDG规范，使用$XDG_DATA_HOME/mudterm，
+/// 或~/.local/share/mudterm。
+/// 未配置world.name时不启用数据目录，相对路径仍相对于当前目录。
+#[derive(Debug, Clone, Default)]
+pub struct DataDir {
+    root: Option<PathBuf>,
+}
+
+impl DataDir {
+    pub fn new(config: &Config) -> Self {
+        if config.world.name.is_empty() {
+            return Self::default();
+        }
+        let base = if !config.world.data_dir.is_empty() {
+            PathBuf::from(&config.world.data_dir)
+        } else if let Some(xdg) = env::var_os("XDG_DATA_HOME").filter(|s| !s.is_empty()) {
+            PathBuf::from(xdg).join("mudterm")
+        } else if let Some(home) = env::var_os("HOME") {
+            PathBuf::from(home).join(".local/share/mudterm")
+        } else {
+            PathBuf::from(".")
+        };
+        Self {
+            root: Some(base.join(&config.world.name)),
+        }
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// 创建所有子目录
+    pub fn create_all(&self) -> Result<()> {
+        if let Some(root) = &self.root {
+            for sub in &["logs", "scripts", "state"] {
+                fs::create_dir_all(root.join(sub))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 解析日志文件路径
+    pub fn log_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.resolve("logs", path.as_ref())
+    }
+
+    /// 解析脚本文件路径
+    pub fn script_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.resolve("scripts", path.as_ref())
+    }
+
+    /// 解析状态文件路径，如地图数据库
+    pub fn state_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.resolve("state", path.as_ref())
+    }
+
+    /// 解析各世界共享的文件路径，位于数据目录根下
+    pub fn global_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        match self.root.as_deref().and_then(Path::parent) {
+            Some(base) if path.is_relative() => base.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    fn resolve(&self, sub: &str, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) if path.is_relative() => root.join(sub).join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// 检查用作目录或文件名的名称，如会话名称、宏名称，不能为空或包含路径分隔符及..
+pub fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains("..") || name.contains(|c: char| c == '/' || c == '\\') {
+        return Err(Error::RuntimeError(i18n::trf("err.invalid_name", &[&name])));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_dir_resolve() {
+        let mut config = Config::default();
+        let dd = DataDir::new(&config);
+        assert_eq!(PathBuf::from("server.log"), dd.log_path("server.log"));
+
+        config.world.name = String::from("pkuxkx");
+        config.world.data_dir = String::from("/tmp/mudterm");
+        let dd = DataDir::new(&config);
+        assert_eq!(
+            PathBuf::from("/tmp/mudterm/pkuxkx/logs/server.log"),
+            dd.log_path("server.log")
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/mudterm/pkuxkx/scripts/init.lua"),
+            dd.script_path("init.lua")
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/mudterm/pkuxkx/state/map.db"),
+            dd.state_path("map.db")
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/mudterm/global_vars.json"),
+            dd.global_path("global_vars.json")
+        );
+        // 绝对路径不做处理
+        assert_eq!(PathBuf::from("/etc/init.lua"), dd.script_path("/etc/init.lua"));
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("pkuxkx").is_ok());
+        assert!(check_name("北大侠客行").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("../../x").is_err());
+        assert!(check_name("a/b").is_err());
+        assert!(check_name("a\\b").is_err());
+    }
+}
@@ -30,12 +30,18 @@ pub enum Error {
     PacketError(String),
     #[error("Toml error {0}")]
     TomlError(#[from] toml::de::Error),
+    #[error("Toml serialize error {0}")]
+    TomlSerError(#[from] toml::ser::Error),
     #[error("Auth error")]
     AuthError,
     #[error("Compile script error {0}")]
     CompileScriptError(String),
     #[error("Database error {0}")]
     DatabaseError(#[from] rusqlite::Error),
+    #[error("Json error {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Health check failed with {0} errors")]
+    HealthCheckError(usize),
 }
 
 impl<T> From<crossbeam_channel::SendError<T>> for Error {
@@ -1,24 +1,43 @@
 use crate::error::Result;
-use crate::runtime::{Engine, RuntimeOutputHandler};
+use crate::probe::ProbeReport;
+use crate::runtime::{Engine, RuntimeOutput, RuntimeOutputHandler};
 use crate::runtime::timer::Timer;
 use crate::runtime::delay_queue::Delay;
+use crate::telnet::Protocols;
 use crate::ui::line::RawLine;
 use crate::ui::UserOutput;
-use crossbeam_channel::Receiver;
+use crate::userinput::PasteChoices;
+use crossbeam_channel::{unbounded, Receiver, RecvError, RecvTimeoutError, Sender};
 use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
 use termion::event::{Key, MouseEvent};
 
 #[derive(Debug)]
 pub enum Event {
-    /// raw bytes received from server
+    /// raw bytes received from server, with connection id
     /// decode it in main loop so that we can
     /// handle codec switching peacefully
-    WorldBytes(Vec<u8>),
+    WorldBytes(u32, Vec<u8>),
     /// lines from server with tui style
     // StyledLinesFromMud(VecDeque<StyledLine>),
     // WorldLines(Vec<RawLine>),
-    // world disconnected, e.g idle for a lone time
-    WorldDisconnected,
+    // world disconnected, e.g idle for a lone time, with connection id
+    WorldDisconnected(u32),
+    // failed to write to world, e.g. write timed out on a stalled connection
+    WorldWriteError(u32, String),
+    // 重连成功，附带重连序号及新连接
+    WorldReconnected(u32, TcpStream),
+    // 重连失败，附带重连序号及错误信息
+    WorldReconnectFailed(u32, String),
+    /// telnet protocols negotiated with server
+    WorldProtocols(Protocols),
+    // GMCP消息内容
+    WorldGmcp(Vec<u8>),
+    // MSDP变量数据
+    WorldMsdp(Vec<u8>),
+    // 连接初期的延迟及包大小测量结果
+    WorldProbe(ProbeReport),
     /// user input line
     UserOutput(UserOutput),
     /// user script line will be sent to script
@@ -36,8 +55,8 @@ pub enum Event {
     NewClient(TcpStream, SocketAddr),
     // client authentication fail
     ClientAuthFail,
-    // client authentication success
-    ClientAuthSuccess(TcpStream),
+    // client authentication success, with client capabilities
+    ClientAuthSuccess(TcpStream, u8),
     // client disconnect
     ClientDisconnect,
     // server down
@@ -48,11 +67,115 @@ pub enum Event {
     TerminalKey(Key),
     // terminal mouse event
     TerminalMouse(MouseEvent),
+    // 无法确定编码的粘贴内容，等待用户选择
+    PasteChoice(PasteChoices),
+    // 脚本等待的按键，取消时为None
+    KeyRead(Option<String>),
+    // 选中的菜单项，从1开始，取消时为None
+    MenuChosen(Option<usize>),
+    // REPL窗格中输入的一行代码
+    ReplInput(String),
+    // 放弃REPL中尚未完整的多行代码
+    ReplCancel,
+    // 脚本包的下载地址及下载结果
+    BundleFetched(String, std::result::Result<Vec<u8>, String>),
+    // 来自指定会话的世界连接及定时器的事件
+    Session(usize, Box<Event>),
+}
+
+/// 创建会话的事件通道，发送的事件以会话序号标记后转发至总线
+pub fn session_channel(evttx: Sender<Event>, id: usize) -> Sender<Event> {
+    let (tx, rx) = unbounded::<Event>();
+    thread::spawn(move || {
+        for evt in rx {
+            if evttx.send(Event::Session(id, Box::new(evt))).is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+/// 各会话的运行时，未标记会话的事件（如用户输入）交给当前会话处理
+pub struct Sessions {
+    engines: Vec<Engine>,
+    active: usize,
+}
+
+impl Sessions {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engines: vec![engine],
+            active: 0,
+        }
+    }
+
+    /// 当前会话的运行时
+    pub fn active(&mut self) -> &mut Engine {
+        &mut self.engines[self.active]
+    }
+
+    pub fn active_id(&self) -> usize {
+        self.active
+    }
+
+    pub fn get(&mut self, id: usize) -> Option<&mut Engine> {
+        self.engines.get_mut(id)
+    }
+
+    /// 添加会话，返回其序号
+    pub fn add(&mut self, engine: Engine) -> usize {
+        self.engines.push(engine);
+        self.engines.len() - 1
+    }
+
+    /// 切换当前会话，序号不存在时返回false
+    pub fn switch(&mut self, id: usize) -> bool {
+        if id >= self.engines.len() {
+            return false;
+        }
+        self.active = id;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.engines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.engines.is_empty()
+    }
+
+    // 各会话中最早需要继续处理暂存世界文本的时间
+    fn backlog_wait(&self) -> Option<Duration> {
+        self.engines.iter().filter_map(|engine| engine.world_backlog_wait()).min()
+    }
+
+    // 继续处理各会话中已到处理周期的暂存世界文本，返回是否有会话继续处理
+    fn resume(&mut self) -> bool {
+        let mut resumed = false;
+        for engine in &mut self.engines {
+            if engine.world_backlog_wait() == Some(Duration::from_millis(0)) {
+                engine.resume_world_lines();
+                resumed = true;
+            }
+        }
+        resumed
+    }
+
+    // 执行各会话的操作队列，返回会话序号及其输出
+    fn apply(&mut self) -> Vec<(usize, RuntimeOutput)> {
+        self.engines
+            .iter_mut()
+            .enumerate()
+            .flat_map(|(id, engine)| engine.apply().into_iter().map(move |output| (id, output)))
+            .collect()
+    }
 }
 
 /// 事件回调
 pub trait EventHandler {
-    fn on_event(&mut self, evt: Event, engine: &mut Engine) -> Result<NextStep>;
+    fn on_event(&mut self, evt: Event, sessions: &mut Sessions) -> Result<NextStep>;
 }
 
 /// 退出回调
@@ -69,7 +192,7 @@ pub enum NextStep {
 
 /// 事件循环
 pub struct EventLoop<EH, QH> {
-    engine: Engine,
+    sessions: Sessions,
     evtrx: Receiver<Event>,
     evt_hdl: EH,
     qt_hdl: QH,
@@ -82,7 +205,7 @@ where
 {
     pub fn new(engine: Engine, evtrx: Receiver<Event>, evt_hdl: EH, qt_hdl: QH) -> Self {
         Self {
-            engine,
+            sessions: Sessions::new(engine),
             evtrx,
             evt_hdl,
             qt_hdl,
@@ -91,26 +214,49 @@ where
 
     pub fn run(mut self) -> Result<()> {
         'outer: loop {
-            let evt = self.evtrx.recv()?;
             // 处理总线上的事件
-            match self.evt_hdl.on_event(evt, &mut self.engine)? {
-                NextStep::Quit => break,
-                NextStep::Skip => continue,
-                NextStep::Run => (),
+            let step = match self.next_event()? {
+                Some(evt) => self.evt_hdl.on_event(evt, &mut self.sessions)?,
+                None => NextStep::Run,
+            };
+            if let NextStep::Quit = step {
+                break;
+            }
+            // 总线繁忙时也按周期继续处理暂存的世界文本，避免积压
+            let resumed = self.sessions.resume();
+            if let (NextStep::Skip, false) = (step, resumed) {
+                continue;
             }
-            let outputs = self.engine.apply();
+            let outputs = self.sessions.apply();
             if outputs.is_empty() {
                 continue;
             }
             // 处理运行时（衍生）事件
-            for output in outputs {
-                match self.evt_hdl.on_runtime_output(output)? {
-                    NextStep::Quit => break 'outer,
-                    _ => (),
+            for (id, output) in outputs {
+                if let NextStep::Quit = self.evt_hdl.on_runtime_output(id, output, &mut self.sessions)? {
+                    break 'outer;
                 }
             }
         }
+        for engine in &self.sessions.engines {
+            if let Err(e) = engine.save_vars() {
+                log::warn!("save variables error {}", e);
+            }
+        }
         self.qt_hdl.on_quit();
         Ok(())
     }
+
+    // 等待下一个事件，有暂存的世界文本时最多等到下个处理周期，超时返回None
+    fn next_event(&self) -> Result<Option<Event>> {
+        let wait = match self.sessions.backlog_wait() {
+            Some(wait) => wait,
+            None => return Ok(Some(self.evtrx.recv()?)),
+        };
+        match self.evtrx.recv_timeout(wait) {
+            Ok(evt) => Ok(Some(evt)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(RecvError.into()),
+        }
+    }
 }
@@ -0,0 +1,343 @@
+use crate::conf::{self, Config, Mode};
+use crate::datadir::DataDir;
+use crate::i18n;
+use crate::map::mapper;
+use crate::ui::layout::{MIN_CMD_HEIGHT, MIN_FLOW_WIDTH};
+use rusqlite::{Connection, OpenFlags};
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+// 地图数据库中必需的表及最少列数，与各from_row按序读取的列对应
+const MAP_TABLES: &[(&str, usize)] = &[("rooms", 8), ("paths", 9), ("zones", 4), ("npcs", 4)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// 启动检查发现的问题及修改建议
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub check: &'static str,
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl Diagnostic {
+    fn error(check: &'static str, message: String, suggestion: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            check,
+            message,
+            suggestion,
+        }
+    }
+
+    fn warning(check: &'static str, message: String, suggestion: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            check,
+            message,
+            suggestion,
+        }
+    }
+}
+
+/// 启动前执行所有检查，返回发现的问题
+pub fn check_all(config: &Config, data_dir: &DataDir) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    match config.mode {
+        Mode::Standalone | Mode::Server => {
+            diags.extend(check_addr("world.addr", &config.world.addr));
+            diags.extend(check_log_file(data_dir, &config.server.log_file));
+            if !config.runtime.map_db.is_empty() {
+                diags.extend(check_map_db(&data_dir.state_path(&config.runtime.map_db)));
+            }
+            for script in config.runtime.all_init_scripts() {
+                diags.extend(check_init_script(&data_dir.script_path(&script)));
+            }
+        }
+        Mode::Client => {
+            diags.extend(check_addr("client.server_addr", &config.client.server_addr));
+            diags.extend(check_log_file(data_dir, &config.client.log_file));
+        }
+    }
+    diags.extend(check_log_file(data_dir, &config.server.debug_file));
+    if !matches!(config.mode, Mode::Server) {
+        diags.extend(check_layout(&config.term, termion::terminal_size().ok()));
+    }
+    diags
+}
+
+/// 检查界面布局的最小尺寸，及是否超出当前终端宽度
+pub fn check_layout(term: &conf::Term, size: Option<(u16, u16)>) -> Vec<Diagnostic> {
+    let layout = &term.layout;
+    let mut diags = Vec::new();
+    if layout.cmd_height < MIN_CMD_HEIGHT {
+        diags.push(Diagnostic::error(
+            "term.layout.cmd_height",
+            i18n::trf("health.cmd_height", &[&layout.cmd_height, &MIN_CMD_HEIGHT]),
+            i18n::tr("health.cmd_height_hint"),
+        ));
+    }
+    let need = [layout.margin_left, layout.margin_right, layout.chat_side_width, MIN_FLOW_WIDTH]
+        .iter()
+        .map(|n| *n as u32)
+        .sum::<u32>();
+    if let Some((width, _)) = size {
+        if need > width as u32 {
+            diags.push(Diagnostic::warning(
+                "term.layout",
+                i18n::trf("health.layout_width", &[&need, &width]),
+                i18n::tr("health.layout_width_hint"),
+            ));
+        }
+    }
+    diags
+}
+
+/// 检查地址能否解析
+pub fn check_addr(key: &'static str, addr: &str) -> Option<Diagnostic> {
+    match addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(_)) => None,
+        Ok(None) => Some(Diagnostic::error(
+            key,
+            i18n::trf("health.addr_unresolved", &[&addr, &"no address"]),
+            i18n::trf("health.addr_hint", &[&key]),
+        )),
+        Err(e) if e.kind() == ErrorKind::InvalidInput => Some(Diagnostic::error(
+            key,
+            i18n::trf("health.addr_unresolved", &[&addr, &e]),
+            i18n::trf("health.addr_hint", &[&key]),
+        )),
+        // 域名解析失败可能是网络暂时不可用
+        Err(e) => Some(Diagnostic::warning(
+            key,
+            i18n::trf("health.addr_unresolved", &[&addr, &e]),
+            i18n::tr("health.network_hint"),
+        )),
+    }
+}
+
+/// 检查地图数据库是否存在且包含所需的表
+pub fn check_map_db(path: &Path) -> Option<Diagnostic> {
+    let key = "runtime.map_db";
+    if !path.is_file() {
+        return Some(Diagnostic::error(
+            key,
+            i18n::trf("health.file_missing", &[&path.display()]),
+            i18n::trf("health.map_db_hint", &[&key]),
+        ));
+    }
+    // 只读打开，避免创建空数据库
+    let conn = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return Some(Diagnostic::error(
+                key,
+                i18n::trf("health.map_db_open", &[&path.display(), &e]),
+                i18n::tr("health.map_db_schema_hint"),
+            ))
+        }
+    };
+    // 更高版本的数据库可能由新版程序修改过结构
+    match mapper::schema_version(&conn) {
+        Ok(version) if version > mapper::SCHEMA_VERSION => {
+            return Some(Diagnostic::error(
+                key,
+                i18n::trf("health.map_db_version", &[&path.display(), &version, &mapper::SCHEMA_VERSION]),
+                i18n::tr("health.map_db_schema_hint"),
+            ))
+        }
+        Ok(_) => (),
+        Err(e) => {
+            return Some(Diagnostic::error(
+                key,
+                i18n::trf("health.map_db_open", &[&path.display(), &e]),
+                i18n::tr("health.map_db_schema_hint"),
+            ))
+        }
+    }
+    let mut problems = Vec::new();
+    for (table, min_cols) in MAP_TABLES {
+        match table_columns(&conn, table) {
+            Ok(0) => problems.push(i18n::trf("health.table_missing", &[table])),
+            Ok(n) if n < *min_cols => {
+                problems.push(i18n::trf("health.table_columns", &[table, &n, min_cols]))
+            }
+            Ok(_) => (),
+            Err(e) => {
+                return Some(Diagnostic::error(
+                    key,
+                    i18n::trf("health.map_db_open", &[&path.display(), &e]),
+                    i18n::tr("health.map_db_schema_hint"),
+                ))
+            }
+        }
+    }
+    if problems.is_empty() {
+        return None;
+    }
+    Some(Diagnostic::error(
+        key,
+        i18n::trf("health.map_db_schema", &[&path.display(), &problems.join("; ")]),
+        i18n::tr("health.map_db_schema_hint"),
+    ))
+}
+
+fn table_columns(conn: &Connection, table: &str) -> rusqlite::Result<usize> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+    let mut n = 0;
+    while rows.next()?.is_some() {
+        n += 1;
+    }
+    Ok(n)
+}
+
+/// 检查初始化脚本是否存在且语法正确，仅编译不执行
+pub fn check_init_script(path: &Path) -> Option<Diagnostic> {
+    let key = "runtime.init_script";
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            return Some(Diagnostic::error(
+                key,
+                i18n::trf("health.script_read", &[&path.display(), &e]),
+                i18n::trf("health.script_read_hint", &[&key]),
+            ))
+        }
+    };
+    let lua = mlua::Lua::new();
+    let compiled = lua.load(&script).into_function().map(|_| ());
+    match compiled {
+        Ok(()) => None,
+        Err(e) => Some(Diagnostic::error(
+            key,
+            i18n::trf("health.script_syntax", &[&path.display(), &e]),
+            i18n::tr("health.script_syntax_hint"),
+        )),
+    }
+}
+
+/// 检查日志文件所在目录是否可写
+pub fn check_log_file(data_dir: &DataDir, file: &str) -> Option<Diagnostic> {
+    let path = data_dir.log_path(file);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    let probe = dir.join(".mudterm-write-test");
+    let res = fs::create_dir_all(&dir).and_then(|_| {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&probe)
+            .map(|_| ())
+    });
+    let _ = fs::remove_file(&probe);
+    match res {
+        Ok(()) => None,
+        Err(e) => Some(Diagnostic::error(
+            "logs",
+            i18n::trf("health.dir_unwritable", &[&dir.display(), &e]),
+            i18n::tr("health.dir_unwritable_hint"),
+        )),
+    }
+}
+
+/// 是否存在阻止启动的问题
+pub fn has_error(diags: &[Diagnostic]) -> bool {
+    diags.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// 生成诊断界面文本
+pub fn render(diags: &[Diagnostic]) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "{}", i18n::tr("health.title"));
+    let _ = writeln!(s, "{}", "=".repeat(40));
+    for (i, d) in diags.iter().enumerate() {
+        let level = match d.severity {
+            Severity::Error => i18n::tr("health.error"),
+            Severity::Warning => i18n::tr("health.warning"),
+        };
+        let _ = writeln!(s, "{}. [{}] {}: {}", i + 1, level, d.check, d.message);
+        let _ = writeln!(s, "   {}", i18n::trf("health.suggestion", &[&d.suggestion]));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_check_addr() {
+        assert!(check_addr("world.addr", "127.0.0.1:8080").is_none());
+        let diag = check_addr("world.addr", "no-port").unwrap();
+        assert_eq!(Severity::Error, diag.severity);
+        assert_eq!("world.addr", diag.check);
+    }
+
+    #[test]
+    fn test_check_layout() {
+        let mut term = conf::Term::default();
+        assert!(check_layout(&term, Some((80, 24))).is_empty());
+        term.layout.cmd_height = 1;
+        term.layout.chat_side_width = 70;
+        let diags = check_layout(&term, Some((80, 24)));
+        assert_eq!(2, diags.len());
+        assert_eq!(Severity::Error, diags[0].severity);
+        assert_eq!(Severity::Warning, diags[1].severity);
+        // 无法获取终端大小时仅检查最小尺寸
+        assert_eq!(1, check_layout(&term, None).len());
+    }
+
+    #[test]
+    fn test_check_files() {
+        let dir = TempDir::new("health");
+
+        let script = dir.join("init.lua");
+        fs::write(&script, "local x = 1\nreturn x").unwrap();
+        assert!(check_init_script(&script).is_none());
+        fs::write(&script, "local x = = 1").unwrap();
+        assert!(check_init_script(&script).is_some());
+        assert!(check_init_script(&dir.join("missing.lua")).is_some());
+
+        let db = dir.join("map.db");
+        assert!(check_map_db(&db).is_some());
+        {
+            let conn = Connection::open(&db).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE rooms(a,b,c,d,e,f,g,h);
+                 CREATE TABLE paths(a,b,c,d,e,f,g,h,i);
+                 CREATE TABLE zones(a,b,c);",
+            )
+            .unwrap();
+        }
+        let diag = check_map_db(&db).unwrap();
+        assert!(diag.message.contains("zones"));
+        assert!(diag.message.contains("npcs"));
+        {
+            let conn = Connection::open(&db).unwrap();
+            conn.execute_batch("DROP TABLE zones; CREATE TABLE zones(a,b,c,d); CREATE TABLE npcs(a,b,c,d);")
+                .unwrap();
+        }
+        assert!(check_map_db(&db).is_none());
+        {
+            let conn = Connection::open(&db).unwrap();
+            conn.execute_batch("PRAGMA user_version = 99").unwrap();
+        }
+        assert!(check_map_db(&db).unwrap().message.contains("99"));
+
+        let data_dir = DataDir::default();
+        assert!(check_log_file(&data_dir, dir.join("server.log").to_str().unwrap()).is_none());
+    }
+}
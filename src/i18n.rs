@@ -0,0 +1,372 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::RwLock;
+
+/// 界面语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Lang {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "en-US")]
+    EnUs,
+}
+
+impl Lang {
+    pub fn parse(s: &str) -> Option<Self> {
+        match &s.to_lowercase()[..] {
+            "zh-cn" | "zh" => Some(Lang::ZhCn),
+            "en-us" | "en" => Some(Lang::EnUs),
+            _ => None,
+        }
+    }
+}
+
+// 内置消息：键，中文，英文
+const MESSAGES: &[(&str, &str, &str)] = &[
+    ("err.create_alias", "创建别名失败：{}", "Failed to create alias: {}"),
+    ("err.create_trigger", "创建触发器失败：{}", "Failed to create trigger: {}"),
+    ("err.create_mxp_trigger", "创建MXP触发器失败：{}", "Failed to create MXP trigger: {}"),
+    (
+        "err.iteration_limit",
+        "操作队列处理超过{}次迭代，剩余操作延后执行",
+        "Action queue exceeded {} iterations, remaining actions deferred",
+    ),
+    (
+        "err.alias_depth",
+        "别名嵌套超过最大深度{}：{}",
+        "Alias nesting exceeds max depth {}: {}",
+    ),
+    ("err.unknown_command", "未知命令：#{}", "Unknown command: #{}"),
+    ("err.rule_not_found", "触发器或别名不存在：{}", "No trigger or alias named {}"),
+    ("err.recording", "正在录制宏{}，请先停止录制", "Already recording macro {}, stop it first"),
+    ("err.not_recording", "当前未在录制宏", "Not recording any macro"),
+    (
+        "err.invalid_name",
+        "名称不合法：{}，不能为空或包含路径分隔符及..",
+        "Invalid name: {}, must not be empty or contain path separators or ..",
+    ),
+    ("err.play_speed", "回放倍速不合法：{}", "Invalid playback speed: {}"),
+    ("err.nothing_to_confirm", "没有等待确认的命令", "No command awaiting confirmation"),
+    ("err.no_pending_bundle", "没有等待安装的脚本包", "No bundle awaiting installation"),
+    (
+        "err.no_trusted_keys",
+        "未配置受信任的公钥，请在{}中添加“名称 公钥”",
+        "No trusted keys, add \"name pubkey\" lines to {}",
+    ),
+    ("err.bad_signature", "脚本包{}签名校验失败", "Signature verification failed for bundle {}"),
+    ("usage.fetch", "用法：#fetch <url> | #fetch install | #fetch cancel", "Usage: #fetch <url> | #fetch install | #fetch cancel"),
+    ("err.line_not_found", "第{}行不存在或已被丢弃", "Line {} does not exist or has been discarded"),
+    ("usage.yank", "用法：#yank <行号> [寄存器]", "Usage: #yank <lineno> [register]"),
+    ("usage.dump", "用法：#dump models <文件>", "Usage: #dump models <file>"),
+    ("usage.marks", "用法：#marks [clear | del <序号>]", "Usage: #marks [clear | del <n>]"),
+    ("usage.jump", "用法：#jump [prev | next | <序号>]", "Usage: #jump [prev | next | <n>]"),
+    ("err.mark_not_found", "书签#{}不存在", "Mark #{} does not exist"),
+    ("usage.trace", "用法：#trace show [n] | #trace export <file> | #trace clear", "Usage: #trace show [n] | #trace export <file> | #trace clear"),
+    ("usage.manage", "用法：#manage [enable|disable <name>]", "Usage: #manage [enable|disable <name>]"),
+    ("usage.reload", "用法：#reload [defs | scripts]", "Usage: #reload [defs | scripts]"),
+    ("usage.record", "用法：#record start <name> | #record stop", "Usage: #record start <name> | #record stop"),
+    ("usage.play", "用法：#play <name> [speed]", "Usage: #play <name> [speed]"),
+    ("usage.go", "用法：#go <书签>", "Usage: #go <bookmark>"),
+    ("err.no_map", "未加载地图数据库", "No map database loaded"),
+    ("err.map_version", "地图数据库版本{}高于支持的版本{}", "Map database version {} is newer than supported version {}"),
+    ("err.bookmark_not_found", "书签不存在：{}", "No bookmark named {}"),
+    ("err.no_walker", "未设置行走函数，请在脚本中调用SetWalker", "No walker set, call SetWalker in scripts"),
+    ("bookmark.title", "书签：", "Bookmarks:"),
+    ("err.trigger_failed", "触发器{}（定义于{}）执行失败：{}", "Trigger {} (defined in {}) failed: {}"),
+    ("err.transformer_failed", "行转换器执行失败：{}", "Line transformer failed: {}"),
+    ("err.transformer_not_found", "行转换器不存在：{}", "No line transformer named {}"),
+    ("loadorder.title", "已加载的脚本：", "Loaded scripts:"),
+    ("loadorder.ok", "成功", "ok"),
+    ("loadorder.failed", "失败：{}", "failed: {}"),
+    ("err.wait_invalid", "等待时间无效：{}，应为毫秒数", "Invalid wait time: {}, expected milliseconds"),
+    (
+        "err.unencodable",
+        "命令包含服务器编码无法表示的字符：{}，未发送",
+        "Command not sent, it contains characters the server encoding cannot represent: {}",
+    ),
+    ("err.def_conflict", "定义{}（位于{}）与已有的规则重名，已跳过", "Definition {} in {} conflicts with an existing rule, skipped"),
+    ("err.log_write", "写入世界日志{}失败，已停止记录：{}", "Failed to write world log {}, logging stopped: {}"),
+    ("err.world_write", "向服务器发送数据失败：{}", "Failed to write to world: {}"),
+    ("err.media_busy", "忽略声音{}，播放命令已达上限{}个", "Sound {} ignored, {} play commands already running"),
+    ("world.disconnected", "与服务器断开了连接，可使用Reconnect()重新连接", "Disconnected from world, use Reconnect() to connect again"),
+    ("world.reconnect_in", "与服务器断开了连接，{}秒后进行第{}次重连", "Disconnected from world, reconnecting in {}s (attempt {})"),
+    ("world.reconnect_failed", "重连失败：{}", "Reconnect failed: {}"),
+    ("world.reconnecting", "正在重新连接服务器", "Reconnecting to world"),
+    ("world.reconnected", "已重新连接服务器", "Reconnected to world"),
+    ("world.connected", "已连接服务器", "Connected to world"),
+    ("world.connect_failed", "连接服务器失败：{}，可使用Reconnect()重新连接", "Failed to connect to world: {}, use Reconnect() to try again"),
+    (
+        "paste.ambiguous",
+        "粘贴内容的编码无法确定，按数字键选择，其他键取消：",
+        "Cannot determine the encoding of pasted text, press a digit to choose or any other key to cancel:",
+    ),
+    ("protocol.summary", "协议协商：{}", "Negotiated protocols: {}"),
+    ("probe.summary", "连接测量：延迟{}（{}/{}次应答），平均每次读取{}字节，最大{}字节", "Connection probe: latency {} ({}/{} replies), {} bytes per read on average, {} max"),
+    ("probe.rec_ok", "连接状况良好，无需调整", "Connection looks good, no changes needed"),
+    ("probe.rec_no_reply", "服务器未应答延迟探测，无法测量延迟", "Server did not answer latency probes"),
+    ("probe.rec_timeout", "延迟较高，建议增大world.write_timeout_secs", "High latency, consider raising world.write_timeout_secs"),
+    ("probe.rec_pacing", "延迟波动较大，建议设置runtime.queue_tag并降低max_depth控制发送节奏", "Unstable latency, consider runtime.queue_tag with a lower max_depth to pace commands"),
+    ("probe.rec_mccp", "服务器输出量较大，建议设置protocol.mccp为on启用压缩", "Heavy server output, consider protocol.mccp = \"on\" to enable compression"),
+    ("protocol.unknown", "尚未完成协议协商", "Protocols not negotiated yet"),
+    ("transform.title", "行转换器：", "Line transformers:"),
+    (
+        "transform.item",
+        "  {} 顺序{} {} 执行{}次 平均{}微秒 错误{}次",
+        "  {} order {} {} calls {} avg {}us errors {}",
+    ),
+    ("manage.triggers", "触发器：", "Triggers:"),
+    ("manage.aliases", "别名：", "Aliases:"),
+    ("manage.enabled", "启用", "enabled"),
+    ("manage.disabled", "禁用", "disabled"),
+    ("stats.title", "分组统计（启用/总数）：", "Groups (enabled/total):"),
+    ("stats.row", "触发器 {}/{}，别名 {}/{}", "triggers {}/{}, aliases {}/{}"),
+    ("stats.observed", "仅观察，命中{}次", "observe only, {} hits"),
+    ("stats.sample", "    样本：{}", "    sample: {}"),
+    (
+        "guard.confirm",
+        "重复命令已拦截：{}，输入#confirm发送",
+        "Duplicate command held: {}, enter #confirm to send",
+    ),
+    ("err.setting_not_found", "设置项不存在：{}", "No setting named {}"),
+    ("err.no_conf_file", "未指定配置文件，无法保存设置", "No config file to save settings to"),
+    ("usage.set", "用法：#set [<键> <值> | save]", "Usage: #set [<key> <value> | save]"),
+    ("settings.title", "运行时设置：", "Runtime settings:"),
+    ("settings.saved", "设置已保存至{}", "Settings saved to {}"),
+    ("err.no_queue_tag", "未配置服务器队列标记queue_tag", "No queue_tag configured"),
+    ("err.no_offline_queue", "未配置离线队列文件offline_queue", "No offline_queue configured"),
+    ("err.offline_disconnected", "尚未连接到服务器，离线队列保留", "Not connected, offline queue kept"),
+    ("usage.queue", "用法：#queue [flush|clear]", "Usage: #queue [flush|clear]"),
+    ("usage.pause", "用法：#pause [on|off]", "Usage: #pause [on|off]"),
+    ("usage.session", "用法：#session [序号|open 名称 地址]", "Usage: #session [number|open name addr]"),
+    ("session.title", "会话列表：", "Sessions:"),
+    ("session.connected", "已连接", "connected"),
+    ("session.disconnected", "已断开", "disconnected"),
+    ("session.switched", "已切换到会话{}：{}", "Switched to session {}: {}"),
+    ("session.invalid", "会话{}不存在", "No session {}"),
+    ("session.unsupported", "仅单机模式支持多会话", "Multiple sessions are only supported in standalone mode"),
+    ("timers.paused", "已暂停{}个定时器", "Paused {} timers"),
+    ("timers.resumed", "已恢复{}个定时器", "Resumed {} timers"),
+    ("queue.status", "服务器队列长度{}，暂存命令{}条", "Server queue depth {}, {} commands held"),
+    ("queue.cleared", "已丢弃{}条暂存命令", "Dropped {} held commands"),
+    ("usage.offline", "用法：#offline [flush|clear]", "Usage: #offline [flush|clear]"),
+    ("offline.queued", "[已暂存] {}", "[queued] {}"),
+    ("offline.status", "离线队列中有{}条命令", "{} commands in offline queue"),
+    ("offline.pending", "已重新连接，离线队列中有{}条命令，登录后使用#offline flush发送", "Reconnected with {} queued commands, use #offline flush after login"),
+    ("offline.flushed", "发送离线队列中的{}条命令", "Sending {} queued commands"),
+    ("offline.cleared", "已丢弃离线队列中的{}条命令", "Dropped {} queued commands"),
+    ("ui.cjk_on", "歧义宽度字符按两列显示", "Ambiguous-width characters shown as 2 columns"),
+    ("ui.cjk_off", "歧义宽度字符按一列显示", "Ambiguous-width characters shown as 1 column"),
+    ("ui.more", "更多", "MORE"),
+    ("cmdbar.search", "(反向搜索)`{}'：{}", "(reverse-i-search)`{}': {}"),
+    ("layout.shrunk", "终端空间不足，{}由{}缩小为{}", "Not enough room, {} shrunk from {} to {}"),
+    ("guard.suppressed", "重复命令已忽略：{}", "Duplicate command suppressed: {}"),
+    ("repeat.denied", "禁止重发的命令：{}", "Command not repeated: {}"),
+    ("fetch.downloading", "正在下载{}", "Downloading {}"),
+    ("fetch.manifest", "脚本包{} {}，作者{}，签名者{}", "Bundle {} {} by {}, signed by {}"),
+    ("fetch.capabilities", "  申请的能力：{}", "  Requested capabilities: {}"),
+    ("fetch.files", "  包含{}个文件", "  Contains {} files"),
+    ("fetch.prompt", "输入#fetch install安装，#fetch cancel取消", "Enter #fetch install to install, #fetch cancel to abort"),
+    ("fetch.installed", "脚本包{}已安装至{}", "Bundle {} installed to {}"),
+    ("fetch.cancelled", "已取消安装脚本包{}", "Installation of bundle {} cancelled"),
+    ("reg.title", "寄存器：", "Registers:"),
+    ("reg.yanked", "第{}行已复制到寄存器{}", "Line {} yanked to register {}"),
+    ("vars.flushed", "{}变量日志{}条记录（{}字节）已写入{}", "{} variable journal of {} entries ({} bytes) written to {}"),
+    ("repl.cancelled", "已放弃未完成的代码", "Pending input discarded"),
+    ("zmud.imported", "已导入{}个触发器及{}个别名（{}），{}条警告", "Imported {} triggers and {} aliases from {}, {} warnings"),
+    ("zmud.warning", "  第{}行：{}", "  line {}: {}"),
+    ("zmud.unbalanced_braces", "大括号不匹配", "unbalanced braces"),
+    ("zmud.unsupported_command", "不支持的命令{}", "unsupported command {}"),
+    ("zmud.missing_args", "{}需要匹配模式及命令", "{} requires pattern and commands"),
+    ("zmud.options_ignored", "已忽略选项{}", "options {} ignored"),
+    ("zmud.invalid_alias", "别名名称不合法：{}", "invalid alias name {}"),
+    ("zmud.dangling", "匹配模式末尾的{}缺少后续字符", "dangling {} in pattern"),
+    ("zmud.unsupported_wildcard", "不支持的通配符%{}", "unsupported pattern wildcard %{}"),
+    ("zmud.pattern_variable", "匹配模式不支持引用变量", "variable reference in pattern not supported"),
+    ("zmud.pattern_braces", "匹配模式中的大括号不匹配", "unbalanced braces in pattern"),
+    ("zmud.invalid_pattern", "匹配模式不合法：{}", "invalid pattern: {}"),
+    ("zmud.unsupported_zscript", "不支持zscript命令{}", "zscript command {} not supported"),
+    ("zmud.send_variable", "命令不支持引用变量", "variable reference in commands not supported"),
+    ("zmud.unsupported_function", "不支持zscript函数%{}", "zscript function %{} not supported"),
+    ("log.started", "开始记录世界文本到{}", "Logging world output to {}"),
+    ("log.stopped", "已停止记录世界文本到{}", "Stopped logging world output to {}"),
+    ("logs.serving", "日志浏览服务已启动：http://{}/，日志目录{}", "Log viewer listening on http://{}/ for {}"),
+    ("logs.files", "日志文件", "Log files"),
+    ("logs.search", "搜索", "Search"),
+    ("logs.hits", "共{}条匹配", "{} matches"),
+    ("logs.prev", "上一页", "Previous"),
+    ("logs.next", "下一页", "Next"),
+    ("reload.done", "已重新加载脚本，共{}个文件", "Scripts reloaded, {} files"),
+    ("reload.scripts", "已加载{}个脚本（{}），{}个失败", "Loaded {} scripts from {}, {} failed"),
+    ("reload.failed", "  {}：{}", "  {}: {}"),
+    ("reload.defs", "已重新加载{}个触发器、别名及定时器（{}个定义文件）", "Reloaded {} triggers, aliases and timers from {} definition files"),
+    ("dump.saved", "已导出{}个触发器、{}个别名及{}个定时器到{}", "Dumped {} triggers, {} aliases and {} timers to {}"),
+    ("mark.added", "已为第{}行添加书签#{}", "Line {} marked as #{}"),
+    ("marks.title", "行书签：", "Line marks:"),
+    ("marks.cleared", "已清空行书签", "Line marks cleared"),
+    ("marks.jump", "书签#{} 第{}行 {} {}", "Mark #{} line {} {} {}"),
+    ("marks.end", "已位于最新位置，没有更多书签", "At the newest position, no more marks"),
+    ("trace.exported", "处理轨迹已导出至{}", "Traces exported to {}"),
+    ("record.started", "开始录制宏{}", "Recording macro {}"),
+    (
+        "record.saved",
+        "宏{}录制完成，共{}条命令，已保存至{}",
+        "Macro {} recorded with {} commands, saved to {}",
+    ),
+    ("health.title", "启动检查发现以下问题：", "Startup checks found the following problems:"),
+    ("health.error", "错误", "error"),
+    ("health.warning", "警告", "warning"),
+    ("health.suggestion", "建议：{}", "Suggestion: {}"),
+    ("health.addr_unresolved", "无法解析地址{}：{}", "Cannot resolve address {}: {}"),
+    (
+        "health.addr_hint",
+        "检查配置项{}，格式应为host:port",
+        "Check {} in the config file, expected host:port",
+    ),
+    ("health.network_hint", "检查网络连接及DNS设置", "Check the network connection and DNS settings"),
+    ("health.file_missing", "文件{}不存在", "File {} does not exist"),
+    (
+        "health.map_db_hint",
+        "将地图数据库放入数据目录的state子目录，或修改配置项{}",
+        "Put the map database under the state directory, or fix {} in the config file",
+    ),
+    ("health.map_db_open", "无法打开地图数据库{}：{}", "Cannot open map database {}: {}"),
+    ("health.map_db_schema", "地图数据库{}结构不符：{}", "Map database {} has unexpected schema: {}"),
+    (
+        "health.map_db_version",
+        "地图数据库{}的版本为{}，当前程序仅支持版本{}",
+        "Map database {} has schema version {}, only version {} is supported",
+    ),
+    (
+        "health.map_db_schema_hint",
+        "确认数据库为mudterm地图数据库，且版本与当前程序匹配",
+        "Make sure it is a mudterm map database matching this version",
+    ),
+    ("health.table_missing", "缺少表{}", "missing table {}"),
+    ("health.table_columns", "表{}仅有{}列，至少需要{}列", "table {} has {} columns, at least {} required"),
+    ("health.script_read", "无法读取初始化脚本{}：{}", "Cannot read init script {}: {}"),
+    (
+        "health.script_read_hint",
+        "将脚本放入数据目录的scripts子目录，或修改配置项{}",
+        "Put the script under the scripts directory, or fix {} in the config file",
+    ),
+    ("health.script_syntax", "初始化脚本{}存在语法错误：{}", "Init script {} has syntax errors: {}"),
+    ("health.script_syntax_hint", "根据错误信息中的行号修正脚本", "Fix the script at the reported line"),
+    ("health.dir_unwritable", "日志目录{}不可写：{}", "Log directory {} is not writable: {}"),
+    (
+        "health.dir_unwritable_hint",
+        "检查目录权限，或修改world.data_dir指向可写目录",
+        "Check directory permissions, or point world.data_dir to a writable directory",
+    ),
+    ("health.cmd_height", "命令区高度{}小于最小值{}", "Command area height {} is below the minimum {}"),
+    ("health.cmd_height_hint", "将term.layout.cmd_height设为至少3", "Set term.layout.cmd_height to at least 3"),
+    (
+        "health.layout_width",
+        "界面布局需要至少{}列，当前终端仅{}列",
+        "The layout needs at least {} columns but the terminal has only {}",
+    ),
+    (
+        "health.layout_width_hint",
+        "减小term.layout的留白或侧栏宽度，窄终端上将自动缩小",
+        "Reduce margins or side width in term.layout; they shrink automatically on narrow terminals",
+    ),
+];
+
+/// 消息目录
+///
+/// 查找顺序为当前语言、中文，均不存在时返回键本身
+#[derive(Debug)]
+pub struct Catalog {
+    lang: Lang,
+    messages: HashMap<(Lang, String), String>,
+}
+
+impl Catalog {
+    pub fn builtin() -> Self {
+        let mut messages = HashMap::new();
+        for (key, zh, en) in MESSAGES {
+            messages.insert((Lang::ZhCn, (*key).to_owned()), (*zh).to_owned());
+            messages.insert((Lang::EnUs, (*key).to_owned()), (*en).to_owned());
+        }
+        Self {
+            lang: Lang::default(),
+            messages,
+        }
+    }
+
+    pub fn set_lang(&mut self, lang: Lang) {
+        self.lang = lang;
+    }
+
+    pub fn register(&mut self, lang: Lang, key: impl Into<String>, msg: impl Into<String>) {
+        self.messages.insert((lang, key.into()), msg.into());
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        self.messages
+            .get(&(self.lang, key.to_owned()))
+            .or_else(|| self.messages.get(&(Lang::ZhCn, key.to_owned())))
+            .cloned()
+            .unwrap_or_else(|| key.to_owned())
+    }
+
+    /// 获取消息并依次替换其中的{}
+    pub fn format(&self, key: &str, args: &[&dyn Display]) -> String {
+        let msg = self.get(key);
+        let mut parts = msg.split("{}");
+        let mut s = parts.next().unwrap_or_default().to_owned();
+        let mut args = args.iter();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                s.push_str(&arg.to_string());
+            } else {
+                s.push_str("{}");
+            }
+            s.push_str(part);
+        }
+        s
+    }
+}
+
+lazy_static! {
+    static ref CATALOG: RwLock<Catalog> = RwLock::new(Catalog::builtin());
+}
+
+/// 设置全局界面语言
+pub fn set_lang(lang: Lang) {
+    CATALOG.write().unwrap().set_lang(lang);
+}
+
+/// 注册消息，可覆盖内置消息
+pub fn register(lang: Lang, key: impl Into<String>, msg: impl Into<String>) {
+    CATALOG.write().unwrap().register(lang, key, msg);
+}
+
+/// 获取当前语言的消息
+pub fn tr(key: &str) -> String {
+    CATALOG.read().unwrap().get(key)
+}
+
+/// 获取当前语言的消息，并依次替换其中的{}
+pub fn trf(key: &str, args: &[&dyn Display]) -> String {
+    CATALOG.read().unwrap().format(key, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_lookup() {
+        let mut catalog = Catalog::builtin();
+        assert_eq!("未知命令：#foo", catalog.format("err.unknown_command", &[&"foo"]));
+        catalog.set_lang(Lang::EnUs);
+        assert_eq!("Unknown command: #foo", catalog.format("err.unknown_command", &[&"foo"]));
+        // 缺少英文时回退到中文，均不存在时返回键
+        catalog.register(Lang::ZhCn, "plugin.hello", "你好，{}");
+        assert_eq!("你好，张三", catalog.format("plugin.hello", &[&"张三"]));
+        assert_eq!("no.such.key", catalog.get("no.such.key"));
+    }
+}
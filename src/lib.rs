@@ -2,12 +2,21 @@ pub mod app;
 pub mod auth;
 pub mod codec;
 pub mod conf;
+pub mod crash;
+pub mod datadir;
 pub mod error;
 pub mod event;
+pub mod health;
+pub mod i18n;
+pub mod localtime;
+pub mod logview;
 pub mod map;
+pub mod probe;
 pub mod proto;
 pub mod runtime;
 pub mod signal;
 pub mod telnet;
+#[cfg(test)]
+pub(crate) mod testutil;
 pub mod ui;
 pub mod userinput;
\ No newline at end of file
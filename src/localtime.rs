@@ -0,0 +1,22 @@
+/// 自UNIX纪元起的毫秒数转换为本地时间，转换失败时返回None
+pub fn localtime(millis: u64) -> Option<libc::tm> {
+    let secs = (millis / 1000) as libc::time_t;
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
+    // 安全性：localtime_r成功时tm已被完整初始化
+    if unsafe { libc::localtime_r(&secs, tm.as_mut_ptr()) }.is_null() {
+        return None;
+    }
+    Some(unsafe { tm.assume_init() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_localtime() {
+        let tm = localtime(86_400_000 * 365).unwrap();
+        assert!(tm.tm_year == 70 || tm.tm_year == 71);
+        assert!((0..24).contains(&tm.tm_hour));
+    }
+}
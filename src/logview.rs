@@ -0,0 +1,507 @@
+use crate::error::{Error, Result};
+use crate::i18n;
+use crate::localtime::localtime;
+use crate::proto::ansi::apply_sgr;
+use crate::ui::style::{Color, Modifier, Style};
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// 查看日志时每页显示的行数
+pub const PAGE_LINES: usize = 1000;
+/// 搜索返回的最大匹配行数
+pub const MAX_HITS: usize = 500;
+
+/// 日志目录中的文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+    // 修改时间，毫秒
+    pub modified: u64,
+}
+
+/// 列出日志目录中的文件，最近修改的在前
+pub fn index(dir: &Path) -> Result<Vec<LogFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        files.push(LogFile {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            size: meta.len(),
+            modified,
+        });
+    }
+    files.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.name.cmp(&b.name)));
+    Ok(files)
+}
+
+/// 日志目录下的文件路径，拒绝子目录、绝对路径及上级目录
+pub fn resolve(dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(dir.join(name)),
+        _ => Err(Error::RuntimeError(format!("invalid log file {}", name))),
+    }
+}
+
+/// 按行读取日志，非UTF-8内容按替换字符显示
+pub fn read_lines(path: &Path) -> Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        while matches!(buf.last(), Some(b'\n') | Some(b'\r')) {
+            buf.pop();
+        }
+        lines.push(String::from_utf8_lossy(&buf).into_owned());
+        buf.clear();
+    }
+    Ok(lines)
+}
+
+/// 搜索结果：文件名、行号（从1开始）及行内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub file: String,
+    pub lineno: usize,
+    pub line: String,
+}
+
+/// 在所有日志中搜索文本，忽略大小写及ANSI控制序列，最多返回MAX_HITS条
+pub fn search(dir: &Path, files: &[LogFile], query: &str) -> Result<Vec<Hit>> {
+    let query = query.to_lowercase();
+    let mut hits = Vec::new();
+    for file in files {
+        for (i, line) in read_lines(&dir.join(&file.name))?.into_iter().enumerate() {
+            if strip_ansi(&line).to_lowercase().contains(&query) {
+                hits.push(Hit {
+                    file: file.name.to_owned(),
+                    lineno: i + 1,
+                    line,
+                });
+                if hits.len() == MAX_HITS {
+                    return Ok(hits);
+                }
+            }
+        }
+    }
+    Ok(hits)
+}
+
+// 文本及控制序列片段
+enum Segment<'a> {
+    Text(&'a str),
+    Sgr(&'a str),
+}
+
+// 拆分文本与SGR序列，其他控制序列及控制字符丢弃
+fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut segs = Vec::new();
+    let bytes = text.as_bytes();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b != 0x1b && (b >= 0x20 || b == b'\t') {
+            i += 1;
+            continue;
+        }
+        if start < i {
+            segs.push(Segment::Text(&text[start..i]));
+        }
+        i += 1;
+        if b == 0x1b && i < bytes.len() {
+            if bytes[i] == b'[' {
+                let params = i + 1;
+                i = params;
+                while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                    i += 1;
+                }
+                if i < bytes.len() && bytes[i] == b'm' {
+                    segs.push(Segment::Sgr(&text[params..i]));
+                }
+            }
+            i += 1;
+        }
+        start = i.min(bytes.len());
+    }
+    if start < bytes.len() {
+        segs.push(Segment::Text(&text[start..]));
+    }
+    segs
+}
+
+/// 去除ANSI控制序列
+pub fn strip_ansi(text: &str) -> String {
+    segments(text)
+        .into_iter()
+        .filter_map(|seg| match seg {
+            Segment::Text(s) => Some(s),
+            Segment::Sgr(_) => None,
+        })
+        .collect()
+}
+
+fn css_color(color: Color) -> Option<String> {
+    let (r, g, b) = color.rgb()?;
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+fn css_style(style: Style) -> String {
+    let mods = style.add_modifier - style.sub_modifier;
+    let (mut fg, mut bg) = (style.fg.and_then(css_color), style.bg.and_then(css_color));
+    if mods.contains(Modifier::REVERSED) {
+        fg = Some(bg.unwrap_or_else(|| "#000000".to_owned()));
+        bg = Some(style.fg.and_then(css_color).unwrap_or_else(|| "#e5e5e5".to_owned()));
+    }
+    let mut css = String::new();
+    if let Some(fg) = fg {
+        let _ = write!(css, "color:{};", fg);
+    }
+    if let Some(bg) = bg {
+        let _ = write!(css, "background:{};", bg);
+    }
+    if mods.contains(Modifier::BOLD) {
+        css.push_str("font-weight:bold;");
+    }
+    if mods.contains(Modifier::DIM) {
+        css.push_str("opacity:0.6;");
+    }
+    if mods.contains(Modifier::ITALIC) {
+        css.push_str("font-style:italic;");
+    }
+    if mods.contains(Modifier::UNDERLINED) {
+        css.push_str("text-decoration:underline;");
+    }
+    if mods.contains(Modifier::CROSSED_OUT) {
+        css.push_str("text-decoration:line-through;");
+    }
+    if mods.contains(Modifier::HIDDEN) {
+        css.push_str("visibility:hidden;");
+    }
+    css
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            '\'' => s.push_str("&#39;"),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+/// 将含ANSI颜色的文本转换为HTML，返回转换结果及行尾的样式，用于连续转换多行
+pub fn ansi_to_html(text: &str, mut style: Style) -> (String, Style) {
+    let mut html = String::new();
+    for seg in segments(text) {
+        match seg {
+            Segment::Sgr(params) => style = apply_sgr(style, params),
+            Segment::Text(s) => {
+                let css = css_style(style);
+                if css.is_empty() {
+                    html.push_str(&escape_html(s));
+                } else {
+                    let _ = write!(html, "<span style=\"{}\">{}</span>", css, escape_html(s));
+                }
+            }
+        }
+    }
+    (html, style)
+}
+
+/// 本地时间的日期及时分秒，如“2021-03-05 21:03:15”
+pub fn datetime(millis: u64) -> String {
+    let tm = match localtime(millis) {
+        Some(tm) => tm,
+        None => return String::from("----------"),
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// 在本机端口上提供日志浏览服务，每个连接使用单独的线程处理
+pub fn serve(dir: &Path, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("{}", i18n::trf("logs.serving", &[&listener.local_addr()?, &dir.display()]));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("log viewer accept error {}", e);
+                continue;
+            }
+        };
+        let dir = dir.to_owned();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &dir) {
+                log::warn!("log viewer request error {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, dir: &Path) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // 忽略请求头
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    if method != "GET" {
+        return respond(stream, "405 Method Not Allowed", String::from("method not allowed"));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = parse_query(query);
+    let page = match path {
+        "/" => index_page(dir, params.get("q").map(|s| s.as_str()).unwrap_or_default()),
+        "/log" => {
+            let name = params.get("file").map(|s| s.as_str()).unwrap_or_default();
+            let page = params.get("page").and_then(|p| p.parse().ok()).unwrap_or(0);
+            file_page(dir, name, page)
+        }
+        _ => return respond(stream, "404 Not Found", String::from("not found")),
+    };
+    match page {
+        Ok(body) => respond(stream, "200 OK", body),
+        Err(e) => respond(stream, "400 Bad Request", escape_html(&e.to_string())),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, body: String) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (url_decode(k), url_decode(v))
+        })
+        .collect()
+}
+
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn url_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b => {
+                let _ = write!(out, "%{:02X}", b);
+            }
+        }
+    }
+    out
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title><style>\
+         body{{background:#000;color:#e5e5e5;font-family:monospace}}\
+         a{{color:#5c5cff}}pre{{white-space:pre-wrap;margin:0}}\
+         td{{padding:0 1em}}.ln{{color:#7f7f7f;user-select:none}}\
+         </style></head><body>{}</body></html>",
+        escape_html(title),
+        body
+    )
+}
+
+fn search_form(query: &str) -> String {
+    format!(
+        "<form action=\"/\"><input name=\"q\" value=\"{}\" size=\"40\"> <input type=\"submit\" value=\"{}\"></form>",
+        escape_html(query),
+        escape_html(&i18n::tr("logs.search"))
+    )
+}
+
+fn file_link(name: &str, lineno: usize) -> String {
+    let page = lineno.saturating_sub(1) / PAGE_LINES;
+    format!("/log?file={}&page={}#L{}", url_encode(name), page, lineno)
+}
+
+// 首页：文件列表，指定搜索文本时显示匹配的行
+fn index_page(dir: &Path, query: &str) -> Result<String> {
+    let files = index(dir)?;
+    let mut body = search_form(query);
+    if query.is_empty() {
+        let _ = write!(body, "<h3>{}</h3><table>", escape_html(&i18n::tr("logs.files")));
+        for file in &files {
+            let _ = write!(
+                body,
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+                file_link(&file.name, 1),
+                escape_html(&file.name),
+                datetime(file.modified),
+                file.size
+            );
+        }
+        body.push_str("</table>");
+    } else {
+        let hits = search(dir, &files, query)?;
+        let _ = write!(body, "<h3>{}</h3><pre>", escape_html(&i18n::trf("logs.hits", &[&hits.len()])));
+        for hit in &hits {
+            let (line, _) = ansi_to_html(&hit.line, Style::default());
+            let _ = writeln!(
+                body,
+                "<a href=\"{}\">{}:{}</a> {}",
+                file_link(&hit.file, hit.lineno),
+                escape_html(&hit.file),
+                hit.lineno,
+                line
+            );
+        }
+        body.push_str("</pre>");
+    }
+    Ok(html_page(&i18n::tr("logs.files"), &body))
+}
+
+// 分页显示单个文件，样式跨行延续
+fn file_page(dir: &Path, name: &str, page: usize) -> Result<String> {
+    let lines = read_lines(&resolve(dir, name)?)?;
+    let start = page * PAGE_LINES;
+    let end = lines.len().min(start + PAGE_LINES);
+    let mut style = Style::default();
+    // 计算本页起始处的样式
+    for line in lines.iter().take(start) {
+        style = ansi_to_html(line, style).1;
+    }
+    let mut body = search_form("");
+    let _ = write!(body, "<h3><a href=\"/\">/</a> {}</h3>", escape_html(name));
+    let mut nav = String::new();
+    if page > 0 {
+        let _ = write!(
+            nav,
+            "<a href=\"/log?file={}&page={}\">{}</a> ",
+            url_encode(name),
+            page - 1,
+            escape_html(&i18n::tr("logs.prev"))
+        );
+    }
+    if end < lines.len() {
+        let _ = write!(
+            nav,
+            "<a href=\"/log?file={}&page={}\">{}</a>",
+            url_encode(name),
+            page + 1,
+            escape_html(&i18n::tr("logs.next"))
+        );
+    }
+    body.push_str(&nav);
+    body.push_str("<pre>");
+    for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+        let (html, next) = ansi_to_html(line, style);
+        style = next;
+        let _ = writeln!(body, "<span id=\"L{0}\" class=\"ln\">{0:>6} </span>{1}", i + 1, html);
+    }
+    body.push_str("</pre>");
+    body.push_str(&nav);
+    Ok(html_page(name, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_ansi_to_html() {
+        let (html, style) = ansi_to_html("\x1b[1;31m张三<b>\x1b[0m走了\x1b[38;5;196m。\r", Style::default());
+        assert_eq!(
+            "<span style=\"color:#cd0000;font-weight:bold;\">张三&lt;b&gt;</span>走了<span style=\"color:#ff0000;\">。</span>",
+            html
+        );
+        assert_eq!(Style::default().fg(Color::Indexed(196)), style);
+        // 样式延续到下一行
+        let (_, style) = ansi_to_html("\x1b[32m你", Style::default());
+        let (html, _) = ansi_to_html("好\x1b[2J", style);
+        assert_eq!("<span style=\"color:#00cd00;\">好</span>", html);
+        assert_eq!("张三走了", strip_ansi("\x1b[1;31m张三\x1b[m走了\x07"));
+        assert_eq!("a b&c", url_decode("a+b%26c"));
+        assert_eq!("%E5%BC%A0 a", url_encode("张 a").replace("%20", " "));
+    }
+
+    #[test]
+    fn test_log_index_search() {
+        let tmp = TempDir::new("logview");
+        let dir = tmp.path();
+        fs::write(dir.join("a.log"), "你走了过来。\r\n\x1b[31mZhang\x1b[0m tells you: hi\r\n").unwrap();
+        fs::write(dir.join("b.log"), "zhang san\n").unwrap();
+        let files = index(&dir).unwrap();
+        assert_eq!(2, files.len());
+        let mut hits = search(&dir, &files, "ZHANG").unwrap();
+        hits.sort_by(|a, b| a.file.cmp(&b.file));
+        assert_eq!(2, hits.len());
+        assert_eq!(("a.log", 2), (hits[0].file.as_str(), hits[0].lineno));
+        assert!(resolve(&dir, "../etc/passwd").is_err());
+        assert!(resolve(&dir, "/etc/passwd").is_err());
+        assert!(file_page(&dir, "a.log", 0).unwrap().contains("id=\"L2\""));
+    }
+}
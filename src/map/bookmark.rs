@@ -0,0 +1,36 @@
+use rusqlite::{Result, Row};
+use mlua::{Lua, ToLua, Value};
+use mlua::Result as LuaResult;
+
+/// 房间书签，与共享的地图数据分开存放于bookmarks表
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub roomid: u32,
+    // 房间名称，房间不存在时为空
+    pub roomname: Option<String>,
+    // 房间备注
+    pub note: Option<String>,
+}
+
+impl Bookmark {
+    pub(crate) fn from_row(row: &Row) -> Result<Self> {
+        Ok(Bookmark {
+            name: row.get(0)?,
+            roomid: row.get(1)?,
+            roomname: row.get(2)?,
+            note: row.get(3)?,
+        })
+    }
+}
+
+impl<'lua> ToLua<'lua> for Bookmark {
+    fn to_lua(self, lua: &'lua Lua) -> LuaResult<Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("name", self.name)?;
+        table.set("roomid", self.roomid)?;
+        table.set("roomname", self.roomname)?;
+        table.set("note", self.note)?;
+        Ok(Value::Table(table))
+    }
+}
@@ -21,13 +21,13 @@ pub trait Edge: Clone + Sized {
 pub trait Edges {
     type Edge: Edge;
     // 查询出口
-    fn exits(&self, id: u32) -> Vec<&Self::Edge>;
+    fn exits(&self, id: u32) -> Vec<Self::Edge>;
 }
 
 impl<T: Edges> Edges for Arc<T> {
     type Edge = <T as Edges>::Edge;
 
-    fn exits(&self, id: u32) -> Vec<&Self::Edge> {
+    fn exits(&self, id: u32) -> Vec<Self::Edge> {
         self.as_ref().exits(id)
     }
 }
@@ -38,8 +38,8 @@ pub struct EdgeMap<E>(HashMap<u32, Vec<E>>);
 
 impl<E: Edge> Edges for EdgeMap<E> {
     type Edge = E;
-    fn exits(&self, id: u32) -> Vec<&E> {
-        self.exits_slice(id).iter().collect()
+    fn exits(&self, id: u32) -> Vec<E> {
+        self.exits_slice(id).to_vec()
     }
 }
 
@@ -86,33 +86,33 @@ impl EdgeMap<Path> {
 
 /// 支持筛选部分路径
 #[derive(Debug, Clone)]
-pub struct FilteredEdges<E, F> {
-    map: Arc<EdgeMap<E>>,
+pub struct FilteredEdges<ES, F> {
+    map: ES,
     filter: F,
 }
 
-impl<E, F> Edges for FilteredEdges<E, F>
+impl<ES, F> Edges for FilteredEdges<ES, F>
 where
-    E: Edge,
-    F: Fn(&E) -> bool,
+    ES: Edges,
+    F: Fn(&ES::Edge) -> bool,
     F: Clone,
 {
-    type Edge = E;
-    fn exits(&self, id: u32) -> Vec<&E> {
-        self.map.exits_slice(id)
-            .iter()
+    type Edge = ES::Edge;
+    fn exits(&self, id: u32) -> Vec<ES::Edge> {
+        self.map.exits(id)
+            .into_iter()
             .filter(|e| (self.filter)(e))
             .collect()
     }
 }
 
-impl<E, F> FilteredEdges<E, F>
+impl<ES, F> FilteredEdges<ES, F>
 where
-    E: Edge,
-    F: Fn(&E) -> bool,
+    ES: Edges,
+    F: Fn(&ES::Edge) -> bool,
     F: Clone,
 {
-    pub fn new(map: Arc<EdgeMap<E>>, filter: F) -> Self {
+    pub fn new(map: ES, filter: F) -> Self {
         Self{map, filter}
     }
 }
\ No newline at end of file
@@ -1,12 +1,22 @@
 use rusqlite::{Connection, params};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::i18n;
+use crate::map::bookmark::Bookmark;
 use crate::map::room::Room;
 use crate::map::npc::Npc;
 use crate::map::zone::Zone;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
-#[derive(Debug)]
+/// 地图数据库的结构版本，记录于user_version
+pub const SCHEMA_VERSION: i32 = 1;
+
+/// 地图数据库的结构版本，未记录版本的旧数据库为0
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version", params![], |row| row.get(0))
+}
+
+#[derive(Debug, Clone)]
 pub struct Mapper(Arc<Mutex<Connection>>);
 
 impl Mapper {
@@ -135,4 +145,159 @@ impl Mapper {
         }
         Ok(None)
     }
+
+    /// 创建房间备注及书签表
+    /// 检查结构版本，拒绝更高版本的数据库
+    ///
+    /// 未记录版本的数据库结构与版本1相同，直接记录为当前版本，只读数据库无法记录时忽略
+    pub fn check_version(&self) -> Result<()> {
+        let conn = self.0.lock().unwrap();
+        match schema_version(&conn)? {
+            SCHEMA_VERSION => Ok(()),
+            0 => {
+                if let Err(e) = conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION)) {
+                    log::warn!("record map schema version error {}", e);
+                }
+                Ok(())
+            }
+            version => Err(Error::RuntimeError(i18n::trf(
+                "err.map_version",
+                &[&version, &SCHEMA_VERSION],
+            ))),
+        }
+    }
+
+    pub fn init_annotations(&self) -> Result<()> {
+        let conn = self.0.lock().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS room_notes (roomid INTEGER PRIMARY KEY, note TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS bookmarks (name TEXT PRIMARY KEY, roomid INTEGER NOT NULL);",
+        )?;
+        Ok(())
+    }
+
+    /// 设置房间备注，备注为空时删除
+    pub fn set_room_note(&self, roomid: u32, note: &str) -> Result<()> {
+        let conn = self.0.lock().unwrap();
+        if note.is_empty() {
+            conn.execute("DELETE FROM room_notes WHERE roomid = ?1", params![roomid])?;
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO room_notes (roomid, note) VALUES (?1, ?2)",
+                params![roomid, note],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn get_room_note(&self, roomid: u32) -> Result<Option<String>> {
+        let conn = self.0.lock().unwrap();
+        let mut stmt = conn
+            .prepare_cached("SELECT note FROM room_notes WHERE roomid = ?1")?;
+        let mut note_iter = stmt.query_map(params![roomid], |row| row.get(0))?;
+        Ok(note_iter.next().transpose()?)
+    }
+
+    /// 添加书签，同名书签将被覆盖
+    pub fn set_bookmark(&self, name: &str, roomid: u32) -> Result<()> {
+        let conn = self.0.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO bookmarks (name, roomid) VALUES (?1, ?2)",
+            params![name, roomid],
+        )?;
+        Ok(())
+    }
+
+    /// 删除书签，返回书签是否存在
+    pub fn delete_bookmark(&self, name: &str) -> Result<bool> {
+        let conn = self.0.lock().unwrap();
+        let n = conn.execute("DELETE FROM bookmarks WHERE name = ?1", params![name])?;
+        Ok(n > 0)
+    }
+
+    pub fn get_bookmark(&self, name: &str) -> Result<Option<Bookmark>> {
+        Ok(self
+            .query_bookmarks("WHERE b.name = ?1", params![name])?
+            .pop())
+    }
+
+    /// 所有书签，按名称排序
+    pub fn list_bookmarks(&self) -> Result<Vec<Bookmark>> {
+        self.query_bookmarks("ORDER BY b.name", params![])
+    }
+
+    fn query_bookmarks(&self, cond: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Bookmark>> {
+        let conn = self.0.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT b.name, b.roomid, r.name, n.note FROM bookmarks b
+             LEFT JOIN rooms r ON r.id = b.roomid
+             LEFT JOIN room_notes n ON n.roomid = b.roomid {}",
+            cond
+        ))?;
+        let bookmark_iter = stmt.query_map(params, Bookmark::from_row)?;
+        let mut bookmarks = Vec::new();
+        // 跳过无法解析的行，避免个别错误数据导致全部书签不可用
+        for bookmark in bookmark_iter {
+            match bookmark {
+                Ok(bookmark) => bookmarks.push(bookmark),
+                Err(e) => log::warn!("skip invalid bookmark {}", e),
+            }
+        }
+        Ok(bookmarks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_mapper_bookmarks() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE rooms(id, name); INSERT INTO rooms VALUES (1, '扬州广场');")
+            .unwrap();
+        let mapper = Mapper::new(Arc::new(Mutex::new(conn)));
+        mapper.check_version().unwrap();
+        mapper.init_annotations().unwrap();
+        mapper.init_annotations().unwrap();
+        mapper.set_room_note(1, "钱庄在北边").unwrap();
+        mapper.set_bookmark("yz", 1).unwrap();
+        mapper.set_bookmark("lost", 99).unwrap();
+        let bookmarks = mapper.list_bookmarks().unwrap();
+        assert_eq!(vec!["lost", "yz"], bookmarks.iter().map(|b| &b.name[..]).collect::<Vec<_>>());
+        assert_eq!(None, bookmarks[0].roomname);
+        let yz = mapper.get_bookmark("yz").unwrap().unwrap();
+        assert_eq!(Some("扬州广场".to_owned()), yz.roomname);
+        assert_eq!(Some("钱庄在北边".to_owned()), yz.note);
+        // 错误数据被跳过
+        mapper
+            .0
+            .lock()
+            .unwrap()
+            .execute("INSERT INTO bookmarks VALUES ('bad', 'not a room')", params![])
+            .unwrap();
+        assert_eq!(2, mapper.list_bookmarks().unwrap().len());
+        assert!(mapper.delete_bookmark("lost").unwrap());
+        assert!(!mapper.delete_bookmark("lost").unwrap());
+        mapper.set_room_note(1, "").unwrap();
+        assert_eq!(None, mapper.get_room_note(1).unwrap());
+    }
+
+    #[test]
+    fn test_mapper_schema_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mapper = Mapper::new(Arc::new(Mutex::new(conn)));
+        // 未记录版本的数据库记录为当前版本
+        mapper.check_version().unwrap();
+        assert_eq!(SCHEMA_VERSION, schema_version(&mapper.0.lock().unwrap()).unwrap());
+        mapper.check_version().unwrap();
+        mapper
+            .0
+            .lock()
+            .unwrap()
+            .execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+            .unwrap();
+        assert!(mapper.check_version().is_err());
+    }
 }
@@ -6,3 +6,6 @@ pub mod zone;
 pub mod node;
 pub mod edge;
 pub mod mapper;
+pub mod module;
+pub mod bookmark;
+pub mod store;
@@ -0,0 +1,161 @@
+use crate::map::edge::Edges;
+use crate::map::mapper::Mapper;
+use crate::map::node::Nodes;
+use crate::map::room::Room;
+use crate::map::store::MapStore;
+use crate::map::zone::Zone;
+use mlua::{Lua, MetaMethod, Table, ToLua, UserData, UserDataMethods, Value};
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// 脚本中通过require("map")使用的地图模块
+///
+/// 房间与区域以userdata返回，字段在首次访问时才从地图数据中加载
+#[derive(Clone)]
+pub struct MapModule {
+    store: Arc<MapStore>,
+    mapper: Mapper,
+}
+
+impl MapModule {
+    pub fn new(store: Arc<MapStore>, mapper: Mapper) -> Self {
+        Self { store, mapper }
+    }
+
+    fn room(&self, id: u32) -> LuaRoom {
+        LuaRoom {
+            id,
+            room: RefCell::new(None),
+            module: self.clone(),
+        }
+    }
+
+    fn loaded_room(&self, room: Room) -> LuaRoom {
+        LuaRoom {
+            id: room.id,
+            room: RefCell::new(Some(room)),
+            module: self.clone(),
+        }
+    }
+
+    fn zone(&self, zone: Zone) -> LuaZone {
+        LuaZone {
+            zone,
+            module: self.clone(),
+        }
+    }
+
+    /// 构造模块表：room(id)、zone(code)、zones()、find(name[, zone])
+    pub fn to_table<'lua>(&self, lua: &'lua Lua) -> mlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+
+        // 房间不存在或不可达时返回nil
+        let m = self.clone();
+        let room = lua.create_function(move |_, id: u32| {
+            Ok(if m.store.contains(id) { Some(m.room(id)) } else { None })
+        })?;
+        table.set("room", room)?;
+
+        let m = self.clone();
+        let zone = lua.create_function(move |_, code: String| {
+            Ok(m.mapper.get_zone_by_code(&code)?.map(|z| m.zone(z)))
+        })?;
+        table.set("zone", zone)?;
+
+        let m = self.clone();
+        let zones = lua.create_function(move |_, ()| {
+            let zones = m.mapper.list_zones()?;
+            Ok(zones.into_iter().map(|z| m.zone(z)).collect::<Vec<_>>())
+        })?;
+        table.set("zones", zones)?;
+
+        let m = self.clone();
+        let find = lua.create_function(move |_, (name, zone): (String, Option<String>)| {
+            let rooms = match zone {
+                Some(zone) => m.mapper.list_rooms_by_name_and_zone(&name, &zone)?,
+                None => m.mapper.list_rooms_by_name(&name)?,
+            };
+            Ok(rooms.into_iter().map(|r| m.loaded_room(r)).collect::<Vec<_>>())
+        })?;
+        table.set("find", find)?;
+
+        Ok(table)
+    }
+}
+
+/// 房间对象，字段：id、name、code、description、zone、exit_text，
+/// 方法：exits()返回出口路径列表，note()返回房间备注
+pub struct LuaRoom {
+    id: u32,
+    // 首次访问字段时加载
+    room: RefCell<Option<Room>>,
+    module: MapModule,
+}
+
+impl LuaRoom {
+    fn field<'lua>(&self, lua: &'lua Lua, key: &str) -> mlua::Result<Value<'lua>> {
+        if key == "id" {
+            return self.id.to_lua(lua);
+        }
+        let mut room = self.room.borrow_mut();
+        if room.is_none() {
+            *room = self.module.store.get(self.id);
+        }
+        let room = match room.as_ref() {
+            Some(room) => room,
+            None => return Ok(Value::Nil),
+        };
+        match key {
+            "name" => room.name.as_str().to_lua(lua),
+            "code" => room.code.as_str().to_lua(lua),
+            "description" => room.description.as_str().to_lua(lua),
+            "zone" => room.zone.as_str().to_lua(lua),
+            "exit_text" => room.exits.as_str().to_lua(lua),
+            _ => Ok(Value::Nil),
+        }
+    }
+}
+
+impl UserData for LuaRoom {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("exits", |_, this, ()| Ok(this.module.store.exits(this.id)));
+        methods.add_method("note", |_, this, ()| Ok(this.module.mapper.get_room_note(this.id)?));
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: String| this.field(lua, &key));
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: mlua::AnyUserData| {
+            Ok(other.borrow::<LuaRoom>().map(|o| o.id == this.id).unwrap_or(false))
+        });
+        methods.add_meta_method(MetaMethod::ToString, |lua, this, ()| {
+            let name = this.field(lua, "name")?;
+            let name = match name {
+                Value::String(s) => s.to_str()?.to_owned(),
+                _ => String::new(),
+            };
+            Ok(format!("room({} {})", this.id, name))
+        });
+    }
+}
+
+/// 区域对象，字段：id、code、name、centercode，方法：rooms()返回区域内的房间
+pub struct LuaZone {
+    zone: Zone,
+    module: MapModule,
+}
+
+impl UserData for LuaZone {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("rooms", |_, this, ()| {
+            let rooms = this.module.mapper.list_rooms_by_zone(&this.zone.code)?;
+            Ok(rooms.into_iter().map(|r| this.module.loaded_room(r)).collect::<Vec<_>>())
+        });
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: String| match key.as_str() {
+            "id" => this.zone.id.as_str().to_lua(lua),
+            "code" => this.zone.code.as_str().to_lua(lua),
+            "name" => this.zone.name.as_str().to_lua(lua),
+            "centercode" => this.zone.centercode.as_str().to_lua(lua),
+            _ => Ok(Value::Nil),
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("zone({} {})", this.zone.code, this.zone.name))
+        });
+    }
+}
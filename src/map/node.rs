@@ -70,19 +70,19 @@ impl NodeMap<Room> {
 
 /// 支持筛选部分节点
 #[derive(Debug, Clone)]
-pub struct FilteredNodes<N, F> {
-    map: Arc<NodeMap<N>>,
+pub struct FilteredNodes<NS, F> {
+    map: NS,
     filter: F,
 }
 
-impl<N, F> Nodes for FilteredNodes<N, F>
+impl<NS, F> Nodes for FilteredNodes<NS, F>
 where
-    N: Node,
-    F: Fn(&N) -> bool,
+    NS: Nodes,
+    F: Fn(&NS::Node) -> bool,
     F: Clone,
 {
-    type Node = N;
-    fn get(&self, id: u32) -> Option<N> {
+    type Node = NS::Node;
+    fn get(&self, id: u32) -> Option<NS::Node> {
         match self.map.get(id) {
             Some(node) if (self.filter)(&node) => Some(node),
             _ => None,
@@ -97,13 +97,13 @@ where
     }
 }
 
-impl<N, F> FilteredNodes<N, F>
+impl<NS, F> FilteredNodes<NS, F>
 where
-    N: Node,
-    F: Fn(&N) -> bool,
+    NS: Nodes,
+    F: Fn(&NS::Node) -> bool,
     F: Clone,
 {
-    pub fn new(map: Arc<NodeMap<N>>, filter: F) -> Self {
+    pub fn new(map: NS, filter: F) -> Self {
         Self{map, filter}
     }
 }
\ No newline at end of file
@@ -2,6 +2,8 @@ use crate::map::edge::Edge;
 use rusqlite::{Result, Row};
 use mlua::{Lua, ToLua, Value};
 use mlua::Result as LuaResult;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Clone)]
 pub struct Path {
@@ -82,7 +84,7 @@ impl<'lua> ToLua<'lua> for &Path {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PathCategory {
     Normal,
     Multiple,
@@ -110,9 +112,9 @@ impl From<u32> for PathCategory {
     }
 }
 
-impl<'a> From<&'a str> for PathCategory {
-    fn from(src: &str) -> Self {
-        match src {
+impl PathCategory {
+    pub fn parse(src: &str) -> Option<Self> {
+        let category = match src {
             "normal" => Self::Normal,
             "multiple" => Self::Multiple,
             "busy" => Self::Busy,
@@ -121,8 +123,15 @@ impl<'a> From<&'a str> for PathCategory {
             "block" => Self::Block,
             "checkbusy" => Self::CheckBusy,
             "bus" => Self::Bus,
-            _ => Self::Normal,
-        }
+            _ => return None,
+        };
+        Some(category)
+    }
+}
+
+impl<'a> From<&'a str> for PathCategory {
+    fn from(src: &str) -> Self {
+        Self::parse(src).unwrap_or(Self::Normal)
     }
 }
 
@@ -140,4 +149,63 @@ impl ToString for PathCategory {
         };
         s.to_owned()
     }
-}
\ No newline at end of file
+}
+// 单条路径调整后的最大权重，避免累加溢出
+const MAX_WEIGHT: u32 = 1 << 20;
+
+/// 按路径类别设置的权重系数
+///
+/// 在路径搜索时读取，修改后对所有共享该实例的规划器立即生效，
+/// 未设置的类别系数为1
+#[derive(Debug, Clone, Default)]
+pub struct CostFactors(Arc<RwLock<HashMap<PathCategory, f64>>>);
+
+impl CostFactors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, category: PathCategory, factor: f64) {
+        let mut factors = self.0.write().unwrap();
+        if factor == 1.0 {
+            factors.remove(&category);
+        } else {
+            factors.insert(category, factor);
+        }
+    }
+
+    pub fn get(&self, category: PathCategory) -> f64 {
+        self.0.read().unwrap().get(&category).cloned().unwrap_or(1.0)
+    }
+
+    /// 计算路径调整后的权重
+    pub fn cost(&self, path: &Path) -> u32 {
+        let factor = self.get(path.category);
+        if factor == 1.0 {
+            return path.weight;
+        }
+        (path.weight as f64 * factor).round().min(MAX_WEIGHT as f64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_factors() {
+        let factors = CostFactors::new();
+        let mut path = Path::pseudo(1);
+        path.weight = 3;
+        path.category = PathCategory::Boat;
+        assert_eq!(3, factors.cost(&path));
+        factors.set(PathCategory::Boat, 2.5);
+        assert_eq!(8, factors.cost(&path));
+        factors.set(PathCategory::Boat, 1e30);
+        assert_eq!(MAX_WEIGHT, factors.cost(&path));
+        factors.set(PathCategory::Boat, 1.0);
+        assert_eq!(1.0, factors.get(PathCategory::Boat));
+        assert_eq!(Some(PathCategory::CheckBusy), PathCategory::parse("checkbusy"));
+        assert_eq!(None, PathCategory::parse("plane"));
+    }
+}
@@ -3,9 +3,13 @@ use crate::map::edge::{Edge, Edges};
 use std::cmp::{Ord, Ordering};
 use std::collections::{BinaryHeap, HashMap, HashSet};
 
-pub struct Planner<NS, ES> {
+// 搜索时计算边权重的函数
+type CostFn<E> = Box<dyn Fn(&E) -> u32 + Send + Sync>;
+
+pub struct Planner<NS, ES: Edges> {
     nodes: NS,
     edges: ES,
+    cost: Option<CostFn<ES::Edge>>,
 }
 
 impl<NS, ES> Planner<NS, ES>
@@ -14,12 +18,32 @@ where
     ES: Edges,
 {
     pub fn new(nodes: NS, edges: ES) -> Self {
-        Self { nodes, edges }
+        Self {
+            nodes,
+            edges,
+            cost: None,
+        }
+    }
+
+    /// 设置边权重的计算函数，搜索时代替边自身的权重
+    pub fn with_cost<F>(mut self, cost: F) -> Self
+    where
+        F: Fn(&ES::Edge) -> u32 + Send + Sync + 'static,
+    {
+        self.cost = Some(Box::new(cost));
+        self
+    }
+
+    fn weight_of(&self, edge: &ES::Edge) -> u32 {
+        match self.cost.as_ref() {
+            Some(cost) => cost(edge),
+            None => edge.weight(),
+        }
     }
 
     /// 使用bfs进行路径搜索，返回的行走计划为路径栈。
     /// 出栈过程即顺序行走
-    pub fn walk(&self, fromid: u32, toid: u32) -> Vec<&ES::Edge> {
+    pub fn walk(&self, fromid: u32, toid: u32) -> Vec<ES::Edge> {
         if !self.nodes.contains(fromid) || !self.nodes.contains(toid) {
             return vec![];
         }
@@ -28,10 +52,9 @@ where
         let mut prev = HashMap::<u32, Weight<ES::Edge>>::new();
         let mut reached = std::u32::MAX;
 
-        let pseudo_path = ES::Edge::pseudo(fromid);
         candidates.push(Weight {
             weight: 0,
-            edge: &pseudo_path,
+            edge: ES::Edge::pseudo(fromid),
         });
         while let Some(curr) = candidates.pop() {
             if curr.weight >= reached {
@@ -39,17 +62,18 @@ where
                 break;
             }
             for e in self.edges.exits(curr.edge.endid()) {
-                let curr_weight = curr.weight + e.weight();
+                let curr_weight = curr.weight + self.weight_of(&e);
+                let endid = e.endid();
                 if curr_weight < reached {
                     // 当前权重小于可到达
-                    if let Some(cal) = prev.get(&e.endid()) {
+                    if let Some(cal) = prev.get(&endid) {
                         // 下一个房间曾经计算过，和当前权重进行比较，取较小者
                         if curr_weight < cal.weight {
                             let w = Weight {
                                 weight: curr_weight,
                                 edge: e,
                             };
-                            prev.insert(e.endid(), w.clone());
+                            prev.insert(endid, w.clone());
                             // 因为使用较小值修改了曾计算的值，需要将该节点
                             // 重新推入队列，导致其衍生的所有后续节点的重新
                             // 计算，这使得优先队列将存放更多的元素。
@@ -61,8 +85,8 @@ where
                             weight: curr_weight,
                             edge: e,
                         };
-                        prev.insert(e.endid(), w.clone());
-                        if e.endid() == toid {
+                        prev.insert(endid, w.clone());
+                        if endid == toid {
                             // 更新到达权重
                             reached = curr_weight;
                         } else {
@@ -76,15 +100,77 @@ where
         let mut plan = vec![];
         let mut currid = toid;
         while currid != fromid {
-            let w = &prev[&currid];
+            // 不可达
+            let w = match prev.get(&currid) {
+                Some(w) => w,
+                None => return vec![],
+            };
             currid = w.edge.startid();
-            plan.push(w.edge);
+            plan.push(w.edge.clone());
         }
         plan
     }
 
+    /// 使用Dijkstra算法由起点向外搜索，返回距离最近且满足条件的节点
+    /// 以及前往该节点的行走计划（路径栈，同walk）
+    pub fn nearest<F>(&self, fromid: u32, mut pred: F) -> Option<(NS::Node, Vec<ES::Edge>)>
+    where
+        F: FnMut(&NS::Node) -> bool,
+    {
+        if !self.nodes.contains(fromid) {
+            return None;
+        }
+        let mut candidates = BinaryHeap::new();
+        let mut prev = HashMap::<u32, Weight<ES::Edge>>::new();
+        let mut visited = HashSet::<u32>::new();
+
+        candidates.push(Weight {
+            weight: 0,
+            edge: ES::Edge::pseudo(fromid),
+        });
+        while let Some(curr) = candidates.pop() {
+            let currid = curr.edge.endid();
+            if !visited.insert(currid) {
+                // 已确定最短距离的节点无需再次计算
+                continue;
+            }
+            if let Some(node) = self.nodes.get(currid) {
+                if pred(&node) {
+                    let mut plan = vec![];
+                    let mut id = currid;
+                    while id != fromid {
+                        let w = &prev[&id];
+                        id = w.edge.startid();
+                        plan.push(w.edge.clone());
+                    }
+                    return Some((node, plan));
+                }
+            }
+            for e in self.edges.exits(currid) {
+                if visited.contains(&e.endid()) {
+                    continue;
+                }
+                let curr_weight = curr.weight + self.weight_of(&e);
+                let endid = e.endid();
+                let shorter = prev
+                    .get(&endid)
+                    .map(|cal| curr_weight < cal.weight)
+                    .unwrap_or(true);
+                if shorter {
+                    let w = Weight {
+                        weight: curr_weight,
+                        edge: e,
+                    };
+                    prev.insert(endid, w.clone());
+                    candidates.push(w);
+                }
+            }
+        }
+        None
+    }
+
     // 使用dfs生成遍历计划
-    pub fn traverse(&self, centerid: u32, depth: u32) -> Vec<&ES::Edge> {
+    pub fn traverse(&self, centerid: u32, depth: u32) -> Vec<ES::Edge> {
         if !self.nodes.contains(centerid) || depth < 1 {
             return vec![];
         }
@@ -100,7 +186,8 @@ where
         }
         let mut plan = Vec::new();
         while let Some(d) = candidates.pop() {
-            if reached.contains(&d.edge.endid()) {
+            let endid = d.edge.endid();
+            if reached.contains(&endid) {
                 // 目标节点已路过，无需再走
                 continue;
             }
@@ -110,12 +197,12 @@ where
                 plan.push(d.edge);
             } else {
                 // 生成walk计划，并加入
-                let mut walkplan = self.walk(currid, d.edge.endid());
+                let mut walkplan = self.walk(currid, endid);
                 if walkplan.is_empty() {
                     log::warn!(
                         "failed to generate traverse plan because {} and {} are not connected",
                         currid,
-                        d.edge.endid()
+                        endid
                     );
                     return vec![];
                 }
@@ -126,7 +213,7 @@ where
             // 将当前节点加入已访问列表
             reached.insert(currid);
             // 设置当前节点设置为目标节点
-            currid = d.edge.endid();
+            currid = endid;
             if d.depth < depth {
                 // 在小于深度时将临近节点加入候选列表
                 for exit in self.edges.exits(currid) {
@@ -143,43 +230,43 @@ where
 }
 
 #[derive(Debug, Clone)]
-struct Depth<'a, E> {
+struct Depth<E> {
     depth: u32,
-    edge: &'a E,
+    edge: E,
 }
 
 #[derive(Debug, Clone)]
-struct Weight<'a, E> {
+struct Weight<E> {
     weight: u32,
-    edge: &'a E,
+    edge: E,
 }
 
-impl<'a, E> Ord for Weight<'a, E> {
+impl<E> Ord for Weight<E> {
     fn cmp(&self, other: &Self) -> Ordering {
         // 逆序
         other.weight.cmp(&self.weight)
     }
 }
 
-impl<'a, E> PartialOrd for Weight<'a, E> {
+impl<E> PartialOrd for Weight<E> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<'a, E> PartialEq for Weight<'a, E> {
+impl<E> PartialEq for Weight<E> {
     fn eq(&self, other: &Self) -> bool {
         self.weight.eq(&other.weight)
     }
 }
 
-impl<'a, E> Eq for Weight<'a, E> {}
+impl<E> Eq for Weight<E> {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::map::node::{NodeMap, Node};
-    use crate::map::edge::{EdgeMap, Edge};
+    use crate::map::node::{FilteredNodes, NodeMap, Node};
+    use crate::map::edge::{EdgeMap, Edge, FilteredEdges};
     use std::sync::Arc;
 
     #[test]
@@ -216,7 +303,7 @@ mod tests {
         let rs = planner.walk(1, 2);
         println!("{:?}", rs);
         assert_eq!(
-            vec![&E {
+            vec![E {
                 startid: 1,
                 endid: 2,
                 weight: 1
@@ -269,22 +356,22 @@ mod tests {
         println!("{:?}", rs);
         assert_eq!(
             vec![
-                &E {
+                E {
                     startid: 4,
                     endid: 5,
                     weight: 1
                 },
-                &E {
+                E {
                     startid: 3,
                     endid: 4,
                     weight: 1
                 },
-                &E {
+                E {
                     startid: 2,
                     endid: 3,
                     weight: 1
                 },
-                &E {
+                E {
                     startid: 1,
                     endid: 2,
                     weight: 1
@@ -294,6 +381,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_planner_walk_with_cost() {
+        let mut nodes = NodeMap::new();
+        nodes.put(N { id: 1 });
+        nodes.put(N { id: 2 });
+        nodes.put(N { id: 3 });
+        let mut edges = EdgeMap::new();
+        edges.insert(E {
+            startid: 1,
+            endid: 3,
+            weight: 1,
+        });
+        edges.insert(E {
+            startid: 1,
+            endid: 2,
+            weight: 2,
+        });
+        edges.insert(E {
+            startid: 2,
+            endid: 3,
+            weight: 2,
+        });
+        // 直达路径权重放大后改走绕行路径
+        let planner = Planner::new(nodes, edges)
+            .with_cost(|e: &E| if e.startid == 1 && e.endid == 3 { e.weight * 10 } else { e.weight });
+        let rs = planner.walk(1, 3);
+        assert_eq!(2, rs.len());
+        assert_eq!(2, rs[0].startid);
+        assert_eq!(1, rs[1].startid);
+    }
+
+    #[test]
+    fn test_planner_filtered_traverse() {
+        let mut nodes = NodeMap::new();
+        for id in 1..=3 {
+            nodes.put(N { id });
+        }
+        let mut edges = EdgeMap::new();
+        for (startid, endid) in &[(1, 2), (2, 1), (1, 3), (3, 1)] {
+            edges.insert(E {
+                startid: *startid,
+                endid: *endid,
+                weight: 1,
+            });
+        }
+        // 节点3不在区域内，遍历时不应进入
+        let area = |id: u32| id != 3;
+        let nodes = FilteredNodes::new(Arc::new(nodes), move |n: &N| area(n.id));
+        let edges = FilteredEdges::new(Arc::new(edges), move |e: &E| area(e.endid));
+        let planner = Planner::new(nodes, edges);
+        let rs = planner.traverse(1, 2);
+        assert_eq!(
+            vec![E {
+                startid: 1,
+                endid: 2,
+                weight: 1
+            }],
+            rs
+        );
+    }
+
     #[test]
     fn test_planner_simple_traverse() {
         let mut nodes = NodeMap::new();
@@ -322,12 +470,12 @@ mod tests {
         println!("{:?}", rs);
         assert_eq!(
             vec![
-                &E {
+                E {
                     startid: 1,
                     endid: 2,
                     weight: 1
                 },
-                &E {
+                E {
                     startid: 2,
                     endid: 3,
                     weight: 1
@@ -418,6 +566,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_planner_nearest() {
+        let mut nodes = NodeMap::new();
+        for id in 1..=5 {
+            nodes.put(N { id });
+        }
+        let mut edges = EdgeMap::new();
+        // 1 -> 2 -> 3, 1 -> 4 -> 5, 1 -> 5 (weight 10)
+        edges.insert(E {
+            startid: 1,
+            endid: 2,
+            weight: 1,
+        });
+        edges.insert(E {
+            startid: 2,
+            endid: 3,
+            weight: 1,
+        });
+        edges.insert(E {
+            startid: 1,
+            endid: 4,
+            weight: 2,
+        });
+        edges.insert(E {
+            startid: 4,
+            endid: 5,
+            weight: 2,
+        });
+        edges.insert(E {
+            startid: 1,
+            endid: 5,
+            weight: 10,
+        });
+        let planner = Planner::new(nodes, edges);
+        // 起点满足条件
+        let (n, plan) = planner.nearest(1, |n| n.id == 1).unwrap();
+        assert_eq!(1, n.id);
+        assert!(plan.is_empty());
+        // 3距离为2，5距离为4
+        let (n, plan) = planner.nearest(1, |n| n.id == 3 || n.id == 5).unwrap();
+        assert_eq!(3, n.id);
+        assert_eq!(2, plan.len());
+        assert_eq!(1, plan.last().unwrap().startid);
+        // 5经过4到达，而非直达
+        let (n, plan) = planner.nearest(1, |n| n.id == 5).unwrap();
+        assert_eq!(5, n.id);
+        assert_eq!(4, plan[0].startid);
+        // 不可达
+        assert!(planner.nearest(3, |n| n.id == 1).is_none());
+    }
+
     #[derive(Debug, Clone, PartialEq)]
     struct E {
         startid: u32,
@@ -0,0 +1,199 @@
+use crate::map::edge::{EdgeMap, Edges};
+use crate::map::node::{NodeMap, Nodes};
+use crate::map::path::Path;
+use crate::map::room::Room;
+use rusqlite::{params, Connection, Result};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// 延迟加载时数据库的内存映射大小
+const MMAP_SIZE: i64 = 256 * 1024 * 1024;
+
+/// 地图数据，启动时全部加载，或按区域延迟加载
+pub enum MapStore {
+    Full {
+        rooms: NodeMap<Room>,
+        paths: EdgeMap<Path>,
+    },
+    Lazy(ZoneCache),
+}
+
+impl MapStore {
+    /// zone_cache为None时全部加载，否则为缓存的区域数
+    pub fn load(conn: Arc<Mutex<Connection>>, zone_cache: Option<usize>) -> Result<Self> {
+        match zone_cache {
+            None => {
+                let conn = conn.lock().unwrap();
+                let rooms = NodeMap::load_from_db(&conn)?;
+                let paths = EdgeMap::load_from_db(&conn, &rooms)?;
+                Ok(Self::Full { rooms, paths })
+            }
+            Some(capacity) => Ok(Self::Lazy(ZoneCache::new(conn, capacity)?)),
+        }
+    }
+}
+
+impl Nodes for MapStore {
+    type Node = Room;
+
+    fn get(&self, id: u32) -> Option<Room> {
+        match self {
+            Self::Full { rooms, .. } => rooms.get(id),
+            Self::Lazy(cache) => cache.zone_data(id)?.rooms.get(&id).cloned(),
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        match self {
+            Self::Full { rooms, .. } => rooms.contains(id),
+            Self::Lazy(cache) => cache.zones.contains_key(&id),
+        }
+    }
+}
+
+impl Edges for MapStore {
+    type Edge = Path;
+
+    fn exits(&self, id: u32) -> Vec<Path> {
+        match self {
+            Self::Full { paths, .. } => paths.exits(id),
+            Self::Lazy(cache) => cache
+                .zone_data(id)
+                .and_then(|data| data.exits.get(&id).cloned())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// 单个区域的房间及由这些房间出发的路径
+#[derive(Debug, Default)]
+struct ZoneData {
+    rooms: HashMap<u32, Room>,
+    exits: HashMap<u32, Vec<Path>>,
+}
+
+/// 按区域加载的地图缓存，超出容量时淘汰最久未使用的区域
+///
+/// 启动时仅加载房间所属区域的索引，跨区域的路径在起点所在区域中加载，
+/// 因此规划器可以正常跨越区域边界
+pub struct ZoneCache {
+    conn: Arc<Mutex<Connection>>,
+    // 有效房间所属的区域
+    zones: HashMap<u32, String>,
+    capacity: usize,
+    // 最近使用的区域位于队尾
+    lru: Mutex<VecDeque<(String, Arc<ZoneData>)>>,
+}
+
+impl ZoneCache {
+    fn new(conn: Arc<Mutex<Connection>>, capacity: usize) -> Result<Self> {
+        let zones = {
+            let conn = conn.lock().unwrap();
+            // 查询量大时直接读取映射的文件页
+            conn.pragma_update(None, "mmap_size", &MMAP_SIZE)?;
+            let mut stmt = conn.prepare("SELECT id, zone FROM rooms where name <> '' and zone <> ''")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<HashMap<u32, String>>>()?
+        };
+        Ok(Self {
+            conn,
+            zones,
+            capacity: capacity.max(1),
+            lru: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    // 房间所在区域的数据，未缓存时从数据库加载
+    fn zone_data(&self, id: u32) -> Option<Arc<ZoneData>> {
+        let zone = self.zones.get(&id)?;
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(pos) = lru.iter().position(|(z, _)| z == zone) {
+            let entry = lru.remove(pos).unwrap();
+            let data = entry.1.clone();
+            lru.push_back(entry);
+            return Some(data);
+        }
+        let data = match self.load_zone(zone) {
+            Ok(data) => Arc::new(data),
+            Err(e) => {
+                log::warn!("load map zone {} error {}", zone, e);
+                return None;
+            }
+        };
+        log::debug!("map zone {} loaded with {} rooms", zone, data.rooms.len());
+        lru.push_back((zone.to_owned(), data.clone()));
+        while lru.len() > self.capacity {
+            lru.pop_front();
+        }
+        Some(data)
+    }
+
+    fn load_zone(&self, zone: &str) -> Result<ZoneData> {
+        let conn = self.conn.lock().unwrap();
+        let mut data = ZoneData::default();
+        let mut stmt = conn.prepare("SELECT * FROM rooms where zone = ?1 and name <> ''")?;
+        for room in stmt.query_map(params![zone], Room::from_row)? {
+            let room = room?;
+            data.rooms.insert(room.id, room);
+        }
+        let mut stmt = conn.prepare(
+            "SELECT paths.* FROM paths JOIN rooms ON paths.startid = rooms.id \
+             where rooms.zone = ?1 and rooms.name <> ''",
+        )?;
+        for path in stmt.query_map(params![zone], Path::from_row)? {
+            let path = path?;
+            // 同全部加载，排除所有不可达路径
+            if self.zones.contains_key(&path.endid) {
+                data.exits.entry(path.startid).or_insert_with(Vec::new).push(path);
+            }
+        }
+        Ok(data)
+    }
+
+    /// 已缓存的区域数
+    pub fn cached(&self) -> usize {
+        self.lru.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::plan::Planner;
+
+    #[test]
+    fn test_map_store_lazy_zones() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE rooms (id INTEGER, name TEXT, code TEXT, description TEXT, exits TEXT, zone TEXT, mapinfo TEXT, blockzone TEXT);
+             CREATE TABLE paths (startid INTEGER, endid INTEGER, path TEXT, endcode TEXT, weight INTEGER, enabled INTEGER, category INTEGER, mapchange INTEGER, blockers TEXT);
+             INSERT INTO rooms VALUES (1, '扬州广场', 'yz1', '', '', 'yz', '', '');
+             INSERT INTO rooms VALUES (2, '北门', 'yz2', '', '', 'yz', '', '');
+             INSERT INTO rooms VALUES (3, '官道', 'gd1', '', '', 'gd', '', '');
+             INSERT INTO rooms VALUES (4, '南门', 'bj1', '', '', 'bj', '', '');
+             INSERT INTO paths VALUES (1, 2, 'n', '', 1, 1, 0, 0, '');
+             INSERT INTO paths VALUES (2, 3, 'n', '', 1, 1, 0, 0, '');
+             INSERT INTO paths VALUES (3, 4, 'n', '', 1, 1, 0, 0, '');
+             INSERT INTO paths VALUES (3, 5, 'e', '', 1, 1, 0, 0, '');",
+        )
+        .unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+        let full = Arc::new(MapStore::load(conn.clone(), None).unwrap());
+        let lazy = Arc::new(MapStore::load(conn, Some(2)).unwrap());
+        let cmds = |store: &Arc<MapStore>| -> Vec<String> {
+            let planner = Planner::new(store.clone(), store.clone());
+            planner.walk(1, 4).into_iter().rev().map(|p| p.path).collect()
+        };
+        // 跨越三个区域的路径与全部加载时一致
+        assert_eq!(vec!["n", "n", "n"], cmds(&full));
+        assert_eq!(cmds(&full), cmds(&lazy));
+        match lazy.as_ref() {
+            MapStore::Lazy(cache) => assert_eq!(2, cache.cached()),
+            _ => unreachable!(),
+        }
+        // 不存在的房间不可达
+        assert!(lazy.exits(3).iter().all(|p| p.endid != 5));
+        assert_eq!("官道", lazy.get(3).unwrap().name);
+        assert!(!lazy.contains(5));
+    }
+}
@@ -0,0 +1,211 @@
+use libtelnet_rs::telnet::op_command as Op;
+use libtelnet_rs::telnet::op_option as Opt;
+use mlua::{Lua, ToLua, Value};
+use std::time::{Duration, Instant};
+
+/// 测量期间最多发送的探测次数
+pub const MAX_PINGS: usize = 5;
+
+// 平均延迟超过该值时建议放宽超时及发送节奏
+const HIGH_LATENCY_MS: u64 = 300;
+// 延迟波动超过该值时建议限制服务器队列长度
+const HIGH_JITTER_MS: u64 = 200;
+// 未压缩时平均每次读取超过该字节数建议启用MCCP
+const LARGE_READ_BYTES: usize = 1024;
+
+/// 连接初期的延迟及包大小测量
+///
+/// 延迟通过IAC DO TIMING-MARK测量，服务器以WILL或WONT应答，
+/// 应答在交给telnet解析器之前被移除，避免解析器回复协商
+#[derive(Debug)]
+pub struct Probe {
+    started: Instant,
+    window: Duration,
+    sent: Option<Instant>,
+    pings: usize,
+    rtts: Vec<Duration>,
+    reads: Vec<usize>,
+}
+
+impl Probe {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            window,
+            sent: None,
+            pings: 0,
+            rtts: Vec::new(),
+            reads: Vec::new(),
+        }
+    }
+
+    /// 发送探测，返回需要发送给服务器的字节
+    pub fn ping(&mut self, now: Instant) -> Vec<u8> {
+        self.sent = Some(now);
+        self.pings += 1;
+        vec![Op::IAC, Op::DO, Opt::TM]
+    }
+
+    /// 记录一次从连接读取的字节数
+    pub fn record_read(&mut self, n: usize) {
+        self.reads.push(n);
+    }
+
+    /// 移除数据中的探测应答并记录延迟，返回剩余数据及是否收到应答
+    pub fn strip_pongs(&mut self, data: &[u8], now: Instant) -> (Vec<u8>, bool) {
+        let mut out = Vec::with_capacity(data.len());
+        let mut ponged = false;
+        let mut i = 0;
+        while i < data.len() {
+            if data[i] == Op::IAC && i + 1 < data.len() {
+                if data[i + 1] == Op::IAC {
+                    out.extend_from_slice(&data[i..i + 2]);
+                    i += 2;
+                    continue;
+                }
+                let reply = data[i + 1] == Op::WILL || data[i + 1] == Op::WONT;
+                if reply && data.get(i + 2) == Some(&Opt::TM) {
+                    if let Some(sent) = self.sent.take() {
+                        self.rtts.push(now.saturating_duration_since(sent));
+                        ponged = true;
+                    }
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(data[i]);
+            i += 1;
+        }
+        (out, ponged)
+    }
+
+    /// 收到应答后是否继续探测
+    pub fn should_ping(&self, now: Instant) -> bool {
+        self.sent.is_none() && self.pings < MAX_PINGS && !self.expired(now)
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= self.window
+    }
+
+    /// 探测次数用尽或测量时间结束
+    pub fn done(&self, now: Instant) -> bool {
+        (self.pings >= MAX_PINGS && self.sent.is_none()) || self.expired(now)
+    }
+
+    pub fn report(&self, mccp: bool) -> ProbeReport {
+        let rtts: Vec<u64> = self.rtts.iter().map(|d| d.as_millis() as u64).collect();
+        ProbeReport {
+            pings: self.pings,
+            rtt_min: rtts.iter().copied().min(),
+            rtt_max: rtts.iter().copied().max(),
+            rtt_avg: if rtts.is_empty() {
+                None
+            } else {
+                Some(rtts.iter().sum::<u64>() / rtts.len() as u64)
+            },
+            rtts,
+            reads: self.reads.len(),
+            bytes: self.reads.iter().sum(),
+            max_read: self.reads.iter().copied().max().unwrap_or(0),
+            mccp,
+        }
+    }
+}
+
+/// 测量结果，延迟单位为毫秒
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProbeReport {
+    pub pings: usize,
+    pub rtts: Vec<u64>,
+    pub rtt_min: Option<u64>,
+    pub rtt_max: Option<u64>,
+    pub rtt_avg: Option<u64>,
+    // 读取次数、总字节数及单次读取的最大字节数
+    pub reads: usize,
+    pub bytes: usize,
+    pub max_read: usize,
+    // 测量结束时MCCP是否已启用
+    pub mccp: bool,
+}
+
+impl ProbeReport {
+    pub fn avg_read(&self) -> usize {
+        self.bytes.checked_div(self.reads).unwrap_or(0)
+    }
+
+    /// 根据测量结果给出的建议，以i18n键表示
+    pub fn recommendations(&self) -> Vec<&'static str> {
+        let mut recs = Vec::new();
+        if self.pings > 0 && self.rtts.is_empty() {
+            recs.push("probe.rec_no_reply");
+        }
+        if self.rtt_avg.map(|avg| avg > HIGH_LATENCY_MS).unwrap_or(false) {
+            recs.push("probe.rec_timeout");
+        }
+        if let (Some(min), Some(max)) = (self.rtt_min, self.rtt_max) {
+            if max - min > HIGH_JITTER_MS {
+                recs.push("probe.rec_pacing");
+            }
+        }
+        if !self.mccp && self.avg_read() > LARGE_READ_BYTES {
+            recs.push("probe.rec_mccp");
+        }
+        if recs.is_empty() {
+            recs.push("probe.rec_ok");
+        }
+        recs
+    }
+}
+
+impl<'lua> ToLua<'lua> for ProbeReport {
+    fn to_lua(self, lua: &'lua Lua) -> mlua::Result<Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("pings", self.pings)?;
+        table.set("rtts", self.rtts.clone())?;
+        table.set("rtt_min", self.rtt_min)?;
+        table.set("rtt_max", self.rtt_max)?;
+        table.set("rtt_avg", self.rtt_avg)?;
+        table.set("reads", self.reads)?;
+        table.set("bytes", self.bytes)?;
+        table.set("max_read", self.max_read)?;
+        table.set("avg_read", self.avg_read())?;
+        table.set("mccp", self.mccp)?;
+        let recs: Vec<&str> = self.recommendations().into_iter().map(|k| k.trim_start_matches("probe.")).collect();
+        table.set("recommendations", recs)?;
+        Ok(Value::Table(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_rtt() {
+        let mut probe = Probe::new(Duration::from_secs(3));
+        let start = Instant::now();
+        assert_eq!(vec![Op::IAC, Op::DO, Opt::TM], probe.ping(start));
+        assert!(!probe.should_ping(start));
+        let data = [b'a', Op::IAC, Op::IAC, Op::IAC, Op::WILL, Opt::TM, b'b'];
+        let (rest, ponged) = probe.strip_pongs(&data, start + Duration::from_millis(100));
+        assert!(ponged);
+        assert_eq!(vec![b'a', Op::IAC, Op::IAC, b'b'], rest);
+        probe.record_read(data.len());
+        probe.ping(start);
+        probe.strip_pongs(&[Op::IAC, Op::WONT, Opt::TM], start + Duration::from_millis(500));
+        assert!(!probe.done(start));
+        assert!(probe.done(start + Duration::from_secs(3)));
+
+        let report = probe.report(false);
+        assert_eq!((Some(100), Some(500), Some(300)), (report.rtt_min, report.rtt_max, report.rtt_avg));
+        assert_eq!(vec!["probe.rec_pacing"], report.recommendations());
+
+        // 未应答的探测
+        let mut probe = Probe::new(Duration::from_secs(3));
+        probe.ping(start);
+        probe.record_read(4096);
+        assert_eq!(vec!["probe.rec_no_reply", "probe.rec_mccp"], probe.report(false).recommendations());
+        assert_eq!(vec!["probe.rec_no_reply"], probe.report(true).recommendations());
+    }
+}
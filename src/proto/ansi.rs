@@ -1,4 +1,5 @@
 use crate::ui::style::{Color, Modifier, Style};
+use std::convert::TryFrom;
 
 #[derive(Debug, Clone)]
 pub struct ClearCells(pub u16);
@@ -9,25 +10,96 @@ impl std::fmt::Display for ClearCells {
     }
 }
 
+/// 光标控制序列中可安全解释的子集，用于服务器绘制的状态栏
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorOp {
+    // ESC 7 或 CSI s
+    Save,
+    // ESC 8 或 CSI u
+    Restore,
+    // CSI n K，0清除至行尾，1清除至行首，2清除整行
+    EraseLine(u8),
+    // CSI row;col H，行列从1开始
+    Position(u16, u16),
+}
+
+impl CursorOp {
+    /// 根据CSI参数及结束字符解析，不支持的序列返回None
+    pub fn from_csi(params: &str, end: char) -> Option<Self> {
+        let mut nums = params.split(';').map(|p| {
+            if p.is_empty() {
+                Some(0)
+            } else {
+                p.parse::<u16>().ok()
+            }
+        });
+        match end {
+            's' if params.is_empty() => Some(Self::Save),
+            'u' if params.is_empty() => Some(Self::Restore),
+            'K' => match nums.next()?? {
+                n @ 0..=2 => Some(Self::EraseLine(n as u8)),
+                _ => None,
+            },
+            'H' | 'f' => {
+                let row = nums.next()??.max(1);
+                let col = nums.next().unwrap_or(Some(1))?.max(1);
+                Some(Self::Position(row, col))
+            }
+            _ => None,
+        }
+    }
+}
+
 /// 给定SGR字符串，应用相应的文本格式并返回
+///
+/// 支持256色（38;5;n）及真彩色（38;2;r;g;b），背景色同理，
+/// 空参数视为0，无法识别的参数被忽略
 pub fn apply_sgr(mut style: Style, sgr: &str) -> Style {
-    let mut n = 0;
-    for c in sgr.chars() {
-        match c {
-            ';' => {
-                style = apply_sgr_code(style, n);
-                n = 0;
-            }
-            '0'..='9' => {
-                n *= 10;
-                n += (c as u8) - b'0';
+    let mut params = sgr.split(';').map(|p| {
+        if p.is_empty() {
+            Some(0)
+        } else {
+            p.parse::<u32>().ok()
+        }
+    });
+    while let Some(param) = params.next() {
+        let code = match param {
+            Some(code) => code,
+            None => {
+                log::debug!("invalid SGR parameter in {:?}", sgr);
+                continue;
             }
-            other => {
-                unreachable!("unreachable char '{}' in sgm sequence", other)
+        };
+        match code {
+            38 | 48 => {
+                let color = match params.next().flatten() {
+                    Some(5) => params.next().flatten().and_then(to_u8).map(Color::from_index),
+                    Some(2) => {
+                        let mut rgb = params.by_ref().take(3).map(|v| v.and_then(to_u8));
+                        match (rgb.next().flatten(), rgb.next().flatten(), rgb.next().flatten()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                match color {
+                    Some(color) if code == 38 => style = style.fg(color),
+                    Some(color) => style = style.bg(color),
+                    None => log::debug!("invalid extended color in SGR {:?}", sgr),
+                }
             }
+            code => match to_u8(code) {
+                Some(code) => style = apply_sgr_code(style, code),
+                None => log::debug!("unknown SGR argument {}", code),
+            },
         }
     }
-    apply_sgr_code(style, n)
+    style
+}
+
+fn to_u8(n: u32) -> Option<u8> {
+    u8::try_from(n).ok()
 }
 
 fn apply_sgr_code(mut style: Style, code: u8) -> Style {
@@ -65,7 +137,7 @@ fn apply_sgr_code(mut style: Style, code: u8) -> Style {
         35 => style.fg(Color::Magenta),
         36 => style.fg(Color::Cyan),
         37 => style.fg(Color::Gray),
-        38 | 39 => {
+        39 => {
             style.fg = None;
             style
         }
@@ -86,7 +158,7 @@ fn apply_sgr_code(mut style: Style, code: u8) -> Style {
         45 => style.bg(Color::Magenta),
         46 => style.bg(Color::Cyan),
         47 => style.bg(Color::Gray),
-        48 | 49 => {
+        49 => {
             style.bg = None;
             style
         }
@@ -98,6 +170,9 @@ fn apply_sgr_code(mut style: Style, code: u8) -> Style {
         105 => style.bg(Color::LightMagenta),
         106 => style.bg(Color::LightCyan),
         107 => style.bg(Color::White),
-        _ => panic!("unknown SGR argument {}", code),
+        _ => {
+            log::debug!("unknown SGR argument {}", code);
+            style
+        }
     }
 }
@@ -2,9 +2,17 @@ use crate::error::{Error, Result};
 use crate::ui::line::RawLine;
 use crate::ui::style::Color;
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
-use std::io::Cursor;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use std::io::{Read, Write};
 
+/// 客户端能力：支持zlib压缩的批量文本
+pub const CAP_ZLIB: u8 = 0x01;
+
+// 压缩的批量文本的包头
+const HEADER_ZLINES: u8 = 0x05;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Packet {
     Ok,
@@ -50,7 +58,12 @@ impl Packet {
             0x02 => Self::AuthResp(bs),
             0x03 => Self::Text(String::from_utf8(bs)?),
             0x04 => {
-                let lines = decode_lines(&bs)?;
+                let lines = decode_lines(&bs[..])?;
+                Self::Lines(lines)
+            }
+            // 边解压边解析，无需额外的解压缓冲
+            HEADER_ZLINES => {
+                let lines = decode_lines(ZlibDecoder::new(&bs[..]))?;
                 Self::Lines(lines)
             }
             0xff => Self::Err(String::from_utf8(bs)?),
@@ -64,25 +77,43 @@ impl Packet {
         Ok(pkt)
     }
 
-    pub fn write_to<W: Write>(self, mut writer: W) -> Result<()> {
+    pub fn write_to<W: Write>(self, writer: W) -> Result<()> {
         let header = self.header();
         let mut bs = self.payload();
         bs.push(header);
-        let mut bs = &bs[..];
-        // let mut bs = &self.0[..];
-        while bs.len() >= 0xff_ffff {
-            let (left, right) = bs.split_at(0xff_ffff);
-            write_packet(&mut writer, left)?;
-            bs = right;
-        }
-        write_packet(&mut writer, bs)?;
-        Ok(())
+        write_packets(writer, &bs)
+    }
+
+    /// 压缩发送批量文本，用于客户端连接后的初始同步，其他类型的包照常发送
+    ///
+    /// 接收方解析后仍为Lines
+    pub fn write_compressed<W: Write>(self, writer: W) -> Result<()> {
+        let lines = match self {
+            Self::Lines(lines) => lines,
+            other => return other.write_to(writer),
+        };
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encode_lines_to(&mut encoder, lines)?;
+        let mut bs = encoder.finish()?;
+        bs.push(HEADER_ZLINES);
+        write_packets(writer, &bs)
+    }
+}
+
+// 超过单包长度时拆分为多个包
+fn write_packets<W: Write>(mut writer: W, bs: &[u8]) -> Result<()> {
+    let mut bs = bs;
+    while bs.len() >= 0xff_ffff {
+        let (left, right) = bs.split_at(0xff_ffff);
+        write_packet(&mut writer, left)?;
+        bs = right;
     }
+    write_packet(&mut writer, bs)?;
+    Ok(())
 }
 
 /// payload of raw lines
-fn decode_lines(src: &[u8]) -> Result<Vec<RawLine>> {
-    let mut cursor = Cursor::new(src);
+fn decode_lines<R: Read>(mut cursor: R) -> Result<Vec<RawLine>> {
     let n_lines = cursor.read_u32::<LE>()?;
     let mut lines = Vec::with_capacity(n_lines as usize);
     for _ in 0..n_lines {
@@ -96,6 +127,11 @@ fn decode_lines(src: &[u8]) -> Result<Vec<RawLine>> {
 
 fn encode_lines(lines: Vec<RawLine>) -> Result<Vec<u8>> {
     let mut bs = Vec::new();
+    encode_lines_to(&mut bs, lines)?;
+    Ok(bs)
+}
+
+fn encode_lines_to<W: Write>(mut bs: W, lines: Vec<RawLine>) -> Result<()> {
     let n_lines = lines.len() as u32;
     bs.write_u32::<LE>(n_lines)?;
     for line in lines {
@@ -103,7 +139,7 @@ fn encode_lines(lines: Vec<RawLine>) -> Result<Vec<u8>> {
         bs.write_u32::<LE>(len)?;
         bs.write_all(line.as_ref().as_bytes())?;
     }
-    Ok(bs)
+    Ok(())
 }
 
 #[allow(dead_code)]
@@ -152,6 +188,8 @@ fn color_to_num(color: Option<Color>) -> u8 {
         Some(Color::LightMagenta) => 95,
         Some(Color::LightCyan) => 96,
         Some(Color::White) => 97,
+        // 扩展颜色以最接近的基础色表示
+        Some(color) => color_to_num(Some(color.to_ansi16())),
     }
 }
 
@@ -223,6 +261,22 @@ mod tests {
         assert_eq!(pkt, decoded);
     }
 
+    #[test]
+    fn test_read_and_write_compressed_lines() {
+        let lines: Vec<RawLine> = (0..1000)
+            .map(|i| RawLine::new(format!("张三对你说道：第{}行\r\n", i)))
+            .collect();
+        let mut plain = vec![];
+        Packet::Lines(lines.clone()).write_to(&mut plain).unwrap();
+        let mut buf = vec![];
+        Packet::Lines(lines.clone())
+            .write_compressed(&mut buf)
+            .unwrap();
+        assert!(buf.len() * 4 < plain.len());
+        let decoded = Packet::read_from(&buf[..]).unwrap();
+        assert_eq!(Packet::Lines(lines), decoded);
+    }
+
     #[test]
     fn test_read_and_write_lines() {
         let pkt = Packet::Lines(vec![RawLine::fmt_err("err"), RawLine::fmt_note("notes")]);
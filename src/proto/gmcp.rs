@@ -0,0 +1,73 @@
+use crate::error::{Error, Result};
+use serde_json::Value as Json;
+
+/// GMCP消息，由包名及JSON数据组成，如Char.Vitals {"hp":100}
+#[derive(Debug, Clone, PartialEq)]
+pub struct GmcpMessage {
+    pub package: String,
+    // 无数据时为Null
+    pub data: Json,
+}
+
+impl GmcpMessage {
+    /// 解析子协商中的消息内容
+    pub fn parse(bs: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(bs)
+            .map_err(|e| Error::DecodeError(format!("gmcp message not utf-8: {}", e)))?;
+        let text = text.trim();
+        let (package, data) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if package.is_empty() {
+            return Err(Error::ParseError("empty gmcp package".to_owned()));
+        }
+        let data = if data.trim().is_empty() {
+            Json::Null
+        } else {
+            serde_json::from_str(data)?
+        };
+        Ok(Self {
+            package: package.to_owned(),
+            data,
+        })
+    }
+
+    /// 包名是否匹配注册的名称，忽略大小写，匹配名称本身及其下的子包
+    ///
+    /// 如Char匹配Char、Char.Vitals，但不匹配Character
+    pub fn matches(&self, name: &str) -> bool {
+        let package = self.package.as_bytes();
+        let name = name.as_bytes();
+        package.len() >= name.len()
+            && package[..name.len()].eq_ignore_ascii_case(name)
+            && (package.len() == name.len() || package[name.len()] == b'.')
+    }
+}
+
+/// 生成客户端发送的消息内容，data为JSON文本
+pub fn encode(package: &str, data: Option<&str>) -> String {
+    match data {
+        Some(data) => format!("{} {}", package, data),
+        None => package.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gmcp_parse() {
+        let msg = GmcpMessage::parse(r#"Char.Vitals {"hp": 100, "name": "张三"}"#.as_bytes()).unwrap();
+        assert_eq!("Char.Vitals", msg.package);
+        assert_eq!(100, msg.data["hp"]);
+        assert_eq!("张三", msg.data["name"]);
+        assert!(msg.matches("char"));
+        assert!(msg.matches("Char.Vitals"));
+        assert!(!msg.matches("Char.Vital"));
+        assert!(!msg.matches("Room"));
+
+        let msg = GmcpMessage::parse(b"Core.Goodbye").unwrap();
+        assert_eq!(Json::Null, msg.data);
+        assert!(GmcpMessage::parse(b"Room.Info {bad").is_err());
+        assert_eq!(r#"Core.Supports.Set ["Char 1"]"#, encode("Core.Supports.Set", Some(r#"["Char 1"]"#)));
+    }
+}
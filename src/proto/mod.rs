@@ -1,11 +1,13 @@
 pub mod ansi;
 pub mod mxp;
 pub mod cli;
+pub mod gmcp;
+pub mod msdp;
 
 use crate::ui::span::Span;
 use crate::ui::style::{Style, Modifier};
-use ansi::apply_sgr;
-use mxp::{Tokenizer, Token, Tokenization, Mode};
+use ansi::{apply_sgr, CursorOp};
+use mxp::{Tokenizer, Token, Tokenization, Mode, ModeState};
 use mlua::{Lua, ToLua, Value};
 
 /// 精简后的MXP标签，主要用于MXP触发器
@@ -160,7 +162,7 @@ impl InlineElements {
                 // label不同，无法合并
                 self.arr.push(Element::Span(span));
             }
-            Element::Span(_) | Element::None => {
+            Element::Span(_) | Element::Cursor(_) | Element::None => {
                 self.cont = false;
             }
         }
@@ -179,6 +181,8 @@ pub enum Element {
     MxpVersion,
     MxpMode(Mode),
     MxpImg(String),
+    // 光标控制，仅在状态栏模式下解释
+    Cursor(CursorOp),
 }
 
 impl<'lua> ToLua<'lua> for &Element {
@@ -193,15 +197,15 @@ impl<'lua> ToLua<'lua> for &Element {
                 stb.set("label", ToLua::to_lua(&span.label, lua)?)?;
                 table.set("span", stb)?;
             }
-            Element::MxpMode(Mode::Open) => {
-                table.set("mode", "open")?;
-            }
-            Element::MxpMode(Mode::Secure) => {
-                table.set("mode", "secure")?;
+            Element::MxpMode(mode) => {
+                table.set("mode", mode.as_str())?;
             }
             Element::MxpImg(src) => {
                 table.set("src", &src[..])?;
             }
+            Element::Cursor(op) => {
+                table.set("op", format!("{:?}", op))?;
+            }
             Element::MxpSupport | Element::MxpVersion | Element::None => (),
         }
         Ok(Value::Table(table))
@@ -224,6 +228,7 @@ impl Element {
             Element::MxpVersion => "version",
             Element::MxpMode(_) => "mode",
             Element::MxpImg(_) => "img",
+            Element::Cursor(_) => "cursor",
         }
     }
 
@@ -265,6 +270,11 @@ impl Parser {
         self.tokenizer.fill(input);
     }
 
+    /// 当前MXP模式
+    pub fn mxp_mode(&self) -> ModeState {
+        self.tokenizer.mode()
+    }
+
     /// 获取下一个元素
     ///
     /// 1. 首先驱动MXP Parser对缓存的输入进行解析。
@@ -462,12 +472,25 @@ impl Parser {
                         }
                         Token::MxpMode(mode) => {
                             let elem = self.output(false);
+                            if mode == Mode::Reset {
+                                // 关闭所有已打开的标签
+                                self.ls.reset();
+                                self.style = Style::default();
+                            }
                             if elem.is_span() {
                                 self.immediate = Some(Element::MxpMode(mode));
                                 return elem;
                             }
                             return Element::MxpMode(mode);
                         }
+                        Token::Cursor(op) => {
+                            let elem = self.output(false);
+                            if elem.is_span() {
+                                self.immediate = Some(Element::Cursor(op));
+                                return elem;
+                            }
+                            return Element::Cursor(op);
+                        }
                         Token::Img(url) => {
                             let elem = self.output(false);
                             if elem.is_span() {
@@ -588,6 +611,17 @@ mod tests {
         assert_eq!(text(">"), parser.next());
     }
 
+    #[test]
+    fn test_parser_extended_colors() {
+        let mut parser = Parser::default();
+        parser.fill("\x1b[38;5;208m橙\x1b[48;2;0;0;128;38;5;3m蓝\x1b[0;73;38;9m无\r\n");
+        assert_eq!(styled_text("橙", Style::default().fg(Color::Indexed(208))), parser.next());
+        let style = Style::default().fg(Color::Yellow).bg(Color::Rgb(0, 0, 128));
+        assert_eq!(styled_text("蓝", style), parser.next());
+        // 无法识别的参数被忽略
+        assert_eq!(text("无\r\n"), parser.next());
+    }
+
     #[test]
     fn test_parser_sgr_in_header() {
         let mut parser = Parser::default();
@@ -0,0 +1,147 @@
+use crate::error::{Error, Result};
+use serde_json::{Map, Value as Json};
+
+/// MSDP的telnet选项
+pub const OPT_MSDP: u8 = 69;
+
+const VAR: u8 = 1;
+const VAL: u8 = 2;
+const TABLE_OPEN: u8 = 3;
+const TABLE_CLOSE: u8 = 4;
+const ARRAY_OPEN: u8 = 5;
+const ARRAY_CLOSE: u8 = 6;
+// 表及数组的最大嵌套深度，避免恶意数据导致栈溢出
+const MAX_DEPTH: usize = 32;
+
+/// 解析子协商中的变量，如VAR "HEALTH" VAL "100"
+///
+/// 表及数组分别解析为JSON对象及数组，其余值均为字符串，
+/// 同一变量后跟多个VAL时视为数组
+pub fn parse(bs: &[u8]) -> Result<Vec<(String, Json)>> {
+    let mut reader = Reader { bs, pos: 0 };
+    let mut vars = Vec::new();
+    while reader.pos < bs.len() {
+        reader.expect(VAR)?;
+        let name = reader.read_str();
+        reader.expect(VAL)?;
+        let mut value = reader.read_value(0)?;
+        while reader.peek() == Some(VAL) {
+            reader.pos += 1;
+            let next = reader.read_value(0)?;
+            match value {
+                Json::Array(ref mut arr) => arr.push(next),
+                _ => value = Json::Array(vec![value, next]),
+            }
+        }
+        vars.push((name, value));
+    }
+    Ok(vars)
+}
+
+/// 生成客户端发送的命令，如REPORT HEALTH ROOM
+pub fn encode(cmd: &str, args: &[String]) -> Vec<u8> {
+    let mut bs = vec![VAR];
+    bs.extend_from_slice(cmd.as_bytes());
+    for arg in args {
+        bs.push(VAL);
+        bs.extend_from_slice(arg.as_bytes());
+    }
+    bs
+}
+
+struct Reader<'a> {
+    bs: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bs.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, marker: u8) -> Result<()> {
+        if self.peek() != Some(marker) {
+            return Err(Error::ParseError(format!(
+                "msdp marker {} expected at {}, found {:?}",
+                marker,
+                self.pos,
+                self.peek()
+            )));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // 读取至下一个标记
+    fn read_str(&mut self) -> String {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if (VAR..=ARRAY_CLOSE).contains(&b) {
+                break;
+            }
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.bs[start..self.pos]).into_owned()
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Json> {
+        if depth >= MAX_DEPTH && matches!(self.peek(), Some(TABLE_OPEN) | Some(ARRAY_OPEN)) {
+            return Err(Error::ParseError(format!(
+                "msdp nesting exceeds max depth {} at {}",
+                MAX_DEPTH, self.pos
+            )));
+        }
+        match self.peek() {
+            Some(TABLE_OPEN) => {
+                self.pos += 1;
+                let mut table = Map::new();
+                while self.peek() != Some(TABLE_CLOSE) {
+                    self.expect(VAR)?;
+                    let key = self.read_str();
+                    self.expect(VAL)?;
+                    table.insert(key, self.read_value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Json::Object(table))
+            }
+            Some(ARRAY_OPEN) => {
+                self.pos += 1;
+                let mut arr = Vec::new();
+                while self.peek() != Some(ARRAY_CLOSE) {
+                    self.expect(VAL)?;
+                    arr.push(self.read_value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Json::Array(arr))
+            }
+            _ => Ok(Json::String(self.read_str())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msdp_parse() {
+        let mut bs = encode("HEALTH", &["100".to_owned()]);
+        bs.extend_from_slice(b"\x01ROOM\x02\x03\x01VNUM\x0236\x01EXITS\x02\x03\x01n\x026\x01s\x027\x04\x04");
+        bs.extend_from_slice(b"\x01LIST\x02\x05\x02a\x02b\x06\x01PAIR\x02x\x02y");
+        let vars = parse(&bs).unwrap();
+        assert_eq!(("HEALTH".to_owned(), Json::from("100")), vars[0]);
+        assert_eq!("ROOM", vars[1].0);
+        assert_eq!("36", vars[1].1["VNUM"]);
+        assert_eq!("7", vars[1].1["EXITS"]["s"]);
+        assert_eq!(Json::from(vec!["a", "b"]), vars[2].1);
+        assert_eq!(Json::from(vec!["x", "y"]), vars[3].1);
+
+        assert!(parse(b"HEALTH\x02100").is_err());
+        // 未闭合的表
+        assert!(parse(b"\x01ROOM\x02\x03\x01VNUM\x0236").is_err());
+        // 嵌套过深
+        let mut bs = b"\x01DEEP\x02".to_vec();
+        bs.extend(std::iter::repeat(b"\x05\x02").take(100_000).flatten());
+        assert!(parse(&bs).is_err());
+    }
+}
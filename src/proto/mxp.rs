@@ -2,23 +2,113 @@
 //!
 //! A good introduction to how to implement it in MUSHClient:
 //! http://www.gammon.com.au/forum/bbshowpost.php?bbsubject_id=222
+use crate::proto::ansi::CursorOp;
 use crate::ui::style::Color;
 
 pub fn supports() -> &'static str {
     "+head +body +afk +title +username +pass +samp +h +high +i +option +bold +xch_page +reset +strong +recommend_option +support +ul +em +send +send.href +send.hint +send.xch_cmd +send.xch_hint +send.prompt +p +hr +html +user +password +a +a.href +a.xch_cmd +a.xch_hint +underline +b +img +img.src +img.xch_mode +pre +li +ol +c +c.fore +c.back +font +font.color +font.back +font.fgcolor +font.bgcolor +u +mxp +mxp.off +version +br +v +var +italic"
 }
 
-/// 目前支持MXP两种模式Open和Secure
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// MXP模式，由服务器通过ESC [ n z 切换
+///
+/// 0-2仅对当前行生效，换行后恢复为默认模式；
+/// 5-7修改默认模式；3重置为Open并关闭所有标签；
+/// 4仅使下一个标签以Secure模式解析
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Mode {
+    #[default]
     Open,
     Secure,
-    // Locked,
-    // Reset,
-    // TempSecur,
-    // LockOpen,
-    // LockSecure,
-    // LockLocked,
+    Locked,
+    Reset,
+    TempSecure,
+    LockOpen,
+    LockSecure,
+    LockLocked,
+}
+
+impl Mode {
+    pub fn from_code(n: u8) -> Option<Self> {
+        let md = match n {
+            0 => Mode::Open,
+            1 => Mode::Secure,
+            2 => Mode::Locked,
+            3 => Mode::Reset,
+            4 => Mode::TempSecure,
+            5 => Mode::LockOpen,
+            6 => Mode::LockSecure,
+            7 => Mode::LockLocked,
+            _ => return None,
+        };
+        Some(md)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Open => "open",
+            Mode::Secure => "secure",
+            Mode::Locked => "locked",
+            Mode::Reset => "reset",
+            Mode::TempSecure => "temp_secure",
+            Mode::LockOpen => "lock_open",
+            Mode::LockSecure => "lock_secure",
+            Mode::LockLocked => "lock_locked",
+        }
+    }
+}
+
+/// 当前生效的MXP模式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ModeState {
+    // 当前行的模式，只可能为Open、Secure或Locked
+    pub line: Mode,
+    // 默认模式，换行后恢复为该模式
+    pub default: Mode,
+    // 下一个标签以Secure模式解析
+    pub temp_secure: bool,
+}
+
+impl ModeState {
+    /// 切换模式
+    pub fn apply(&mut self, mode: Mode) {
+        match mode {
+            Mode::Open | Mode::Secure | Mode::Locked => self.line = mode,
+            Mode::Reset => {
+                self.line = Mode::Open;
+                self.default = Mode::Open;
+                self.temp_secure = false;
+            }
+            Mode::TempSecure => self.temp_secure = true,
+            Mode::LockOpen => {
+                self.line = Mode::Open;
+                self.default = Mode::Open;
+            }
+            Mode::LockSecure => {
+                self.line = Mode::Secure;
+                self.default = Mode::Secure;
+            }
+            Mode::LockLocked => {
+                self.line = Mode::Locked;
+                self.default = Mode::Locked;
+            }
+        }
+    }
+
+    /// 换行后恢复默认模式
+    pub fn end_line(&mut self) {
+        self.line = self.default;
+        self.temp_secure = false;
+    }
+
+    /// 是否解析标签及转义字符
+    pub fn parse_tags(&self, strict: bool) -> bool {
+        match self.line {
+            Mode::Secure => true,
+            _ if self.temp_secure => true,
+            Mode::Locked => false,
+            _ => strict,
+        }
+    }
 }
 
 /// 定义MXP Tags
@@ -96,6 +186,8 @@ pub enum Token {
     SGR(String),
     // MXP模式转换
     MxpMode(Mode),
+    // 光标控制
+    Cursor(CursorOp),
     // amper转移字符
     AmperChar(char),
     // 文本
@@ -407,7 +499,7 @@ impl ParserState {
 
 #[derive(Debug)]
 pub struct Tokenizer {
-    mode: Mode,
+    mode: ModeState,
     state: ParserState,
     buf: String,
     token: Option<Token>,
@@ -425,7 +517,7 @@ pub struct Tokenizer {
 impl Default for Tokenizer {
     fn default() -> Self {
         Self{
-            mode: Mode::Open,
+            mode: ModeState::default(),
             state: ParserState::Normal(0),
             buf: String::new(),
             token: None,
@@ -446,6 +538,11 @@ impl Tokenizer {
         }
     }
 
+    // 当前MXP模式
+    pub fn mode(&self) -> ModeState {
+        self.mode
+    }
+
     // 填充字符串
     pub fn fill(&mut self, input: &str) {
         self.buf.push_str(input);
@@ -464,7 +561,9 @@ impl Tokenizer {
                 ParserState::Normal(offset) => {
                     match c {
                         // 只在严格模式或者MXP安全模式下，才进行标签解析
-                        '<' if *strict || *mode == Mode::Secure => {
+                        '<' if mode.parse_tags(*strict) => {
+                            // 临时安全模式仅对一个标签生效
+                            mode.temp_secure = false;
                             if *offset > idx {
                                 let text = Self::unify_text(buf, idx, *offset);
                                 self.state = ParserState::StartTagOpen(*offset+1);
@@ -481,7 +580,7 @@ impl Tokenizer {
                             *state = ParserState::Esc(*offset+1);
                         }
                         // 只在严格模式或者MXP安全模式下，才进行html转义解析
-                        '&' if *strict || *mode == Mode::Secure => {
+                        '&' if mode.parse_tags(*strict) => {
                             if *offset > idx {
                                 let text = Self::unify_text(buf, idx, *offset);
                                 *state = ParserState::Amper{
@@ -502,11 +601,8 @@ impl Tokenizer {
                                 text.push('\r');
                             }
                             text.push('\n');
-                            // 根据MXP协议，换行切换为Open模式
-                            // *mode = Mode::Open;
-                            // *state = ParserState::Normal(*offset+1);
-                            // buf.clear();
-                            // *state = ParserState::Normal(0);
+                            // 根据MXP协议，换行后恢复默认模式
+                            mode.end_line();
                             self.reset();
                             return Tokenization::Ok(Token::LineEndedText(text));
                         }
@@ -767,6 +863,11 @@ impl Tokenizer {
                 ParserState::Esc(offset) => {
                     match c {
                         '[' => *state = ParserState::EscBracket(*offset+1),
+                        '7' | '8' => {
+                            let op = if c == '7' { CursorOp::Save } else { CursorOp::Restore };
+                            *state = ParserState::Normal(*offset+1);
+                            return Tokenization::Ok(Token::Cursor(op));
+                        }
                         _ if *strict => return self.invalidate(idx),
                         _ => *state = ParserState::Normal(*offset+c.len_utf8()),
                     }
@@ -778,6 +879,11 @@ impl Tokenizer {
                             *state = ParserState::Normal(*offset+1);
                             return Tokenization::Ok(Token::SGR(String::new()));
                         }
+                        's' | 'u' | 'K' | 'H' | 'f' => {
+                            let op = CursorOp::from_csi("", c).unwrap();
+                            *state = ParserState::Normal(*offset+1);
+                            return Tokenization::Ok(Token::Cursor(op));
+                        }
                         _ if *strict => return self.invalidate(idx),
                         _ => *state = ParserState::Normal(*offset+c.len_utf8()),
                     }
@@ -790,23 +896,32 @@ impl Tokenizer {
                             *state = ParserState::Normal(*end+1);
                             return Tokenization::Ok(tk);
                         }
+                        'K' | 'H' | 'f' => {
+                            match CursorOp::from_csi(&buf[*start..*end], c) {
+                                Some(op) => {
+                                    *state = ParserState::Normal(*end+1);
+                                    return Tokenization::Ok(Token::Cursor(op));
+                                }
+                                None if *strict => return self.invalidate(idx),
+                                None => *state = ParserState::Normal(*end+c.len_utf8()),
+                            }
+                        }
                         'z' => {
                             match buf[*start..*end].parse::<u8>() {
                                 Ok(n) => {
-                                    let md = match n {
-                                        0 => Mode::Open,
-                                        1 => Mode::Secure,
-                                        _ if *strict => {
+                                    let md = match Mode::from_code(n) {
+                                        Some(md) => md,
+                                        None if *strict => {
                                             log::warn!("unhandled mxp mode change: {}", n);
                                             return self.invalidate(idx);
                                         }
-                                        _ => {
+                                        None => {
                                             log::warn!("unhandled mxp mode change: {}", n);
                                             *state = ParserState::Normal(*end+1);
                                             continue;
                                         }
                                     };
-                                    *mode = md;
+                                    mode.apply(md);
                                     *state = ParserState::Normal(*end+1);
                                     return Tokenization::Ok(Token::MxpMode(md));
                                 }
@@ -871,8 +986,7 @@ impl Tokenizer {
     }
 
     fn reset(&mut self) {
-        let Self{mode, state, buf, token, attr_name, n_applies, ..} = self;
-        *mode = Mode::Open;
+        let Self{state, buf, token, attr_name, n_applies, ..} = self;
         *state = ParserState::Normal(0);
         buf.clear();
         Self::clear_token(token, attr_name, n_applies);
@@ -1067,6 +1181,44 @@ mod tests {
         assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::Secure)), parser.next());
     }
 
+    #[test]
+    fn test_mxp_mode_line_reversion() {
+        let mut parser = Tokenizer::default();
+        // 行模式在换行后恢复为默认的Open模式
+        parser.fill("\x1b[1z<B>\r\n<B>\r\n");
+        assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::Secure)), parser.next());
+        assert_eq!(Tokenization::Ok(Token::Bold(true)), parser.next());
+        assert_eq!(Tokenization::Ok(Token::LineEndedText("\r\n".to_owned())), parser.next());
+        assert_eq!(Mode::Open, parser.mode().line);
+        parser.fill("<B>\r\n");
+        assert_eq!(Tokenization::Ok(Token::LineEndedText("<B>\r\n".to_owned())), parser.next());
+        // 锁定为Secure后，换行仍保持Secure
+        parser.fill("\x1b[6z\r\n<B>");
+        assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::LockSecure)), parser.next());
+        assert_eq!(Tokenization::Ok(Token::LineEndedText("\r\n".to_owned())), parser.next());
+        parser.fill("<B>");
+        assert_eq!(Tokenization::Ok(Token::Bold(true)), parser.next());
+        // Locked仅对当前行生效
+        parser.fill("\x1b[2z<B>\r\n");
+        assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::Locked)), parser.next());
+        assert_eq!(Tokenization::Ok(Token::LineEndedText("<B>\r\n".to_owned())), parser.next());
+        assert_eq!(Mode::Secure, parser.mode().line);
+        // 重置后恢复Open
+        parser.fill("\x1b[3z");
+        assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::Reset)), parser.next());
+        assert_eq!(ModeState::default(), parser.mode());
+    }
+
+    #[test]
+    fn test_mxp_mode_temp_secure() {
+        let mut parser = Tokenizer::default();
+        parser.fill("\x1b[4z<B>x<B>");
+        assert_eq!(Tokenization::Ok(Token::MxpMode(Mode::TempSecure)), parser.next());
+        assert_eq!(Tokenization::Ok(Token::Bold(true)), parser.next());
+        // 仅第一个标签按Secure解析
+        assert_eq!(Tokenization::Ok(Token::Text("x<B>".to_owned())), parser.next());
+    }
+
     #[test]
     fn test_strict_mxp_sgr() {
         let input = "\x1b[m\x1b[1;37;44m";
@@ -0,0 +1,257 @@
+use crate::error::{Error, Result};
+use crate::event::Event;
+use crypto::ed25519;
+use serde::Deserialize;
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+/// 脚本包清单
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    // 声明使用的能力，如send、timer、map，仅用于安装前提示
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    // 安装后加载的入口脚本
+    pub entry: String,
+}
+
+/// 脚本包
+///
+/// 为单个JSON文件：{"manifest": {...}, "files": {"路径": "内容"}, "signature": "..."}，
+/// 签名为作者对去除signature字段后的JSON（键按字典序，无空白）的ed25519签名，
+/// 以十六进制表示
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub files: BTreeMap<String, String>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut json: Json = serde_json::from_slice(bytes)?;
+        let obj = json
+            .as_object_mut()
+            .ok_or_else(|| Error::ParseError("bundle must be a json object".to_owned()))?;
+        let signature = match obj.remove("signature") {
+            Some(Json::String(s)) => decode_hex(&s)?,
+            _ => return Err(Error::ParseError("bundle signature missing".to_owned())),
+        };
+        let manifest: Manifest = serde_json::from_value(
+            obj.get("manifest")
+                .cloned()
+                .ok_or_else(|| Error::ParseError("bundle manifest missing".to_owned()))?,
+        )?;
+        let files: BTreeMap<String, String> =
+            serde_json::from_value(obj.get("files").cloned().unwrap_or_default())?;
+        validate_name(&manifest.name)?;
+        for path in files.keys() {
+            validate_path(path)?;
+        }
+        if !files.contains_key(&manifest.entry) {
+            return Err(Error::ParseError(format!(
+                "bundle entry {} not found",
+                manifest.entry
+            )));
+        }
+        // serde_json的对象按键排序，序列化结果确定
+        let payload = serde_json::to_vec(&json)?;
+        Ok(Self {
+            manifest,
+            files,
+            payload,
+            signature,
+        })
+    }
+
+    /// 使用受信任的公钥校验签名，返回签名者
+    pub fn verify<'a>(&self, keys: &'a TrustedKeys) -> Option<&'a str> {
+        if self.signature.len() != 64 {
+            return None;
+        }
+        keys.0
+            .iter()
+            .find(|(_, key)| ed25519::verify(&self.payload, key, &self.signature))
+            .map(|(name, _)| &name[..])
+    }
+
+    /// 将文件写入<dir>/<name>，返回入口脚本路径
+    pub fn install(&self, dir: &Path) -> Result<PathBuf> {
+        let root = dir.join(&self.manifest.name);
+        for (path, content) in &self.files {
+            let target = root.join(path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, content)?;
+        }
+        Ok(root.join(&self.manifest.entry))
+    }
+}
+
+/// 受信任的作者公钥
+///
+/// 文件每行为“名称 十六进制公钥”，空行及#开头的行忽略
+#[derive(Debug, Clone, Default)]
+pub struct TrustedKeys(Vec<(String, [u8; 32])>);
+
+impl TrustedKeys {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (name, hex) = match (parts.next(), parts.next()) {
+                (Some(name), Some(hex)) => (name, hex),
+                _ => return Err(Error::ParseError(format!("invalid trusted key line {}", line))),
+            };
+            let bytes = decode_hex(hex)?;
+            if bytes.len() != 32 {
+                return Err(Error::ParseError(format!("invalid public key of {}", name)));
+            }
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            keys.push((name.to_owned(), key));
+        }
+        Ok(Self(keys))
+    }
+
+    /// 从文件加载，文件不存在时返回空列表
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// 通过HTTPS下载，调用系统curl，限制超时与大小
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    if !url.starts_with("https://") {
+        return Err(Error::RuntimeError(format!("only https url is allowed: {}", url)));
+    }
+    let output = Command::new("curl")
+        .args(["-fsSL", "--proto", "=https", "--max-time", "30"])
+        .args(["--max-filesize", "4194304"])
+        .arg(url)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::RuntimeError(format!(
+            "download {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// 在后台线程中下载，完成后将结果事件交给回调发送至事件循环
+pub fn spawn_fetch<F>(url: String, done: F)
+where
+    F: FnOnce(Event) + Send + 'static,
+{
+    thread::spawn(move || {
+        let res = fetch(&url).map_err(|e| e.to_string());
+        done(Event::BundleFetched(url, res));
+    });
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(Error::ParseError(format!("invalid bundle name {}", name)));
+    }
+    Ok(())
+}
+
+// 仅允许相对路径，禁止..
+fn validate_path(path: &str) -> Result<()> {
+    let valid = !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !valid {
+        return Err(Error::ParseError(format!("invalid file path {} in bundle", path)));
+    }
+    Ok(())
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(Error::DecodeError(format!("invalid hex string {}", s)));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| Error::DecodeError(format!("invalid hex string {}", s)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+
+    #[test]
+    fn test_bundle_verify_install() {
+        let (secret, public) = ed25519::keypair(&[7u8; 32]);
+        let keys = TrustedKeys::parse(&format!("# 作者\nalice {}\n", encode_hex(&public))).unwrap();
+        let mut body = json!({
+            "manifest": {"name": "hello", "version": "1.0", "capabilities": ["send"], "entry": "init.lua"},
+            "files": {"init.lua": "Send('hi')", "lib/util.lua": "return {}"},
+        });
+        let sig = ed25519::signature(&serde_json::to_vec(&body).unwrap(), &secret);
+        body["signature"] = json!(encode_hex(&sig));
+        let bundle = Bundle::parse(&serde_json::to_vec_pretty(&body).unwrap()).unwrap();
+        assert_eq!(Some("alice"), bundle.verify(&keys));
+        assert_eq!(vec!["send"], bundle.manifest.capabilities);
+
+        let dir = TempDir::new("bundle");
+        let entry = bundle.install(dir.path()).unwrap();
+        assert_eq!(dir.join("hello/init.lua"), entry);
+        assert_eq!("return {}", fs::read_to_string(dir.join("hello/lib/util.lua")).unwrap());
+
+        // 内容被篡改后签名失效
+        body["files"]["init.lua"] = json!("Send('kill')");
+        let bundle = Bundle::parse(&serde_json::to_vec(&body).unwrap()).unwrap();
+        assert_eq!(None, bundle.verify(&keys));
+    }
+
+    #[test]
+    fn test_bundle_reject_path() {
+        let body = json!({
+            "manifest": {"name": "evil", "version": "1.0", "entry": "../init.lua"},
+            "files": {"../init.lua": ""},
+            "signature": "00",
+        });
+        assert!(Bundle::parse(&serde_json::to_vec(&body).unwrap()).is_err());
+    }
+
+    fn encode_hex(bs: &[u8]) -> String {
+        bs.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
@@ -1,3 +1,4 @@
+use crate::runtime::model::NumberOrString;
 use crate::ui::line::Line;
 use crate::ui::style::Style;
 use std::collections::VecDeque;
@@ -37,7 +38,8 @@ impl CacheText {
         ct
     }
 
-    fn ended(&self) -> bool {
+    /// 最后一行是否已结束
+    pub fn ended(&self) -> bool {
         self.text.is_empty() || self.text.ends_with('\n')
     }
 
@@ -78,6 +80,16 @@ impl CacheText {
         }
     }
 
+    /// 丢弃尚未结束的行，用于回车覆盖当前行
+    pub fn discard_open(&mut self) {
+        if self.ended() {
+            return;
+        }
+        if let Some(meta) = self.meta.pop_back() {
+            self.text.truncate(self.text.len() - meta.len);
+        }
+    }
+
     // 获取最后N行文本
     pub fn lastn(&self, n: usize) -> Option<&str> {
         if n == 0 || n > self.min_lines {
@@ -100,6 +112,26 @@ impl CacheText {
         })
     }
 
+    /// 触发器匹配的多行窗口，即最后min_lines行，每行为去除换行符的文本及样式
+    pub fn window(&self) -> Vec<(&str, &[InlineStyle])> {
+        let n = self.min_lines.min(self.meta.len());
+        let nlen: usize = self.meta.iter().rev().take(n).map(|ld| ld.len).sum();
+        let mut offset = self.text.len() - nlen;
+        let mut lines = Vec::with_capacity(n);
+        for meta in self.meta.iter().skip(self.meta.len() - n) {
+            let line = &self.text[offset..offset + meta.len];
+            offset += meta.len;
+            let line = line
+                .strip_suffix("\r\n")
+                .or_else(|| line.strip_suffix('\n'))
+                .unwrap_or(line);
+            // 去除换行符上的样式
+            let end = meta.styles.iter().take_while(|is| is.offset < line.len()).count();
+            lines.push((line, &meta.styles[..end]));
+        }
+        lines
+    }
+
     // 获取最后一行文本
     pub fn last(&self) -> Option<(&str, &[InlineStyle])> {
         match self.meta.back() {
@@ -174,6 +206,56 @@ impl<'lua> mlua::ToLua<'lua> for InlineStyle {
     }
 }
 
+/// 区域[start, end)内覆盖字节数最多的样式，空区域取起始处的样式
+pub fn dominant_style(styles: &[InlineStyle], start: usize, end: usize) -> Option<InlineStyle> {
+    let mut counts: Vec<(Style, usize)> = Vec::new();
+    for (i, is) in styles.iter().enumerate() {
+        let span_end = styles.get(i + 1).map(|next| next.offset).unwrap_or(usize::MAX);
+        let overlap = if start == end {
+            (is.offset <= start && start < span_end) as usize
+        } else {
+            span_end.min(end).saturating_sub(is.offset.max(start))
+        };
+        if overlap == 0 {
+            continue;
+        }
+        match counts.iter_mut().find(|(style, _)| *style == is.style) {
+            Some((_, n)) => *n += overlap,
+            None => counts.push((is.style, overlap)),
+        }
+    }
+    // 数量相同时取靠前的样式
+    let mut dominant: Option<(Style, usize)> = None;
+    for (style, n) in counts {
+        if dominant.map(|(_, max)| n > max).unwrap_or(true) {
+            dominant = Some((style, n));
+        }
+    }
+    dominant.map(|(style, _)| InlineStyle { offset: start, style })
+}
+
+/// 传给触发器回调的行样式：按序排列的片段样式，及captures字段中各捕获区域的主要样式
+#[derive(Debug, Clone, Default)]
+pub struct LineStyles {
+    pub spans: Vec<InlineStyle>,
+    pub captures: Vec<(NumberOrString, InlineStyle)>,
+}
+
+impl<'lua> mlua::ToLua<'lua> for LineStyles {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_sequence_from(self.spans)?;
+        let captures = lua.create_table()?;
+        for (k, is) in self.captures {
+            match k {
+                NumberOrString::Number(n) => captures.set(n, is)?,
+                NumberOrString::String(s) => captures.set(s, is)?,
+            }
+        }
+        table.set("captures", captures)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,6 +275,47 @@ mod tests {
         assert_eq!("hp\r\nsk\r\n", ct.lastn(2).unwrap());
     }
 
+    #[test]
+    fn test_cache_text_window() {
+        use crate::ui::span::Span;
+        use crate::ui::style::Color;
+        use crate::proto::Label;
+        let mut ct = CacheText::new(2, 4);
+        ct.push_line(&Line::fmt_raw("张三走了过来。"));
+        let red = Style::default().fg(Color::Red);
+        ct.push_line(&Line::new(vec![
+            Span::new("李四", red, Label::None),
+            Span::new("走了过来。\r\n", Style::default(), Label::None),
+        ]));
+        let window = ct.window();
+        assert_eq!(2, window.len());
+        assert_eq!("张三走了过来。", window[0].0);
+        assert_eq!("李四走了过来。", window[1].0);
+        assert_eq!(2, window[1].1.len());
+        assert_eq!(red, window[1].1[0].style);
+        // 未结束的行也在窗口中
+        ct.push_line(&Line::new(vec![Span::new("hp: ", Style::default(), Label::None)]));
+        assert_eq!(vec!["李四走了过来。", "hp: "], ct.window().iter().map(|(t, _)| *t).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_dominant_style() {
+        use crate::ui::style::Color;
+        let red = Style::default().fg(Color::Red);
+        let green = Style::default().fg(Color::Green);
+        let styles = vec![
+            InlineStyle { offset: 0, style: red },
+            InlineStyle { offset: 2, style: green },
+            InlineStyle { offset: 8, style: red },
+        ];
+        assert_eq!(green, dominant_style(&styles, 1, 8).unwrap().style);
+        // 不相邻的相同样式合并计算
+        assert_eq!(red, dominant_style(&styles, 0, 12).unwrap().style);
+        assert_eq!(red, dominant_style(&styles, 9, 9).unwrap().style);
+        assert_eq!(5, dominant_style(&styles, 5, 6).unwrap().offset);
+        assert!(dominant_style(&[], 0, 3).is_none());
+    }
+
     #[test]
     fn test_cache_text_with_regex() {
         let re = Regex::new("^(.*)走了过来。$").unwrap();
@@ -0,0 +1,94 @@
+use crate::error::{Error, Result};
+use regex::RegexSet;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// 行分类器
+///
+/// 由配置文件[classify]中的各类正则列表编译而成，如chat、combat、system，
+/// 行路由及脚本共用同一份定义，避免各脚本重复维护正则
+#[derive(Debug, Clone, Default)]
+pub struct Classifier(Arc<Vec<Class>>);
+
+#[derive(Debug)]
+struct Class {
+    name: String,
+    patterns: Vec<String>,
+    set: RegexSet,
+}
+
+impl Classifier {
+    pub fn new(classes: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let mut compiled = Vec::with_capacity(classes.len());
+        for (name, patterns) in classes {
+            let set = RegexSet::new(patterns)?;
+            compiled.push(Class {
+                name: name.to_owned(),
+                patterns: patterns.clone(),
+                set,
+            });
+        }
+        Ok(Self(Arc::new(compiled)))
+    }
+
+    /// 文本是否属于指定分类，分类不存在时返回false
+    pub fn is(&self, class: &str, text: &str) -> bool {
+        self.0
+            .iter()
+            .find(|c| c.name == class)
+            .map(|c| c.set.is_match(text))
+            .unwrap_or(false)
+    }
+
+    /// 文本所属的全部分类，按名称排序
+    pub fn classes(&self, text: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|c| c.set.is_match(text))
+            .map(|c| &c.name[..])
+            .collect()
+    }
+
+    /// 分类中各正则的合并，供行路由编译到同一个RegexSet中
+    pub fn pattern(&self, class: &str) -> Result<String> {
+        let class = self
+            .0
+            .iter()
+            .find(|c| c.name == class)
+            .ok_or_else(|| Error::RuntimeError(format!("unknown line class {}", class)))?;
+        if class.patterns.is_empty() {
+            // 空分类不匹配任何文本
+            return Ok(String::from("\\b\\B"));
+        }
+        Ok(class
+            .patterns
+            .iter()
+            .map(|p| format!("(?:{})", p))
+            .collect::<Vec<_>>()
+            .join("|"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifier() {
+        let mut classes = BTreeMap::new();
+        classes.insert("chat".to_owned(), vec!["^【闲聊】".to_owned(), "告诉你".to_owned()]);
+        classes.insert("combat".to_owned(), vec!["^你.*攻击".to_owned()]);
+        classes.insert("empty".to_owned(), vec![]);
+        let classifier = Classifier::new(&classes).unwrap();
+        assert!(classifier.is("chat", "张三告诉你：你好"));
+        assert!(!classifier.is("combat", "张三告诉你：你好"));
+        assert!(!classifier.is("system", "张三告诉你：你好"));
+        assert_eq!(vec!["chat", "combat"], classifier.classes("你对着告诉你的人发起攻击"));
+        assert_eq!("(?:^【闲聊】)|(?:告诉你)", classifier.pattern("chat").unwrap());
+        assert!(!regex::Regex::new(&classifier.pattern("empty").unwrap()).unwrap().is_match("x"));
+        assert!(classifier.pattern("system").is_err());
+
+        classes.insert("bad".to_owned(), vec!["(".to_owned()]);
+        assert!(Classifier::new(&classes).is_err());
+    }
+}
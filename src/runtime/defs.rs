@@ -0,0 +1,181 @@
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// 定义文件中的触发器、别名及定时器
+///
+/// 扩展名为.json时按JSON解析，否则按TOML解析，如：
+///
+/// ```toml
+/// [[trigger]]
+/// name = "hp"
+/// pattern = "^气血：(\\d+)"
+/// callback = "OnHp"
+///
+/// [[timer]]
+/// name = "save"
+/// interval_ms = 600000
+/// send = "save"
+/// ```
+///
+/// 每条定义的动作为send（支持%1、%<name>替换的命令文本）或callback（Lua全局函数名）之一，
+/// 回调在执行时按名称查找，可以在定义文件加载后再由脚本定义
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Defs {
+    pub trigger: Vec<RuleDef>,
+    pub alias: Vec<RuleDef>,
+    pub timer: Vec<TimerDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleDef {
+    #[serde(default)]
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub send: Option<String>,
+    pub callback: Option<String>,
+    #[serde(default)]
+    pub keep_evaluating: bool,
+    // 以下仅触发器有效
+    #[serde(default)]
+    pub oneshot: bool,
+    #[serde(default = "default_match_lines")]
+    pub match_lines: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimerDef {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub group: String,
+    pub interval_ms: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub send: Option<String>,
+    pub callback: Option<String>,
+    #[serde(default)]
+    pub oneshot: bool,
+    #[serde(default)]
+    pub fixed_delay: bool,
+}
+
+/// 定义的动作
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefAction {
+    Send(String),
+    Callback(String),
+}
+
+/// 已加载的定义种类
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefKind {
+    Trigger,
+    Alias,
+    Timer,
+}
+
+/// 已加载的定义，重新加载时据此删除
+#[derive(Debug, Clone)]
+pub struct LoadedDef {
+    pub kind: DefKind,
+    pub name: String,
+    pub action: DefAction,
+    // 定义文件路径，与规则的来源一致时才在重新加载时删除
+    pub source: String,
+}
+
+impl Defs {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let json = path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("json"))
+            .unwrap_or(false);
+        let defs = if json {
+            serde_json::from_str(&text)
+                .map_err(|e| Error::ParseError(format!("{}: {}", path.display(), e)))?
+        } else {
+            toml::from_str(&text)
+                .map_err(|e| Error::ParseError(format!("{}: {}", path.display(), e)))?
+        };
+        Ok(defs)
+    }
+}
+
+impl RuleDef {
+    pub fn action(&self) -> Result<DefAction> {
+        action(&self.name, &self.send, &self.callback)
+    }
+}
+
+impl TimerDef {
+    pub fn action(&self) -> Result<DefAction> {
+        action(&self.name, &self.send, &self.callback)
+    }
+}
+
+fn action(name: &str, send: &Option<String>, callback: &Option<String>) -> Result<DefAction> {
+    match (send, callback) {
+        (Some(send), None) => Ok(DefAction::Send(send.to_owned())),
+        (None, Some(callback)) => Ok(DefAction::Callback(callback.to_owned())),
+        _ => Err(Error::ParseError(format!(
+            "definition {} requires exactly one of send and callback",
+            name
+        ))),
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_match_lines() -> u8 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defs_toml_and_json() {
+        let defs: Defs = toml::from_str(
+            r#"
+            [[trigger]]
+            name = "hp"
+            pattern = "^气血：(\\d+)"
+            callback = "OnHp"
+            match_lines = 2
+
+            [[alias]]
+            pattern = "^gg (.*)$"
+            send = "get %1;give %1 to guard"
+
+            [[timer]]
+            name = "save"
+            interval_ms = 600000
+            send = "save"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(DefAction::Callback("OnHp".to_owned()), defs.trigger[0].action().unwrap());
+        assert_eq!(2, defs.trigger[0].match_lines);
+        assert!(defs.alias[0].enabled);
+        assert!(!defs.timer[0].enabled);
+        assert_eq!(DefAction::Send("save".to_owned()), defs.timer[0].action().unwrap());
+
+        let defs: Defs =
+            serde_json::from_str(r#"{"alias": [{"name": "x", "pattern": "^x$", "send": "a", "callback": "b"}]}"#)
+                .unwrap();
+        assert!(defs.trigger.is_empty());
+        assert!(defs.alias[0].action().is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::runtime::alias::{Alias, AliasFlags};
+use crate::runtime::defs::DefAction;
+use crate::runtime::timer::TimerModel;
+use crate::runtime::trigger::{Trigger, TriggerFlags};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// 导出的触发器或别名
+///
+/// 字段与定义文件（def_files）中的[[trigger]]及[[alias]]一致，外部编辑器修改后可作为定义文件重新加载。
+/// 配置文件及定义文件中的规则附带send或callback；脚本定义的规则回调为Lua函数，两者皆无，
+/// 作为定义文件加载前需补充
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RuleDump {
+    pub name: String,
+    pub group: String,
+    pub pattern: String,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    pub keep_evaluating: bool,
+    // 以下仅触发器有效
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oneshot: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_lines: Option<u8>,
+    // 定义文件不支持的标志，仅供查看
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<&'static str>,
+    // 定义所在的脚本或配置文件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// 导出的定时器，字段与定义文件中的[[timer]]一致
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TimerDump {
+    pub name: String,
+    pub group: String,
+    pub interval_ms: u64,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    pub oneshot: bool,
+    pub fixed_delay: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// 已注册的全部模型，各列表按名称排序
+#[derive(Debug, Default, Serialize)]
+pub struct ModelsDump {
+    pub trigger: Vec<RuleDump>,
+    pub alias: Vec<RuleDump>,
+    pub timer: Vec<TimerDump>,
+}
+
+/// 模型的定义来源：定义文件及配置文件规则的动作
+#[derive(Debug, Default)]
+pub struct Origins {
+    pub files: HashMap<String, String>,
+    pub actions: HashMap<String, DefAction>,
+}
+
+impl Origins {
+    // 动作对应的send及callback字段
+    fn action(&self, name: &str) -> (Option<String>, Option<String>) {
+        match self.actions.get(name) {
+            Some(DefAction::Send(send)) => (Some(send.to_owned()), None),
+            Some(DefAction::Callback(callback)) => (None, Some(callback.to_owned())),
+            None => (None, None),
+        }
+    }
+}
+
+impl ModelsDump {
+    pub fn add_trigger(&mut self, tr: &Trigger, origins: &Origins) {
+        let mut flags = Vec::new();
+        for (flag, name) in &[(TriggerFlags::PROMPT, "prompt"), (TriggerFlags::CONTEXT, "context")] {
+            if tr.extra.flags.contains(*flag) {
+                flags.push(*name);
+            }
+        }
+        let (send, callback) = origins.action(&tr.name);
+        self.trigger.push(RuleDump {
+            name: tr.name.to_owned(),
+            group: tr.group.to_owned(),
+            pattern: tr.pattern.to_owned(),
+            enabled: tr.enabled,
+            send,
+            callback,
+            keep_evaluating: tr.extra.flags.contains(TriggerFlags::KEEP_EVALUATING),
+            oneshot: Some(tr.extra.flags.contains(TriggerFlags::ONESHOT)),
+            match_lines: Some(tr.extra.match_lines),
+            flags,
+            source: origins.files.get(&tr.name).cloned(),
+        });
+    }
+
+    pub fn add_alias(&mut self, alias: &Alias, origins: &Origins) {
+        let (send, callback) = origins.action(&alias.name);
+        self.alias.push(RuleDump {
+            name: alias.name.to_owned(),
+            group: alias.group.to_owned(),
+            pattern: alias.pattern.to_owned(),
+            enabled: alias.enabled,
+            send,
+            callback,
+            keep_evaluating: alias.extra.contains(AliasFlags::KEEP_EVALUATING),
+            oneshot: None,
+            match_lines: None,
+            flags: Vec::new(),
+            source: origins.files.get(&alias.name).cloned(),
+        });
+    }
+
+    pub fn add_timer(&mut self, tm: &TimerModel, origins: &Origins) {
+        let (send, callback) = origins.action(&tm.name);
+        self.timer.push(TimerDump {
+            name: tm.name.to_owned(),
+            group: tm.group.to_owned(),
+            interval_ms: tm.tick_time.as_millis() as u64,
+            enabled: tm.enabled(),
+            send,
+            callback,
+            oneshot: tm.oneshot(),
+            fixed_delay: tm.fixed_delay(),
+            source: origins.files.get(&tm.name).cloned(),
+        });
+    }
+
+    /// 排序使导出结果稳定，便于比较
+    pub fn sort(&mut self) {
+        self.trigger.sort_by(|a, b| a.name.cmp(&b.name));
+        self.alias.sort_by(|a, b| a.name.cmp(&b.name));
+        self.timer.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
@@ -1,28 +1,79 @@
-use crate::codec::{Codec, MudCodec};
+use crate::codec::{Codec, Fallback, MudCodec};
 use crate::conf;
+use crate::crash;
+use crate::datadir::{self, DataDir};
 use crate::error::{Error, Result};
 use crate::event::Event;
-use crate::runtime::alias::Alias;
+use crate::i18n;
+use crate::map::mapper::Mapper;
+use crate::runtime::alias::{Alias, AliasFlags};
 use crate::runtime::alias::Aliases;
-use crate::runtime::cache::{CacheText, InlineStyle};
-use crate::runtime::init::init_lua;
+use crate::runtime::bundle::{Bundle, TrustedKeys};
+use crate::runtime::defs::{DefAction, DefKind, Defs, LoadedDef};
+use crate::runtime::classify::Classifier;
+use crate::runtime::cache::{CacheText, InlineStyle, LineStyles};
+use crate::runtime::group::{GroupMeta, GroupMetas};
+use crate::runtime::observe::{Observation, Observer};
+use crate::runtime::offline::{OfflineQueue, QueuedCmd};
+use crate::runtime::media::{self, MediaDirective, MediaPlayer};
+use crate::runtime::pacer::{Held, Pacer};
+use crate::runtime::prompt::PromptParser;
+use crate::runtime::settings;
+use crate::runtime::guard::{DupGuard, Verdict};
+use crate::runtime::frame::{Frame, Frames};
+use crate::runtime::json;
+use crate::runtime::init::{
+    create_named_callback, create_send_callback, create_timer_send_callback, init_classify,
+    init_group_vars, init_lua, init_mapper, init_protocols, init_screen, init_term_caps, init_timer_view,
+    init_trigger_window, init_world_log,
+};
+use crate::telnet::Protocols;
 use crate::runtime::model::{ModelStore, ModelCaptures};
+use crate::runtime::newline::{self, Newlines};
 use crate::runtime::queue::{ActionQueue, OutputQueue};
-use crate::runtime::trigger::{Triggers, Trigger};
+use crate::runtime::record::{Macro, Recorder};
+use crate::runtime::trace::Tracer;
+use crate::runtime::transform::{self, Transformers};
+use crate::runtime::trigger::{GroupWindow, Triggers, Trigger, TriggerContext, TriggerExtra, TriggerFlags};
 use crate::runtime::mxp_trigger::{MxpTriggers, MxpTrigger};
-use crate::runtime::vars::Variables;
-use crate::runtime::RuntimeOutput;
+use crate::runtime::worldlog::{self, WorldLog};
+use crate::runtime::vars::{GroupVars, Variables};
+use crate::runtime::zmud::{self, RuleKind};
+use crate::runtime::route::{Route, Router};
+use crate::runtime::dump::{ModelsDump, Origins};
+use crate::runtime::export::{Exporter, Target};
+use crate::runtime::marks::{self, LineMarks};
+use crate::runtime::register::{self, Registers};
+use crate::runtime::repl::Repl;
+use crate::runtime::scrollback::{now_millis, Scrollback};
+use crate::runtime::status::{Feed, Status, StatusCapture, StatusKind};
+use crate::runtime::statusbar::StatusBar;
+use crate::runtime::{RuntimeOutput, SessionCmd};
 use crate::runtime::delay_queue::{Delay, Delayed};
-use crate::runtime::timer::{Timers, Timer, TimerModel};
-use crate::proto::{Parser, Element};
+use crate::runtime::timer::{Timers, Timer, TimerFlags, TimerModel};
+use crate::proto::{Element, Label, Parser};
+use crate::proto::gmcp::GmcpMessage;
+use crate::proto::msdp;
+use crate::probe::ProbeReport;
+use crate::proto::mxp::ModeState;
 use crate::ui::line::{Line, Lines, RawLine};
+use crate::ui::span::Span;
+use crate::ui::style::{Color, Style};
+use crate::ui::view::ScreenView;
 use crate::ui::UserOutput;
-use std::collections::VecDeque;
+use regex::RegexSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use crossbeam_channel::Sender;
 use mlua::ToLua;
+use rusqlite::Connection;
+use serde_json::Value as Json;
+use uuid::Uuid;
 
 // 别名回调存储于Lua脚本引擎的全局变量表中
 pub(crate) const GLOBAL_ALIAS_CALLBACKS: &str = "_global_alias_callbacks";
@@ -32,6 +83,65 @@ pub(crate) const GLOBAL_TRIGGER_CALLBACKS: &str = "_global_trigger_callbacks";
 pub(crate) const GLOBAL_MXP_TRIGGER_CALLBACKS: &str = "_global_mxp_trigger_callbacks";
 // 计时器回调存储于Lua脚本引擎的全局变量表中
 pub(crate) const GLOBAL_TIMER_CALLBACKS: &str = "_global_timer_callbacks";
+// 状态界面回调存储于Lua脚本引擎的全局变量表中，以界面名称为键
+pub(crate) const GLOBAL_STATUS_CALLBACKS: &str = "_global_status_callbacks";
+pub(crate) const GLOBAL_TRANSFORMER_CALLBACKS: &str = "_global_transformer_callbacks";
+// 等待按键的回调，同一时刻仅有一个
+pub(crate) const GLOBAL_READKEY_CALLBACK: &str = "_global_readkey_callback";
+// 弹出菜单的回调，同一时刻仅有一个
+pub(crate) const GLOBAL_MENU_CALLBACK: &str = "_global_menu_callback";
+// 服务器声音及音乐指令的回调
+pub(crate) const GLOBAL_MEDIA_CALLBACK: &str = "_global_media_callback";
+// GMCP回调，以包名为键
+pub(crate) const GLOBAL_GMCP_CALLBACKS: &str = "_global_gmcp_callbacks";
+// MSDP变量变化的回调，以变量名为键
+pub(crate) const GLOBAL_MSDP_CALLBACKS: &str = "_global_msdp_callbacks";
+// 连接测量完成时的回调
+pub(crate) const GLOBAL_PROBE_CALLBACK: &str = "_global_probe_callback";
+// 正在加载的脚本文件
+pub(crate) const GLOBAL_LOADING_FILE: &str = "_global_loading_file";
+// 触发器的定义文件
+pub(crate) const GLOBAL_TRIGGER_ORIGINS: &str = "_global_trigger_origins";
+// 别名及定时器的定义文件
+pub(crate) const GLOBAL_ALIAS_ORIGINS: &str = "_global_alias_origins";
+pub(crate) const GLOBAL_TIMER_ORIGINS: &str = "_global_timer_origins";
+// #go命令调用的行走函数
+pub(crate) const GLOBAL_WALKER: &str = "_global_walker";
+// 配置文件中定义的触发器和别名的默认分组
+const CONF_GROUP: &str = "conf";
+// 导入的zMUD触发器和别名未指定类名时的分组
+const ZMUD_GROUP: &str = "zmud";
+// 跳转到书签时，显示书签行之后的行数
+const JUMP_CONTEXT: usize = 5;
+// 内置命令名，以#开头且名称不在此列的文本原样发送至服务器
+const BUILTINS: &[&str] = &[
+    "manage",
+    "record",
+    "play",
+    "confirm",
+    "fetch",
+    "reg",
+    "yank",
+    "dump",
+    "flushvars",
+    "mark",
+    "marks",
+    "jump",
+    "trace",
+    "transformers",
+    "loadorder",
+    "reload",
+    "stats",
+    "queue",
+    "offline",
+    "session",
+    "pause",
+    "set",
+    "get",
+    "protocols",
+    "bookmarks",
+    "go",
+];
 
 /// 运行时操作
 #[derive(Debug, Clone, PartialEq)]
@@ -43,81 +153,405 @@ pub enum EngineAction {
     CreateTrigger(Trigger),
     DeleteTrigger(String),
     EnableTriggerGroup(String, bool),
+    // 限制触发器组的启用时间或匹配次数
+    LimitTriggerGroup(String, GroupWindow),
     CreateTimer(TimerModel),
     DeleteTimer(String),
     ExecuteTimer(Delay<Timer>),
     EnableTimerGroup(String, bool),
+    EnableTimer(String, bool),
+    // 从当前时间重新开始计时
+    ResetTimer(String),
+    // 暂停或恢复所有定时器
+    PauseTimers(bool),
     CreateMxpTrigger(MxpTrigger),
     DeleteMxpTrigger(String),
     EnableMxpTriggerGroup(String, bool),
+    // 删除分组内的别名、触发器及定时器，并清除分组变量
+    DeleteGroup(String),
+    // 行转换器：名称及执行顺序
+    CreateTransformer(String, i32),
+    DeleteTransformer(String),
+    EnableTransformer(String, bool),
     LoadFile(String),
+    // 导入zMUD/CMUD导出的触发器及别名
+    ImportZmud(String),
     // ExecuteUserCmd(String),
     // ExecuteUserScript(String),
     ExecuteUserOutput(UserOutput),
+    // 别名回调中发送的命令，附带别名调用链
+    ExecuteAliasCmd(String, Vec<String>),
+    // 等待标记之后的命令，等待指定时间后执行，节奏控制开启时排在暂存的命令之后
+    WaitAliasCmd(Duration, String, Vec<String>),
     ParseWorldBytes(Vec<u8>),
+    // 与服务器协商的协议
+    UpdateProtocols(Protocols),
+    // 服务器发送的GMCP消息
+    ReceiveGmcp(Vec<u8>),
+    // 服务器发送的MSDP变量
+    ReceiveMsdp(Vec<u8>),
+    // 连接初期的延迟及包大小测量结果
+    UpdateProbe(ProbeReport),
+    // 与服务器断开连接，之后的用户命令进入离线队列
+    WorldDisconnected,
+    // 重新连接到服务器，离线队列待登录后发送
+    WorldConnected,
+    // 脚本要求立即重连，已连接时先断开
+    Reconnect,
+    // 会话管理，由应用处理
+    ManageSession(SessionCmd),
+    // 通过#set调整的界面设置，由界面应用
+    UpdateTerm(conf::Term),
+    // 在后台下载脚本包，由应用处理
+    FetchBundle(String),
+    // 脚本包的下载地址及下载结果
+    BundleFetched(String, std::result::Result<Vec<u8>, String>),
+    // 发送离线队列中的命令
+    FlushOfflineQueue,
+    // 设置分组的显示属性
+    SetGroupMeta(String, GroupMeta),
+    // 播放声音目录下的文件：文件名及音量
+    PlaySound(String, Option<u32>),
+    // 开启或关闭触发器组的观察：组名、是否观察及保留的样本数
+    ObserveTriggerGroup(String, bool, usize),
+    // 开始记录世界文本：日志目录下的文件及日志选项
+    StartLog(String, conf::Log),
+    // 停止记录世界文本
+    StopLog,
+    // 清除脚本定义的规则并重新执行初始化脚本
+    ReloadScript,
+    // 设置空命令时禁止重发的命令模式
+    SetRepeatDeny(Vec<String>),
+    // 在命令行短暂显示重发的命令
+    FlashCmd(String),
+    // REPL窗格中输入的一行代码
+    EvalRepl(String),
+    // 放弃REPL中尚未完整的多行代码
+    CancelRepl,
+    // 脚本等待按键：标识及提示文本
+    ReadKey(String, String),
+    // 界面读取的按键，取消时为None
+    KeyRead(Option<String>),
+    // 等待按键超时，附带标识
+    KeyReadTimeout(String),
+    // 弹出菜单：标题及选项
+    ShowMenu(String, Vec<String>),
+    // 选中的菜单项，从1开始，取消时为None
+    MenuChosen(Option<usize>),
     // 将文本发送到UI界面，原始文本可选（来源于服务端）
     SendLineToUI(Line, Option<RawLine>),
+    // 路由到指定窗口的行，目标窗口及文本
+    SendLineToWindow(String, Line),
+    // 服务器状态栏的内容
+    SendStatusToUI(Vec<Line>),
+    // 仅转发原始文本，用于不在主窗格显示的行
+    SendRawToUI(RawLine),
     SendToServer(String),
+    // 不回显、不经过别名和节奏控制的命令，用于密码等
+    SendNoEcho(String),
+    // 原样写入的字节，不经过编码，可包含telnet的IAC序列
+    SendRawToServer(Vec<u8>),
+    // 发送按队列长度暂存的命令或开始等待，不再经过节奏控制
+    SendHeldToServer(Vec<Held>),
     ProcessWorldLines(Vec<RawLine>),
 }
 
 /// 用于执行各类运行时操作
 pub struct Engine {
     lua: mlua::Lua,
+    // 世界变量，读取时以全局变量为后备
     vars: Variables,
+    // 各世界共享的全局变量
+    global_vars: Variables,
+    // 以分组为命名空间的变量
+    group_vars: GroupVars,
     actq: VecDeque<EngineAction>,
+    // 已执行的操作数
+    action_seq: u64,
     // 临时队列，用于脚本执行生成操作的临时处理队列
     tmpq: ActionQueue,
     mud_codec: MudCodec,
+    // 服务器编码无法表示的字符的处理
+    encode_fallback: Fallback,
     parser: Parser,
-    cache: CacheText,
+    // 换行符的规范化
+    newlines: Newlines,
+    // 分帧处理的世界文本
+    frames: Frames,
+    // 当前MXP模式，供脚本诊断
+    mxp_mode: Arc<RwLock<ModeState>>,
+    cache: Arc<RwLock<CacheText>>,
+    // 已输出到界面的历史行
+    scrollback: Scrollback,
+    // 历史行书签
+    line_marks: LineMarks,
+    // 界面主窗格的可见内容
+    screen: ScreenView,
+    // 命名寄存器
+    registers: Registers,
     aliases: Aliases,
     triggers: Triggers,
+    // 当前未结束的行中已执行的提示符触发器
+    prompt_fired: HashSet<String>,
+    // 与服务器协商的协议，连接后首次收到文本前未知
+    protocols: Arc<RwLock<Option<Protocols>>>,
+    // 连接初期的测量结果
+    probe: Arc<RwLock<Option<ProbeReport>>>,
+    // 服务器通过MSDP发送的变量
+    msdp_vars: Arc<RwLock<HashMap<String, Json>>>,
+    // 限时/限次启用的触发器组
+    trigger_windows: HashMap<String, GroupWindow>,
+    // 分组的颜色及图标
+    group_metas: GroupMetas,
+    // 仅观察的触发器组
+    observer: Observer,
+    // 最近的文本处理轨迹
+    tracer: Tracer,
+    // 本批次处理的原始行，处理完毕后写入崩溃报告
+    crash_lines: Vec<String>,
+    // 服务器通过光标定位绘制的状态栏，未启用时为None
+    status_bar: Option<StatusBar>,
     // mxp triggers
     mxp_triggers: MxpTriggers,
+    // 解析后、触发器匹配前执行的行转换器
+    transformers: Transformers,
     timers: Timers,
+    // 行路由，先于触发器执行
+    router: Router,
+    route_rules: Vec<conf::Route>,
+    classify_conf: BTreeMap<String, Vec<String>>,
+    classifier: Classifier,
+    // 配置文件中定义的触发器和别名
+    conf_triggers: Vec<conf::SendRule>,
+    conf_aliases: Vec<conf::SendRule>,
+    echo: Option<Echo>,
+    // 界面中尚未结束的行（通常为提示符）的文本，用于回显的合并或换行
+    open_line: Option<String>,
+    // 正在录制的宏
+    recorder: Option<Recorder>,
+    // 重复命令保护
+    dup_guard_conf: conf::DupGuard,
+    dup_guard: Option<DupGuard>,
+    // 按服务器命令队列长度控制发送节奏
+    queue_tag_conf: conf::QueueTag,
+    pacer: Option<Pacer>,
+    // 提示符解析
+    prompt_conf: conf::Prompt,
+    prompt_parser: Option<PromptParser>,
+    // 声音指令的处理，配置了播放命令时可播放声音
+    media_conf: conf::Media,
+    player: Option<MediaPlayer>,
+    // 变量导出，由spawn_exporter启动，引擎释放时导出线程退出
+    export_conf: conf::Export,
+    export_stop: Option<Sender<()>>,
+    // 可通过#set调整的运行时及界面设置，及保存设置的配置文件
+    settings_conf: conf::Config,
+    conf_file: String,
+    // 已下载并校验，等待确认安装的脚本包
+    pending_bundle: Option<Bundle>,
+    // 状态界面解析
+    status_parser: bool,
+    status: Option<StatusCapture>,
+    // 脚本正在等待的按键标识
+    read_key: Option<String>,
     cmd_delim: char,
+    // 命令中的等待标记，为空时关闭
+    wait_token: String,
     send_empty_cmd: bool,
-    init_script: String,
-    logger: Option<File>,
+    repeat_last: bool,
+    // 上一条非空的用户命令
+    last_cmd: Option<String>,
+    repeat_deny: RegexSet,
+    // REPL中尚未完整的多行代码
+    repl: Repl,
+    max_alias_depth: usize,
+    init_scripts: Vec<String>,
+    autoload_dir: String,
+    def_files: Vec<String>,
+    // 从定义文件加载的触发器、别名及定时器
+    loaded_defs: Vec<LoadedDef>,
+    // 已加载的脚本，按加载顺序
+    loaded: Vec<LoadRecord>,
+    map_db: String,
+    // 按区域延迟加载地图时缓存的区域数，为None时全部加载
+    map_zone_cache: Option<usize>,
+    // 加载地图数据库后可用
+    mapper: Option<Mapper>,
+    vars_file: String,
+    global_vars_file: String,
+    // 与服务器的连接状态
+    connected: bool,
+    // 断线期间暂存的用户命令，未配置文件时为None
+    offline_queue_file: String,
+    offline_queue: Option<OfflineQueue>,
+    // 变量修改日志的同步间隔
+    vars_sync: Duration,
+    data_dir: DataDir,
+    log_conf: conf::Log,
+    logger: Option<WorldLog>,
 }
 
 impl Engine {
     pub fn new(config: &conf::Config) -> Self {
+        let global_vars = Variables::new().shared();
         Self {
             // evttx,
             lua: mlua::Lua::new(),
-            vars: Variables::new(),
+            vars: Variables::new().with_global(&global_vars),
+            global_vars,
+            group_vars: GroupVars::new(),
             actq: VecDeque::new(),
+            action_seq: 0,
             tmpq: ActionQueue::new(),
             mud_codec: MudCodec::new(),
+            encode_fallback: Fallback::new(
+                config.runtime.encode_fallback,
+                &config.runtime.encode_translit,
+            ),
             parser: Parser::default(),
+            newlines: Newlines::new(config.runtime.newline),
+            frames: Frames::new(&config.runtime.frame),
+            mxp_mode: Arc::new(RwLock::new(ModeState::default())),
             // only allow up to 5 lines for trigger
-            cache: CacheText::new(5, 10),
+            cache: Arc::new(RwLock::new(CacheText::new(5, 10))),
+            scrollback: Scrollback::new(2000),
+            line_marks: LineMarks::default(),
+            screen: ScreenView::default(),
+            registers: Registers::new(&config.runtime),
             aliases: Aliases::new(),
             triggers: Triggers::new(),
+            prompt_fired: HashSet::new(),
+            trigger_windows: HashMap::new(),
+            group_metas: GroupMetas::default(),
+            observer: Observer::default(),
+            protocols: Arc::new(RwLock::new(None)),
+            probe: Arc::new(RwLock::new(None)),
+            msdp_vars: Arc::new(RwLock::new(HashMap::new())),
+            tracer: Tracer::new(config.runtime.trace_capacity),
+            crash_lines: vec![],
+            status_bar: match config.term.server_status_rows {
+                0 => None,
+                rows => Some(StatusBar::new(rows as usize)),
+            },
             mxp_triggers: MxpTriggers::new(),
+            transformers: Transformers::with_builtins(),
             timers: Timers::new(),
+            router: Router::default(),
+            route_rules: config.routes.clone(),
+            classify_conf: config.classify.clone(),
+            classifier: Classifier::default(),
+            conf_triggers: config.trigger.clone(),
+            conf_aliases: config.alias.clone(),
+            echo: Echo::new(&config.runtime),
+            open_line: None,
+            recorder: None,
+            dup_guard_conf: config.runtime.dup_guard.clone(),
+            dup_guard: None,
+            queue_tag_conf: config.runtime.queue_tag.clone(),
+            pacer: None,
+            prompt_conf: config.prompt.clone(),
+            prompt_parser: None,
+            media_conf: config.media.clone(),
+            player: None,
+            export_conf: config.export.clone(),
+            export_stop: None,
+            settings_conf: config.clone(),
+            conf_file: config.conf_file.to_owned(),
+            pending_bundle: None,
+            status_parser: config.runtime.status_parser,
+            status: None,
+            read_key: None,
             cmd_delim: config.runtime.cmd_delim,
+            wait_token: config.runtime.wait_token.to_owned(),
             send_empty_cmd: config.runtime.send_empty_cmd,
-            init_script: config.runtime.init_script.to_owned(),
+            repeat_last: config.runtime.repeat_last_on_empty,
+            last_cmd: None,
+            repeat_deny: RegexSet::empty(),
+            repl: Repl::new(),
+            max_alias_depth: config.runtime.max_alias_depth,
+            init_scripts: config.runtime.all_init_scripts(),
+            autoload_dir: config.runtime.autoload_dir.to_owned(),
+            def_files: config.runtime.def_files.clone(),
+            loaded_defs: Vec::new(),
+            loaded: Vec::new(),
+            map_db: config.runtime.map_db.to_owned(),
+            map_zone_cache: if config.runtime.map_preload {
+                None
+            } else {
+                Some(config.runtime.map_zone_cache)
+            },
+            mapper: None,
+            vars_file: config.runtime.vars_file.to_owned(),
+            global_vars_file: config.runtime.global_vars_file.to_owned(),
+            connected: true,
+            offline_queue_file: config.runtime.offline_queue.to_owned(),
+            offline_queue: None,
+            vars_sync: Duration::from_millis(config.runtime.vars_sync_ms),
+            data_dir: DataDir::new(config),
+            log_conf: config.log.clone(),
             logger: None,
         }
     }
 
-    pub fn set_logger(&mut self, logger: File) {
+    /// 界面主窗格的可见内容，由界面线程更新
+    pub fn screen_view(&self) -> ScreenView {
+        self.screen.clone()
+    }
+
+    /// 按配置开始记录世界文本
+    pub fn open_log(&mut self, file: &str) -> Result<PathBuf> {
+        self.create_log(file, self.log_conf.clone())
+    }
+
+    /// 开始记录世界文本，文件必须位于日志目录，替换正在记录的日志
+    pub fn start_log(&mut self, file: &str, opts: conf::Log) -> Result<PathBuf> {
+        worldlog::validate_file(file)?;
+        self.create_log(file, opts)
+    }
+
+    fn create_log(&mut self, file: &str, opts: conf::Log) -> Result<PathBuf> {
+        let logger = WorldLog::create(self.data_dir.log_path(file), opts)?;
+        let path = logger.path().to_path_buf();
         self.logger = Some(logger);
+        Ok(path)
     }
 
     pub fn init(&mut self) -> Result<()> {
-        init_lua(&self.lua, &self.vars, &self.tmpq)?;
-        if !self.init_script.is_empty() {
-            log::info!("loading initial script '{}'", &self.init_script);
-            let mut f = File::open(&self.init_script)?;
-            let mut init_script = String::new();
-            f.read_to_string(&mut init_script)?;
-            self.lua.load(&init_script).exec()?;
+        self.classifier = Classifier::new(&self.classify_conf)?;
+        if !self.route_rules.is_empty() {
+            log::info!("compiling {} routing rules", self.route_rules.len());
+            self.router = Router::new(&self.route_rules, &self.classifier)?
+                .with_data_dir(self.data_dir.clone());
+        }
+        if self.dup_guard_conf.enabled {
+            self.dup_guard = Some(DupGuard::new(&self.dup_guard_conf)?);
+        }
+        if !self.queue_tag_conf.pattern.is_empty() {
+            self.pacer = Some(Pacer::new(&self.queue_tag_conf)?);
+        }
+        if !self.prompt_conf.pattern.is_empty() {
+            self.prompt_parser = Some(PromptParser::new(&self.prompt_conf)?);
+        }
+        if !self.media_conf.play_cmd.is_empty() {
+            let dir = self.data_dir.state_path(&self.media_conf.sound_dir);
+            self.player = Some(MediaPlayer::new(&self.media_conf.play_cmd, dir));
+        }
+        if !self.global_vars_file.is_empty() {
+            self.global_vars
+                .recover(&self.data_dir.global_path(&self.global_vars_file), self.vars_sync)?;
+        }
+        if !self.vars_file.is_empty() {
+            self.vars
+                .recover(&self.data_dir.state_path(&self.vars_file), self.vars_sync)?;
+        }
+        if !self.offline_queue_file.is_empty() {
+            let queue = OfflineQueue::open(self.data_dir.state_path(&self.offline_queue_file))?;
+            if !queue.is_empty() {
+                log::info!("{} commands left in offline queue", queue.len());
+            }
+            self.offline_queue = Some(queue);
         }
+        self.init_scripting()?;
         let outputs = self.apply();
         if !outputs.is_empty() {
             log::warn!("initial script should NOT contain any IO operation");
@@ -128,6 +562,282 @@ impl Engine {
         Ok(())
     }
 
+    /// 初始化Lua状态：注册函数，加载配置及定义文件中的规则、地图和各初始化脚本
+    fn init_scripting(&mut self) -> Result<()> {
+        init_lua(
+            &self.lua,
+            &self.vars,
+            &self.global_vars,
+            &self.tmpq,
+            &self.scrollback,
+            &self.mxp_mode,
+            &self.registers,
+        )?;
+        init_term_caps(&self.lua, self.settings_conf.term.mouse)?;
+        init_screen(&self.lua, &self.screen)?;
+        init_protocols(&self.lua, &self.protocols, &self.probe, &self.msdp_vars)?;
+        init_group_vars(&self.lua, &self.group_vars, &self.tmpq)?;
+        init_trigger_window(&self.lua, &self.cache)?;
+        init_timer_view(&self.lua, &self.timers.view())?;
+        init_world_log(&self.lua, &self.tmpq, &self.log_conf)?;
+        init_classify(&self.lua, &self.classifier)?;
+        self.load_send_rules()?;
+        // 定义文件有误时仅提示，不影响启动
+        if let Err(e) = self.load_defs() {
+            log::warn!("failed to load definitions: {}", e);
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+        if !self.map_db.is_empty() {
+            let map_db = self.data_dir.state_path(&self.map_db);
+            log::info!("loading map database '{}'", map_db.display());
+            let conn = Connection::open(map_db)?;
+            self.mapper = Some(init_mapper(&self.lua, conn, self.map_zone_cache)?);
+        }
+        for script in self.init_scripts.clone() {
+            let init_script = self.data_dir.script_path(&script);
+            log::info!("loading initial script '{}'", init_script.display());
+            self.load_script(init_script, "init")?;
+        }
+        let (files, failed) = self.load_autoload_dir()?;
+        for (file, e) in failed {
+            log::warn!("failed to load script '{}': {}", file, e);
+        }
+        if !files.is_empty() {
+            log::info!("loaded {} scripts from autoload directory", files.len());
+        }
+        Ok(())
+    }
+
+    /// 重新加载脚本，无需重启会话
+    ///
+    /// 清除全部触发器、别名、定时器及脚本注册的转换器，以新的Lua状态重新初始化，
+    /// 变量、界面及与服务器的连接保持不变
+    fn reload(&mut self) -> Result<()> {
+        log::info!("reloading scripts");
+        self.triggers = Triggers::new();
+        self.aliases = Aliases::new();
+        self.mxp_triggers = MxpTriggers::new();
+        // 定时器调度线程持有原调度队列，逐个删除，已调度的任务在到期时被忽略
+        let timers: Vec<String> = self.timers.iter().map(|tm| tm.name.to_owned()).collect();
+        for name in timers {
+            self.timers.remove(&name);
+        }
+        self.transformers = Transformers::with_builtins();
+        self.trigger_windows.clear();
+        self.prompt_fired.clear();
+        self.read_key = None;
+        self.mapper = None;
+        self.loaded.clear();
+        self.loaded_defs.clear();
+        self.lua = mlua::Lua::new();
+        self.init_scripting()
+    }
+
+    /// 保存世界变量与全局变量，未配置持久化文件时跳过
+    pub fn save_vars(&self) -> Result<()> {
+        if !self.global_vars_file.is_empty() {
+            self.global_vars
+                .save(&self.data_dir.global_path(&self.global_vars_file))?;
+        }
+        if !self.vars_file.is_empty() {
+            self.vars.save(&self.data_dir.state_path(&self.vars_file))?;
+        }
+        Ok(())
+    }
+
+    /// 加载配置文件中定义的触发器和别名
+    fn load_send_rules(&mut self) -> Result<()> {
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TRIGGER_CALLBACKS)?;
+        for (i, rule) in self.conf_triggers.iter().enumerate() {
+            let name = rule_name(&rule.name, "conf-trigger", i);
+            let trigger = Trigger::builder()
+                .name(&name)
+                .group(rule_group(&rule.group))
+                .pattern(&rule.pattern)?
+                .enabled(rule.enabled)
+                .build();
+            callbacks.set(&name[..], create_send_callback(&self.lua, &self.tmpq, &rule.send)?)?;
+            self.tmpq.push(EngineAction::CreateTrigger(trigger));
+        }
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_ALIAS_CALLBACKS)?;
+        for (i, rule) in self.conf_aliases.iter().enumerate() {
+            let name = rule_name(&rule.name, "conf-alias", i);
+            let alias = Alias::builder()
+                .name(&name)
+                .group(rule_group(&rule.group))
+                .pattern(&rule.pattern)?
+                .enabled(rule.enabled)
+                .build();
+            callbacks.set(&name[..], create_send_callback(&self.lua, &self.tmpq, &rule.send)?)?;
+            self.tmpq.push(EngineAction::CreateAlias(alias));
+        }
+        Ok(())
+    }
+
+    /// 加载定义文件中的触发器、别名及定时器，返回加载的数量
+    fn load_defs(&mut self) -> Result<usize> {
+        let compiled = self.compile_defs()?;
+        self.install_defs(compiled)
+    }
+
+    // 解析全部定义文件并编译规则，任一文件有误时返回错误，不影响已加载的定义
+    fn compile_defs(&self) -> Result<Vec<CompiledDef>> {
+        let mut compiled = Vec::new();
+        for file in &self.def_files {
+            let path = self.data_dir.script_path(file);
+            log::info!("loading definitions '{}'", path.display());
+            let defs = Defs::load(&path)?;
+            let source = path.display().to_string();
+            for (i, def) in defs.trigger.iter().enumerate() {
+                let name = rule_name(&def.name, &format!("{}-trigger", file), i);
+                let mut flags = TriggerFlags::empty();
+                flags.set(TriggerFlags::KEEP_EVALUATING, def.keep_evaluating);
+                flags.set(TriggerFlags::ONESHOT, def.oneshot);
+                let trigger = Trigger::builder()
+                    .name(&name)
+                    .group(rule_group(&def.group))
+                    .pattern(&def.pattern)?
+                    .enabled(def.enabled)
+                    .extra(TriggerExtra {
+                        match_lines: def.match_lines,
+                        flags,
+                        style: None,
+                    })
+                    .build();
+                compiled.push(CompiledDef {
+                    rule: DefRule::Trigger(trigger),
+                    action: def.action()?,
+                    source: source.clone(),
+                });
+            }
+            for (i, def) in defs.alias.iter().enumerate() {
+                let name = rule_name(&def.name, &format!("{}-alias", file), i);
+                let mut flags = AliasFlags::empty();
+                flags.set(AliasFlags::KEEP_EVALUATING, def.keep_evaluating);
+                let alias = Alias::builder()
+                    .name(&name)
+                    .group(rule_group(&def.group))
+                    .pattern(&def.pattern)?
+                    .enabled(def.enabled)
+                    .extra(flags)
+                    .build();
+                compiled.push(CompiledDef {
+                    rule: DefRule::Alias(alias),
+                    action: def.action()?,
+                    source: source.clone(),
+                });
+            }
+            for (i, def) in defs.timer.iter().enumerate() {
+                let name = rule_name(&def.name, &format!("{}-timer", file), i);
+                let mut flags = TimerFlags::empty();
+                flags.set(TimerFlags::ENABLED, def.enabled);
+                flags.set(TimerFlags::ONESHOT, def.oneshot);
+                flags.set(TimerFlags::FIXED_DELAY, def.fixed_delay);
+                let tick_time = Duration::from_millis(def.interval_ms);
+                let tm = TimerModel::new(&name[..], rule_group(&def.group), tick_time, flags);
+                compiled.push(CompiledDef {
+                    rule: DefRule::Timer(tm),
+                    action: def.action()?,
+                    source: source.clone(),
+                });
+            }
+        }
+        Ok(compiled)
+    }
+
+    // 注册编译后的定义及其回调，返回加载的数量
+    //
+    // 与已有规则重名的定义被跳过，不覆盖已有规则的回调
+    fn install_defs(&mut self, compiled: Vec<CompiledDef>) -> Result<usize> {
+        let mut n = 0;
+        for def in compiled {
+            let (kind, name, callbacks, origins) = match &def.rule {
+                DefRule::Trigger(tr) => (
+                    DefKind::Trigger,
+                    tr.name.to_owned(),
+                    GLOBAL_TRIGGER_CALLBACKS,
+                    GLOBAL_TRIGGER_ORIGINS,
+                ),
+                DefRule::Alias(alias) => (
+                    DefKind::Alias,
+                    alias.name.to_owned(),
+                    GLOBAL_ALIAS_CALLBACKS,
+                    GLOBAL_ALIAS_ORIGINS,
+                ),
+                DefRule::Timer(tm) => (
+                    DefKind::Timer,
+                    tm.name.to_owned(),
+                    GLOBAL_TIMER_CALLBACKS,
+                    GLOBAL_TIMER_ORIGINS,
+                ),
+            };
+            let callbacks: mlua::Table = self.lua.globals().get(callbacks)?;
+            let origins: mlua::Table = self.lua.globals().get(origins)?;
+            let exists = !matches!(callbacks.get::<_, mlua::Value>(&name[..])?, mlua::Value::Nil)
+                || !matches!(origins.get::<_, mlua::Value>(&name[..])?, mlua::Value::Nil)
+                || match kind {
+                    DefKind::Trigger => self.triggers.get(&name).is_some(),
+                    DefKind::Alias => self.aliases.get(&name).is_some(),
+                    DefKind::Timer => self.timers.get(&name).is_some(),
+                };
+            if exists {
+                log::warn!("definition {} in {} conflicts with an existing rule", name, def.source);
+                let err_lines = Lines::fmt_err(i18n::trf("err.def_conflict", &[&name, &def.source]));
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+                continue;
+            }
+            let callback = match (&def.action, kind) {
+                (DefAction::Send(send), DefKind::Timer) => create_timer_send_callback(&self.lua, &self.tmpq, send)?,
+                (DefAction::Send(send), _) => create_send_callback(&self.lua, &self.tmpq, send)?,
+                (DefAction::Callback(func), _) => create_named_callback(&self.lua, func)?,
+            };
+            callbacks.set(&name[..], callback)?;
+            origins.set(&name[..], &def.source[..])?;
+            self.tmpq.push(match def.rule {
+                DefRule::Trigger(tr) => EngineAction::CreateTrigger(tr),
+                DefRule::Alias(alias) => EngineAction::CreateAlias(alias),
+                DefRule::Timer(tm) => EngineAction::CreateTimer(tm),
+            });
+            self.loaded_defs.push(LoadedDef {
+                kind,
+                name,
+                action: def.action,
+                source: def.source,
+            });
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// 删除从定义文件加载的触发器、别名及定时器
+    ///
+    /// 与unload_script_models相同按来源删除，同名但由脚本创建的规则不受影响
+    fn unload_defs(&mut self) -> Result<()> {
+        for def in std::mem::take(&mut self.loaded_defs) {
+            let origins = match def.kind {
+                DefKind::Trigger => GLOBAL_TRIGGER_ORIGINS,
+                DefKind::Alias => GLOBAL_ALIAS_ORIGINS,
+                DefKind::Timer => GLOBAL_TIMER_ORIGINS,
+            };
+            let origins: mlua::Table = self.lua.globals().get(origins)?;
+            if origins.get::<_, Option<String>>(&def.name[..])?.as_deref() != Some(&def.source[..]) {
+                continue;
+            }
+            drop(origins);
+            match def.kind {
+                DefKind::Trigger => self.delete_trigger(&def.name)?,
+                DefKind::Alias => self.delete_alias(&def.name)?,
+                DefKind::Timer => self.delete_timer(&def.name)?,
+            }
+        }
+        Ok(())
+    }
+
     pub fn spawn_timer(&self, evttx: Sender<Event>) -> JoinHandle<()> {
         let schedule = self.timers.schedule();
         thread::spawn(move || {
@@ -141,11 +851,63 @@ impl Engine {
         })
     }
 
+    /// 按配置启动变量导出线程，未配置导出目标时返回None
+    pub fn spawn_exporter(&mut self) -> Result<Option<JoinHandle<()>>> {
+        if self.export_conf.target.is_empty() {
+            return Ok(None);
+        }
+        let target = Target::parse(&self.export_conf.target, &self.data_dir);
+        log::info!("exporting variables to {:?}", target);
+        let exporter = Exporter::new(
+            self.vars.clone(),
+            self.export_conf.vars.clone(),
+            target,
+            Duration::from_millis(self.export_conf.interval_ms),
+        );
+        let (stoptx, stoprx) = crossbeam_channel::bounded(0);
+        let handle = exporter.spawn(stoprx)?;
+        self.export_stop = Some(stoptx);
+        Ok(Some(handle))
+    }
+
     /// 推送操作
     pub fn push(&mut self, action: EngineAction) {
+        // 仅处理用户输入的命令，脚本发送的命令不参与重发
+        let action = match action {
+            EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)) => match self.repeat_last_cmd(cmd) {
+                Some(cmd) => EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)),
+                None => return,
+            },
+            other => other,
+        };
+        // 重复命令检查仅针对命令栏输入，内置命令（含快捷键发出的命令）不受限制
+        if let EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)) = &action {
+            if !cmd.starts_with('#') && !self.guard_user_cmd(cmd) {
+                return;
+            }
+        }
+        // 录制用户输入的命令，不包括录制与回放命令本身
+        if let (Some(recorder), EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd))) =
+            (self.recorder.as_mut(), &action)
+        {
+            let cmd = cmd.trim_end_matches(&['\r', '\n'][..]);
+            if !matches!(parse_builtin(cmd), Some(("record", _)) | Some(("play", _))) {
+                recorder.record(cmd);
+            }
+        }
         self.actq.push_back(action);
     }
 
+    /// 有暂存的世界文本时返回距下个处理周期的时间
+    pub fn world_backlog_wait(&self) -> Option<Duration> {
+        self.frames.wait(Instant::now())
+    }
+
+    /// 继续处理暂存的世界文本
+    pub fn resume_world_lines(&mut self) {
+        self.actq.push_back(EngineAction::ProcessWorldLines(vec![]));
+    }
+
     /// 执行操作队列
     pub fn apply(&mut self) -> Vec<RuntimeOutput> {
         let mut output = OutputQueue::new();
@@ -159,6 +921,10 @@ impl Engine {
 
     /// 执行单个操作    
     fn run_action(&mut self, action: EngineAction, output: &mut OutputQueue) {
+        // 操作序号，用于调试时核对执行顺序
+        self.action_seq += 1;
+        log::trace!("action #{} {:?}", self.action_seq, action);
+        self.tracer.action(self.action_seq, &action);
         match action {
             EngineAction::SwitchCodec(code) => {
                 self.mud_codec.switch_codec(code);
@@ -166,7 +932,7 @@ impl Engine {
             EngineAction::CreateAlias(alias) => {
                 let name = alias.name.to_owned();
                 if let Err(alias) = self.create_alias(alias) {
-                    let err_lines = Lines::fmt_err(format!("创建别名失败：{:?}", alias));
+                    let err_lines = Lines::fmt_err(i18n::trf("err.create_alias", &[&format!("{:?}", alias)]));
                     for err_line in err_lines.into_vec() {
                         self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
                     }
@@ -189,7 +955,7 @@ impl Engine {
             EngineAction::CreateTrigger(trigger) => {
                 let name = trigger.name.to_owned();
                 if let Err(trigger) = self.create_trigger(trigger) {
-                    let err_lines = Lines::fmt_err(format!("创建触发器失败：{:?}", trigger));
+                    let err_lines = Lines::fmt_err(i18n::trf("err.create_trigger", &[&format!("{:?}", trigger)]));
                     for err_line in err_lines.into_vec() {
                         self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
                     }
@@ -209,10 +975,14 @@ impl Engine {
                     log::warn!("enable trigger group error {}", e);
                 }
             }
+            EngineAction::LimitTriggerGroup(group, window) => {
+                log::debug!("Limiting trigger group {}: {:?}", group, window);
+                self.trigger_windows.insert(group, window);
+            }
             EngineAction::CreateMxpTrigger(trigger) => {
                 let name = trigger.name.to_owned();
                 if let Err(trigger) = self.create_mxp_trigger(trigger) {
-                    let err_lines = Lines::fmt_err(format!("创建MXP触发器失败：{:?}", trigger));
+                    let err_lines = Lines::fmt_err(i18n::trf("err.create_mxp_trigger", &[&format!("{:?}", trigger)]));
                     for err_line in err_lines.into_vec() {
                         self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
                     }
@@ -232,6 +1002,24 @@ impl Engine {
                     log::warn!("enable MXP trigger group error {}", e);
                 }
             }
+            EngineAction::CreateTransformer(name, order) => {
+                self.transformers.add_script(&name, order);
+            }
+            EngineAction::DeleteTransformer(name) => {
+                if self.transformers.remove(&name) {
+                    if let Err(e) = self.delete_transformer_callback(&name) {
+                        log::warn!("delete transformer callback error {}", e);
+                    }
+                }
+            }
+            EngineAction::EnableTransformer(name, enabled) => {
+                if !self.transformers.enable(&name, enabled) {
+                    let err_lines = Lines::fmt_err(i18n::trf("err.transformer_not_found", &[&name]));
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
             EngineAction::CreateTimer(tm) => {
                 self.create_timer(tm);
             }
@@ -245,25 +1033,28 @@ impl Engine {
                     log::warn!("enable timer group error {}", e);
                 }
             }
+            EngineAction::EnableTimer(name, enabled) => {
+                log::debug!("Enabling timer {}, enabled={}", name, enabled);
+                self.timers.enable(&name, enabled);
+            }
+            EngineAction::ResetTimer(name) => {
+                if !self.timers.reset(&name) {
+                    log::warn!("reset timer error: timer {} not found", name);
+                }
+            }
+            EngineAction::PauseTimers(paused) => self.pause_timers(paused),
             EngineAction::ExecuteTimer(task) => {
-                match self.timers.remove(&task.value.name) {
-                    // 无法匹配，不做操作
-                    None => (),
-                    Some(tm) => {
-                        if let Some(uuid) = tm.uuid() {
-                            if uuid == task.value.uuid && tm.enabled() {
-                                // 仅当uuid匹配且调度器开启时，执行
-                                if let Err(e) = self.exec_timer(&task.value.name) {
-                                    log::warn!("execute timer error {}", e);
-                                }
-                                // 若非临时，需要将定时器重新调度
-                                if !tm.oneshot() {
-                                    self.timers.insert_at(tm, task.delay_until());
-                                } else {
-                                    log::debug!("Removing oneshot timer {}", tm.name);
-                                }
-                            }
-                        }
+                // 仅当uuid匹配、定时器开启且未暂停时执行，过期的任务不影响定时器
+                if self.timers.is_current(&task.value) {
+                    let tm = self.timers.remove(&task.value.name).unwrap();
+                    if let Err(e) = self.exec_timer(&task.value.name) {
+                        log::warn!("execute timer error {}", e);
+                    }
+                    // 若非临时，需要将定时器重新调度
+                    if !tm.oneshot() {
+                        self.timers.reschedule(tm, task.delay_until());
+                    } else {
+                        log::debug!("Removing oneshot timer {}", tm.name);
                     }
                 }
             }
@@ -272,32 +1063,229 @@ impl Engine {
                     log::warn!("load file error {}", e);
                 }
             }
+            EngineAction::ImportZmud(path) => {
+                if let Err(e) = self.import_zmud(&path) {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
             EngineAction::ExecuteUserOutput(output) => match output {
-                UserOutput::Cmd(cmd) => self.process_user_cmd(cmd),
+                UserOutput::Cmd(cmd) => self.process_user_cmd(cmd, &[]),
                 UserOutput::Script(script) => self.process_user_script(script),
             },
+            EngineAction::ExecuteAliasCmd(cmd, chain) => self.process_user_cmd(cmd, &chain),
+            EngineAction::WaitAliasCmd(delay, cmd, chain) => {
+                let wait = match self.pacer.as_mut() {
+                    Some(pacer) => pacer.offer_wait(delay, cmd, chain),
+                    None => Some(Held::Wait(delay, cmd, chain)),
+                };
+                if let Some(Held::Wait(delay, cmd, chain)) = wait {
+                    self.start_wait(delay, cmd, chain);
+                }
+            }
             EngineAction::ParseWorldBytes(bs) => {
                 if let Err(e) = self.parse_world_bytes(bs) {
                     log::warn!("parse raw bytes error {}", e);
                 }
             }
+            EngineAction::UpdateProtocols(protocols) => {
+                let prev = self.protocols.write().unwrap().replace(protocols);
+                // 服务器接管回显时通常在输入密码，通知界面不记录历史
+                let secret = protocols.contains(Protocols::ECHO);
+                if prev.map(|p| p.contains(Protocols::ECHO)).unwrap_or(false) != secret {
+                    output.push(RuntimeOutput::SecretInput(secret));
+                }
+                // 仅在首次协商完成时显示
+                if prev.is_none() {
+                    self.exec_protocols();
+                }
+            }
+            EngineAction::UpdateProbe(report) => {
+                if let Err(e) = self.update_probe(report) {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
+            EngineAction::WorldDisconnected => self.connected = false,
+            EngineAction::ManageSession(cmd) => output.push(RuntimeOutput::ManageSession(cmd)),
+            EngineAction::UpdateTerm(term) => output.push(RuntimeOutput::UpdateTerm(term)),
+            EngineAction::FetchBundle(url) => output.push(RuntimeOutput::FetchBundle(url)),
+            EngineAction::BundleFetched(url, res) => {
+                if let Err(e) = self.bundle_fetched(&url, res) {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
+            EngineAction::Reconnect => {
+                self.connected = false;
+                self.send_note(i18n::tr("world.reconnecting"));
+                output.push(RuntimeOutput::Reconnect);
+            }
+            EngineAction::WorldConnected => {
+                self.connected = true;
+                if let Some(queue) = self.offline_queue.as_ref().filter(|q| !q.is_empty()) {
+                    self.send_note(i18n::trf("offline.pending", &[&queue.len()]));
+                }
+            }
+            EngineAction::FlushOfflineQueue => {
+                if let Err(e) = self.flush_offline_queue() {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
+            // 有暂存的世界文本时排在其后，按到达顺序处理
+            action @ EngineAction::ReceiveMsdp(_) | action @ EngineAction::ReceiveGmcp(_)
+                if self.frames.pending() > 0 =>
+            {
+                self.frames.push_oob(action)
+            }
+            EngineAction::ReceiveMsdp(bs) => self.receive_oob(|engine| engine.exec_msdp(&bs)),
+            EngineAction::ReceiveGmcp(bs) => self.receive_oob(|engine| engine.exec_gmcp(&bs)),
+            EngineAction::SetGroupMeta(group, meta) => self.group_metas.set(group, meta),
+            EngineAction::DeleteGroup(group) => {
+                if let Err(e) = self.delete_group(&group) {
+                    log::warn!("delete group error {}", e);
+                }
+            }
+            EngineAction::PlaySound(file, volume) => {
+                if let Err(e) = self.play_sound(None, &file, volume) {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            }
+            EngineAction::ObserveTriggerGroup(group, observe, samples) => {
+                self.observer.set(&group, observe, samples)
+            }
+            EngineAction::StartLog(file, opts) => match self.start_log(&file, opts) {
+                Ok(path) => self.send_note(i18n::trf("log.started", &[&path.display()])),
+                Err(e) => {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            },
+            EngineAction::ReloadScript => match self.reload() {
+                Ok(()) => self.send_note(i18n::trf("reload.done", &[&self.loaded.len()])),
+                Err(e) => {
+                    let err_lines = Lines::fmt_err(e.to_string());
+                    for err_line in err_lines.into_vec() {
+                        self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                    }
+                }
+            },
+            EngineAction::StopLog => {
+                if let Some(logger) = self.logger.take() {
+                    self.send_note(i18n::trf("log.stopped", &[&logger.path().display()]));
+                }
+            }
+            EngineAction::SetRepeatDeny(patterns) => match RegexSet::new(&patterns) {
+                Ok(deny) => self.repeat_deny = deny,
+                Err(e) => log::warn!("invalid repeat deny pattern {}", e),
+            },
+            EngineAction::FlashCmd(cmd) => output.push(RuntimeOutput::FlashCmd(cmd)),
+            EngineAction::EvalRepl(code) => output.push(RuntimeOutput::ToRepl(self.eval_repl(&code))),
+            EngineAction::CancelRepl => {
+                if self.repl.is_pending() {
+                    self.repl.cancel();
+                    output.push(RuntimeOutput::ToRepl(Lines::from(vec![Line::fmt_note(i18n::tr("repl.cancelled"))])));
+                }
+            }
+            EngineAction::ReadKey(id, prompt) => {
+                self.read_key = Some(id);
+                output.push(RuntimeOutput::ReadKey(Some(prompt)));
+            }
+            EngineAction::KeyRead(key) => {
+                if self.read_key.take().is_some() {
+                    self.exec_callback(GLOBAL_READKEY_CALLBACK, key);
+                }
+            }
+            EngineAction::ShowMenu(title, items) => {
+                output.push(RuntimeOutput::ShowMenu(title, items));
+            }
+            EngineAction::MenuChosen(idx) => self.exec_callback(GLOBAL_MENU_CALLBACK, idx),
+            EngineAction::KeyReadTimeout(id) => {
+                // 已读取按键或被新的等待替换时忽略
+                if self.read_key.as_ref() == Some(&id) {
+                    self.read_key = None;
+                    output.push(RuntimeOutput::ReadKey(None));
+                    self.exec_callback(GLOBAL_READKEY_CALLBACK, None::<String>);
+                }
+            }
             EngineAction::ProcessWorldLines(lines) => {
                 // 这里可能产生递归调用
                 self.process_world_lines(lines, output);
             }
             // 所有IO输出必定经过以下两个操作
             EngineAction::SendLineToUI(line, rawline) => {
-                // output.send_styled_line(line);
+                self.open_line = if line.ended() {
+                    None
+                } else {
+                    let mut text = self.open_line.take().filter(|_| !line.returns()).unwrap_or_default();
+                    text.push_str(&line.plain_text());
+                    Some(text)
+                };
+                let lineno = self.scrollback.push_line(line.clone());
                 if let Some(rawline) = rawline {
-                    output.send_line(rawline, line);
+                    output.send_line(rawline, line, lineno);
                 } else {
-                    output.send_styled_line(line);
+                    output.send_styled_line(line, lineno);
                 }
             }
+            EngineAction::SendLineToWindow(target, line) => output.send_window_line(target, line),
+            EngineAction::SendStatusToUI(lines) => output.send_status(lines),
+            EngineAction::SendRawToUI(rawline) => output.send_raw_line(rawline),
             EngineAction::SendToServer(cmd) => {
-                output.send_cmd(cmd, self.mud_codec.encoder());
+                let cmd = match self.pacer.as_mut() {
+                    Some(pacer) => match pacer.offer(cmd) {
+                        Some(cmd) => cmd,
+                        None => return,
+                    },
+                    None => cmd,
+                };
+                self.send_server_cmd(cmd, output);
+            }
+            EngineAction::SendHeldToServer(items) => {
+                for item in items {
+                    match item {
+                        Held::Cmd(cmd) => self.send_server_cmd(cmd, output),
+                        Held::Wait(delay, cmd, chain) => self.start_wait(delay, cmd, chain),
+                    }
+                }
+            }
+            EngineAction::SendNoEcho(cmd) => self.send_server_cmd(cmd, output),
+            EngineAction::SendRawToServer(bs) => output.send_bytes(bs),
+        }
+    }
+
+    fn send_server_cmd(&mut self, cmd: String, output: &mut OutputQueue) {
+        let cmd = match self.encode_fallback.apply(self.mud_codec.encoder(), cmd) {
+            Ok(cmd) => cmd,
+            Err(chars) => {
+                let chars: String = chars.into_iter().collect();
+                let err_lines = Lines::fmt_err(i18n::trf("err.unencodable", &[&chars]));
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+                return;
+            }
+        };
+        if self.status_parser {
+            if let Some(kind) = StatusKind::from_cmd(&cmd) {
+                self.status = Some(StatusCapture::new(kind));
             }
         }
+        output.send_cmd(cmd, self.mud_codec.encoder());
     }
 
     /// 执行任意脚本，用户可通过UI界面直接输入脚本
@@ -318,7 +1306,7 @@ impl Engine {
         let wildcards = alias.captures(&text)?;
         let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_ALIAS_CALLBACKS)?;
         let func: mlua::Function = callbacks.get(&alias.name[..])?;
-        func.call((name, text, wildcards))?;
+        func.call::<_, ()>((name, text, wildcards))?;
         Ok(())
     }
 
@@ -333,6 +1321,8 @@ impl Engine {
     fn delete_alias_callback(&mut self, name: &str) -> Result<()> {
         let alias_callbacks: mlua::Table = self.lua.globals().get(GLOBAL_ALIAS_CALLBACKS)?;
         alias_callbacks.set(name, mlua::Value::Nil)?;
+        let origins: mlua::Table = self.lua.globals().get(GLOBAL_ALIAS_ORIGINS)?;
+        origins.set(name, mlua::Value::Nil)?;
         Ok(())
     }
 
@@ -349,6 +1339,9 @@ impl Engine {
         log::debug!("Enabling alias group {}, enabled={}", group, enabled);
         let n = self.aliases.enable_group(group, enabled);
         log::trace!("{} aliases effected", n);
+        if !enabled {
+            self.clear_group_vars(group);
+        }
         Ok(())
     }
 
@@ -358,13 +1351,39 @@ impl Engine {
         trigger: &Trigger,
         text: String,
         styles: Vec<InlineStyle>,
+        ctx: Option<&TriggerContext>,
     ) -> Result<()> {
         log::debug!("Executing trigger {}", trigger.name);
         log::trace!("matched text={}", text);
         let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TRIGGER_CALLBACKS)?;
         let func: mlua::Function = callbacks.get(&trigger.name[..])?;
         let wildcards = trigger.captures(&text)?;
-        func.call((trigger.name.to_owned(), text, wildcards, styles))?;
+        let styles = LineStyles {
+            captures: trigger.capture_styles(&text, &styles),
+            spans: styles,
+        };
+        let res = match ctx {
+            // 开启上下文的触发器额外接收上下文参数
+            Some(ctx) if trigger.extra.context() => func.call::<_, ()>((
+                trigger.name.to_owned(),
+                text,
+                wildcards,
+                styles,
+                ctx.clone(),
+            )),
+            _ => func.call::<_, ()>((trigger.name.to_owned(), text, wildcards, styles)),
+        };
+        if let Err(e) = res {
+            // 附带触发器的定义文件
+            let origins: mlua::Table = self.lua.globals().get(GLOBAL_TRIGGER_ORIGINS)?;
+            if let Some(file) = origins.get::<_, Option<String>>(&trigger.name[..])? {
+                return Err(Error::RuntimeError(i18n::trf(
+                    "err.trigger_failed",
+                    &[&trigger.name, &file, &e],
+                )));
+            }
+            return Err(e.into());
+        }
         Ok(())
     }
 
@@ -379,6 +1398,8 @@ impl Engine {
     fn delete_trigger_callback(&mut self, name: &str) -> Result<()> {
         let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TRIGGER_CALLBACKS)?;
         callbacks.set(name, mlua::Value::Nil)?;
+        let origins: mlua::Table = self.lua.globals().get(GLOBAL_TRIGGER_ORIGINS)?;
+        origins.set(name, mlua::Value::Nil)?;
         Ok(())
     }
 
@@ -393,11 +1414,35 @@ impl Engine {
     // 启用/禁用触发器组
     fn enable_trigger_group(&mut self, group: &str, enabled: bool) -> Result<()> {
         log::debug!("Enabling trigger group {}, enabled={}", group, enabled);
+        // 重新启用或禁用时取消原有的启用窗口
+        self.trigger_windows.remove(group);
         let n = self.triggers.enable_group(group, enabled);
         log::trace!("{} triggers effected", n);
+        if !enabled {
+            self.clear_group_vars(group);
+        }
         Ok(())
     }
 
+    // 禁用启用窗口已到期的触发器组
+    fn expire_trigger_groups(&mut self) {
+        if self.trigger_windows.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .trigger_windows
+            .iter()
+            .filter(|(_, w)| w.expired(now))
+            .map(|(group, _)| group.to_owned())
+            .collect();
+        for group in expired {
+            log::debug!("Trigger group {} expired", group);
+            self.trigger_windows.remove(&group);
+            self.triggers.enable_group(&group, false);
+        }
+    }
+
     // 执行MXP触发器
     fn exec_mxp_trigger(&self, trigger: &MxpTrigger, elem: &Element) -> Result<()> {
         log::debug!("Executing MXP trigger {}", trigger.name);
@@ -410,7 +1455,7 @@ impl Engine {
             ModelCaptures::default()
         };
         let value = elem.to_lua(&self.lua)?;
-        func.call((trigger.name.to_owned(), value, wildcards))?;
+        func.call::<_, ()>((trigger.name.to_owned(), value, wildcards))?;
         Ok(())
     }
 
@@ -441,6 +1486,9 @@ impl Engine {
         log::debug!("Enabling MXP trigger group {}, enabled={}", group, enabled);
         let n = self.mxp_triggers.enable_group(group, enabled);
         log::trace!("{} MXP triggers effected", n);
+        if !enabled {
+            self.clear_group_vars(group);
+        }
         Ok(())
     }
 
@@ -449,7 +1497,7 @@ impl Engine {
         log::debug!("Executing timer {}", name);
         let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TIMER_CALLBACKS)?;
         let func: mlua::Function = callbacks.get(name)?;
-        func.call(())?;
+        func.call::<_, ()>(())?;
         Ok(())
     }
 
@@ -464,6 +1512,8 @@ impl Engine {
     fn delete_timer_callback(&mut self, name: &str) -> Result<()> {
         let timer_callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TIMER_CALLBACKS)?;
         timer_callbacks.set(name, mlua::Value::Nil)?;
+        let origins: mlua::Table = self.lua.globals().get(GLOBAL_TIMER_ORIGINS)?;
+        origins.set(name, mlua::Value::Nil)?;
         Ok(())
     }
 
@@ -480,25 +1530,203 @@ impl Engine {
         log::debug!("Enabling timer group {}, enabled={}", group, enabled);
         let n = self.timers.enable_group(group, enabled);
         log::trace!("{} timers effected", n);
+        if !enabled {
+            self.clear_group_vars(group);
+        }
+        Ok(())
+    }
+
+    // 删除分组内的别名、触发器、MXP触发器及定时器，并清除分组变量
+    fn delete_group(&mut self, group: &str) -> Result<()> {
+        log::debug!("Deleting group {}", group);
+        for name in self.aliases.group_names(group) {
+            self.delete_alias(&name)?;
+        }
+        for name in self.triggers.group_names(group) {
+            self.delete_trigger(&name)?;
+        }
+        for name in self.mxp_triggers.group_names(group) {
+            self.delete_mxp_trigger(&name)?;
+        }
+        for name in self.timers.group_names(group) {
+            self.delete_timer(&name)?;
+        }
+        self.trigger_windows.remove(group);
+        self.clear_group_vars(group);
         Ok(())
     }
 
+    // 清除分组变量
+    fn clear_group_vars(&self, group: &str) {
+        let n = self.group_vars.remove_group(group);
+        if n > 0 {
+            log::debug!("{} variables of group {} cleared", n, group);
+        }
+    }
+
     // 加载外部文件
     fn load_file(&mut self, path: &str) -> Result<()> {
-        log::debug!("Loading file {}", path);
-        let mut file = File::open(path)?;
+        let path = self.data_dir.script_path(path);
+        log::debug!("Loading file {}", path.display());
+        self.load_script(path, "LoadFile")
+    }
+
+    /// 导入zMUD/CMUD导出文件，命令中的%1..%9按捕获替换，未指定类名时归入zmud组
+    ///
+    /// 无法转换的定义逐条提示所在行号，触发器名称包含文件名，导入多个文件时互不覆盖
+    fn import_zmud(&mut self, path: &str) -> Result<()> {
+        let path = self.data_dir.script_path(path);
+        let file = path.display().to_string();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
         let mut text = String::new();
-        file.read_to_string(&mut text)?;
-        self.lua.load(&text).exec()?;
+        File::open(&path)?.read_to_string(&mut text)?;
+        let import = zmud::convert(&text);
+        let (mut triggers, mut aliases) = (0, 0);
+        for rule in &import.rules {
+            let group = if rule.group.is_empty() { ZMUD_GROUP } else { &rule.group };
+            let (callbacks, origins) = match rule.kind {
+                RuleKind::Trigger => (GLOBAL_TRIGGER_CALLBACKS, GLOBAL_TRIGGER_ORIGINS),
+                RuleKind::Alias => (GLOBAL_ALIAS_CALLBACKS, GLOBAL_ALIAS_ORIGINS),
+            };
+            let name = match rule.kind {
+                RuleKind::Trigger => format!("zmud-trigger-{}-{}", stem, rule.lineno),
+                RuleKind::Alias => format!("zmud-alias-{}", rule.name),
+            };
+            let callback = create_send_callback(&self.lua, &self.tmpq, &rule.send)?;
+            let callbacks: mlua::Table = self.lua.globals().get(callbacks)?;
+            callbacks.set(&name[..], callback)?;
+            let origins: mlua::Table = self.lua.globals().get(origins)?;
+            origins.set(&name[..], format!("{}:{}", file, rule.lineno))?;
+            match rule.kind {
+                RuleKind::Trigger => {
+                    let trigger = Trigger::builder()
+                        .name(&name)
+                        .group(group)
+                        .pattern(&rule.pattern)?
+                        .enabled(true)
+                        .build();
+                    self.tmpq.push(EngineAction::CreateTrigger(trigger));
+                    triggers += 1;
+                }
+                RuleKind::Alias => {
+                    let alias = Alias::builder()
+                        .name(&name)
+                        .group(group)
+                        .pattern(&rule.pattern)?
+                        .enabled(true)
+                        .build();
+                    self.tmpq.push(EngineAction::CreateAlias(alias));
+                    aliases += 1;
+                }
+            }
+        }
+        self.send_note(i18n::trf(
+            "zmud.imported",
+            &[&triggers, &aliases, &file, &import.warnings.len()],
+        ));
+        for (lineno, warning) in &import.warnings {
+            self.send_note(i18n::trf("zmud.warning", &[lineno, warning]));
+        }
+        Ok(())
+    }
+
+    /// 加载并执行脚本，记录加载来源
+    ///
+    /// 以文件路径作为代码块名称，错误信息中将包含文件名与行号；
+    /// 加载期间创建的触发器记录其定义文件
+    fn load_script(&mut self, path: std::path::PathBuf, via: &'static str) -> Result<()> {
+        let file = path.display().to_string();
+        let res = (|| -> Result<()> {
+            let mut text = String::new();
+            File::open(&path)?.read_to_string(&mut text)?;
+            let globals = self.lua.globals();
+            let prev: mlua::Value = globals.get(GLOBAL_LOADING_FILE)?;
+            globals.set(GLOBAL_LOADING_FILE, &file[..])?;
+            let res = self.lua.load(&text).set_name(&format!("@{}", file))?.exec();
+            globals.set(GLOBAL_LOADING_FILE, prev)?;
+            res?;
+            Ok(())
+        })();
+        self.loaded.push(LoadRecord {
+            file,
+            via,
+            error: res.as_ref().err().map(|e| e.to_string()),
+        });
+        res
+    }
+
+    /// 按文件名顺序加载自动加载目录中的*.lua，单个文件出错时继续加载其余文件
+    ///
+    /// 返回加载的全部文件及出错的文件与错误，目录不存在时不加载
+    fn load_autoload_dir(&mut self) -> Result<(Vec<String>, Vec<(String, String)>)> {
+        let (mut files, mut failed) = (Vec::new(), Vec::new());
+        if self.autoload_dir.is_empty() {
+            return Ok((files, failed));
+        }
+        let dir = self.data_dir.script_path(&self.autoload_dir);
+        if !dir.is_dir() {
+            return Ok((files, failed));
+        }
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().map(|ext| ext == "lua").unwrap_or(false) {
+                paths.push(path);
+            }
+        }
+        // 按文件名的字节序排列，加载顺序与文件系统无关
+        paths.sort();
+        for path in paths {
+            let file = path.display().to_string();
+            log::info!("loading autoload script '{}'", file);
+            if let Err(e) = self.load_script(path, "autoload") {
+                failed.push((file.clone(), e.to_string()));
+            }
+            files.push(file);
+        }
+        Ok((files, failed))
+    }
+
+    /// 删除定义于指定脚本文件中的触发器、别名及定时器
+    fn unload_script_models(&mut self, files: &HashSet<String>) -> Result<()> {
+        for table in &[GLOBAL_TRIGGER_ORIGINS, GLOBAL_ALIAS_ORIGINS, GLOBAL_TIMER_ORIGINS] {
+            let mut names = Vec::new();
+            let origins: mlua::Table = self.lua.globals().get(*table)?;
+            for pair in origins.pairs::<String, String>() {
+                let (name, file) = pair?;
+                if files.contains(&file) {
+                    names.push(name);
+                }
+            }
+            drop(origins);
+            for name in names {
+                match *table {
+                    GLOBAL_TRIGGER_ORIGINS => self.delete_trigger(&name)?,
+                    GLOBAL_ALIAS_ORIGINS => self.delete_alias(&name)?,
+                    _ => self.delete_timer(&name)?,
+                }
+            }
+        }
         Ok(())
     }
 
     /// 这是对原始字节流的处理，这里仅解码并处理换行
     fn parse_world_bytes(&mut self, bs: Vec<u8>) -> Result<()> {
         let s = self.mud_codec.decode(&bs);
-        if let Some(logger) = self.logger.as_mut() {
-            logger.write_all(s.as_bytes())?;
+        // 日志写入或轮转失败时停止记录，文本照常处理
+        if let Some(Err(e)) = self.logger.as_mut().map(|logger| logger.write_text(&s)) {
+            if let Some(logger) = self.logger.take() {
+                log::warn!("write world log {} error {}", logger.path().display(), e);
+                let err_lines = Lines::fmt_err(i18n::trf("err.log_write", &[&logger.path().display(), &e]));
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+            }
         }
+        let s = self.newlines.normalize(&s);
 
         // here just split into lines
         let mut lines = Vec::new();
@@ -520,33 +1748,147 @@ impl Engine {
 
     // 处理世界文本
     fn process_world_line(&mut self, raw: RawLine) {
-        self.parser.fill(raw.as_ref());
+        self.crash_lines.push(raw.as_ref().to_owned());
+        // 覆盖模式下以回车开头的行替换当前未结束的行，回车本身不参与解析及匹配
+        let returns = self.newlines.mode() == conf::NewlineMode::Overwrite && newline::starts_with_return(raw.as_ref());
+        if returns {
+            self.cache.write().unwrap().discard_open();
+            self.prompt_fired.clear();
+            self.parser.fill(&raw.as_ref()[1..]);
+        } else {
+            self.parser.fill(raw.as_ref());
+        }
         let mut styled = vec![];
         let mut mxp_events = vec![];
+        // 该行是否包含状态栏内容
+        let mut captured = false;
         loop {
             match self.parser.next() {
                 Element::None => {
                     break;
                 }
                 Element::Span(span) => {
+                    // 状态栏中的文本不进入主窗格
+                    let span = match self.status_bar.as_mut() {
+                        Some(bar) => {
+                            let span = bar.capture(span);
+                            captured |= span.is_none();
+                            span
+                        }
+                        None => Some(span),
+                    };
                     // handle accumulation of mxp events
-                    styled.push(span);
+                    styled.extend(span);
+                }
+                Element::Cursor(op) => {
+                    if let Some(bar) = self.status_bar.as_mut() {
+                        bar.apply(op);
+                        captured = true;
+                    }
                 }
                 other => {
                     mxp_events.push(other);
                 }
             }
         }
+        *self.mxp_mode.write().unwrap() = self.parser.mxp_mode();
+        if let Some(lines) = self.status_bar.as_mut().and_then(|bar| bar.take_changed()) {
+            self.tmpq.push(EngineAction::SendStatusToUI(lines));
+        }
+        // 除换行外整行均为状态栏内容
+        if captured
+            && styled
+                .iter()
+                .all(|s| s.content.trim_end_matches(&['\r', '\n'][..]).is_empty())
+        {
+            self.tmpq.push(EngineAction::SendRawToUI(raw));
+            return;
+        }
         let styled = Line::new(styled);
+        let (styled, raw) = self.transform_line(styled, raw);
+        let (styled, raw) = match self.handle_media(styled, raw) {
+            Some(line) => line,
+            None => return,
+        };
+        self.tracer.begin(styled.plain_text());
+        self.observe_queue(&styled.plain_text());
+        // 提示符为未以换行结束的行，等待用户输入
+        if styled.ended() {
+            self.capture_status(&styled.plain_text());
+        } else {
+            self.parse_prompt(&styled.plain_text());
+        }
+        // 仅对完整的行进行路由
+        if !self.router.is_empty() && styled.ended() {
+            let text = styled.plain_text();
+            match self.router.route(&text).cloned() {
+                None => (),
+                Some(Route::Gag) => {
+                    log::trace!("line gagged: {}", text);
+                    return;
+                }
+                Some(Route::Log(target)) => {
+                    if let Err(e) = self.router.write_log(&target, &text) {
+                        log::warn!("write route log {} error {}", target, e);
+                    }
+                    return;
+                }
+                Some(Route::Window(target)) => {
+                    // 同时保留在主窗格中，窗口由UI按名称显示
+                    log::trace!("line routed to window {}: {}", target, text);
+                    let styled = if returns { styled.with_return() } else { styled };
+                    self.tmpq
+                        .push(EngineAction::SendLineToWindow(target, styled.clone()));
+                    self.tmpq
+                        .push(EngineAction::SendLineToUI(styled, Some(raw)));
+                    return;
+                }
+            }
+        }
         // 添加进文本缓存，供触发器进行匹配
-        self.cache.push_line(&styled);
+        self.cache.write().unwrap().push_line(&styled);
+        // 使用is_match预先匹配
+        // 普通触发器仅匹配完整的行，提示符触发器在每次收到数据时匹配未结束的行，
+        // 同一行中只执行一次
+        let ended = self.cache.read().unwrap().ended();
+        self.expire_trigger_groups();
+        let trs = self.triggers.trigger_all(&self.cache.read().unwrap());
+        // 仅当有触发器需要时才构造上下文
+        let ctx = if trs.iter().any(|(tr, ..)| tr.extra.context()) {
+            let lineno = self.scrollback.next_lineno();
+            Some(TriggerContext::new(lineno, now_millis(), &raw, &styled))
+        } else {
+            None
+        };
         // 推送到事件队列
+        let styled = if returns { styled.with_return() } else { styled };
         self.tmpq
             .push(EngineAction::SendLineToUI(styled, Some(raw)));
-        // 使用is_match预先匹配
-        let trs = self.triggers.trigger_all(&self.cache);
+        // 是否匹配了限次启用的触发器组
+        let mut consumed = false;
         for (tr, text, styles) in trs {
-            if let Err(e) = self.exec_trigger(tr, text, styles) {
+            if tr.extra.prompt() {
+                if !self.prompt_fired.insert(tr.name.to_owned()) {
+                    continue;
+                }
+            } else if !ended {
+                continue;
+            }
+            // 仅观察的分组只记录命中，不执行回调也不计入限次
+            if self.observer.record(&tr.group, &tr.name, &text) {
+                continue;
+            }
+            self.tracer.trigger(&tr.name);
+            if let Some(w) = self.trigger_windows.get_mut(&tr.group) {
+                // 同一行中已达到限次时，组内其余触发器不再执行
+                if w.expired(Instant::now()) {
+                    continue;
+                }
+                w.consume();
+                consumed = true;
+            }
+            if let Err(e) = self.exec_trigger(tr, text, styles, ctx.as_ref()) {
+                self.tracer.error(e.to_string());
                 let err_lines = Lines::fmt_err(e.to_string());
                 for err_line in err_lines.into_vec() {
                     self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
@@ -558,6 +1900,12 @@ impl Engine {
                     .push(EngineAction::DeleteTrigger(tr.name.to_owned()));
             }
         }
+        if consumed {
+            self.expire_trigger_groups();
+        }
+        if ended {
+            self.prompt_fired.clear();
+        }
         if !mxp_events.is_empty() {
             // 记录MXP事件
             log::debug!("MXP events: {:?}", mxp_events);
@@ -565,7 +1913,9 @@ impl Engine {
             for me in mxp_events {
                 let trs = self.mxp_triggers.trigger_all(&me);
                 for tr in trs {
+                    self.tracer.trigger(&tr.name);
                     if let Err(e) = self.exec_mxp_trigger(tr, &me) {
+                        self.tracer.error(e.to_string());
                         let err_lines = Lines::fmt_err(e.to_string());
                         for err_line in err_lines.into_vec() {
                             self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
@@ -581,54 +1931,317 @@ impl Engine {
         }
     }
 
-    // 处理多行世界文本
-    // 由于每一行都肯能触发脚本，改变后续文本的处理方式，因此需要在处理完
-    // 每一行以后，运行临时操作队列直到其清空，方可处理下一行
-    fn process_world_lines(
-        &mut self,
-        lines: impl IntoIterator<Item = RawLine>,
-        output: &mut OutputQueue,
-    ) {
-        for line in lines {
+    // 捕获状态界面，完成后更新变量并执行回调
+    fn capture_status(&mut self, text: &str) {
+        let status = match self.status.as_mut().map(|c| c.feed(text)) {
+            None | Some(Feed::Pending) => return,
+            Some(Feed::Expired) => {
+                log::debug!("status capture expired");
+                self.status = None;
+                return;
+            }
+            Some(Feed::Done(status)) => status,
+        };
+        self.status = None;
+        if let Err(e) = self.exec_status_callback(status) {
+            self.tracer.error(e.to_string());
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+    }
+
+    // 调用并清除一次性的回调，如等待按键及弹出菜单，取消或超时时参数为nil
+    fn exec_callback<T>(&mut self, name: &str, arg: Option<T>)
+    where
+        T: std::fmt::Debug + for<'lua> ToLua<'lua>,
+    {
+        log::debug!("Executing callback {} with {:?}", name, arg);
+        let res = self
+            .lua
+            .globals()
+            .get::<_, Option<mlua::Function>>(name)
+            .and_then(|func| {
+                self.lua.globals().set(name, mlua::Value::Nil)?;
+                match func {
+                    Some(func) => func.call::<_, ()>(arg),
+                    None => Ok(()),
+                }
+            });
+        if let Err(e) = res {
+            log::warn!("callback {} error {}", name, e);
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+    }
+
+    fn exec_status_callback(&self, status: Status) -> Result<()> {
+        let name = status.kind.name();
+        log::debug!("Executing status callback {}", name);
+        self.vars.insert_all(status.vars());
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_STATUS_CALLBACKS)?;
+        if let Some(func) = callbacks.get::<_, Option<mlua::Function>>(name)? {
+            func.call::<_, ()>(status.to_lua(&self.lua)?)?;
+        }
+        Ok(())
+    }
+
+    // 执行行转换器，文本改变时根据转换后的片段重新生成原始文本
+    fn transform_line(&mut self, line: Line, raw: RawLine) -> (Line, RawLine) {
+        if !self.transformers.active() {
+            return (line, raw);
+        }
+        let lua = &self.lua;
+        let (transformed, errors) = self
+            .transformers
+            .apply(line.clone(), |name, line| exec_transformer(lua, name, line));
+        for e in errors {
+            log::warn!("transformer error {}", e);
+            self.tracer.error(e.clone());
+            let err_lines = Lines::fmt_err(i18n::trf("err.transformer_failed", &[&e]));
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+        if transformed == line {
+            return (line, raw);
+        }
+        let raw = RawLine::new(transformed.spans().iter().map(|s| s.to_string()).collect::<String>());
+        (transformed, raw)
+    }
+
+    // 处理声音及音乐指令，开启隐藏时从文本中删除指令，仅含指令的行返回None
+    fn handle_media(&mut self, line: Line, raw: RawLine) -> Option<(Line, RawLine)> {
+        if !media::maybe_contains(&line.plain_text()) {
+            return Some((line, raw));
+        }
+        let mut directives = vec![];
+        let stripped = transform::map_spans(line.clone(), |text| {
+            let (text, found) = media::extract(text);
+            directives.extend(found);
+            Ok(text)
+        });
+        for directive in directives {
+            log::debug!("media directive {:?}", directive);
+            if let Err(e) = self.exec_media_directive(directive) {
+                self.tracer.error(e.to_string());
+                let err_lines = Lines::fmt_err(e.to_string());
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+            }
+        }
+        if !self.media_conf.gag {
+            return Some((line, raw));
+        }
+        let stripped = stripped.ok()?;
+        if stripped.plain_text().trim().is_empty() {
+            log::trace!("media directive line gagged");
+            return None;
+        }
+        let raw = RawLine::new(stripped.spans().iter().map(|s| s.to_string()).collect::<String>());
+        Some((stripped, raw))
+    }
+
+    fn exec_media_directive(&self, directive: MediaDirective) -> Result<()> {
+        if !directive.is_off() {
+            self.play_sound(directive.category.as_deref(), &directive.file, directive.volume)?;
+        }
+        let func: Option<mlua::Function> = self.lua.globals().get(GLOBAL_MEDIA_CALLBACK)?;
+        if let Some(func) = func {
+            func.call::<_, ()>(directive)?;
+        }
+        Ok(())
+    }
+
+    /// 以包名及数据调用匹配的GMCP回调，注册的包名同时匹配其下的子包
+    fn exec_gmcp(&self, bs: &[u8]) -> Result<()> {
+        let msg = match GmcpMessage::parse(bs) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("invalid gmcp message {}", e);
+                return Ok(());
+            }
+        };
+        log::debug!("Received gmcp package {}", msg.package);
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_GMCP_CALLBACKS)?;
+        for pair in callbacks.pairs::<String, mlua::Function>() {
+            let (name, func) = pair?;
+            if msg.matches(&name) {
+                let data = json::json_to_lua(&self.lua, &msg.data)?;
+                func.call::<_, ()>((msg.package.as_str(), data))?;
+            }
+        }
+        Ok(())
+    }
+
+    // 更新MSDP变量表，对值发生变化的变量调用OnMsdp注册的回调
+    fn exec_msdp(&self, bs: &[u8]) -> Result<()> {
+        let vars = match msdp::parse(bs) {
+            Ok(vars) => vars,
+            Err(e) => {
+                log::warn!("invalid msdp data {}", e);
+                return Ok(());
+            }
+        };
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_MSDP_CALLBACKS)?;
+        for (name, value) in vars {
+            let prev = self.msdp_vars.write().unwrap().insert(name.clone(), value.clone());
+            if prev.as_ref() == Some(&value) {
+                continue;
+            }
+            log::debug!("Msdp variable {} changed", name);
+            let func: Option<mlua::Function> = callbacks.get(name.as_str())?;
+            if let Some(func) = func {
+                let value = json::json_to_lua(&self.lua, &value)?;
+                func.call::<_, ()>((name.as_str(), value))?;
+            }
+        }
+        Ok(())
+    }
+
+    // 未配置播放命令时忽略
+    fn play_sound(&self, category: Option<&str>, file: &str, volume: Option<u32>) -> Result<()> {
+        match self.player.as_ref() {
+            Some(player) => player.play(category, file, volume),
+            None => {
+                log::debug!("no media player, sound {} ignored", file);
+                Ok(())
+            }
+        }
+    }
+
+    fn delete_transformer_callback(&self, name: &str) -> Result<()> {
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TRANSFORMER_CALLBACKS)?;
+        callbacks.set(name, mlua::Value::Nil)?;
+        Ok(())
+    }
+
+    // 处理多行世界文本
+    // 由于每一行都肯能触发脚本，改变后续文本的处理方式，因此需要在处理完
+    // 每一行以后，运行临时操作队列直到其清空，方可处理下一行
+    fn process_world_lines(
+        &mut self,
+        lines: impl IntoIterator<Item = RawLine>,
+        output: &mut OutputQueue,
+    ) {
+        // 覆盖模式下先在单独的回车处拆分
+        let lines: Vec<RawLine> = lines.into_iter().flat_map(|line| self.newlines.split_returns(line)).collect();
+        self.frames.extend(lines);
+        // 本周期处理量达到上限后，剩余的行留待后续周期
+        while let Some(frame) = self.frames.next(Instant::now()) {
+            let line = match frame {
+                Frame::Line(line) => line,
+                Frame::OutOfBand(EngineAction::ReceiveMsdp(bs)) => {
+                    self.receive_oob(|engine| engine.exec_msdp(&bs));
+                    self.apply_tmpq(output);
+                    continue;
+                }
+                Frame::OutOfBand(EngineAction::ReceiveGmcp(bs)) => {
+                    self.receive_oob(|engine| engine.exec_gmcp(&bs));
+                    self.apply_tmpq(output);
+                    continue;
+                }
+                Frame::OutOfBand(action) => {
+                    self.tmpq.push(action);
+                    self.apply_tmpq(output);
+                    continue;
+                }
+            };
             self.process_world_line(line);
             // 这里，每处理一行，都需要将操作立即执行
             // 否则可能导致先前行开启/关闭的触发器对后续行
             // 的不正确的影响。
             self.apply_tmpq(output);
+            self.tracer.end();
+        }
+        crash::record(self.crash_lines.drain(..), self.tracer.take_unreported());
+    }
+
+    // 处理带外消息，错误显示在界面上
+    fn receive_oob(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) {
+        if let Err(e) = f(self) {
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
         }
     }
 
-    // 执行临时队列，直到队列为空
+    /// 执行临时队列中的操作
+    ///
+    /// 按深度优先的顺序执行：操作执行中产生的新操作（脚本中的Send、别名展开、触发器及定时器回调等）
+    /// 按产生顺序紧随该操作执行，先于排在该操作之后的操作。因此发往服务器的命令与其逻辑顺序一致，
+    /// 如"a;b"中别名a发送的命令总是先于b发送。嵌套超过限制时剩余操作延后到下次执行
     fn apply_tmpq(&mut self, output: &mut OutputQueue) {
         const ITER_CNT: usize = 30;
-        let mut i = 0;
-        loop {
-            if self.tmpq.is_empty() {
-                log::trace!("apply tmp action queue with iteration={}", i);
-                return;
-            }
-            if i >= ITER_CNT {
-                log::error!("reach iteration limit {} on tmp action queue processing", i);
-                log::warn!("tmpq.len={}", self.tmpq.len());
-                log::warn!("tmpq={:?}", self.tmpq);
+        // 待执行的操作及其嵌套深度
+        let mut pending: VecDeque<(usize, EngineAction)> =
+            self.tmpq.drain_all().into_iter().map(|action| (0, action)).collect();
+        while let Some((depth, action)) = pending.pop_front() {
+            if depth >= ITER_CNT {
+                log::error!("reach iteration limit {} on tmp action queue processing", depth);
+                log::warn!("tmpq.len={}", pending.len() + 1);
+                log::warn!("tmpq={:?}", pending);
+                self.tmpq.push(action);
+                for (_, action) in pending {
+                    self.tmpq.push(action);
+                }
+                let err_lines = Lines::fmt_err(i18n::trf("err.iteration_limit", &[&ITER_CNT]));
+                for err_line in err_lines.into_vec() {
+                    let lineno = self.scrollback.push_line(err_line.clone());
+                    output.send_styled_line(err_line, lineno);
+                }
                 return;
             }
-            let actions = self.tmpq.drain_all();
-            for action in actions {
-                self.run_action(action, output);
+            self.run_action(action, output);
+            for action in self.tmpq.drain_all().into_iter().rev() {
+                pending.push_front((depth + 1, action));
             }
-            i += 1;
         }
     }
 
     /// 处理用户命令，拆分并做别名转换
-    fn process_user_cmd(&mut self, mut cmd: String) {
+    ///
+    /// chain为触发该命令的别名调用链，用户直接输入时为空
+    fn process_user_cmd(&mut self, mut cmd: String, chain: &[String]) {
         // todo: might be other built-in command
         if cmd.ends_with("\r\n") {
             cmd.truncate(cmd.len() - 2);
         } else if cmd.ends_with('\n') {
             cmd.truncate(cmd.len() - 1);
         }
+        // 等待标记之后的命令延迟执行
+        if let Some((head, args, rest)) = self.split_wait(&cmd) {
+            let (head, args, mut rest) = (head.to_owned(), args.to_owned(), rest.to_owned());
+            if cmd.starts_with(' ') {
+                rest.insert(0, ' ');
+            }
+            if !head.trim().is_empty() {
+                self.process_user_cmd(head, chain);
+            }
+            if let Err(e) = self.schedule_cmd(&args, rest, chain) {
+                let err_lines = Lines::fmt_err(e.to_string());
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+            }
+            return;
+        }
+        // 以空格开头及关闭回显时输入的命令视为敏感命令，不回显也不写入离线队列文件
+        let secret = cmd.starts_with(' ') || self.echo_off();
+        // 以空格开头的命令不回显
+        let echo = match self.echo {
+            Some(_) if cmd.starts_with(' ') => {
+                cmd.remove(0);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
         let cmds = self.translate_cmds(cmd, self.cmd_delim, self.send_empty_cmd);
         if cmds.is_empty() {
             // 对于空字符，推送空行
@@ -637,15 +2250,45 @@ impl Engine {
         }
         for cmd in cmds {
             match cmd {
-                PostCmd::Raw(mut s) => {
-                    if !s.ends_with('\n') {
-                        s.push('\n');
+                PostCmd::Raw(s) => {
+                    // 断线期间暂存命令，重连并登录后发送
+                    if !self.connected && self.offline_queue.is_some() {
+                        self.queue_offline(QueuedCmd::new(s, !secret));
+                        continue;
                     }
-                    // self.outq.send_cmd(s);
-                    self.tmpq.push(EngineAction::SendToServer(s));
+                    self.send_raw_cmd(s, echo);
                 }
                 PostCmd::Alias { name, text } => {
-                    if let Err(e) = self.exec_alias(name, text) {
+                    let mut chain = chain.to_vec();
+                    chain.push(name.to_owned());
+                    if chain.len() > self.max_alias_depth {
+                        let err_lines = Lines::fmt_err(i18n::trf(
+                            "err.alias_depth",
+                            &[&self.max_alias_depth, &chain.join(" -> ")],
+                        ));
+                        for err_line in err_lines.into_vec() {
+                            self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                        }
+                        continue;
+                    }
+                    // 标记别名回调中发送的命令，以追踪嵌套深度
+                    let start = self.tmpq.len();
+                    let res = self.exec_alias(name, text);
+                    self.tmpq.map_since(start, |action| match action {
+                        EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)) => {
+                            EngineAction::ExecuteAliasCmd(cmd, chain.clone())
+                        }
+                        other => other,
+                    });
+                    if let Err(e) = res {
+                        let err_lines = Lines::fmt_err(e.to_string());
+                        for err_line in err_lines.into_vec() {
+                            self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                        }
+                    }
+                }
+                PostCmd::Builtin { name, args } => {
+                    if let Err(e) = self.exec_builtin(&name, &args) {
                         let err_lines = Lines::fmt_err(e.to_string());
                         for err_line in err_lines.into_vec() {
                             self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
@@ -656,316 +2299,3639 @@ impl Engine {
         }
     }
 
-    /// 处理用户脚本
-    fn process_user_script(&mut self, script: String) {
-        if let Err(e) = self.exec_script(&script) {
-            let err_lines = Lines::fmt_err(e.to_string());
-            for err_line in err_lines.into_vec() {
-                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+    // 回显并经发送节流器发送原始命令
+    fn send_raw_cmd(&mut self, mut cmd: String, echo: bool) {
+        if echo {
+            self.echo_cmd(&cmd);
+        }
+        if !cmd.ends_with('\n') {
+            cmd.push('\n');
+        }
+        self.tmpq.push(EngineAction::SendToServer(cmd));
+    }
+
+    /// 回显命令，回显文本不经过触发器
+    ///
+    /// 之前的行未结束时，按设置合并到该行，或先结束该行再另起一行回显
+    fn echo_cmd(&mut self, cmd: &str) {
+        if let Some(echo) = self.echo.as_ref() {
+            let open = self.open_line.take();
+            let merge = echo.merge && open.as_deref().map(|t| self.is_prompt(t)).unwrap_or(false);
+            let text = if merge {
+                cmd.to_owned()
+            } else {
+                format!("{}{}", echo.prefix, cmd)
+            };
+            let line_break = open.is_some() && !merge;
+            if echo.log {
+                if let Some(logger) = self.logger.as_mut() {
+                    let res = if line_break {
+                        logger.write_text(&format!("\n{}\n", text))
+                    } else {
+                        logger.write_text(&format!("{}\n", text))
+                    };
+                    if let Err(e) = res {
+                        log::warn!("write echo to log error {}", e);
+                    }
+                }
+            }
+            if line_break {
+                self.tmpq.push(EngineAction::SendLineToUI(Line::fmt_raw(""), None));
             }
+            self.tmpq.push(EngineAction::SendLineToUI(
+                Line::fmt_with_style(text, echo.style),
+                None,
+            ));
         }
     }
 
-    /// 改写命令，根据换行与分隔符切分命名，并进行别名匹配与替换
-    fn translate_cmds(&self, cmd: String, delim: char, send_empty_cmd: bool) -> Vec<PostCmd> {
-        if cmd.is_empty() {
-            return vec![];
+    // 未配置提示符解析时，所有未结束的行均视为提示符
+    fn is_prompt(&self, text: &str) -> bool {
+        match self.prompt_parser.as_ref() {
+            Some(parser) => parser.parse(text).is_some(),
+            None => true,
         }
-        let raw_lines: Vec<String> = cmd
-            .split(|c| c == '\n' || c == delim)
-            .filter(|s| send_empty_cmd || !s.is_empty())
-            .map(|s| s.to_owned())
+    }
+
+    /// 执行内置命令
+    fn exec_builtin(&mut self, name: &str, args: &str) -> Result<()> {
+        log::debug!("Executing builtin command #{} {}", name, args);
+        match name {
+            "manage" => self.exec_manage(args),
+            "record" => self.exec_record(args),
+            "play" => self.exec_play(args),
+            "confirm" => self.exec_confirm(),
+            "fetch" => self.exec_fetch(args),
+            "reg" => self.exec_reg(),
+            "yank" => self.exec_yank(args),
+            "dump" => self.exec_dump(args),
+            "flushvars" => self.exec_flushvars(),
+            "mark" => self.exec_mark(args),
+            "marks" => self.exec_marks(args),
+            "jump" => self.exec_jump(args),
+            "trace" => self.exec_trace(args),
+            "transformers" => self.exec_transformers(),
+            "loadorder" => self.exec_loadorder(),
+            "reload" => self.exec_reload(args),
+            "stats" => self.exec_stats(),
+            "queue" => self.exec_queue(args),
+            "offline" => self.exec_offline(args),
+            "session" => self.exec_session(args),
+            "pause" => {
+                match args.trim() {
+                    "" => self.pause_timers(!self.timers.is_paused()),
+                    "on" => self.pause_timers(true),
+                    "off" => self.pause_timers(false),
+                    _ => return Err(Error::RuntimeError(i18n::tr("usage.pause"))),
+                }
+                Ok(())
+            }
+            "set" => self.exec_set(args),
+            "get" => self.exec_get(args),
+            "protocols" => {
+                self.exec_protocols();
+                Ok(())
+            }
+            "bookmarks" => self.exec_bookmarks(),
+            "go" => self.exec_go(args),
+            _ => Err(Error::RuntimeError(i18n::trf("err.unknown_command", &[&name]))),
+        }
+    }
+
+    /// #manage：列出触发器与别名，或启用/禁用指定触发器与别名
+    fn exec_manage(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (None, _) => {
+                let mut triggers: Vec<_> = self.triggers.iter().collect();
+                triggers.sort_by(|a, b| a.name.cmp(&b.name));
+                self.send_note(i18n::tr("manage.triggers"));
+                for tr in triggers {
+                    self.send_group_note(
+                        format!("  {} ", tr.name),
+                        &tr.group,
+                        format!(
+                            " {} {}",
+                            i18n::tr(if tr.enabled { "manage.enabled" } else { "manage.disabled" }),
+                            tr.pattern
+                        ),
+                    );
+                }
+                let mut aliases: Vec<_> = self.aliases.iter().collect();
+                aliases.sort_by(|a, b| a.name.cmp(&b.name));
+                self.send_note(i18n::tr("manage.aliases"));
+                for alias in aliases {
+                    self.send_group_note(
+                        format!("  {} ", alias.name),
+                        &alias.group,
+                        format!(
+                            " {} {}",
+                            i18n::tr(if alias.enabled { "manage.enabled" } else { "manage.disabled" }),
+                            alias.pattern
+                        ),
+                    );
+                }
+                Ok(())
+            }
+            (Some(op @ "enable"), Some(name)) | (Some(op @ "disable"), Some(name)) => {
+                let enabled = op == "enable";
+                if self.triggers.enable(name, enabled).is_none()
+                    && self.aliases.enable(name, enabled).is_none()
+                {
+                    return Err(Error::RuntimeError(i18n::trf("err.rule_not_found", &[&name])));
+                }
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.manage"))),
+        }
+    }
+
+    fn mapper(&self) -> Result<&Mapper> {
+        self.mapper
+            .as_ref()
+            .ok_or_else(|| Error::RuntimeError(i18n::tr("err.no_map")))
+    }
+
+    /// #flushvars：将变量写入快照并清空修改日志，显示压缩前的日志大小
+    fn exec_flushvars(&mut self) -> Result<()> {
+        for (name, vars, file) in [
+            ("world", &self.vars, &self.vars_file),
+            ("global", &self.global_vars, &self.global_vars_file),
+        ] {
+            if let Some((entries, bytes)) = vars.journal_stats() {
+                self.send_note(i18n::trf("vars.flushed", &[&name, &entries, &bytes, file]));
+            }
+        }
+        self.save_vars()
+    }
+
+    /// #stats：按分组统计触发器与别名的启用数量
+    fn exec_stats(&mut self) -> Result<()> {
+        // 分组 => (启用的触发器, 触发器, 启用的别名, 别名)
+        let mut groups: BTreeMap<&str, (usize, usize, usize, usize)> = BTreeMap::new();
+        for tr in self.triggers.iter() {
+            let counts = groups.entry(&tr.group).or_default();
+            counts.0 += tr.enabled as usize;
+            counts.1 += 1;
+        }
+        for alias in self.aliases.iter() {
+            let counts = groups.entry(&alias.group).or_default();
+            counts.2 += alias.enabled as usize;
+            counts.3 += 1;
+        }
+        self.send_note(i18n::tr("stats.title"));
+        for (group, (te, tt, ae, at)) in groups {
+            self.send_group_note(
+                "  ".to_owned(),
+                group,
+                format!(" {}", i18n::trf("stats.row", &[&te, &tt, &ae, &at])),
+            );
+        }
+        let observed: Vec<(String, Observation)> = self
+            .observer
+            .list()
+            .into_iter()
+            .map(|(g, ob)| (g.to_owned(), ob.clone()))
             .collect();
-        let mut cmds = Vec::new();
-        for raw_line in raw_lines {
-            if raw_line.is_empty() {
-                // send empty line directly, maybe filtered before this action
-                cmds.push(PostCmd::Raw(raw_line));
-            } else if let Some(alias) = self.aliases.match_first(&raw_line) {
-                log::debug!(
-                    "alias[{}/{}: {}] matched",
-                    alias.group,
-                    alias.name,
-                    alias.pattern
-                );
-                cmds.push(PostCmd::Alias {
-                    name: alias.name.clone(),
-                    text: raw_line,
-                })
-            } else {
-                cmds.push(PostCmd::Raw(raw_line));
+        for (group, ob) in observed {
+            self.send_group_note(
+                "  ".to_owned(),
+                &group,
+                format!(" {}", i18n::trf("stats.observed", &[&ob.total()])),
+            );
+            for (name, hits) in &ob.hits {
+                self.send_note(format!("    {}：{}", name, hits));
+            }
+            for sample in &ob.samples {
+                self.send_note(i18n::trf("stats.sample", &[sample]));
             }
         }
-        cmds
+        Ok(())
     }
-}
 
-/// 预处理后的命令，用户原始命令，或经过别名匹配后的脚本名
-#[derive(Debug, Clone)]
-pub enum PostCmd {
-    Raw(String),
-    Alias { name: String, text: String },
-}
+    /// #loadorder：按顺序列出已加载的脚本
+    fn exec_loadorder(&mut self) -> Result<()> {
+        self.send_note(i18n::tr("loadorder.title"));
+        for (i, r) in self.loaded.iter().enumerate() {
+            let status = match &r.error {
+                None => i18n::tr("loadorder.ok"),
+                Some(e) => i18n::trf("loadorder.failed", &[e]),
+            };
+            self.send_note(format!("  {}. {} [{}] {}", i + 1, r.file, r.via, status));
+        }
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::ui::line::{Line, RawLine, RawLines};
-    use crate::ui::span::Span;
-    use crate::ui::style::Style;
-    use crate::ui::UserOutput;
-    use crate::proto::Label;
+    /// #protocols：显示与服务器协商的协议
+    fn exec_protocols(&self) {
+        let protocols = *self.protocols.read().unwrap();
+        let protocols = match protocols {
+            Some(protocols) => protocols,
+            None => {
+                self.send_note(i18n::tr("protocol.unknown"));
+                return;
+            }
+        };
+        let items: Vec<String> = protocols
+            .list()
+            .into_iter()
+            .map(|(name, enabled)| {
+                let state = if enabled { "manage.enabled" } else { "manage.disabled" };
+                format!("{} {}", name.to_uppercase(), i18n::tr(state))
+            })
+            .collect();
+        self.send_note(i18n::trf("protocol.summary", &[&items.join(", ")]));
+    }
+
+    // 显示连接测量结果及建议，并调用OnProbe注册的回调
+    fn update_probe(&mut self, report: ProbeReport) -> Result<()> {
+        let rtt = match (report.rtt_min, report.rtt_avg, report.rtt_max) {
+            (Some(min), Some(avg), Some(max)) => format!("{}/{}/{}ms", min, avg, max),
+            _ => String::from("-"),
+        };
+        self.send_note(i18n::trf(
+            "probe.summary",
+            &[&rtt, &report.rtts.len(), &report.pings, &report.avg_read(), &report.max_read],
+        ));
+        for key in report.recommendations() {
+            self.send_note(format!("  {}", i18n::tr(key)));
+        }
+        self.probe.write().unwrap().replace(report.clone());
+        let func: Option<mlua::Function> = self.lua.globals().get(GLOBAL_PROBE_CALLBACK)?;
+        if let Some(func) = func {
+            func.call::<_, ()>(report)?;
+        }
+        Ok(())
+    }
+
+    /// #transformers：列出行转换器及其执行统计
+    fn exec_transformers(&mut self) -> Result<()> {
+        self.send_note(i18n::tr("transform.title"));
+        for (name, order, enabled, stats) in self.transformers.list() {
+            self.send_note(i18n::trf(
+                "transform.item",
+                &[
+                    &name,
+                    &order,
+                    &i18n::tr(if enabled { "manage.enabled" } else { "manage.disabled" }),
+                    &stats.calls,
+                    &stats.avg_micros(),
+                    &stats.errors,
+                ],
+            ));
+        }
+        Ok(())
+    }
+
+    /// #bookmarks：列出房间书签
+    fn exec_bookmarks(&mut self) -> Result<()> {
+        let bookmarks = self.mapper()?.list_bookmarks()?;
+        self.send_note(i18n::tr("bookmark.title"));
+        for b in bookmarks {
+            let mut line = format!("  {} #{} {}", b.name, b.roomid, b.roomname.unwrap_or_default());
+            if let Some(note) = b.note {
+                line.push_str(&format!(" ({})", note));
+            }
+            self.send_note(line);
+        }
+        Ok(())
+    }
+
+    /// #go：按书签前往房间，行走由脚本通过SetWalker设置的函数完成
+    fn exec_go(&mut self, args: &str) -> Result<()> {
+        let name = args.trim();
+        if name.is_empty() {
+            return Err(Error::RuntimeError(i18n::tr("usage.go")));
+        }
+        let bookmark = self
+            .mapper()?
+            .get_bookmark(name)?
+            .ok_or_else(|| Error::RuntimeError(i18n::trf("err.bookmark_not_found", &[&name])))?;
+        let walker: Option<mlua::Function> = self.lua.globals().get(GLOBAL_WALKER)?;
+        let walker = walker.ok_or_else(|| Error::RuntimeError(i18n::tr("err.no_walker")))?;
+        walker.call::<_, ()>((bookmark.roomid, bookmark.name))?;
+        Ok(())
+    }
+
+    /// #record：开始或停止录制宏
+    fn exec_record(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("start"), Some(name)) => {
+                if let Some(recorder) = self.recorder.as_ref() {
+                    return Err(Error::RuntimeError(i18n::trf(
+                        "err.recording",
+                        &[&recorder.name()],
+                    )));
+                }
+                datadir::check_name(name)?;
+                self.recorder = Some(Recorder::new(name));
+                self.send_note(i18n::trf("record.started", &[&name]));
+                Ok(())
+            }
+            (Some("stop"), _) => {
+                let recorder = self
+                    .recorder
+                    .take()
+                    .ok_or_else(|| Error::RuntimeError(i18n::tr("err.not_recording")))?;
+                let m = recorder.finish();
+                let path = self.macro_path(&m.name);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, m.to_lua())?;
+                self.send_note(i18n::trf(
+                    "record.saved",
+                    &[&m.name, &m.steps.len(), &path.display()],
+                ));
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.record"))),
+        }
+    }
+
+    /// #reload：重新加载全部脚本，或仅重新加载定义文件或自动加载目录中的脚本
+    fn exec_reload(&mut self, args: &str) -> Result<()> {
+        match args.trim() {
+            "" => {
+                self.tmpq.push(EngineAction::ReloadScript);
+                Ok(())
+            }
+            "defs" => {
+                // 全部定义文件解析成功后才替换，出错时保留当前的定义
+                let compiled = self.compile_defs()?;
+                self.unload_defs()?;
+                let n = self.install_defs(compiled)?;
+                self.send_note(i18n::trf("reload.defs", &[&n, &self.def_files.len()]));
+                Ok(())
+            }
+            "scripts" => {
+                // 先删除上次从该目录加载的模型，避免重复定义
+                let files: HashSet<String> = self
+                    .loaded
+                    .iter()
+                    .filter(|r| r.via == "autoload")
+                    .map(|r| r.file.clone())
+                    .collect();
+                self.unload_script_models(&files)?;
+                self.loaded.retain(|r| r.via != "autoload");
+                let (files, failed) = self.load_autoload_dir()?;
+                let dir = self.data_dir.script_path(&self.autoload_dir);
+                self.send_note(i18n::trf(
+                    "reload.scripts",
+                    &[&files.len(), &dir.display(), &failed.len()],
+                ));
+                for (file, e) in failed {
+                    self.send_note(i18n::trf("reload.failed", &[&file, &e]));
+                }
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.reload"))),
+        }
+    }
+
+    /// #trace：查看、导出或清空最近的文本处理轨迹
+    fn exec_trace(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("show"), n) => {
+                let n = match n {
+                    Some(n) => n
+                        .parse::<usize>()
+                        .map_err(|_| Error::RuntimeError(i18n::tr("usage.trace")))?,
+                    None => 10,
+                };
+                let lines: Vec<String> = self.tracer.last(n).flat_map(|t| t.format()).collect();
+                for line in lines {
+                    self.send_note(line);
+                }
+                Ok(())
+            }
+            (Some("export"), Some(file)) => {
+                // 仅写入世界的日志目录
+                datadir::check_name(file)?;
+                let path = self.data_dir.log_path(file);
+                std::fs::write(&path, self.tracer.export())?;
+                self.send_note(i18n::trf("trace.exported", &[&path.display()]));
+                Ok(())
+            }
+            (Some("clear"), None) => {
+                self.tracer.clear();
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.trace"))),
+        }
+    }
+
+    /// #reg：列出所有寄存器
+    fn exec_reg(&mut self) -> Result<()> {
+        self.send_note(i18n::tr("reg.title"));
+        for (name, text) in self.registers.list() {
+            self.send_note(format!("  {} {}", name, text));
+        }
+        Ok(())
+    }
+
+    /// #yank：将指定行号的历史行复制到寄存器
+    fn exec_yank(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let lineno = args.next().and_then(|n| n.parse::<usize>().ok());
+        let (lineno, name) = match (lineno, args.next()) {
+            (Some(lineno), name) => (lineno, name.unwrap_or(register::UNNAMED)),
+            _ => return Err(Error::RuntimeError(i18n::tr("usage.yank"))),
+        };
+        let line = self
+            .scrollback
+            .get(lineno)
+            .ok_or_else(|| Error::RuntimeError(i18n::trf("err.line_not_found", &[&lineno])))?;
+        self.registers.set(name, line.plain_text())?;
+        self.send_note(i18n::trf("reg.yanked", &[&lineno, &name]));
+        Ok(())
+    }
+
+    /// #dump models：将全部触发器、别名及定时器以JSON格式写入文件，供外部编辑器使用
+    fn exec_dump(&mut self, args: &str) -> Result<()> {
+        let path = match args.trim().split_once(' ') {
+            Some(("models", path)) if !path.trim().is_empty() => self.data_dir.state_path(path.trim()),
+            _ => return Err(Error::RuntimeError(i18n::tr("usage.dump"))),
+        };
+        let mut dump = ModelsDump::default();
+        let origins = self.model_origins(GLOBAL_TRIGGER_ORIGINS, &self.conf_triggers, "conf-trigger", DefKind::Trigger)?;
+        for tr in self.triggers.iter() {
+            dump.add_trigger(tr, &origins);
+        }
+        let origins = self.model_origins(GLOBAL_ALIAS_ORIGINS, &self.conf_aliases, "conf-alias", DefKind::Alias)?;
+        for alias in self.aliases.iter() {
+            dump.add_alias(alias, &origins);
+        }
+        let origins = self.model_origins(GLOBAL_TIMER_ORIGINS, &[], "", DefKind::Timer)?;
+        for tm in self.timers.iter() {
+            dump.add_timer(tm, &origins);
+        }
+        dump.sort();
+        std::fs::write(&path, serde_json::to_string_pretty(&dump)?)?;
+        self.send_note(i18n::trf(
+            "dump.saved",
+            &[&dump.trigger.len(), &dump.alias.len(), &dump.timer.len(), &path.display()],
+        ));
+        Ok(())
+    }
+
+    // 脚本及定义文件中的模型来自记录的加载文件，配置文件定义的规则来自配置文件，
+    // 配置文件及定义文件中的规则附带send或callback
+    fn model_origins(
+        &self,
+        table: &str,
+        rules: &[conf::SendRule],
+        prefix: &str,
+        kind: DefKind,
+    ) -> Result<Origins> {
+        let mut origins = Origins::default();
+        let files: mlua::Table = self.lua.globals().get(table)?;
+        for pair in files.pairs::<String, String>() {
+            let (name, file) = pair?;
+            origins.files.insert(name, file);
+        }
+        for (i, rule) in rules.iter().enumerate() {
+            let name = rule_name(&rule.name, prefix, i);
+            if !self.conf_file.is_empty() {
+                origins.files.insert(name.clone(), self.conf_file.to_owned());
+            }
+            origins.actions.insert(name, DefAction::Send(rule.send.to_owned()));
+        }
+        for def in self.loaded_defs.iter().filter(|def| def.kind == kind) {
+            origins.actions.insert(def.name.clone(), def.action.clone());
+        }
+        Ok(origins)
+    }
+
+    /// #mark：为最新一行或指定行号的历史行添加书签
+    fn exec_mark(&mut self, args: &str) -> Result<()> {
+        let args = args.trim();
+        let (first, rest) = args.split_once(' ').unwrap_or((args, ""));
+        let (lineno, label) = match first.parse::<usize>() {
+            Ok(lineno) => (lineno, rest.trim()),
+            Err(_) => (self.scrollback.last_lineno(), args),
+        };
+        if self.scrollback.get(lineno).is_none() {
+            return Err(Error::RuntimeError(i18n::trf("err.line_not_found", &[&lineno])));
+        }
+        let n = self.line_marks.add(lineno, now_millis(), label);
+        self.send_note(i18n::trf("mark.added", &[&lineno, &n]));
+        Ok(())
+    }
+
+    /// #marks：列出、删除或清空历史行书签
+    fn exec_marks(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (None, _) => {
+                self.send_note(i18n::tr("marks.title"));
+                let marks = self.line_marks.list().to_vec();
+                for (i, mark) in marks.into_iter().enumerate() {
+                    let text = self.scrollback.get(mark.lineno).map(|l| l.plain_text()).unwrap_or_default();
+                    self.send_note(format!(
+                        "  #{} {} {} {} {}",
+                        i + 1,
+                        marks::clock(mark.time),
+                        mark.lineno,
+                        mark.label,
+                        text.trim_end()
+                    ));
+                }
+                Ok(())
+            }
+            (Some("clear"), None) => {
+                self.line_marks.clear();
+                self.send_note(i18n::tr("marks.cleared"));
+                Ok(())
+            }
+            (Some("del"), Some(n)) => {
+                let n: usize = n.parse().map_err(|_| Error::RuntimeError(i18n::tr("usage.marks")))?;
+                self.line_marks
+                    .remove(n)
+                    .ok_or_else(|| Error::RuntimeError(i18n::trf("err.mark_not_found", &[&n])))?;
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.marks"))),
+        }
+    }
+
+    /// #jump：沿跳转列表前后移动，或跳转到指定序号的书签，显示书签行及其后数行
+    fn exec_jump(&mut self, args: &str) -> Result<()> {
+        let args = args.trim();
+        let jumped = match args {
+            "" | "prev" => self.line_marks.older(),
+            "next" => self.line_marks.newer(),
+            n => {
+                let n: usize = n.parse().map_err(|_| Error::RuntimeError(i18n::tr("usage.jump")))?;
+                Some(
+                    self.line_marks
+                        .jump(n)
+                        .ok_or_else(|| Error::RuntimeError(i18n::trf("err.mark_not_found", &[&n])))?,
+                )
+            }
+        };
+        let (n, mark) = match jumped {
+            Some((n, mark)) => (n, mark.clone()),
+            None => {
+                self.send_note(i18n::tr("marks.end"));
+                return Ok(());
+            }
+        };
+        self.send_note(i18n::trf(
+            "marks.jump",
+            &[&n, &mark.lineno, &marks::clock(mark.time), &mark.label],
+        ));
+        let lines = self.scrollback.range(mark.lineno, mark.lineno + JUMP_CONTEXT);
+        if lines.is_empty() {
+            return Err(Error::RuntimeError(i18n::trf("err.line_not_found", &[&mark.lineno])));
+        }
+        for (i, line) in lines.into_iter().enumerate() {
+            self.send_note(format!("  {} {}", mark.lineno + i, line.plain_text().trim_end()));
+        }
+        Ok(())
+    }
+
+    // 下载完成后校验签名，通过时显示脚本包信息并等待确认安装
+    fn bundle_fetched(&mut self, url: &str, res: std::result::Result<Vec<u8>, String>) -> Result<()> {
+        let bs = res.map_err(Error::RuntimeError)?;
+        let keys = TrustedKeys::load(&self.data_dir.state_path("trusted_keys"))?;
+        let bundle = Bundle::parse(&bs)?;
+        let signer = bundle
+            .verify(&keys)
+            .ok_or_else(|| Error::RuntimeError(i18n::trf("err.bad_signature", &[&url])))?
+            .to_owned();
+        self.show_bundle(&bundle, &signer);
+        self.pending_bundle = Some(bundle);
+        Ok(())
+    }
+
+    /// #fetch：下载并校验脚本包，确认后安装至scripts/plugins并加载
+    fn exec_fetch(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("install"), None) => {
+                let bundle = self
+                    .pending_bundle
+                    .take()
+                    .ok_or_else(|| Error::RuntimeError(i18n::tr("err.no_pending_bundle")))?;
+                let entry = bundle.install(&self.data_dir.script_path("plugins"))?;
+                self.send_note(i18n::trf(
+                    "fetch.installed",
+                    &[&bundle.manifest.name, &entry.display()],
+                ));
+                self.tmpq
+                    .push(EngineAction::LoadFile(entry.to_string_lossy().into_owned()));
+                Ok(())
+            }
+            (Some("cancel"), None) => {
+                if let Some(bundle) = self.pending_bundle.take() {
+                    self.send_note(i18n::trf("fetch.cancelled", &[&bundle.manifest.name]));
+                }
+                Ok(())
+            }
+            (Some(url), None) => {
+                let keys = TrustedKeys::load(&self.data_dir.state_path("trusted_keys"))?;
+                if keys.is_empty() {
+                    return Err(Error::RuntimeError(i18n::trf(
+                        "err.no_trusted_keys",
+                        &[&self.data_dir.state_path("trusted_keys").display()],
+                    )));
+                }
+                // 下载可能耗时较长，在后台进行，完成后再校验签名
+                self.send_note(i18n::trf("fetch.downloading", &[&url]));
+                self.tmpq.push(EngineAction::FetchBundle(url.to_owned()));
+                Ok(())
+            }
+            _ => Err(Error::RuntimeError(i18n::tr("usage.fetch"))),
+        }
+    }
+
+    // 展示脚本包清单及声明的能力
+    fn show_bundle(&self, bundle: &Bundle, signer: &str) {
+        let m = &bundle.manifest;
+        self.send_note(i18n::trf("fetch.manifest", &[&m.name, &m.version, &m.author, &signer]));
+        if !m.description.is_empty() {
+            self.send_note(format!("  {}", m.description));
+        }
+        let caps = if m.capabilities.is_empty() {
+            "-".to_owned()
+        } else {
+            m.capabilities.join(", ")
+        };
+        self.send_note(i18n::trf("fetch.capabilities", &[&caps]));
+        self.send_note(i18n::trf("fetch.files", &[&bundle.files.len()]));
+        self.send_note(i18n::tr("fetch.prompt"));
+    }
+
+    /// 记录非空命令，开启重发时将空命令替换为上一条命令，返回需执行的命令
+    ///
+    /// 上一条命令匹配禁止列表时不发送任何内容，避免误重发危险命令
+    fn repeat_last_cmd(&mut self, cmd: String) -> Option<String> {
+        if !cmd.trim_end_matches(&['\r', '\n'][..]).is_empty() {
+            // 以空格开头、关闭回显时输入的敏感命令及内置命令不参与重发
+            if !cmd.starts_with(' ') && !cmd.starts_with('#') && !self.echo_off() {
+                self.last_cmd = Some(cmd.clone());
+            }
+            return Some(cmd);
+        }
+        let last = match self.last_cmd.as_ref().filter(|_| self.repeat_last) {
+            Some(last) => last.to_owned(),
+            None => return Some(cmd),
+        };
+        if self.repeat_deny.is_match(&last) {
+            self.send_note(i18n::trf("repeat.denied", &[&last]));
+            return None;
+        }
+        self.actq.push_back(EngineAction::FlashCmd(last.clone()));
+        Some(last)
+    }
+
+    // 服务器接管回显，通常正在输入密码
+    fn echo_off(&self) -> bool {
+        self.protocols
+            .read()
+            .unwrap()
+            .map(|p| p.contains(Protocols::ECHO))
+            .unwrap_or(false)
+    }
+
+    /// 重复命令检查，返回是否继续执行
+    fn guard_user_cmd(&mut self, cmd: &str) -> bool {
+        let cmd = cmd.trim_end_matches(&['\r', '\n'][..]);
+        let verdict = match self.dup_guard.as_mut() {
+            Some(guard) => guard.check(cmd, Instant::now()),
+            None => return true,
+        };
+        match verdict {
+            Verdict::Pass => true,
+            Verdict::Suppress => {
+                self.send_note(i18n::trf("guard.suppressed", &[&cmd]));
+                false
+            }
+            Verdict::Confirm => {
+                self.send_note(i18n::trf("guard.confirm", &[&cmd]));
+                false
+            }
+        }
+    }
+
+    // 提示符中报告的服务器队列长度，保存至变量并放行暂存的命令
+    fn observe_queue(&mut self, text: &str) {
+        let pacer = match self.pacer.as_mut() {
+            Some(pacer) => pacer,
+            None => return,
+        };
+        if let Some(depth) = pacer.observe(text) {
+            self.vars.insert(pacer.var().to_owned(), depth.to_string());
+            let cmds = pacer.release();
+            if !cmds.is_empty() {
+                self.tmpq.push(EngineAction::SendHeldToServer(cmds));
+            }
+        }
+    }
+
+    // 按模板解析提示符，更新变量及状态栏，服务器状态栏开启时不更新状态栏
+    fn parse_prompt(&mut self, text: &str) {
+        let parser = match self.prompt_parser.as_ref() {
+            Some(parser) => parser,
+            None => return,
+        };
+        let vars = match parser.parse(text.trim_end_matches(&['\r', '\n'][..])) {
+            Some(vars) => vars,
+            None => return,
+        };
+        if let Some(status) = parser.status_text(&vars) {
+            if self.status_bar.is_none() {
+                self.tmpq.push(EngineAction::SendStatusToUI(vec![Line::fmt_raw(status)]));
+            }
+        }
+        self.vars.insert_all(vars);
+    }
+
+    /// #queue：查看服务器队列长度及暂存的命令，flush全部发送，clear全部丢弃
+    fn exec_queue(&mut self, args: &str) -> Result<()> {
+        let pacer = self
+            .pacer
+            .as_mut()
+            .ok_or_else(|| Error::RuntimeError(i18n::tr("err.no_queue_tag")))?;
+        match args.trim() {
+            "" => {
+                let note = i18n::trf("queue.status", &[&pacer.depth(), &pacer.held()]);
+                self.send_note(note);
+            }
+            "flush" => {
+                let cmds = pacer.flush();
+                if !cmds.is_empty() {
+                    self.tmpq.push(EngineAction::SendHeldToServer(cmds));
+                }
+            }
+            "clear" => {
+                let n = pacer.clear();
+                self.send_note(i18n::trf("queue.cleared", &[&n]));
+            }
+            _ => return Err(Error::RuntimeError(i18n::tr("usage.queue"))),
+        }
+        Ok(())
+    }
+
+    // 暂停时记录各定时器的剩余时间，恢复时按剩余时间继续调度
+    fn pause_timers(&mut self, paused: bool) {
+        let now = Instant::now();
+        if paused {
+            if !self.timers.is_paused() {
+                let n = self.timers.pause(now);
+                self.send_note(i18n::trf("timers.paused", &[&n]));
+            }
+        } else if self.timers.is_paused() {
+            let n = self.timers.resume(now);
+            self.send_note(i18n::trf("timers.resumed", &[&n]));
+        }
+    }
+
+    // 写入离线队列并显示暂存标记，敏感命令不显示内容
+    fn queue_offline(&mut self, cmd: QueuedCmd) {
+        let queue = match self.offline_queue.as_mut() {
+            Some(queue) => queue,
+            None => return,
+        };
+        let note = i18n::trf("offline.queued", &[&offline_text(&cmd)]);
+        match queue.push(cmd) {
+            Ok(()) => self.send_note(note),
+            Err(e) => {
+                let err_lines = Lines::fmt_err(e.to_string());
+                for err_line in err_lines.into_vec() {
+                    self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+                }
+            }
+        }
+    }
+
+    // 按暂存顺序发送离线队列中的命令，仍未连接时保留
+    fn flush_offline_queue(&mut self) -> Result<()> {
+        if !self.connected {
+            return Err(Error::RuntimeError(i18n::tr("err.offline_disconnected")));
+        }
+        let queue = match self.offline_queue.as_mut() {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let cmds = queue.take()?;
+        if cmds.is_empty() {
+            return Ok(());
+        }
+        self.send_note(i18n::trf("offline.flushed", &[&cmds.len()]));
+        for cmd in cmds {
+            let echo = cmd.echo && self.echo.is_some();
+            self.send_raw_cmd(cmd.cmd, echo);
+        }
+        Ok(())
+    }
+
+    /// #session：列出会话、按序号切换或打开新会话
+    fn exec_session(&mut self, args: &str) -> Result<()> {
+        let usage = || Error::RuntimeError(i18n::tr("usage.session"));
+        let args: Vec<&str> = args.split_whitespace().collect();
+        let cmd = match args[..] {
+            [] => SessionCmd::List,
+            ["open", name, addr] => SessionCmd::Open(name.to_owned(), addr.to_owned()),
+            [n] => SessionCmd::Switch(n.parse().ok().filter(|n| *n > 0).ok_or_else(usage)?),
+            _ => return Err(usage()),
+        };
+        self.tmpq.push(EngineAction::ManageSession(cmd));
+        Ok(())
+    }
+
+    /// #offline：查看、发送或丢弃断线期间暂存的命令
+    fn exec_offline(&mut self, args: &str) -> Result<()> {
+        let queue = self
+            .offline_queue
+            .as_mut()
+            .ok_or_else(|| Error::RuntimeError(i18n::tr("err.no_offline_queue")))?;
+        match args.trim() {
+            "" => {
+                let cmds = queue.cmds().to_vec();
+                self.send_note(i18n::trf("offline.status", &[&cmds.len()]));
+                for cmd in cmds {
+                    self.send_note(format!("  {}", offline_text(&cmd)));
+                }
+            }
+            "flush" => self.flush_offline_queue()?,
+            "clear" => {
+                let n = queue.take()?.len();
+                self.send_note(i18n::trf("offline.cleared", &[&n]));
+            }
+            _ => return Err(Error::RuntimeError(i18n::tr("usage.offline"))),
+        }
+        Ok(())
+    }
+
+    /// #set：调整运行时设置，无参数时列出所有设置，save将当前设置写回配置文件
+    fn exec_set(&mut self, args: &str) -> Result<()> {
+        let (key, value) = match args.find(' ') {
+            Some(idx) => (&args[..idx], &args[idx + 1..]),
+            None => (args, ""),
+        };
+        match (key, value) {
+            ("", _) => self.send_settings(),
+            ("save", "") => {
+                if self.conf_file.is_empty() {
+                    return Err(Error::RuntimeError(i18n::tr("err.no_conf_file")));
+                }
+                settings::save(Path::new(&self.conf_file), &self.settings_conf)?;
+                self.send_note(i18n::trf("settings.saved", &[&self.conf_file]));
+            }
+            (_, "") => return Err(Error::RuntimeError(i18n::tr("usage.set"))),
+            (key, value) => {
+                let setting = settings::find(key)
+                    .ok_or_else(|| Error::RuntimeError(i18n::trf("err.setting_not_found", &[&key])))?;
+                setting.set(&mut self.settings_conf, value)?;
+                if setting.section == "term" {
+                    self.tmpq.push(EngineAction::UpdateTerm(self.settings_conf.term.clone()));
+                } else {
+                    self.apply_settings()?;
+                }
+                let value = setting.get(&self.settings_conf);
+                self.send_note(format!("{} = {}", key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// #get：查看运行时设置
+    fn exec_get(&mut self, args: &str) -> Result<()> {
+        if args.is_empty() {
+            self.send_settings();
+            return Ok(());
+        }
+        let setting = settings::find(args)
+            .ok_or_else(|| Error::RuntimeError(i18n::trf("err.setting_not_found", &[&args])))?;
+        self.send_note(format!("{} = {}", args, setting.get(&self.settings_conf)));
+        Ok(())
+    }
+
+    fn send_settings(&self) {
+        self.send_note(i18n::tr("settings.title"));
+        for setting in settings::SETTINGS {
+            self.send_note(format!("  {} = {}", setting.key, setting.get(&self.settings_conf)));
+        }
+    }
+
+    // 按调整后的设置更新运行时状态
+    fn apply_settings(&mut self) -> Result<()> {
+        let config = &self.settings_conf.runtime;
+        self.echo = Echo::new(config);
+        self.cmd_delim = config.cmd_delim;
+        self.wait_token = config.wait_token.to_owned();
+        self.send_empty_cmd = config.send_empty_cmd;
+        self.repeat_last = config.repeat_last_on_empty;
+        self.max_alias_depth = config.max_alias_depth;
+        self.status_parser = config.status_parser;
+        self.dup_guard_conf = config.dup_guard.clone();
+        self.dup_guard = if config.dup_guard.enabled {
+            Some(DupGuard::new(&config.dup_guard)?)
+        } else {
+            None
+        };
+        self.encode_fallback = Fallback::new(config.encode_fallback, &config.encode_translit);
+        if self.newlines.mode() != config.newline {
+            self.newlines = Newlines::new(config.newline);
+        }
+        self.queue_tag_conf = config.queue_tag.clone();
+        if let Some(pacer) = self.pacer.as_mut() {
+            pacer.set_max_depth(config.queue_tag.max_depth);
+        }
+        Ok(())
+    }
+
+    /// #confirm：发送被拦截的重复命令
+    fn exec_confirm(&mut self) -> Result<()> {
+        match self.dup_guard.as_mut().and_then(|g| g.take_pending()) {
+            Some(cmd) => {
+                self.process_user_cmd(cmd, &[]);
+                Ok(())
+            }
+            None => Err(Error::RuntimeError(i18n::tr("err.nothing_to_confirm"))),
+        }
+    }
+
+    /// #play：按录制时的间隔回放宏，speed为回放倍速
+    fn exec_play(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let name = args
+            .next()
+            .ok_or_else(|| Error::RuntimeError(i18n::tr("usage.play")))?;
+        let speed = match args.next() {
+            Some(s) => s
+                .parse::<f64>()
+                .ok()
+                .filter(|speed| *speed > 0.0)
+                .ok_or_else(|| Error::RuntimeError(i18n::trf("err.play_speed", &[&s])))?,
+            None => 1.0,
+        };
+        datadir::check_name(name)?;
+        let mut script = String::new();
+        File::open(self.macro_path(name))?.read_to_string(&mut script)?;
+        let m = Macro::from_lua(&self.lua, name, &script)?;
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TIMER_CALLBACKS)?;
+        let mut at = 0.0;
+        for step in m.steps {
+            at += step.delay / speed;
+            let tm = TimerModel::new(
+                Uuid::new_v4().to_simple().to_string(),
+                format!("macro-{}", name),
+                Duration::from_secs_f64(at),
+                TimerFlags::ENABLED | TimerFlags::ONESHOT,
+            );
+            let queue = self.tmpq.clone();
+            let cmd = step.cmd;
+            let send = self.lua.create_function(move |_, ()| {
+                queue.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.clone())));
+                Ok(())
+            })?;
+            callbacks.set(&tm.name[..], send)?;
+            self.tmpq.push(EngineAction::CreateTimer(tm));
+        }
+        Ok(())
+    }
+
+    fn macro_path(&self, name: &str) -> std::path::PathBuf {
+        self.data_dir.script_path(format!("macros/{}.lua", name))
+    }
+
+    /// 向界面发送提示信息
+    fn send_note(&self, text: impl Into<String>) {
+        self.tmpq
+            .push(EngineAction::SendLineToUI(Line::fmt_note(text), None));
+    }
+
+    // 提示行中的组名按分组设置显示颜色及图标
+    fn send_group_note(&self, prefix: String, group: &str, suffix: String) {
+        let note = Style::default().fg(Color::LightBlue);
+        let line = Line::new(vec![
+            Span::new(prefix, note, Label::None),
+            self.group_metas.label(group, note),
+            Span::fmt_note(suffix),
+        ]);
+        self.tmpq.push(EngineAction::SendLineToUI(line, None));
+    }
+
+    /// 处理用户脚本
+    fn process_user_script(&mut self, script: String) {
+        if let Err(e) = self.exec_script(&script) {
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+    }
+
+    // 回显REPL输入并求值，代码不完整时以续行提示符回显
+    fn eval_repl(&mut self, code: &str) -> Lines {
+        let prompt = if self.repl.is_pending() { ">> " } else { "> " };
+        let mut lines = Lines::new();
+        lines.push_line(Line::fmt_with_style(
+            format!("{}{}", prompt, code),
+            Style::default().fg(Color::Yellow),
+        ));
+        match self.repl.eval(&self.lua, code) {
+            None => (),
+            Some(Ok(values)) => {
+                for value in values {
+                    for text in value.split('\n') {
+                        lines.push_line(Line::fmt_raw(text));
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                for line in Lines::fmt_err(e).into_vec() {
+                    lines.push_line(line);
+                }
+            }
+        }
+        lines
+    }
+
+    /// 在第一个等待标记处拆分命令，返回之前的命令、等待参数及之后的命令
+    fn split_wait<'a>(&self, cmd: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
+        if self.wait_token.is_empty() {
+            return None;
+        }
+        // 当前命令的起始位置及之前命令的结束位置
+        let (mut start, mut head_end) = (0, 0);
+        let ends = cmd.char_indices().chain(std::iter::once((cmd.len(), '\n')));
+        for (idx, c) in ends.filter(|(_, c)| *c == '\n' || *c == self.cmd_delim) {
+            let args = cmd[start..idx]
+                .trim_start()
+                .strip_prefix(&self.wait_token[..])
+                .filter(|args| args.is_empty() || args.starts_with(' '));
+            if let Some(args) = args {
+                let rest = cmd.get(idx + c.len_utf8()..).unwrap_or("");
+                return Some((&cmd[..head_end], args.trim(), rest));
+            }
+            head_end = idx;
+            start = idx + c.len_utf8();
+        }
+        None
+    }
+
+    /// 等待指定毫秒后执行命令，保留别名调用链
+    ///
+    /// 等待排在之前的命令之后，节奏控制暂存了之前的命令时，放行后才开始计时
+    fn schedule_cmd(&mut self, millis: &str, cmd: String, chain: &[String]) -> Result<()> {
+        // 过大的等待时间无法计算到期时刻，同样视为无效
+        let delay = millis
+            .parse::<u64>()
+            .ok()
+            .map(Duration::from_millis)
+            .filter(|d| Instant::now().checked_add(*d).is_some())
+            .ok_or_else(|| Error::RuntimeError(i18n::trf("err.wait_invalid", &[&millis])))?;
+        if !cmd.trim().is_empty() {
+            self.tmpq.push(EngineAction::WaitAliasCmd(delay, cmd, chain.to_vec()));
+        }
+        Ok(())
+    }
+
+    // 创建一次性定时器，到期后执行命令
+    fn start_wait(&mut self, delay: Duration, cmd: String, chain: Vec<String>) {
+        if let Err(e) = self.create_wait_timer(delay, cmd, chain) {
+            let err_lines = Lines::fmt_err(e.to_string());
+            for err_line in err_lines.into_vec() {
+                self.tmpq.push(EngineAction::SendLineToUI(err_line, None));
+            }
+        }
+    }
+
+    fn create_wait_timer(&mut self, delay: Duration, cmd: String, chain: Vec<String>) -> Result<()> {
+        let tm = TimerModel::new(
+            Uuid::new_v4().to_simple().to_string(),
+            "TemporaryWait",
+            delay,
+            TimerFlags::ENABLED | TimerFlags::ONESHOT,
+        );
+        let queue = self.tmpq.clone();
+        let send = self.lua.create_function(move |_, ()| {
+            queue.push(EngineAction::ExecuteAliasCmd(cmd.clone(), chain.clone()));
+            Ok(())
+        })?;
+        let callbacks: mlua::Table = self.lua.globals().get(GLOBAL_TIMER_CALLBACKS)?;
+        callbacks.set(&tm.name[..], send)?;
+        self.tmpq.push(EngineAction::CreateTimer(tm));
+        Ok(())
+    }
+
+    /// 改写命令，根据换行与分隔符切分命名，并进行别名匹配与替换
+    fn translate_cmds(&self, cmd: String, delim: char, send_empty_cmd: bool) -> Vec<PostCmd> {
+        if cmd.is_empty() {
+            return vec![];
+        }
+        let raw_lines: Vec<String> = cmd
+            .split(|c| c == '\n' || c == delim)
+            .filter(|s| send_empty_cmd || !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect();
+        let mut cmds = Vec::new();
+        for raw_line in raw_lines {
+            if raw_line.is_empty() {
+                // send empty line directly, maybe filtered before this action
+                cmds.push(PostCmd::Raw(raw_line));
+            } else if let Some((name, args)) = parse_builtin(&raw_line) {
+                cmds.push(PostCmd::Builtin {
+                    name: name.to_owned(),
+                    args: args.to_owned(),
+                });
+            } else if let Some(alias) = self.aliases.match_first(&raw_line) {
+                log::debug!(
+                    "alias[{}/{}: {}] matched",
+                    alias.group,
+                    alias.name,
+                    alias.pattern
+                );
+                cmds.push(PostCmd::Alias {
+                    name: alias.name.clone(),
+                    text: raw_line,
+                })
+            } else {
+                cmds.push(PostCmd::Raw(raw_line));
+            }
+        }
+        cmds
+    }
+}
+
+// 离线队列中命令的显示文本，敏感命令不显示内容
+fn offline_text(cmd: &QueuedCmd) -> &str {
+    if cmd.echo {
+        &cmd.cmd
+    } else {
+        "******"
+    }
+}
+
+// 解析内置命令的名称与参数，未注册的名称返回None
+fn parse_builtin(line: &str) -> Option<(&str, &str)> {
+    let builtin = line.strip_prefix('#')?;
+    let (name, args) = match builtin.find(' ') {
+        Some(idx) => (&builtin[..idx], builtin[idx + 1..].trim()),
+        None => (builtin, ""),
+    };
+    if BUILTINS.contains(&name) {
+        Some((name, args))
+    } else {
+        None
+    }
+}
+
+/// 命令回显设置
+#[derive(Debug, Clone)]
+struct Echo {
+    prefix: String,
+    style: Style,
+    log: bool,
+    merge: bool,
+}
+
+impl Echo {
+    fn new(config: &conf::Runtime) -> Option<Self> {
+        if !config.echo_cmd {
+            return None;
+        }
+        Some(Self {
+            prefix: config.echo_prefix.to_owned(),
+            style: Style::default().fg(Color::from_str_or_default(&config.echo_color, Color::Yellow)),
+            log: config.echo_log,
+            merge: config.echo_merge,
+        })
+    }
+}
+
+/// 预处理后的命令，用户原始命令，或经过别名匹配后的脚本名
+#[derive(Debug, Clone)]
+pub enum PostCmd {
+    Raw(String),
+    Alias { name: String, text: String },
+    Builtin { name: String, args: String },
+}
+
+/// 脚本加载记录
+#[derive(Debug, Clone)]
+struct LoadRecord {
+    file: String,
+    // 加载方式：init、autoload或LoadFile
+    via: &'static str,
+    error: Option<String>,
+}
+
+// 配置中未指定名称时，使用前缀与序号生成
+fn rule_name(name: &str, prefix: &str, idx: usize) -> String {
+    if name.is_empty() {
+        format!("{}-{}", prefix, idx + 1)
+    } else {
+        name.to_owned()
+    }
+}
+
+fn rule_group(group: &str) -> &str {
+    if group.is_empty() {
+        CONF_GROUP
+    } else {
+        group
+    }
+}
+
+// 编译后尚未注册的定义
+struct CompiledDef {
+    rule: DefRule,
+    action: DefAction,
+    source: String,
+}
+
+enum DefRule {
+    Trigger(Trigger),
+    Alias(Alias),
+    Timer(TimerModel),
+}
+
+// 执行脚本转换器，回调逐个接收片段文本，返回nil时保持不变
+fn exec_transformer(lua: &mlua::Lua, name: &str, line: Line) -> Result<Line> {
+    let callbacks: mlua::Table = lua.globals().get(GLOBAL_TRANSFORMER_CALLBACKS)?;
+    let func: mlua::Function = callbacks.get(name)?;
+    transform::map_spans(line, |text| {
+        let res: Option<String> = func.call(text)?;
+        Ok(res.unwrap_or_else(|| text.to_owned()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use crate::ui::line::{Line, RawLine, RawLines};
+    use crate::ui::span::Span;
+    use crate::ui::style::Style;
+    use crate::ui::UserOutput;
+
+    #[test]
+    fn test_engine_single_user_cmd() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "hp".to_owned(),
+        )));
+        let mut evts = engine.apply();
+        assert_eq!(1, evts.len());
+        assert_eq!(
+            RuntimeOutput::ToServer(b"hp\n".to_vec()),
+            evts.pop().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_engine_multi_user_cmds() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "hp;say hi".to_owned(),
+        )));
+        let mut evts = engine.apply();
+        assert_eq!(1, evts.len());
+        assert_eq!(
+            RuntimeOutput::ToServer(b"hp\nsay hi\n".to_vec()),
+            evts.pop().unwrap()
+        );
+
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "hp\nsay hi".to_owned(),
+        )));
+        let mut evts = engine.apply();
+        assert_eq!(1, evts.len());
+        assert_eq!(
+            RuntimeOutput::ToServer(b"hp\nsay hi\n".to_vec()),
+            evts.pop().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_engine_simple_alias() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+        local n = function() Send("north") end
+        CreateAlias("alias-n", "map", "^n$", alias_flag.Enabled, n)
+        "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        // start user output
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "n".to_owned(),
+        )));
+        let mut outputs = engine.apply();
+        assert_eq!(1, outputs.len());
+        assert_eq!(
+            RuntimeOutput::ToServer(b"north\n".to_vec()),
+            outputs.pop().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_engine_complex_alias() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+        local m = function(name, line, wildcards) Send(wildcards[1]..wildcards[2]) end
+        CreateAlias("alias-m", "number", "^num (\\d+)\\s+(\\d+)$", alias_flag.Enabled, m) 
+        "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "x;num 123 456".to_owned(),
+        )));
+        let mut outputs = engine.apply();
+        assert_eq!(1, outputs.len());
+        assert_eq!(
+            RuntimeOutput::ToServer(b"x\n123456\n".to_vec()),
+            outputs.pop().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_engine_alias_add_trigger() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            local addtr = function()
+                local trcb = function() end
+                CreateTrigger("tr1", "trg", "^say hi$", trigger_flag.Enabled, 1, trcb)
+            end
+            CreateAlias("alias-tr", "addtr", "^addtr$", alias_flag.Enabled, addtr)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "addtr".to_owned(),
+        )));
+        let outputs = engine.apply();
+        assert!(outputs.is_empty());
+        assert_eq!(1, engine.triggers.len());
+    }
+
+    #[test]
+    fn test_engine_simple_trigger() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            local f = function() Send("triggered") end
+            CreateTrigger("trigger-f", "trg", "^张三走了过来。$", trigger_flag.Enabled, 1, f)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "张三走了过来。\r\n",
+        )]));
+        let mut evts = engine.apply();
+        assert_eq!(2, evts.len());
+        let mut rawlines = RawLines::unbounded();
+        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
+        let mut lines = Lines::new();
+        lines.push_line(Line::new(vec![Span::new(
+            "张三走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        assert_eq!(RuntimeOutput::ToUI(rawlines, lines, engine.scrollback.last_lineno()), evts.remove(0));
+        assert_eq!(
+            RuntimeOutput::ToServer(b"triggered\n".to_vec()),
+            evts.remove(0)
+        );
+    }
+
+    #[test]
+    fn test_engine_trigger_context() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            local f = function(name, line, wildcards, styles, ctx)
+                ctx_args = {ctx.lineno, ctx.source, ctx.text, ctx.time > 0}
+            end
+            local g = function(name, line, wildcards, styles, ctx)
+                plain_ctx = ctx
+            end
+            CreateTrigger("trigger-f", "trg", "^李四", trigger_flag.Context, 1, f)
+            CreateTrigger("trigger-g", "trg", "^李四", 0, 1, g)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("张三走了过来。\r\n"),
+            RawLine::new("李四走了过来。\r\n"),
+        ]));
+        engine.apply();
+        let args: (usize, String, String, bool) = engine
+            .lua
+            .load("return unpack(ctx_args)")
+            .eval()
+            .unwrap();
+        assert_eq!((2, "world".to_owned(), "李四走了过来。".to_owned(), true), args);
+        let plain: mlua::Value = engine.lua.globals().get("plain_ctx").unwrap();
+        assert_eq!(mlua::Value::Nil, plain);
+    }
+
+    #[test]
+    fn test_engine_trigger_capture_styles() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            local f = function(name, line, wildcards, styles)
+                capture_fgs = {#styles, styles.captures[1].fg, styles.captures.target.fg, styles.captures[2].offset}
+            end
+            CreateTrigger("trigger-c", "trg", "^(\\w+) hits (?P<target>\\w+)", 0, 1, f)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ParseWorldBytes(
+            b"\x1b[31mZhang\x1b[0m hits \x1b[1;32mLi\x1b[0m!\r\n".to_vec(),
+        ));
+        engine.apply();
+        let fgs: (usize, String, String, usize) = engine
+            .lua
+            .load("return unpack(capture_fgs)")
+            .eval()
+            .unwrap();
+        assert_eq!(4, fgs.0);
+        assert_eq!(("red".to_owned(), "green".to_owned(), 11), (fgs.1, fgs.2, fgs.3));
+    }
+
+    #[test]
+    fn test_engine_trigger_group_window() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("trigger-q", "quest", "^李四", 0, 1, function() Send("hit") end)
+            EnableTriggerGroup("quest", true, {matches = 2})
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        let mut sent = 0;
+        for _ in 0..3 {
+            engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+                "李四走了过来。\r\n",
+            )]));
+            sent += engine
+                .apply()
+                .iter()
+                .filter(|o| matches!(o, RuntimeOutput::ToServer(_)))
+                .count();
+        }
+        assert_eq!(2, sent);
+        assert!(!engine.triggers.get("trigger-q").unwrap().enabled);
+        // 超过存活时间
+        engine
+            .lua
+            .load(r#"EnableTriggerGroup("quest", true, {ttl = 0.01})"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        assert!(engine.triggers.get("trigger-q").unwrap().enabled);
+        std::thread::sleep(Duration::from_millis(20));
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "李四走了过来。\r\n",
+        )]));
+        assert!(engine.apply().iter().all(|o| !matches!(o, RuntimeOutput::ToServer(_))));
+        assert!(!engine.triggers.get("trigger-q").unwrap().enabled);
+        assert!(engine
+            .lua
+            .load(r#"EnableTriggerGroup("quest", true, {ttl = 0})"#)
+            .exec()
+            .is_err());
+        assert!(engine
+            .lua
+            .load(r#"EnableTriggerGroup("quest", true, {ttl = 1e300})"#)
+            .exec()
+            .is_err());
+
+        // 同一行匹配组内多个触发器时，达到限次后其余触发器不再执行
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("trigger-a", "loot", "^王五", trigger_flag.KeepEvaluating, 1, function() Send("get a") end)
+            CreateTrigger("trigger-b", "loot", "^王五", trigger_flag.KeepEvaluating, 1, function() Send("get b") end)
+            EnableTriggerGroup("loot", true, {matches = 1})
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "王五倒下了。\r\n",
+        )]));
+        let sent: Vec<u8> = engine
+            .apply()
+            .into_iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToServer(bs) => Some(bs),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(6, sent.len());
+        assert!(!engine.triggers.get("trigger-b").unwrap().enabled);
+    }
+
+    #[test]
+    fn test_engine_trigger_window() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("trigger-w", "", "^hp", 0, 1, function()
+                local window = GetTriggerWindow()
+                SetVariable("rows", #window)
+                SetVariable("prev", window[#window - 1].text)
+                SetVariable("ended", tostring(window.ended))
+            end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("气血  内力\r\n"),
+            RawLine::new("hp 100/100\r\n"),
+        ]));
+        engine.apply();
+        assert_eq!(Some("5".to_owned()), engine.vars.get("rows"));
+        assert_eq!(Some("气血  内力".to_owned()), engine.vars.get("prev"));
+        assert_eq!(Some("true".to_owned()), engine.vars.get("ended"));
+    }
+
+    #[test]
+    fn test_engine_group_vars() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("trigger-q", "quest", "^李四", 0, 1, function() end)
+            CreateTimer("timer-q", "quest", 1000, 1, function() end)
+            DeclareGroupVar("quest", "step", "1")
+            SetGroupVar("quest", "step", "2")
+            DeclareGroupVar("fight", "target", "张三")
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        assert!(engine.lua.load(r#"SetGroupVar("quest", "npc", "李四")"#).exec().is_err());
+        let step: String = engine.lua.load(r#"return DeclareGroupVar("quest", "step", "1")"#).eval().unwrap();
+        assert_eq!("2", step);
+        // 禁用分组时清除变量
+        engine.push(EngineAction::EnableTimerGroup("fight".to_owned(), false));
+        engine.apply();
+        assert_eq!(None, engine.group_vars.get("fight", "target"));
+        // 删除分组时同时删除其中的触发器及定时器
+        engine.lua.load(r#"DeleteGroup("quest")"#).exec().unwrap();
+        engine.apply();
+        assert!(engine.triggers.get("trigger-q").is_none());
+        assert_eq!(0, engine.timers.len());
+        assert_eq!(None, engine.group_vars.get("quest", "step"));
+    }
+
+    #[test]
+    fn test_engine_protocols() {
+        let mut engine = new_engine().unwrap();
+        let negotiated: bool = engine.lua.load("return GetProtocols() ~= nil").eval().unwrap();
+        assert!(!negotiated);
+        engine.push(EngineAction::UpdateProtocols(Protocols::MCCP | Protocols::NAWS));
+        // 首次协商完成时显示结果
+        assert_eq!(1, engine.apply().len());
+        engine.push(EngineAction::UpdateProtocols(Protocols::MCCP | Protocols::ECHO));
+        assert_eq!(vec![RuntimeOutput::SecretInput(true)], engine.apply());
+        let flags: (bool, bool, bool) = engine
+            .lua
+            .load("local p = GetProtocols() return p.mccp, p.naws, p.echo")
+            .eval()
+            .unwrap();
+        assert_eq!((true, false, true), flags);
+    }
+
+    #[test]
+    fn test_engine_probe() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load("OnProbe(function(p) probe_rec = p.recommendations[1] end)")
+            .exec()
+            .unwrap();
+        let report = ProbeReport {
+            pings: 2,
+            rtts: vec![400, 500],
+            rtt_min: Some(400),
+            rtt_max: Some(500),
+            rtt_avg: Some(450),
+            ..ProbeReport::default()
+        };
+        engine.push(EngineAction::UpdateProbe(report));
+        // 摘要及一条建议
+        let lines = match engine.apply().remove(0) {
+            RuntimeOutput::ToUI(_, lines, _) => lines.into_vec(),
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert_eq!(2, lines.len());
+        let rec: String = engine.lua.globals().get("probe_rec").unwrap();
+        assert_eq!("rec_timeout", rec);
+        let avg: u64 = engine.lua.load("return GetProbe().rtt_avg").eval().unwrap();
+        assert_eq!(450, avg);
+    }
+
+    #[test]
+    fn test_engine_server_status_bar() {
+        let mut config = crate::conf::Config::default();
+        config.term.server_status_rows = 2;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("\x1b7\x1b[24;1H\x1b[K气血：100\x1b8\r\n"),
+            RawLine::new("你走了过来。\r\n"),
+        ]));
+        let mut status = vec![];
+        let mut flow = vec![];
+        for output in engine.apply() {
+            match output {
+                RuntimeOutput::ToStatus(lines) => {
+                    status.extend(lines.iter().map(|l| l.plain_text()))
+                }
+                RuntimeOutput::ToUI(raw, lines, _) => {
+                    // 原始文本完整保留，供客户端解析
+                    assert_eq!(2, raw.into_vec().len());
+                    flow.extend(lines.into_vec().iter().map(|l| l.plain_text()));
+                }
+                other => panic!("unexpected output {:?}", other),
+            }
+        }
+        assert_eq!(vec!["气血：100"], status);
+        assert_eq!(vec!["你走了过来。"], flow);
+    }
+
+    #[test]
+    fn test_engine_prompt_parser() {
+        let mut config = crate::conf::Config::default();
+        config.prompt.pattern = r"HP:(?P<hp>\d+)/(?P<hp_max>\d+)".to_owned();
+        config.prompt.status = "HP {hp}/{hp_max}".to_owned();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        // 完整的行不是提示符
+        engine.push(EngineAction::ParseWorldBytes(b"HP:10/100\r\n".to_vec()));
+        engine.apply();
+        assert_eq!(None, engine.vars.get("hp"));
+        engine.push(EngineAction::ParseWorldBytes(b"HP:80/100 > ".to_vec()));
+        let status: Vec<String> = engine
+            .apply()
+            .into_iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToStatus(lines) => Some(lines[0].plain_text()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec!["HP 80/100"], status);
+        assert_eq!(Some("80".to_owned()), engine.vars.get("hp"));
+        assert_eq!(Some("100".to_owned()), engine.vars.get("hp_max"));
+    }
+
+    #[test]
+    fn test_engine_media_directive() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(r#"OnMediaDirective(function(d) media = d.kind .. ":" .. d.file .. ":" .. d.volume end)"#)
+            .exec()
+            .unwrap();
+        engine.push(EngineAction::ParseWorldBytes(
+            b"!!SOUND(hit.wav V=80)\r\nYou are hit!!MUSIC(fight.mid V=50)\r\n".to_vec(),
+        ));
+        let flow: Vec<String> = engine
+            .apply()
+            .into_iter()
+            .flat_map(|o| match o {
+                RuntimeOutput::ToUI(_, lines, _) => lines.into_vec().iter().map(|l| l.plain_text()).collect(),
+                _ => vec![],
+            })
+            .collect();
+        // 仅含指令的行被隐藏，其他行删除指令
+        assert_eq!(1, flow.len());
+        assert!(flow[0].starts_with("You are hit") && !flow[0].contains("!!"));
+        let media: String = engine.lua.globals().get("media").unwrap();
+        assert_eq!("music:fight.mid:50", media);
+    }
+
+    #[test]
+    fn test_engine_gmcp() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            gmcp_calls = {}
+            OnGmcp("Char", function(package, data) table.insert(gmcp_calls, package .. ":" .. data.hp) end)
+            OnGmcp("Room.Info", function(package, data) table.insert(gmcp_calls, data.name) end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.push(EngineAction::ReceiveGmcp(br#"char.vitals {"hp": 80}"#.to_vec()));
+        engine.push(EngineAction::ReceiveGmcp(br#"Room.Info {"name": "Center"}"#.to_vec()));
+        engine.push(EngineAction::ReceiveGmcp(br#"Comm.Channel {"text": "hi"}"#.to_vec()));
+        // 格式错误的消息被忽略
+        engine.push(EngineAction::ReceiveGmcp(b"Char.Vitals {".to_vec()));
+        engine.apply();
+        let calls: Vec<String> = engine.lua.globals().get("gmcp_calls").unwrap();
+        assert_eq!(vec!["char.vitals:80", "Center"], calls);
+    }
+
+    #[test]
+    fn test_engine_msdp() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            msdp_calls = {}
+            OnMsdp("HEALTH", function(name, value) table.insert(msdp_calls, name .. "=" .. value) end)
+            OnMsdp("ROOM", function(name, value) table.insert(msdp_calls, value.NAME) end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.push(EngineAction::ReceiveMsdp(b"\x01HEALTH\x0280\x01ROOM\x02\x03\x01NAME\x02Center\x04".to_vec()));
+        // 值未变化时不调用回调
+        engine.push(EngineAction::ReceiveMsdp(b"\x01HEALTH\x0280\x01MANA\x0210".to_vec()));
+        engine.push(EngineAction::ReceiveMsdp(b"\x01HEALTH\x0275".to_vec()));
+        engine.apply();
+        let calls: Vec<String> = engine.lua.globals().get("msdp_calls").unwrap();
+        assert_eq!(vec!["HEALTH=80", "Center", "HEALTH=75"], calls);
+        let mana: String = engine.lua.load(r#"return GetMsdp("MANA")"#).eval().unwrap();
+        assert_eq!("10", mana);
+    }
+
+    #[test]
+    fn test_engine_group_stats() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("t1", "fight", "^你死了", 0, 1, function() end)
+            CreateTrigger("t2", "fight", "^张三", 0, 1, function() end)
+            SetGroupMeta("fight", {color="red", icon="⚔"})
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::EnableTriggerGroup("fight".to_owned(), false));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#stats".to_owned())));
+        let lines = match engine.apply().remove(0) {
+            RuntimeOutput::ToUI(_, lines, _) => lines.into_vec(),
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert_eq!(2, lines.len());
+        let group = &lines[1].spans()[1];
+        assert_eq!("[⚔ fight]", group.content);
+        assert_eq!(Style::default().fg(Color::Red), group.style);
+        assert!(lines[1].plain_text().contains("0/2"));
+        let invalid: bool = engine
+            .lua
+            .load(r#"return pcall(SetGroupMeta, "fight", {color="pink"})"#)
+            .eval()
+            .unwrap();
+        assert!(!invalid);
+    }
+
+    #[test]
+    fn test_engine_observe_trigger_group() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("rat", "hunt", "^You see a (\\w+)", 0, 1, function() fired = true end)
+            ObserveTriggerGroup("hunt", true, 1)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ParseWorldBytes(b"You see a rat\r\nYou see a cat\r\n".to_vec()));
+        engine.apply();
+        let fired: Option<bool> = engine.lua.globals().get("fired").unwrap();
+        assert_eq!(None, fired);
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#stats".to_owned())));
+        let text: Vec<String> = match engine.apply().remove(0) {
+            RuntimeOutput::ToUI(_, lines, _) => lines.into_vec().iter().map(|l| l.plain_text()).collect(),
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert!(text[2].contains("[hunt]"));
+        assert!(text[3].ends_with("rat：2"));
+        assert!(text[4].ends_with("You see a cat"));
+        // 取消观察后执行回调
+        engine.lua.load(r#"ObserveTriggerGroup("hunt", false)"#).exec().unwrap();
+        engine.apply();
+        engine.push(EngineAction::ParseWorldBytes(b"You see a rat\r\n".to_vec()));
+        engine.apply();
+        let fired: Option<bool> = engine.lua.globals().get("fired").unwrap();
+        assert_eq!(Some(true), fired);
+    }
+
+    #[test]
+    fn test_engine_load_order() {
+        let dir = TempDir::new("load");
+        let a = dir.join("a.lua");
+        let b = dir.join("b.lua");
+        std::fs::write(
+            &a,
+            r#"CreateTrigger("trigger-a", "trg", "^李四", 0, 1, function() error("boom") end)"#,
+        )
+        .unwrap();
+        std::fs::write(&b, "this is not lua").unwrap();
+        let mut engine = new_engine().unwrap();
+        engine.load_script(a.clone(), "init").unwrap();
+        assert!(engine.load_script(b.clone(), "LoadFile").is_err());
+        engine.apply();
+        let texts = |outputs: Vec<RuntimeOutput>| -> String {
+            outputs
+                .into_iter()
+                .filter_map(|o| match o {
+                    RuntimeOutput::ToUI(_, lines, _) => Some(lines),
+                    _ => None,
+                })
+                .flat_map(|lines| lines.into_vec())
+                .map(|l| l.plain_text())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#loadorder".to_owned(),
+        )));
+        let text = texts(engine.apply());
+        let pa = text.find("a.lua [init]").unwrap();
+        let pb = text.find("b.lua [LoadFile]").unwrap();
+        assert!(pa < pb);
+        // 触发器出错时提示定义文件
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "李四走了过来。\r\n",
+        )]));
+        let text = texts(engine.apply());
+        assert!(text.contains(&a.display().to_string()));
+    }
+
+    #[test]
+    fn test_engine_multiline_trigger() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.lua.load(
+            r#"
+            local m = function() Send("triggered") end
+            CreateTrigger("trigger-m", "trg", "^张三走了过来。\r\n李四走了过来。$", trigger_flag.Enabled, 2, m)
+            "#
+        ).exec().unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("张三走了过来。\r\n"),
+            RawLine::new("李四走了过来。\r\n"),
+        ]));
+        let mut evts = engine.apply();
+        let mut rawlines = RawLines::unbounded();
+        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
+        rawlines.push_line(RawLine::new("李四走了过来。\r\n"));
+        let mut lines = Lines::new();
+        lines.push_line(Line::new(vec![Span::new(
+            "张三走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        lines.push_line(Line::new(vec![Span::new(
+            "李四走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        assert_eq!(RuntimeOutput::ToUI(rawlines, lines, engine.scrollback.last_lineno() - 1), evts.remove(0));
+        assert_eq!(
+            RuntimeOutput::ToServer(b"triggered\n".to_vec()),
+            evts.remove(0)
+        );
+    }
+
+    #[test]
+    fn test_engine_wildcard_trigger() {
+        let mut engine = new_engine().unwrap();
+        // 默认使用utf8编码
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            local f = function(name, line, wildcards) Send(wildcards[1]) end
+            CreateTrigger("trigger-f", "trg", "^(.*)走了过来。$", trigger_flag.Enabled, 1, f)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "张三走了过来。\r\n",
+        )]));
+        let mut evts = engine.apply();
+        assert_eq!(2, evts.len());
+        let mut rawlines = RawLines::unbounded();
+        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
+        let mut lines = Lines::new();
+        lines.push_line(Line::new(vec![Span::new(
+            "张三走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        assert_eq!(RuntimeOutput::ToUI(rawlines, lines, engine.scrollback.last_lineno()), evts.remove(0));
+        assert_eq!(
+            RuntimeOutput::ToServer("张三\n".as_bytes().to_vec()),
+            evts.remove(0)
+        );
+    }
+
+    #[test]
+    fn test_engine_oneshot_trigger() {
+        let mut engine = new_engine().unwrap();
+        // 默认使用utf8编码
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.lua.load(r#"
+            local oneshot = function(name, line, wildcards) Send(wildcards[1]) end
+            CreateTrigger("trigger-oneshot", "trg", "^(.*)走了过来。$", trigger_flag.Enabled + trigger_flag.OneShot, 1, oneshot)
+        "#).exec().unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "张三走了过来。\r\n",
+        )]));
+        let mut evts = engine.apply();
+        let mut rawlines = RawLines::unbounded();
+        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
+        let mut lines = Lines::new();
+        lines.push_line(Line::new(vec![Span::new(
+            "张三走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        assert_eq!(RuntimeOutput::ToUI(rawlines, lines, engine.scrollback.last_lineno()), evts.remove(0));
+        assert_eq!(
+            RuntimeOutput::ToServer("张三\n".as_bytes().to_vec()),
+            evts.remove(0)
+        );
+        assert_eq!(0, engine.triggers.len());
+    }
+
+    #[test]
+    fn test_engine_classify() {
+        let mut config = crate::conf::Config::default();
+        config.classify.insert("chat".to_owned(), vec!["^【闲聊】".to_owned()]);
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let (chat, combat, classes): (bool, bool, Vec<String>) = engine
+            .lua
+            .load(r#"return IsChat("【闲聊】你好"), IsCombat("【闲聊】你好"), GetLineClasses("【闲聊】你好")"#)
+            .eval()
+            .unwrap();
+        assert!(chat);
+        assert!(!combat);
+        assert_eq!(vec!["chat".to_owned()], classes);
+    }
+
+    #[test]
+    fn test_engine_route_gag() {
+        let mut config = crate::conf::Config::default();
+        config.routes.push(crate::conf::Route {
+            pattern: "^张三".to_owned(),
+            class: String::new(),
+            action: crate::conf::RouteAction::Gag,
+            target: String::new(),
+        });
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            local f = function() Send("triggered") end
+            CreateTrigger("trigger-f", "trg", "走了过来", 0, 1, f)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("张三走了过来。\r\n"),
+            RawLine::new("李四走了过来。\r\n"),
+        ]));
+        let mut evts = engine.apply();
+        assert_eq!(2, evts.len());
+        let mut rawlines = RawLines::unbounded();
+        rawlines.push_line(RawLine::new("李四走了过来。\r\n"));
+        let mut lines = Lines::new();
+        lines.push_line(Line::new(vec![Span::new(
+            "李四走了过来。\r\n",
+            Style::default(),
+            Label::None,
+        )]));
+        assert_eq!(RuntimeOutput::ToUI(rawlines, lines, engine.scrollback.last_lineno()), evts.remove(0));
+        assert_eq!(
+            RuntimeOutput::ToServer(b"triggered\n".to_vec()),
+            evts.remove(0)
+        );
+    }
+
+    #[test]
+    fn test_engine_route_window() {
+        let mut config = crate::conf::Config::default();
+        config.routes.push(crate::conf::Route {
+            pattern: "^【闲聊】".to_owned(),
+            class: String::new(),
+            action: crate::conf::RouteAction::Window,
+            target: "chat".to_owned(),
+        });
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("【闲聊】张三：你好\r\n"),
+            RawLine::new("李四走了过来。\r\n"),
+        ]));
+        let evts = engine.apply();
+        // 路由的行发送至聊天窗口，同时保留在主窗格中
+        let window: Vec<(String, Vec<String>)> = evts
+            .iter()
+            .filter_map(|evt| match evt {
+                RuntimeOutput::ToWindow(target, lines) => Some((
+                    target.to_owned(),
+                    lines.clone().into_vec().iter().map(|l| l.plain_text()).collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![("chat".to_owned(), vec!["【闲聊】张三：你好".to_owned()])], window);
+        let main: Vec<String> = evts
+            .into_iter()
+            .filter_map(|evt| match evt {
+                RuntimeOutput::ToUI(_, lines, _) => Some(lines.into_vec()),
+                _ => None,
+            })
+            .flatten()
+            .map(|l| l.plain_text())
+            .collect();
+        assert_eq!(2, main.len());
+    }
+
+    #[test]
+    fn test_engine_parse_ansi() {
+        let engine = new_engine().unwrap();
+        let spans: mlua::Table = engine
+            .lua
+            .load(r#"return ParseAnsi("\27[31mhello\27[0m world")"#)
+            .eval()
+            .unwrap();
+        assert_eq!(2, spans.len().unwrap());
+        let first: mlua::Table = spans.get(1).unwrap();
+        assert_eq!("hello", first.get::<_, String>("text").unwrap());
+        assert_eq!("red", first.get::<_, String>("fg").unwrap());
+        let second: mlua::Table = spans.get(2).unwrap();
+        assert_eq!(" world", second.get::<_, String>("text").unwrap());
+    }
+
+    #[test]
+    fn test_engine_get_line() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("张三走了过来。\r\n"),
+            RawLine::new("李四走了过来。\r\n"),
+        ]));
+        engine.apply();
+        let line: String = engine.lua.load("return GetLine(2)").eval().unwrap();
+        assert_eq!("李四走了过来。", line);
+        let lines: Vec<String> = engine.lua.load("return GetLineRange(1, 5)").eval().unwrap();
+        assert_eq!(vec!["张三走了过来。", "李四走了过来。"], lines);
+        let line: Option<String> = engine.lua.load("return GetLine(3)").eval().unwrap();
+        assert!(line.is_none());
+    }
+
+    #[test]
+    fn test_engine_conf_send_rules() {
+        let mut config = crate::conf::Config::default();
+        config.trigger.push(crate::conf::SendRule {
+            name: String::new(),
+            pattern: "^(.*)走了过来。$".to_owned(),
+            send: "hi %1".to_owned(),
+            group: String::new(),
+            enabled: true,
+        });
+        config.alias.push(crate::conf::SendRule {
+            name: "kk".to_owned(),
+            pattern: "^kk (?P<target>.*)$".to_owned(),
+            send: "kill %<target>;hit %1".to_owned(),
+            group: "fight".to_owned(),
+            enabled: true,
+        });
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "kk rat".to_owned(),
+        )));
+        let mut evts = engine.apply();
+        assert_eq!(
+            RuntimeOutput::ToServer(b"kill rat\nhit rat\n".to_vec()),
+            evts.remove(0)
+        );
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "张三走了过来。\r\n",
+        )]));
+        let mut evts = engine.apply();
+        assert_eq!(
+            RuntimeOutput::ToServer("hi 张三\n".as_bytes().to_vec()),
+            evts.remove(1)
+        );
+        // 禁用后不再触发
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#manage disable conf-trigger-1".to_owned(),
+        )));
+        assert!(engine.apply().is_empty());
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "张三走了过来。\r\n",
+        )]));
+        assert_eq!(1, engine.apply().len());
+    }
+
+    #[test]
+    fn test_engine_echo_cmd() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.echo_cmd = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "n;e".to_owned(),
+        )));
+        let evts = engine.apply();
+        // 回显与命令交替输出
+        assert_eq!(4, evts.len());
+        match &evts[0] {
+            RuntimeOutput::ToUI(_, lines, _) => {
+                let texts: Vec<String> =
+                    lines.clone().into_vec().iter().map(|l| l.plain_text()).collect();
+                assert_eq!(vec!["> n".to_owned()], texts);
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(RuntimeOutput::ToServer(b"n\n".to_vec()), evts[1]);
+        // 以空格开头不回显
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            " secret".to_owned(),
+        )));
+        let evts = engine.apply();
+        assert_eq!(vec![RuntimeOutput::ToServer(b"secret\n".to_vec())], evts);
+    }
+
+    #[test]
+    fn test_engine_echo_merge() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.echo_cmd = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let echo_texts = |outputs: Vec<RuntimeOutput>| -> Vec<String> {
+            outputs
+                .into_iter()
+                .filter_map(|o| match o {
+                    RuntimeOutput::ToUI(_, lines, _) => Some(lines.into_vec()),
+                    _ => None,
+                })
+                .flatten()
+                .map(|l| l.plain_text())
+                .collect()
+        };
+        // 未开启合并时，先结束提示符行再另起一行
+        engine.push(EngineAction::ParseWorldBytes(b"hp> ".to_vec()));
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("kill rat".to_owned())));
+        assert_eq!(vec!["", "> kill rat"], echo_texts(engine.apply()));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#set echo_merge on".to_owned(),
+        )));
+        engine.apply();
+        engine.push(EngineAction::ParseWorldBytes(b"\r\nhp> ".to_vec()));
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("kill rat".to_owned())));
+        assert_eq!(vec!["kill rat"], echo_texts(engine.apply()));
+        // 之前的行已结束时正常回显
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("look".to_owned())));
+        assert_eq!(vec!["> look"], echo_texts(engine.apply()));
+    }
+
+    #[test]
+    fn test_engine_encode_fallback() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("say 好😀".to_owned())));
+        let mut expected = b"say ".to_vec();
+        expected.extend_from_slice(b"\xba\xc3?\n");
+        assert_eq!(vec![RuntimeOutput::ToServer(expected)], engine.apply());
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#set encode_fallback reject;say 😀".to_owned(),
+        )));
+        let outputs = engine.apply();
+        assert!(outputs.iter().all(|o| !matches!(o, RuntimeOutput::ToServer(_))));
+        match outputs.last() {
+            Some(RuntimeOutput::ToUI(_, lines, _)) => {
+                assert!(lines.clone().into_vec().last().unwrap().plain_text().contains('😀'));
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_engine_term_caps_mouse() {
+        let mut config = crate::conf::Config::default();
+        config.term.mouse = crate::conf::MouseMode::Off;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let mouse: bool = engine.lua.load("GetTermCaps().mouse").eval().unwrap();
+        assert!(!mouse);
+    }
+
+    #[test]
+    fn test_engine_set_get() {
+        let mut engine = new_engine().unwrap();
+        let mut run = |cmd: &str| -> Vec<String> {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.to_owned())));
+            engine
+                .apply()
+                .into_iter()
+                .flat_map(|o| match o {
+                    RuntimeOutput::ToUI(_, lines, _) => {
+                        lines.into_vec().iter().map(|l| l.plain_text()).collect()
+                    }
+                    RuntimeOutput::ToServer(bs) => vec![String::from_utf8(bs).unwrap()],
+                    _ => vec![],
+                })
+                .collect()
+        };
+        assert_eq!(vec!["n\n"], run("n"));
+        assert_eq!(vec!["echo_cmd = true"], run("#set echo_cmd on"));
+        // 设置立即生效
+        assert_eq!(vec!["> n", "n\n"], run("n"));
+        assert_eq!(vec!["echo_cmd = true"], run("#get echo_cmd"));
+        assert_eq!(1, run("#set max_alias_depth 0").len());
+        assert_eq!(vec!["max_alias_depth = 10"], run("#get max_alias_depth"));
+        assert_eq!(1, run("#get nothing").len());
+        // 未指定配置文件时无法保存
+        assert_eq!(1, run("#set save").len());
+        // 界面设置交由界面应用
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#set wrap off".to_owned())));
+        let outputs = engine.apply();
+        assert!(outputs.iter().any(|o| matches!(o, RuntimeOutput::UpdateTerm(term) if !term.wrap)));
+    }
+
+    #[test]
+    fn test_engine_fsm() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            quest = fsm.new{
+                name="quest",
+                initial="idle",
+                states={
+                    idle={
+                        events={{pattern="^你接受了任务。$", to="walking"}},
+                    },
+                    walking={
+                        on_enter=function(m) Send("north") end,
+                        on_exit=function(m) Send("stop") end,
+                        events={{pattern="^你到达了(.*)。$", action=function(m, line, wildcards)
+                            SetVariable("arrived", wildcards[1])
+                            return "done"
+                        end}},
+                    },
+                    done={},
+                },
+            }
+            quest:start()
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        // 未处于walking状态，不会触发
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "你到达了扬州。\r\n",
+        )]));
+        assert_eq!(1, engine.apply().len());
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "你接受了任务。\r\n",
+        )]));
+        let evts = engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"north\n".to_vec()), evts[1]);
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
+            "你到达了扬州。\r\n",
+        )]));
+        let evts = engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"stop\n".to_vec()), evts[1]);
+        assert_eq!(Some("扬州".to_owned()), engine.vars.get(&"arrived".to_owned()));
+        let state: String = engine.lua.load("return quest.state").eval().unwrap();
+        assert_eq!("done", state);
+    }
+
+    #[test]
+    fn test_engine_recursive_alias() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.max_alias_depth = 3;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateAlias("ping", "g", "^ping$", 0, function() Send("pong") end)
+            CreateAlias("pong", "g", "^pong$", 0, function() Send("ping") end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "ping".to_owned(),
+        )));
+        let evts = engine.apply();
+        assert_eq!(1, evts.len());
+        match &evts[0] {
+            RuntimeOutput::ToUI(_, lines, _) => {
+                let text = lines.clone().into_vec()[0].plain_text();
+                assert!(text.contains("ping -> pong -> ping -> pong"));
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        // 非递归的嵌套不受影响
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "north".to_owned(),
+        )));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"north\n".to_vec())], engine.apply());
+    }
+
+    #[test]
+    fn test_engine_send_order() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateAlias("a", "g", "^a$", 0, function() Send("x;c;y") end)
+            CreateAlias("c", "g", "^c$", 0, function() Send("z") end)
+            CreateTrigger("tr", "g", "^You see", 0, 1, function() Send("a") end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        let sent = |outputs: Vec<RuntimeOutput>| -> String {
+            outputs
+                .into_iter()
+                .filter_map(|o| match o {
+                    RuntimeOutput::ToServer(bs) => Some(String::from_utf8(bs).unwrap()),
+                    _ => None,
+                })
+                .collect()
+        };
+        // 别名展开的命令紧随别名，先于其后的命令
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("a;b".to_owned())));
+        assert_eq!("x\nz\ny\nb\n", sent(engine.apply()));
+        // 同一批次中，触发器发送的命令先于之后的操作
+        engine.push(EngineAction::ParseWorldBytes(b"You see a rat\r\n".to_vec()));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("b".to_owned())));
+        assert_eq!("x\nz\ny\nb\n", sent(engine.apply()));
+    }
+
+    #[test]
+    fn test_engine_record_play() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "record".to_owned();
+        let tmp = TempDir::new("record");
+        config.world.data_dir = tmp.path_string();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        // 名称不能指向宏目录之外
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#record start ../walk".to_owned(),
+        )));
+        engine.apply();
+        assert!(engine.recorder.is_none());
+        for cmd in &["#record start walk", "n;e", "#recordx", "look", "#record stop"] {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+                (*cmd).to_owned(),
+            )));
+            engine.apply();
+        }
+        let script = std::fs::read_to_string(engine.macro_path("walk")).unwrap();
+        let m = Macro::from_lua(&engine.lua, "walk", &script).unwrap();
+        let cmds: Vec<&str> = m.steps.iter().map(|s| &s.cmd[..]).collect();
+        // 仅排除录制与回放命令本身
+        assert_eq!(vec!["n;e", "#recordx", "look"], cmds);
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "#play walk 2".to_owned(),
+        )));
+        assert!(engine.apply().is_empty());
+        assert_eq!(2, engine.timers.len());
+    }
+
+    #[test]
+    fn test_engine_defs_reload() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "defs".to_owned();
+        let tmp = TempDir::new("defs");
+        config.world.data_dir = tmp.path_string();
+        config.runtime.def_files = vec!["rules.toml".to_owned()];
+        let data_dir = DataDir::new(&config);
+        data_dir.create_all().unwrap();
+        let path = data_dir.script_path("rules.toml");
+        std::fs::write(
+            &path,
+            "[[alias]]\nname = \"gg\"\npattern = \"^gg (.*)$\"\nsend = \"get %1\"\n\n\
+             [[timer]]\nname = \"save\"\ninterval_ms = 60000\ncallback = \"OnSave\"\n",
+        )
+        .unwrap();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        assert!(engine.aliases.get("gg").is_some());
+        assert_eq!(1, engine.timers.len());
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("gg sword".to_owned())));
+        let sent: Vec<_> = engine
+            .apply()
+            .into_iter()
+            .filter(|o| matches!(o, RuntimeOutput::ToServer(_)))
+            .collect();
+        assert_eq!(vec![RuntimeOutput::ToServer(b"get sword\n".to_vec())], sent);
+
+        std::fs::write(&path, "[[trigger]]\nname = \"hp\"\npattern = \"^hp\"\ncallback = \"OnHp\"\n")
+            .unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#reload defs".to_owned())));
+        engine.apply();
+        assert!(engine.aliases.get("gg").is_none());
+        assert_eq!(0, engine.timers.len());
+        assert!(engine.triggers.get("hp").is_some());
+
+        // 与脚本创建的规则重名的定义被跳过，重新加载时不删除脚本创建的规则
+        engine
+            .lua
+            .load(r#"CreateAlias("sc", "g", "^sc$", 0, function() Send("script") end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        std::fs::write(
+            &path,
+            "[[trigger]]\nname = \"hp\"\npattern = \"^hp\"\ncallback = \"OnHp\"\n\n\
+             [[alias]]\nname = \"sc\"\npattern = \"^sc$\"\nsend = \"defs\"\n",
+        )
+        .unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#reload defs".to_owned())));
+        let outputs = engine.apply();
+        assert!(outputs.iter().any(|o| matches!(o, RuntimeOutput::ToUI(..))));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#reload defs".to_owned())));
+        engine.apply();
+        assert!(engine.aliases.get("sc").is_some());
+        assert!(engine.triggers.get("hp").is_some());
+        let origin: Option<String> = engine
+            .lua
+            .globals()
+            .get::<_, mlua::Table>(GLOBAL_ALIAS_ORIGINS)
+            .unwrap()
+            .get("sc")
+            .unwrap();
+        assert!(origin.map(|o| !o.ends_with("rules.toml")).unwrap_or(true));
+
+        // 定义有误时提示错误并保留当前的定义
+        std::fs::write(
+            &path,
+            "[[trigger]]\nname = \"mp\"\npattern = \"^mp\"\ncallback = \"OnMp\"\n\n\
+             [[alias]]\nname = \"bad\"\npattern = \"(\"\nsend = \"x\"\n",
+        )
+        .unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#reload defs".to_owned())));
+        let outputs = engine.apply();
+        assert!(outputs.iter().any(|o| matches!(o, RuntimeOutput::ToUI(..))));
+        assert!(engine.triggers.get("hp").is_some());
+        assert!(engine.triggers.get("mp").is_none());
+
+        // 启动时定义有误不影响初始化
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        assert!(engine.triggers.get("mp").is_none());
+    }
+
+    #[test]
+    fn test_engine_autoload_dir() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "autoload".to_owned();
+        let tmp = TempDir::new("autoload");
+        config.world.data_dir = tmp.path_string();
+        let data_dir = DataDir::new(&config);
+        data_dir.create_all().unwrap();
+        let dir = data_dir.script_path("scripts.d");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("20-b.lua"), r#"order = order .. "b""#).unwrap();
+        std::fs::write(dir.join("10-a.lua"), r#"order = "a""#).unwrap();
+        std::fs::write(dir.join("15-broken.lua"), "this is not lua").unwrap();
+        std::fs::write(
+            dir.join("30-c.lua"),
+            r#"CreateAlias("c", "g", "^c$", 0, function() end)"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let order: String = engine.lua.globals().get("order").unwrap();
+        assert_eq!("ab", order);
+        assert_eq!(4, engine.loaded.len());
+        assert!(engine.loaded[1].error.is_some());
+        assert!(engine.aliases.get("c").is_some());
+
+        std::fs::remove_file(dir.join("30-c.lua")).unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#reload scripts".to_owned())));
+        engine.apply();
+        assert!(engine.aliases.get("c").is_none());
+        assert_eq!(3, engine.loaded.len());
+    }
+
+    #[test]
+    fn test_engine_reload_script() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "reload".to_owned();
+        let tmp = TempDir::new("reload");
+        config.world.data_dir = tmp.path_string();
+        config.runtime.init_script = "init.lua".to_owned();
+        let data_dir = DataDir::new(&config);
+        data_dir.create_all().unwrap();
+        std::fs::write(
+            data_dir.script_path("init.lua"),
+            r#"CreateAlias("a", "g", "^a$", 0, function() end)"#,
+        )
+        .unwrap();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine
+            .lua
+            .load(r#"CreateTimer("t", "", 10000, 1, function() end) scratch = 1"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        assert_eq!(1, engine.timers.len());
+
+        engine.lua.load("Reload()").exec().unwrap();
+        engine.apply();
+        assert!(engine.aliases.get("a").is_some());
+        assert_eq!(0, engine.timers.len());
+        let scratch: Option<i64> = engine.lua.globals().get("scratch").unwrap();
+        assert!(scratch.is_none());
+        assert_eq!(1, engine.loaded.len());
+    }
+
+    #[test]
+    fn test_engine_import_zmud() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "zmud".to_owned();
+        let tmp = TempDir::new("zmud");
+        config.world.data_dir = tmp.path_string();
+        let data_dir = DataDir::new(&config);
+        data_dir.create_all().unwrap();
+        std::fs::write(data_dir.script_path("a.txt"), "#TRIGGER {^hello} {wave}\n#ALIAS rr {rest}\n").unwrap();
+        std::fs::write(data_dir.script_path("b.txt"), "#TRIGGER {^bye} {bow}\n").unwrap();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::ImportZmud("a.txt".to_owned()));
+        engine.push(EngineAction::ImportZmud("b.txt".to_owned()));
+        engine.apply();
+        // 不同文件的同一行互不覆盖
+        assert!(engine.triggers.get("zmud-trigger-a-1").is_some());
+        assert!(engine.triggers.get("zmud-trigger-b-1").is_some());
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("rr now".to_owned())));
+        assert!(engine.apply().contains(&RuntimeOutput::ToServer(b"rest\n".to_vec())));
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("hello\r\n")]));
+        assert!(engine.apply().contains(&RuntimeOutput::ToServer(b"wave\n".to_vec())));
+    }
+
+    #[test]
+    fn test_engine_timer_remaining() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(r#"CreateTimer("timer-r", "", 10000, 1, function() end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        let remaining = |engine: &Engine| -> Option<u64> {
+            engine.lua.load(r#"return GetTimerRemaining("timer-r")"#).eval().unwrap()
+        };
+        assert!(remaining(&engine).unwrap() > 9000);
+        engine.lua.load(r#"EnableTimer("timer-r", false)"#).exec().unwrap();
+        engine.apply();
+        assert_eq!(None, remaining(&engine));
+        engine.lua.load(r#"EnableTimer("timer-r", true) ResetTimer("timer-r")"#).exec().unwrap();
+        engine.apply();
+        assert!(remaining(&engine).unwrap() > 9000);
+        assert_eq!(None, engine.lua.load(r#"return GetTimerRemaining("none")"#).eval::<Option<u64>>().unwrap());
+    }
+
+    #[test]
+    fn test_engine_repeat_last_cmd() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.repeat_last_on_empty = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let user_cmd = |engine: &mut Engine, cmd: &str| {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.to_owned())));
+            engine.apply()
+        };
+        assert_eq!(vec![RuntimeOutput::ToServer(b"look\n".to_vec())], user_cmd(&mut engine, "look"));
+        assert_eq!(
+            vec![
+                RuntimeOutput::FlashCmd("look".to_owned()),
+                RuntimeOutput::ToServer(b"look\n".to_vec())
+            ],
+            user_cmd(&mut engine, "")
+        );
+        // 禁止列表中的命令不重发
+        engine.lua.load(r#"SetRepeatDeny({"^kill "})"#).exec().unwrap();
+        engine.apply();
+        user_cmd(&mut engine, "kill rat");
+        let outputs = user_cmd(&mut engine, "");
+        assert!(!outputs.iter().any(|o| matches!(o, RuntimeOutput::ToServer(_))));
+        assert!(engine.lua.load(r#"SetRepeatDeny({"("})"#).exec().is_err());
+        engine.lua.load(r#"SetRepeatDeny({})"#).exec().unwrap();
+        engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"kill rat\n".to_vec()), user_cmd(&mut engine, "")[1]);
+    }
+
+    #[test]
+    fn test_engine_repeat_skips_secret() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.repeat_last_on_empty = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let user_cmd = |engine: &mut Engine, cmd: &str| {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.to_owned())));
+            engine.apply()
+        };
+        let sent = |outputs: Vec<RuntimeOutput>| -> Vec<u8> {
+            outputs
+                .into_iter()
+                .filter_map(|o| match o {
+                    RuntimeOutput::ToServer(bs) => Some(bs),
+                    _ => None,
+                })
+                .flatten()
+                .collect()
+        };
+        user_cmd(&mut engine, "look");
+        // 关闭回显时输入的密码不会被重发
+        engine.push(EngineAction::UpdateProtocols(Protocols::ECHO));
+        engine.apply();
+        assert_eq!(b"hunter2\n".to_vec(), sent(user_cmd(&mut engine, "hunter2")));
+        engine.push(EngineAction::UpdateProtocols(Protocols::empty()));
+        engine.apply();
+        assert_eq!(b"look\n".to_vec(), sent(user_cmd(&mut engine, "")));
+        // 以空格开头的命令及内置命令同样不记录
+        user_cmd(&mut engine, " tell zhao hunter2");
+        user_cmd(&mut engine, "#mark");
+        assert_eq!(b"look\n".to_vec(), sent(user_cmd(&mut engine, "")));
+    }
+
+    #[test]
+    fn test_engine_session_cmd() {
+        let mut engine = new_engine().unwrap();
+        let user_cmd = |engine: &mut Engine, cmd: &str| {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.to_owned())));
+            engine.apply()
+        };
+        assert_eq!(vec![RuntimeOutput::ManageSession(SessionCmd::List)], user_cmd(&mut engine, "#session"));
+        assert_eq!(
+            vec![RuntimeOutput::ManageSession(SessionCmd::Open("b".to_owned(), "host:1".to_owned()))],
+            user_cmd(&mut engine, "#session open b host:1")
+        );
+        assert_eq!(vec![RuntimeOutput::ManageSession(SessionCmd::Switch(2))], user_cmd(&mut engine, "#session 2"));
+        // 序号从1开始，参数错误时提示用法
+        for cmd in ["#session 0", "#session x", "#session open b"] {
+            let outputs = user_cmd(&mut engine, cmd);
+            assert!(outputs.iter().all(|o| matches!(o, RuntimeOutput::ToUI(..))), "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn test_engine_eval_repl() {
+        let mut engine = new_engine().unwrap();
+        let eval = |engine: &mut Engine, code: &str| -> Vec<String> {
+            engine.push(EngineAction::EvalRepl(code.to_owned()));
+            match engine.apply().pop() {
+                Some(RuntimeOutput::ToRepl(lines)) => {
+                    lines.into_vec().iter().map(|l| l.plain_text().trim_end().to_owned()).collect()
+                }
+                other => panic!("unexpected output {:?}", other),
+            }
+        };
+        // 与脚本共享全局环境
+        eval(&mut engine, "SetVariable('hp', '100')");
+        assert_eq!(vec!["> GetVariable('hp')", "\"100\""], eval(&mut engine, "GetVariable('hp')"));
+        assert_eq!(vec!["> for i = 1, 2 do"], eval(&mut engine, "for i = 1, 2 do"));
+        assert_eq!(vec![">> end"], eval(&mut engine, "end"));
+        // 取消后重新开始输入
+        eval(&mut engine, "if true then");
+        engine.push(EngineAction::CancelRepl);
+        assert_eq!(1, engine.apply().len());
+        assert_eq!(vec!["> 1", "1"], eval(&mut engine, "1"));
+        engine.push(EngineAction::CancelRepl);
+        assert!(engine.apply().is_empty());
+    }
+
+    #[test]
+    fn test_engine_pause_timers() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("look;#wait 20;north".to_owned())));
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#pause".to_owned())));
+        engine.apply();
+        // 暂停期间到期的任务被忽略，定时器保留
+        let schedule = engine.timers.schedule();
+        let task = schedule.pop_timeout(Duration::from_secs(1)).unwrap();
+        engine.push(EngineAction::ExecuteTimer(task));
+        assert!(engine.apply().iter().all(|o| !matches!(o, RuntimeOutput::ToServer(_))));
+        assert_eq!(1, engine.timers.len());
+        engine.push(EngineAction::PauseTimers(false));
+        engine.apply();
+        let task = schedule.pop_timeout(Duration::from_secs(1)).unwrap();
+        engine.push(EngineAction::ExecuteTimer(task));
+        assert!(engine.apply().contains(&RuntimeOutput::ToServer(b"north\n".to_vec())));
+    }
+
+    #[test]
+    fn test_engine_wait_token() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "look;#wait 10;north;#wait 10;south".to_owned(),
+        )));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"look\n".to_vec())], engine.apply());
+        assert_eq!(1, engine.timers.len());
+        // 定时器到期后依次执行其余命令
+        let schedule = engine.timers.schedule();
+        let mut sent = vec![];
+        while let Some(task) = schedule.pop_timeout(Duration::from_secs(1)) {
+            engine.push(EngineAction::ExecuteTimer(task));
+            for output in engine.apply() {
+                if let RuntimeOutput::ToServer(bs) = output {
+                    sent.push(String::from_utf8(bs).unwrap());
+                }
+            }
+            if engine.timers.len() == 0 {
+                break;
+            }
+        }
+        assert_eq!(vec!["north\n", "south\n"], sent);
+
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("n;#wait soon;s".to_owned())));
+        let outputs = engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"n\n".to_vec()), outputs[0]);
+        assert!(matches!(outputs[1], RuntimeOutput::ToUI(..)));
+        assert_eq!(0, engine.timers.len());
+
+        // 到期时刻溢出的等待时间被拒绝
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
+            "n;#wait 18446744073709551615;s".to_owned(),
+        )));
+        let outputs = engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"n\n".to_vec()), outputs[0]);
+        assert!(matches!(outputs[1], RuntimeOutput::ToUI(..)));
+        assert_eq!(0, engine.timers.len());
+    }
+
+    #[test]
+    fn test_engine_typed_vars() {
+        let engine = new_engine().unwrap();
+        let (hp, busy, count, name): (f64, bool, f64, String) = engine
+            .lua
+            .load(
+                r#"
+            SetVariables({hp=100, busy=true, name="张三"})
+            SetVarTable("bag", {"sword", gold=10})
+            IncrVar("count")
+            IncrVar("count", 2)
+            local bag = GetVarTable("bag")
+            return GetVarNumber("hp"), GetVarBool("busy"), GetVarNumber("count"), bag[1]
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(100.0, hp);
+        assert!(busy);
+        assert_eq!(3.0, count);
+        assert_eq!("sword", name);
+        assert_eq!(Some("3".to_owned()), engine.vars.get("count"));
+    }
+
+    #[test]
+    fn test_engine_trigger_style() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("who", "g", "^(?P<who>\\S+)说道：", 0, 1,
+                function(name, line, wildcards) Send("hi " .. wildcards.who) end, {fg = "yellow", capture = "who"})
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ParseWorldBytes(
+            "\x1b[33m张三\x1b[0m说道：你好\r\n李四说道：你好\r\n".as_bytes().to_vec(),
+        ));
+        let sent: Vec<Vec<u8>> = engine
+            .apply()
+            .into_iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToServer(bs) => Some(bs),
+                _ => None,
+            })
+            .collect();
+        // 仅黄色名字的行满足条件
+        assert_eq!(vec!["hi 张三\n".as_bytes().to_vec()], sent);
+        assert!(engine
+            .lua
+            .load(r#"CreateTrigger("bad", "g", "x", 0, 2, function() end, {fg = "yellow"})"#)
+            .exec()
+            .is_err());
+        assert!(engine
+            .lua
+            .load(r#"CreateTrigger("bad", "g", "x", 0, 1, function() end, {fg = "pink"})"#)
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_engine_newline_overwrite() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.newline = conf::NewlineMode::Overwrite;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine
+            .lua
+            .load(r#"CreateTrigger("done", "g", "^Loading 100%$", 0, 1, function() Send("go") end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        let mut lines = Lines::new();
+        let mut sent = vec![];
+        for chunk in [&b"Loading 10%\r"[..], b"Loading 50%\r\x00Loading 100%", b"\r\n"] {
+            engine.push(EngineAction::ParseWorldBytes(chunk.to_vec()));
+            for output in engine.apply() {
+                match output {
+                    RuntimeOutput::ToUI(_, styled, _) => styled.into_vec().into_iter().for_each(|l| lines.push_line(l)),
+                    RuntimeOutput::ToServer(bs) => sent.push(bs),
+                    _ => (),
+                }
+            }
+        }
+        // 回车后的文本覆盖当前行，触发器仅匹配覆盖后的内容
+        let lines = lines.into_vec();
+        assert_eq!(1, lines.len());
+        assert_eq!("Loading 100%", lines[0].plain_text());
+        assert_eq!(vec![b"go\n".to_vec()], sent);
+    }
+
+    #[test]
+    fn test_engine_world_frames() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.frame = conf::Frame {
+            tick_ms: 20,
+            max_lines: 2,
+            max_bytes: 0,
+        };
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let count_lines = |engine: &mut Engine| -> usize {
+            engine
+                .apply()
+                .into_iter()
+                .map(|output| match output {
+                    RuntimeOutput::ToUI(_, styled, _) => styled.into_vec().len(),
+                    _ => 0,
+                })
+                .sum()
+        };
+        engine
+            .lua
+            .load(r#"OnGmcp("Room.Info", function(package, data) gmcp_room = data.name end)"#)
+            .exec()
+            .unwrap();
+        let gmcp_room = |engine: &Engine| -> Option<String> { engine.lua.globals().get("gmcp_room").unwrap() };
+        engine.push(EngineAction::ParseWorldBytes(b"a\r\nb\r\nc\r\nd\r\ne\r\n".to_vec()));
+        assert_eq!(2, count_lines(&mut engine));
+        // 带外消息排在暂存的行之后处理
+        engine.push(EngineAction::ReceiveGmcp(br#"Room.Info {"name": "Center"}"#.to_vec()));
+        assert_eq!(0, count_lines(&mut engine));
+        assert_eq!(None, gmcp_room(&engine));
+        // 未到下个周期时不继续处理
+        engine.resume_world_lines();
+        assert_eq!(0, count_lines(&mut engine));
+        let wait = engine.world_backlog_wait().unwrap();
+        thread::sleep(wait);
+        engine.resume_world_lines();
+        assert_eq!(2, count_lines(&mut engine));
+        assert_eq!(None, gmcp_room(&engine));
+        thread::sleep(engine.world_backlog_wait().unwrap());
+        engine.resume_world_lines();
+        assert_eq!(1, count_lines(&mut engine));
+        assert_eq!(Some("Center".to_owned()), gmcp_room(&engine));
+        assert_eq!(None, engine.world_backlog_wait());
+    }
+
+    #[test]
+    fn test_engine_unknown_builtin() -> Result<()> {
+        let mut engine = new_engine()?;
+        // 未注册的#文本原样发送
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#foo bar;#".to_owned())));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"#foo bar\n#\n".to_vec())], engine.apply());
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#pause".to_owned())));
+        assert!(engine.apply().iter().all(|o| !matches!(o, RuntimeOutput::ToServer(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_engine_send_no_echo_raw() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.echo_cmd = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine
+            .lua
+            .load(r#"CreateAlias("pw", "g", "^secret$", 0, function() Send("alias") end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.lua.load(r#"SendNoEcho("secret") SendRaw("\255\249")"#).exec().unwrap();
+        let mut sent = vec![];
+        for output in engine.apply() {
+            match output {
+                RuntimeOutput::ToServer(bs) => sent.extend(bs),
+                RuntimeOutput::ToUI(..) => panic!("no-echo command echoed"),
+                _ => (),
+            }
+        }
+        // 不经过别名，原始字节不转义
+        assert_eq!(b"secret\n\xff\xf9".to_vec(), sent);
+    }
+
+    #[test]
+    fn test_engine_prompt_trigger() {
+        let mut engine = new_engine().unwrap();
+        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("prompt", "g", "^你确定吗", trigger_flag.Prompt, 1, function() Send("y") end)
+            CreateTrigger("line", "g", "^你确定吗", 0, 1, function() Send("line") end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        let sent = |evts: Vec<RuntimeOutput>| -> Vec<Vec<u8>> {
+            evts.into_iter()
+                .filter_map(|evt| match evt {
+                    RuntimeOutput::ToServer(bs) => Some(bs),
+                    _ => None,
+                })
+                .collect()
+        };
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("你确定吗？")]));
+        assert_eq!(vec![b"y\n".to_vec()], sent(engine.apply()));
+        // 同一行不重复触发
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("(y/n)")]));
+        assert!(sent(engine.apply()).is_empty());
+        // 行结束后普通触发器才会执行
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("\r\n")]));
+        assert_eq!(vec![b"line\n".to_vec()], sent(engine.apply()));
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("你确定吗？")]));
+        assert_eq!(vec![b"y\n".to_vec()], sent(engine.apply()));
+    }
+
+    #[test]
+    fn test_engine_dup_guard() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.dup_guard.enabled = true;
+        config.runtime.dup_guard.interval_ms = 60_000;
+        config.runtime.dup_guard.whitelist = vec!["^n$".to_owned()];
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let mut run = |cmd: &str| {
+            engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.to_owned())));
+            engine.apply()
+        };
+        assert_eq!(vec![RuntimeOutput::ToServer(b"buy\n".to_vec())], run("buy"));
+        // 重复命令被拦截，仅输出提示
+        let outputs = run("buy");
+        assert_eq!(1, outputs.len());
+        assert!(matches!(outputs[0], RuntimeOutput::ToUI(..)));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"buy\n".to_vec())], run("#confirm"));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"n\n".to_vec())], run("n"));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"n\n".to_vec())], run("n"));
+    }
+
+    #[test]
+    fn test_engine_dup_guard_script_send() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.dup_guard.enabled = true;
+        config.runtime.dup_guard.interval_ms = 60_000;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine
+            .lua
+            .load(
+                r#"
+            CreateTrigger("trigger-buy", "", "^伙计说道", 0, 1, function()
+                Send("buy")
+                Send("buy")
+            end)
+            "#,
+            )
+            .exec()
+            .unwrap();
+        engine.apply();
+        // 触发器发送的重复命令不受拦截
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("伙计说道：欢迎光临\r\n")]));
+        let sent: Vec<u8> = engine
+            .apply()
+            .into_iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToServer(bs) => Some(bs),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(b"buy\nbuy\n".to_vec(), sent);
+    }
+
+    #[test]
+    fn test_engine_queue_tag() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.queue_tag.pattern = r"^\[Q:(\d+)\]>".to_owned();
+        config.runtime.queue_tag.max_depth = 2;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let sent = |outputs: Vec<RuntimeOutput>| -> Vec<u8> {
+            outputs
+                .into_iter()
+                .filter_map(|o| match o {
+                    RuntimeOutput::ToServer(bs) => Some(bs),
+                    _ => None,
+                })
+                .flatten()
+                .collect()
+        };
+        engine.push(EngineAction::ParseWorldBytes(b"[Q:1]> ".to_vec()));
+        engine.apply();
+        assert_eq!(Some("1".to_owned()), engine.vars.get("queue_depth"));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("n;e;s".to_owned())));
+        assert_eq!(b"n\n".to_vec(), sent(engine.apply()));
+        // 服务器队列缩短后放行暂存的命令
+        engine.push(EngineAction::ParseWorldBytes(b"\r\n[Q:0]> ".to_vec()));
+        assert_eq!(b"e\ns\n".to_vec(), sent(engine.apply()));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("w;#queue clear".to_owned())));
+        assert!(sent(engine.apply()).is_empty());
+        // 等待排在暂存的命令之后，命令放行后才开始计时
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#queue clear".to_owned())));
+        engine.push(EngineAction::ParseWorldBytes(b"\r\n[Q:2]> ".to_vec()));
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("n;#wait 10;s".to_owned())));
+        assert!(sent(engine.apply()).is_empty());
+        assert_eq!(0, engine.timers.len());
+        engine.push(EngineAction::ParseWorldBytes(b"\r\n[Q:0]> ".to_vec()));
+        assert_eq!(b"n\n".to_vec(), sent(engine.apply()));
+        assert_eq!(1, engine.timers.len());
+    }
+
+    #[test]
+    fn test_engine_read_key() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(r#"ReadKey("确认？(y/n)", 500, function(key) SetVariable("answer", key or "timeout") end)"#)
+            .exec()
+            .unwrap();
+        let outputs = engine.apply();
+        assert!(outputs.contains(&RuntimeOutput::ReadKey(Some("确认？(y/n)".to_owned()))));
+        let id = engine.read_key.clone().unwrap();
+        engine.push(EngineAction::KeyRead(Some("y".to_owned())));
+        engine.apply();
+        assert_eq!(Some("y".to_owned()), engine.vars.get("answer"));
+        // 已读取按键后的超时被忽略
+        engine.push(EngineAction::KeyReadTimeout(id));
+        assert!(engine.apply().is_empty());
+        assert_eq!(Some("y".to_owned()), engine.vars.get("answer"));
+
+        engine
+            .lua
+            .load(r#"ReadKey("继续？", 500, function(key) SetVariable("answer", key or "timeout") end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        let id = engine.read_key.clone().unwrap();
+        engine.push(EngineAction::KeyReadTimeout(id));
+        assert_eq!(vec![RuntimeOutput::ReadKey(None)], engine.apply());
+        assert_eq!(Some("timeout".to_owned()), engine.vars.get("answer"));
+        // 超时后的按键不再调用回调
+        engine.push(EngineAction::KeyRead(Some("n".to_owned())));
+        engine.apply();
+        assert_eq!(Some("timeout".to_owned()), engine.vars.get("answer"));
+    }
 
     #[test]
-    fn test_engine_single_user_cmd() {
+    fn test_engine_show_menu() {
         let mut engine = new_engine().unwrap();
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "hp".to_owned(),
-        )));
-        let mut evts = engine.apply();
-        assert_eq!(1, evts.len());
+        engine
+            .lua
+            .load(r#"ShowMenu("选择", {"接受", "放弃"}, function(idx) SetVariable("choice", tostring(idx)) end)"#)
+            .exec()
+            .unwrap();
         assert_eq!(
-            RuntimeOutput::ToServer(b"hp\n".to_vec()),
-            evts.pop().unwrap()
+            vec![RuntimeOutput::ShowMenu("选择".to_owned(), vec!["接受".to_owned(), "放弃".to_owned()])],
+            engine.apply()
         );
+        engine.push(EngineAction::MenuChosen(Some(2)));
+        engine.apply();
+        assert_eq!(Some("2".to_owned()), engine.vars.get("choice"));
+        // 回调仅调用一次
+        engine.push(EngineAction::MenuChosen(None));
+        engine.apply();
+        assert_eq!(Some("2".to_owned()), engine.vars.get("choice"));
+        assert!(engine.lua.load(r#"ShowMenu("空", {}, function() end)"#).exec().is_err());
     }
 
     #[test]
-    fn test_engine_multi_user_cmds() {
+    fn test_engine_line_marks() {
         let mut engine = new_engine().unwrap();
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "hp;say hi".to_owned(),
-        )));
-        let mut evts = engine.apply();
-        assert_eq!(1, evts.len());
-        assert_eq!(
-            RuntimeOutput::ToServer(b"hp\nsay hi\n".to_vec()),
-            evts.pop().unwrap()
-        );
+        for text in &["战斗开始", "你被打了一拳", "张三告诉你：回城"] {
+            engine.push(EngineAction::SendLineToUI(Line::fmt_raw(*text), None));
+        }
+        engine.apply();
+        // 提示也会进入历史行，先标记最新一行
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#mark tell".to_owned())));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#mark 1 fight".to_owned())));
+        engine.apply();
+        let marks: Vec<_> = engine.line_marks.list().iter().map(|m| (m.lineno, &m.label[..])).collect();
+        assert_eq!(vec![(1, "fight"), (3, "tell")], marks);
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#jump".to_owned())));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#jump prev".to_owned())));
+        let text: Vec<String> = engine
+            .apply()
+            .into_iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToUI(_, lines, _) => Some(lines.into_vec()),
+                _ => None,
+            })
+            .flatten()
+            .map(|l| l.plain_text())
+            .collect();
+        assert!(text.iter().any(|l| l.contains("3 张三告诉你：回城")));
+        // 跳转到书签1时显示其后的行
+        assert!(text.iter().any(|l| l.contains("1 战斗开始")));
+        assert!(text.iter().any(|l| l.contains("2 你被打了一拳")));
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#mark 99".to_owned())));
+        engine.apply();
+        assert_eq!(2, engine.line_marks.list().len());
+    }
 
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "hp\nsay hi".to_owned(),
-        )));
-        let mut evts = engine.apply();
-        assert_eq!(1, evts.len());
-        assert_eq!(
-            RuntimeOutput::ToServer(b"hp\nsay hi\n".to_vec()),
-            evts.pop().unwrap()
+    #[test]
+    fn test_engine_dump_models() {
+        let mut config = crate::conf::Config::default();
+        config.alias.push(crate::conf::SendRule {
+            name: String::new(),
+            pattern: "^gg$".to_owned(),
+            send: "get gold".to_owned(),
+            group: String::new(),
+            enabled: true,
+        });
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let dir = TempDir::new("dump");
+        let script = dir.join("fight.lua");
+        std::fs::write(
+            &script,
+            r#"CreateTrigger("hit", "fight", "^你被打", 32768, 1, function() end)
+            CreateTimer("heal", "fight", 1500, 0, function() end)"#,
+        )
+        .unwrap();
+        engine.push(EngineAction::LoadFile(script.to_string_lossy().into_owned()));
+        engine.apply();
+        let out = dir.join("models.json");
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(format!(
+            "#dump models {}",
+            out.display()
+        ))));
+        engine.apply();
+        let dump: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+        let hit = &dump["trigger"][0];
+        assert_eq!("hit", hit["name"]);
+        assert_eq!(true, hit["oneshot"]);
+        assert!(hit.get("flags").is_none());
+        assert!(hit["source"].as_str().unwrap().ends_with("fight.lua"));
+        assert!(hit.get("send").is_none());
+        assert!(hit.get("callback").is_none());
+        let alias = &dump["alias"][0];
+        assert_eq!("conf-alias-1", alias["name"]);
+        assert_eq!("get gold", alias["send"]);
+        assert_eq!("conf", alias["group"]);
+        assert_eq!(1500, dump["timer"][0]["interval_ms"]);
+    }
+
+    #[test]
+    fn test_engine_dump_models_reload() {
+        let tmp = TempDir::new("dump-reload");
+        let dump_defs = |def_file: &str, text: Option<&str>| -> String {
+            let mut config = crate::conf::Config::default();
+            config.world.name = "dump".to_owned();
+            config.world.data_dir = tmp.path_string();
+            config.runtime.def_files = vec![def_file.to_owned()];
+            let data_dir = DataDir::new(&config);
+            data_dir.create_all().unwrap();
+            if let Some(text) = text {
+                std::fs::write(data_dir.script_path(def_file), text).unwrap();
+            }
+            let mut engine = Engine::new(&config);
+            engine.init().unwrap();
+            engine.apply();
+            engine.exec_dump("models models.json").unwrap();
+            std::fs::read_to_string(data_dir.state_path("models.json")).unwrap()
+        };
+        let first = dump_defs(
+            "rules.toml",
+            Some(
+                "[[trigger]]\nname = \"hp\"\npattern = \"^气血：(\\\\d+)\"\ncallback = \"OnHp\"\n\
+                 match_lines = 2\noneshot = true\n\n\
+                 [[alias]]\nname = \"gg\"\npattern = \"^gg (.*)$\"\nsend = \"get %1\"\nkeep_evaluating = true\n\n\
+                 [[timer]]\nname = \"save\"\ngroup = \"auto\"\ninterval_ms = 60000\nsend = \"save\"\n\
+                 enabled = false\nfixed_delay = true\n",
+            ),
         );
+        // 导出结果可以作为定义文件重新加载，除来源外与原定义一致
+        std::fs::copy(tmp.path().join("dump/state/models.json"), tmp.path().join("dump/scripts/models.json")).unwrap();
+        let second = dump_defs("models.json", None);
+        let strip = |text: &str| -> serde_json::Value {
+            let mut dump: serde_json::Value = serde_json::from_str(text).unwrap();
+            for kind in &["trigger", "alias", "timer"] {
+                for model in dump[kind].as_array_mut().unwrap() {
+                    model.as_object_mut().unwrap().remove("source");
+                }
+            }
+            dump
+        };
+        assert_eq!(strip(&first), strip(&second));
+        let dump = strip(&first);
+        assert_eq!("OnHp", dump["trigger"][0]["callback"]);
+        assert_eq!("get %1", dump["alias"][0]["send"]);
+        assert_eq!(false, dump["timer"][0]["enabled"]);
     }
 
     #[test]
-    fn test_engine_simple_alias() {
+    fn test_engine_registers() {
         let mut engine = new_engine().unwrap();
-        engine
+        engine.push(EngineAction::SendLineToUI(Line::fmt_raw("张三告诉你：你好"), None));
+        engine.apply();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#yank 1 t".to_owned())));
+        engine.apply();
+        let (tell, unnamed): (String, Option<String>) = engine
             .lua
             .load(
                 r#"
-        local n = function() Send("north") end
-        CreateAlias("alias-n", "map", "^n$", alias_flag.Enabled, n)
-        "#,
+            local tell = GetClipboard("t")
+            SetClipboard("reply")
+            return tell, GetClipboard()
+            "#,
             )
+            .eval()
+            .unwrap();
+        assert_eq!("张三告诉你：你好", tell);
+        assert_eq!(Some("reply".to_owned()), unnamed);
+    }
+
+    #[test]
+    fn test_engine_trace() {
+        let mut engine = new_engine().unwrap();
+        engine
+            .lua
+            .load(r#"CreateTrigger("hit", "g", "^你被打了", 0, 1, function() Send("heal") end)"#)
             .exec()
             .unwrap();
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("你被打了一拳\r\n"),
+            RawLine::new("天气晴朗\r\n"),
+        ]));
         engine.apply();
-        // start user output
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "n".to_owned(),
-        )));
-        let mut outputs = engine.apply();
-        assert_eq!(1, outputs.len());
-        assert_eq!(
-            RuntimeOutput::ToServer(b"north\n".to_vec()),
-            outputs.pop().unwrap()
-        );
+        let traces: Vec<_> = engine.tracer.last(2).cloned().collect();
+        assert_eq!(2, traces.len());
+        assert_eq!("你被打了一拳", traces[0].line);
+        assert_eq!(vec!["hit"], traces[0].triggers);
+        assert!(traces[0].actions.iter().any(|(_, a)| a == "send heal"));
+        assert!(traces[1].triggers.is_empty());
     }
 
     #[test]
-    fn test_engine_complex_alias() {
-        let mut engine = new_engine().unwrap();
+    fn test_engine_trace_export() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "trace".to_owned();
+        let tmp = TempDir::new("trace");
+        config.world.data_dir = tmp.path_string();
+        DataDir::new(&config).create_all().unwrap();
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("天气晴朗\r\n")]));
+        engine.apply();
+        // 不能写入日志目录之外
+        for file in &["../trace.txt", "/tmp/trace.txt"] {
+            assert!(engine.exec_trace(&format!("export {}", file)).is_err());
+        }
+        assert!(!tmp.path().join("trace/trace.txt").exists());
+        engine.exec_trace("export trace.txt").unwrap();
+        assert!(tmp.path().join("trace/logs/trace.txt").exists());
+    }
+
+    #[test]
+    fn test_engine_status_parser() {
+        let mut config = crate::conf::Config::default();
+        config.runtime.status_parser = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
         engine
             .lua
-            .load(
-                r#"
-        local m = function(name, line, wildcards) Send(wildcards[1]..wildcards[2]) end
-        CreateAlias("alias-m", "number", "^num (\\d+)\\s+(\\d+)$", alias_flag.Enabled, m) 
-        "#,
-            )
+            .load(r#"OnStatus("hp", function(t) SetVariable("neili", t.neili.cur .. "/" .. t.neili.max) end)"#)
             .exec()
             .unwrap();
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("hp".to_owned())));
+        engine.push(EngineAction::ProcessWorldLines(vec![
+            RawLine::new("┌───个人状态───┐\r\n"),
+            RawLine::new("│【气血】 1800 / 2000 [ 95%] │【内力】 100 / 3000 (+ 0) │\r\n"),
+            RawLine::new("└──────────┘\r\n"),
+        ]));
         engine.apply();
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "x;num 123 456".to_owned(),
-        )));
-        let mut outputs = engine.apply();
-        assert_eq!(1, outputs.len());
-        assert_eq!(
-            RuntimeOutput::ToServer(b"x\n123456\n".to_vec()),
-            outputs.pop().unwrap()
-        );
+        assert_eq!(Some("100/3000".to_owned()), engine.vars.get("neili"));
+        assert_eq!(Some("2000".to_owned()), engine.vars.get("hp.qi.max"));
+        assert!(engine.status.is_none());
     }
 
     #[test]
-    fn test_engine_alias_add_trigger() {
+    fn test_engine_global_vars() {
         let mut engine = new_engine().unwrap();
-        engine
+        let (master, kills, global_kills): (String, String, String) = engine
             .lua
             .load(
                 r#"
-            local addtr = function()
-                local trcb = function() end
-                CreateTrigger("tr1", "trg", "^say hi$", trigger_flag.Enabled, 1, trcb)
-            end
-            CreateAlias("alias-tr", "addtr", "^addtr$", alias_flag.Enabled, addtr)
+            SetGlobalVariable("master", "岳不群")
+            SetGlobalVariable("kills", "1")
+            SetVariable("kills", "5")
+            return GetVariable("master"), GetVariable("kills"), GetGlobalVariable("kills")
             "#,
             )
-            .exec()
+            .eval()
             .unwrap();
+        assert_eq!("岳不群", master);
+        assert_eq!("5", kills);
+        assert_eq!("1", global_kills);
         engine.apply();
-        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(
-            "addtr".to_owned(),
-        )));
-        let outputs = engine.apply();
-        assert!(outputs.is_empty());
-        assert_eq!(1, engine.triggers.len());
     }
 
     #[test]
-    fn test_engine_simple_trigger() {
+    fn test_engine_bookmarks() {
         let mut engine = new_engine().unwrap();
-        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE rooms(id, name, code, description, exits, zone, mapinfo, blockzone);
+             CREATE TABLE paths(startid, endid, path, endcode, weight, enabled, category, mapchange, blockers);
+             INSERT INTO rooms VALUES (1, '扬州广场', 'yz1', '', '', 'yz', '', '');",
+        )
+        .unwrap();
+        engine.mapper = Some(init_mapper(&engine.lua, conn, None).unwrap());
         engine
             .lua
             .load(
                 r#"
-            local f = function() Send("triggered") end
-            CreateTrigger("trigger-f", "trg", "^张三走了过来。$", trigger_flag.Enabled, 1, f)
+            SetRoomNote(1, "钱庄在北边")
+            SetBookmark("yz", 1)
+            SetWalker(function(roomid, name) Send("walk " .. roomid .. " " .. name) end)
             "#,
             )
             .exec()
             .unwrap();
-        engine.apply();
-        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
-            "张三走了过来。\r\n",
-        )]));
-        let mut evts = engine.apply();
-        assert_eq!(2, evts.len());
-        let mut rawlines = RawLines::unbounded();
-        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
-        let mut lines = Lines::new();
-        lines.push_line(Line::new(vec![Span::new(
-            "张三走了过来。\r\n",
-            Style::default(),
-            Label::None,
-        )]));
-        assert_eq!(RuntimeOutput::ToUI(rawlines, lines), evts.remove(0));
-        assert_eq!(
-            RuntimeOutput::ToServer(b"triggered\n".to_vec()),
-            evts.remove(0)
-        );
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#go yz".to_owned())));
+        assert_eq!(vec![RuntimeOutput::ToServer(b"walk 1 yz\n".to_vec())], engine.apply());
+        let note: String = engine.lua.load("return GetBookmark('yz').note").eval().unwrap();
+        assert_eq!("钱庄在北边", note);
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("#bookmarks".to_owned())));
+        let outputs = engine.apply();
+        assert_eq!(1, outputs.len());
     }
 
     #[test]
-    fn test_engine_multiline_trigger() {
-        let mut engine = new_engine().unwrap();
-        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
-        engine.lua.load(
-            r#"
-            local m = function() Send("triggered") end
-            CreateTrigger("trigger-m", "trg", "^张三走了过来。\r\n李四走了过来。$", trigger_flag.Enabled, 2, m)
-            "#
-        ).exec().unwrap();
-        engine.apply();
-        engine.push(EngineAction::ProcessWorldLines(vec![
-            RawLine::new("张三走了过来。\r\n"),
-            RawLine::new("李四走了过来。\r\n"),
-        ]));
-        let mut evts = engine.apply();
-        let mut rawlines = RawLines::unbounded();
-        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
-        rawlines.push_line(RawLine::new("李四走了过来。\r\n"));
-        let mut lines = Lines::new();
-        lines.push_line(Line::new(vec![Span::new(
-            "张三走了过来。\r\n",
-            Style::default(),
-            Label::None,
-        )]));
-        lines.push_line(Line::new(vec![Span::new(
-            "李四走了过来。\r\n",
-            Style::default(),
-            Label::None,
-        )]));
-        assert_eq!(RuntimeOutput::ToUI(rawlines, lines), evts.remove(0));
-        assert_eq!(
-            RuntimeOutput::ToServer(b"triggered\n".to_vec()),
-            evts.remove(0)
-        );
+    fn test_engine_map_module() {
+        let engine = new_engine().unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE rooms(id, name, code, description, exits, zone, mapinfo, blockzone);
+             CREATE TABLE paths(startid, endid, path, endcode, weight, enabled, category, mapchange, blockers);
+             CREATE TABLE zones(id, code, name, centercode);
+             INSERT INTO rooms VALUES (1, '扬州广场', 'yz1', '', 'north', 'yz', '', '');
+             INSERT INTO rooms VALUES (2, '北门', 'yz2', '', 'south', 'yz', '', '');
+             INSERT INTO paths VALUES (1, 2, 'n', 'yz2', 1, 1, 1, 0, '');
+             INSERT INTO zones VALUES ('1', 'yz', '扬州', 'yz1');",
+        )
+        .unwrap();
+        init_mapper(&engine.lua, conn, None).unwrap();
+        let (name, exit, zone, rooms, missing, same): (String, u32, String, usize, bool, bool) = engine
+            .lua
+            .load(
+                r#"
+            local map = require("map")
+            local room = map.room(1)
+            SetRoomNote(1, "钱庄在北边")
+            assert(room:note() == "钱庄在北边")
+            assert(tostring(room) == "room(1 扬州广场)")
+            local zone = map.zone(room.zone)
+            return room.name, room:exits()[1].endid, zone.name, #zone:rooms(),
+                map.room(3) == nil, map.find("北门")[1] == map.room(2)
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!("扬州广场", name);
+        assert_eq!(2, exit);
+        assert_eq!("扬州", zone);
+        assert_eq!(2, rooms);
+        assert!(missing);
+        assert!(same);
     }
 
     #[test]
-    fn test_engine_wildcard_trigger() {
+    fn test_engine_transformer() {
         let mut engine = new_engine().unwrap();
-        // 默认使用utf8编码
-        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
         engine
             .lua
             .load(
                 r#"
-            local f = function(name, line, wildcards) Send(wildcards[1]) end
-            CreateTrigger("trigger-f", "trg", "^(.*)走了过来。$", trigger_flag.Enabled, 1, f)
+            CreateTransformer("t2s", 1, function(s) return (s:gsub("張", "张")) end)
+            CreateTrigger("greet", "g", "^张三", 0, 1, function() Send("hi") end)
             "#,
             )
             .exec()
             .unwrap();
         engine.apply();
-        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
-            "张三走了过来。\r\n",
-        )]));
-        let mut evts = engine.apply();
-        assert_eq!(2, evts.len());
-        let mut rawlines = RawLines::unbounded();
-        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
-        let mut lines = Lines::new();
-        lines.push_line(Line::new(vec![Span::new(
-            "张三走了过来。\r\n",
-            Style::default(),
-            Label::None,
-        )]));
-        assert_eq!(RuntimeOutput::ToUI(rawlines, lines), evts.remove(0));
-        assert_eq!(
-            RuntimeOutput::ToServer("张三\n".as_bytes().to_vec()),
-            evts.remove(0)
-        );
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("張三走了过来。\r\n")]));
+        let mut outputs = engine.apply();
+        assert_eq!(RuntimeOutput::ToServer(b"hi\n".to_vec()), outputs.pop().unwrap());
+        match outputs.pop().unwrap() {
+            RuntimeOutput::ToUI(raw, lines, _) => {
+                assert!(raw.into_vec()[0].as_ref().contains("张三走了过来。"));
+                assert_eq!("张三走了过来。", lines.into_vec()[0].plain_text());
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        let stats = engine.transformers.list();
+        assert_eq!(1, stats.iter().find(|t| t.0 == "t2s").unwrap().3.calls);
+        // 执行出错时显示错误，文本不变
+        engine
+            .lua
+            .load(r#"CreateTransformer("bad", 2, function(s) error("boom") end)"#)
+            .exec()
+            .unwrap();
+        engine.apply();
+        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new("天气晴朗\r\n")]));
+        let texts: Vec<String> = engine
+            .apply()
+            .into_iter()
+            .flat_map(|output| match output {
+                RuntimeOutput::ToUI(_, lines, _) => lines.into_vec(),
+                _ => vec![],
+            })
+            .map(|line| line.plain_text())
+            .collect();
+        assert!(texts.iter().any(|t| t == "天气晴朗"));
+        assert!(texts.iter().any(|t| t.contains("boom")));
     }
 
     #[test]
-    fn test_engine_oneshot_trigger() {
-        let mut engine = new_engine().unwrap();
-        // 默认使用utf8编码
-        engine.push(EngineAction::SwitchCodec(Codec::Utf8));
-        engine.lua.load(r#"
-            local oneshot = function(name, line, wildcards) Send(wildcards[1]) end
-            CreateTrigger("trigger-oneshot", "trg", "^(.*)走了过来。$", trigger_flag.Enabled + trigger_flag.OneShot, 1, oneshot)
-        "#).exec().unwrap();
-        engine.apply();
-        engine.push(EngineAction::ProcessWorldLines(vec![RawLine::new(
-            "张三走了过来。\r\n",
-        )]));
-        let mut evts = engine.apply();
-        let mut rawlines = RawLines::unbounded();
-        rawlines.push_line(RawLine::new("张三走了过来。\r\n"));
-        let mut lines = Lines::new();
-        lines.push_line(Line::new(vec![Span::new(
-            "张三走了过来。\r\n",
-            Style::default(),
-            Label::None,
-        )]));
-        assert_eq!(RuntimeOutput::ToUI(rawlines, lines), evts.remove(0));
-        assert_eq!(
-            RuntimeOutput::ToServer("张三\n".as_bytes().to_vec()),
-            evts.remove(0)
-        );
-        assert_eq!(0, engine.triggers.len());
+    fn test_engine_screen_text() {
+        let engine = new_engine().unwrap();
+        engine.screen_view().update(vec![
+            Line::fmt_raw("张三走了过来。"),
+            Line::fmt_with_style("你好", Style::default().fg(Color::Red)),
+        ]);
+        let (n, text, fg): (usize, String, String) = engine
+            .lua
+            .load(
+                r#"
+            local rows = GetScreenText(1)
+            return #GetScreenText(), rows[1].text, rows[1].spans[1].fg
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(2, n);
+        assert_eq!("你好", text);
+        assert_eq!("red", fg);
+    }
+
+    #[test]
+    fn test_engine_offline_queue() {
+        let mut config = crate::conf::Config::default();
+        config.world.name = "offline".to_owned();
+        let tmp = TempDir::new("offline");
+        config.world.data_dir = tmp.path_string();
+        config.runtime.offline_queue = "queue.jsonl".to_owned();
+        config.runtime.echo_cmd = true;
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        engine.push(EngineAction::WorldDisconnected);
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd("look;n".to_owned())));
+        match &engine.apply()[..] {
+            [RuntimeOutput::ToUI(_, lines, _)] => assert_eq!(2, lines.clone().into_vec().len()),
+            other => panic!("unexpected outputs {:?}", other),
+        }
+        // 敏感命令不显示内容，也不写入文件
+        engine.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(" hunter2".to_owned())));
+        match &engine.apply()[..] {
+            [RuntimeOutput::ToUI(_, lines, _)] => {
+                assert!(!lines.clone().into_vec()[0].plain_text().contains("hunter2"))
+            }
+            other => panic!("unexpected outputs {:?}", other),
+        }
+        let saved = std::fs::read_to_string(DataDir::new(&config).state_path("queue.jsonl")).unwrap();
+        assert!(saved.contains("look") && !saved.contains("hunter2"));
+        // 未连接时保留队列
+        engine.push(EngineAction::FlushOfflineQueue);
+        assert!(engine.apply().iter().all(|o| !matches!(o, RuntimeOutput::ToServer(_))));
+        engine.push(EngineAction::WorldConnected);
+        engine.push(EngineAction::FlushOfflineQueue);
+        // 与直接输入一样回显并发送
+        let outputs = engine.apply();
+        let texts: Vec<String> = outputs
+            .iter()
+            .filter_map(|o| match o {
+                RuntimeOutput::ToUI(_, lines, _) => Some(lines.clone().into_vec()),
+                _ => None,
+            })
+            .flatten()
+            .map(|l| l.plain_text())
+            .collect();
+        assert!(texts.contains(&"> look".to_owned()));
+        assert!(texts.contains(&"> n".to_owned()));
+        assert!(outputs.contains(&RuntimeOutput::ToServer(b"look\n".to_vec())));
+        assert!(outputs.contains(&RuntimeOutput::ToServer(b"n\n".to_vec())));
+        assert!(outputs.contains(&RuntimeOutput::ToServer(b"hunter2\n".to_vec())));
+        assert!(texts.iter().all(|t| !t.contains("hunter2")));
+        assert!(engine.offline_queue.as_ref().unwrap().is_empty());
     }
 
     fn new_engine() -> Result<Engine> {
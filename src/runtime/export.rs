@@ -0,0 +1,190 @@
+use crate::datadir::DataDir;
+use crate::error::Result;
+use crate::runtime::vars::Variables;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde_json::{Map, Number, Value as Json};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// 写入套接字连接的超时，超时的连接被关闭
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// 导出目标
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    // 每次变化时替换整个文件
+    File(PathBuf),
+    // 本地套接字，每次变化时向所有连接写入一行JSON
+    Socket(PathBuf),
+}
+
+impl Target {
+    /// 以“unix:”开头时为本地套接字，否则为文件，相对路径位于世界的state目录
+    pub fn parse(target: &str, data_dir: &DataDir) -> Self {
+        match target.strip_prefix("unix:") {
+            Some(path) => Target::Socket(data_dir.state_path(path)),
+            None => Target::File(data_dir.state_path(target)),
+        }
+    }
+}
+
+/// 定期将选定的变量导出为JSON，供外部程序（如直播叠加层）读取
+///
+/// 数值变量（包括IncrVar维护的计数）导出为JSON数值，其余为字符串
+pub struct Exporter {
+    vars: Variables,
+    patterns: Vec<String>,
+    target: Target,
+    interval: Duration,
+    // 上次导出的内容，未变化时不重复写入
+    last: Option<String>,
+}
+
+impl Exporter {
+    pub fn new(vars: Variables, patterns: Vec<String>, target: Target, interval: Duration) -> Self {
+        Self {
+            vars,
+            patterns,
+            target,
+            interval,
+            last: None,
+        }
+    }
+
+    /// 生成当前变量的JSON，与上次相同时返回None
+    pub fn render(&mut self) -> Option<String> {
+        let vars: Map<String, Json> = self
+            .vars
+            .select(&self.patterns)
+            .into_iter()
+            .map(|(name, value)| (name, to_json(value)))
+            .collect();
+        let json = Json::Object(vars).to_string();
+        if self.last.as_ref() == Some(&json) {
+            return None;
+        }
+        self.last = Some(json.clone());
+        Some(json)
+    }
+
+    /// 启动导出线程，stop的发送端释放后线程退出
+    pub fn spawn(mut self, stop: Receiver<()>) -> Result<JoinHandle<()>> {
+        let handle = match self.target.clone() {
+            Target::File(path) => thread::spawn(move || loop {
+                if let Some(json) = self.render() {
+                    if let Err(e) = write_file(&path, &json) {
+                        log::warn!("export to {} error {}", path.display(), e);
+                    }
+                }
+                if !wait(&stop, self.interval) {
+                    return;
+                }
+            }),
+            Target::Socket(path) => {
+                // 移除上次运行残留的套接字文件
+                let _ = fs::remove_file(&path);
+                let listener = UnixListener::bind(&path)?;
+                listener.set_nonblocking(true)?;
+                thread::spawn(move || {
+                    let mut conns: Vec<UnixStream> = vec![];
+                    loop {
+                        let json = self.render();
+                        // 新连接立即收到最近一次的内容
+                        while let Some(mut conn) = accept(&listener) {
+                            if let Some(last) = self.last.as_ref().filter(|_| json.is_none()) {
+                                if writeln!(conn, "{}", last).is_err() {
+                                    continue;
+                                }
+                            }
+                            conns.push(conn);
+                        }
+                        if let Some(json) = json {
+                            conns.retain_mut(|conn| writeln!(conn, "{}", json).is_ok());
+                        }
+                        if !wait(&stop, self.interval) {
+                            let _ = fs::remove_file(&path);
+                            return;
+                        }
+                    }
+                })
+            }
+        };
+        Ok(handle)
+    }
+}
+
+// 等待下次导出，需要退出时返回false
+fn wait(stop: &Receiver<()>, interval: Duration) -> bool {
+    matches!(stop.recv_timeout(interval), Err(RecvTimeoutError::Timeout))
+}
+
+// 接受一个等待中的连接，没有时返回None
+fn accept(listener: &UnixListener) -> Option<UnixStream> {
+    match listener.accept() {
+        Ok((conn, _)) => {
+            if conn.set_nonblocking(false).and_then(|_| conn.set_write_timeout(Some(WRITE_TIMEOUT))).is_err() {
+                return None;
+            }
+            Some(conn)
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+        Err(e) => {
+            log::warn!("accept export connection error {}", e);
+            None
+        }
+    }
+}
+
+// 先写入临时文件再替换，避免读取方看到写了一半的内容
+fn write_file(path: &PathBuf, json: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(json.as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn to_json(value: String) -> Json {
+    if let Ok(n) = value.trim().parse::<i64>() {
+        return Json::from(n);
+    }
+    match value.trim().parse::<f64>().ok().and_then(Number::from_f64) {
+        Some(n) => Json::Number(n),
+        None => Json::String(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exporter_render() {
+        let vars = Variables::new();
+        vars.insert("hp".to_owned(), "120".to_owned());
+        vars.insert("hp_max".to_owned(), "200".to_owned());
+        vars.insert("quest".to_owned(), "杀死老鼠".to_owned());
+        vars.insert("secret".to_owned(), "x".to_owned());
+        vars.incr("kills", 1.5).unwrap();
+        let patterns = vec!["hp*".to_owned(), "quest".to_owned(), "kills".to_owned(), "mp".to_owned()];
+        let mut exporter = Exporter::new(vars.clone(), patterns, Target::File(PathBuf::new()), Duration::from_secs(1));
+        let json: Json = serde_json::from_str(&exporter.render().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({"hp": 120, "hp_max": 200, "kills": 1.5, "quest": "杀死老鼠"}),
+            json
+        );
+        // 未变化时不重复导出
+        assert_eq!(None, exporter.render());
+        vars.insert("hp".to_owned(), "80".to_owned());
+        assert!(exporter.render().unwrap().contains("\"hp\":80"));
+
+        let config = crate::conf::Config::default();
+        let data_dir = DataDir::new(&config);
+        assert_eq!(Target::Socket(PathBuf::from("/tmp/hud.sock")), Target::parse("unix:/tmp/hud.sock", &data_dir));
+        assert_eq!(Target::File(data_dir.state_path("hud.json")), Target::parse("hud.json", &data_dir));
+    }
+}
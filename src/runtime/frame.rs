@@ -0,0 +1,141 @@
+use crate::conf;
+use crate::runtime::engine::EngineAction;
+use crate::ui::line::RawLine;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// 暂存的世界数据
+#[derive(Debug)]
+pub enum Frame {
+    Line(RawLine),
+    // GMCP、MSDP等带外消息，与文本行按到达顺序处理，不计入处理量
+    OutOfBand(EngineAction),
+}
+
+/// 世界文本的分帧处理
+///
+/// 每个周期内处理的行数及字节数有上限，超出后剩余的行暂存，
+/// 待下个周期继续处理，避免服务器大量输出时长时间占用事件循环而无法响应按键
+#[derive(Debug)]
+pub struct Frames {
+    tick: Duration,
+    // 每周期的上限，0表示不限制
+    max_lines: usize,
+    max_bytes: usize,
+    // 当前周期的开始时间及已处理的量
+    start: Instant,
+    lines: usize,
+    bytes: usize,
+    backlog: VecDeque<Frame>,
+}
+
+impl Frames {
+    pub fn new(config: &conf::Frame) -> Self {
+        Self {
+            tick: Duration::from_millis(config.tick_ms),
+            max_lines: config.max_lines,
+            max_bytes: config.max_bytes,
+            start: Instant::now(),
+            lines: 0,
+            bytes: 0,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    /// 追加待处理的行
+    pub fn extend(&mut self, lines: impl IntoIterator<Item = RawLine>) {
+        self.backlog.extend(lines.into_iter().map(Frame::Line));
+    }
+
+    /// 追加带外消息，排在已暂存的行之后
+    pub fn push_oob(&mut self, action: EngineAction) {
+        self.backlog.push_back(Frame::OutOfBand(action));
+    }
+
+    /// 取出下一行或带外消息，本周期已达上限时返回None
+    pub fn next(&mut self, now: Instant) -> Option<Frame> {
+        if now.duration_since(self.start) >= self.tick {
+            self.start = now;
+            self.lines = 0;
+            self.bytes = 0;
+        }
+        if (self.max_lines > 0 && self.lines >= self.max_lines)
+            || (self.max_bytes > 0 && self.bytes >= self.max_bytes)
+        {
+            return None;
+        }
+        let frame = self.backlog.pop_front()?;
+        if let Frame::Line(line) = &frame {
+            self.lines += 1;
+            self.bytes += line.as_ref().len();
+        }
+        Some(frame)
+    }
+
+    /// 暂存的行及带外消息数
+    pub fn pending(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// 有暂存的行时返回距下个周期开始的时间
+    pub fn wait(&self, now: Instant) -> Option<Duration> {
+        if self.backlog.is_empty() {
+            return None;
+        }
+        Some(self.tick.saturating_sub(now.duration_since(self.start)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_budget() {
+        let config = conf::Frame {
+            tick_ms: 50,
+            max_lines: 2,
+            max_bytes: 0,
+        };
+        let mut frames = Frames::new(&config);
+        let now = Instant::now();
+        frames.extend((0..5).map(|i| RawLine::new(format!("line {}\r\n", i))));
+        frames.push_oob(EngineAction::ReceiveGmcp(b"Char.Vitals {}".to_vec()));
+        assert_eq!("line 0\r\n", text(frames.next(now)));
+        assert_eq!("line 1\r\n", text(frames.next(now)));
+        // 本周期已达上限
+        assert!(frames.next(now).is_none());
+        assert_eq!(4, frames.pending());
+        assert!(frames.wait(now + Duration::from_millis(10)).unwrap() <= Duration::from_millis(40));
+        let now = now + Duration::from_millis(50);
+        assert_eq!("line 2\r\n", text(frames.next(now)));
+        assert_eq!("line 3\r\n", text(frames.next(now)));
+        // 带外消息排在之前的行之后，不计入处理量
+        assert!(frames.next(now).is_none());
+        let now = now + Duration::from_millis(50);
+        assert_eq!("line 4\r\n", text(frames.next(now)));
+        assert!(matches!(frames.next(now), Some(Frame::OutOfBand(EngineAction::ReceiveGmcp(_)))));
+        assert_eq!(0, frames.pending());
+
+        // 按字节数限制
+        let config = conf::Frame {
+            tick_ms: 50,
+            max_lines: 0,
+            max_bytes: 10,
+        };
+        let mut frames = Frames::new(&config);
+        frames.extend(vec![RawLine::new("x".repeat(8)), RawLine::new("yy".to_owned()), RawLine::new("z".to_owned())]);
+        let now = Instant::now();
+        assert!(frames.next(now).is_some());
+        assert!(frames.next(now).is_some());
+        assert!(frames.next(now).is_none());
+        assert_eq!(None, Frames::new(&config).wait(now));
+    }
+
+    fn text(frame: Option<Frame>) -> String {
+        match frame {
+            Some(Frame::Line(line)) => line.as_ref().to_owned(),
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+}
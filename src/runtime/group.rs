@@ -0,0 +1,87 @@
+use crate::error::{Error, Result};
+use crate::proto::Label;
+use crate::ui::span::Span;
+use crate::ui::style::{Color, Style};
+use std::collections::HashMap;
+
+/// 分组的显示属性，用于管理及统计列表
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupMeta {
+    pub color: Option<Color>,
+    // 显示在组名前的图标，如emoji
+    pub icon: String,
+}
+
+impl GroupMeta {
+    pub fn new(color: Option<&str>, icon: Option<String>) -> Result<Self> {
+        let color = match color {
+            Some(name) => Some(
+                Color::from_str(name)
+                    .ok_or_else(|| Error::RuntimeError(format!("invalid group color {}", name)))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            color,
+            icon: icon.unwrap_or_default(),
+        })
+    }
+}
+
+/// 各分组的显示属性，触发器、别名及定时器的同名分组共用
+#[derive(Debug, Default)]
+pub struct GroupMetas(HashMap<String, GroupMeta>);
+
+impl GroupMetas {
+    /// 属性均为空时删除分组设置
+    pub fn set(&mut self, group: impl Into<String>, meta: GroupMeta) {
+        let group = group.into();
+        if meta == GroupMeta::default() {
+            self.0.remove(&group);
+        } else {
+            self.0.insert(group, meta);
+        }
+    }
+
+    pub fn get(&self, group: &str) -> Option<&GroupMeta> {
+        self.0.get(group)
+    }
+
+    /// 组名标签，如“[⚔ fight]”，未设置颜色时使用默认样式
+    pub fn label(&self, group: &str, default: Style) -> Span {
+        match self.get(group) {
+            None => Span::new(format!("[{}]", group), default, Label::None),
+            Some(meta) => {
+                let text = if meta.icon.is_empty() {
+                    format!("[{}]", group)
+                } else {
+                    format!("[{} {}]", meta.icon, group)
+                };
+                let style = meta.color.map(|c| default.fg(c)).unwrap_or(default);
+                Span::new(text, style, Label::None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_metas() {
+        let mut metas = GroupMetas::default();
+        let default = Style::default().fg(Color::LightBlue);
+        metas.set("fight", GroupMeta::new(Some("red"), Some("⚔".to_owned())).unwrap());
+        metas.set("walk", GroupMeta::new(None, Some("👣".to_owned())).unwrap());
+        let label = metas.label("fight", default);
+        assert_eq!("[⚔ fight]", label.content);
+        assert_eq!(Style::default().fg(Color::Red), label.style);
+        assert_eq!(default, metas.label("walk", default).style);
+        assert_eq!("[chat]", metas.label("chat", default).content);
+        // 清空属性
+        metas.set("walk", GroupMeta::default());
+        assert!(metas.get("walk").is_none());
+        assert!(GroupMeta::new(Some("pink"), None).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::conf::{self, DupAction};
+use crate::error::Result;
+use regex::RegexSet;
+use std::time::{Duration, Instant};
+
+/// 重复命令的检查结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Pass,
+    Suppress,
+    Confirm,
+}
+
+/// 重复命令保护，防止回车抖动导致命令重复发送
+#[derive(Debug)]
+pub struct DupGuard {
+    interval: Duration,
+    action: DupAction,
+    whitelist: RegexSet,
+    // 上一条发送的命令及时间
+    last: Option<(String, Instant)>,
+    // 等待确认的命令
+    pending: Option<String>,
+}
+
+impl DupGuard {
+    pub fn new(config: &conf::DupGuard) -> Result<Self> {
+        Ok(Self {
+            interval: Duration::from_millis(config.interval_ms),
+            action: config.action,
+            whitelist: RegexSet::new(&config.whitelist)?,
+            last: None,
+            pending: None,
+        })
+    }
+
+    /// 检查命令，空命令及白名单中的命令总是通过
+    pub fn check(&mut self, cmd: &str, now: Instant) -> Verdict {
+        if cmd.is_empty() || self.whitelist.is_match(cmd) {
+            return Verdict::Pass;
+        }
+        if let Some((last, at)) = self.last.as_ref() {
+            if last == cmd && now.duration_since(*at) < self.interval {
+                return match self.action {
+                    DupAction::Suppress => Verdict::Suppress,
+                    DupAction::Confirm => {
+                        self.pending = Some(cmd.to_owned());
+                        Verdict::Confirm
+                    }
+                };
+            }
+        }
+        self.last = Some((cmd.to_owned(), now));
+        Verdict::Pass
+    }
+
+    /// 取出等待确认的命令
+    pub fn take_pending(&mut self) -> Option<String> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dup_guard_check() {
+        let config = conf::DupGuard {
+            enabled: true,
+            whitelist: vec!["^n$".to_owned()],
+            ..conf::DupGuard::default()
+        };
+        let mut guard = DupGuard::new(&config).unwrap();
+        let now = Instant::now();
+        assert_eq!(Verdict::Pass, guard.check("buy sword", now));
+        assert_eq!(Verdict::Confirm, guard.check("buy sword", now + Duration::from_millis(100)));
+        assert_eq!(Some("buy sword".to_owned()), guard.take_pending());
+        assert_eq!(None, guard.take_pending());
+        // 超过间隔后不再拦截
+        assert_eq!(Verdict::Pass, guard.check("buy sword", now + Duration::from_secs(1)));
+        assert_eq!(Verdict::Pass, guard.check("n", now));
+        assert_eq!(Verdict::Pass, guard.check("n", now));
+    }
+}
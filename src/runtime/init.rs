@@ -1,34 +1,69 @@
 use crate::codec::Codec;
+use crate::conf::{self, LogRotate};
 use crate::error::{Error, Result};
+use crate::i18n::{self, Lang};
 use crate::runtime::alias::{AliasFlags, Alias};
 use crate::runtime::engine;
 use crate::runtime::engine::EngineAction;
+use crate::runtime::cache::CacheText;
+use crate::runtime::classify::Classifier;
+use crate::runtime::group::GroupMeta;
+use crate::runtime::observe;
+use crate::runtime::json;
+use crate::runtime::model::NumberOrString;
 use crate::runtime::queue::ActionQueue;
-use crate::runtime::trigger::{TriggerExtra, TriggerFlags, Trigger};
-use crate::runtime::timer::{TimerModel, TimerFlags};
+use crate::runtime::trigger::{GroupWindow, StyleCond, TriggerExtra, TriggerFlags, Trigger};
+use crate::runtime::timer::{TimerFlags, TimerModel, TimerView};
 use crate::runtime::mxp_trigger::{MxpTriggerExtra, MxpTrigger};
-use crate::runtime::vars::Variables;
+use crate::runtime::register::{self, Registers};
+use crate::runtime::scrollback::Scrollback;
+use crate::runtime::sub::{self, Sub, SubParser};
+use crate::runtime::vars::{GroupVars, Variables};
+use crate::runtime::worldlog;
 use crate::map::plan::Planner;
-use crate::map::node::{NodeMap, FilteredNodes};
-use crate::map::edge::{EdgeMap, FilteredEdges};
+use crate::telnet::Protocols;
+use crate::probe::ProbeReport;
+use crate::proto::{Element, Parser};
+use crate::proto::mxp::ModeState;
+use crate::map::node::{FilteredNodes, Nodes};
+use crate::map::edge::FilteredEdges;
+use crate::map::store::MapStore;
 use crate::map::mapper::Mapper;
-use crate::map::path::PathCategory;
+use crate::map::module::MapModule;
+use crate::map::path::{CostFactors, Path, PathCategory};
+use crate::map::room::Room;
+use crate::ui::caps::TermCaps;
 use crate::ui::line::Line;
-use crate::ui::style::{Color, Style};
+use crate::ui::view::ScreenView;
+use crate::ui::style::{Color, Modifier, Style};
 use crate::ui::UserOutput;
-use std::time::Duration;
-use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use mlua::{Lua, ToLua};
+use serde_json::Value as Json;
 use uuid::Uuid;
 use rusqlite::Connection;
 
+// 内置的状态机脚本
+const FSM_SCRIPT: &str = include_str!("../../lua/fsm.lua");
+
 /// 初始化运行时
 ///
 /// 1. 定义全局变量表，Lua脚本通过SetVariable()和GetVariable()函数
-///    对其中的值进行设置和查询
+///    对其中的值进行设置和查询；通过SetGlobalVariable()和GetGlobalVariable()
+///    访问各世界共享的全局变量，GetVariable()在世界变量不存在时读取全局变量
 /// 2. 定义Lua脚本引擎中的的核心函数
 ///    有一部分函数借鉴了MUSHClient的函数签名。
-pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
+pub fn init_lua(
+    lua: &Lua,
+    vtb: &Variables,
+    global_vtb: &Variables,
+    tmpq: &ActionQueue,
+    scrollback: &Scrollback,
+    mxp_mode: &Arc<RwLock<ModeState>>,
+    registers: &Registers,
+) -> Result<()> {
     log::info!("initializing lua runtime");
     let globals = lua.globals();
 
@@ -52,6 +87,108 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "GetVariable", get_variable)?;
 
+    // 初始化SetGlobalVariable函数，全局变量在各世界间共享
+    let vars = global_vtb.clone();
+    let set_global_variable = lua.create_function(move |_, (k, v): (String, String)| {
+        log::trace!("SetGlobalVariable function called");
+        vars.insert(k, v);
+        Ok(())
+    })?;
+    register_function(&globals, "SetGlobalVariable", set_global_variable)?;
+
+    // 初始化GetGlobalVariable函数，不受同名世界变量影响
+    let vars = global_vtb.clone();
+    let get_global_variable = lua.create_function(move |_, k: String| {
+        log::trace!("GetGlobalVariable function called");
+        Ok(vars.get(&k))
+    })?;
+    register_function(&globals, "GetGlobalVariable", get_global_variable)?;
+
+    // 初始化SetVariables函数
+    let vars = vtb.clone();
+    let set_variables = lua.create_function(move |_, table: mlua::Table| {
+        log::trace!("SetVariables function called");
+        let mut kvs = Vec::new();
+        for pair in table.pairs::<String, mlua::Value>() {
+            let (k, v) = pair?;
+            kvs.push((k, var_to_string(v)?));
+        }
+        vars.insert_all(kvs);
+        Ok(())
+    })?;
+    register_function(&globals, "SetVariables", set_variables)?;
+
+    // 初始化SetVarNumber函数
+    let vars = vtb.clone();
+    let set_var_number = lua.create_function(move |_, (k, v): (String, f64)| {
+        log::trace!("SetVarNumber function called");
+        vars.insert(k, json::format_number(v));
+        Ok(())
+    })?;
+    register_function(&globals, "SetVarNumber", set_var_number)?;
+
+    // 初始化GetVarNumber函数，变量不存在或不是数字时返回nil
+    let vars = vtb.clone();
+    let get_var_number = lua.create_function(move |_, k: String| {
+        log::trace!("GetVarNumber function called");
+        Ok(vars.get(&k).and_then(|v| v.trim().parse::<f64>().ok()))
+    })?;
+    register_function(&globals, "GetVarNumber", get_var_number)?;
+
+    // 初始化SetVarBool函数
+    let vars = vtb.clone();
+    let set_var_bool = lua.create_function(move |_, (k, v): (String, bool)| {
+        log::trace!("SetVarBool function called");
+        vars.insert(k, v.to_string());
+        Ok(())
+    })?;
+    register_function(&globals, "SetVarBool", set_var_bool)?;
+
+    // 初始化GetVarBool函数
+    let vars = vtb.clone();
+    let get_var_bool = lua.create_function(move |_, k: String| {
+        log::trace!("GetVarBool function called");
+        Ok(vars.get(&k).and_then(|v| match &v[..] {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }))
+    })?;
+    register_function(&globals, "GetVarBool", get_var_bool)?;
+
+    // 初始化SetVarTable函数，表以JSON格式存储
+    let vars = vtb.clone();
+    let set_var_table = lua.create_function(move |_, (k, v): (String, mlua::Table)| {
+        log::trace!("SetVarTable function called");
+        let json = json::lua_to_json(mlua::Value::Table(v))?;
+        vars.insert(k, json.to_string());
+        Ok(())
+    })?;
+    register_function(&globals, "SetVarTable", set_var_table)?;
+
+    // 初始化GetVarTable函数
+    let vars = vtb.clone();
+    let get_var_table = lua.create_function(move |lua, k: String| {
+        log::trace!("GetVarTable function called");
+        match vars.get(&k) {
+            Some(v) => {
+                let json: serde_json::Value =
+                    serde_json::from_str(&v).map_err(|e| mlua::Error::external(Error::from(e)))?;
+                json::json_to_lua(lua, &json)
+            }
+            None => Ok(mlua::Value::Nil),
+        }
+    })?;
+    register_function(&globals, "GetVarTable", get_var_table)?;
+
+    // 初始化IncrVar函数，delta默认为1，返回新值
+    let vars = vtb.clone();
+    let incr_var = lua.create_function(move |_, (k, delta): (String, Option<f64>)| {
+        log::trace!("IncrVar function called");
+        Ok(vars.incr(&k, delta.unwrap_or(1.0))?)
+    })?;
+    register_function(&globals, "IncrVar", incr_var)?;
+
     // 初始化SwitchCodec函数
     let queue = tmpq.clone();
     let switch_codec = lua.create_function(move |_, code: String| {
@@ -80,6 +217,52 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "Send", send)?;
 
+    // 初始化SendNoEcho函数，发送命令但不回显，用于密码等
+    let queue = tmpq.clone();
+    let send_no_echo = lua.create_function(move |_, s: String| {
+        log::trace!("SendNoEcho function called");
+        queue.push(EngineAction::SendNoEcho(s));
+        Ok(())
+    })?;
+    register_function(&globals, "SendNoEcho", send_no_echo)?;
+
+    // 初始化SendRaw函数，原样发送字节，如"\255\251\1"
+    let queue = tmpq.clone();
+    let send_raw = lua.create_function(move |_, bs: mlua::String| {
+        log::trace!("SendRaw function called");
+        queue.push(EngineAction::SendRawToServer(bs.as_bytes().to_vec()));
+        Ok(())
+    })?;
+    register_function(&globals, "SendRaw", send_raw)?;
+
+    // 初始化Reload函数，清除全部触发器、别名及定时器并重新执行初始化脚本
+    let queue = tmpq.clone();
+    let reload = lua.create_function(move |_, ()| {
+        log::trace!("Reload function called");
+        queue.push(EngineAction::ReloadScript);
+        Ok(())
+    })?;
+    register_function(&globals, "Reload", reload)?;
+
+    // 初始化Reconnect函数，立即重连服务器，已连接时先断开
+    let queue = tmpq.clone();
+    let reconnect = lua.create_function(move |_, ()| {
+        log::trace!("Reconnect function called");
+        queue.push(EngineAction::Reconnect);
+        Ok(())
+    })?;
+    register_function(&globals, "Reconnect", reconnect)?;
+
+    // 初始化SetRepeatDeny函数，空命令重发时跳过匹配的命令，空表清除限制
+    let queue = tmpq.clone();
+    let set_repeat_deny = lua.create_function(move |_, patterns: Vec<String>| {
+        log::trace!("SetRepeatDeny function called");
+        regex::RegexSet::new(&patterns).map_err(|e| mlua::Error::external(Error::from(e)))?;
+        queue.push(EngineAction::SetRepeatDeny(patterns));
+        Ok(())
+    })?;
+    register_function(&globals, "SetRepeatDeny", set_repeat_deny)?;
+
     // 初始化Note函数
     let queue = tmpq.clone();
     let note = lua.create_function(move |_, s: String| {
@@ -105,6 +288,125 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "ColourNote", colour_note)?;
 
+    // 初始化GetLine函数
+    let sb = scrollback.clone();
+    let get_line = lua.create_function(move |_, lineno: usize| {
+        log::trace!("GetLine function called");
+        Ok(sb.get(lineno).map(|line| line.plain_text()))
+    })?;
+    register_function(&globals, "GetLine", get_line)?;
+
+    // 初始化GetLineRange函数
+    let sb = scrollback.clone();
+    let get_line_range = lua.create_function(move |_, (start, end): (usize, usize)| {
+        log::trace!("GetLineRange function called");
+        let lines: Vec<String> = sb.range(start, end).iter().map(|line| line.plain_text()).collect();
+        Ok(lines)
+    })?;
+    register_function(&globals, "GetLineRange", get_line_range)?;
+
+    // 初始化GetLinesBetweenTimestamps函数
+    // 时间为自UNIX纪元起的秒数，与os.time()一致，可带小数；end默认为当前时间
+    let sb = scrollback.clone();
+    let get_lines_between = lua.create_function(move |lua, (start, end): (f64, Option<f64>)| {
+        log::trace!("GetLinesBetweenTimestamps function called");
+        let start = (start.max(0.0) * 1000.0) as u64;
+        let end = end.map(|end| (end.max(0.0) * 1000.0) as u64).unwrap_or(u64::MAX);
+        let table = lua.create_table()?;
+        for (i, (lineno, time, line)) in sb.between(start, end).into_iter().enumerate() {
+            let row = lua.create_table()?;
+            row.set("lineno", lineno)?;
+            row.set("time", time as f64 / 1000.0)?;
+            row.set("text", line.plain_text())?;
+            table.set(i + 1, row)?;
+        }
+        Ok(table)
+    })?;
+    register_function(&globals, "GetLinesBetweenTimestamps", get_lines_between)?;
+
+    // 初始化GetHistoryStats函数，返回历史行压缩统计
+    let sb = scrollback.clone();
+    let get_history_stats = lua.create_function(move |lua, ()| {
+        log::trace!("GetHistoryStats function called");
+        let stats = sb.compact_stats();
+        let table = lua.create_table()?;
+        table.set("lines", stats.lines)?;
+        table.set("spans_merged", stats.spans_merged)?;
+        table.set("bytes_saved", stats.bytes_saved)?;
+        Ok(table)
+    })?;
+    register_function(&globals, "GetHistoryStats", get_history_stats)?;
+
+    // 初始化_函数，获取当前语言的消息，额外参数依次替换消息中的{}
+    let translate = lua.create_function(move |_, (key, args): (String, mlua::Variadic<mlua::Value>)| {
+        let args: Vec<String> = args.into_iter().map(var_to_string).collect::<mlua::Result<_>>()?;
+        let args: Vec<&dyn std::fmt::Display> = args.iter().map(|a| a as &dyn std::fmt::Display).collect();
+        Ok(i18n::trf(&key, &args))
+    })?;
+    register_function(&globals, "_", translate)?;
+
+    // 初始化RegisterMessages函数，供插件注册消息
+    let register_messages = lua.create_function(move |_, (lang, table): (String, mlua::Table)| {
+        log::trace!("RegisterMessages function called");
+        let lang = Lang::parse(&lang).ok_or_else(|| {
+            mlua::Error::external(Error::RuntimeError(format!("unsupported language {}", lang)))
+        })?;
+        for pair in table.pairs::<String, String>() {
+            let (k, v) = pair?;
+            i18n::register(lang, k, v);
+        }
+        Ok(())
+    })?;
+    register_function(&globals, "RegisterMessages", register_messages)?;
+
+    // 初始化GetMxpMode函数
+    let mode = mxp_mode.clone();
+    let get_mxp_mode = lua.create_function(move |lua, _: ()| {
+        log::trace!("GetMxpMode function called");
+        let mode = *mode.read().unwrap();
+        let table = lua.create_table()?;
+        table.set("mode", mode.line.as_str())?;
+        table.set("default", mode.default.as_str())?;
+        table.set("temp_secure", mode.temp_secure)?;
+        Ok(table)
+    })?;
+    register_function(&globals, "GetMxpMode", get_mxp_mode)?;
+
+    // 初始化SetClipboard函数，未指定寄存器时写入默认寄存器
+    let regs = registers.clone();
+    let set_clipboard = lua.create_function(move |_, (text, name): (String, Option<String>)| {
+        log::trace!("SetClipboard function called");
+        regs.set(name.as_deref().unwrap_or(register::UNNAMED), text)?;
+        Ok(())
+    })?;
+    register_function(&globals, "SetClipboard", set_clipboard)?;
+
+    // 初始化GetClipboard函数
+    let regs = registers.clone();
+    let get_clipboard = lua.create_function(move |_, name: Option<String>| {
+        log::trace!("GetClipboard function called");
+        Ok(regs.get(name.as_deref().unwrap_or(register::UNNAMED))?)
+    })?;
+    register_function(&globals, "GetClipboard", get_clipboard)?;
+
+    // 初始化ParseAnsi函数
+    let parse_ansi = lua.create_function(move |lua, text: String| {
+        log::trace!("ParseAnsi function called");
+        let mut parser = Parser::default();
+        parser.fill(&text);
+        let mut spans = Vec::new();
+        loop {
+            match parser.next() {
+                Element::None => break,
+                Element::Span(span) => spans.push(span),
+                // 忽略MXP事件
+                _ => (),
+            }
+        }
+        spans.to_lua(lua)
+    })?;
+    register_function(&globals, "ParseAnsi", parse_ansi)?;
+
     // 初始化GetUniqueID函数
     let get_unique_id = lua.create_function(move |_, _: ()| {
         let id = Uuid::new_v4();
@@ -166,6 +468,7 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
             // 2. 需保证在下次检验名称时若重名则失败
             // 重要：在处理RuntimAction时，需清理曾添加的回调函数
             alias_callbacks.set(alias.name.to_owned(), func)?;
+            record_origin(lua, engine::GLOBAL_ALIAS_ORIGINS, &alias.name)?;
             queue.push(EngineAction::CreateAlias(alias));
             Ok(())
         },
@@ -186,23 +489,31 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     // trigger_flag.set("Enabled", 1)?;
     trigger_flag.set("KeepEvaluating", 8)?;
     trigger_flag.set("OneShot", 32768)?;
+    trigger_flag.set("Prompt", 256)?;
+    trigger_flag.set("Context", 64)?;
     globals.set("trigger_flag", trigger_flag)?;
 
     // 触发器回调注册表
     let trigger_callbacks = lua.create_table()?;
     globals.set(engine::GLOBAL_TRIGGER_CALLBACKS, trigger_callbacks)?;
+    // 触发器定义文件
+    let trigger_origins = lua.create_table()?;
+    globals.set(engine::GLOBAL_TRIGGER_ORIGINS, trigger_origins)?;
+    globals.set(engine::GLOBAL_ALIAS_ORIGINS, lua.create_table()?)?;
+    globals.set(engine::GLOBAL_TIMER_ORIGINS, lua.create_table()?)?;
 
     // 初始化CreateTrigger函数
     let queue = tmpq.clone();
     let create_trigger = lua.create_function(
         move |lua,
-              (name, group, pattern, flags, match_lines, func): (
+              (name, group, pattern, flags, match_lines, func, style): (
             String,
             String,
             String,
             u16,
             u8,
             mlua::Function,
+            Option<mlua::Table>,
         )| {
             log::trace!("CreateTrigger function called");
             if pattern.is_empty() {
@@ -216,6 +527,13 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
                     flags
                 )))
             })?;
+            // 多行匹配没有样式信息
+            let style = style.map(style_cond).transpose()?.map(Box::new);
+            if style.is_some() && match_lines > 1 {
+                return Err(mlua::Error::external(Error::RuntimeError(
+                    "style condition not supported on multi-line trigger".to_owned(),
+                )));
+            }
 
             let trigger_callbacks: mlua::Table =
                 lua.globals().get(engine::GLOBAL_TRIGGER_CALLBACKS)?;
@@ -230,10 +548,12 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
                 .group(group)
                 .pattern(pattern)?
                 .enabled(true)
-                .extra(TriggerExtra { match_lines, flags })
+                .extra(TriggerExtra { match_lines, flags, style })
                 .build();
             // 同alias
             trigger_callbacks.set(trigger.name.to_owned(), func)?;
+            // 记录加载中的脚本文件，执行出错时提示
+            record_origin(lua, engine::GLOBAL_TRIGGER_ORIGINS, &trigger.name)?;
             queue.push(EngineAction::CreateTrigger(trigger));
             Ok(())
         },
@@ -251,12 +571,68 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
 
     // 初始化EnableTriggerGroup函数
     let queue = tmpq.clone();
-    let enable_trigger_group = lua.create_function(move |_, (name, enabled): (String, bool)| {
-        log::trace!("EnableTriggerGroup function called");
-        queue.push(EngineAction::EnableTriggerGroup(name, enabled));
+    // 可选参数opts：{ttl=秒数, matches=次数}，开启后到期自动禁用
+    let enable_trigger_group = lua.create_function(
+        move |_, (name, enabled, opts): (String, bool, Option<mlua::Table>)| {
+            log::trace!("EnableTriggerGroup function called");
+            let window = match opts {
+                Some(opts) if enabled => {
+                    let ttl: Option<f64> = opts.get("ttl")?;
+                    let matches: Option<u32> = opts.get("matches")?;
+                    // 非正数、NaN及过大的存活时间均视为错误
+                    let deadline = match ttl {
+                        Some(secs) => Some(
+                            Duration::try_from_secs_f64(secs)
+                                .ok()
+                                .filter(|ttl| !ttl.is_zero())
+                                .and_then(|ttl| Instant::now().checked_add(ttl))
+                                .ok_or_else(|| {
+                                    mlua::Error::external(Error::RuntimeError(format!(
+                                        "invalid trigger group ttl {}",
+                                        secs
+                                    )))
+                                })?,
+                        ),
+                        None => None,
+                    };
+                    GroupWindow::new(deadline, matches)
+                }
+                _ => None,
+            };
+            queue.push(EngineAction::EnableTriggerGroup(name.to_owned(), enabled));
+            if let Some(window) = window {
+                queue.push(EngineAction::LimitTriggerGroup(name, window));
+            }
+            Ok(())
+        },
+    )?;
+    register_function(&globals, "EnableTriggerGroup", enable_trigger_group)?;
+
+    // 初始化SetGroupMeta函数
+    // meta：{color=颜色名, icon=图标}，用于管理及统计列表中显示组名
+    let queue = tmpq.clone();
+    let set_group_meta = lua.create_function(move |_, (group, meta): (String, mlua::Table)| {
+        log::trace!("SetGroupMeta function called");
+        let color: Option<String> = meta.get("color")?;
+        let icon: Option<String> = meta.get("icon")?;
+        let meta = GroupMeta::new(color.as_deref(), icon).map_err(mlua::Error::external)?;
+        queue.push(EngineAction::SetGroupMeta(group, meta));
         Ok(())
     })?;
-    register_function(&globals, "EnableTriggerGroup", enable_trigger_group)?;
+    register_function(&globals, "SetGroupMeta", set_group_meta)?;
+
+    // 初始化ObserveTriggerGroup函数
+    // 观察中的分组匹配时仅记录命中次数及最近samples条文本，可在#stats中查看
+    let queue = tmpq.clone();
+    let observe_trigger_group = lua.create_function(
+        move |_, (group, observe, samples): (String, bool, Option<usize>)| {
+            log::trace!("ObserveTriggerGroup function called");
+            let samples = samples.unwrap_or(observe::DEFAULT_SAMPLES);
+            queue.push(EngineAction::ObserveTriggerGroup(group, observe, samples));
+            Ok(())
+        },
+    )?;
+    register_function(&globals, "ObserveTriggerGroup", observe_trigger_group)?;
 
     // MXP触发器回调注册表
     let mxp_trigger_callbacks = lua.create_table()?;
@@ -332,6 +708,7 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     let timer_flag: mlua::Table = lua.create_table()?;
     // timer_flag.set("Enabled", 1)?;
     timer_flag.set("OneShot", 4)?;
+    timer_flag.set("FixedDelay", 8)?;
     globals.set("timer_flag", timer_flag)?;
 
     // 定时器回调注册表
@@ -354,6 +731,7 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
             let tm = TimerModel::new(name, group, tick_time, flags);
             // 同alias
             timer_callbacks.set(tm.name.to_owned(), func)?;
+            record_origin(lua, engine::GLOBAL_TIMER_ORIGINS, &tm.name)?;
             queue.push(EngineAction::CreateTimer(tm));
             Ok(())
     })?;
@@ -378,6 +756,34 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "EnableTimerGroup", enable_timer_group)?;
 
+    // 初始化EnableTimer函数
+    let queue = tmpq.clone();
+    let enable_timer = lua.create_function(move |_, (name, enabled): (String, bool)| {
+        log::trace!("EnableTimer function called");
+        queue.push(EngineAction::EnableTimer(name, enabled));
+        Ok(())
+    })?;
+    register_function(&globals, "EnableTimer", enable_timer)?;
+
+    // 初始化ResetTimer函数，从当前时间重新开始计时，无需删除后重建
+    let queue = tmpq.clone();
+    let reset_timer = lua.create_function(move |_, name: String| {
+        log::trace!("ResetTimer function called");
+        queue.push(EngineAction::ResetTimer(name));
+        Ok(())
+    })?;
+    register_function(&globals, "ResetTimer", reset_timer)?;
+
+    // 初始化PauseTimers函数
+    // 暂停或恢复所有定时器，恢复后按暂停时的剩余时间继续调度
+    let queue = tmpq.clone();
+    let pause_timers = lua.create_function(move |_, paused: Option<bool>| {
+        log::trace!("PauseTimers function called");
+        queue.push(EngineAction::PauseTimers(paused.unwrap_or(true)));
+        Ok(())
+    })?;
+    register_function(&globals, "PauseTimers", pause_timers)?;
+
     // 初始化DoAfter函数
     let queue = tmpq.clone();
     let do_after = lua.create_function(move |lua, (tick_in_millis, func): (u64, mlua::Function)| {
@@ -394,6 +800,161 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "DoAfter", do_after)?;
 
+    // 初始化ReadKey函数
+    // 下一次按键交给回调而非命令行，超时未按键时以nil调用，timeout为0时一直等待
+    let queue = tmpq.clone();
+    let read_key = lua.create_function(
+        move |lua, (prompt, timeout, func): (String, u64, mlua::Function)| {
+            log::trace!("ReadKey function called");
+            lua.globals().set(engine::GLOBAL_READKEY_CALLBACK, func)?;
+            let id = Uuid::new_v4().to_simple().to_string();
+            if timeout > 0 {
+                let timer_callbacks: mlua::Table = lua.globals().get(engine::GLOBAL_TIMER_CALLBACKS)?;
+                let flags = TimerFlags::ENABLED | TimerFlags::ONESHOT;
+                let tm = TimerModel::new(id.clone(), "TemporaryReadKey", Duration::from_millis(timeout), flags);
+                let expire_queue = queue.clone();
+                let expire_id = id.clone();
+                let expire = lua.create_function(move |_, ()| {
+                    expire_queue.push(EngineAction::KeyReadTimeout(expire_id.clone()));
+                    Ok(())
+                })?;
+                timer_callbacks.set(tm.name.to_owned(), expire)?;
+                queue.push(EngineAction::CreateTimer(tm));
+            }
+            queue.push(EngineAction::ReadKey(id, prompt));
+            Ok(())
+        },
+    )?;
+    register_function(&globals, "ReadKey", read_key)?;
+
+    // 初始化ShowMenu函数
+    // 弹出编号菜单，以选中的序号（从1开始）调用回调，取消时为nil
+    let queue = tmpq.clone();
+    let show_menu = lua.create_function(
+        move |lua, (title, items, func): (String, Vec<String>, mlua::Function)| {
+            log::trace!("ShowMenu function called");
+            if items.is_empty() {
+                return Err(mlua::Error::external(Error::RuntimeError(
+                    "menu items must not be empty".to_owned(),
+                )));
+            }
+            lua.globals().set(engine::GLOBAL_MENU_CALLBACK, func)?;
+            queue.push(EngineAction::ShowMenu(title, items));
+            Ok(())
+        },
+    )?;
+    register_function(&globals, "ShowMenu", show_menu)?;
+
+    // 状态界面回调注册表
+    let status_callbacks = lua.create_table()?;
+    globals.set(engine::GLOBAL_STATUS_CALLBACKS, status_callbacks)?;
+
+    // 初始化OnStatus函数
+    // kind为hp、score或skills，界面解析完成后以结构化的表调用回调
+    let on_status = lua.create_function(move |lua, (kind, func): (String, Option<mlua::Function>)| {
+        log::trace!("OnStatus function called");
+        if !matches!(&kind[..], "hp" | "score" | "skills") {
+            return Err(mlua::Error::external(Error::RuntimeError(format!(
+                "invalid status kind {}",
+                kind
+            ))));
+        }
+        let status_callbacks: mlua::Table = lua.globals().get(engine::GLOBAL_STATUS_CALLBACKS)?;
+        status_callbacks.set(kind, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "OnStatus", on_status)?;
+
+    // 初始化OnMediaDirective函数
+    // 收到!!SOUND或!!MUSIC指令时以结构化的表调用回调，传入nil取消
+    let on_media_directive = lua.create_function(move |lua, func: Option<mlua::Function>| {
+        log::trace!("OnMediaDirective function called");
+        lua.globals().set(engine::GLOBAL_MEDIA_CALLBACK, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "OnMediaDirective", on_media_directive)?;
+
+    // 初始化OnProbe函数
+    // 连接测量完成时以测量结果调用回调，结果同GetProbe
+    let on_probe = lua.create_function(move |lua, func: Option<mlua::Function>| {
+        log::trace!("OnProbe function called");
+        lua.globals().set(engine::GLOBAL_PROBE_CALLBACK, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "OnProbe", on_probe)?;
+
+    // GMCP回调注册表
+    let gmcp_callbacks = lua.create_table()?;
+    globals.set(engine::GLOBAL_GMCP_CALLBACKS, gmcp_callbacks)?;
+
+    // 初始化OnGmcp函数
+    // 收到包名匹配的GMCP消息时以包名及数据调用回调，如Char同时匹配Char.Vitals，传入nil取消
+    let on_gmcp = lua.create_function(move |lua, (package, func): (String, Option<mlua::Function>)| {
+        log::trace!("OnGmcp function called");
+        let gmcp_callbacks: mlua::Table = lua.globals().get(engine::GLOBAL_GMCP_CALLBACKS)?;
+        gmcp_callbacks.set(package, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "OnGmcp", on_gmcp)?;
+
+    // MSDP回调注册表
+    let msdp_callbacks = lua.create_table()?;
+    globals.set(engine::GLOBAL_MSDP_CALLBACKS, msdp_callbacks)?;
+
+    // 初始化OnMsdp函数
+    // 变量值发生变化时以变量名及新值调用回调，表及数组转换为table，传入nil取消
+    let on_msdp = lua.create_function(move |lua, (name, func): (String, Option<mlua::Function>)| {
+        log::trace!("OnMsdp function called");
+        let msdp_callbacks: mlua::Table = lua.globals().get(engine::GLOBAL_MSDP_CALLBACKS)?;
+        msdp_callbacks.set(name, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "OnMsdp", on_msdp)?;
+
+    // 初始化PlaySound函数
+    // 通过配置的播放命令播放声音目录下的文件，volume为0-100
+    let queue = tmpq.clone();
+    let play_sound = lua.create_function(move |_, (file, volume): (String, Option<u32>)| {
+        log::trace!("PlaySound function called");
+        queue.push(EngineAction::PlaySound(file, volume));
+        Ok(())
+    })?;
+    register_function(&globals, "PlaySound", play_sound)?;
+
+    let transformer_callbacks = lua.create_table()?;
+    globals.set(engine::GLOBAL_TRANSFORMER_CALLBACKS, transformer_callbacks)?;
+
+    // 初始化CreateTransformer函数
+    // 回调逐个接收片段文本并返回转换后的文本，order小的先执行
+    let queue = tmpq.clone();
+    let create_transformer = lua.create_function(
+        move |lua, (name, order, func): (String, i32, mlua::Function)| {
+            log::trace!("CreateTransformer function called");
+            let callbacks: mlua::Table = lua.globals().get(engine::GLOBAL_TRANSFORMER_CALLBACKS)?;
+            callbacks.set(&name[..], func)?;
+            queue.push(EngineAction::CreateTransformer(name, order));
+            Ok(())
+        },
+    )?;
+    register_function(&globals, "CreateTransformer", create_transformer)?;
+
+    // 初始化DeleteTransformer函数
+    let queue = tmpq.clone();
+    let delete_transformer = lua.create_function(move |_, name: String| {
+        queue.push(EngineAction::DeleteTransformer(name));
+        Ok(())
+    })?;
+    register_function(&globals, "DeleteTransformer", delete_transformer)?;
+
+    // 初始化EnableTransformer函数
+    // 也可用于启用内置转换器，如normalize-whitespace
+    let queue = tmpq.clone();
+    let enable_transformer = lua.create_function(move |_, (name, enabled): (String, bool)| {
+        queue.push(EngineAction::EnableTransformer(name, enabled));
+        Ok(())
+    })?;
+    register_function(&globals, "EnableTransformer", enable_transformer)?;
+
     // 初始化LoadFile函数
     let queue = tmpq.clone();
     let load_file = lua.create_function(move |_, path: String| {
@@ -402,20 +963,341 @@ pub fn init_lua(lua: &Lua, vtb: &Variables, tmpq: &ActionQueue) -> Result<()> {
     })?;
     register_function(&globals, "LoadFile", load_file)?;
 
+    // 初始化ImportZmud函数
+    // 导入zMUD/CMUD导出的#TRIGGER及#ALIAS定义，无法转换的定义按行号提示
+    let queue = tmpq.clone();
+    let import_zmud = lua.create_function(move |_, path: String| {
+        log::trace!("ImportZmud function called");
+        queue.push(EngineAction::ImportZmud(path));
+        Ok(())
+    })?;
+    register_function(&globals, "ImportZmud", import_zmud)?;
+
+    // 初始化FlushOfflineQueue函数
+    // 发送断线期间暂存的命令，通常在重连后的登录成功触发器中调用
+    let queue = tmpq.clone();
+    let flush_offline_queue = lua.create_function(move |_, ()| {
+        log::trace!("FlushOfflineQueue function called");
+        queue.push(EngineAction::FlushOfflineQueue);
+        Ok(())
+    })?;
+    register_function(&globals, "FlushOfflineQueue", flush_offline_queue)?;
+
+    // 加载内置的fsm模块
+    lua.load(FSM_SCRIPT).exec()?;
+
+    Ok(())
+}
+
+/// 初始化协议查询函数
+pub fn init_protocols(
+    lua: &Lua,
+    protocols: &Arc<RwLock<Option<Protocols>>>,
+    probe: &Arc<RwLock<Option<ProbeReport>>>,
+    msdp_vars: &Arc<RwLock<HashMap<String, Json>>>,
+) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化GetProtocols函数
+    // 返回协议名称到是否启用的映射，尚未完成协商时返回nil
+    let protocols = protocols.clone();
+    let get_protocols = lua.create_function(move |lua, _: ()| {
+        log::trace!("GetProtocols function called");
+        let protocols = match *protocols.read().unwrap() {
+            Some(protocols) => protocols,
+            None => return Ok(mlua::Value::Nil),
+        };
+        let table = lua.create_table()?;
+        for (name, enabled) in protocols.list() {
+            table.set(name, enabled)?;
+        }
+        Ok(mlua::Value::Table(table))
+    })?;
+    register_function(&globals, "GetProtocols", get_protocols)?;
+
+    // 初始化GetProbe函数
+    // 返回连接初期测量的延迟（毫秒）、读取字节数及建议，未开启测量或尚未完成时返回nil
+    let probe = probe.clone();
+    let get_probe = lua.create_function(move |_, _: ()| {
+        log::trace!("GetProbe function called");
+        Ok(probe.read().unwrap().clone())
+    })?;
+    register_function(&globals, "GetProbe", get_probe)?;
+
+    // 初始化GetMsdp函数
+    // 返回服务器通过MSDP发送的变量值，未收到时返回nil
+    let msdp_vars = msdp_vars.clone();
+    let get_msdp = lua.create_function(move |lua, name: String| {
+        log::trace!("GetMsdp function called");
+        match msdp_vars.read().unwrap().get(&name) {
+            Some(value) => json::json_to_lua(lua, value),
+            None => Ok(mlua::Value::Nil),
+        }
+    })?;
+    register_function(&globals, "GetMsdp", get_msdp)?;
+    Ok(())
+}
+
+/// 初始化分组变量函数
+pub fn init_group_vars(lua: &Lua, group_vars: &GroupVars, tmpq: &ActionQueue) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化DeclareGroupVar函数
+    // 声明分组变量并返回当前值，已声明时不覆盖，分组禁用或删除时清除
+    let vars = group_vars.clone();
+    let declare_group_var =
+        lua.create_function(move |_, (group, key, default): (String, String, Option<String>)| {
+            log::trace!("DeclareGroupVar function called");
+            Ok(vars.declare(&group, &key, default.unwrap_or_default()))
+        })?;
+    register_function(&globals, "DeclareGroupVar", declare_group_var)?;
+
+    // 初始化GetGroupVar函数
+    let vars = group_vars.clone();
+    let get_group_var = lua.create_function(move |_, (group, key): (String, String)| {
+        log::trace!("GetGroupVar function called");
+        Ok(vars.get(&group, &key))
+    })?;
+    register_function(&globals, "GetGroupVar", get_group_var)?;
+
+    // 初始化SetGroupVar函数，变量需先声明
+    let vars = group_vars.clone();
+    let set_group_var = lua.create_function(move |_, (group, key, value): (String, String, String)| {
+        log::trace!("SetGroupVar function called");
+        vars.set(&group, &key, value).map_err(mlua::Error::external)
+    })?;
+    register_function(&globals, "SetGroupVar", set_group_var)?;
+
+    // 初始化DeleteGroup函数，删除分组内的别名、触发器及定时器，并清除分组变量
+    let queue = tmpq.clone();
+    let delete_group = lua.create_function(move |_, group: String| {
+        log::trace!("DeleteGroup function called");
+        queue.push(EngineAction::DeleteGroup(group));
+        Ok(())
+    })?;
+    register_function(&globals, "DeleteGroup", delete_group)?;
+    Ok(())
+}
+
+/// 初始化GetTermCaps函数，鼠标支持与界面一致，按配置开启或关闭
+pub fn init_term_caps(lua: &Lua, mouse: conf::MouseMode) -> Result<()> {
+    let globals = lua.globals();
+    let caps = TermCaps::detect().with_mouse(mouse);
+    let get_term_caps = lua.create_function(move |lua, _: ()| {
+        log::trace!("GetTermCaps function called");
+        let table = lua.create_table()?;
+        table.set("term", &caps.term[..])?;
+        table.set("mouse", caps.mouse)?;
+        table.set("colors", caps.colors)?;
+        table.set("truecolor", caps.truecolor)?;
+        table.set("unicode", caps.unicode)?;
+        Ok(table)
+    })?;
+    register_function(&globals, "GetTermCaps", get_term_caps)?;
+    Ok(())
+}
+
+/// 初始化定时器查询函数
+pub fn init_timer_view(lua: &Lua, view: &TimerView) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化GetTimerRemaining函数
+    // 返回距下次执行的毫秒数，暂停期间为暂停时的剩余时间，定时器不存在或已禁用时返回nil
+    let view = view.clone();
+    let get_timer_remaining = lua.create_function(move |_, name: String| {
+        log::trace!("GetTimerRemaining function called");
+        Ok(view.remaining(&name, Instant::now()).map(|d| d.as_millis() as u64))
+    })?;
+    register_function(&globals, "GetTimerRemaining", get_timer_remaining)?;
+    Ok(())
+}
+
+/// 初始化行分类函数
+pub fn init_classify(lua: &Lua, classifier: &Classifier) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化IsClass函数，判断文本是否属于[classify]中的指定分类
+    let cls = classifier.clone();
+    let is_class = lua.create_function(move |_, (class, line): (String, String)| {
+        log::trace!("IsClass function called");
+        Ok(cls.is(&class, &line))
+    })?;
+    register_function(&globals, "IsClass", is_class)?;
+
+    // 初始化GetLineClasses函数，返回文本所属的全部分类
+    let cls = classifier.clone();
+    let get_line_classes = lua.create_function(move |_, line: String| {
+        log::trace!("GetLineClasses function called");
+        Ok(cls.classes(&line).into_iter().map(String::from).collect::<Vec<_>>())
+    })?;
+    register_function(&globals, "GetLineClasses", get_line_classes)?;
+
+    // 常用分类的快捷函数：IsChat、IsCombat、IsSystem
+    for &(func, class) in &[("IsChat", "chat"), ("IsCombat", "combat"), ("IsSystem", "system")] {
+        let cls = classifier.clone();
+        let is_class = lua.create_function(move |_, line: String| Ok(cls.is(class, &line)))?;
+        register_function(&globals, func, is_class)?;
+    }
+    Ok(())
+}
+
+/// 初始化世界文本日志函数
+pub fn init_world_log(lua: &Lua, tmpq: &ActionQueue, defaults: &conf::Log) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化StartLog函数，在日志目录开始记录世界文本，替换正在记录的日志
+    // 可选参数opts：{plain=true, timestamp=true, session=true, rotate="size"|"daily"|"none",
+    // max_size=字节数, keep=保留文件数}，未指定的选项使用配置文件中的值
+    let queue = tmpq.clone();
+    let defaults = defaults.clone();
+    let start_log =
+        lua.create_function(move |_, (path, opts): (String, Option<mlua::Table>)| {
+            log::trace!("StartLog function called");
+            worldlog::validate_file(&path).map_err(mlua::Error::external)?;
+            let mut log = defaults.clone();
+            if let Some(opts) = opts {
+                if let Some(plain) = opts.get::<_, Option<bool>>("plain")? {
+                    log.plain = plain;
+                }
+                if let Some(timestamp) = opts.get::<_, Option<bool>>("timestamp")? {
+                    log.timestamp = timestamp;
+                }
+                if let Some(session) = opts.get::<_, Option<bool>>("session")? {
+                    log.session = session;
+                }
+                if let Some(rotate) = opts.get::<_, Option<String>>("rotate")? {
+                    log.rotate = match rotate.as_str() {
+                        "none" => LogRotate::None,
+                        "size" => LogRotate::Size,
+                        "daily" => LogRotate::Daily,
+                        _ => {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "invalid log rotation {}",
+                                rotate
+                            )))
+                        }
+                    };
+                }
+                if let Some(max_size) = opts.get::<_, Option<u64>>("max_size")? {
+                    log.max_size = max_size;
+                }
+                if let Some(keep) = opts.get::<_, Option<usize>>("keep")? {
+                    log.keep = keep;
+                }
+            }
+            queue.push(EngineAction::StartLog(path, log));
+            Ok(())
+        })?;
+    register_function(&globals, "StartLog", start_log)?;
+
+    // 初始化StopLog函数，停止记录世界文本
+    let queue = tmpq.clone();
+    let stop_log = lua.create_function(move |_, ()| {
+        log::trace!("StopLog function called");
+        queue.push(EngineAction::StopLog);
+        Ok(())
+    })?;
+    register_function(&globals, "StopLog", stop_log)?;
+    Ok(())
+}
+
+/// 初始化触发器匹配窗口的读取函数
+pub fn init_trigger_window(lua: &Lua, cache: &Arc<RwLock<CacheText>>) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化GetTriggerWindow函数
+    // 返回触发器匹配的多行窗口，每行包含去除换行符的文本及各片段的样式，
+    // ended字段表示最后一行是否已结束，便于在Lua中处理跨行的表格等复杂文本
+    let cache = cache.clone();
+    let get_trigger_window = lua.create_function(move |lua, ()| {
+        log::trace!("GetTriggerWindow function called");
+        let cache = cache.read().unwrap();
+        let table = lua.create_table()?;
+        for (i, (text, styles)) in cache.window().into_iter().enumerate() {
+            let row = lua.create_table()?;
+            row.set("text", text)?;
+            row.set("styles", lua.create_sequence_from(styles.iter().cloned())?)?;
+            table.set(i + 1, row)?;
+        }
+        table.set("ended", cache.ended())?;
+        Ok(table)
+    })?;
+    register_function(&globals, "GetTriggerWindow", get_trigger_window)?;
     Ok(())
 }
 
-pub fn init_mapper(lua: &Lua, conn: Connection) -> Result<()> {
+/// 初始化屏幕读取函数
+pub fn init_screen(lua: &Lua, view: &ScreenView) -> Result<()> {
+    let globals = lua.globals();
+
+    // 初始化GetScreenText函数
+    // 返回主窗格最下方的rows行（默认全部），折行及过滤后与用户所见一致，
+    // 每行包含纯文本及各片段的样式
+    let view = view.clone();
+    let get_screen_text = lua.create_function(move |lua, rows: Option<usize>| {
+        log::trace!("GetScreenText function called");
+        let table = lua.create_table()?;
+        for (i, line) in view.rows(rows).iter().enumerate() {
+            let row = lua.create_table()?;
+            row.set("text", line.plain_text())?;
+            let spans = lua.create_table()?;
+            for (j, span) in line.spans().iter().enumerate() {
+                spans.set(j + 1, span)?;
+            }
+            row.set("spans", spans)?;
+            table.set(i + 1, row)?;
+        }
+        Ok(table)
+    })?;
+    register_function(&globals, "GetScreenText", get_screen_text)?;
+    Ok(())
+}
+
+/// 初始化地图相关函数，返回地图数据访问对象
+///
+/// zone_cache为None时启动时加载全部房间及路径，否则按区域延迟加载并缓存指定数量的区域
+pub fn init_mapper(lua: &Lua, conn: Connection, zone_cache: Option<usize>) -> Result<Mapper> {
     log::info!("initializing mapper");
     let globals = lua.globals();
 
-    let rooms = NodeMap::load_from_db(&conn)?;
-    let paths = EdgeMap::load_from_db(&conn, &rooms)?;
-    let rooms = Arc::new(rooms);
-    let paths = Arc::new(paths);
-    
+    let conn = Arc::new(Mutex::new(conn));
+    // 房间与路径由同一个对象提供
+    let rooms = Arc::new(MapStore::load(conn.clone(), zone_cache)?);
+    let paths = rooms.clone();
+
+    // 各规划器共享的路径类别权重系数
+    let factors = CostFactors::new();
+
+    // 初始化SetPathCostFactor函数
+    let fs = factors.clone();
+    let set_path_cost_factor = lua.create_function(move |_, (category, factor): (String, f64)| {
+        let category = PathCategory::parse(&category).ok_or_else(|| {
+            mlua::Error::external(Error::RuntimeError(format!("unknown path category {}", category)))
+        })?;
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(mlua::Error::external(Error::RuntimeError(format!(
+                "invalid path cost factor {}",
+                factor
+            ))));
+        }
+        fs.set(category, factor);
+        Ok(())
+    })?;
+    register_function(&globals, "SetPathCostFactor", set_path_cost_factor)?;
+
+    // 初始化GetPathCostFactor函数
+    let fs = factors.clone();
+    let get_path_cost_factor = lua.create_function(move |_, category: String| {
+        let category = PathCategory::parse(&category).ok_or_else(|| {
+            mlua::Error::external(Error::RuntimeError(format!("unknown path category {}", category)))
+        })?;
+        Ok(fs.get(category))
+    })?;
+    register_function(&globals, "GetPathCostFactor", get_path_cost_factor)?;
+
     // 初始化FastWalk函数
-    let planner = Planner::new(rooms.clone(), paths.clone());
+    let fs = factors.clone();
+    let planner = Planner::new(rooms.clone(), paths.clone()).with_cost(move |p| fs.cost(p));
     let fast_walk = lua.create_function(move |lua, (fromid, toid): (u32, u32)| {
         let plan = planner.walk(fromid, toid);
         plan.to_lua(lua)
@@ -425,7 +1307,8 @@ pub fn init_mapper(lua: &Lua, conn: Connection) -> Result<()> {
     // 初始化Walk函数
     let planner = {
         let paths = FilteredEdges::new(paths.clone(), |p| p.category != PathCategory::Bus);
-        Planner::new(rooms.clone(), paths.clone())
+        let fs = factors.clone();
+        Planner::new(rooms.clone(), paths.clone()).with_cost(move |p| fs.cost(p))
     };
     let walk = lua.create_function(move |lua, (fromid, toid): (u32, u32)| {
         let plan = planner.walk(fromid, toid);
@@ -433,12 +1316,60 @@ pub fn init_mapper(lua: &Lua, conn: Connection) -> Result<()> {
     })?;
     register_function(&globals, "Walk", walk)?;
 
+    // 初始化NearestRoom函数
+    // 目标可以是房间编号列表，或者接收房间并返回布尔值的函数
+    let planner = {
+        let paths = FilteredEdges::new(paths.clone(), |p| p.category != PathCategory::Bus);
+        let fs = factors.clone();
+        Planner::new(rooms.clone(), paths.clone()).with_cost(move |p| fs.cost(p))
+    };
+    let nearest_room = lua.create_function(move |lua, (fromid, target): (u32, mlua::Value)| {
+        let found = match target {
+            mlua::Value::Table(ids) => {
+                let ids = ids
+                    .sequence_values::<u32>()
+                    .collect::<mlua::Result<HashSet<u32>>>()?;
+                planner.nearest(fromid, |room| ids.contains(&room.id))
+            }
+            mlua::Value::Function(pred) => {
+                let mut err = None;
+                let found = planner.nearest(fromid, |room| {
+                    if err.is_some() {
+                        return false;
+                    }
+                    match pred.call::<_, bool>(room) {
+                        Ok(matched) => matched,
+                        Err(e) => {
+                            err = Some(e);
+                            false
+                        }
+                    }
+                });
+                if let Some(e) = err {
+                    return Err(e);
+                }
+                found
+            }
+            _ => {
+                return Err(mlua::Error::external(Error::RuntimeError(
+                    "target must be table of room ids or function".to_owned(),
+                )))
+            }
+        };
+        match found {
+            Some((room, plan)) => Ok((room.to_lua(lua)?, plan.to_lua(lua)?)),
+            None => Ok((mlua::Value::Nil, mlua::Value::Nil)),
+        }
+    })?;
+    register_function(&globals, "NearestRoom", nearest_room)?;
+
     // 初始化traverse函数
     let planner = {
         let paths = FilteredEdges::new(paths.clone(), |p| {
             p.category != PathCategory::Bus && p.category != PathCategory::Boat
         });
-        Planner::new(rooms.clone(), paths.clone())
+        let fs = factors.clone();
+        Planner::new(rooms.clone(), paths.clone()).with_cost(move |p| fs.cost(p))
     };
     let traverse = lua.create_function(move |lua, (centerid, depth): (u32, u32)| {
         let plan = planner.traverse(centerid, depth);
@@ -446,7 +1377,35 @@ pub fn init_mapper(lua: &Lua, conn: Connection) -> Result<()> {
     })?;
     register_function(&globals, "Traverse", traverse)?;
 
-    let conn = Arc::new(Mutex::new(conn));
+    // 初始化TraverseZone函数
+    // 仅在起点所在区域内遍历，也可指定区域代码列表
+    let (rs, ps, fs) = (rooms.clone(), paths.clone(), factors.clone());
+    let traverse_zone = lua.create_function(
+        move |lua, (centerid, depth, zones): (u32, u32, Option<Vec<String>>)| {
+            let zones: HashSet<String> = match zones {
+                Some(zones) => zones.into_iter().collect(),
+                None => rs.get(centerid).map(|room| room.zone).into_iter().collect(),
+            };
+            let zones = Arc::new(zones);
+            let nodes = {
+                let zones = zones.clone();
+                FilteredNodes::new(rs.clone(), move |room: &Room| zones.contains(&room.zone))
+            };
+            let paths = {
+                let rs = rs.clone();
+                FilteredEdges::new(ps.clone(), move |p: &Path| {
+                    p.category != PathCategory::Bus
+                        && p.category != PathCategory::Boat
+                        && rs.get(p.endid).map(|room| zones.contains(&room.zone)).unwrap_or(false)
+                })
+            };
+            let fs = fs.clone();
+            let planner = Planner::new(nodes, paths).with_cost(move |p| fs.cost(p));
+            let plan = planner.traverse(centerid, depth);
+            plan.to_lua(lua)
+        },
+    )?;
+    register_function(&globals, "TraverseZone", traverse_zone)?;
 
     // 初始化ListZones函数
     let mapper = Mapper::new(conn.clone());
@@ -541,7 +1500,158 @@ pub fn init_mapper(lua: &Lua, conn: Connection) -> Result<()> {
     })?;
     register_function(&globals, "ListRoomsByNpc", list_rooms_by_npc)?;
 
-    Ok(())
+    // 房间备注与书签存放于独立的表，不修改共享的地图数据
+    // 无法建表（如只读数据库）时仍加载地图，仅备注与书签不可用
+    let mapper = Mapper::new(conn);
+    mapper.check_version()?;
+    if let Err(e) = mapper.init_annotations() {
+        log::warn!("init room annotations error {}", e);
+    }
+
+    // 初始化map模块，脚本中通过require("map")使用
+    let module = MapModule::new(rooms.clone(), mapper.clone());
+    let load_map = lua.create_function(move |lua, _: mlua::MultiValue| module.to_table(lua))?;
+    let package: mlua::Table = globals.get("package")?;
+    let preload: mlua::Table = package.get("preload")?;
+    preload.set("map", load_map)?;
+
+    // 初始化SetRoomNote函数
+    let m = mapper.clone();
+    let set_room_note = lua.create_function(move |_, (roomid, note): (u32, Option<String>)| {
+        m.set_room_note(roomid, note.as_deref().unwrap_or_default())?;
+        Ok(())
+    })?;
+    register_function(&globals, "SetRoomNote", set_room_note)?;
+
+    // 初始化GetRoomNote函数
+    let m = mapper.clone();
+    let get_room_note = lua.create_function(move |_, roomid: u32| Ok(m.get_room_note(roomid)?))?;
+    register_function(&globals, "GetRoomNote", get_room_note)?;
+
+    // 初始化SetBookmark函数
+    let m = mapper.clone();
+    let set_bookmark = lua.create_function(move |_, (name, roomid): (String, u32)| {
+        m.set_bookmark(&name, roomid)?;
+        Ok(())
+    })?;
+    register_function(&globals, "SetBookmark", set_bookmark)?;
+
+    // 初始化DeleteBookmark函数
+    let m = mapper.clone();
+    let delete_bookmark = lua.create_function(move |_, name: String| Ok(m.delete_bookmark(&name)?))?;
+    register_function(&globals, "DeleteBookmark", delete_bookmark)?;
+
+    // 初始化GetBookmark函数
+    let m = mapper.clone();
+    let get_bookmark = lua.create_function(move |lua, name: String| match m.get_bookmark(&name)? {
+        Some(bookmark) => Ok(bookmark.to_lua(lua)?),
+        None => Ok(mlua::Value::Nil),
+    })?;
+    register_function(&globals, "GetBookmark", get_bookmark)?;
+
+    // 初始化ListBookmarks函数
+    let m = mapper.clone();
+    let list_bookmarks = lua.create_function(move |lua, _: ()| {
+        let bookmarks = m.list_bookmarks()?;
+        bookmarks.to_lua(lua)
+    })?;
+    register_function(&globals, "ListBookmarks", list_bookmarks)?;
+
+    // 初始化SetWalker函数
+    // #go命令以书签的房间编号及书签名称调用该函数，由脚本完成行走
+    let set_walker = lua.create_function(move |lua, func: Option<mlua::Function>| {
+        lua.globals().set(engine::GLOBAL_WALKER, func)?;
+        Ok(())
+    })?;
+    register_function(&globals, "SetWalker", set_walker)?;
+
+    Ok(mapper)
+}
+
+/// 创建发送命令的回调函数，用于配置文件中定义的触发器和别名
+///
+/// 回调的第三个参数为捕获表，send中的%1..%9以及%<name>将被替换为捕获的文本
+pub fn create_send_callback<'lua>(lua: &'lua Lua, tmpq: &ActionQueue, send: &str) -> Result<mlua::Function<'lua>> {
+    let subs = SubParser::new().parse(send)?;
+    let queue = tmpq.clone();
+    let callback = lua.create_function(move |_, (_, _, wildcards): (String, String, mlua::Table)| {
+        let cmd = sub::expand(&subs, |s| match s {
+            Sub::Number(n) => wildcards.get::<_, Option<String>>(*n).ok().flatten(),
+            Sub::Name(name) => wildcards.get::<_, Option<String>>(&name[..]).ok().flatten(),
+            Sub::Text(_) => None,
+        });
+        queue.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)));
+        Ok(())
+    })?;
+    Ok(callback)
+}
+
+/// 定时器发送命令的回调，定时器回调不接收参数
+pub fn create_timer_send_callback<'lua>(lua: &'lua Lua, tmpq: &ActionQueue, send: &str) -> Result<mlua::Function<'lua>> {
+    let queue = tmpq.clone();
+    let cmd = send.to_owned();
+    let callback = lua.create_function(move |_, ()| {
+        queue.push(EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd.clone())));
+        Ok(())
+    })?;
+    Ok(callback)
+}
+
+/// 按名称调用Lua全局函数的回调，执行时才查找，参数原样传递
+pub fn create_named_callback<'lua>(lua: &'lua Lua, name: &str) -> Result<mlua::Function<'lua>> {
+    let name = name.to_owned();
+    let callback = lua.create_function(move |lua, args: mlua::MultiValue| {
+        let func: Option<mlua::Function> = lua.globals().get(&name[..])?;
+        let func = func.ok_or_else(|| {
+            mlua::Error::external(Error::RuntimeError(format!("callback function {} not found", name)))
+        })?;
+        func.call::<_, ()>(args)
+    })?;
+    Ok(callback)
+}
+
+// 解析触发器的样式条件，如{fg="yellow", modifier=1, capture="who"}
+fn style_cond(table: mlua::Table) -> mlua::Result<StyleCond> {
+    let color = |key: &str| -> mlua::Result<Option<Color>> {
+        match table.get::<_, Option<String>>(key)? {
+            Some(name) => Color::from_str(&name)
+                .map(Some)
+                .ok_or_else(|| mlua::Error::external(Error::RuntimeError(format!("invalid color {}", name)))),
+            None => Ok(None),
+        }
+    };
+    let modifier = table.get::<_, Option<u16>>("modifier")?.unwrap_or(0);
+    let modifier = Modifier::from_bits(modifier)
+        .ok_or_else(|| mlua::Error::external(Error::RuntimeError(format!("invalid modifier {}", modifier))))?;
+    let capture = match table.get::<_, mlua::Value>("capture")? {
+        mlua::Value::Nil => NumberOrString::Number(0),
+        mlua::Value::Integer(n) if n >= 0 => NumberOrString::Number(n as usize),
+        mlua::Value::String(s) => NumberOrString::new_string(s.to_str()?),
+        other => {
+            return Err(mlua::Error::external(Error::RuntimeError(format!(
+                "invalid capture {:?}",
+                other
+            ))))
+        }
+    };
+    Ok(StyleCond {
+        fg: color("fg")?,
+        bg: color("bg")?,
+        modifier,
+        capture,
+    })
+}
+
+// 变量值转换为字符串存储，表以JSON格式存储
+fn var_to_string(value: mlua::Value) -> mlua::Result<String> {
+    let s = match value {
+        mlua::Value::String(s) => s.to_str()?.to_owned(),
+        mlua::Value::Integer(n) => n.to_string(),
+        mlua::Value::Number(n) => json::format_number(n),
+        mlua::Value::Boolean(b) => b.to_string(),
+        other => json::lua_to_json(other)?.to_string(),
+    };
+    Ok(s)
 }
 
 fn register_function<'lua>(namespace: &'lua mlua::Table, name: impl AsRef<str>, function: mlua::Function<'lua>) -> Result<()> {
@@ -550,3 +1660,13 @@ fn register_function<'lua>(namespace: &'lua mlua::Table, name: impl AsRef<str>,
     namespace.set(name, function)?;
     Ok(())
 }
+
+// 记录模型定义所在的脚本文件，不在加载脚本时不记录
+fn record_origin(lua: &mlua::Lua, table: &str, name: &str) -> mlua::Result<()> {
+    let loading: Option<String> = lua.globals().get(engine::GLOBAL_LOADING_FILE)?;
+    if let Some(file) = loading {
+        let origins: mlua::Table = lua.globals().get(table)?;
+        origins.set(name, file)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::error::{Error, Result};
+use mlua::{Lua, Value};
+use serde_json::{Map, Number, Value as Json};
+
+/// 将Lua值转换为JSON
+///
+/// 键为1..n的连续整数的非空表转换为数组，其余表转换为对象
+pub fn lua_to_json(value: Value) -> Result<Json> {
+    let json = match value {
+        Value::Nil => Json::Null,
+        Value::Boolean(b) => Json::Bool(b),
+        Value::Integer(n) => Json::Number(n.into()),
+        // Lua 5.1中整数也以浮点数表示
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => Json::Number((n as i64).into()),
+        Value::Number(n) => Number::from_f64(n).map(Json::Number).ok_or_else(|| {
+            Error::RuntimeError(format!("cannot convert number {} to json", n))
+        })?,
+        Value::String(s) => Json::String(s.to_str()?.to_owned()),
+        Value::Table(table) => {
+            let len = table.raw_len() as usize;
+            let mut pairs = Vec::new();
+            for pair in table.pairs::<Value, Value>() {
+                pairs.push(pair?);
+            }
+            let seq_idx = |k: &Value| match k {
+                Value::Integer(i) if *i >= 1 && *i as usize <= len => Some(*i as usize),
+                Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 && *n as usize <= len => {
+                    Some(*n as usize)
+                }
+                _ => None,
+            };
+            if len > 0 && pairs.len() == len && pairs.iter().all(|(k, _)| seq_idx(k).is_some()) {
+                let mut arr = vec![Json::Null; len];
+                for (k, v) in pairs {
+                    arr[seq_idx(&k).unwrap() - 1] = lua_to_json(v)?;
+                }
+                Json::Array(arr)
+            } else {
+                let mut obj = Map::new();
+                for (k, v) in pairs {
+                    let key = match k {
+                        Value::String(s) => s.to_str()?.to_owned(),
+                        Value::Integer(i) => i.to_string(),
+                        Value::Number(n) => format_number(n),
+                        other => {
+                            return Err(Error::RuntimeError(format!(
+                                "unsupported table key type {}",
+                                other.type_name()
+                            )))
+                        }
+                    };
+                    obj.insert(key, lua_to_json(v)?);
+                }
+                Json::Object(obj)
+            }
+        }
+        other => {
+            return Err(Error::RuntimeError(format!(
+                "cannot convert {} to json",
+                other.type_name()
+            )))
+        }
+    };
+    Ok(json)
+}
+
+/// 将JSON转换为Lua值
+pub fn json_to_lua<'lua>(lua: &'lua Lua, json: &Json) -> mlua::Result<Value<'lua>> {
+    let value = match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::Boolean(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or_default()),
+        },
+        Json::String(s) => Value::String(lua.create_string(s)?),
+        Json::Array(arr) => {
+            let table = lua.create_table()?;
+            for (i, v) in arr.iter().enumerate() {
+                table.raw_set(i + 1, json_to_lua(lua, v)?)?;
+            }
+            Value::Table(table)
+        }
+        Json::Object(obj) => {
+            let table = lua.create_table()?;
+            for (k, v) in obj {
+                // 整数键还原为数字，与lua_to_json对应
+                match k.parse::<i64>() {
+                    Ok(i) => table.raw_set(i, json_to_lua(lua, v)?)?,
+                    Err(_) => table.raw_set(&k[..], json_to_lua(lua, v)?)?,
+                }
+            }
+            Value::Table(table)
+        }
+    };
+    Ok(value)
+}
+
+/// 数字转为字符串，整数不保留小数部分
+pub fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lua_roundtrip() {
+        let lua = Lua::new();
+        let value: Value = lua
+            .load(r#"return {name="张三", hp=100, tags={"a", "b"}, ok=true}"#)
+            .eval()
+            .unwrap();
+        let json = lua_to_json(value).unwrap();
+        assert_eq!(
+            serde_json::json!({"name": "张三", "hp": 100, "tags": ["a", "b"], "ok": true}),
+            json
+        );
+        let value = json_to_lua(&lua, &json).unwrap();
+        assert_eq!(json, lua_to_json(value).unwrap());
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!("3", format_number(3.0));
+        assert_eq!("-2.5", format_number(-2.5));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::localtime::localtime;
+
+/// 历史行上的书签
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineMark {
+    pub lineno: usize,
+    // 标记时间，自UNIX纪元起的毫秒数
+    pub time: u64,
+    pub label: String,
+}
+
+/// 历史行书签及跳转列表，仅在本次会话中保留
+///
+/// 书签按行号排序，序号从1开始
+#[derive(Debug, Default)]
+pub struct LineMarks {
+    marks: Vec<LineMark>,
+    // 跳转列表中的当前位置，None表示位于最新书签之后
+    cursor: Option<usize>,
+}
+
+impl LineMarks {
+    /// 添加书签，同一行重复标记时更新标签及时间，返回其序号
+    pub fn add(&mut self, lineno: usize, time: u64, label: impl Into<String>) -> usize {
+        let mark = LineMark {
+            lineno,
+            time,
+            label: label.into(),
+        };
+        let idx = match self.marks.binary_search_by_key(&lineno, |m| m.lineno) {
+            Ok(idx) => {
+                self.marks[idx] = mark;
+                idx
+            }
+            Err(idx) => {
+                self.marks.insert(idx, mark);
+                idx
+            }
+        };
+        self.cursor = None;
+        idx + 1
+    }
+
+    pub fn list(&self) -> &[LineMark] {
+        &self.marks
+    }
+
+    /// 删除指定序号的书签
+    pub fn remove(&mut self, n: usize) -> Option<LineMark> {
+        if n == 0 || n > self.marks.len() {
+            return None;
+        }
+        self.cursor = None;
+        Some(self.marks.remove(n - 1))
+    }
+
+    pub fn clear(&mut self) {
+        self.marks.clear();
+        self.cursor = None;
+    }
+
+    /// 跳转到指定序号的书签
+    pub fn jump(&mut self, n: usize) -> Option<(usize, &LineMark)> {
+        let mark = self.marks.get(n.checked_sub(1)?)?;
+        self.cursor = Some(n - 1);
+        Some((n, mark))
+    }
+
+    /// 跳转到更早的书签，已位于最早的书签时停留在原处
+    pub fn older(&mut self) -> Option<(usize, &LineMark)> {
+        let idx = match self.cursor {
+            None => self.marks.len().checked_sub(1)?,
+            Some(idx) => idx.saturating_sub(1),
+        };
+        self.jump(idx + 1)
+    }
+
+    /// 跳转到更新的书签，越过最新的书签时返回None
+    pub fn newer(&mut self) -> Option<(usize, &LineMark)> {
+        let idx = self.cursor? + 1;
+        if idx >= self.marks.len() {
+            self.cursor = None;
+            return None;
+        }
+        self.jump(idx + 1)
+    }
+}
+
+/// 本地时间的时分秒，如“21:03:15”
+pub fn clock(millis: u64) -> String {
+    let tm = match localtime(millis) {
+        Some(tm) => tm,
+        None => return String::from("--:--:--"),
+    };
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_marks_jump_list() {
+        let mut marks = LineMarks::default();
+        assert!(marks.older().is_none());
+        assert_eq!(1, marks.add(10, 0, "战斗开始"));
+        assert_eq!(2, marks.add(30, 0, ""));
+        // 按行号插入
+        assert_eq!(2, marks.add(20, 0, "张三告诉你"));
+        assert_eq!(3, marks.add(30, 1, "更新"));
+        assert_eq!(3, marks.list().len());
+        assert_eq!("更新", marks.list()[2].label);
+
+        assert_eq!(30, marks.older().unwrap().1.lineno);
+        assert_eq!(20, marks.older().unwrap().1.lineno);
+        assert_eq!(10, marks.older().unwrap().1.lineno);
+        assert_eq!(10, marks.older().unwrap().1.lineno);
+        assert_eq!(20, marks.newer().unwrap().1.lineno);
+        assert_eq!(30, marks.newer().unwrap().1.lineno);
+        assert!(marks.newer().is_none());
+        assert_eq!((1, 10), marks.jump(1).map(|(n, m)| (n, m.lineno)).unwrap());
+        assert!(marks.jump(4).is_none());
+        assert_eq!(10, marks.remove(1).unwrap().lineno);
+        assert!(marks.remove(0).is_none());
+        assert_eq!(8, clock(0).len());
+    }
+}
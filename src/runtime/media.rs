@@ -0,0 +1,212 @@
+use crate::error::{Error, Result};
+use crate::i18n;
+use lazy_static::lazy_static;
+use mlua::{Lua, ToLua, Value};
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// 同时运行的播放命令上限，避免服务器大量发送指令时产生过多子进程
+const MAX_PLAYING: usize = 4;
+
+lazy_static! {
+    // MSP指令，如!!SOUND(hit.wav V=80 L=1)
+    static ref DIRECTIVE: Regex = Regex::new(r"!!(SOUND|MUSIC)\(([^)]*)\)").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MediaKind {
+    #[default]
+    Sound,
+    Music,
+}
+
+impl MediaKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sound => "sound",
+            Self::Music => "music",
+        }
+    }
+}
+
+/// 服务器发送的MSP声音或音乐指令
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MediaDirective {
+    pub kind: MediaKind,
+    // 文件名，Off表示停止播放
+    pub file: String,
+    // V：音量，0-100
+    pub volume: Option<u32>,
+    // L：重复次数，-1表示无限循环
+    pub loops: Option<i32>,
+    // P：优先级，仅用于声音
+    pub priority: Option<u32>,
+    // C：文件相同时是否继续播放，仅用于音乐
+    pub continued: Option<bool>,
+    // T：类型，即文件所在的子目录
+    pub category: Option<String>,
+    // U：下载地址
+    pub url: Option<String>,
+}
+
+impl MediaDirective {
+    fn parse(kind: &str, args: &str) -> Self {
+        let mut directive = Self {
+            kind: if kind == "MUSIC" { MediaKind::Music } else { MediaKind::Sound },
+            ..Self::default()
+        };
+        for arg in args.split_whitespace() {
+            match arg.split_once('=') {
+                None if directive.file.is_empty() => directive.file = arg.to_owned(),
+                None => (),
+                Some((key, value)) => match key.to_ascii_uppercase().as_str() {
+                    "V" => directive.volume = value.parse().ok(),
+                    "L" => directive.loops = value.parse().ok(),
+                    "P" => directive.priority = value.parse().ok(),
+                    "C" => directive.continued = Some(value == "1"),
+                    "T" => directive.category = Some(value.to_owned()),
+                    "U" => directive.url = Some(value.to_owned()),
+                    _ => log::debug!("unknown media directive argument {}", arg),
+                },
+            }
+        }
+        directive
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.file.eq_ignore_ascii_case("off")
+    }
+}
+
+impl<'lua> ToLua<'lua> for MediaDirective {
+    fn to_lua(self, lua: &'lua Lua) -> mlua::Result<Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind.name())?;
+        table.set("file", self.file)?;
+        table.set("volume", self.volume)?;
+        table.set("loops", self.loops)?;
+        table.set("priority", self.priority)?;
+        table.set("continue", self.continued)?;
+        table.set("type", self.category)?;
+        table.set("url", self.url)?;
+        Ok(Value::Table(table))
+    }
+}
+
+/// 是否可能包含指令，避免对每行执行正则
+pub fn maybe_contains(text: &str) -> bool {
+    text.contains("!!SOUND(") || text.contains("!!MUSIC(")
+}
+
+/// 提取文本中的全部指令，返回删除指令后的文本
+pub fn extract(text: &str) -> (String, Vec<MediaDirective>) {
+    let directives = DIRECTIVE
+        .captures_iter(text)
+        .map(|caps| MediaDirective::parse(&caps[1], &caps[2]))
+        .collect();
+    (DIRECTIVE.replace_all(text, "").into_owned(), directives)
+}
+
+/// 通过外部命令播放声音文件
+///
+/// 文件路径及音量通过环境变量MUD_MEDIA_FILE及MUD_MEDIA_VOLUME传入，
+/// 不拼接进命令行，避免服务器发送的文件名被解释为命令
+#[derive(Debug, Clone)]
+pub struct MediaPlayer {
+    cmd: String,
+    dir: PathBuf,
+    // 正在运行的播放命令数
+    playing: Arc<AtomicUsize>,
+}
+
+impl MediaPlayer {
+    pub fn new(cmd: impl Into<String>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cmd: cmd.into(),
+            dir: dir.into(),
+            playing: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// 声音目录下的文件路径，拒绝绝对路径及上级目录
+    pub fn resolve(&self, category: Option<&str>, file: &str) -> Result<PathBuf> {
+        let mut path = self.dir.clone();
+        for part in category.into_iter().chain(std::iter::once(file)) {
+            if !Path::new(part).components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(Error::RuntimeError(format!("invalid media file {}", part)));
+            }
+            path.push(part);
+        }
+        Ok(path)
+    }
+
+    /// 在后台播放，不等待命令结束，运行中的命令达到上限时返回错误
+    pub fn play(&self, category: Option<&str>, file: &str, volume: Option<u32>) -> Result<()> {
+        let path = self.resolve(category, file)?;
+        if self.playing.load(Ordering::SeqCst) >= MAX_PLAYING {
+            return Err(Error::RuntimeError(i18n::trf("err.media_busy", &[&file, &MAX_PLAYING])));
+        }
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.cmd)
+            .env("MUD_MEDIA_FILE", &path)
+            .env("MUD_MEDIA_VOLUME", volume.unwrap_or(100).to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        self.playing.fetch_add(1, Ordering::SeqCst);
+        let playing = Arc::clone(&self.playing);
+        thread::spawn(move || {
+            if let Err(e) = child.wait() {
+                log::warn!("media command error {}", e);
+            }
+            playing.fetch_sub(1, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_extract() {
+        assert!(!maybe_contains("你走了过来。"));
+        let (text, directives) = extract("!!SOUND(hit.wav V=80 L=2 T=combat)你被击中了。!!MUSIC(Off)");
+        assert_eq!("你被击中了。", text);
+        assert_eq!(2, directives.len());
+        assert_eq!(MediaKind::Sound, directives[0].kind);
+        assert_eq!("hit.wav", directives[0].file);
+        assert_eq!(Some(80), directives[0].volume);
+        assert_eq!(Some(2), directives[0].loops);
+        assert_eq!(Some("combat".to_owned()), directives[0].category);
+        assert!(directives[1].is_off());
+
+        let player = MediaPlayer::new("true", "/tmp/sounds");
+        assert_eq!(
+            PathBuf::from("/tmp/sounds/combat/hit.wav"),
+            player.resolve(Some("combat"), "hit.wav").unwrap()
+        );
+        assert!(player.resolve(None, "../secret").is_err());
+        assert!(player.resolve(None, "/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_media_player_limit() {
+        let player = MediaPlayer::new("sleep 0.2", "/tmp/sounds");
+        for _ in 0..MAX_PLAYING {
+            player.play(None, "hit.wav", None).unwrap();
+        }
+        assert!(player.play(None, "hit.wav", None).is_err());
+        // 命令结束后可以继续播放
+        thread::sleep(std::time::Duration::from_millis(500));
+        assert_eq!(0, player.playing.load(Ordering::SeqCst));
+        player.play(None, "hit.wav", None).unwrap();
+    }
+}
@@ -1,19 +1,48 @@
 pub mod alias;
+pub mod bundle;
 pub mod cache;
+pub mod classify;
+pub mod defs;
 pub mod delay_queue;
+pub mod dump;
 pub mod engine;
+pub mod export;
+pub mod frame;
+pub mod group;
+pub mod guard;
 pub mod init;
+pub mod json;
+pub mod marks;
+pub mod media;
 pub mod model;
+pub mod newline;
+pub mod observe;
+pub mod offline;
 pub mod queue;
+pub mod record;
+pub mod register;
+pub mod repl;
+pub mod route;
+pub mod scrollback;
+pub mod settings;
+pub mod status;
+pub mod statusbar;
 pub mod sub;
 pub mod timer;
+pub mod trace;
+pub mod transform;
 pub mod trigger;
 pub mod mxp_trigger;
+pub mod pacer;
+pub mod prompt;
 pub mod vars;
+pub mod worldlog;
+pub mod zmud;
 
+use crate::conf;
 use crate::error::Result;
-use crate::event::NextStep;
-use crate::ui::line::{Lines, RawLines};
+use crate::event::{NextStep, Sessions};
+use crate::ui::line::{Line, Lines, RawLines};
 
 pub use engine::{Engine, EngineAction};
 
@@ -21,11 +50,43 @@ pub use engine::{Engine, EngineAction};
 pub enum RuntimeOutput {
     /// 发送给服务器的命令
     ToServer(Vec<u8>),
-    /// 发送给UI的文本（包含原始文本，以及格式解析后的文本）
-    ToUI(RawLines, Lines),
+    /// 发送给UI的文本（包含原始文本，以及格式解析后的文本），及首行在回滚缓冲中的行号
+    ToUI(RawLines, Lines, usize),
+    /// 路由到指定窗口的文本，同时已发送至主窗格
+    ToWindow(String, Lines),
+    /// 服务器状态栏的各行
+    ToStatus(Vec<Line>),
+    /// 脚本等待按键时的提示，None表示超时并恢复命令行
+    ReadKey(Option<String>),
+    /// 弹出菜单的标题及选项
+    ShowMenu(String, Vec<String>),
+    /// 在命令行短暂显示重发的上一条命令
+    FlashCmd(String),
+    /// 服务器关闭或恢复回显，关闭时的输入不记录历史
+    SecretInput(bool),
+    /// REPL窗格中的输入回显及求值结果
+    ToRepl(Lines),
+    /// 立即重连服务器
+    Reconnect,
+    /// 列出、切换或打开会话
+    ManageSession(SessionCmd),
+    /// 通过#set调整后的界面设置
+    UpdateTerm(conf::Term),
+    /// 在后台下载脚本包，完成后以事件返回
+    FetchBundle(String),
 }
 
-/// 运行时事件回调
+/// #session子命令
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionCmd {
+    List,
+    // 切换到指定会话，序号从1开始
+    Switch(usize),
+    // 以名称及服务器地址打开新会话
+    Open(String, String),
+}
+
+/// 运行时事件回调，附带产生输出的会话序号
 pub trait RuntimeOutputHandler {
-    fn on_runtime_output(&mut self, output: RuntimeOutput) -> Result<NextStep>;
+    fn on_runtime_output(&mut self, session: usize, output: RuntimeOutput, sessions: &mut Sessions) -> Result<NextStep>;
 }
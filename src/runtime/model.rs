@@ -2,6 +2,7 @@ use crate::error::{Error, Result};
 use mlua::ToLua;
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
 use lazy_static::lazy_static;
 
 /// 持有模型的基本属性
@@ -12,7 +13,7 @@ pub struct Model<X> {
     pub pattern: String,
     pub enabled: bool,
     pub extra: X,
-    pub(super) re: Regex,
+    pub(super) re: Arc<Regex>,
 }
 
 impl<X: PartialEq> PartialEq for Model<X> {
@@ -31,11 +32,30 @@ pub struct ModelBuilder<X> {
     pattern: String,
     enabled: bool,
     extra: X,
-    re: Regex,
+    re: Arc<Regex>,
 }
 
 lazy_static! {
-    static ref EMPTY_REGEX: Regex = Regex::new("").unwrap();
+    static ref EMPTY_REGEX: Arc<Regex> = Arc::new(Regex::new("").unwrap());
+    // 已编译的正则表达式，以模式字符串为键，在所有模型间共享
+    static ref REGEX_CACHE: Mutex<HashMap<String, Weak<Regex>>> = Mutex::new(HashMap::new());
+}
+
+/// 编译正则表达式，相同模式复用已编译的结果
+///
+/// 缓存仅持有弱引用，模型全部删除后正则表达式随之释放
+pub fn compile_regex(pattern: &str) -> Result<Arc<Regex>> {
+    let mut cache = REGEX_CACHE.lock().unwrap();
+    if let Some(re) = cache.get(pattern).and_then(Weak::upgrade) {
+        return Ok(re);
+    }
+    let re = Arc::new(Regex::new(pattern)?);
+    // 容量翻倍前清理失效的条目
+    if cache.len() == cache.capacity() {
+        cache.retain(|_, re| re.strong_count() > 0);
+    }
+    cache.insert(pattern.to_owned(), Arc::downgrade(&re));
+    Ok(re)
 }
 
 impl<X: Default> Default for ModelBuilder<X> {
@@ -65,7 +85,7 @@ impl<X> ModelBuilder<X> {
 
     pub fn pattern(mut self, pattern: impl Into<String>) -> Result<Self> {
         let pattern = pattern.into();
-        let re = Regex::new(&pattern)?;
+        let re = compile_regex(&pattern)?;
         self.pattern = pattern;
         self.re = re;
         Ok(self)
@@ -179,6 +199,9 @@ where
     /// 禁用模型
     fn enable_group(&mut self, group: impl AsRef<str>, enabled: bool) -> usize;
 
+    /// 分组内所有模型的名称
+    fn group_names(&self, group: impl AsRef<str>) -> Vec<String>;
+
     /// 查询单个模型
     fn get(&self, name: impl AsRef<str>) -> Option<&M>;
 
@@ -205,6 +228,11 @@ where
     pub fn new() -> Self {
         Self(HashMap::new())
     }
+
+    /// 遍历所有模型，顺序不固定
+    pub fn iter(&self) -> impl Iterator<Item = &M> {
+        self.0.values()
+    }
 }
 
 impl<X> ModelStore<Model<X>> for MapModelStore<Model<X>>
@@ -243,6 +271,11 @@ where
         n
     }
 
+    fn group_names(&self, group: impl AsRef<str>) -> Vec<String> {
+        let group = group.as_ref();
+        self.0.values().filter(|m| m.group == group).map(|m| m.name.to_owned()).collect()
+    }
+
     fn get(&self, name: impl AsRef<str>) -> Option<&Model<X>> {
         self.0.get(name.as_ref())
     }
@@ -352,6 +385,14 @@ where
         n
     }
 
+    fn group_names(&self, group: impl AsRef<str>) -> Vec<String> {
+        let group = group.as_ref();
+        if group.is_empty() {
+            return vec![];
+        }
+        self.0.iter().filter(|me| me.group == group).map(|me| me.name.to_owned()).collect()
+    }
+
     fn get(&self, name: impl AsRef<str>) -> Option<&Model<X>> {
         let name = name.as_ref();
         if name.is_empty() {
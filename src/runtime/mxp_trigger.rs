@@ -7,12 +7,12 @@ pub type MxpTriggers = MapModelStore<MxpTrigger>;
 impl MxpTriggers {
     pub fn trigger_first(&self, input: &Element) -> Option<&MxpTrigger> {
         self.0.values()
-            .find(|tr| tr.is_match(input))
+            .find(|tr| tr.enabled && tr.is_match(input))
     }
 
     pub fn trigger_all(&self, input: &Element) -> Vec<&MxpTrigger> {
         self.0.values()
-            .filter(|tr| tr.is_match(input))
+            .filter(|tr| tr.enabled && tr.is_match(input))
             .collect()
     }
 }
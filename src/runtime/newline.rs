@@ -0,0 +1,132 @@
+use crate::conf::NewlineMode;
+use crate::ui::line::RawLine;
+use std::borrow::Cow;
+
+/// 服务器文本的换行符规范化
+///
+/// 单独的回车（包括RFC 854中的"\r\0"）按模式删除、视为换行或保留为行内覆盖，
+/// "\n\r"视为一个换行；数据以回车结束时暂不处理，待下一段数据确定其含义
+#[derive(Debug)]
+pub struct Newlines {
+    mode: NewlineMode,
+    pending_cr: bool,
+}
+
+impl Newlines {
+    pub fn new(mode: NewlineMode) -> Self {
+        Self {
+            mode,
+            pending_cr: false,
+        }
+    }
+
+    pub fn mode(&self) -> NewlineMode {
+        self.mode
+    }
+
+    /// 规范化一段解码后的文本，keep模式下原样返回
+    pub fn normalize<'a>(&mut self, s: &'a str) -> Cow<'a, str> {
+        if self.mode == NewlineMode::Keep {
+            return Cow::Borrowed(s);
+        }
+        let mut out = String::with_capacity(s.len() + 1);
+        let mut chars = s.chars().peekable();
+        if std::mem::take(&mut self.pending_cr) {
+            self.carriage_return(&mut out, &mut chars);
+        }
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' if chars.peek().is_none() => self.pending_cr = true,
+                '\r' => self.carriage_return(&mut out, &mut chars),
+                '\n' => {
+                    // "\n\r"视为一个换行
+                    let mut rest = chars.clone();
+                    if rest.next() == Some('\r') && !matches!(rest.next(), Some('\n')) {
+                        chars.next();
+                    }
+                    out.push_str("\r\n");
+                }
+                c => out.push(c),
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    // 处理回车及其后的字符
+    fn carriage_return(&self, out: &mut String, chars: &mut std::iter::Peekable<std::str::Chars>) {
+        match chars.peek() {
+            Some('\n') => {
+                chars.next();
+                out.push_str("\r\n");
+                return;
+            }
+            Some('\0') => {
+                chars.next();
+            }
+            _ => (),
+        }
+        match self.mode {
+            NewlineMode::Keep | NewlineMode::Strip => (),
+            NewlineMode::Break => out.push_str("\r\n"),
+            NewlineMode::Overwrite => out.push('\r'),
+        }
+    }
+
+    /// 覆盖模式下在单独的回车处拆分行，回车后的片段以回车开头，
+    /// 处理时覆盖当前未结束的行
+    pub fn split_returns(&self, line: RawLine) -> Vec<RawLine> {
+        if self.mode != NewlineMode::Overwrite {
+            return vec![line];
+        }
+        let s = line.as_ref();
+        let mut lines = vec![];
+        let mut start = 0;
+        for (i, _) in s.match_indices('\r') {
+            if i > start && !s[i..].starts_with("\r\n") {
+                lines.push(RawLine::new(s[start..i].to_owned()));
+                start = i;
+            }
+        }
+        if lines.is_empty() {
+            return vec![line];
+        }
+        lines.push(RawLine::new(s[start..].to_owned()));
+        lines
+    }
+}
+
+/// 是否以单独的回车开头
+pub fn starts_with_return(s: &str) -> bool {
+    s.starts_with('\r') && !s.starts_with("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newline_normalize() {
+        let mut strip = Newlines::new(NewlineMode::Strip);
+        assert_eq!("ab\r\ncd\r\nef", strip.normalize("a\rb\r\0\r\ncd\n\ref"));
+        let mut brk = Newlines::new(NewlineMode::Break);
+        assert_eq!("a\r\nb\r\n\r\n", brk.normalize("a\r\0b\r\n\n"));
+        // 跨数据包的"\r\n"不产生额外的换行
+        assert_eq!("c", brk.normalize("c\r"));
+        assert_eq!("\r\nd", brk.normalize("\nd"));
+        let mut keep = Newlines::new(NewlineMode::Keep);
+        assert_eq!("a\r\0b", keep.normalize("a\r\0b"));
+
+        let mut overwrite = Newlines::new(NewlineMode::Overwrite);
+        let s = overwrite.normalize("10%\r\x0020%\r");
+        assert_eq!("10%\r20%", s);
+        assert_eq!("\r30%\r\n", overwrite.normalize("30%\r\n"));
+        let lines: Vec<String> = overwrite
+            .split_returns(RawLine::new("10%\r20%\r30%\r\n".to_owned()))
+            .into_iter()
+            .map(|l| l.as_ref().to_owned())
+            .collect();
+        assert_eq!(vec!["10%", "\r20%", "\r30%\r\n"], lines);
+        assert!(starts_with_return("\r30%"));
+        assert!(!starts_with_return("\r\n"));
+    }
+}
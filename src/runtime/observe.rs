@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// 未指定时每个分组保留的样本数
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// 仅观察的触发器组，匹配时只记录命中次数及样本，不执行回调
+///
+/// 用于在真实文本上验证新的触发器，确认无误后再取消观察
+#[derive(Debug, Default)]
+pub struct Observer {
+    groups: HashMap<String, Observation>,
+}
+
+/// 单个分组的观察结果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Observation {
+    // 各触发器的命中次数，按名称排序
+    pub hits: BTreeMap<String, u64>,
+    // 最近命中的文本，最多保留max_samples条
+    pub samples: VecDeque<String>,
+    max_samples: usize,
+}
+
+impl Observation {
+    pub fn total(&self) -> u64 {
+        self.hits.values().sum()
+    }
+}
+
+impl Observer {
+    /// 开启或关闭分组的观察，重复开启时保留已有统计，仅调整样本数量
+    pub fn set(&mut self, group: &str, observe: bool, max_samples: usize) {
+        if !observe {
+            self.groups.remove(group);
+            return;
+        }
+        let ob = self.groups.entry(group.to_owned()).or_default();
+        ob.max_samples = max_samples;
+        while ob.samples.len() > max_samples {
+            ob.samples.pop_front();
+        }
+    }
+
+    pub fn is_observed(&self, group: &str) -> bool {
+        self.groups.contains_key(group)
+    }
+
+    /// 记录一次命中，分组未被观察时返回false
+    pub fn record(&mut self, group: &str, trigger: &str, text: &str) -> bool {
+        let ob = match self.groups.get_mut(group) {
+            Some(ob) => ob,
+            None => return false,
+        };
+        *ob.hits.entry(trigger.to_owned()).or_default() += 1;
+        if ob.max_samples > 0 {
+            if ob.samples.len() == ob.max_samples {
+                ob.samples.pop_front();
+            }
+            ob.samples.push_back(text.to_owned());
+        }
+        true
+    }
+
+    pub fn get(&self, group: &str) -> Option<&Observation> {
+        self.groups.get(group)
+    }
+
+    /// 按组名排序
+    pub fn list(&self) -> Vec<(&str, &Observation)> {
+        let mut groups: Vec<_> = self.groups.iter().map(|(g, ob)| (g.as_str(), ob)).collect();
+        groups.sort_by_key(|(g, _)| *g);
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observer_record() {
+        let mut observer = Observer::default();
+        assert!(!observer.record("fight", "t1", "你死了"));
+        observer.set("fight", true, 2);
+        assert!(observer.record("fight", "t1", "张三攻击你"));
+        assert!(observer.record("fight", "t2", "李四攻击你"));
+        assert!(observer.record("fight", "t1", "王五攻击你"));
+        let ob = observer.get("fight").unwrap();
+        assert_eq!(3, ob.total());
+        assert_eq!(Some(&2), ob.hits.get("t1"));
+        // 仅保留最近的样本
+        assert_eq!(vec!["李四攻击你", "王五攻击你"], ob.samples.iter().collect::<Vec<_>>());
+        observer.set("fight", true, 1);
+        assert_eq!(1, observer.get("fight").unwrap().samples.len());
+        observer.set("fight", false, 0);
+        assert!(!observer.is_observed("fight"));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// 暂存的命令
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedCmd {
+    pub cmd: String,
+    // 是否回显，不回显的命令（如密码）仅保存在内存中，不写入文件
+    pub echo: bool,
+}
+
+impl QueuedCmd {
+    pub fn new(cmd: impl Into<String>, echo: bool) -> Self {
+        Self { cmd: cmd.into(), echo }
+    }
+}
+
+/// 断线期间暂存的用户命令
+///
+/// 每条需回显的命令以JSON对象的形式追加写入文件的一行，
+/// 程序重启后仍可恢复，发送后清空文件
+#[derive(Debug)]
+pub struct OfflineQueue {
+    path: PathBuf,
+    cmds: Vec<QueuedCmd>,
+}
+
+impl OfflineQueue {
+    /// 打开队列文件，恢复上次未发送的命令
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut cmds = Vec::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(cmd) => cmds.push(cmd),
+                    Err(e) => log::warn!("skip invalid queued command {:?}: {}", line, e),
+                }
+            }
+        }
+        Ok(Self { path, cmds })
+    }
+
+    pub fn push(&mut self, cmd: QueuedCmd) -> Result<()> {
+        if cmd.echo {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&cmd)?)?;
+        }
+        self.cmds.push(cmd);
+        Ok(())
+    }
+
+    pub fn cmds(&self) -> &[QueuedCmd] {
+        &self.cmds
+    }
+
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    /// 取出所有命令并清空文件
+    pub fn take(&mut self) -> Result<Vec<QueuedCmd>> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(std::mem::take(&mut self.cmds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_offline_queue() {
+        let tmp = TempDir::new("offline");
+        let path = tmp.join("queue.jsonl");
+        let mut queue = OfflineQueue::open(path.clone()).unwrap();
+        assert!(queue.is_empty());
+        queue.push(QueuedCmd::new("look", true)).unwrap();
+        queue.push(QueuedCmd::new("hunter2", false)).unwrap();
+        queue.push(QueuedCmd::new("say 你好\n再见", true)).unwrap();
+        assert_eq!(3, queue.len());
+        // 重新打开后按顺序恢复，不回显的命令未写入文件
+        assert!(!fs::read_to_string(&path).unwrap().contains("hunter2"));
+        let mut queue = OfflineQueue::open(path.clone()).unwrap();
+        assert_eq!(&[QueuedCmd::new("look", true), QueuedCmd::new("say 你好\n再见", true)], queue.cmds());
+        assert_eq!(2, queue.take().unwrap().len());
+        assert!(!path.exists());
+        assert!(OfflineQueue::open(path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::conf;
+use crate::error::{Error, Result};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// 暂存的命令或等待
+#[derive(Debug, Clone, PartialEq)]
+pub enum Held {
+    Cmd(String),
+    // 等待标记的等待时间、之后的命令及别名调用链，放行时才开始计时
+    Wait(Duration, String, Vec<String>),
+}
+
+/// 按服务器命令队列长度控制发送节奏
+///
+/// 服务器报告的队列长度在每次发送后加一作为估计值，
+/// 估计值达到上限时暂存命令，收到新的队列长度后按余量依次放行。
+/// 等待与命令一同排队，之前的命令放行后才开始计时，且不占用队列余量
+#[derive(Debug)]
+pub struct Pacer {
+    pattern: Regex,
+    group: usize,
+    var: String,
+    max_depth: usize,
+    depth: usize,
+    held: VecDeque<Held>,
+}
+
+impl Pacer {
+    pub fn new(config: &conf::QueueTag) -> Result<Self> {
+        let pattern = Regex::new(&config.pattern)?;
+        if config.group >= pattern.captures_len() {
+            return Err(Error::RuntimeError(format!(
+                "queue tag group {} not in pattern {}",
+                config.group, config.pattern
+            )));
+        }
+        Ok(Self {
+            pattern,
+            group: config.group,
+            var: config.var.to_owned(),
+            max_depth: config.max_depth.max(1),
+            depth: 0,
+            held: VecDeque::new(),
+        })
+    }
+
+    /// 保存队列长度的变量名
+    pub fn var(&self) -> &str {
+        &self.var
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.max(1);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// 从服务器文本中提取队列长度
+    pub fn observe(&mut self, text: &str) -> Option<usize> {
+        let depth = self
+            .pattern
+            .captures(text)?
+            .get(self.group)?
+            .as_str()
+            .trim()
+            .parse()
+            .ok()?;
+        self.depth = depth;
+        Some(depth)
+    }
+
+    /// 发送命令，队列已满或已有暂存命令时暂存并返回None
+    pub fn offer(&mut self, cmd: String) -> Option<String> {
+        if !self.held.is_empty() || self.depth >= self.max_depth {
+            self.held.push_back(Held::Cmd(cmd));
+            return None;
+        }
+        self.depth += 1;
+        Some(cmd)
+    }
+
+    /// 开始等待，已有暂存命令时排在其后并返回None
+    pub fn offer_wait(&mut self, delay: Duration, cmd: String, chain: Vec<String>) -> Option<Held> {
+        let wait = Held::Wait(delay, cmd, chain);
+        if self.held.is_empty() {
+            return Some(wait);
+        }
+        self.held.push_back(wait);
+        None
+    }
+
+    /// 按队列余量取出可以发送的暂存命令，以及排在其间的等待
+    pub fn release(&mut self) -> Vec<Held> {
+        let mut released = Vec::new();
+        while let Some(item) = self.held.front() {
+            if let Held::Cmd(_) = item {
+                if self.depth >= self.max_depth {
+                    break;
+                }
+                self.depth += 1;
+            }
+            released.extend(self.held.pop_front());
+        }
+        released
+    }
+
+    /// 取出全部暂存命令，用于服务器不再报告队列长度时手动放行
+    pub fn flush(&mut self) -> Vec<Held> {
+        self.depth += self.held.iter().filter(|item| matches!(item, Held::Cmd(_))).count();
+        self.held.drain(..).collect()
+    }
+
+    /// 丢弃全部暂存命令
+    pub fn clear(&mut self) -> usize {
+        let n = self.held.len();
+        self.held.clear();
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacer_queue_depth() {
+        let config = conf::QueueTag {
+            pattern: r"\[队列:(\d+)\]".to_owned(),
+            max_depth: 3,
+            ..conf::QueueTag::default()
+        };
+        let mut pacer = Pacer::new(&config).unwrap();
+        assert_eq!(Some(2), pacer.observe("[队列:2]>"));
+        assert_eq!(None, pacer.observe("你走了过来。"));
+        assert_eq!(Some("n".to_owned()), pacer.offer("n".to_owned()));
+        assert_eq!(None, pacer.offer("e".to_owned()));
+        let wait = Duration::from_millis(500);
+        assert_eq!(None, pacer.offer_wait(wait, "s".to_owned(), vec![]));
+        assert!(pacer.release().is_empty());
+        // 队列缩短后按余量放行，等待不占用余量
+        pacer.observe("[队列:1]>");
+        assert_eq!(
+            vec![Held::Cmd("e".to_owned()), Held::Wait(wait, "s".to_owned(), vec![])],
+            pacer.release()
+        );
+        assert_eq!(2, pacer.depth());
+        assert_eq!(Some(Held::Wait(wait, "s".to_owned(), vec![])), pacer.offer_wait(wait, "s".to_owned(), vec![]));
+        assert_eq!(Some("e".to_owned()), pacer.offer("e".to_owned()));
+        assert_eq!(3, pacer.depth());
+        assert_eq!(None, pacer.offer("w".to_owned()));
+        assert_eq!(1, pacer.clear());
+        let config = conf::QueueTag {
+            pattern: r"\[队列\]".to_owned(),
+            ..conf::QueueTag::default()
+        };
+        assert!(Pacer::new(&config).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::conf;
+use crate::error::{Error, Result};
+use regex::Regex;
+
+/// 按配置的模板解析提示符中的角色状态
+#[derive(Debug)]
+pub struct PromptParser {
+    pattern: Regex,
+    var_prefix: String,
+    status: String,
+}
+
+impl PromptParser {
+    pub fn new(config: &conf::Prompt) -> Result<Self> {
+        let pattern = Regex::new(&config.pattern)?;
+        if pattern.capture_names().flatten().next().is_none() {
+            return Err(Error::RuntimeError(format!(
+                "prompt pattern {} has no named group",
+                config.pattern
+            )));
+        }
+        Ok(Self {
+            pattern,
+            var_prefix: config.var_prefix.to_owned(),
+            status: config.status.to_owned(),
+        })
+    }
+
+    /// 匹配提示符，返回各命名组对应的变量名及值，未参与匹配的组不返回
+    pub fn parse(&self, text: &str) -> Option<Vec<(String, String)>> {
+        let caps = self.pattern.captures(text)?;
+        let vars = self
+            .pattern
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                let value = caps.name(name)?.as_str().trim();
+                Some((format!("{}{}", self.var_prefix, name), value.to_owned()))
+            })
+            .collect();
+        Some(vars)
+    }
+
+    /// 按格式生成状态栏文本，{组名}替换为捕获的值，未匹配的组替换为空，未配置格式时返回None
+    pub fn status_text(&self, vars: &[(String, String)]) -> Option<String> {
+        if self.status.is_empty() {
+            return None;
+        }
+        let mut text = self.status.to_owned();
+        for name in self.pattern.capture_names().flatten() {
+            let var = format!("{}{}", self.var_prefix, name);
+            let value = vars
+                .iter()
+                .find(|(k, _)| *k == var)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or_default();
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_parser() {
+        let config = conf::Prompt {
+            pattern: r"气血:(?P<hp>\d+)/(?P<hp_max>\d+) 内力:(?P<mp>\d+)(?: 敌人:(?P<enemy>\S+))?".to_owned(),
+            var_prefix: "prompt.".to_owned(),
+            status: "HP {hp}/{hp_max} MP {mp} {enemy}".to_owned(),
+        };
+        let parser = PromptParser::new(&config).unwrap();
+        let vars = parser.parse("气血:80/100 内力:50 敌人:张三 >").unwrap();
+        assert_eq!(("prompt.hp".to_owned(), "80".to_owned()), vars[0]);
+        assert_eq!(("prompt.enemy".to_owned(), "张三".to_owned()), vars[3]);
+        assert_eq!(Some("HP 80/100 MP 50 张三".to_owned()), parser.status_text(&vars));
+        // 可选的组未匹配时不更新变量
+        let vars = parser.parse("气血:90/100 内力:50 >").unwrap();
+        assert_eq!(3, vars.len());
+        assert_eq!(Some("HP 90/100 MP 50 ".to_owned()), parser.status_text(&vars));
+        assert!(parser.parse("你走了过来。").is_none());
+        let config = conf::Prompt {
+            pattern: r"hp:(\d+)".to_owned(),
+            ..conf::Prompt::default()
+        };
+        assert!(PromptParser::new(&config).is_err());
+    }
+}
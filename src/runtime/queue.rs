@@ -18,30 +18,54 @@ impl OutputQueue {
         Self(vec![])
     }
 
-    pub fn send_line(&mut self, raw: RawLine, styled: Line) {
+    /// 发送运行时输出的行，lineno为其在回滚缓冲中的行号
+    pub fn send_line(&mut self, raw: RawLine, styled: Line, lineno: usize) {
         self.send_raw_line(raw);
-        self.send_styled_line(styled);
+        self.send_styled_line(styled, lineno);
     }
 
-    fn send_raw_line(&mut self, raw: RawLine) {
-        if let Some(RuntimeOutput::ToUI(raw_lines, _)) = self.0.last_mut() {
+    pub fn send_raw_line(&mut self, raw: RawLine) {
+        if let Some(RuntimeOutput::ToUI(raw_lines, ..)) = self.0.last_mut() {
             raw_lines.push_line(raw);
             return;
         }
         let mut raw_lines = RawLines::unbounded();
         raw_lines.push_line(raw);
-        self.0.push(RuntimeOutput::ToUI(raw_lines, Lines::new()));
+        self.0.push(RuntimeOutput::ToUI(raw_lines, Lines::new(), 0));
     }
 
-    pub fn send_styled_line(&mut self, styled: Line) {
-        if let Some(RuntimeOutput::ToUI(_, styled_lines)) = self.0.last_mut() {
+    pub fn send_styled_line(&mut self, styled: Line, lineno: usize) {
+        if let Some(RuntimeOutput::ToUI(_, styled_lines, first)) = self.0.last_mut() {
+            // 仅有原始文本时，以本行作为首行
+            if styled_lines.is_empty() {
+                *first = lineno;
+            }
             styled_lines.push_line(styled);
             return;
         }
         let mut styled_lines = Lines::new();
         styled_lines.push_line(styled);
         self.0
-            .push(RuntimeOutput::ToUI(RawLines::unbounded(), styled_lines));
+            .push(RuntimeOutput::ToUI(RawLines::unbounded(), styled_lines, lineno));
+    }
+
+    /// 连续路由到同一窗口的行合并发送
+    pub fn send_window_line(&mut self, target: String, styled: Line) {
+        if let Some(RuntimeOutput::ToWindow(t, styled_lines)) = self.0.last_mut() {
+            if *t == target {
+                styled_lines.push_line(styled);
+                return;
+            }
+        }
+        let mut styled_lines = Lines::new();
+        styled_lines.push_line(styled);
+        self.0.push(RuntimeOutput::ToWindow(target, styled_lines));
+    }
+
+    /// 状态栏仅保留最新内容
+    pub fn send_status(&mut self, lines: Vec<Line>) {
+        self.0.retain(|o| !matches!(o, RuntimeOutput::ToStatus(_)));
+        self.0.push(RuntimeOutput::ToStatus(lines));
     }
 
     /// 推送命令必须以\n结尾
@@ -64,6 +88,15 @@ impl OutputQueue {
         self.0.push(RuntimeOutput::ToServer(output));
     }
 
+    /// 推送原始字节，不做编码
+    pub fn send_bytes(&mut self, bs: Vec<u8>) {
+        if let Some(RuntimeOutput::ToServer(s)) = self.0.last_mut() {
+            s.extend(bs);
+            return;
+        }
+        self.0.push(RuntimeOutput::ToServer(bs));
+    }
+
     pub fn drain_all(&mut self) -> Vec<RuntimeOutput> {
         self.0.drain(..).collect()
     }
@@ -104,4 +137,15 @@ impl ActionQueue {
     pub fn drain_all(&self) -> Vec<EngineAction> {
         self.0.lock().unwrap().drain(..).collect()
     }
+
+    /// 对从start开始新加入的操作进行转换
+    pub fn map_since<F>(&self, start: usize, mut f: F)
+    where
+        F: FnMut(EngineAction) -> EngineAction,
+    {
+        let mut q = self.0.lock().unwrap();
+        let start = start.min(q.len());
+        let tail: Vec<EngineAction> = q.drain(start..).collect();
+        q.extend(tail.into_iter().map(&mut f));
+    }
 }
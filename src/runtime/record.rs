@@ -0,0 +1,156 @@
+use crate::error::{Error, Result};
+use std::time::Instant;
+
+/// 宏中的一步：距上一条命令的间隔（秒）及命令
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub delay: f64,
+    pub cmd: String,
+}
+
+/// 录制的命令宏
+///
+/// 以Lua脚本形式保存，文件返回一个由{delay=..., cmd=...}组成的数组，
+/// 可直接手动编辑
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Macro {
+    /// 生成Lua脚本
+    pub fn to_lua(&self) -> String {
+        let mut s = format!("-- 宏：{}\nreturn {{\n", self.name);
+        for step in &self.steps {
+            s.push_str(&format!(
+                "    {{delay={}, cmd={}}},\n",
+                step.delay,
+                lua_quote(&step.cmd)
+            ));
+        }
+        s.push_str("}\n");
+        s
+    }
+
+    /// 解析Lua脚本
+    pub fn from_lua(lua: &mlua::Lua, name: &str, script: &str) -> Result<Self> {
+        let table: mlua::Table = lua.load(script).eval()?;
+        let mut steps = Vec::new();
+        for entry in table.sequence_values::<mlua::Table>() {
+            let entry = entry?;
+            let cmd: String = entry.get("cmd")?;
+            let delay: Option<f64> = entry.get("delay")?;
+            let delay = delay.unwrap_or(0.0);
+            if delay < 0.0 {
+                return Err(Error::RuntimeError(format!(
+                    "宏{}中的延迟不能为负数：{}",
+                    name, delay
+                )));
+            }
+            steps.push(Step { delay, cmd });
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            steps,
+        })
+    }
+}
+
+/// 命令录制器，记录用户输入的命令及时间间隔
+#[derive(Debug)]
+pub struct Recorder {
+    name: String,
+    last: Instant,
+    steps: Vec<Step>,
+}
+
+impl Recorder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            last: Instant::now(),
+            steps: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn record(&mut self, cmd: impl Into<String>) {
+        let now = Instant::now();
+        let delay = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        // 第一条命令立即执行，间隔保留两位小数
+        let delay = if self.steps.is_empty() {
+            0.0
+        } else {
+            (delay * 100.0).round() / 100.0
+        };
+        self.steps.push(Step {
+            delay,
+            cmd: cmd.into(),
+        });
+    }
+
+    pub fn finish(self) -> Macro {
+        Macro {
+            name: self.name,
+            steps: self.steps,
+        }
+    }
+}
+
+fn lua_quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_lua_roundtrip() {
+        let m = Macro {
+            name: "walk".to_owned(),
+            steps: vec![
+                Step {
+                    delay: 0.0,
+                    cmd: "north".to_owned(),
+                },
+                Step {
+                    delay: 1.5,
+                    cmd: "say \"你好\"".to_owned(),
+                },
+            ],
+        };
+        let script = m.to_lua();
+        let lua = mlua::Lua::new();
+        let parsed = Macro::from_lua(&lua, "walk", &script).unwrap();
+        assert_eq!(m, parsed);
+    }
+
+    #[test]
+    fn test_recorder_first_step_immediate() {
+        let mut rec = Recorder::new("r");
+        rec.record("n");
+        rec.record("e");
+        let m = rec.finish();
+        assert_eq!(2, m.steps.len());
+        assert_eq!(0.0, m.steps[0].delay);
+        assert_eq!("e", m.steps[1].cmd);
+    }
+}
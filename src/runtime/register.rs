@@ -0,0 +1,123 @@
+use crate::conf;
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+/// 默认寄存器名称
+pub const UNNAMED: &str = "\"";
+/// 系统剪贴板寄存器名称，配置了剪贴板命令时与系统剪贴板同步
+pub const SYSTEM: &str = "+";
+
+/// 命名寄存器，类似vim寄存器，供脚本暂存文本片段
+#[derive(Debug, Clone)]
+pub struct Registers(Arc<RwLock<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    regs: BTreeMap<String, String>,
+    copy_cmd: String,
+    paste_cmd: String,
+}
+
+impl Registers {
+    pub fn new(config: &conf::Runtime) -> Self {
+        Self(Arc::new(RwLock::new(Inner {
+            regs: BTreeMap::new(),
+            copy_cmd: config.clipboard_copy_cmd.to_owned(),
+            paste_cmd: config.clipboard_paste_cmd.to_owned(),
+        })))
+    }
+
+    /// 设置寄存器，写入系统剪贴板寄存器时同时调用复制命令
+    pub fn set(&self, name: &str, text: impl Into<String>) -> Result<()> {
+        let text = text.into();
+        let copy_cmd = {
+            let mut inner = self.0.write().unwrap();
+            inner.regs.insert(name.to_owned(), text.clone());
+            inner.copy_cmd.clone()
+        };
+        if name == SYSTEM && !copy_cmd.is_empty() {
+            run_copy(&copy_cmd, &text)?;
+        }
+        Ok(())
+    }
+
+    /// 读取寄存器，读取系统剪贴板寄存器时优先调用粘贴命令
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        let (text, paste_cmd) = {
+            let inner = self.0.read().unwrap();
+            (inner.regs.get(name).cloned(), inner.paste_cmd.clone())
+        };
+        if name == SYSTEM && !paste_cmd.is_empty() {
+            return run_paste(&paste_cmd).map(Some);
+        }
+        Ok(text)
+    }
+
+    /// 所有寄存器，按名称排序
+    pub fn list(&self) -> Vec<(String, String)> {
+        let inner = self.0.read().unwrap();
+        inner
+            .regs
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+}
+
+fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+fn run_copy(cmd: &str, text: &str) -> Result<()> {
+    let mut child = shell(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::RuntimeError(format!("clipboard command {} failed", cmd)));
+    }
+    Ok(())
+}
+
+fn run_paste(cmd: &str) -> Result<String> {
+    let output = shell(cmd).stderr(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::RuntimeError(format!("clipboard command {} failed", cmd)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_registers_system_bridge() {
+        let tmp = TempDir::new("clip");
+        let path = tmp.join("clip");
+        let config = conf::Runtime {
+            clipboard_copy_cmd: format!("cat > {}", path.display()),
+            clipboard_paste_cmd: format!("cat {}", path.display()),
+            ..conf::Runtime::default()
+        };
+        let regs = Registers::new(&config);
+        regs.set("a", "张三").unwrap();
+        assert_eq!(Some("张三".to_owned()), regs.get("a").unwrap());
+        assert_eq!(None, regs.get(UNNAMED).unwrap());
+        regs.set(SYSTEM, "hello").unwrap();
+        assert_eq!("hello", std::fs::read_to_string(&path).unwrap());
+        assert_eq!(Some("hello".to_owned()), regs.get(SYSTEM).unwrap());
+        assert_eq!(2, regs.list().len());
+    }
+}
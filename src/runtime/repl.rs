@@ -0,0 +1,152 @@
+use crate::runtime::json;
+use mlua::{Lua, MultiValue, Value};
+
+// 表嵌套超过该深度时不再展开
+const MAX_DEPTH: usize = 4;
+
+/// 交互式求值，与脚本共享全局环境
+///
+/// 输入先尝试作为表达式求值，失败时作为语句执行，
+/// 代码不完整（如未闭合的function）时暂存，与后续输入合并后再求值
+#[derive(Debug, Default)]
+pub struct Repl {
+    pending: String,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 是否有尚未完整的多行代码
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// 输入一行代码，代码不完整时返回None，否则返回各返回值的格式化文本或错误信息
+    pub fn eval(&mut self, lua: &Lua, input: &str) -> Option<std::result::Result<Vec<String>, String>> {
+        let chunk = format!("{}{}", self.pending, input);
+        if chunk.trim().is_empty() {
+            self.pending.clear();
+            return Some(Ok(vec![]));
+        }
+        let expr = format!("return {}", chunk);
+        let func = match lua.load(&expr).set_name("=repl").and_then(|c| c.into_function()) {
+            Ok(func) => func,
+            Err(_) => match lua.load(&chunk).set_name("=repl").and_then(|c| c.into_function()) {
+                Ok(func) => func,
+                Err(mlua::Error::SyntaxError {
+                    incomplete_input: true,
+                    ..
+                }) => {
+                    self.pending = chunk + "\n";
+                    return None;
+                }
+                Err(e) => {
+                    self.pending.clear();
+                    return Some(Err(e.to_string()));
+                }
+            },
+        };
+        self.pending.clear();
+        let res = func
+            .call::<_, MultiValue>(())
+            .map(|values| values.iter().map(|v| format_value(v, 0)).collect())
+            .map_err(|e| e.to_string());
+        Some(res)
+    }
+
+    /// 放弃尚未完整的代码
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// 格式化Lua值，表按键排序并逐层缩进
+pub fn format_value(value: &Value, depth: usize) -> String {
+    match value {
+        Value::Nil => "nil".to_owned(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Number(n) => json::format_number(*n),
+        Value::String(s) => format!("{:?}", String::from_utf8_lossy(s.as_bytes())),
+        Value::Table(table) => {
+            let mut pairs: Vec<(String, String)> = table
+                .clone()
+                .pairs::<Value, Value>()
+                .filter_map(|pair| pair.ok())
+                .map(|(k, v)| {
+                    let key = match &k {
+                        Value::String(s) => String::from_utf8_lossy(s.as_bytes()).into_owned(),
+                        other => format!("[{}]", format_value(other, MAX_DEPTH)),
+                    };
+                    let value = if depth + 1 >= MAX_DEPTH {
+                        match v {
+                            Value::Table(_) => "{...}".to_owned(),
+                            other => format_value(&other, depth + 1),
+                        }
+                    } else {
+                        format_value(&v, depth + 1)
+                    };
+                    (key, value)
+                })
+                .collect();
+            if pairs.is_empty() {
+                return "{}".to_owned();
+            }
+            // 数组下标按数值排序，其余按键名排序
+            pairs.sort_by(|(a, _), (b, _)| match (index_of(a), index_of(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => a.cmp(b),
+            });
+            let indent = "  ".repeat(depth + 1);
+            let mut s = String::from("{\n");
+            for (key, value) in pairs {
+                s.push_str(&format!("{}{} = {},\n", indent, key, value));
+            }
+            s.push_str(&"  ".repeat(depth));
+            s.push('}');
+            s
+        }
+        Value::Function(_) => "<function>".to_owned(),
+        Value::Thread(_) => "<thread>".to_owned(),
+        Value::UserData(_) | Value::LightUserData(_) => "<userdata>".to_owned(),
+        Value::Error(e) => format!("<error {}>", e),
+    }
+}
+
+// 整数键格式化为[n]，解析其数值用于排序
+fn index_of(key: &str) -> Option<i64> {
+    key.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_eval() {
+        let lua = Lua::new();
+        let mut repl = Repl::new();
+        assert_eq!(Some(Ok(vec!["3".to_owned()])), repl.eval(&lua, "1 + 2"));
+        assert_eq!(Some(Ok(vec![])), repl.eval(&lua, "x = 10"));
+        assert_eq!(Some(Ok(vec!["10".to_owned(), "\"a\"".to_owned()])), repl.eval(&lua, "x, 'a'"));
+        // 多行代码在完整后执行
+        assert_eq!(None, repl.eval(&lua, "function f(n)"));
+        assert!(repl.is_pending());
+        assert_eq!(None, repl.eval(&lua, "  return n * 2"));
+        assert_eq!(Some(Ok(vec![])), repl.eval(&lua, "end"));
+        assert_eq!(Some(Ok(vec!["8".to_owned()])), repl.eval(&lua, "f(4)"));
+        assert!(repl.eval(&lua, "error('boom')").unwrap().is_err());
+        assert!(repl.eval(&lua, "1 +* 2").unwrap().is_err());
+        assert!(!repl.is_pending());
+
+        let table = repl.eval(&lua, "{10, 20, name = 'rat', hp = {cur = 5}}").unwrap().unwrap();
+        assert_eq!(
+            "{\n  [1] = 10,\n  [2] = 20,\n  hp = {\n    cur = 5,\n  },\n  name = \"rat\",\n}",
+            table[0]
+        );
+    }
+}
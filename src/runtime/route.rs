@@ -0,0 +1,176 @@
+use crate::conf;
+use crate::datadir::DataDir;
+use crate::error::{Error, Result};
+use crate::runtime::classify::Classifier;
+use regex::RegexSet;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+/// 行路由规则的处理方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    // 输出到指定窗口
+    Window(String),
+    // 写入指定日志，不在界面显示
+    Log(String),
+    // 直接丢弃
+    Gag,
+}
+
+impl From<&conf::Route> for Route {
+    fn from(src: &conf::Route) -> Self {
+        match src.action {
+            conf::RouteAction::Window => Route::Window(src.target.to_owned()),
+            conf::RouteAction::Log => Route::Log(src.target.to_owned()),
+            conf::RouteAction::Gag => Route::Gag,
+        }
+    }
+}
+
+/// 行路由器
+///
+/// 所有规则编译为一个RegexSet，按配置顺序取第一个匹配的规则，
+/// 在触发器之前执行，被路由的行不再参与触发器匹配。
+/// 按行分类匹配的规则使用分类中各正则的合并
+#[derive(Debug)]
+pub struct Router {
+    set: RegexSet,
+    routes: Vec<Route>,
+    logs: HashMap<String, File>,
+    data_dir: DataDir,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            set: RegexSet::empty(),
+            routes: vec![],
+            logs: HashMap::new(),
+            data_dir: DataDir::default(),
+        }
+    }
+}
+
+/// 路由规则的匹配模式，使用class时为该分类的模式
+pub fn rule_pattern(rule: &conf::Route, classifier: &Classifier) -> Result<String> {
+    match (rule.pattern.is_empty(), rule.class.is_empty()) {
+        (false, true) => Ok(rule.pattern.to_owned()),
+        (true, false) => classifier.pattern(&rule.class),
+        _ => Err(Error::RuntimeError(format!(
+            "route requires exactly one of pattern and class: {:?}",
+            rule
+        ))),
+    }
+}
+
+impl Router {
+    pub fn new(rules: &[conf::Route], classifier: &Classifier) -> Result<Self> {
+        let patterns = rules
+            .iter()
+            .map(|rule| rule_pattern(rule, classifier))
+            .collect::<Result<Vec<_>>>()?;
+        let set = RegexSet::new(&patterns)?;
+        let routes = rules.iter().map(Route::from).collect();
+        Ok(Self {
+            set,
+            routes,
+            logs: HashMap::new(),
+            data_dir: DataDir::default(),
+        })
+    }
+
+    /// 日志文件写入数据目录
+    pub fn with_data_dir(mut self, data_dir: DataDir) -> Self {
+        self.data_dir = data_dir;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// 匹配第一条规则
+    pub fn route(&self, text: &str) -> Option<&Route> {
+        if self.routes.is_empty() {
+            return None;
+        }
+        self.set
+            .matches(text)
+            .into_iter()
+            .next()
+            .map(|idx| &self.routes[idx])
+    }
+
+    /// 将文本追加写入指定日志，日志文件在首次写入时创建
+    pub fn write_log(&mut self, target: &str, text: &str) -> Result<()> {
+        if !self.logs.contains_key(target) {
+            let f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.data_dir.log_path(format!("{}.log", target)))?;
+            self.logs.insert(target.to_owned(), f);
+        }
+        let f = self.logs.get_mut(target).unwrap();
+        f.write_all(text.as_bytes())?;
+        f.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_router_first_match() {
+        let rules = vec![
+            rule("^【闲聊】", conf::RouteAction::Window, "chat"),
+            rule("^你.*攻击", conf::RouteAction::Log, "combat"),
+            rule("闲聊", conf::RouteAction::Gag, ""),
+        ];
+        let router = Router::new(&rules, &Classifier::default()).unwrap();
+        assert_eq!(
+            Some(&Route::Window("chat".to_owned())),
+            router.route("【闲聊】张三：你好")
+        );
+        assert_eq!(
+            Some(&Route::Log("combat".to_owned())),
+            router.route("你对着李四发起攻击")
+        );
+        assert_eq!(Some(&Route::Gag), router.route("不要闲聊"));
+        assert_eq!(None, router.route("张三走了过来。"));
+    }
+
+    #[test]
+    fn test_router_invalid_pattern() {
+        let rules = vec![rule("(", conf::RouteAction::Gag, "")];
+        assert!(Router::new(&rules, &Classifier::default()).is_err());
+    }
+
+    #[test]
+    fn test_router_class() {
+        let mut classes = std::collections::BTreeMap::new();
+        classes.insert("chat".to_owned(), vec!["^【闲聊】".to_owned(), "告诉你".to_owned()]);
+        let classifier = Classifier::new(&classes).unwrap();
+        let mut chat = rule("", conf::RouteAction::Window, "chat");
+        chat.class = "chat".to_owned();
+        let router = Router::new(&[chat.clone()], &classifier).unwrap();
+        assert_eq!(
+            Some(&Route::Window("chat".to_owned())),
+            router.route("张三告诉你：你好")
+        );
+        assert_eq!(None, router.route("张三走了过来。"));
+        chat.class = "combat".to_owned();
+        assert!(Router::new(&[chat], &classifier).is_err());
+    }
+
+    fn rule(pattern: &str, action: conf::RouteAction, target: &str) -> conf::Route {
+        conf::Route {
+            pattern: pattern.to_owned(),
+            class: String::new(),
+            action,
+            target: target.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::ui::line::{CompactStats, Line};
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 已输出到界面的历史行，按绝对行号寻址
+///
+/// 行号从1开始，与界面行号栏一致，未结束的行会与后续文本合并为同一行。
+/// 每行记录提交（结束）时间，可按时间区间查询
+#[derive(Debug, Clone)]
+pub struct Scrollback(Arc<RwLock<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    lines: VecDeque<Line>,
+    // 各行的提交时间，自UNIX纪元起的毫秒数，单调不减
+    times: VecDeque<u64>,
+    // 下一行的行号
+    next_lineno: usize,
+    capacity: usize,
+    stats: CompactStats,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(RwLock::new(Inner {
+            lines: VecDeque::new(),
+            times: VecDeque::new(),
+            next_lineno: 1,
+            capacity,
+            stats: CompactStats::default(),
+        })))
+    }
+
+    /// 追加行，返回其行号
+    ///
+    /// 行结束时对其进行压缩，合并相同样式的相邻片段
+    pub fn push_line(&self, line: Line) -> usize {
+        self.push_line_at(line, now_millis())
+    }
+
+    fn push_line_at(&self, line: Line, time: u64) -> usize {
+        let mut inner = self.0.write().unwrap();
+        let inner = &mut *inner;
+        if let Some(last_line) = inner.lines.back_mut() {
+            if !last_line.ended() {
+                last_line.push_line(line);
+                if last_line.ended() {
+                    inner.stats += last_line.compact();
+                }
+                // 以行结束的时间为准
+                if let Some(last_time) = inner.times.back_mut() {
+                    *last_time = time;
+                }
+                return inner.next_lineno - 1;
+            }
+        }
+        let mut line = line;
+        if line.ended() {
+            inner.stats += line.compact();
+        }
+        inner.lines.push_back(line);
+        inner.times.push_back(time);
+        inner.next_lineno += 1;
+        while inner.lines.len() > inner.capacity {
+            inner.lines.pop_front();
+            inner.times.pop_front();
+        }
+        inner.next_lineno - 1
+    }
+
+    /// 累计的压缩统计
+    pub fn compact_stats(&self) -> CompactStats {
+        self.0.read().unwrap().stats
+    }
+
+    /// 最新一行的行号，无任何行时返回0
+    pub fn last_lineno(&self) -> usize {
+        self.0.read().unwrap().next_lineno - 1
+    }
+
+    /// 下一次追加的行号，最新一行未结束时与其相同
+    pub fn next_lineno(&self) -> usize {
+        let inner = self.0.read().unwrap();
+        match inner.lines.back() {
+            Some(line) if !line.ended() => inner.next_lineno - 1,
+            _ => inner.next_lineno,
+        }
+    }
+
+    pub fn get(&self, lineno: usize) -> Option<Line> {
+        self.range(lineno, lineno).pop()
+    }
+
+    /// 获取时间区间[start, end]内提交的行及其行号与时间，时间为毫秒数
+    pub fn between(&self, start: u64, end: u64) -> Vec<(usize, u64, Line)> {
+        let inner = self.0.read().unwrap();
+        let first_lineno = inner.next_lineno - inner.lines.len();
+        // 时间单调不减，二分查找起始位置
+        let from = inner.times.partition_point(|t| *t < start);
+        inner
+            .times
+            .iter()
+            .zip(inner.lines.iter())
+            .enumerate()
+            .skip(from)
+            .take_while(|(_, (t, _))| **t <= end)
+            .map(|(i, (t, line))| (first_lineno + i, *t, line.clone()))
+            .collect()
+    }
+
+    /// 获取行号区间[start, end]内仍保留的行
+    pub fn range(&self, start: usize, end: usize) -> Vec<Line> {
+        let inner = self.0.read().unwrap();
+        let first_lineno = inner.next_lineno - inner.lines.len();
+        let start = start.max(first_lineno);
+        let end = end.min(inner.next_lineno - 1);
+        if start > end {
+            return vec![];
+        }
+        inner
+            .lines
+            .range(start - first_lineno..=end - first_lineno)
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Label;
+    use crate::ui::span::Span;
+    use crate::ui::style::{Color, Style};
+
+    #[test]
+    fn test_scrollback_lineno() {
+        let sb = Scrollback::new(3);
+        assert_eq!(0, sb.last_lineno());
+        assert_eq!(1, sb.push_line(Line::fmt_raw("a")));
+        assert_eq!(2, sb.push_line(Line::new(vec![])));
+        // 未结束的行被合并
+        assert_eq!(2, sb.push_line(Line::fmt_raw("b")));
+        assert_eq!(3, sb.push_line(Line::fmt_raw("c")));
+        assert_eq!(4, sb.push_line(Line::fmt_raw("d")));
+        assert_eq!("b", sb.get(2).unwrap().plain_text());
+        // 超出容量的行被丢弃
+        assert!(sb.get(1).is_none());
+        assert!(sb.get(5).is_none());
+        let texts: Vec<String> = sb.range(0, 10).iter().map(|l| l.plain_text()).collect();
+        assert_eq!(vec!["b", "c", "d"], texts);
+        assert!(sb.range(4, 3).is_empty());
+    }
+
+    #[test]
+    fn test_scrollback_between() {
+        let sb = Scrollback::new(3);
+        sb.push_line_at(Line::fmt_raw("a"), 1000);
+        sb.push_line_at(Line::new(vec![Span::new("b", Style::default(), Label::None)]), 2000);
+        sb.push_line_at(Line::fmt_raw("c"), 3000);
+        sb.push_line_at(Line::fmt_raw("d"), 4000);
+        sb.push_line_at(Line::fmt_raw("e"), 5000);
+        // 未结束的行以结束时间为准
+        let lines: Vec<(usize, u64, String)> = sb
+            .between(2500, 4000)
+            .into_iter()
+            .map(|(n, t, l)| (n, t, l.plain_text()))
+            .collect();
+        assert_eq!(vec![(2, 3000, "bc".to_owned()), (3, 4000, "d".to_owned())], lines);
+        // 超出容量的行被丢弃
+        assert!(sb.between(0, 1000).is_empty());
+        assert_eq!(3, sb.between(0, u64::MAX).len());
+    }
+
+    #[test]
+    fn test_scrollback_compact() {
+        let sb = Scrollback::new(10);
+        let red = Style::default().fg(Color::Red);
+        sb.push_line(Line::new(vec![
+            Span::new("a", red, Label::None),
+            Span::new("b", red, Label::None),
+        ]));
+        assert_eq!(CompactStats::default(), sb.compact_stats());
+        sb.push_line(Line::single(Span::fmt_with_style("c", red)));
+        let stats = sb.compact_stats();
+        assert_eq!(1, stats.lines);
+        assert_eq!(2, stats.spans_merged);
+        assert_eq!(1, sb.get(1).unwrap().spans().len());
+        assert_eq!("abc", sb.get(1).unwrap().plain_text());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::conf::{self, EncodeFallback, NewlineMode, ThemeStyle};
+use crate::error::{Error, Result};
+use crate::ui::style::Color;
+use crate::ui::theme::Role;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+/// 可在运行时通过#set调整的设置项，键与配置文件中section段下的路径一致
+pub struct Setting {
+    // 配置文件中的段，runtime段由运行时使用，term段由界面使用
+    pub section: &'static str,
+    pub key: &'static str,
+    get: fn(&conf::Config) -> Value,
+    // 解析并校验文本，非法时返回None
+    set: fn(&mut conf::Config, &str) -> Option<()>,
+}
+
+impl Setting {
+    pub fn get(&self, config: &conf::Config) -> Value {
+        (self.get)(config)
+    }
+
+    pub fn set(&self, config: &mut conf::Config, value: &str) -> Result<()> {
+        (self.set)(config, value.trim()).ok_or_else(|| {
+            Error::RuntimeError(format!("invalid value {} for setting {}", value, self.key))
+        })
+    }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "true" | "on" | "1" => Some(true),
+        "false" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// 主题写作“角色:前景色/背景色”，多个角色以逗号分隔，如“flow:white/black,gutter:darkgray”，
+// 颜色可省略，为空时恢复默认主题
+fn parse_theme(s: &str) -> Option<HashMap<String, ThemeStyle>> {
+    let mut theme = HashMap::new();
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (role, colors) = match item.find(':') {
+            Some(idx) => (&item[..idx], &item[idx + 1..]),
+            None => (item, ""),
+        };
+        Role::parse(role)?;
+        let (fg, bg) = match colors.find('/') {
+            Some(idx) => (&colors[..idx], &colors[idx + 1..]),
+            None => (colors, ""),
+        };
+        for color in [fg, bg].iter().filter(|c| !c.is_empty()) {
+            Color::from_str(color)?;
+        }
+        let style = ThemeStyle {
+            fg: fg.to_owned(),
+            bg: bg.to_owned(),
+        };
+        theme.insert(role.to_owned(), style);
+    }
+    Some(theme)
+}
+
+fn theme_text(theme: &HashMap<String, ThemeStyle>) -> String {
+    let mut items: Vec<String> = theme
+        .iter()
+        .map(|(role, ts)| {
+            if ts.bg.is_empty() {
+                format!("{}:{}", role, ts.fg)
+            } else {
+                format!("{}:{}/{}", role, ts.fg, ts.bg)
+            }
+        })
+        .collect();
+    items.sort();
+    items.join(",")
+}
+
+fn fallback_name(fallback: EncodeFallback) -> &'static str {
+    match fallback {
+        EncodeFallback::Replace => "replace",
+        EncodeFallback::Translit => "translit",
+        EncodeFallback::Reject => "reject",
+    }
+}
+
+fn newline_name(mode: NewlineMode) -> &'static str {
+    match mode {
+        NewlineMode::Keep => "keep",
+        NewlineMode::Strip => "strip",
+        NewlineMode::Break => "break",
+        NewlineMode::Overwrite => "overwrite",
+    }
+}
+
+/// 所有设置项，按键排序
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        section: "runtime",
+        key: "cmd_delim",
+        get: |c| Value::String(c.runtime.cmd_delim.to_string()),
+        set: |c, s| {
+            c.runtime.cmd_delim = parse_char(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "dup_guard.enabled",
+        get: |c| Value::Boolean(c.runtime.dup_guard.enabled),
+        set: |c, s| {
+            c.runtime.dup_guard.enabled = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "dup_guard.interval_ms",
+        get: |c| Value::Integer(c.runtime.dup_guard.interval_ms as i64),
+        set: |c, s| {
+            c.runtime.dup_guard.interval_ms = s.parse().ok()?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "echo_cmd",
+        get: |c| Value::Boolean(c.runtime.echo_cmd),
+        set: |c, s| {
+            c.runtime.echo_cmd = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "echo_color",
+        get: |c| Value::String(c.runtime.echo_color.to_owned()),
+        set: |c, s| {
+            Color::from_str(s)?;
+            c.runtime.echo_color = s.to_owned();
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "echo_log",
+        get: |c| Value::Boolean(c.runtime.echo_log),
+        set: |c, s| {
+            c.runtime.echo_log = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "echo_merge",
+        get: |c| Value::Boolean(c.runtime.echo_merge),
+        set: |c, s| {
+            c.runtime.echo_merge = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "echo_prefix",
+        get: |c| Value::String(c.runtime.echo_prefix.to_owned()),
+        set: |c, s| {
+            c.runtime.echo_prefix = s.to_owned();
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "encode_fallback",
+        get: |c| Value::String(fallback_name(c.runtime.encode_fallback).to_owned()),
+        set: |c, s| {
+            c.runtime.encode_fallback = match s {
+                "replace" => EncodeFallback::Replace,
+                "translit" => EncodeFallback::Translit,
+                "reject" => EncodeFallback::Reject,
+                _ => return None,
+            };
+            Some(())
+        },
+    },
+    Setting {
+        section: "term",
+        key: "follow",
+        get: |c| Value::Boolean(c.term.follow),
+        set: |c, s| {
+            c.term.follow = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "max_alias_depth",
+        get: |c| Value::Integer(c.runtime.max_alias_depth as i64),
+        set: |c, s| {
+            c.runtime.max_alias_depth = s.parse().ok().filter(|n| *n > 0)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "newline",
+        get: |c| Value::String(newline_name(c.runtime.newline).to_owned()),
+        set: |c, s| {
+            c.runtime.newline = match s {
+                "keep" => NewlineMode::Keep,
+                "strip" => NewlineMode::Strip,
+                "break" => NewlineMode::Break,
+                "overwrite" => NewlineMode::Overwrite,
+                _ => return None,
+            };
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "queue_tag.max_depth",
+        get: |c| Value::Integer(c.runtime.queue_tag.max_depth as i64),
+        set: |c, s| {
+            c.runtime.queue_tag.max_depth = s.parse().ok().filter(|n| *n > 0)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "repeat_last_on_empty",
+        get: |c| Value::Boolean(c.runtime.repeat_last_on_empty),
+        set: |c, s| {
+            c.runtime.repeat_last_on_empty = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "send_empty_cmd",
+        get: |c| Value::Boolean(c.runtime.send_empty_cmd),
+        set: |c, s| {
+            c.runtime.send_empty_cmd = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "status_parser",
+        get: |c| Value::Boolean(c.runtime.status_parser),
+        set: |c, s| {
+            c.runtime.status_parser = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "term",
+        key: "theme",
+        get: |c| Value::String(theme_text(&c.term.theme)),
+        set: |c, s| {
+            c.term.theme = parse_theme(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "term",
+        key: "timestamps",
+        get: |c| Value::Boolean(c.term.timestamps),
+        set: |c, s| {
+            c.term.timestamps = parse_bool(s)?;
+            Some(())
+        },
+    },
+    Setting {
+        section: "runtime",
+        key: "wait_token",
+        get: |c| Value::String(c.runtime.wait_token.to_owned()),
+        set: |c, s| {
+            c.runtime.wait_token = s.to_owned();
+            Some(())
+        },
+    },
+    Setting {
+        section: "term",
+        key: "wrap",
+        get: |c| Value::Boolean(c.term.wrap),
+        set: |c, s| {
+            c.term.wrap = parse_bool(s)?;
+            Some(())
+        },
+    },
+];
+
+pub fn find(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|s| s.key == key)
+}
+
+/// 补全#set及#get命令中的键，返回需追加到命令行的文本
+///
+/// 唯一匹配时补全整个键并追加空格，多个匹配时补全至公共前缀
+pub fn complete(line: &str) -> Option<String> {
+    let prefix = line
+        .strip_prefix("#set ")
+        .or_else(|| line.strip_prefix("#get "))?;
+    if prefix.contains(' ') {
+        return None;
+    }
+    let keys: Vec<&str> = SETTINGS
+        .iter()
+        .map(|s| s.key)
+        .filter(|k| k.starts_with(prefix))
+        .collect();
+    let first = keys.first()?;
+    if keys.len() == 1 {
+        return Some(format!("{} ", &first[prefix.len()..]));
+    }
+    let common = keys.iter().fold(first.len(), |n, k| {
+        first
+            .bytes()
+            .zip(k.bytes())
+            .take(n)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    Some(first[prefix.len()..common].to_owned())
+}
+
+/// 将设置写回配置文件的对应段，其余内容保持不变，但文件中的注释不会保留
+///
+/// 写入的值与配置文件的格式一致，如主题保存为表
+pub fn save(path: &Path, config: &conf::Config) -> Result<()> {
+    let mut doc: Value = match fs::read_to_string(path) {
+        Ok(s) => toml::from_str(&s)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Value::Table(Default::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let current = Value::try_from(config)?;
+    for setting in SETTINGS {
+        let value = std::iter::once(setting.section)
+            .chain(setting.key.split('.'))
+            .try_fold(&current, |value, part| value.get(part))
+            .ok_or_else(|| Error::RuntimeError(format!("setting {} not serialized", setting.key)))?;
+        let mut table = &mut doc;
+        let mut parts: Vec<&str> = setting.key.split('.').collect();
+        let last = parts.pop().unwrap();
+        for part in std::iter::once(setting.section).chain(parts) {
+            table = table
+                .as_table_mut()
+                .ok_or_else(|| Error::RuntimeError(format!("{} is not a table", part)))?
+                .entry(part)
+                .or_insert_with(|| Value::Table(Default::default()));
+        }
+        table
+            .as_table_mut()
+            .ok_or_else(|| Error::RuntimeError(format!("{} is not a table", setting.key)))?
+            .insert(last.to_owned(), value.clone());
+    }
+    fs::write(path, toml::to_string(&doc)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_settings_set_complete_save() {
+        let mut config = conf::Config::default();
+        find("echo_cmd").unwrap().set(&mut config, "on").unwrap();
+        assert!(config.runtime.echo_cmd);
+        assert!(find("echo_color").unwrap().set(&mut config, "pink").is_err());
+        assert!(find("cmd_delim").unwrap().set(&mut config, ";;").is_err());
+        find("dup_guard.interval_ms").unwrap().set(&mut config, "800").unwrap();
+        assert_eq!(Value::Integer(800), find("dup_guard.interval_ms").unwrap().get(&config));
+        // 键按字母顺序排列
+        assert!(SETTINGS.windows(2).all(|w| w[0].key < w[1].key));
+
+        // 界面设置
+        find("wrap").unwrap().set(&mut config, "off").unwrap();
+        assert!(!config.term.wrap);
+        find("timestamps").unwrap().set(&mut config, "on").unwrap();
+        find("follow").unwrap().set(&mut config, "true").unwrap();
+        assert!(find("follow").unwrap().set(&mut config, "yes").is_err());
+        find("theme").unwrap().set(&mut config, "gutter:darkgray, flow:white/black").unwrap();
+        assert_eq!(
+            Value::String("flow:white/black,gutter:darkgray".to_owned()),
+            find("theme").unwrap().get(&config)
+        );
+        assert!(find("theme").unwrap().set(&mut config, "title:red").is_err());
+        assert!(find("theme").unwrap().set(&mut config, "flow:pink").is_err());
+        assert_eq!(2, config.term.theme.len());
+
+        assert_eq!(Some("ho_".to_owned()), complete("#set ec"));
+        assert_eq!(Some("lor ".to_owned()), complete("#get echo_co"));
+        assert_eq!(Some("".to_owned()), complete("#set echo_"));
+        assert_eq!(None, complete("#set echo_cmd "));
+        assert_eq!(None, complete("#set x"));
+
+        let tmp = TempDir::new("settings");
+        let path = tmp.join("mudterm.toml");
+        fs::write(&path, "mode = \"standalone\"\n[runtime]\nmap_db = \"map.db\"\n").unwrap();
+        save(&path, &config).unwrap();
+        let saved: conf::Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(saved.runtime.echo_cmd);
+        assert_eq!(800, saved.runtime.dup_guard.interval_ms);
+        assert!(!saved.term.wrap && saved.term.timestamps && saved.term.follow);
+        assert_eq!("black", saved.term.theme["flow"].bg);
+        assert_eq!("map.db", saved.runtime.map_db);
+    }
+}
@@ -0,0 +1,341 @@
+use lazy_static::lazy_static;
+use mlua::ToLua;
+use regex::Regex;
+
+// 发送命令后，等待状态界面开始的最大行数
+const MAX_WAIT_LINES: usize = 20;
+// 状态界面的最大行数
+const MAX_BODY_LINES: usize = 200;
+
+// 常用字段的变量名
+const FIELD_KEYS: &[(&str, &str)] = &[
+    ("精气", "jing"),
+    ("气血", "qi"),
+    ("精力", "jingli"),
+    ("内力", "neili"),
+    ("真气", "zhenqi"),
+    ("食物", "food"),
+    ("饮水", "water"),
+    ("潜能", "pot"),
+    ("经验", "exp"),
+    ("状态", "state"),
+    ("膂力", "str"),
+    ("悟性", "int"),
+    ("根骨", "con"),
+    ("身法", "dex"),
+];
+
+lazy_static! {
+    // hp中的【精气】 1000 / 1000 [100%]
+    static ref BRACKET_FIELD: Regex = Regex::new(r"【([^】]+)】\s*([^│【]*)").unwrap();
+    // score中的膂力：[ 20]
+    static ref COLON_FIELD: Regex =
+        Regex::new(r"([^\s│：:【】\[\]]+)\s*[：:]\s*(\[\s*-?\d+\s*\]|[^\s│]+)").unwrap();
+    static ref RATIO: Regex = Regex::new(r"^(-?\d+)\s*/\s*(-?\d+)").unwrap();
+    static ref NUMBER: Regex = Regex::new(r"^\[?\s*(-?\d+)\s*\]?$").unwrap();
+    // skills中的□ 凌波微步 (lingboweibu) - 粗通皮毛  60/    0
+    static ref SKILL: Regex =
+        Regex::new(r"(□)?\s*(\S+)\s*\(([\w-]+)\)\s*-\s*(\S+)\s+(\d+)\s*/\s*(\d+)").unwrap();
+}
+
+/// 支持解析的状态界面
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusKind {
+    Hp,
+    Score,
+    Skills,
+}
+
+impl StatusKind {
+    /// 根据发送的命令识别状态界面
+    pub fn from_cmd(cmd: &str) -> Option<Self> {
+        match cmd.split_whitespace().next()? {
+            "hp" => Some(Self::Hp),
+            "score" | "sc" => Some(Self::Score),
+            "skills" | "cha" => Some(Self::Skills),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hp => "hp",
+            Self::Score => "score",
+            Self::Skills => "skills",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Number(i64),
+    // 当前值与最大值
+    Ratio(i64, i64),
+    Text(String),
+}
+
+impl Field {
+    fn parse(value: &str) -> Self {
+        let value = value.trim();
+        // 超出i64范围的数字按文本保留
+        if let Some(caps) = RATIO.captures(value) {
+            if let (Ok(cur), Ok(max)) = (caps[1].parse(), caps[2].parse()) {
+                return Field::Ratio(cur, max);
+            }
+        } else if let Some(caps) = NUMBER.captures(value) {
+            if let Ok(n) = caps[1].parse() {
+                return Field::Number(n);
+            }
+        }
+        Field::Text(value.to_owned())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skill {
+    pub id: String,
+    pub name: String,
+    // 等级描述，如“初学乍练”
+    pub desc: String,
+    pub level: u32,
+    pub progress: u32,
+    // 是否已激发
+    pub enabled: bool,
+}
+
+/// 解析后的状态界面
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub kind: StatusKind,
+    pub fields: Vec<(String, Field)>,
+    pub skills: Vec<Skill>,
+}
+
+impl Status {
+    fn new(kind: StatusKind) -> Self {
+        Self {
+            kind,
+            fields: vec![],
+            skills: vec![],
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        match self.kind {
+            StatusKind::Hp => {
+                for caps in BRACKET_FIELD.captures_iter(line) {
+                    self.fields.push((field_key(&caps[1]), Field::parse(&caps[2])));
+                }
+            }
+            StatusKind::Score => {
+                for caps in COLON_FIELD.captures_iter(line) {
+                    self.fields.push((field_key(&caps[1]), Field::parse(&caps[2])));
+                }
+            }
+            StatusKind::Skills => {
+                if let Some(caps) = SKILL.captures(line) {
+                    self.skills.push(Skill {
+                        id: caps[3].to_owned(),
+                        name: caps[2].to_owned(),
+                        desc: caps[4].to_owned(),
+                        level: caps[5].parse().unwrap_or_default(),
+                        progress: caps[6].parse().unwrap_or_default(),
+                        enabled: caps.get(1).is_some(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// 转换为变量，以界面名称为前缀，如hp.qi、hp.qi.max、skills.dodge
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let prefix = self.kind.name();
+        let mut vars = Vec::new();
+        for (key, field) in &self.fields {
+            match field {
+                Field::Number(n) => vars.push((format!("{}.{}", prefix, key), n.to_string())),
+                Field::Ratio(cur, max) => {
+                    vars.push((format!("{}.{}", prefix, key), cur.to_string()));
+                    vars.push((format!("{}.{}.max", prefix, key), max.to_string()));
+                }
+                Field::Text(s) => vars.push((format!("{}.{}", prefix, key), s.to_owned())),
+            }
+        }
+        for skill in &self.skills {
+            vars.push((format!("{}.{}", prefix, skill.id), skill.level.to_string()));
+        }
+        vars
+    }
+}
+
+impl<'lua> ToLua<'lua> for Status {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind.name())?;
+        for (key, field) in self.fields {
+            match field {
+                Field::Number(n) => table.set(key, n)?,
+                Field::Ratio(cur, max) => {
+                    let ratio = lua.create_table()?;
+                    ratio.set("cur", cur)?;
+                    ratio.set("max", max)?;
+                    table.set(key, ratio)?;
+                }
+                Field::Text(s) => table.set(key, s)?,
+            }
+        }
+        if self.kind == StatusKind::Skills {
+            let skills = lua.create_table()?;
+            for (i, skill) in self.skills.into_iter().enumerate() {
+                let t = lua.create_table()?;
+                t.set("id", skill.id)?;
+                t.set("name", skill.name)?;
+                t.set("desc", skill.desc)?;
+                t.set("level", skill.level)?;
+                t.set("progress", skill.progress)?;
+                t.set("enabled", skill.enabled)?;
+                skills.set(i + 1, t)?;
+            }
+            table.set("skills", skills)?;
+        }
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+fn field_key(label: &str) -> String {
+    let label = label.trim();
+    FIELD_KEYS
+        .iter()
+        .find(|(zh, _)| *zh == label)
+        .map(|(_, key)| (*key).to_owned())
+        .unwrap_or_else(|| label.to_owned())
+}
+
+/// 逐行输入的结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Feed {
+    Pending,
+    Done(Status),
+    // 超过等待行数仍未出现状态界面
+    Expired,
+}
+
+/// 状态界面捕获，发送命令后启动，以制表符边框识别界面的开始与结束
+#[derive(Debug, Clone)]
+pub struct StatusCapture {
+    status: Status,
+    started: bool,
+    lines: usize,
+}
+
+impl StatusCapture {
+    pub fn new(kind: StatusKind) -> Self {
+        Self {
+            status: Status::new(kind),
+            started: false,
+            lines: 0,
+        }
+    }
+
+    pub fn kind(&self) -> StatusKind {
+        self.status.kind
+    }
+
+    pub fn feed(&mut self, line: &str) -> Feed {
+        self.lines += 1;
+        let trimmed = line.trim();
+        if !self.started {
+            if trimmed.starts_with('┌') {
+                self.started = true;
+                self.lines = 0;
+                return Feed::Pending;
+            }
+            if self.lines > MAX_WAIT_LINES {
+                return Feed::Expired;
+            }
+            return Feed::Pending;
+        }
+        if trimmed.starts_with('└') {
+            return Feed::Done(self.status.clone());
+        }
+        if self.lines > MAX_BODY_LINES {
+            return Feed::Expired;
+        }
+        self.status.parse_line(trimmed);
+        Feed::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(kind: StatusKind, screen: &str) -> Status {
+        let mut capture = StatusCapture::new(kind);
+        for line in screen.lines() {
+            if let Feed::Done(status) = capture.feed(line) {
+                return status;
+            }
+        }
+        panic!("status screen not completed");
+    }
+
+    #[test]
+    fn test_status_hp() {
+        let screen = "\
+hp
+┌───个人状态────────────┬───────────────────┐
+│【精气】 1000    / 1200     [100%]    │【精力】 900     / 1000    (+   0)    │
+│【气血】 1800    / 2000     [ 95%]    │【内力】 3000    / 3000    (+   0)    │
+│【潜能】 1234                          │【经验】 123456                      │
+├─────────────────┴───────────────────┤
+│【状态】 健康                                                        │
+└──────────────────────────────北大侠客行────────┘";
+        let status = capture(StatusKind::Hp, screen);
+        assert!(status.fields.contains(&("qi".to_owned(), Field::Ratio(1800, 2000))));
+        assert!(status.fields.contains(&("pot".to_owned(), Field::Number(1234))));
+        assert!(status.fields.contains(&("state".to_owned(), Field::Text("健康".to_owned()))));
+        let vars = status.vars();
+        assert!(vars.contains(&("hp.jing.max".to_owned(), "1200".to_owned())));
+        assert!(vars.contains(&("hp.exp".to_owned(), "123456".to_owned())));
+    }
+
+    #[test]
+    fn test_field_overflow() {
+        assert_eq!(Field::Number(-12), Field::parse("[ -12]"));
+        assert_eq!(Field::Text("99999999999999999999".to_owned()), Field::parse("99999999999999999999"));
+        assert_eq!(
+            Field::Text("1 / 99999999999999999999".to_owned()),
+            Field::parse("1 / 99999999999999999999")
+        );
+    }
+
+    #[test]
+    fn test_status_score_skills() {
+        let screen = "\
+┌─────────────个人档案──────────────┐
+│ 膂力：[ 20]  悟性：[ 25]  根骨：[ 20]  身法：[ 18]  │
+│ 师承：华山派岳不群                                 │
+└──────────────────────────────────┘";
+        let status = capture(StatusKind::Score, screen);
+        assert!(status.fields.contains(&("int".to_owned(), Field::Number(25))));
+        assert!(status.fields.contains(&("师承".to_owned(), Field::Text("华山派岳不群".to_owned()))));
+
+        let screen = "\
+你目前所学过的技能：（共2项技能）
+┌──────────────────────────────────────┐
+│  基本轻功 (dodge)                        - 初学乍练  50/   10│
+│□ 凌波微步 (lingboweibu)                 - 粗通皮毛  60/    0│
+└──────────────────────────────────────┘";
+        let status = capture(StatusKind::Skills, screen);
+        assert_eq!(2, status.skills.len());
+        assert_eq!("dodge", status.skills[0].id);
+        assert_eq!(10, status.skills[0].progress);
+        assert!(status.skills[1].enabled);
+        assert!(status.vars().contains(&("skills.lingboweibu".to_owned(), "60".to_owned())));
+
+        let mut capture = StatusCapture::new(StatusKind::Hp);
+        let expired = (0..=MAX_WAIT_LINES).any(|_| capture.feed("天气晴朗") == Feed::Expired);
+        assert!(expired);
+    }
+}
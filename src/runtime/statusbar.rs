@@ -0,0 +1,142 @@
+use crate::proto::ansi::CursorOp;
+use crate::ui::line::Line;
+use crate::ui::span::Span;
+use std::collections::BTreeMap;
+
+/// 服务器状态栏
+///
+/// 部分服务器通过光标定位在屏幕固定行绘制状态栏，这里按行号收集定位后的文本，
+/// 不输出到主窗格。保存光标后的定位持续到恢复光标为止，未保存光标的定位持续到行尾。
+/// 不跟踪列位置，清除行时清空整行
+#[derive(Debug)]
+pub struct StatusBar {
+    max_rows: usize,
+    rows: BTreeMap<u16, Vec<Span>>,
+    // 正在绘制的行号
+    cursor: Option<u16>,
+    saved: bool,
+    changed: bool,
+}
+
+impl StatusBar {
+    pub fn new(max_rows: usize) -> Self {
+        Self {
+            max_rows,
+            rows: BTreeMap::new(),
+            cursor: None,
+            saved: false,
+            changed: false,
+        }
+    }
+
+    pub fn apply(&mut self, op: CursorOp) {
+        match op {
+            CursorOp::Save => self.saved = true,
+            CursorOp::Restore => {
+                self.saved = false;
+                self.cursor = None;
+            }
+            CursorOp::Position(row, _) => self.goto(row),
+            CursorOp::EraseLine(_) => {
+                if let Some(spans) = self.cursor.and_then(|row| self.rows.get_mut(&row)) {
+                    spans.clear();
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    fn goto(&mut self, row: u16) {
+        self.cursor = Some(row);
+        self.rows.entry(row).or_default();
+        // 仅保留行号最大的若干行，通常位于屏幕底部
+        while self.rows.len() > self.max_rows {
+            self.rows.pop_first();
+        }
+        self.changed = true;
+    }
+
+    /// 绘制状态栏时收集片段并返回None，否则原样返回
+    pub fn capture(&mut self, span: Span) -> Option<Span> {
+        let row = match self.cursor {
+            Some(row) => row,
+            None => return Some(span),
+        };
+        let ended = span.ended();
+        let content = span.content.trim_end_matches(&['\r', '\n'][..]);
+        if !content.is_empty() {
+            if let Some(spans) = self.rows.get_mut(&row) {
+                spans.push(Span::new(content, span.style, span.label.clone()));
+                self.changed = true;
+            }
+        }
+        if ended {
+            // 保存光标后的换行继续绘制下一行
+            if self.saved {
+                self.goto(row.saturating_add(1));
+            } else {
+                self.cursor = None;
+            }
+        }
+        None
+    }
+
+    /// 内容变化时按行号顺序返回各行，每行均以换行结尾
+    pub fn take_changed(&mut self) -> Option<Vec<Line>> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        let lines = self
+            .rows
+            .values()
+            .map(|spans| {
+                let mut line = Line::new(spans.clone());
+                line.push_span(Span::fmt_raw(""));
+                line
+            })
+            .collect();
+        Some(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{Element, Parser};
+
+    #[test]
+    fn test_status_bar_capture() {
+        let mut bar = StatusBar::new(2);
+        let mut parser = Parser::default();
+        let mut flow = String::new();
+        for raw in &[
+            "你走了过来。\x1b7\x1b[24;1H\x1b[K气血：100\x1b8\r\n",
+            "\x1b[23;1H【状态】\x1b[25;1H经验：10\r\n",
+            "\x1b[25;1H\x1b[2K经验：20\r\n",
+        ] {
+            parser.fill(raw);
+            loop {
+                match parser.next() {
+                    Element::None => break,
+                    Element::Span(span) => {
+                        if let Some(span) = bar.capture(span) {
+                            flow.push_str(&span.content);
+                        }
+                    }
+                    Element::Cursor(op) => bar.apply(op),
+                    _ => (),
+                }
+            }
+        }
+        assert_eq!("你走了过来。\r\n", flow);
+        let rows: Vec<String> = bar
+            .take_changed()
+            .unwrap()
+            .iter()
+            .map(|l| l.plain_text())
+            .collect();
+        assert_eq!(vec!["气血：100", "经验：20"], rows);
+        assert_eq!(None, bar.take_changed());
+    }
+}
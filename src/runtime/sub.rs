@@ -146,6 +146,25 @@ impl SubParser {
     }
 }
 
+/// 展开替换列表，lookup返回数字及名称对应的文本，查找不到时替换为空
+pub fn expand<F>(subs: &[Sub], mut lookup: F) -> String
+where
+    F: FnMut(&Sub) -> Option<String>,
+{
+    let mut rs = String::new();
+    for sub in subs {
+        match sub {
+            Sub::Text(s) => rs.push_str(s),
+            other => {
+                if let Some(s) = lookup(other) {
+                    rs.push_str(&s);
+                }
+            }
+        }
+    }
+    rs
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SubState {
     None,
@@ -179,4 +198,15 @@ mod tests {
             r
         );
     }
+
+    #[test]
+    fn test_sub_expand() {
+        let subs = SubParser::new().parse("kill %1 with %<weapon>%3").unwrap();
+        let s = expand(&subs, |sub| match sub {
+            Sub::Number(1) => Some("rat".to_owned()),
+            Sub::Name(name) if name == "weapon" => Some("sword".to_owned()),
+            _ => None,
+        });
+        assert_eq!("kill rat with sword", s);
+    }
 }
@@ -1,5 +1,6 @@
 use crate::runtime::delay_queue::{Delay, DelayQueue, Delayed};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 use bitflags::bitflags;
@@ -8,6 +9,8 @@ bitflags! {
     pub struct TimerFlags: u16 {
         const ENABLED = 0x0001;
         const ONESHOT = 0x0004;
+        // 下一次调度从回调执行完毕时开始计时，默认按初始时间固定频率调度
+        const FIXED_DELAY = 0x0008;
     }
 }
 
@@ -15,6 +18,31 @@ bitflags! {
 pub struct Timers {
     schedule: DelayQueue<Delay<Timer>>,
     models: HashMap<String, TimerModel>,
+    // 暂停时各定时器距下次调度的剩余时间，未暂停时为None
+    paused: Option<HashMap<String, Duration>>,
+    view: TimerView,
+}
+
+/// 各定时器下次调度时间的只读视图，供脚本同步查询
+///
+/// 定时器每次变化后整体刷新，仅包含已启用的定时器
+#[derive(Debug, Clone, Default)]
+pub struct TimerView(Arc<RwLock<HashMap<String, Countdown>>>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Countdown {
+    Until(Instant),
+    Paused(Duration),
+}
+
+impl TimerView {
+    /// 距下次调度的剩余时间，定时器不存在或已禁用时返回None
+    pub fn remaining(&self, name: &str, now: Instant) -> Option<Duration> {
+        match self.0.read().unwrap().get(name)? {
+            Countdown::Until(deadline) => Some(deadline.saturating_duration_since(now)),
+            Countdown::Paused(remaining) => Some(*remaining),
+        }
+    }
 }
 
 impl Timers {
@@ -22,6 +50,31 @@ impl Timers {
         Self {
             schedule: DelayQueue::new(),
             models: HashMap::new(),
+            paused: None,
+            view: TimerView::default(),
+        }
+    }
+
+    /// 获取只读视图，与定时器共享
+    pub fn view(&self) -> TimerView {
+        self.view.clone()
+    }
+
+    // 刷新只读视图
+    fn publish(&self) {
+        let mut view = self.view.0.write().unwrap();
+        view.clear();
+        for tm in self.models.values().filter(|tm| tm.enabled()) {
+            if let Some(deadline) = tm.deadline {
+                view.insert(tm.name.to_owned(), Countdown::Until(deadline));
+            }
+        }
+        if let Some(remaining) = self.paused.as_ref() {
+            for (name, delay) in remaining {
+                if self.is_enabled(name) {
+                    view.insert(name.to_owned(), Countdown::Paused(*delay));
+                }
+            }
         }
     }
 
@@ -38,10 +91,15 @@ impl Timers {
         self.models.len()
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &TimerModel> {
+        self.models.values()
+    }
+
     pub fn insert(&mut self, tm: TimerModel) {
         if !tm.enabled() {
             // 仅插入而不启动
             self.models.insert(tm.name.to_owned(), tm);
+            self.publish();
             return;
         }
         self.insert_at(tm, Instant::now());
@@ -50,8 +108,103 @@ impl Timers {
     pub fn insert_at(&mut self, tm: TimerModel, start_time: Instant) {
         debug_assert!(tm.enabled());
         let (timer, tm) = tm.start_at(start_time);
-        self.schedule.push(timer);
+        let tm = self.push(timer, tm);
         self.models.insert(tm.name.to_owned(), tm);
+        self.publish();
+    }
+
+    /// 从当前时间重新开始计时，暂停期间仅重置剩余时间，返回定时器是否存在
+    pub fn reset(&mut self, name: &str) -> bool {
+        match self.models.remove(name) {
+            None => false,
+            Some(tm) if !tm.enabled() => {
+                self.models.insert(name.to_owned(), tm);
+                true
+            }
+            Some(tm) => {
+                self.insert_at(tm, Instant::now());
+                true
+            }
+        }
+    }
+
+    // 暂停期间仅记录剩余时间，恢复时再调度
+    fn push(&mut self, timer: Delay<Timer>, mut tm: TimerModel) -> TimerModel {
+        match self.paused.as_mut() {
+            Some(remaining) => {
+                let delay = timer.delay_until().saturating_duration_since(Instant::now());
+                remaining.insert(tm.name.to_owned(), delay);
+                tm.uuid.take();
+                tm.deadline.take();
+            }
+            None => self.schedule.push(timer),
+        }
+        tm
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// 暂停所有定时器，记录各定时器的剩余时间，返回暂停的定时器数
+    ///
+    /// 已在调度队列中的任务因uuid失效而被忽略
+    pub fn pause(&mut self, now: Instant) -> usize {
+        if self.paused.is_some() {
+            return 0;
+        }
+        let mut remaining = HashMap::new();
+        for tm in self.models.values_mut().filter(|tm| tm.enabled()) {
+            if let Some(deadline) = tm.deadline.take() {
+                tm.uuid.take();
+                remaining.insert(tm.name.to_owned(), deadline.saturating_duration_since(now));
+            }
+        }
+        let n = remaining.len();
+        self.paused = Some(remaining);
+        self.publish();
+        n
+    }
+
+    /// 恢复暂停的定时器，按剩余时间重新调度，返回恢复的定时器数
+    pub fn resume(&mut self, now: Instant) -> usize {
+        let remaining = match self.paused.take() {
+            Some(remaining) => remaining,
+            None => return 0,
+        };
+        let mut n = 0;
+        for (name, delay) in remaining {
+            // 暂停期间被删除或禁用的定时器不再调度
+            if let Some(tm) = self.models.remove(&name) {
+                if !tm.enabled() {
+                    self.models.insert(name, tm);
+                    continue;
+                }
+                let (timer, tm) = tm.start_until(now + delay);
+                self.schedule.push(timer);
+                self.models.insert(name, tm);
+                n += 1;
+            }
+        }
+        self.publish();
+        n
+    }
+
+    /// 调度任务是否与当前定时器一致，暂停期间的任务均不执行
+    pub fn is_current(&self, timer: &Timer) -> bool {
+        if self.paused.is_some() {
+            return false;
+        }
+        match self.models.get(&timer.name) {
+            Some(tm) => tm.enabled() && tm.uuid == Some(timer.uuid),
+            None => false,
+        }
+    }
+
+    /// 定时任务执行完毕后开启下一次调度，deadline为本次任务的调度时间
+    pub fn reschedule(&mut self, tm: TimerModel, deadline: Instant) {
+        let start_time = tm.next_start(deadline, Instant::now());
+        self.insert_at(tm, start_time);
     }
 
     pub fn is_enabled(&self, name: &str) -> bool {
@@ -72,6 +225,8 @@ impl Timers {
                 let tm = self.models.get_mut(name).unwrap();
                 tm.set_enabled(false);
                 tm.uuid.take();
+                tm.deadline.take();
+                self.publish();
                 return;
             }
             if !tm.enabled() && enabled {
@@ -88,26 +243,39 @@ impl Timers {
 
     pub fn enable_group(&mut self, group: &str, enabled: bool) -> usize {
         let mut n = 0;
+        // 从禁用变为启用的定时器
+        let mut started = vec![];
         for tm in self.models.values_mut() {
             if tm.group == group {
                 n += 1;
                 if !tm.enabled() && enabled {
                     // 从禁用变为启用，生成调度
                     tm.set_enabled(true);
-                    let (new_timer, new_tm) = tm.clone().start_now();
-                    self.schedule.push(new_timer);
-                    *tm = new_tm;
+                    started.push(tm.name.to_owned());
                 } else {
                     tm.set_enabled(enabled);
                 }
             }
         }
+        for name in started {
+            let (new_timer, new_tm) = self.models.remove(&name).unwrap().start_now();
+            let new_tm = self.push(new_timer, new_tm);
+            self.models.insert(name, new_tm);
+        }
+        self.publish();
         n
     }
 
+    /// 分组内所有定时器的名称
+    pub fn group_names(&self, group: &str) -> Vec<String> {
+        self.models.values().filter(|tm| tm.group == group).map(|tm| tm.name.to_owned()).collect()
+    }
+
     pub fn remove(&mut self, name: &str) -> Option<TimerModel> {
         // 无需处理已调度的定时任务，在每次pop时将检验
-        self.models.remove(name)
+        let tm = self.models.remove(name);
+        self.publish();
+        tm
     }
 
     pub fn finish(&mut self, task: Delay<Timer>) {
@@ -118,6 +286,7 @@ impl Timers {
                     let (name, tm) = self.models.remove_entry(&task.value.name).unwrap();
                     if tm.oneshot() {
                         // 临时任务，直接退出
+                        self.publish();
                         return;
                     }
                     if !tm.enabled() {
@@ -126,9 +295,7 @@ impl Timers {
                         return;
                     }
                     // 处于启用状态，开启下一次调度
-                    let (next_timer, next_tm) = tm.start_at(task.delay_until());
-                    self.schedule.push(next_timer);
-                    self.models.insert(name, next_tm);
+                    self.reschedule(tm, task.delay_until());
                 }
             }
         }
@@ -149,6 +316,8 @@ pub struct TimerModel {
     pub tick_time: Duration,
     flags: TimerFlags,
     uuid: Option<u128>,
+    // 下一次调度时间
+    deadline: Option<Instant>,
 }
 
 impl TimerModel {
@@ -164,6 +333,7 @@ impl TimerModel {
             tick_time,
             flags,
             uuid: None,
+            deadline: None,
         }
     }
 
@@ -191,6 +361,22 @@ impl TimerModel {
         }
     } 
 
+    pub fn fixed_delay(&self) -> bool {
+        self.flags.contains(TimerFlags::FIXED_DELAY)
+    }
+
+    /// 计算下一次调度的起始时间，调度时间为起始时间加周期
+    ///
+    /// 固定延迟模式从当前时间开始计时，固定频率模式从本次调度时间开始计时，
+    /// 负载过高错过多个周期时跳过错过的周期，避免集中触发
+    pub fn next_start(&self, deadline: Instant, now: Instant) -> Instant {
+        if self.fixed_delay() || self.tick_time.is_zero() {
+            return now;
+        }
+        let missed = now.saturating_duration_since(deadline).as_nanos() / self.tick_time.as_nanos();
+        deadline + self.tick_time * missed as u32
+    }
+
     pub fn uuid(&self) -> Option<u128> {
         self.uuid
     }
@@ -199,8 +385,13 @@ impl TimerModel {
         self.start_at(Instant::now())
     }
 
-    pub fn start_at(mut self, start_time: Instant) -> (Delay<Timer>, TimerModel) {
+    pub fn start_at(self, start_time: Instant) -> (Delay<Timer>, TimerModel) {
         let next_time = start_time + self.tick_time;
+        self.start_until(next_time)
+    }
+
+    fn start_until(mut self, next_time: Instant) -> (Delay<Timer>, TimerModel) {
+        self.deadline.replace(next_time);
         // uuid将作为检验定时任务是否与当前定时器匹配的依据
         let uuid = Uuid::new_v4().as_u128();
         self.uuid.replace(uuid);
@@ -301,4 +492,77 @@ mod tests {
         timers.finish(task);
         assert!(schedule.pop_timeout(Duration::from_millis(11)).is_none());
     }
+
+    #[test]
+    fn test_timer_pause() {
+        let mut timers = Timers::new();
+        let schedule = timers.schedule();
+        timers.insert(TimerModel::new(
+            "t8",
+            "timer",
+            Duration::from_millis(30),
+            TimerFlags::ENABLED,
+        ));
+        assert_eq!(1, timers.pause(Instant::now()));
+        // 暂停前已调度的任务失效
+        let task = schedule.pop_timeout(Duration::from_millis(31)).unwrap();
+        assert!(!timers.is_current(&task.value));
+        // 暂停期间启用的定时器在恢复后调度
+        timers.insert(TimerModel::new(
+            "t9",
+            "timer",
+            Duration::from_millis(10),
+            TimerFlags::ENABLED,
+        ));
+        assert!(schedule.pop_timeout(Duration::from_millis(11)).is_none());
+        assert_eq!(2, timers.resume(Instant::now()));
+        let task = schedule.pop_timeout(Duration::from_millis(11)).unwrap();
+        assert_eq!("t9", task.value.name);
+        assert!(timers.is_current(&task.value));
+        // 按剩余时间调度，而非完整周期
+        let task = schedule.pop_timeout(Duration::from_millis(30)).unwrap();
+        assert_eq!("t8", task.value.name);
+        assert!(timers.is_current(&task.value));
+    }
+
+    #[test]
+    fn test_timer_remaining() {
+        let mut timers = Timers::new();
+        let view = timers.view();
+        let start = Instant::now();
+        timers.insert(TimerModel::new("t7", "timer", Duration::from_secs(10), TimerFlags::ENABLED));
+        let remaining = view.remaining("t7", start).unwrap();
+        assert!(remaining > Duration::from_secs(9));
+        assert!(view.remaining("t7", start + Duration::from_secs(4)).unwrap() < Duration::from_secs(7));
+        // 暂停时剩余时间不变
+        timers.pause(start + Duration::from_secs(4));
+        assert_eq!(view.remaining("t7", start + Duration::from_secs(8)), view.remaining("t7", start));
+        timers.resume(Instant::now());
+        timers.reset("t7");
+        assert!(view.remaining("t7", Instant::now()).unwrap() > Duration::from_secs(9));
+        assert!(!timers.reset("t8"));
+        timers.enable("t7", false);
+        assert_eq!(None, view.remaining("t7", start));
+        timers.enable("t7", true);
+        assert!(view.remaining("t7", start).is_some());
+        timers.remove("t7");
+        assert_eq!(None, view.remaining("t7", start));
+    }
+
+    #[test]
+    fn test_timer_next_start() {
+        let tick = Duration::from_millis(100);
+        let tm = TimerModel::new("t6", "timer", tick, TimerFlags::ENABLED);
+        let deadline = Instant::now();
+        // 按调度时间计时，不受执行延迟影响
+        assert_eq!(deadline, tm.next_start(deadline, deadline + Duration::from_millis(30)));
+        // 错过的周期被跳过，仍与初始时间对齐
+        assert_eq!(
+            deadline + tick * 2,
+            tm.next_start(deadline, deadline + Duration::from_millis(250))
+        );
+        let tm = TimerModel::new("t7", "timer", tick, TimerFlags::ENABLED | TimerFlags::FIXED_DELAY);
+        let now = deadline + Duration::from_millis(30);
+        assert_eq!(now, tm.next_start(deadline, now));
+    }
 }
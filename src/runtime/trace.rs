@@ -0,0 +1,238 @@
+use crate::runtime::engine::EngineAction;
+use crate::ui::UserOutput;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 单行文本的处理轨迹
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    // 处理时间，自UNIX纪元起的毫秒数
+    pub time: u128,
+    pub line: String,
+    // 执行的触发器
+    pub triggers: Vec<String>,
+    // 由该行产生的操作及其序号
+    pub actions: Vec<(u64, String)>,
+    // 隐去命令文本的操作摘要，用于可能公开的崩溃报告
+    redacted: Vec<(u64, String)>,
+    // 执行过程中的错误
+    pub errors: Vec<String>,
+}
+
+impl Trace {
+    fn new(line: String) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        Self {
+            time,
+            line,
+            triggers: vec![],
+            actions: vec![],
+            redacted: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// 隐去发送的命令及脚本文本的副本，避免密码等写入崩溃报告
+    pub fn redacted(&self) -> Self {
+        Self {
+            actions: self.redacted.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// 格式化为多行文本
+    pub fn format(&self) -> Vec<String> {
+        let mut lines = vec![format!("[{}] {}", self.time, self.line)];
+        for tr in &self.triggers {
+            lines.push(format!("  trigger {}", tr));
+        }
+        for (seq, action) in &self.actions {
+            lines.push(format!("  action  #{} {}", seq, action));
+        }
+        for err in &self.errors {
+            lines.push(format!("  error   {}", err));
+        }
+        lines
+    }
+}
+
+/// 最近若干行的处理轨迹，使用环形缓冲区保存
+#[derive(Debug)]
+pub struct Tracer {
+    capacity: usize,
+    traces: VecDeque<Trace>,
+    current: Option<Trace>,
+    // 尚未写入崩溃报告的轨迹数
+    unreported: usize,
+}
+
+impl Tracer {
+    /// 容量为0时不记录
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            traces: VecDeque::new(),
+            current: None,
+            unreported: 0,
+        }
+    }
+
+    /// 开始记录新的一行
+    pub fn begin(&mut self, line: impl Into<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.end();
+        self.current = Some(Trace::new(line.into()));
+    }
+
+    /// 结束当前行的记录
+    pub fn end(&mut self) {
+        if let Some(trace) = self.current.take() {
+            self.traces.push_back(trace);
+            while self.traces.len() > self.capacity {
+                self.traces.pop_front();
+            }
+            self.unreported = (self.unreported + 1).min(self.traces.len());
+        }
+    }
+
+    /// 上次调用以来结束的轨迹，已隐去命令文本，用于批量写入崩溃报告
+    pub fn take_unreported(&mut self) -> Vec<Trace> {
+        let n = std::mem::take(&mut self.unreported);
+        self.last(n).map(Trace::redacted).collect()
+    }
+
+    pub fn trigger(&mut self, name: &str) {
+        if let Some(trace) = self.current.as_mut() {
+            trace.triggers.push(name.to_owned());
+        }
+    }
+
+    pub fn action(&mut self, seq: u64, action: &EngineAction) {
+        if let Some(trace) = self.current.as_mut() {
+            if let Some(s) = describe(action, false) {
+                trace.actions.push((seq, s));
+            }
+            if let Some(s) = describe(action, true) {
+                trace.redacted.push((seq, s));
+            }
+        }
+    }
+
+    pub fn error(&mut self, err: impl Into<String>) {
+        if let Some(trace) = self.current.as_mut() {
+            trace.errors.push(err.into());
+        }
+    }
+
+    /// 最近n条轨迹，按时间顺序
+    pub fn last(&self, n: usize) -> impl Iterator<Item = &Trace> {
+        self.traces.iter().skip(self.traces.len().saturating_sub(n))
+    }
+
+    pub fn clear(&mut self) {
+        self.traces.clear();
+        self.unreported = 0;
+    }
+
+    /// 导出所有轨迹为文本
+    pub fn export(&self) -> String {
+        let mut s = String::new();
+        for trace in &self.traces {
+            for line in trace.format() {
+                let _ = writeln!(s, "{}", line);
+            }
+        }
+        s
+    }
+}
+
+// 操作摘要，世界文本本身的输出不记录
+//
+// redact时隐去命令及脚本文本，其余操作仅保留名称
+fn describe(action: &EngineAction, redact: bool) -> Option<String> {
+    let text = |s: &str| if redact { "<redacted>".to_owned() } else { s.trim_end().to_owned() };
+    let s = match action {
+        EngineAction::SendLineToUI(_, Some(_))
+        | EngineAction::SendLineToWindow(..)
+        | EngineAction::SendStatusToUI(_)
+        | EngineAction::SendRawToUI(_)
+        | EngineAction::ParseWorldBytes(_) => return None,
+        EngineAction::SendLineToUI(line, None) => format!("ui {}", line.plain_text()),
+        EngineAction::SendToServer(cmd) => format!("send {}", text(cmd)),
+        EngineAction::SendHeldToServer(cmds) => format!("send held {}", cmds.len()),
+        // 不记录命令内容
+        EngineAction::SendNoEcho(_) => "send no-echo".to_owned(),
+        EngineAction::SendRawToServer(bs) => format!("send raw {} bytes", bs.len()),
+        EngineAction::ExecuteUserOutput(UserOutput::Cmd(cmd)) => format!("cmd {}", text(cmd)),
+        EngineAction::ExecuteUserOutput(UserOutput::Script(script)) => format!("script {}", text(script)),
+        EngineAction::ExecuteAliasCmd(cmd, chain) => {
+            format!("alias-cmd {} ({})", text(cmd), chain.join(" -> "))
+        }
+        EngineAction::WaitAliasCmd(delay, cmd, _) => format!("wait {}ms {}", delay.as_millis(), text(cmd)),
+        EngineAction::CreateTrigger(tr) => format!("create trigger {}", tr.name),
+        EngineAction::DeleteTrigger(name) => format!("delete trigger {}", name),
+        EngineAction::EnableTriggerGroup(group, enabled) => {
+            format!("enable trigger group {} {}", group, enabled)
+        }
+        EngineAction::CreateAlias(alias) => format!("create alias {}", alias.name),
+        EngineAction::DeleteAlias(name) => format!("delete alias {}", name),
+        EngineAction::EnableAliasGroup(group, enabled) => {
+            format!("enable alias group {} {}", group, enabled)
+        }
+        EngineAction::CreateTimer(tm) => format!("create timer {}", tm.name),
+        EngineAction::DeleteTimer(name) => format!("delete timer {}", name),
+        EngineAction::EnableTimerGroup(group, enabled) => {
+            format!("enable timer group {} {}", group, enabled)
+        }
+        EngineAction::CreateMxpTrigger(tr) => format!("create mxp trigger {}", tr.name),
+        EngineAction::DeleteMxpTrigger(name) => format!("delete mxp trigger {}", name),
+        EngineAction::EnableMxpTriggerGroup(group, enabled) => {
+            format!("enable mxp trigger group {} {}", group, enabled)
+        }
+        EngineAction::BundleFetched(url, res) => format!("bundle fetched {} {}", url, res.is_ok()),
+        other if redact => {
+            let s = format!("{:?}", other);
+            s.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_owned()
+        }
+        other => format!("{:?}", other),
+    };
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracer_ring_buffer() {
+        let mut tracer = Tracer::new(2);
+        for line in &["a", "b", "c"] {
+            tracer.begin(*line);
+            tracer.trigger("tr");
+            tracer.action(1, &EngineAction::SendToServer("kill\n".to_owned()));
+        }
+        tracer.end();
+        let lines: Vec<&str> = tracer.last(5).map(|t| &t.line[..]).collect();
+        assert_eq!(vec!["b", "c"], lines);
+        assert_eq!(vec!["c"], tracer.last(1).map(|t| &t.line[..]).collect::<Vec<_>>());
+        assert!(tracer.export().contains("  trigger tr"));
+        assert!(tracer.export().contains("  action  #1 send kill"));
+
+        // 崩溃报告中的轨迹不含命令文本
+        let reported = tracer.take_unreported();
+        assert_eq!(2, reported.len());
+        assert_eq!(vec![(1, "send <redacted>".to_owned())], reported[1].actions);
+        assert!(tracer.take_unreported().is_empty());
+
+        let mut tracer = Tracer::new(0);
+        tracer.begin("a");
+        tracer.end();
+        assert_eq!(0, tracer.last(1).count());
+    }
+}
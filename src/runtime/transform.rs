@@ -0,0 +1,216 @@
+use crate::error::Result;
+use crate::ui::line::Line;
+use crate::ui::span::Span;
+use std::time::{Duration, Instant};
+
+/// 内置的空白规范化转换器名称
+pub const NORMALIZE_WHITESPACE: &str = "normalize-whitespace";
+
+/// 行转换器，在解析之后、路由及触发器匹配之前修改文本
+pub trait LineTransformer {
+    fn transform(&mut self, line: Line) -> Result<Line>;
+}
+
+/// 转换器的执行统计
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransformStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub elapsed: Duration,
+}
+
+impl TransformStats {
+    /// 平均耗时，单位微秒
+    pub fn avg_micros(&self) -> u128 {
+        if self.calls == 0 {
+            return 0;
+        }
+        self.elapsed.as_micros() / self.calls as u128
+    }
+}
+
+// 脚本转换器的回调存放于Lua全局表中，执行时由调用方提供
+enum Kind {
+    Native(Box<dyn LineTransformer>),
+    Script,
+}
+
+struct Entry {
+    name: String,
+    order: i32,
+    enabled: bool,
+    kind: Kind,
+    stats: TransformStats,
+}
+
+/// 有序的具名转换器，按order从小到大依次执行
+#[derive(Default)]
+pub struct Transformers(Vec<Entry>);
+
+impl Transformers {
+    /// 包含默认禁用的内置转换器
+    pub fn with_builtins() -> Self {
+        let mut transformers = Self::default();
+        transformers.add_native(NORMALIZE_WHITESPACE, 0, NormalizeWhitespace);
+        transformers.enable(NORMALIZE_WHITESPACE, false);
+        transformers
+    }
+
+    pub fn add_native(&mut self, name: &str, order: i32, transformer: impl LineTransformer + 'static) {
+        self.add(name, order, Kind::Native(Box::new(transformer)));
+    }
+
+    /// 添加脚本转换器，回调由调用方按名称管理
+    pub fn add_script(&mut self, name: &str, order: i32) {
+        self.add(name, order, Kind::Script);
+    }
+
+    // 同名转换器将被替换，相同order按添加顺序执行
+    fn add(&mut self, name: &str, order: i32, kind: Kind) {
+        self.remove(name);
+        let pos = self.0.partition_point(|e| e.order <= order);
+        self.0.insert(
+            pos,
+            Entry {
+                name: name.to_owned(),
+                order,
+                enabled: true,
+                kind,
+                stats: TransformStats::default(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|e| e.name != name);
+        self.0.len() != len
+    }
+
+    /// 启用/禁用转换器，返回转换器是否存在
+    pub fn enable(&mut self, name: &str, enabled: bool) -> bool {
+        match self.0.iter_mut().find(|e| e.name == name) {
+            Some(e) => {
+                e.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 是否存在启用的转换器
+    pub fn active(&self) -> bool {
+        self.0.iter().any(|e| e.enabled)
+    }
+
+    /// 依次执行启用的转换器，出错的转换器不改变文本，错误信息以转换器名称为前缀返回
+    pub fn apply(
+        &mut self,
+        mut line: Line,
+        mut script: impl FnMut(&str, Line) -> Result<Line>,
+    ) -> (Line, Vec<String>) {
+        let mut errors = vec![];
+        for e in self.0.iter_mut().filter(|e| e.enabled) {
+            let start = Instant::now();
+            let res = match &mut e.kind {
+                Kind::Native(t) => t.transform(line.clone()),
+                Kind::Script => script(&e.name, line.clone()),
+            };
+            e.stats.calls += 1;
+            e.stats.elapsed += start.elapsed();
+            match res {
+                Ok(transformed) => line = transformed,
+                Err(err) => {
+                    e.stats.errors += 1;
+                    errors.push(format!("{}: {}", e.name, err));
+                }
+            }
+        }
+        (line, errors)
+    }
+
+    /// 名称、顺序、是否启用及执行统计
+    pub fn list(&self) -> Vec<(String, i32, bool, TransformStats)> {
+        self.0
+            .iter()
+            .map(|e| (e.name.to_owned(), e.order, e.enabled, e.stats))
+            .collect()
+    }
+}
+
+/// 对每个片段的文本（不含行尾换行符）进行转换，保留片段样式
+pub fn map_spans(line: Line, mut f: impl FnMut(&str) -> Result<String>) -> Result<Line> {
+    let mut spans = Vec::new();
+    for span in line.into_spans() {
+        let (text, ending) = split_ending(&span.content);
+        let mut content = f(text)?;
+        content.push_str(ending);
+        spans.push(Span::new(content, span.style, span.label));
+    }
+    Ok(Line::new(spans))
+}
+
+fn split_ending(s: &str) -> (&str, &str) {
+    let text = s.trim_end_matches(&['\r', '\n'][..]);
+    (text, &s[text.len()..])
+}
+
+/// 空白规范化：制表符替换为空格，全角空格替换为两个半角空格，删除行尾空白
+pub struct NormalizeWhitespace;
+
+impl LineTransformer for NormalizeWhitespace {
+    fn transform(&mut self, line: Line) -> Result<Line> {
+        let ended = line.ended();
+        let line = map_spans(line, |s| Ok(s.replace('\t', " ").replace('\u{3000}', "  ")))?;
+        if !ended {
+            return Ok(line);
+        }
+        // 仅对完整的行删除行尾空白
+        let mut spans = line.into_spans();
+        for span in spans.iter_mut().rev() {
+            let (text, ending) = split_ending(&span.content);
+            let trimmed = text.trim_end();
+            let done = !trimmed.is_empty();
+            span.content = format!("{}{}", trimmed, ending);
+            if done {
+                break;
+            }
+        }
+        Ok(Line::new(spans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_transformers_order_and_stats() {
+        let mut ts = Transformers::with_builtins();
+        ts.add_script("upper", 10);
+        ts.add_script("fail", 5);
+        ts.enable(NORMALIZE_WHITESPACE, true);
+        let names: Vec<String> = ts.list().into_iter().map(|(name, ..)| name).collect();
+        assert_eq!(vec![NORMALIZE_WHITESPACE, "fail", "upper"], names);
+
+        let (line, errors) = ts.apply(Line::fmt_raw("hello\u{3000}world \t "), |name, line| {
+            match name {
+                "upper" => map_spans(line, |s| Ok(s.to_uppercase())),
+                _ => Err(Error::RuntimeError("boom".to_owned())),
+            }
+        });
+        assert_eq!("HELLO  WORLD", line.plain_text());
+        assert!(line.ended());
+        assert_eq!(1, errors.len());
+        assert!(errors[0].starts_with("fail: "));
+        let stats = ts.list();
+        assert_eq!((1, 1), (stats[1].3.calls, stats[1].3.errors));
+
+        assert!(ts.enable("upper", false));
+        assert!(ts.remove("fail"));
+        assert!(!ts.enable("fail", true));
+        let (line, _) = ts.apply(Line::fmt_raw("a b"), |_, line| Ok(line));
+        assert_eq!("a b", line.plain_text());
+    }
+}
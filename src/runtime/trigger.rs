@@ -1,6 +1,10 @@
-use crate::runtime::cache::{CacheText, InlineStyle};
-use crate::runtime::model::{MapModelStore, Model, ModelMatch};
+use crate::proto::Label;
+use crate::runtime::cache::{dominant_style, CacheText, InlineStyle};
+use crate::runtime::model::{MapModelStore, Model, ModelMatch, NumberOrString};
+use crate::ui::line::{Line, RawLine};
+use crate::ui::style::{Color, Modifier};
 use bitflags::bitflags;
+use std::time::Instant;
 
 pub type Triggers = MapModelStore<Trigger>;
 
@@ -10,11 +14,13 @@ impl Triggers {
     /// 与match_first不同之处在于支持多行匹配
     pub fn trigger_first(&self, text: &CacheText) -> Option<(&Trigger, String, Vec<InlineStyle>)> {
         self.0.values()
+            .filter(|tr| tr.enabled)
             .find_map(|tr| tr.match_trigger(text))
     }
 
     pub fn trigger_all(&self, text: &CacheText) -> Vec<(&Trigger, String, Vec<InlineStyle>)> {
         self.0.values()
+            .filter(|tr| tr.enabled)
             .filter_map(|tr| tr.match_trigger(text))
             .collect()
     }
@@ -33,13 +39,53 @@ impl Trigger {
             }
         } else {
             if let Some((line, styles)) = text.last_trimmed() {
-                if self.is_match(line) {
+                if self.is_match(line) && self.style_match(line, styles) {
                     return Some((self, line.to_owned(), styles.to_vec()));
                 }
             }
         }
         None
     }
+
+    /// 匹配区域的主要样式是否满足样式条件，未设置条件时总是满足
+    pub fn style_match(&self, text: &str, styles: &[InlineStyle]) -> bool {
+        let cond = match self.extra.style.as_ref() {
+            Some(cond) => cond,
+            None => return true,
+        };
+        let caps = match self.re.captures(text) {
+            Some(caps) => caps,
+            None => return false,
+        };
+        let region = match &cond.capture {
+            NumberOrString::Number(i) => caps.get(*i),
+            NumberOrString::String(name) => caps.name(name),
+        };
+        region
+            .and_then(|m| dominant_style(styles, m.start(), m.end()))
+            .map(|is| cond.matches(&is))
+            .unwrap_or(false)
+    }
+
+    /// 各捕获区域的主要样式，按序号及组名索引，多行匹配时没有样式信息
+    pub fn capture_styles(&self, text: &str, styles: &[InlineStyle]) -> Vec<(NumberOrString, InlineStyle)> {
+        let caps = match self.re.captures(text) {
+            Some(caps) if !styles.is_empty() => caps,
+            _ => return vec![],
+        };
+        let mut res = Vec::new();
+        for (i, name) in self.re.capture_names().enumerate().skip(1) {
+            let is = match caps.get(i).and_then(|m| dominant_style(styles, m.start(), m.end())) {
+                Some(is) => is,
+                None => continue,
+            };
+            if let Some(name) = name {
+                res.push((NumberOrString::new_string(name), is.clone()));
+            }
+            res.push((NumberOrString::Number(i), is));
+        }
+        res
+    }
 }
 
 bitflags! {
@@ -48,7 +94,11 @@ bitflags! {
         // const OmitFromLog = 0x0002;
         // const OmitFromOutput = 0x0004;
         const KEEP_EVALUATING = 0x0008;
+        // 对未结束的行（如提示符）进行匹配
+        const PROMPT = 0x0100;
         // const IgnoreCase = 0x10;
+        // 回调额外接收执行上下文
+        const CONTEXT = 0x0040;
         // const RegularExpression = 0x0020;
         // const ExpandVariables = 0x0200;
         // const LowercaseWildcard = 0x0400;
@@ -62,11 +112,46 @@ bitflags! {
 pub struct TriggerExtra {
     pub match_lines: u8,
     pub flags: TriggerFlags,
+    // 匹配区域的样式条件，仅用于单行匹配
+    pub style: Option<Box<StyleCond>>,
 }
 
 impl Default for TriggerExtra {
     fn default() -> Self {
-        Self{match_lines: 1, flags: TriggerFlags::empty()}
+        Self{match_lines: 1, flags: TriggerFlags::empty(), style: None}
+    }
+}
+
+/// 触发器的样式条件，用于区分文字相同而颜色不同的行
+///
+/// 检查指定捕获区域（默认为整个匹配）中占比最多的样式，设置的各项均需满足
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleCond {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    // 必须包含的修饰，如粗体
+    pub modifier: Modifier,
+    pub capture: NumberOrString,
+}
+
+impl Default for StyleCond {
+    fn default() -> Self {
+        Self {
+            fg: None,
+            bg: None,
+            modifier: Modifier::empty(),
+            capture: NumberOrString::Number(0),
+        }
+    }
+}
+
+impl StyleCond {
+    pub fn matches(&self, is: &InlineStyle) -> bool {
+        let mut modifier = is.style.add_modifier;
+        modifier.remove(is.style.sub_modifier);
+        self.fg.map(|fg| is.style.fg == Some(fg)).unwrap_or(true)
+            && self.bg.map(|bg| is.style.bg == Some(bg)).unwrap_or(true)
+            && modifier.contains(self.modifier)
     }
 }
 
@@ -83,6 +168,14 @@ impl TriggerExtra {
         self.flags.contains(TriggerFlags::ONESHOT)
     }
 
+    pub fn prompt(&self) -> bool {
+        self.flags.contains(TriggerFlags::PROMPT)
+    }
+
+    pub fn context(&self) -> bool {
+        self.flags.contains(TriggerFlags::CONTEXT)
+    }
+
     pub fn set_one_shot(&mut self, one_shot: bool) {
         if one_shot {
             self.flags.insert(TriggerFlags::ONESHOT);
@@ -94,11 +187,132 @@ impl TriggerExtra {
 
 pub const NO_TRIGGERS: [Trigger; 0] = [];
 
+/// 触发器执行上下文，作为回调的第5个参数传入
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerContext {
+    // 匹配行（多行匹配时为最后一行）在回滚缓冲区中的行号
+    pub lineno: usize,
+    // 收到该行的时间，自UNIX纪元起的毫秒数
+    pub time: u64,
+    // 未结束的行（提示符）
+    pub prompt: bool,
+    // 包含控制序列的原始文本
+    pub raw: String,
+    pub text: String,
+    // 带标签的片段：字节偏移、标签、片段文本
+    pub labels: Vec<(usize, Label, String)>,
+}
+
+impl TriggerContext {
+    pub fn new(lineno: usize, time: u64, raw: &RawLine, line: &Line) -> Self {
+        let mut offset = 0;
+        let mut labels = vec![];
+        for span in line.spans() {
+            if span.label != Label::None {
+                labels.push((offset, span.label.clone(), span.content.trim_end().to_owned()));
+            }
+            offset += span.content.len();
+        }
+        Self {
+            lineno,
+            time,
+            prompt: !line.ended(),
+            raw: raw.content().to_owned(),
+            text: line.plain_text(),
+            labels,
+        }
+    }
+}
+
+impl<'lua> mlua::ToLua<'lua> for TriggerContext {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("lineno", self.lineno)?;
+        table.set("time", self.time)?;
+        table.set("source", if self.prompt { "prompt" } else { "world" })?;
+        table.set("raw", self.raw)?;
+        table.set("text", self.text)?;
+        let labels = lua.create_table()?;
+        for (i, (offset, label, text)) in self.labels.into_iter().enumerate() {
+            let t = lua.create_table()?;
+            t.set("offset", offset)?;
+            t.set("text", text)?;
+            match label {
+                Label::A { href, hint } => {
+                    t.set("kind", "a")?;
+                    t.set("href", href)?;
+                    t.set("hint", hint)?;
+                }
+                Label::S { href, hint } => {
+                    t.set("kind", "send")?;
+                    t.set("href", href)?;
+                    t.set("hint", hint)?;
+                }
+                Label::H(level) => {
+                    t.set("kind", "h")?;
+                    t.set("level", level)?;
+                }
+                Label::None => (),
+            }
+            labels.set(i + 1, t)?;
+        }
+        table.set("labels", labels)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// 触发器组的启用窗口，超过存活时间或匹配次数后自动禁用该组
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupWindow {
+    deadline: Option<Instant>,
+    // 剩余匹配次数
+    remaining: Option<u32>,
+}
+
+impl GroupWindow {
+    /// 到期时刻与匹配次数均未指定时返回None
+    pub fn new(deadline: Option<Instant>, matches: Option<u32>) -> Option<Self> {
+        if deadline.is_none() && matches.is_none() {
+            return None;
+        }
+        Some(Self {
+            deadline,
+            remaining: matches,
+        })
+    }
+
+    /// 记录一次匹配
+    pub fn consume(&mut self) {
+        if let Some(n) = self.remaining.as_mut() {
+            *n = n.saturating_sub(1);
+        }
+    }
+
+    pub fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|d| d <= now) || self.remaining == Some(0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
     use regex::Regex;
+    use std::time::Duration;
+
+    #[test]
+    fn test_group_window() {
+        let now = Instant::now();
+        assert_eq!(None, GroupWindow::new(None, None));
+        let mut w = GroupWindow::new(None, Some(2)).unwrap();
+        w.consume();
+        assert!(!w.expired(now));
+        w.consume();
+        assert!(w.expired(now));
+        let w = GroupWindow::new(Some(now + Duration::from_secs(5)), None).unwrap();
+        assert!(!w.expired(now + Duration::from_secs(4)));
+        assert!(w.expired(now + Duration::from_secs(5)));
+    }
 
     #[test]
     fn test_regex_match() {
@@ -117,6 +331,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_trigger_shared_regex() {
+        let build = |name: &str| {
+            Trigger::builder()
+                .name(name)
+                .pattern("^你被(.*)击中了").unwrap()
+                .build()
+        };
+        let (t1, t2) = (build("t1"), build("t2"));
+        assert!(std::sync::Arc::ptr_eq(&t1.re, &t2.re));
+        assert!(t2.is_match("你被一掌击中了"));
+    }
+
     #[test]
     fn test_trigger_match() {
         let input = "你一觉醒来觉得精力充沛。";
@@ -142,4 +369,53 @@ mod tests {
             .build();
         assert!(tr.is_match(input));
     }
+
+    #[test]
+    fn test_triggers_skip_disabled() {
+        let mut ct = CacheText::new(2, 4);
+        ct.push_line(&Line::fmt_raw("张三走了过来。"));
+        let mut triggers = Triggers::new();
+        let tr = Trigger::builder()
+            .name("t1")
+            .pattern("^张三").unwrap()
+            .group("default")
+            .enabled(false)
+            .build();
+        triggers.0.insert(tr.name.clone(), tr);
+        assert!(triggers.trigger_first(&ct).is_none());
+        assert!(triggers.trigger_all(&ct).is_empty());
+        triggers.0.get_mut("t1").unwrap().enabled = true;
+        assert_eq!("t1", triggers.trigger_first(&ct).unwrap().0.name);
+        assert_eq!(1, triggers.trigger_all(&ct).len());
+    }
+
+    #[test]
+    fn test_trigger_style_match() {
+        use crate::ui::style::Style;
+        let text = "张三说道：你好";
+        let styles = vec![
+            InlineStyle { offset: 0, style: Style::default().fg(Color::Yellow) },
+            InlineStyle { offset: "张三".len(), style: Style::default() },
+        ];
+        let tr = |cond: StyleCond| {
+            Trigger::builder()
+                .name("t")
+                .pattern("^(?P<who>\\S+)说道：").unwrap()
+                .group("default")
+                .extra(TriggerExtra { style: Some(Box::new(cond)), ..TriggerExtra::default() })
+                .build()
+        };
+        // 整个匹配中黄色占少数
+        assert!(!tr(StyleCond { fg: Some(Color::Yellow), ..StyleCond::default() }).style_match(text, &styles));
+        let who = StyleCond {
+            fg: Some(Color::Yellow),
+            capture: NumberOrString::new_string("who"),
+            ..StyleCond::default()
+        };
+        assert!(tr(who.clone()).style_match(text, &styles));
+        assert!(!tr(StyleCond { modifier: Modifier::BOLD, ..who.clone() }).style_match(text, &styles));
+        assert!(!tr(StyleCond { fg: Some(Color::Red), ..who }).style_match(text, &styles));
+        // 没有样式信息时不满足条件
+        assert!(!tr(StyleCond { capture: NumberOrString::new_number(1), ..StyleCond::default() }).style_match(text, &[]));
+    }
 }
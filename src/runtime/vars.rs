@@ -1,28 +1,476 @@
+use crate::error::{Error, Result};
+use crate::runtime::json::format_number;
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
 use std::hash::Hash;
-use std::sync::{Arc, RwLock};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// 脚本环境中的变量存储和查询
+///
+/// 世界变量可关联全局变量：读取时世界变量优先，不存在时读取全局变量；
+/// 写入仅作用于自身
 #[derive(Debug, Clone)]
-pub struct Variables(Arc<RwLock<HashMap<String, String>>>);
+pub struct Variables {
+    vars: Arc<RwLock<HashMap<String, String>>>,
+    global: Option<Arc<RwLock<HashMap<String, String>>>>,
+    // 修改日志，开启持久化后每次修改追加记录
+    journal: Arc<Mutex<Option<Journal>>>,
+    // 多个世界共用的变量文件，记录本进程修改过的变量，保存时与文件合并
+    changed: Option<Arc<Mutex<HashSet<String>>>>,
+}
 
 impl Variables {
     pub fn new() -> Self {
-        Self(Arc::new(RwLock::new(HashMap::new())))
+        Self {
+            vars: Arc::new(RwLock::new(HashMap::new())),
+            global: None,
+            journal: Arc::new(Mutex::new(None)),
+            changed: None,
+        }
+    }
+
+    /// 变量文件由多个世界共用，保存时仅覆盖本进程修改过的变量，保留其他世界的修改
+    pub fn shared(mut self) -> Self {
+        self.changed = Some(Arc::new(Mutex::new(HashSet::new())));
+        self
+    }
+
+    /// 关联全局变量，作为读取时的后备
+    pub fn with_global(mut self, global: &Variables) -> Self {
+        self.global = Some(global.vars.clone());
+        self
     }
 
     pub fn get<Q>(&self, name: &Q) -> Option<String>
     where
         String: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
-        let m = self.0.read().unwrap();
-        m.get(name).map(|s| s.to_owned())
+        let m = self.vars.read().unwrap();
+        if let Some(v) = m.get(name) {
+            return Some(v.to_owned());
+        }
+        let global = self.global.as_ref()?.read().unwrap();
+        global.get(name).map(|s| s.to_owned())
     }
 
     pub fn insert(&self, name: String, value: String) -> Option<String> {
-        let mut m = self.0.write().unwrap();
+        let mut m = self.vars.write().unwrap();
+        self.append(&name, &value);
         m.insert(name, value)
     }
+
+    /// 按名称选取变量，以*结尾的名称按前缀匹配，世界变量优先于全局变量
+    pub fn select(&self, patterns: &[String]) -> BTreeMap<String, String> {
+        let matches = |name: &str| {
+            patterns.iter().any(|p| match p.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == p,
+            })
+        };
+        let mut selected = BTreeMap::new();
+        // 与get、incr相同，先锁定世界变量再锁定全局变量
+        let m = self.vars.read().unwrap();
+        let global = self.global.as_ref().map(|g| g.read().unwrap());
+        for (name, value) in global.iter().flat_map(|g| g.iter()).chain(m.iter()) {
+            if matches(name) {
+                selected.insert(name.to_owned(), value.to_owned());
+            }
+        }
+        selected
+    }
+
+    /// 批量设置变量
+    pub fn insert_all(&self, vars: impl IntoIterator<Item = (String, String)>) {
+        let mut m = self.vars.write().unwrap();
+        for (name, value) in vars {
+            self.append(&name, &value);
+            m.insert(name, value);
+        }
+    }
+
+    /// 原子地将数值变量增加delta，返回新值
+    ///
+    /// 与get相同，变量不存在时以全局变量为初始值，均不存在时视为0，结果写入自身
+    pub fn incr(&self, name: &str, delta: f64) -> Result<f64> {
+        let mut m = self.vars.write().unwrap();
+        let curr = match m.get(name).cloned().or_else(|| {
+            let global = self.global.as_ref()?.read().unwrap();
+            global.get(name).cloned()
+        }) {
+            Some(s) => s.trim().parse::<f64>().map_err(|_| {
+                Error::RuntimeError(format!("variable {} is not a number: {}", name, s))
+            })?,
+            None => 0.0,
+        };
+        let value = curr + delta;
+        let text = format_number(value);
+        self.append(name, &text);
+        m.insert(name.to_owned(), text);
+        Ok(value)
+    }
+
+    // 写入修改日志，调用时需持有变量的写锁以保证记录顺序与修改一致
+    fn append(&self, name: &str, value: &str) {
+        if let Some(changed) = self.changed.as_ref() {
+            changed.lock().unwrap().insert(name.to_owned());
+        }
+        if let Some(journal) = self.journal.lock().unwrap().as_mut() {
+            if let Err(e) = journal.append(name, value) {
+                log::warn!("append variable journal {} error {}", journal.path.display(), e);
+            }
+        }
+    }
+
+    /// 从快照及修改日志恢复变量，并开启修改日志
+    ///
+    /// 恢复后立即压缩：将全部变量写入快照并清空日志
+    pub fn recover(&self, path: &Path, sync_interval: Duration) -> Result<()> {
+        self.load(path)?;
+        let journal_path = Journal::path_of(path);
+        let replayed = Journal::replay(&journal_path)?;
+        if !replayed.is_empty() {
+            log::info!("{} variable changes recovered from {}", replayed.len(), journal_path.display());
+            // 日志中的修改尚未写入快照，合并保存时需要保留
+            if let Some(changed) = self.changed.as_ref() {
+                changed.lock().unwrap().extend(replayed.iter().map(|(name, _)| name.to_owned()));
+            }
+            self.vars.write().unwrap().extend(replayed);
+        }
+        self.save(path)?;
+        let prev = self
+            .journal
+            .lock()
+            .unwrap()
+            .replace(Journal::create(journal_path, sync_interval)?);
+        if prev.is_none() {
+            self.spawn_sync(sync_interval);
+        }
+        Ok(())
+    }
+
+    // 后台定期同步修改日志，修改停止后最后的记录也能在一个间隔内落盘，变量释放后退出
+    fn spawn_sync(&self, interval: Duration) {
+        let weak = Arc::downgrade(&self.journal);
+        thread::spawn(move || loop {
+            thread::sleep(interval.max(Duration::from_millis(10)));
+            let shared = match weak.upgrade() {
+                Some(shared) => shared,
+                None => return,
+            };
+            let mut guard = shared.lock().unwrap();
+            if let Some(journal) = guard.as_mut().filter(|j| j.dirty) {
+                if let Err(e) = journal.sync() {
+                    log::warn!("sync variable journal {} error {}", journal.path.display(), e);
+                }
+            }
+        });
+    }
+
+    /// 修改日志的记录数及字节数，未开启时返回None
+    pub fn journal_stats(&self) -> Option<(usize, u64)> {
+        self.journal.lock().unwrap().as_ref().map(|j| (j.entries, j.bytes))
+    }
+
+    /// 从JSON文件加载变量，文件不存在时忽略
+    ///
+    /// 加载的变量与文件一致，不记录修改日志
+    pub fn load(&self, path: &Path) -> Result<()> {
+        let vars = Self::read_file(path)?;
+        self.vars.write().unwrap().extend(vars);
+        Ok(())
+    }
+
+    // 读取文件中的变量，文件不存在时为空
+    fn read_file(path: &Path) -> Result<HashMap<String, String>> {
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// 保存变量至JSON文件，不包括关联的全局变量
+    ///
+    /// 共用的变量文件先读取其他世界保存的内容，以本进程修改过的变量覆盖后写入，
+    /// 并将其他世界的修改更新到内存中
+    pub fn save(&self, path: &Path) -> Result<()> {
+        // 持有写锁直至日志清空，避免期间的修改既不在快照中也不在日志中
+        let mut m = self.vars.write().unwrap();
+        if let Some(changed) = self.changed.as_ref() {
+            let mut changed = changed.lock().unwrap();
+            let mut merged = Self::read_file(path)?;
+            for name in changed.iter() {
+                if let Some(value) = m.get(name) {
+                    merged.insert(name.to_owned(), value.to_owned());
+                }
+            }
+            *m = merged;
+            changed.clear();
+        }
+        let sorted: BTreeMap<_, _> = m.iter().collect();
+        let json = serde_json::to_string_pretty(&sorted)?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        // 先写入临时文件再替换，避免写入中断导致文件损坏
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // 快照已包含全部修改，清空日志
+        if let Some(journal) = self.journal.lock().unwrap().as_mut() {
+            journal.truncate()?;
+        }
+        Ok(())
+    }
+}
+
+/// 以分组为命名空间的变量
+///
+/// 变量需先声明，分组禁用或删除时全部清除，不持久化，
+/// 避免旧任务脚本的状态残留到新的运行中
+#[derive(Debug, Clone, Default)]
+pub struct GroupVars(Arc<RwLock<HashMap<String, HashMap<String, String>>>>);
+
+impl GroupVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 声明变量，已声明时保留当前值，返回当前值
+    pub fn declare(&self, group: &str, key: &str, default: String) -> String {
+        let mut m = self.0.write().unwrap();
+        m.entry(group.to_owned())
+            .or_default()
+            .entry(key.to_owned())
+            .or_insert(default)
+            .to_owned()
+    }
+
+    pub fn get(&self, group: &str, key: &str) -> Option<String> {
+        let m = self.0.read().unwrap();
+        m.get(group)?.get(key).map(|v| v.to_owned())
+    }
+
+    /// 设置已声明的变量
+    pub fn set(&self, group: &str, key: &str, value: String) -> Result<()> {
+        let mut m = self.0.write().unwrap();
+        match m.get_mut(group).and_then(|vars| vars.get_mut(key)) {
+            Some(v) => {
+                *v = value;
+                Ok(())
+            }
+            None => Err(Error::RuntimeError(format!(
+                "group variable {}.{} not declared",
+                group, key
+            ))),
+        }
+    }
+
+    /// 清除分组的全部变量，返回清除的变量数
+    pub fn remove_group(&self, group: &str) -> usize {
+        let mut m = self.0.write().unwrap();
+        m.remove(group).map(|vars| vars.len()).unwrap_or(0)
+    }
+}
+
+/// 变量修改日志，每行为一条由变量名及值组成的JSON数组
+///
+/// 每条记录立即写入文件，进程崩溃时不会丢失；距上次同步超过间隔时在追加时同步至磁盘，
+/// 其余由后台线程每个间隔同步一次，断电时最多丢失一个间隔内的修改
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: File,
+    sync_interval: Duration,
+    last_sync: Instant,
+    // 存在尚未同步至磁盘的记录
+    dirty: bool,
+    entries: usize,
+    bytes: u64,
+}
+
+impl Journal {
+    fn path_of(snapshot: &Path) -> PathBuf {
+        snapshot.with_extension("journal")
+    }
+
+    fn create(path: PathBuf, sync_interval: Duration) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(0)?;
+        Ok(Self {
+            path,
+            file,
+            sync_interval,
+            last_sync: Instant::now(),
+            dirty: false,
+            entries: 0,
+            bytes: 0,
+        })
+    }
+
+    // 按顺序读取日志中的修改，忽略崩溃时未写完的行
+    fn replay(path: &Path) -> Result<Vec<(String, String)>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut changes = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<(String, String)>(&line) {
+                Ok(change) => changes.push(change),
+                Err(e) => log::warn!("skip broken variable journal entry {:?}: {}", line, e),
+            }
+        }
+        Ok(changes)
+    }
+
+    fn append(&mut self, name: &str, value: &str) -> Result<()> {
+        let mut line = serde_json::to_string(&(name, value))?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.entries += 1;
+        self.bytes += line.len() as u64;
+        self.dirty = true;
+        if self.last_sync.elapsed() >= self.sync_interval {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.last_sync = Instant::now();
+        self.dirty = false;
+        self.entries = 0;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_vars_incr() {
+        let vars = Variables::new();
+        assert_eq!(1.0, vars.incr("kills", 1.0).unwrap());
+        assert_eq!(3.5, vars.incr("kills", 2.5).unwrap());
+        assert_eq!(Some("3.5".to_owned()), vars.get("kills"));
+        vars.insert("name".to_owned(), "张三".to_owned());
+        assert!(vars.incr("name", 1.0).is_err());
+    }
+
+    #[test]
+    fn test_group_vars() {
+        let vars = GroupVars::new();
+        assert!(vars.set("quest", "npc", "张三".to_owned()).is_err());
+        assert_eq!("0", vars.declare("quest", "step", "0".to_owned()));
+        vars.set("quest", "step", "2".to_owned()).unwrap();
+        // 重复声明保留当前值
+        assert_eq!("2", vars.declare("quest", "step", "0".to_owned()));
+        vars.declare("fight", "step", "1".to_owned());
+        assert_eq!(1, vars.remove_group("quest"));
+        assert_eq!(None, vars.get("quest", "step"));
+        assert_eq!(Some("1".to_owned()), vars.get("fight", "step"));
+    }
+
+    #[test]
+    fn test_vars_global_scope() {
+        let global = Variables::new();
+        let vars = Variables::new().with_global(&global);
+        global.insert("master".to_owned(), "岳不群".to_owned());
+        global.insert("kills".to_owned(), "1".to_owned());
+        vars.insert("kills".to_owned(), "5".to_owned());
+        assert_eq!(Some("岳不群".to_owned()), vars.get("master"));
+        // 世界变量优先
+        assert_eq!(Some("5".to_owned()), vars.get("kills"));
+        assert_eq!(Some("1".to_owned()), global.get("kills"));
+        // 自增以全局变量为初始值，结果写入世界变量
+        global.insert("deaths".to_owned(), "2".to_owned());
+        assert_eq!(3.0, vars.incr("deaths", 1.0).unwrap());
+        assert_eq!(Some("2".to_owned()), global.get("deaths"));
+
+        let tmp = TempDir::new("vars");
+        let path = tmp.join("vars.json");
+        vars.save(&path).unwrap();
+        let loaded = Variables::new();
+        loaded.load(&path).unwrap();
+        assert_eq!(Some("5".to_owned()), loaded.get("kills"));
+        assert_eq!(None, loaded.get("master"));
+    }
+
+    #[test]
+    fn test_vars_shared_save() {
+        let tmp = TempDir::new("shared");
+        let path = tmp.join("shared.json");
+        let a = Variables::new().shared();
+        let b = Variables::new().shared();
+        a.load(&path).unwrap();
+        b.load(&path).unwrap();
+        a.insert("master".to_owned(), "岳不群".to_owned());
+        a.insert("kills".to_owned(), "1".to_owned());
+        b.insert("kills".to_owned(), "3".to_owned());
+        a.save(&path).unwrap();
+        // 后保存的世界保留其他世界的修改，仅覆盖自身修改过的变量
+        b.save(&path).unwrap();
+        let saved = Variables::new();
+        saved.load(&path).unwrap();
+        assert_eq!(Some("岳不群".to_owned()), saved.get("master"));
+        assert_eq!(Some("3".to_owned()), saved.get("kills"));
+        assert_eq!(Some("岳不群".to_owned()), b.get("master"));
+        // 未再修改的变量不覆盖其他世界之后保存的值
+        a.insert("master".to_owned(), "风清扬".to_owned());
+        a.save(&path).unwrap();
+        b.save(&path).unwrap();
+        saved.load(&path).unwrap();
+        assert_eq!(Some("风清扬".to_owned()), saved.get("master"));
+    }
+
+    #[test]
+    fn test_vars_journal_recover() {
+        let dir = TempDir::new("journal");
+        let path = dir.join("vars.json");
+        let vars = Variables::new();
+        vars.recover(&path, Duration::from_secs(60)).unwrap();
+        vars.insert("master".to_owned(), "岳不群".to_owned());
+        vars.incr("kills", 2.0).unwrap();
+        let journal = dir.join("vars.journal");
+        let size = fs::metadata(&journal).unwrap().len();
+        assert_eq!(Some((2, size)), vars.journal_stats());
+        // 模拟崩溃：未保存快照，日志末尾有未写完的行
+        drop(vars);
+        let mut content = fs::read_to_string(&journal).unwrap();
+        content.push_str("[\"kills\",\"");
+        fs::write(&journal, content).unwrap();
+
+        let vars = Variables::new();
+        vars.recover(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(Some("岳不群".to_owned()), vars.get("master"));
+        assert_eq!(Some("2".to_owned()), vars.get("kills"));
+        // 恢复后压缩至快照
+        assert_eq!(Some((0, 0)), vars.journal_stats());
+        assert_eq!(0, fs::metadata(&journal).unwrap().len());
+        let snapshot = Variables::new();
+        snapshot.load(&path).unwrap();
+        assert_eq!(Some("2".to_owned()), snapshot.get("kills"));
+    }
 }
@@ -0,0 +1,335 @@
+use crate::conf::{Log, LogRotate};
+use crate::error::{Error, Result};
+use crate::logview::{datetime, strip_ansi};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 世界文本日志
+///
+/// 按配置去除ANSI序列、为每行添加时间，并按大小或日期轮转，
+/// 轮转后的文件名在主文件名后以“.”附加时间，如“server.20210305-210315.log”，
+/// 按日轮转时为该文件开始记录的时间，按大小轮转时为轮转的时间
+#[derive(Debug)]
+pub struct WorldLog {
+    path: PathBuf,
+    opts: Log,
+    file: File,
+    size: u64,
+    // 当前文件开始记录的时间及日期，用于按日轮转
+    opened: u64,
+    day: String,
+    // 下一个字符位于行首
+    line_start: bool,
+    // 跨越两次写入的未完整控制序列
+    pending: String,
+}
+
+impl WorldLog {
+    /// 创建日志文件，按会话记录时文件名附加启动时间
+    pub fn create(path: impl Into<PathBuf>, opts: Log) -> Result<Self> {
+        let mut path = path.into();
+        let now = now_millis();
+        if opts.session {
+            path = stamped(&path, now);
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            opts,
+            file,
+            size: 0,
+            opened: now,
+            day: day(now),
+            line_start: true,
+            pending: String::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 写入解码后的世界文本，可包含多行及未结束的行
+    pub fn write_text(&mut self, text: &str) -> Result<()> {
+        let now = now_millis();
+        self.rotate_if_needed(now)?;
+        let text = if self.opts.plain {
+            let mut buf = std::mem::take(&mut self.pending);
+            buf.push_str(text);
+            let cut = incomplete_escape(&buf);
+            self.pending = buf.split_off(cut);
+            strip_ansi(&buf.replace('\r', ""))
+        } else {
+            text.to_owned()
+        };
+        let out = if self.opts.timestamp {
+            let prefix = format!("[{}] ", datetime(now));
+            let mut out = String::with_capacity(text.len());
+            for piece in text.split_inclusive('\n') {
+                if self.line_start {
+                    out.push_str(&prefix);
+                }
+                out.push_str(piece);
+                self.line_start = piece.ends_with('\n');
+            }
+            out
+        } else {
+            text
+        };
+        self.file.write_all(out.as_bytes())?;
+        self.size += out.len() as u64;
+        Ok(())
+    }
+
+    fn rotate_if_needed(&mut self, now: u64) -> Result<()> {
+        let rotate = match self.opts.rotate {
+            LogRotate::None => false,
+            LogRotate::Size => self.opts.max_size > 0 && self.size >= self.opts.max_size,
+            LogRotate::Daily => day(now) != self.day,
+        };
+        if !rotate {
+            return Ok(());
+        }
+        self.file.flush()?;
+        // 按日轮转的文件以其记录的日期命名
+        let stamp = if self.opts.rotate == LogRotate::Daily { self.opened } else { now };
+        let mut n = 1;
+        let mut target = rotated(&self.path, stamp, n);
+        while target.exists() {
+            n += 1;
+            target = rotated(&self.path, stamp, n);
+        }
+        fs::rename(&self.path, &target)?;
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        self.opened = now;
+        self.day = day(now);
+        if self.opts.keep > 0 {
+            prune(&self.path, self.opts.keep)?;
+        }
+        Ok(())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// 本地日期，如“2021-03-05”
+fn day(millis: u64) -> String {
+    datetime(millis)[..10].to_owned()
+}
+
+// 紧凑的本地时间，如“20210305-210315”
+fn compact(millis: u64) -> String {
+    datetime(millis)
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | ':' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|s| format!(".{}", s.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, ext)
+}
+
+// 按会话记录时文件名附加启动时间，如“server.log”变为“server-20210305-210315.log”
+fn stamped(path: &Path, millis: u64) -> PathBuf {
+    let (stem, ext) = split_name(path);
+    path.with_file_name(format!("{}-{}{}", stem, compact(millis), ext))
+}
+
+// 轮转后的文件名，以“.”附加时间以区别于按会话记录的文件，同一秒内多次轮转时附加序号，
+// 如“server.log”变为“server.20210305-210315.log”及“server.20210305-210315-2.log”
+fn rotated(path: &Path, millis: u64, n: usize) -> PathBuf {
+    let (stem, ext) = split_name(path);
+    let seq = if n > 1 { format!("-{}", n) } else { String::new() };
+    path.with_file_name(format!("{}.{}{}{}", stem, compact(millis), seq, ext))
+}
+
+/// 检查日志文件名，仅允许日志目录下的相对路径，禁止..
+pub fn validate_file(file: &str) -> Result<()> {
+    let valid = !file.is_empty()
+        && Path::new(file)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !valid {
+        return Err(Error::RuntimeError(format!("invalid log file {}", file)));
+    }
+    Ok(())
+}
+
+// 删除最旧的轮转文件，仅保留keep个，按会话记录的文件不受影响
+fn prune(path: &Path, keep: usize) -> Result<()> {
+    let (stem, ext) = split_name(path);
+    let prefix = format!("{}.", stem);
+    let dir = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let mut rotated = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let stamp = match name
+            .strip_prefix(&prefix)
+            .and_then(|s| s.strip_suffix(ext.as_str()))
+        {
+            Some(stamp) => stamp,
+            None => continue,
+        };
+        // 仅匹配轮转时间开头的文件名，不误删其他同名前缀的文件
+        let bytes = stamp.as_bytes();
+        let is_stamp = bytes.len() >= 15
+            && bytes[8] == b'-'
+            && bytes[..15]
+                .iter()
+                .enumerate()
+                .all(|(i, b)| i == 8 || b.is_ascii_digit());
+        if is_stamp {
+            let seq = stamp[15..]
+                .strip_prefix('-')
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or(1);
+            rotated.push((stamp[..15].to_owned(), seq, name));
+        }
+    }
+    if rotated.len() <= keep {
+        return Ok(());
+    }
+    rotated.sort();
+    for (_, _, name) in &rotated[..rotated.len() - keep] {
+        fs::remove_file(dir.join(name))?;
+    }
+    Ok(())
+}
+
+// 末尾未完整的控制序列的起始位置，不存在时为文本长度
+fn incomplete_escape(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let esc = match bytes.iter().rposition(|b| *b == 0x1b) {
+        Some(esc) => esc,
+        None => return bytes.len(),
+    };
+    match bytes.get(esc + 1) {
+        None => esc,
+        Some(b'[') if !bytes[esc + 2..].iter().any(|b| (0x40..=0x7e).contains(b)) => esc,
+        Some(_) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_world_log_plain_timestamp() {
+        let dir = TempDir::new("worldlog-plain");
+        let opts = Log {
+            plain: true,
+            timestamp: true,
+            ..Log::default()
+        };
+        let mut log = WorldLog::create(dir.join("server.log"), opts).unwrap();
+        // 控制序列跨越两次写入
+        log.write_text("\x1b[1;3").unwrap();
+        log.write_text("1m张三\x1b[m走了\r\n你").unwrap();
+        log.write_text("好\n").unwrap();
+        log.write_text("look\n").unwrap();
+        let text = fs::read_to_string(log.path()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with('[') && lines[0].ends_with("] 张三走了"));
+        assert!(lines[1].ends_with("] 你好"));
+        assert!(lines[2].ends_with("] look"));
+    }
+
+    #[test]
+    fn test_world_log_rotate_by_size() {
+        let dir = TempDir::new("worldlog-size");
+        let opts = Log {
+            rotate: LogRotate::Size,
+            max_size: 4,
+            keep: 2,
+            ..Log::default()
+        };
+        let mut log = WorldLog::create(dir.join("server.log"), opts).unwrap();
+        for text in &["aaaa", "bbbb", "cccc", "dddd"] {
+            log.write_text(text).unwrap();
+        }
+        assert_eq!("dddd", fs::read_to_string(dir.join("server.log")).unwrap());
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        // 仅保留最近两个轮转文件
+        assert_eq!(3, names.len());
+        let rotated: Vec<String> = names[..2]
+            .iter()
+            .map(|n| fs::read_to_string(dir.join(n)).unwrap())
+            .collect();
+        assert!(rotated.contains(&"bbbb".to_owned()) && rotated.contains(&"cccc".to_owned()));
+    }
+
+    #[test]
+    fn test_world_log_rotate_daily() {
+        let dir = TempDir::new("worldlog-daily");
+        // 按会话记录的文件与轮转文件同名前缀，清理时不受影响
+        let session = dir.join("server-20210305-210315.log");
+        fs::write(&session, "session").unwrap();
+        let opts = Log {
+            rotate: LogRotate::Daily,
+            keep: 1,
+            ..Log::default()
+        };
+        let mut log = WorldLog::create(dir.join("server.log"), opts).unwrap();
+        log.write_text("yesterday\n").unwrap();
+        // 模拟前一天开始记录的文件
+        let opened = log.opened - 86_400_000;
+        log.opened = opened;
+        log.day = day(opened);
+        log.write_text("today\n").unwrap();
+        assert_eq!("today\n", fs::read_to_string(dir.join("server.log")).unwrap());
+        let rotated = rotated(&dir.join("server.log"), opened, 1);
+        assert_eq!("yesterday\n", fs::read_to_string(&rotated).unwrap());
+        assert!(session.exists());
+    }
+
+    #[test]
+    fn test_validate_file() {
+        assert!(validate_file("server.log").is_ok());
+        assert!(validate_file("chat/today.log").is_ok());
+        assert!(validate_file("").is_err());
+        assert!(validate_file("/etc/passwd").is_err());
+        assert!(validate_file("../server.log").is_err());
+        assert!(validate_file("chat/../../x.log").is_err());
+    }
+
+    #[test]
+    fn test_incomplete_escape() {
+        assert_eq!(3, incomplete_escape("abc"));
+        assert_eq!(3, incomplete_escape("abc\x1b"));
+        assert_eq!(1, incomplete_escape("a\x1b[1;3"));
+        assert_eq!(7, incomplete_escape("a\x1b[1;3m"));
+    }
+}
@@ -0,0 +1,357 @@
+// zMUD/CMUD文本导出格式（#TRIGGER及#ALIAS）的转换
+//
+// 模式转换为正则，命令中的%1..%9与本程序的捕获替换一致，保持不变；
+// 无法转换的写法按所在行号给出警告
+
+use crate::i18n;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    Trigger,
+    Alias,
+}
+
+/// 转换后的触发器或别名
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedRule {
+    pub kind: RuleKind,
+    // 别名为原别名名称，触发器为空
+    pub name: String,
+    pub pattern: String,
+    pub send: String,
+    // 原类名，未指定时为空
+    pub group: String,
+    // 定义所在的行号，从1开始
+    pub lineno: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Import {
+    pub rules: Vec<ImportedRule>,
+    // 行号及警告内容
+    pub warnings: Vec<(usize, String)>,
+}
+
+impl Import {
+    fn warn(&mut self, lineno: usize, msg: impl Into<String>) {
+        self.warnings.push((lineno, msg.into()));
+    }
+}
+
+/// 转换导出文本，大括号跨行的定义合并处理，行号为定义的起始行
+pub fn convert(text: &str) -> Import {
+    let mut import = Import::default();
+    let mut stmt = String::new();
+    let mut start = 0;
+    for (i, line) in text.lines().enumerate() {
+        if stmt.is_empty() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            start = i + 1;
+        } else {
+            stmt.push('\n');
+        }
+        stmt.push_str(line);
+        if brace_depth(&stmt) > 0 {
+            continue;
+        }
+        convert_stmt(&std::mem::take(&mut stmt), start, &mut import);
+    }
+    if !stmt.is_empty() {
+        import.warn(start, i18n::tr("zmud.unbalanced_braces"));
+    }
+    import
+}
+
+// 未闭合的大括号层数，~为转义符
+fn brace_depth(s: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => {
+                chars.next();
+            }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    depth
+}
+
+fn convert_stmt(stmt: &str, lineno: usize, import: &mut Import) {
+    let stmt = stmt.trim();
+    let (cmd, rest) = match stmt.split_once(|c: char| c.is_whitespace() || c == '{') {
+        Some((cmd, _)) => (cmd, stmt[cmd.len()..].trim_start()),
+        None => (stmt, ""),
+    };
+    let kind = match cmd.to_ascii_uppercase().as_str() {
+        "#TR" | "#TRI" | "#TRIG" | "#TRIGGER" | "#ACTION" => RuleKind::Trigger,
+        "#AL" | "#ALI" | "#ALIAS" => RuleKind::Alias,
+        _ => {
+            import.warn(lineno, i18n::trf("zmud.unsupported_command", &[&cmd]));
+            return;
+        }
+    };
+    let args = split_args(rest);
+    if args.len() < 2 {
+        import.warn(lineno, i18n::trf("zmud.missing_args", &[&cmd]));
+        return;
+    }
+    if args.len() > 3 {
+        import.warn(lineno, i18n::trf("zmud.options_ignored", &[&args[3..].join(" ")]));
+    }
+    let group = args.get(2).cloned().unwrap_or_default();
+    let send = match convert_send(&args[1]) {
+        Ok(send) => send,
+        Err(msg) => {
+            import.warn(lineno, msg);
+            return;
+        }
+    };
+    let (name, pattern) = match kind {
+        RuleKind::Trigger => match convert_pattern(&args[0]) {
+            Ok(pattern) => (String::new(), pattern),
+            Err(msg) => {
+                import.warn(lineno, msg);
+                return;
+            }
+        },
+        RuleKind::Alias => {
+            let name = args[0].trim().to_owned();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                import.warn(lineno, i18n::trf("zmud.invalid_alias", &[&name]));
+                return;
+            }
+            let pattern = alias_pattern(&name, max_placeholder(&send));
+            (name, pattern)
+        }
+    };
+    import.rules.push(ImportedRule {
+        kind,
+        name,
+        pattern,
+        send,
+        group,
+        lineno,
+    });
+}
+
+// 拆分参数：{...}（可嵌套）、"..."或不含空白的单词
+fn split_args(s: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        match c {
+            '{' => {
+                chars.next();
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '~' => {
+                            arg.push(c);
+                            arg.extend(chars.next());
+                            continue;
+                        }
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => (),
+                    }
+                    arg.push(c);
+                }
+            }
+            '"' => {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    arg.push(c);
+                }
+            }
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    arg.push(c);
+                    chars.next();
+                }
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// 将zMUD模式转换为正则
+///
+/// 支持*、%d、%w、%s、%x、%n、%a、(...)捕获、{a|b}选择、[...]字符集、^及$锚点和~转义
+pub fn convert_pattern(pattern: &str) -> Result<String, String> {
+    let mut re = String::new();
+    let mut chars = pattern.chars().peekable();
+    let mut alternation = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => match chars.next() {
+                Some(c) => re.push_str(&regex::escape(&c.to_string())),
+                None => return Err(i18n::trf("zmud.dangling", &[&c])),
+            },
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '%' => {
+                let class = match chars.next() {
+                    Some('d') => r"\d+",
+                    Some('w') => r"[A-Za-z]+",
+                    Some('a') => r"\w+",
+                    Some('s') => r"\s+",
+                    Some('x') => r"\S+",
+                    Some('n') => r"[+-]?\d+",
+                    Some(other) => return Err(i18n::trf("zmud.unsupported_wildcard", &[&other])),
+                    None => return Err(i18n::trf("zmud.dangling", &[&c])),
+                };
+                re.push_str(class);
+            }
+            '@' => return Err(i18n::tr("zmud.pattern_variable")),
+            '(' | ')' => re.push(c),
+            '{' => {
+                alternation += 1;
+                re.push_str("(?:");
+            }
+            '}' if alternation > 0 => {
+                alternation -= 1;
+                re.push(')');
+            }
+            '|' if alternation > 0 => re.push('|'),
+            '[' => {
+                re.push('[');
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            '^' if re.is_empty() => re.push('^'),
+            '$' if chars.peek().is_none() => re.push('$'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if alternation > 0 {
+        return Err(i18n::tr("zmud.pattern_braces"));
+    }
+    regex::Regex::new(&re).map_err(|e| i18n::trf("zmud.invalid_pattern", &[&e]))?;
+    Ok(re)
+}
+
+/// 检查命令，zscript的函数、变量及内置命令无法转换，多条命令按行分隔，与命令分隔符的配置无关
+fn convert_send(send: &str) -> Result<String, String> {
+    let cmds: Vec<&str> = send.split(['\n', ';']).map(str::trim).filter(|c| !c.is_empty()).collect();
+    if let Some(cmd) = cmds.iter().find(|c| c.starts_with('#')) {
+        return Err(i18n::trf("zmud.unsupported_zscript", &[cmd]));
+    }
+    let send = cmds.join("\n");
+    if send.contains('@') {
+        return Err(i18n::tr("zmud.send_variable"));
+    }
+    let mut chars = send.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.peek() {
+                Some(d) if d.is_ascii_digit() => (),
+                Some('%') => {
+                    chars.next();
+                }
+                Some(_) => {
+                    let func: String = chars.by_ref().take_while(|c| c.is_alphanumeric()).collect();
+                    return Err(i18n::trf("zmud.unsupported_function", &[&func]));
+                }
+                None => (),
+            }
+        }
+    }
+    Ok(send)
+}
+
+// 命令中引用的最大参数序号
+fn max_placeholder(send: &str) -> u8 {
+    let bytes = send.as_bytes();
+    bytes
+        .windows(2)
+        .filter(|w| w[0] == b'%' && w[1].is_ascii_digit())
+        .map(|w| w[1] - b'0')
+        .max()
+        .unwrap_or(0)
+}
+
+/// 别名名称后按空白分隔参数，最后一个参数包含剩余的全部文本
+///
+/// 命令中未引用参数时，仍允许名称后跟随任意参数
+fn alias_pattern(name: &str, args: u8) -> String {
+    let args = args.max(1);
+    let mut pattern = format!("^{}", regex::escape(name));
+    for i in 1..=args {
+        if i == args {
+            pattern.push_str(r"(?:\s+(.*))?");
+        } else {
+            pattern.push_str(r"(?:\s+(\S+))?");
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zmud_convert() {
+        let text = r#"#TRIGGER {^(%w) tells you: (*)$} {reply %1 got it} "chat"
+#ALIAS gg {get all;give all to %1}
+#AL kk {kill %1;
+  wield sword}
+#ALIAS rr {rest}
+#TR {You are {hungry|thirsty}} {#if (@food) {eat}}
+#VAR food 1
+#TRIG {~[%d~] exp} {score} {} {nocr}
+"#;
+        let import = convert(text);
+        assert_eq!(5, import.rules.len());
+        let tr = &import.rules[0];
+        assert_eq!(RuleKind::Trigger, tr.kind);
+        assert_eq!(r"^([A-Za-z]+) tells you: (.*)$", tr.pattern);
+        assert_eq!("reply %1 got it", tr.send);
+        assert_eq!("chat", tr.group);
+        let alias = &import.rules[1];
+        assert_eq!("gg", alias.name);
+        assert_eq!(r"^gg(?:\s+(.*))?$", alias.pattern);
+        // 跨行定义使用起始行号
+        let alias = &import.rules[2];
+        assert_eq!(3, alias.lineno);
+        assert_eq!("kill %1\nwield sword", alias.send);
+        // 未引用参数的别名同样接受参数
+        assert_eq!(r"^rr(?:\s+(.*))?$", import.rules[3].pattern);
+        assert_eq!(r"\[\d+\] exp", import.rules[4].pattern);
+        assert_eq!(8, import.rules[4].lineno);
+        let lines: Vec<usize> = import.warnings.iter().map(|(n, _)| *n).collect();
+        assert_eq!(vec![6, 7, 8], lines);
+        assert!(import.warnings[0].1.contains("#if"));
+    }
+}
@@ -1,15 +1,67 @@
-use crate::error::Result;
+use crate::conf::{self, Negotiate};
+use crate::error::{Error, Result};
+use crate::probe::{Probe, ProbeReport};
+use crate::proto::gmcp;
+use crate::proto::msdp::{self, OPT_MSDP};
+use bitflags::bitflags;
+use flate2::{Decompress, FlushDecompress, Status};
 use libtelnet_rs::events::{TelnetEvents, TelnetIAC, TelnetNegotiation, TelnetSubnegotiation};
-use libtelnet_rs::compatibility::CompatibilityTable;
+use libtelnet_rs::compatibility::{CompatibilityEntry, CompatibilityTable};
 use libtelnet_rs::telnet::op_command as Op;
+use libtelnet_rs::telnet::op_option as Opt;
 use libtelnet_rs::Parser;
 use std::collections::VecDeque;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+const OPT_MXP: u8 = 91;
+// 交给事件循环的单个文本帧的最大字节数
+const MAX_FRAME: usize = 4096;
+
+bitflags! {
+    /// 已协商启用的协议
+    pub struct Protocols: u8 {
+        const MXP = 0x01;
+        const MCCP = 0x02;
+        const GMCP = 0x04;
+        const NAWS = 0x08;
+        const ECHO = 0x10;
+        const MSDP = 0x20;
+    }
+}
+
+// 协议、telnet选项及名称，其中NAWS由客户端提供，其余由服务器提供
+const PROTOCOLS: [(Protocols, u8, &str); 6] = [
+    (Protocols::MXP, OPT_MXP, "mxp"),
+    (Protocols::MCCP, Opt::MCCP2, "mccp"),
+    (Protocols::GMCP, Opt::GMCP, "gmcp"),
+    (Protocols::MSDP, OPT_MSDP, "msdp"),
+    (Protocols::NAWS, Opt::NAWS, "naws"),
+    (Protocols::ECHO, Opt::ECHO, "echo"),
+];
+
+impl Protocols {
+    /// 各协议的名称及是否启用
+    pub fn list(self) -> Vec<(&'static str, bool)> {
+        PROTOCOLS
+            .iter()
+            .map(|(p, _, name)| (*name, self.contains(*p)))
+            .collect()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum TelnetEvent {
     Text(Vec<u8>),
     DataToSend(Vec<u8>),
+    // 首次收到文本时报告协商结果，之后在变化时报告
+    Protocols(Protocols),
+    // GMCP子协商的消息内容
+    Gmcp(Vec<u8>),
+    // MSDP子协商的变量数据
+    Msdp(Vec<u8>),
+    // 连接初期的测量结果
+    Probe(ProbeReport),
     Empty,
     Disconnected,
 }
@@ -19,25 +71,61 @@ pub struct Telnet<R> {
     recv_buf: Vec<u8>,
     parser: Parser,
     buf: VecDeque<TelnetEvent>,
+    // MCCP压缩开始后的解压状态
+    inflate: Option<Decompress>,
+    protocols: Protocols,
+    reported: bool,
+    naws: (u16, u16),
+    gmcp_supports: Vec<String>,
+    msdp_reports: Vec<String>,
+    probe: Option<Probe>,
 }
 
 impl<R> Telnet<R>
 where
     R: Read,
 {
-    pub fn new(reader: R, buf_size: usize) -> Self {
+    pub fn new(reader: R, buf_size: usize, config: &conf::Protocol) -> Self {
         let mut compat_table = CompatibilityTable::new();
-        // compat_table.support_local(86);
-        // compat_table.support_remote(86);
-        // compat_table.support_local(91);
-        // compat_table.support_remote(91);
+        let mut buf = VecDeque::new();
+        for &(p, opt, _) in PROTOCOLS.iter() {
+            let mode = negotiate_mode(config, p);
+            if mode == Negotiate::Off {
+                continue;
+            }
+            if p == Protocols::NAWS {
+                compat_table.support_local(opt);
+            } else if p == Protocols::MCCP || p == Protocols::GMCP || p == Protocols::MSDP {
+                // 解析器仅在本地启用时处理子协商，对带子协商的服务器选项预先置位本地状态
+                compat_table.set_option(opt, CompatibilityEntry::new(true, true, true, false));
+            } else {
+                compat_table.support_remote(opt);
+            }
+            if mode == Negotiate::On {
+                let cmd = if p == Protocols::NAWS { Op::WILL } else { Op::DO };
+                buf.push_back(TelnetEvent::DataToSend(vec![Op::IAC, cmd, opt]));
+            }
+        }
+        let probe = if config.probe_secs > 0 {
+            let mut probe = Probe::new(Duration::from_secs(config.probe_secs));
+            buf.push_back(TelnetEvent::DataToSend(probe.ping(Instant::now())));
+            Some(probe)
+        } else {
+            None
+        };
         let telnet = Parser::with_support_and_capacity(4096, compat_table);
-        let buf = VecDeque::new();
         Self {
             reader,
             recv_buf: vec![0u8; buf_size],
             parser: telnet,
             buf,
+            inflate: None,
+            protocols: Protocols::empty(),
+            reported: false,
+            naws: (config.naws_width, config.naws_height),
+            gmcp_supports: config.gmcp_supports.clone(),
+            msdp_reports: config.msdp_reports.clone(),
+            probe,
         }
     }
 
@@ -51,7 +139,72 @@ where
         if n == 0 {
             return Ok(TelnetEvent::Disconnected);
         }
-        let events = self.parser.receive(&self.recv_buf[..n]);
+        let data = self.recv_buf[..n].to_vec();
+        if let Some(probe) = self.probe.as_mut() {
+            probe.record_read(n);
+        }
+        self.feed(data)?;
+        self.report();
+        self.finish_probe();
+        if let Some(msg) = self.buf.pop_front() {
+            return Ok(msg);
+        }
+        Ok(TelnetEvent::Empty)
+    }
+
+    // 解析收到的数据，MCCP压缩开始后先解压
+    fn feed(&mut self, mut data: Vec<u8>) -> Result<()> {
+        loop {
+            let (plain, rest) = match self.inflate.as_mut() {
+                Some(inflate) => inflate_mccp(inflate, &data)?,
+                None => (data, None),
+            };
+            if rest.is_some() {
+                log::debug!("MCCP compression ended");
+                self.inflate = None;
+            }
+            let plain = self.strip_pongs(plain);
+            let events = self.parser.receive(&plain);
+            match (self.handle(events), rest) {
+                (Some(compressed), _) => {
+                    log::debug!("MCCP compression started");
+                    self.inflate = Some(Decompress::new(true));
+                    data = compressed;
+                }
+                (None, Some(rest)) if !rest.is_empty() => data = rest,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // 测量期间移除探测应答，收到应答后继续探测
+    fn strip_pongs(&mut self, plain: Vec<u8>) -> Vec<u8> {
+        let probe = match self.probe.as_mut() {
+            Some(probe) => probe,
+            None => return plain,
+        };
+        let now = Instant::now();
+        let (plain, ponged) = probe.strip_pongs(&plain, now);
+        if ponged && probe.should_ping(now) {
+            self.buf.push_back(TelnetEvent::DataToSend(probe.ping(now)));
+        }
+        plain
+    }
+
+    // 测量结束后报告结果
+    fn finish_probe(&mut self) {
+        if !self.probe.as_ref().map(|p| p.done(Instant::now())).unwrap_or(false) {
+            return;
+        }
+        if let Some(probe) = self.probe.take() {
+            let report = probe.report(self.inflate.is_some() || self.protocols.contains(Protocols::MCCP));
+            self.buf.push_back(TelnetEvent::Probe(report));
+        }
+    }
+
+    // 处理解析事件，MCCP压缩开始时返回其后的压缩数据
+    fn handle(&mut self, events: Vec<TelnetEvents>) -> Option<Vec<u8>> {
+        let mut compressed = None;
         for event in events {
             match event {
                 TelnetEvents::IAC(TelnetIAC { command }) => {
@@ -59,9 +212,13 @@ where
                 }
                 TelnetEvents::Negotiation(TelnetNegotiation { command, option }) => {
                     log::trace!("TelnetNegotiation[command={}, option={}]", command, option);
+                    self.negotiated(command, option);
                 }
                 TelnetEvents::DataReceive(bs) => {
-                    self.buf.push_back(TelnetEvent::Text(bs));
+                    // 解压后的数据可能很大，拆分为有限大小的帧
+                    for frame in bs.chunks(MAX_FRAME) {
+                        self.buf.push_back(TelnetEvent::Text(frame.to_vec()));
+                    }
                 }
                 TelnetEvents::DataSend(bs) => {
                     self.buf.push_back(TelnetEvent::DataToSend(bs));
@@ -72,14 +229,106 @@ where
                         option,
                         buffer
                     );
+                    if option == Opt::GMCP {
+                        self.buf.push_back(TelnetEvent::Gmcp(buffer.to_vec()));
+                    } else if option == OPT_MSDP {
+                        self.buf.push_back(TelnetEvent::Msdp(buffer.to_vec()));
+                    }
                 }
-                _ => (),
+                TelnetEvents::DecompressImmediate(bs) => compressed = Some(bs),
             }
         }
-        if let Some(msg) = self.buf.pop_front() {
-            return Ok(msg);
+        compressed
+    }
+
+    // 选项启用后的初始子协商
+    fn negotiated(&mut self, command: u8, option: u8) {
+        let subs = match (command, option) {
+            (Op::DO, Opt::NAWS) => {
+                let (w, h) = self.naws;
+                let mut data = w.to_be_bytes().to_vec();
+                data.extend_from_slice(&h.to_be_bytes());
+                vec![self.parser.subnegotiation(Opt::NAWS, data)]
+            }
+            (Op::WILL, Opt::GMCP) => {
+                let hello = self.parser.subnegotiation_text(
+                    Opt::GMCP,
+                    concat!(r#"Core.Hello {"client":"mudterm","version":""#, env!("CARGO_PKG_VERSION"), r#""}"#),
+                );
+                let supports = serde_json::to_string(&self.gmcp_supports).unwrap_or_default();
+                let supports = self
+                    .parser
+                    .subnegotiation_text(Opt::GMCP, &gmcp::encode("Core.Supports.Set", Some(&supports)));
+                vec![hello, supports]
+            }
+            (Op::WILL, OPT_MSDP) if !self.msdp_reports.is_empty() => {
+                vec![self.parser.subnegotiation(OPT_MSDP, msdp::encode("REPORT", &self.msdp_reports))]
+            }
+            _ => vec![],
+        };
+        for sub in subs {
+            if let Some(TelnetEvents::DataSend(bs)) = sub {
+                self.buf.push_back(TelnetEvent::DataToSend(bs));
+            }
+        }
+    }
+
+    // 首次收到文本时报告协商结果，之后仅在变化时报告
+    fn report(&mut self) {
+        let received = self.buf.iter().any(|e| matches!(e, TelnetEvent::Text(_)));
+        if !self.reported && !received {
+            return;
+        }
+        let current = PROTOCOLS
+            .iter()
+            .filter(|(p, opt, _)| {
+                let entry = self.parser.options.get_option(*opt);
+                if *p == Protocols::NAWS {
+                    entry.local_state
+                } else {
+                    entry.remote_state
+                }
+            })
+            .fold(Protocols::empty(), |acc, (p, ..)| acc | *p);
+        if self.reported && current == self.protocols {
+            return;
+        }
+        self.reported = true;
+        self.protocols = current;
+        self.buf.push_front(TelnetEvent::Protocols(current));
+    }
+}
+
+fn negotiate_mode(config: &conf::Protocol, p: Protocols) -> Negotiate {
+    match p {
+        Protocols::MXP => config.mxp,
+        Protocols::MCCP => config.mccp,
+        Protocols::GMCP => config.gmcp,
+        Protocols::MSDP => config.msdp,
+        Protocols::NAWS => config.naws,
+        _ => config.echo,
+    }
+}
+
+// 解压MCCP数据，压缩流结束时一并返回其后未压缩的数据
+fn inflate_mccp(inflate: &mut Decompress, input: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut out = Vec::with_capacity(input.len() * 4 + 64);
+    let mut pos = 0;
+    loop {
+        if out.len() == out.capacity() {
+            out.reserve(4096);
+        }
+        let before = inflate.total_in();
+        let status = inflate
+            .decompress_vec(&input[pos..], &mut out, FlushDecompress::Sync)
+            .map_err(|e| Error::DecodeError(format!("mccp decompress error {}", e)))?;
+        pos += (inflate.total_in() - before) as usize;
+        if status == Status::StreamEnd {
+            return Ok((out, Some(input[pos..].to_vec())));
+        }
+        if pos >= input.len() && out.len() < out.capacity() {
+            return Ok((out, None));
         }
-        Ok(TelnetEvent::Empty)
     }
 }
 
@@ -98,9 +347,16 @@ where
     }
 
     pub fn send(&mut self, bs: Vec<u8>) -> Result<()> {
-        self.writer.write_all(&bs)?;
-        self.writer.flush()?;
-        Ok(())
+        self.writer
+            .write_all(&bs)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| match e.kind() {
+                // 设置写超时后，超时在不同平台上表现为WouldBlock或TimedOut
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    Error::RuntimeError("write to world timed out".to_owned())
+                }
+                _ => Error::from(e),
+            })
     }
 }
 
@@ -108,3 +364,100 @@ where
 pub enum WorldInput {
     Bytes(Vec<u8>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_telnet_negotiate_mccp() {
+        let mut input = vec![Op::IAC, Op::WILL, Opt::GMCP, Op::IAC, Op::DO, Opt::NAWS];
+        input.extend_from_slice(&[Op::IAC, Op::SB, Opt::GMCP]);
+        input.extend_from_slice(br#"Char.Vitals {"hp":100}"#);
+        input.extend_from_slice(&[Op::IAC, Op::SE]);
+        input.extend_from_slice(&[Op::IAC, Op::WILL, OPT_MSDP, Op::IAC, Op::SB, OPT_MSDP]);
+        input.extend_from_slice(b"\x01HEALTH\x02100");
+        input.extend_from_slice(&[Op::IAC, Op::SE]);
+        input.extend_from_slice(&[Op::IAC, Op::WILL, Opt::MCCP2]);
+        input.extend_from_slice(&[Op::IAC, Op::SB, Opt::MCCP2, Op::IAC, Op::SE]);
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        enc.write_all("欢迎光临\r\n".as_bytes()).unwrap();
+        input.extend(enc.finish().unwrap());
+        // 压缩流结束后恢复为未压缩数据
+        input.extend_from_slice(b"bye");
+        let config = conf::Protocol {
+            echo: Negotiate::Off,
+            ..conf::Protocol::default()
+        };
+        let mut telnet = Telnet::new(Cursor::new(input), 4096, &config);
+        let mut text = vec![];
+        let mut sent = vec![];
+        let mut reports = vec![];
+        let mut gmcp = vec![];
+        let mut msdp = vec![];
+        loop {
+            match telnet.recv().unwrap() {
+                TelnetEvent::Text(bs) => text.extend(bs),
+                TelnetEvent::DataToSend(bs) => sent.extend(bs),
+                TelnetEvent::Protocols(p) => reports.push(p),
+                TelnetEvent::Gmcp(bs) => gmcp.push(String::from_utf8(bs).unwrap()),
+                TelnetEvent::Msdp(bs) => msdp.push(bs),
+                TelnetEvent::Probe(_) => (),
+                TelnetEvent::Empty => (),
+                TelnetEvent::Disconnected => break,
+            }
+        }
+        assert_eq!("欢迎光临\r\nbye", String::from_utf8(text).unwrap());
+        assert_eq!(
+            vec![Protocols::MCCP | Protocols::GMCP | Protocols::MSDP | Protocols::NAWS],
+            reports
+        );
+        let naws = [Op::IAC, Op::SB, Opt::NAWS, 0, 80, 0, 24, Op::IAC, Op::SE];
+        assert!(sent.windows(naws.len()).any(|w| w == naws));
+        assert!(sent.windows(3).any(|w| w == [Op::IAC, Op::DO, Opt::MCCP2]));
+        assert_eq!(vec![r#"Char.Vitals {"hp":100}"#.to_owned()], gmcp);
+        let supports = br#"Core.Supports.Set ["Char 1","Room 1","Comm 1"]"#;
+        assert!(sent.windows(supports.len()).any(|w| w == supports));
+        assert_eq!(vec![b"\x01HEALTH\x02100".to_vec()], msdp);
+        let report = msdp::encode("REPORT", &config.msdp_reports);
+        assert!(sent.windows(report.len()).any(|w| w == &report[..]));
+
+        // 关闭的选项被拒绝
+        let input = vec![Op::IAC, Op::WILL, Opt::ECHO];
+        let mut telnet = Telnet::new(Cursor::new(input), 4096, &config);
+        match telnet.recv().unwrap() {
+            TelnetEvent::DataToSend(bs) => assert_eq!(vec![Op::IAC, Op::DONT, Opt::ECHO], bs),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_telnet_probe() {
+        let mut input = vec![Op::IAC, Op::WILL, Opt::TM];
+        input.extend_from_slice(b"hi");
+        let config = conf::Protocol {
+            probe_secs: 5,
+            ..conf::Protocol::default()
+        };
+        let mut telnet = Telnet::new(Cursor::new(input), 4096, &config);
+        let mut text = vec![];
+        let mut pings = 0;
+        loop {
+            match telnet.recv().unwrap() {
+                TelnetEvent::Text(bs) => text.extend(bs),
+                TelnetEvent::DataToSend(bs) => {
+                    // 应答不交给解析器，不会回复DONT
+                    assert_eq!(vec![Op::IAC, Op::DO, Opt::TM], bs);
+                    pings += 1;
+                }
+                TelnetEvent::Disconnected => break,
+                _ => (),
+            }
+        }
+        assert_eq!(b"hi".to_vec(), text);
+        assert_eq!(2, pings);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::conf;
+use crate::error::{Error, Result};
+use crate::ui::line::Line;
+use crate::ui::style::{Color, Modifier, Style};
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use regex::RegexSet;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 等待朗读的最大行数，超出时丢弃新行
+const QUEUE_CAPACITY: usize = 64;
+
+/// 朗读的文本类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // 世界文本
+    World,
+    // 路由到聊天窗口的文本
+    Chat,
+    // 客户端提示及错误
+    System,
+}
+
+impl Category {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "world" => Ok(Self::World),
+            "chat" => Ok(Self::Chat),
+            "system" => Ok(Self::System),
+            _ => Err(Error::ParseError(format!("invalid announce category {}", s))),
+        }
+    }
+}
+
+/// 行朗读，将完整的行以纯文本异步写入外部命令（如TTS程序）的标准输入
+///
+/// 每行启动一次命令，前一行朗读结束后才开始下一行
+pub struct Announcer {
+    tx: Sender<String>,
+    categories: Vec<Category>,
+    chat_filter: RegexSet,
+    // 每秒最多朗读的行数，0表示不限制
+    rate: u32,
+    window: Instant,
+    count: u32,
+    dropped: usize,
+    // 未结束的行
+    pending: Option<Line>,
+}
+
+impl Announcer {
+    /// 未配置朗读命令时返回None
+    pub fn new(config: &conf::Term, chat_filter: RegexSet) -> Result<Option<Self>> {
+        if config.announce_cmd.is_empty() {
+            return Ok(None);
+        }
+        let (tx, rx) = bounded(QUEUE_CAPACITY);
+        let announcer = Self::with_sender(config, chat_filter, tx)?;
+        spawn_worker(config.announce_cmd.to_owned(), rx);
+        Ok(Some(announcer))
+    }
+
+    fn with_sender(config: &conf::Term, chat_filter: RegexSet, tx: Sender<String>) -> Result<Self> {
+        let categories = config
+            .announce_categories
+            .iter()
+            .map(|c| Category::parse(c))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            tx,
+            categories,
+            chat_filter,
+            rate: config.announce_rate,
+            window: Instant::now(),
+            count: 0,
+            dropped: 0,
+            pending: None,
+        })
+    }
+
+    pub fn push_lines<'a>(&mut self, lines: impl IntoIterator<Item = &'a Line>) {
+        for line in lines {
+            self.push_line(line.clone());
+        }
+    }
+
+    pub fn push_line(&mut self, line: Line) {
+        // 等待行结束后再朗读
+        let line = match self.pending.take() {
+            Some(mut pending) => {
+                pending.push_line(line);
+                pending
+            }
+            None => line,
+        };
+        if !line.ended() {
+            self.pending = Some(line);
+            return;
+        }
+        let text = line.plain_text();
+        if text.trim().is_empty() {
+            return;
+        }
+        let category = self.classify(&line, &text);
+        if !self.categories.is_empty() && !self.categories.contains(&category) {
+            return;
+        }
+        if !self.acquire(Instant::now()) {
+            self.drop_line();
+            return;
+        }
+        match self.tx.try_send(text) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) => self.drop_line(),
+            Err(TrySendError::Disconnected(_)) => {
+                log::warn!("announce worker stopped");
+            }
+        }
+    }
+
+    fn classify(&self, line: &Line, text: &str) -> Category {
+        if self.chat_filter.is_match(text) {
+            return Category::Chat;
+        }
+        // 提示及错误由客户端以固定样式生成
+        let note = Style::default().fg(Color::LightBlue);
+        let err = Style::default().add_modifier(Modifier::REVERSED);
+        if line.spans().iter().all(|s| s.style == note || s.style == err) {
+            return Category::System;
+        }
+        Category::World
+    }
+
+    // 以秒为窗口限制朗读行数
+    fn acquire(&mut self, now: Instant) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        if now.duration_since(self.window) >= Duration::from_secs(1) {
+            self.window = now;
+            self.count = 0;
+        }
+        if self.count >= self.rate {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+
+    fn drop_line(&mut self) {
+        self.dropped += 1;
+        log::debug!("announce rate exceeded, {} lines dropped", self.dropped);
+    }
+}
+
+fn spawn_worker(cmd: String, rx: Receiver<String>) {
+    thread::spawn(move || {
+        while let Ok(text) = rx.recv() {
+            if let Err(e) = run_announce(&cmd, &text) {
+                log::warn!("announce command error {}", e);
+            }
+        }
+    });
+}
+
+fn run_announce(cmd: &str, text: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", text)?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Label;
+    use crate::ui::span::Span;
+
+    #[test]
+    fn test_announcer_filter_and_rate() {
+        let config = conf::Term {
+            announce_cmd: "cat".to_owned(),
+            announce_categories: vec!["world".to_owned(), "chat".to_owned()],
+            announce_rate: 2,
+            ..conf::Term::default()
+        };
+        let (tx, rx) = bounded(QUEUE_CAPACITY);
+        let chat = RegexSet::new(["^【闲聊】"]).unwrap();
+        let mut announcer = Announcer::with_sender(&config, chat, tx).unwrap();
+        // 未结束的行与后续文本合并
+        let partial = Span::new("张三", Style::default(), Label::None);
+        announcer.push_line(Line::new(vec![partial]));
+        announcer.push_line(Line::fmt_raw("走了过来。"));
+        announcer.push_line(Line::fmt_note("系统提示"));
+        announcer.push_line(Line::fmt_raw("【闲聊】李四：你好"));
+        announcer.push_line(Line::fmt_raw("超出限制"));
+        let texts: Vec<String> = rx.try_iter().collect();
+        assert_eq!(vec!["张三走了过来。", "【闲聊】李四：你好"], texts);
+        assert_eq!(1, announcer.dropped);
+        assert!(announcer.acquire(Instant::now() + Duration::from_secs(1)));
+
+        let config = conf::Term {
+            announce_categories: vec!["speech".to_owned()],
+            ..config
+        };
+        let (tx, _) = bounded(1);
+        assert!(Announcer::with_sender(&config, RegexSet::empty(), tx).is_err());
+    }
+}
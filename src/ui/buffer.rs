@@ -254,6 +254,24 @@ impl BufferVec {
         }
     }
 
+    /// 重置指定区域
+    pub fn reset_area(&mut self, area: Rect) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                self.get_mut(x, y).reset();
+            }
+        }
+    }
+
+    /// 从另一份缓存复制指定区域
+    pub fn copy_area(&mut self, other: &BufferVec, area: Rect) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                *self.get_mut(x, y) = other.get(x, y).clone();
+            }
+        }
+    }
+
     pub fn index_of(&self, x: u16, y: u16) -> usize {
         debug_assert!(
             x >= self.area.left()
@@ -413,6 +431,19 @@ mod tests {
         println!("updates={:#?}", updates);
     }
 
+    #[test]
+    fn test_buffer_copy_area() {
+        let area = Rect::new(1, 1, 5, 2);
+        let mut buf1 = BufferVec::empty(area);
+        buf1.set_line_str(1, 1, "hello", 6, Style::default(), true);
+        buf1.set_line_str(1, 2, "world", 6, Style::default(), true);
+        let mut buf2 = BufferVec::empty(area);
+        buf2.copy_area(&buf1, Rect::new(1, 2, 5, 1));
+        assert_eq!(vec!["", "world"], multi_lines(&buf2));
+        buf1.reset_area(Rect::new(1, 1, 5, 1));
+        assert_eq!(buf1, buf2);
+    }
+
     #[test]
     fn test_buffer_move() {
         let area = Rect::new(1, 1, 10, 4);
@@ -0,0 +1,175 @@
+use crate::conf::MouseMode;
+use crate::ui::style::{Color, Modifier, Style};
+use std::env;
+
+/// 终端能力
+///
+/// 启动时根据TERM、COLORTERM及locale环境变量推断，
+/// 不支持的能力使用降级方案，避免输出终端无法识别的序列
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermCaps {
+    pub term: String,
+    // 是否支持鼠标事件上报
+    pub mouse: bool,
+    // 支持的颜色数：8，16或256
+    pub colors: u16,
+    // 是否支持24位真彩色，COLORTERM为truecolor或24bit时开启
+    pub truecolor: bool,
+    // 是否支持Unicode制表符
+    pub unicode: bool,
+}
+
+impl TermCaps {
+    /// 从当前进程环境变量检测
+    pub fn detect() -> Self {
+        Self::from_env(|key| env::var(key).ok().filter(|v| !v.is_empty()))
+    }
+
+    pub fn from_env<F: Fn(&str) -> Option<String>>(lookup: F) -> Self {
+        let term = lookup("TERM").unwrap_or_default();
+        let dumb = term.is_empty() || term == "dumb";
+        // Linux控制台及vt系列终端不支持鼠标上报
+        let mouse = !dumb && !term.starts_with("linux") && !term.starts_with("vt");
+        let colors = if dumb || term.starts_with("vt") {
+            8
+        } else if term.contains("256color") || lookup("COLORTERM").is_some() {
+            256
+        } else if term.starts_with("linux") || term == "xterm-color" {
+            8
+        } else {
+            16
+        };
+        let truecolor = !dumb
+            && matches!(lookup("COLORTERM").as_deref(), Some("truecolor") | Some("24bit"));
+        let locale = lookup("LC_ALL")
+            .or_else(|| lookup("LC_CTYPE"))
+            .or_else(|| lookup("LANG"))
+            .unwrap_or_default()
+            .to_lowercase();
+        let unicode = !dumb && (locale.contains("utf-8") || locale.contains("utf8"));
+        Self {
+            term,
+            mouse,
+            colors,
+            truecolor,
+            unicode,
+        }
+    }
+
+    /// 按配置覆盖鼠标上报，关闭时保留终端原生的选择功能
+    pub fn with_mouse(mut self, mode: MouseMode) -> Self {
+        match mode {
+            MouseMode::Auto => (),
+            MouseMode::On => self.mouse = true,
+            MouseMode::Off => self.mouse = false,
+        }
+        self
+    }
+
+    /// 将样式转换为终端支持的颜色
+    ///
+    /// 不支持真彩色时转换为最接近的256色，仅支持16色时转换为最接近的基础色，
+    /// 仅支持8色时，高亮前景色转换为对应基础色加粗，高亮背景色转换为基础色
+    pub fn adapt_style(&self, mut style: Style) -> Style {
+        if self.truecolor {
+            return style;
+        }
+        let adapt = |color: Color| {
+            if self.colors >= 256 {
+                color.to_indexed()
+            } else {
+                color.to_ansi16()
+            }
+        };
+        style.fg = style.fg.map(adapt);
+        style.bg = style.bg.map(adapt);
+        if self.colors >= 16 {
+            return style;
+        }
+        if let Some(fg) = style.fg {
+            let (base, bright) = to_basic(fg);
+            style.fg = Some(base);
+            if bright {
+                style = style.add_modifier(Modifier::BOLD);
+            }
+        }
+        if let Some(bg) = style.bg {
+            style.bg = Some(to_basic(bg).0);
+        }
+        style
+    }
+}
+
+// 转换为基础8色，返回基础色及是否为高亮色
+fn to_basic(color: Color) -> (Color, bool) {
+    match color {
+        Color::DarkGray => (Color::Black, true),
+        Color::LightRed => (Color::Red, true),
+        Color::LightGreen => (Color::Green, true),
+        Color::LightYellow => (Color::Yellow, true),
+        Color::LightBlue => (Color::Blue, true),
+        Color::LightMagenta => (Color::Magenta, true),
+        Color::LightCyan => (Color::Cyan, true),
+        Color::White => (Color::Gray, true),
+        other => (other, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_term_caps_detect() {
+        let caps = caps_of(&[("TERM", "xterm-256color"), ("LANG", "zh_CN.UTF-8")]);
+        assert!(caps.mouse);
+        assert_eq!(256, caps.colors);
+        assert!(caps.unicode);
+
+        assert!(!caps.clone().with_mouse(MouseMode::Off).mouse);
+        assert!(caps.clone().with_mouse(MouseMode::Auto).mouse);
+
+        let caps = caps_of(&[("TERM", "linux"), ("LANG", "C")]);
+        assert!(!caps.mouse);
+        assert!(caps.clone().with_mouse(MouseMode::On).mouse);
+        assert_eq!(8, caps.colors);
+        assert!(!caps.unicode);
+
+        let caps = caps_of(&[("LC_ALL", "en_US.UTF-8")]);
+        assert!(!caps.mouse);
+        assert!(!caps.unicode);
+    }
+
+    #[test]
+    fn test_term_caps_adapt_style() {
+        let caps = caps_of(&[("TERM", "linux")]);
+        let style = caps.adapt_style(Style::default().fg(Color::LightRed).bg(Color::LightBlue));
+        assert_eq!(
+            Style::default()
+                .fg(Color::Red)
+                .bg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            style
+        );
+        let caps = caps_of(&[("TERM", "xterm")]);
+        let style = Style::default().fg(Color::LightRed);
+        assert_eq!(style, caps.adapt_style(style));
+        // 扩展颜色按终端能力降级
+        let style = Style::default().fg(Color::Rgb(0xfa, 0x88, 0x05));
+        assert_eq!(Style::default().fg(Color::LightRed), caps.adapt_style(Style::default().fg(Color::Indexed(196))));
+        let caps = caps_of(&[("TERM", "xterm-256color")]);
+        assert_eq!(Style::default().fg(Color::Indexed(208)), caps.adapt_style(style));
+        let caps = caps_of(&[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]);
+        assert!(caps.truecolor);
+        assert_eq!(style, caps.adapt_style(style));
+    }
+
+    fn caps_of(vars: &[(&str, &str)]) -> TermCaps {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        TermCaps::from_env(|key| vars.get(key).cloned())
+    }
+}
@@ -1,3 +1,6 @@
+use crate::conf;
+use crate::i18n;
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Rect {
     pub x: u16,
@@ -36,7 +39,7 @@ impl Rect {
     }
 
     pub fn right(self) -> u16 {
-        self.x + self.width
+        self.x.saturating_add(self.width)
     }
 
     pub fn top(self) -> u16 {
@@ -44,6 +47,161 @@ impl Rect {
     }
 
     pub fn bottom(self) -> u16 {
-        self.y + self.height
+        self.y.saturating_add(self.height)
+    }
+
+    /// 坐标是否位于区域内，用于判断鼠标事件的位置
+    pub fn contains(self, x: u16, y: u16) -> bool {
+        x >= self.left() && x < self.right() && y >= self.top() && y < self.bottom()
+    }
+}
+
+// 命令区含上下边框，至少3行
+pub const MIN_CMD_HEIGHT: u16 = 3;
+// 主窗格的最小尺寸，空间不足时依次缩小状态栏、聊天窗格及留白
+pub const MIN_FLOW_WIDTH: u16 = 20;
+pub const MIN_FLOW_HEIGHT: u16 = 3;
+
+/// 屏幕各区域，由[term.layout]配置及终端大小计算
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScreenLayout {
+    pub flow: Rect,
+    pub chat: Rect,
+    pub status: Rect,
+    pub cmd: Rect,
+}
+
+impl ScreenLayout {
+    /// 计算各区域，同时返回因空间不足而未能满足的配置项
+    pub fn compute(term: &conf::Term, width: u16, height: u16, split: bool) -> (Self, Vec<String>) {
+        let layout = &term.layout;
+        let mut conflicts = Vec::new();
+        let mut shrink = |key: &str, from: u16, to: u16| {
+            if from > to {
+                conflicts.push(i18n::trf("layout.shrunk", &[&key, &from, &to]));
+            }
+            from.min(to)
+        };
+        let cmd_height = shrink(
+            "layout.cmd_height",
+            layout.cmd_height.max(MIN_CMD_HEIGHT),
+            height.saturating_sub(MIN_FLOW_HEIGHT).max(MIN_CMD_HEIGHT),
+        );
+        let avail = height.saturating_sub(cmd_height);
+        let status_height = shrink(
+            "server_status_rows",
+            term.server_status_rows,
+            avail.saturating_sub(MIN_FLOW_HEIGHT) / 2,
+        );
+        // 左右留白同时缩小
+        let margins = layout.margin_left.saturating_add(layout.margin_right);
+        let (margin_left, margin_right) =
+            if margins > 0 && shrink("layout.margin", margins, width.saturating_sub(MIN_FLOW_WIDTH)) < margins {
+                (0, 0)
+            } else {
+                (layout.margin_left, layout.margin_right)
+            };
+        let main = Rect {
+            x: margin_left.saturating_add(1),
+            y: 1,
+            width: width.saturating_sub(margin_left).saturating_sub(margin_right),
+            height: avail.saturating_sub(status_height),
+        };
+        let (flow, chat) = if layout.chat_side_width > 0 {
+            let chat_width = shrink(
+                "layout.chat_side_width",
+                layout.chat_side_width,
+                main.width.saturating_sub(MIN_FLOW_WIDTH),
+            );
+            let chat = Rect {
+                x: main.right().saturating_sub(chat_width),
+                width: chat_width,
+                ..main
+            };
+            let flow = Rect {
+                width: main.width.saturating_sub(chat_width),
+                ..main
+            };
+            (flow, chat)
+        } else {
+            let chat_height = shrink(
+                "chat_height",
+                term.chat_height,
+                main.height.saturating_sub(MIN_FLOW_HEIGHT).min(main.height / 2),
+            );
+            let chat = Rect {
+                height: chat_height,
+                ..main
+            };
+            let flow = Rect {
+                y: chat.bottom(),
+                height: main.height.saturating_sub(chat_height),
+                ..main
+            };
+            (flow, chat)
+        };
+        let status = Rect {
+            x: 1,
+            y: main.bottom(),
+            width,
+            height: status_height,
+        };
+        let cmd = Rect {
+            x: 1,
+            y: status.bottom(),
+            width,
+            height: cmd_height,
+        };
+        // 未分屏时主窗格占据聊天窗格的位置，但聊天窗格仍需参与冲突检查
+        let flow = if split { flow } else { main };
+        (Self { flow, chat, status, cmd }, conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_layout_compute() {
+        let mut term = conf::Term::default();
+        let (layout, conflicts) = ScreenLayout::compute(&term, 80, 24, false);
+        assert!(conflicts.is_empty());
+        assert_eq!(Rect::new(1, 1, 80, 21), layout.flow);
+        assert_eq!(Rect::new(1, 22, 80, 3), layout.cmd);
+        let (layout, _) = ScreenLayout::compute(&term, 80, 24, true);
+        assert_eq!(Rect::new(1, 1, 80, 8), layout.chat);
+        assert_eq!(Rect::new(1, 9, 80, 13), layout.flow);
+
+        term.server_status_rows = 2;
+        term.layout.cmd_height = 4;
+        term.layout.margin_left = 2;
+        term.layout.margin_right = 2;
+        term.layout.chat_side_width = 30;
+        let (layout, conflicts) = ScreenLayout::compute(&term, 80, 24, true);
+        assert!(conflicts.is_empty());
+        assert_eq!(Rect::new(3, 1, 46, 18), layout.flow);
+        assert_eq!(Rect::new(49, 1, 30, 18), layout.chat);
+        assert_eq!(Rect::new(1, 19, 80, 2), layout.status);
+        assert_eq!(Rect::new(1, 21, 80, 4), layout.cmd);
+
+        // 终端过窄时先取消留白，再缩小侧栏
+        let (layout, conflicts) = ScreenLayout::compute(&term, 22, 24, true);
+        assert_eq!(2, conflicts.len());
+        assert_eq!(Rect::new(1, 1, 20, 18), layout.flow);
+        assert_eq!(Rect::new(21, 1, 2, 18), layout.chat);
+        // 终端过矮时缩小状态栏
+        let (layout, conflicts) = ScreenLayout::compute(&term, 80, 9, true);
+        assert_eq!(1, conflicts.len());
+        assert_eq!(1, layout.status.height);
+        assert_eq!(Rect::new(3, 1, 46, 4), layout.flow);
+        // 配置值过大或终端极小时不溢出
+        term.layout.margin_left = u16::MAX;
+        term.layout.margin_right = u16::MAX;
+        term.layout.chat_side_width = u16::MAX;
+        let (layout, conflicts) = ScreenLayout::compute(&term, 10, 2, true);
+        assert!(!conflicts.is_empty());
+        assert_eq!(1, layout.flow.left());
+        assert!(layout.chat.right() <= 11);
     }
 }
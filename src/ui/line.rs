@@ -135,6 +135,14 @@ impl Lines {
         Self(Vec::new())
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn push_line(&mut self, line: Line) {
         if let Some(last_line) = self.0.last_mut() {
             if !last_line.ended() {
@@ -192,13 +200,30 @@ impl Line {
             .unwrap_or(false)
     }
 
+    /// 是否以单独的回车开头，即覆盖当前未结束的行
+    pub fn returns(&self) -> bool {
+        self.0.first().map(|s| s.content == "\r").unwrap_or(false)
+    }
+
+    /// 在行首添加单独的回车
+    pub fn with_return(mut self) -> Self {
+        self.0.insert(0, Span::new("\r", Style::default(), Label::None));
+        self
+    }
+
     pub fn display_width(&self, cjk: bool) -> usize {
         self.append_width(0, cjk)
     }
 
     pub fn wrap(&self, max_width: usize, cjk: bool) -> WrapLine {
+        self.wrap_hyphen(max_width, cjk, 0)
+    }
+
+    /// 折行时，若折断处位于长度不小于hyphen_after的连续ASCII串中，在行尾显示连字符，
+    /// 仅影响显示，不修改原文本，hyphen_after为0时不添加
+    pub fn wrap_hyphen(&self, max_width: usize, cjk: bool, hyphen_after: usize) -> WrapLine {
         let mut lines = vec![];
-        wrap_line(&self, max_width, cjk, &mut lines);
+        wrap_line(self, max_width, cjk, hyphen_after, &mut lines);
         WrapLine(lines)
     }
 
@@ -222,10 +247,14 @@ impl Line {
         true
     }
 
+    /// 以单独的回车开头的行覆盖未结束的内容，合并后仍保留行首的回车
     pub fn push_line(&mut self, line: Line) {
         if self.ended() {
             return;
         }
+        if line.returns() {
+            self.0.clear();
+        }
         for span in line.0 {
             if !self.push_span(span) {
                 return;
@@ -233,15 +262,81 @@ impl Line {
         }
     }
 
+    /// 纯文本，去除行尾换行符
+    pub fn plain_text(&self) -> String {
+        let spans = &self.0[self.returns() as usize..];
+        let mut text: String = spans.iter().map(|s| &s.content[..]).collect();
+        if text.ends_with('\n') {
+            text.pop();
+            if text.ends_with('\r') {
+                text.pop();
+            }
+        }
+        text
+    }
+
     pub fn spans(&self) -> &[Span] {
         &self.0
     }
 
+    /// 合并相邻的样式及标签均相同的片段，并释放多余的字符串容量
+    ///
+    /// 样式本身为小型Copy结构，合并片段即可消除重复，无需额外驻留。
+    /// 行首单独的回车不参与合并
+    pub fn compact(&mut self) -> CompactStats {
+        let mut stats = CompactStats {
+            lines: 1,
+            ..CompactStats::default()
+        };
+        let before: usize = self.0.iter().map(|s| s.content.capacity()).sum();
+        let returns = self.returns();
+        let mut spans: Vec<Span> = Vec::with_capacity(self.0.len());
+        for span in self.0.drain(..) {
+            let after_return = returns && spans.len() == 1;
+            if let Some(last) = spans.last_mut().filter(|_| !after_return) {
+                if last.style == span.style && last.label == span.label && !last.ended() {
+                    last.push_str(span.content);
+                    stats.spans_merged += 1;
+                    continue;
+                }
+            }
+            spans.push(span);
+        }
+        for span in spans.iter_mut() {
+            span.content.shrink_to_fit();
+        }
+        spans.shrink_to_fit();
+        let after: usize = spans.iter().map(|s| s.content.capacity()).sum();
+        stats.bytes_saved = before.saturating_sub(after)
+            + stats.spans_merged * std::mem::size_of::<Span>();
+        self.0 = spans;
+        stats
+    }
+
     pub fn into_spans(self) -> Vec<Span> {
         self.0
     }
 }
 
+/// 历史行压缩统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    // 压缩的行数
+    pub lines: usize,
+    // 合并掉的片段数
+    pub spans_merged: usize,
+    // 估算节省的字节数
+    pub bytes_saved: usize,
+}
+
+impl std::ops::AddAssign for CompactStats {
+    fn add_assign(&mut self, other: Self) {
+        self.lines += other.lines;
+        self.spans_merged += other.spans_merged;
+        self.bytes_saved += other.bytes_saved;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct WrapLine(pub Vec<Line>);
 
@@ -249,7 +344,7 @@ impl WrapLine {
     pub fn reshape(&self, max_width: usize, cjk: bool) -> Self {
         let mut lines = vec![];
         for line in &self.0 {
-            wrap_line(line, max_width, cjk, &mut lines);
+            wrap_line(line, max_width, cjk, 0, &mut lines);
         }
         WrapLine(lines)
     }
@@ -260,7 +355,13 @@ impl WrapLine {
 
     /// when calling this method, the max_width should be identical to previous setting
     /// otherwise, please call reshape() method at last
-    pub fn push_span(&mut self, span: Span, max_width: usize, cjk: bool) -> bool {
+    pub fn push_span(
+        &mut self,
+        span: Span,
+        max_width: usize,
+        cjk: bool,
+        hyphen_after: usize,
+    ) -> bool {
         if self.ended() {
             return false;
         }
@@ -272,7 +373,7 @@ impl WrapLine {
         last_line.push_span(span);
         if last_line.display_width(cjk) > max_width {
             // exceeds max width, must wrap
-            let wl = last_line.wrap(max_width, cjk);
+            let wl = last_line.wrap_hyphen(max_width, cjk, hyphen_after);
             self.0.pop();
             self.0.extend(wl.0);
         }
@@ -281,17 +382,28 @@ impl WrapLine {
 }
 
 /// 根据指定行宽将单行拆解为多行，并添加到可变数组中
-pub fn wrap_line(line: &Line, max_width: usize, cjk: bool, lines: &mut Vec<Line>) {
+pub fn wrap_line(
+    line: &Line,
+    max_width: usize,
+    cjk: bool,
+    hyphen_after: usize,
+    lines: &mut Vec<Line>,
+) {
     let mut curr_line = if lines.last().map(|l| !l.ended()).unwrap_or(false) {
         lines.pop().unwrap().into_spans()
     } else {
         Vec::new()
     };
     let mut curr_width = curr_line.append_width(0, cjk);
+    // 当前连续ASCII串的长度
+    let mut run = 0;
     for span in line.spans() {
         // 判断宽度是否超过限制
         let next_width = span.append_width(curr_width, cjk);
-        if next_width <= max_width {
+        if next_width <= max_width && (hyphen_after == 0 || next_width < max_width) {
+            for c in span.content.chars() {
+                run = if is_token_char(c) { run + 1 } else { 0 };
+            }
             // 合并到当前行
             append_span(&mut curr_line, span.clone());
             if curr_line.last().unwrap().ended() {
@@ -305,10 +417,31 @@ pub fn wrap_line(line: &Line, max_width: usize, cjk: bool, lines: &mut Vec<Line>
             let new_style = span.style;
             // let new_ended = span.ended;
             let mut new_content = String::new();
-            for c in span.content.chars() {
+            let mut chars = span.content.chars().peekable();
+            while let Some(c) = chars.next() {
                 // let cw = if cjk { c.width_cjk() } else { c.width() }.unwrap_or(0);
                 let next_width = c.append_width(curr_width, cjk);
-                if next_width <= max_width {
+                // 放入当前字符后行已满且串未结束时，改为放入连字符并折行
+                let hyphen = hyphen_after > 0
+                    && next_width == max_width
+                    && run + 1 >= hyphen_after
+                    && run > 0
+                    && curr_width > 0
+                    && is_token_char(c)
+                    && chars.peek().map(|n| is_token_char(*n)).unwrap_or(false);
+                run = if is_token_char(c) { run + 1 } else { 0 };
+                if hyphen {
+                    new_content.push('-');
+                    let new_span = Span::new(
+                        std::mem::take(&mut new_content),
+                        new_style,
+                        span.label.clone(),
+                    );
+                    append_span(&mut curr_line, new_span);
+                    lines.push(Line::new(std::mem::take(&mut curr_line)));
+                    new_content.push(c);
+                    curr_width = c.append_width(0, cjk);
+                } else if next_width <= max_width {
                     new_content.push(c);
                     curr_width = next_width;
                 } else {
@@ -338,6 +471,11 @@ pub fn wrap_line(line: &Line, max_width: usize, cjk: bool, lines: &mut Vec<Line>
     }
 }
 
+// 可添加连字符的字符
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_graphic()
+}
+
 // 将span合并进行，返回行是否结束
 fn append_span(line: &mut Vec<Span>, span: Span) {
     if let Some(last_span) = line.last_mut() {
@@ -416,6 +554,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_wrap_hyphen() {
+        let line = Line::new(vec![ended_span("see http://a.b/cdefg ok")]);
+        let wl = line.wrap_hyphen(8, true, 6);
+        assert_eq!(
+            wl,
+            WrapLine(vec![
+                Line::new(vec![partial_span("see http")]),
+                Line::new(vec![partial_span("://a.b/-")]),
+                Line::new(vec![ended_span("cdefg ok")]),
+            ])
+        );
+        // 原文本不变
+        assert_eq!("see http://a.b/cdefg ok", line.plain_text());
+        // 短串不添加连字符
+        let wl = line.wrap_hyphen(6, true, 20);
+        assert_eq!(line.wrap(6, true), wl);
+    }
+
+    #[test]
+    fn test_compact_line() {
+        let mut line = Line::new(vec![
+            red_span("a"),
+            red_span("b"),
+            partial_span("c"),
+            partial_span("d"),
+            ended_span("e"),
+            red_span("f"),
+        ]);
+        let stats = line.compact();
+        assert_eq!(3, stats.spans_merged);
+        assert!(stats.bytes_saved > 0);
+        assert_eq!(
+            Line::new(vec![red_span("ab"), ended_span("cde"), red_span("f")]),
+            line
+        );
+        assert_eq!(0, line.compact().spans_merged);
+
+        let mut line = Line::new(vec![partial_span("a"), partial_span("b")]).with_return();
+        assert_eq!(1, line.compact().spans_merged);
+        assert!(line.returns());
+        assert_eq!("ab", line.plain_text());
+    }
+
     fn ended_span(s: &str) -> Span {
         let mut s = s.to_owned();
         s.push_str("\r\n");
@@ -1,21 +1,40 @@
+pub mod announce;
 pub mod buffer;
+pub mod caps;
 pub mod layout;
 pub mod line;
 pub mod span;
 pub mod style;
 pub mod symbol;
 pub mod terminal;
+pub mod theme;
+pub mod view;
 pub mod widget;
 pub mod width;
 
+use crate::conf::{self, Config, RouteAction};
+use crate::datadir::DataDir;
 use crate::error::{Error, Result};
 use crate::event::Event;
+use crate::ui::announce::Announcer;
+use crate::ui::caps::TermCaps;
 use crate::ui::terminal::Terminal;
-use crossbeam_channel::Sender;
-use layout::Rect;
+use crate::ui::theme::Theme;
+use crate::ui::view::ScreenView;
+use crate::userinput::PasteChoices;
+use crate::runtime::classify::Classifier;
+use crate::runtime::route;
+use crate::runtime::settings;
+use crate::i18n;
+use crossbeam_channel::{bounded, select, unbounded, Receiver, Sender, TrySendError};
+use layout::{Rect, ScreenLayout};
+use regex::RegexSet;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Instant;
 use line::{Line, Lines};
-use termion::event::{Key, MouseEvent};
-use widget::{CmdBar, Flow, Widget};
+use termion::event::{Key, MouseButton, MouseEvent};
+use widget::{Border, CmdBar, Flow, Menu, MenuAction, Widget};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum UserOutput {
@@ -82,6 +101,14 @@ impl UserOutput {
 pub trait UICallback {
     fn on_output(&mut self, output: UserOutput);
 
+    fn on_key_read(&mut self, key: Option<String>);
+
+    fn on_menu_chosen(&mut self, idx: Option<usize>);
+
+    fn on_repl(&mut self, code: String);
+
+    fn on_repl_cancel(&mut self);
+
     fn on_quit(&mut self);
 }
 
@@ -92,6 +119,22 @@ impl UICallback for EventBusCallback {
         self.0.send(Event::UserOutput(output)).unwrap()
     }
 
+    fn on_key_read(&mut self, key: Option<String>) {
+        self.0.send(Event::KeyRead(key)).unwrap()
+    }
+
+    fn on_repl(&mut self, code: String) {
+        self.0.send(Event::ReplInput(code)).unwrap()
+    }
+
+    fn on_repl_cancel(&mut self) {
+        self.0.send(Event::ReplCancel).unwrap()
+    }
+
+    fn on_menu_chosen(&mut self, idx: Option<usize>) {
+        self.0.send(Event::MenuChosen(idx)).unwrap()
+    }
+
     fn on_quit(&mut self) {
         self.0.send(Event::Quit).unwrap();
     }
@@ -101,42 +144,270 @@ impl UICallback for EventBusCallback {
 pub enum UIEvent {
     Line(Line),
     Lines(Lines),
+    // 运行时输出的文本，附带首行在回滚缓冲中的行号
+    NumberedLines(usize, Lines),
+    // 服务器状态栏的全部内容
+    Status(Vec<Line>),
     Key(Key),
     Tick,
     WindowResize,
     Mouse(MouseEvent),
+    // 无法确定编码的粘贴内容
+    PasteChoice(PasteChoices),
+    // 脚本等待按键的提示，None表示超时
+    ReadKey(Option<String>),
+    // 弹出菜单的标题及选项
+    Menu(String, Vec<String>),
+    // 空命令重发的上一条命令
+    FlashCmd(String),
+    // 服务器是否关闭了回显
+    SecretInput(bool),
+    // REPL的输入回显及求值结果
+    Repl(Lines),
+    // 路由到指定窗口的文本
+    Window(String, Lines),
+    // 后台会话的文本及首行行号，保存于其主窗格
+    SessionLines(usize, usize, Lines),
+    // 切换到指定会话的主窗格，附带该会话的屏幕内容
+    SwitchSession(usize, ScreenView),
+    // 通过#set调整后的界面设置
+    UpdateTerm(conf::Term),
+}
+
+/// 文本事件通道容量，超过时发送方阻塞
+pub const UI_OUTPUT_CAPACITY: usize = 1024;
+
+// 滚轮每次翻阅的行数
+const WHEEL_LINES: usize = 3;
+
+/// 创建UI事件通道
+///
+/// 按键、鼠标及窗口变化走无界通道，保证用户输入不会被大量文本事件阻塞；
+/// 文本及Tick事件走有界通道，文本在通道满时阻塞发送方，Tick在通道满时丢弃
+pub fn ui_channel(capacity: usize) -> (UISender, UIReceiver) {
+    let (inputtx, inputrx) = unbounded();
+    let (outputtx, outputrx) = bounded(capacity);
+    (
+        UISender {
+            input: inputtx,
+            output: outputtx,
+        },
+        UIReceiver {
+            input: inputrx,
+            output: outputrx,
+            capacity,
+            stashed: RefCell::new(None),
+        },
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct UISender {
+    input: Sender<UIEvent>,
+    output: Sender<UIEvent>,
+}
+
+impl UISender {
+    pub fn send(&self, evt: UIEvent) -> Result<()> {
+        match evt {
+            UIEvent::Line(_) | UIEvent::Lines(_) | UIEvent::NumberedLines(..) => self.output.send(evt)?,
+            // 会话切换与各会话文本按发送顺序处理，切换前的文本不会显示在切换后的窗格中
+            UIEvent::SessionLines(..) | UIEvent::SwitchSession(..) => self.output.send(evt)?,
+            UIEvent::Tick => match self.output.try_send(evt) {
+                Ok(_) | Err(TrySendError::Full(_)) => (),
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(Error::SendError("ui channel disconnected".to_owned()))
+                }
+            },
+            // 状态栏仅刷新固定区域，不参与文本合并
+            UIEvent::Status(_)
+            | UIEvent::Key(_)
+            | UIEvent::Mouse(_)
+            | UIEvent::PasteChoice(_)
+            | UIEvent::ReadKey(_)
+            | UIEvent::Menu(..)
+            | UIEvent::FlashCmd(_)
+            | UIEvent::SecretInput(_)
+            | UIEvent::Repl(_)
+            | UIEvent::Window(..)
+            | UIEvent::UpdateTerm(_)
+            | UIEvent::WindowResize => self.input.send(evt)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct UIReceiver {
+    input: Receiver<UIEvent>,
+    output: Receiver<UIEvent>,
+    capacity: usize,
+    // 合并时遇到的无法合并的文本事件，下次优先返回
+    stashed: RefCell<Option<UIEvent>>,
+}
+
+impl UIReceiver {
+    /// 接收下一个事件
+    ///
+    /// 优先返回用户输入事件，连续的同类文本事件合并为一个事件返回，
+    /// 行号不连续的文本不合并，期间的Tick事件被丢弃
+    pub fn recv(&self) -> Result<UIEvent> {
+        if let Ok(evt) = self.input.try_recv() {
+            return Ok(evt);
+        }
+        let evt = match self.stashed.borrow_mut().take() {
+            Some(evt) => evt,
+            None => select! {
+                recv(self.input) -> evt => return Ok(evt?),
+                recv(self.output) -> evt => evt?,
+            },
+        };
+        let (first, mut merged) = match evt {
+            UIEvent::Lines(lines) => (None, lines),
+            UIEvent::Line(line) => (None, Lines::from(vec![line])),
+            UIEvent::NumberedLines(first, lines) => (Some(first), lines),
+            other => return Ok(other),
+        };
+        let mut n = 1;
+        // 存在用户输入时停止合并，尽快响应
+        while n < self.capacity && self.input.is_empty() {
+            let (lineno, lines) = match self.output.try_recv() {
+                Ok(UIEvent::Lines(lines)) => (None, lines),
+                Ok(UIEvent::Line(line)) => (None, Lines::from(vec![line])),
+                Ok(UIEvent::NumberedLines(lineno, lines)) => (Some(lineno), lines),
+                Ok(UIEvent::Tick) => {
+                    log::trace!("ui event tick merged");
+                    continue;
+                }
+                // 会话事件不合并，按顺序在合并的文本之后返回
+                Ok(evt) => {
+                    *self.stashed.borrow_mut() = Some(evt);
+                    break;
+                }
+                Err(_) => break,
+            };
+            // 后续文本可能续接未结束的行，其行号与上一行相同
+            let contiguous = match (first, lineno) {
+                (None, None) => true,
+                (Some(first), Some(lineno)) => {
+                    let next = first + merged.len();
+                    lineno == next || lineno + 1 == next
+                }
+                _ => false,
+            };
+            if !contiguous {
+                *self.stashed.borrow_mut() = Some(match lineno {
+                    Some(lineno) => UIEvent::NumberedLines(lineno, lines),
+                    None => UIEvent::Lines(lines),
+                });
+                break;
+            }
+            for line in lines.into_vec() {
+                merged.push_line(line);
+            }
+            n += 1;
+        }
+        match first {
+            Some(first) => Ok(UIEvent::NumberedLines(first, merged)),
+            None => Ok(UIEvent::Lines(merged)),
+        }
+    }
+}
+
+// 创建主窗格，各会话的主窗格使用相同配置
+fn main_flow(area: Rect, term: &conf::Term) -> Flow {
+    let mut flow = Flow::new(area, 2000, term.cjk_width)
+        .with_hyphen(term.hyphen_after)
+        .with_live_rows(term.scroll_live_rows);
+    flow.set_wrap(term.wrap);
+    flow.set_timestamps(term.timestamps);
+    flow.set_follow(term.follow);
+    flow
 }
 
 pub struct Screen<C> {
     flow: Flow,
     flowarea: Rect,
+    // 当前会话序号，其余会话的主窗格暂存于parked
+    session: usize,
+    parked: HashMap<usize, Flow>,
+    // 聊天窗格，分屏时显示于主窗格上方，内容为运行时路由到chat_window的行
+    chat: Flow,
+    chat_window: String,
+    chatarea: Rect,
+    split: bool,
+    // REPL窗格，打开时占据聊天窗格的位置
+    repl: Flow,
+    repl_open: bool,
+    // 未使用的命令行，REPL打开时为普通命令行，否则为REPL命令行，两者分别保存历史
+    idle_bar: CmdBar,
+    // 服务器状态栏，位于命令行上方，未启用时高度为0
+    status: Flow,
+    statusarea: Rect,
     cmdbar: CmdBar,
     cmdarea: Rect,
     terminal: Terminal,
+    // 布局配置，终端大小变化时重新计算各区域
+    term_conf: conf::Term,
+    // 最近一次提示的布局冲突，避免重复提示
+    conflicts: Vec<String>,
+    // 主窗格可见内容，与运行时共享
+    view: ScreenView,
+    // 配置了朗读命令时可用
+    announcer: Option<Announcer>,
+    // 等待用户选择编码的粘贴内容
+    paste_choices: Option<PasteChoices>,
+    // 弹出菜单，显示于其他组件之上
+    menu: Option<Menu>,
+    border: Border,
     uicb: C,
 }
 
 impl Screen<EventBusCallback> {
-    pub fn init(evttx: Sender<Event>) -> Result<Self> {
+    pub fn init(evttx: Sender<Event>, config: &Config, view: ScreenView) -> Result<Self> {
         let (width, height) = termion::terminal_size()?;
-        // 流占据主屏幕大半部分
-        let flowarea = Rect {
-            x: 1,
-            y: 1,
-            width,
-            height: height - 3,
-        };
-        let flow = Flow::new(flowarea, 2000, true);
-        // 命令行占据屏幕最下部3行
-        let cmdarea = Rect {
-            x: 1,
-            y: height - 2,
-            width,
-            height: 3,
-        };
-        let cmdbar = CmdBar::new('.', true, 200);
+        let mut term_conf = config.term.clone();
+        // 提示符状态栏占用一行
+        if term_conf.server_status_rows == 0
+            && !config.prompt.pattern.is_empty()
+            && !config.prompt.status.is_empty()
+        {
+            term_conf.server_status_rows = 1;
+        }
+        let (layout, conflicts) = ScreenLayout::compute(&term_conf, width, height, false);
+        let cjk = config.term.cjk_width;
+        let status = Flow::new(layout.status, layout.status.height as usize, cjk);
+        let flow = main_flow(layout.flow, &config.term);
+        // 朗读时按路由到聊天窗口的规则区分聊天类别
+        let classifier = Classifier::new(&config.classify)?;
+        let chat_patterns = config
+            .routes
+            .iter()
+            .filter(|r| r.action == RouteAction::Window && r.target == config.term.chat_window)
+            .map(|r| route::rule_pattern(r, &classifier))
+            .collect::<Result<Vec<_>>>()?;
+        let chat_filter = RegexSet::new(chat_patterns)?;
+        let announcer = Announcer::new(&config.term, chat_filter.clone())?;
+        let chat = Flow::new(layout.chat, 2000, cjk)
+            .with_hyphen(config.term.hyphen_after);
+        let caps = TermCaps::detect().with_mouse(config.term.mouse);
+        log::info!("terminal capabilities {:?}", caps);
+        // 不支持Unicode时使用ASCII边框
+        let border = if caps.unicode { Border::Rounded } else { Border::Ascii };
+        let mut cmdbar = CmdBar::new('.', cjk, config.term.history_size).with_border(border);
+        if !config.term.history_file.is_empty() {
+            let path = DataDir::new(config).state_path(&config.term.history_file);
+            cmdbar = cmdbar.with_history_file(path);
+        }
+        // REPL中的输入均为脚本，不使用脚本前缀
+        let mut idle_bar = CmdBar::new('\0', cjk, config.term.history_size).with_border(border);
+        if !config.term.repl_history_file.is_empty() {
+            let path = DataDir::new(config).state_path(&config.term.repl_history_file);
+            idle_bar = idle_bar.with_history_file(path);
+        }
+        let repl = Flow::new(layout.chat, 2000, cjk);
         let mut uicb = EventBusCallback(evttx);
-        let terminal = match Terminal::init() {
+        let terminal = match Terminal::init(caps) {
             Err(e) => {
                 log::error!("error init raw terminal {}", e);
                 uicb.on_quit();
@@ -144,28 +415,140 @@ impl Screen<EventBusCallback> {
                     "screen initialization failed".to_owned(),
                 ));
             }
-            Ok(terminal) => {
+            Ok(mut terminal) => {
                 log::debug!("raw terminal intiailized");
+                terminal.set_theme(Theme::from_conf(&config.term.theme));
                 terminal
             }
         };
 
         let mut screen = Self {
             flow,
-            flowarea,
+            flowarea: layout.flow,
+            session: 0,
+            parked: HashMap::new(),
+            chat,
+            chat_window: config.term.chat_window.clone(),
+            chatarea: layout.chat,
+            split: false,
+            repl,
+            repl_open: false,
+            idle_bar,
+            status,
+            statusarea: layout.status,
             cmdbar,
-            cmdarea,
+            cmdarea: layout.cmd,
             terminal,
+            term_conf,
+            conflicts: vec![],
+            view,
+            announcer,
+            paste_choices: None,
+            menu: None,
+            border,
             uicb,
         };
+        screen.report_conflicts(conflicts);
         screen.flush()?;
         Ok(screen)
     }
 
     pub fn process_event(&mut self, event: UIEvent) -> Result<bool> {
+        // 仅影响命令行的按键，只刷新命令行区域
+        let cmdbar_only = matches!(
+            event,
+            UIEvent::Key(Key::Char(_) | Key::Backspace | Key::Up | Key::Down | Key::Ctrl('r'))
+        );
+        // 按键时，或显示足够时长后收到文本时，清除重发命令的显示
+        match &event {
+            UIEvent::Key(_) => self.cmdbar.expire_flash(true),
+            UIEvent::Line(_) | UIEvent::Lines(_) | UIEvent::NumberedLines(..) => self.cmdbar.expire_flash(false),
+            _ => (),
+        }
+        // 菜单弹出时，除退出外的按键用于选择
+        if let (Some(menu), UIEvent::Key(key)) = (self.menu.as_mut(), &event) {
+            if *key != Key::Ctrl('q') {
+                match menu.on_key(*key) {
+                    MenuAction::Pending => (),
+                    MenuAction::Chosen(idx) => {
+                        self.menu = None;
+                        self.uicb.on_menu_chosen(Some(idx + 1));
+                    }
+                    MenuAction::Cancel => {
+                        self.menu = None;
+                        self.uicb.on_menu_chosen(None);
+                    }
+                }
+                self.flush()?;
+                return Ok(false);
+            }
+        }
+        // 脚本等待按键时，除退出外的按键交给脚本
+        if let (Some(_), UIEvent::Key(key)) = (self.cmdbar.prompt(), &event) {
+            if *key != Key::Ctrl('q') {
+                self.cmdbar.set_prompt(None);
+                self.uicb.on_key_read(key_name(*key));
+                self.flush_cmdbar()?;
+                return Ok(false);
+            }
+        }
+        // 选择粘贴内容的编码，其他按键取消选择
+        if let (Some(choices), UIEvent::Key(key)) = (self.paste_choices.as_ref(), &event) {
+            let picked = match key {
+                Key::Char(c) => c
+                    .to_digit(10)
+                    .and_then(|n| choices.get((n as usize).checked_sub(1)?)),
+                _ => None,
+            };
+            if let Some((_, text)) = picked {
+                for c in text.chars() {
+                    self.cmdbar.push_char(c);
+                }
+            }
+            self.paste_choices = None;
+            self.flush_cmdbar()?;
+            return Ok(false);
+        }
+        // 反向搜索历史命令，回车以匹配的命令填充命令行，Esc取消
+        if let (true, UIEvent::Key(key)) = (self.cmdbar.is_searching(), &event) {
+            if *key != Key::Ctrl('q') {
+                match key {
+                    Key::Char('\n') => self.cmdbar.accept_search(),
+                    Key::Char(c) => self.cmdbar.search_push(*c),
+                    Key::Backspace => self.cmdbar.search_pop(),
+                    Key::Ctrl('r') => self.cmdbar.search_prev(),
+                    Key::Esc | Key::Ctrl('g') => self.cmdbar.cancel_search(),
+                    _ => (),
+                }
+                self.flush_cmdbar()?;
+                return Ok(false);
+            }
+        }
         match event {
             UIEvent::Key(key) => match key {
+                Key::Char('\n') if self.repl_open => {
+                    let code = self.cmdbar.take();
+                    self.uicb.on_repl(code.as_ref().to_owned());
+                }
+                // 放弃REPL中尚未完整的多行代码及当前输入
+                Key::Esc | Key::Ctrl('c') if self.repl_open => {
+                    self.cmdbar.clear();
+                    self.uicb.on_repl_cancel();
+                }
                 Key::Char('\n') => self.uicb.on_output(self.cmdbar.take()),
+                Key::Ctrl('r') => self.cmdbar.search_prev(),
+                // 补全#set及#get的设置项
+                Key::Char('\t') if self.cmdbar.text().starts_with("#set ")
+                    || self.cmdbar.text().starts_with("#get ") =>
+                {
+                    if let Some(suffix) = settings::complete(self.cmdbar.text()) {
+                        for c in suffix.chars() {
+                            self.cmdbar.push_char(c);
+                        }
+                    }
+                }
+                // 以主窗格中最近出现的单词补全
+                Key::Char('\t') => self.cmdbar.complete(),
                 Key::Char(c) => {
                     self.cmdbar.push_char(c);
                 }
@@ -178,7 +561,40 @@ impl Screen<EventBusCallback> {
                 Key::Down => {
                     self.cmdbar.next_cmd();
                 }
+                Key::F(2) => {
+                    self.flow.toggle_gutter();
+                }
+                Key::F(3) => {
+                    self.toggle_split();
+                }
+                Key::F(4) => {
+                    self.toggle_cjk()?;
+                }
+                Key::F(6) => {
+                    self.toggle_repl();
+                }
+                // 翻阅历史，End回到最新的行（终端中无法区分Ctrl-End）
+                Key::PageUp => self.flow.scroll_up(self.flow.page_size()),
+                Key::PageDown => self.flow.scroll_down(self.flow.page_size()),
+                Key::End => self.flow.scroll_to_bottom(),
+                // 与滚轮相同的逐行翻阅，供关闭鼠标或终端不支持鼠标时使用
+                Key::Alt('k') => self.flow.scroll_up(WHEEL_LINES),
+                Key::Alt('j') => self.flow.scroll_down(WHEEL_LINES),
+                // 分屏时单独翻阅聊天窗格
+                Key::Alt('K') if self.chat_visible() => self.chat.scroll_up(WHEEL_LINES),
+                Key::Alt('J') if self.chat_visible() => self.chat.scroll_down(WHEEL_LINES),
+                // 暂停或恢复所有定时器，便于查看历史或调试
+                Key::F(5) => self.uicb.on_output(UserOutput::Cmd("#pause".to_owned())),
+                // 行书签，终端中Ctrl-M与回车无法区分，因此使用Alt组合键
+                Key::Alt('m') => self.uicb.on_output(UserOutput::Cmd("#mark".to_owned())),
+                Key::Alt('p') => self.uicb.on_output(UserOutput::Cmd("#jump prev".to_owned())),
+                Key::Alt('n') => self.uicb.on_output(UserOutput::Cmd("#jump next".to_owned())),
+                Key::Alt(c @ '1'..='9') => self.uicb.on_output(UserOutput::Cmd(format!("#session {}", c))),
                 Key::Ctrl('q') => {
+                    if self.cmdbar.prompt().is_some() {
+                        self.cmdbar.set_prompt(None);
+                        self.uicb.on_key_read(None);
+                    }
                     self.uicb.on_quit();
                     return Ok(true);
                 }
@@ -186,25 +602,281 @@ impl Screen<EventBusCallback> {
                     log::debug!("unhandled key {:?}", k);
                 }
             },
-            UIEvent::Lines(lines) => self.flow.push_lines(lines.into_vec()),
-            UIEvent::Line(line) => self.flow.push_line(line),
+            UIEvent::Lines(lines) => {
+                let lines = lines.into_vec();
+                if let Some(announcer) = self.announcer.as_mut() {
+                    announcer.push_lines(&lines);
+                }
+                for line in &lines {
+                    self.main_bar().index_line(line);
+                }
+                self.flow.push_lines(lines);
+            }
+            UIEvent::NumberedLines(first, lines) => {
+                let lines = lines.into_vec();
+                if let Some(announcer) = self.announcer.as_mut() {
+                    announcer.push_lines(&lines);
+                }
+                for line in &lines {
+                    self.main_bar().index_line(line);
+                }
+                self.flow.push_numbered_lines(first, lines);
+            }
+            UIEvent::Line(line) => {
+                if let Some(announcer) = self.announcer.as_mut() {
+                    announcer.push_line(line.clone());
+                }
+                self.main_bar().index_line(&line);
+                self.flow.push_line(line);
+            }
+            UIEvent::Status(lines) => {
+                let mut status = Flow::new(
+                    self.statusarea,
+                    self.statusarea.height as usize,
+                    self.term_conf.cjk_width,
+                );
+                status.push_lines(lines);
+                self.status = status;
+            }
+            UIEvent::PasteChoice(choices) => {
+                self.flow.push_line(Line::fmt_note(i18n::tr("paste.ambiguous")));
+                for (i, (codec, text)) in choices.iter().enumerate() {
+                    let text = text.replace(&['\r', '\n'][..], " ");
+                    self.flow.push_line(Line::fmt_note(format!("  {} {}：{}", i + 1, codec, text)));
+                }
+                self.paste_choices = Some(choices);
+            }
+            UIEvent::Menu(title, items) => {
+                // 新菜单替换尚未选择的菜单，其回调已在运行时中被替换
+                self.menu = Some(Menu::new(title, items, self.term_conf.cjk_width).with_border(self.border));
+            }
+            UIEvent::ReadKey(prompt) => {
+                self.cmdbar.set_prompt(prompt);
+                self.flush_cmdbar()?;
+                return Ok(false);
+            }
+            UIEvent::FlashCmd(cmd) => {
+                self.cmdbar.flash(cmd);
+                self.flush_cmdbar()?;
+                return Ok(false);
+            }
+            UIEvent::SecretInput(secret) => {
+                self.cmdbar.set_secret(secret);
+                return Ok(false);
+            }
+            UIEvent::Window(target, lines) => {
+                if target != self.chat_window {
+                    log::trace!("lines of window {} dropped", target);
+                    return Ok(false);
+                }
+                self.chat.push_lines(lines.into_vec());
+                if !self.chat_visible() {
+                    return Ok(false);
+                }
+            }
+            UIEvent::Repl(lines) => {
+                self.repl.push_lines(lines.into_vec());
+                if !self.repl_open {
+                    return Ok(false);
+                }
+            }
+            // 切换前发出的文本可能在切换后到达
+            UIEvent::SessionLines(id, first, lines) if id == self.session => {
+                return self.process_event(UIEvent::NumberedLines(first, lines));
+            }
+            UIEvent::SessionLines(id, first, lines) => {
+                let (area, term_conf) = (self.flowarea, &self.term_conf);
+                self.parked
+                    .entry(id)
+                    .or_insert_with(|| main_flow(area, term_conf))
+                    .push_numbered_lines(first, lines.into_vec());
+                return Ok(false);
+            }
+            UIEvent::SwitchSession(id, view) => self.switch_session(id, view),
+            UIEvent::UpdateTerm(term) => self.update_term(term),
+            // 滚轮翻阅历史，位于聊天窗格时翻阅聊天窗格，其余鼠标事件不重绘
+            UIEvent::Mouse(MouseEvent::Press(MouseButton::WheelUp, x, y))
+                if self.chat_visible() && self.chatarea.contains(x, y) =>
+            {
+                self.chat.scroll_up(WHEEL_LINES);
+            }
+            UIEvent::Mouse(MouseEvent::Press(MouseButton::WheelDown, x, y))
+                if self.chat_visible() && self.chatarea.contains(x, y) =>
+            {
+                self.chat.scroll_down(WHEEL_LINES);
+            }
+            UIEvent::Mouse(MouseEvent::Press(MouseButton::WheelUp, ..)) => {
+                self.flow.scroll_up(WHEEL_LINES);
+            }
+            UIEvent::Mouse(MouseEvent::Press(MouseButton::WheelDown, ..)) => {
+                self.flow.scroll_down(WHEEL_LINES);
+            }
             UIEvent::Mouse(_) => {
                 // not to render the screen
                 return Ok(false);
             }
-            UIEvent::Tick | UIEvent::WindowResize => (),
+            UIEvent::WindowResize => {
+                let (width, height) = termion::terminal_size()?;
+                if (width, height) != self.terminal.size() {
+                    self.terminal.resize(width, height)?;
+                    self.relayout();
+                }
+            }
+            UIEvent::Tick => (),
+        }
+        if cmdbar_only {
+            self.flush_cmdbar()?;
+            return Ok(false);
         }
         self.flush()?;
-        let (cursor_x, cursor_y) = self.cmdbar.cursor_pos(self.cmdarea, true);
+        let (cursor_x, cursor_y) = self.cmdbar.cursor_pos(self.cmdarea);
         self.terminal.set_cursor(cursor_x, cursor_y)?;
         Ok(false)
     }
 
+    /// 切换分屏，分屏时聊天窗格占据主窗格上方或右侧区域
+    fn toggle_split(&mut self) {
+        self.split = !self.split;
+        self.relayout();
+    }
+
+    // 聊天窗格是否显示，REPL打开时被其覆盖
+    fn chat_visible(&self) -> bool {
+        self.split && !self.repl_open
+    }
+
+    /// 打开或关闭REPL窗格，同时切换命令行，各自保留输入内容及历史
+    fn toggle_repl(&mut self) {
+        self.repl_open = !self.repl_open;
+        std::mem::swap(&mut self.cmdbar, &mut self.idle_bar);
+        self.relayout();
+    }
+
+    /// 切换主窗格至指定会话，暂存的窗格按当前布局及字符宽度重新折行
+    ///
+    /// 刷新时更新该会话的屏幕内容，其余会话保留切换前的内容
+    fn switch_session(&mut self, id: usize, view: ScreenView) {
+        if id == self.session {
+            return;
+        }
+        self.view = view;
+        let flow = self
+            .parked
+            .remove(&id)
+            .unwrap_or_else(|| main_flow(self.flowarea, &self.term_conf));
+        let prev = std::mem::replace(&mut self.flow, flow);
+        self.parked.insert(self.session, prev);
+        self.session = id;
+        self.flow.reshape(self.flowarea);
+        self.flow.set_cjk(self.term_conf.cjk_width);
+    }
+
+    /// 应用#set调整的主题、折行、时间戳及跟随设置，各会话的主窗格同时更新
+    fn update_term(&mut self, term: conf::Term) {
+        if term.theme != self.term_conf.theme {
+            self.terminal.set_theme(Theme::from_conf(&term.theme));
+        }
+        self.term_conf.theme = term.theme;
+        self.term_conf.wrap = term.wrap;
+        self.term_conf.timestamps = term.timestamps;
+        self.term_conf.follow = term.follow;
+        for flow in std::iter::once(&mut self.flow).chain(self.parked.values_mut()) {
+            flow.set_wrap(term.wrap);
+            flow.set_timestamps(term.timestamps);
+            flow.set_follow(term.follow);
+        }
+    }
+
+    // 接收普通命令的命令行，用于收集补全的单词
+    fn main_bar(&mut self) -> &mut CmdBar {
+        if self.repl_open {
+            &mut self.idle_bar
+        } else {
+            &mut self.cmdbar
+        }
+    }
+
+    /// 切换歧义宽度字符的列宽，各组件按新宽度重新折行，终端清屏后全部重绘
+    fn toggle_cjk(&mut self) -> Result<()> {
+        let cjk = !self.term_conf.cjk_width;
+        self.term_conf.cjk_width = cjk;
+        self.flow.set_cjk(cjk);
+        self.chat.set_cjk(cjk);
+        self.repl.set_cjk(cjk);
+        self.status.set_cjk(cjk);
+        self.cmdbar.set_cjk(cjk);
+        self.idle_bar.set_cjk(cjk);
+        if let Some(menu) = self.menu.as_mut() {
+            menu.set_cjk(cjk);
+        }
+        self.terminal.clear()?;
+        let key = if cjk { "ui.cjk_on" } else { "ui.cjk_off" };
+        self.flow.push_line(Line::fmt_note(i18n::tr(key)));
+        Ok(())
+    }
+
+    /// 按当前终端大小及分屏状态重新计算布局
+    fn relayout(&mut self) {
+        let (width, height) = self.terminal.size();
+        let split = self.split || self.repl_open;
+        let (layout, conflicts) = ScreenLayout::compute(&self.term_conf, width, height, split);
+        self.flowarea = layout.flow;
+        self.flow.reshape(layout.flow);
+        self.chatarea = layout.chat;
+        self.chat.reshape(layout.chat);
+        self.repl.reshape(layout.chat);
+        self.statusarea = layout.status;
+        self.status.reshape(layout.status);
+        self.cmdarea = layout.cmd;
+        self.report_conflicts(conflicts);
+    }
+
+    // 布局冲突变化时在主窗格中提示
+    fn report_conflicts(&mut self, conflicts: Vec<String>) {
+        if conflicts == self.conflicts {
+            return;
+        }
+        for conflict in &conflicts {
+            log::warn!("layout conflict: {}", conflict);
+            self.flow.push_line(Line::fmt_note(conflict));
+        }
+        self.conflicts = conflicts;
+    }
+
     pub fn flush(&mut self) -> Result<()> {
         self.terminal.render_widget(&mut self.flow, self.flowarea)?;
+        self.view.update(self.flow.visible_rows().cloned());
         self.terminal
             .render_widget(&mut self.cmdbar, self.cmdarea)?;
-        self.terminal.flush(vec![self.flowarea, self.cmdarea])?;
+        let mut areas = vec![self.flowarea, self.cmdarea];
+        if self.statusarea.height > 0 {
+            self.terminal.render_widget(&mut self.status, self.statusarea)?;
+            areas.push(self.statusarea);
+        }
+        if self.repl_open {
+            self.terminal.render_widget(&mut self.repl, self.chatarea)?;
+            areas.insert(0, self.chatarea);
+        } else if self.split {
+            self.terminal.render_widget(&mut self.chat, self.chatarea)?;
+            areas.insert(0, self.chatarea);
+        }
+        // 弹出层最后绘制，覆盖主窗格
+        if let Some(menu) = self.menu.as_mut() {
+            let area = menu.area(self.flowarea);
+            self.terminal.render_widget(menu, area)?;
+            areas.push(area);
+        }
+        self.terminal.flush(areas)?;
+        Ok(())
+    }
+
+    /// 仅重绘命令行并移动光标
+    fn flush_cmdbar(&mut self) -> Result<()> {
+        let start = Instant::now();
+        self.terminal.render_widget(&mut self.cmdbar, self.cmdarea)?;
+        let cursor = self.cmdbar.cursor_pos(self.cmdarea);
+        self.terminal.flush_area(self.cmdarea, cursor)?;
+        log::trace!("cmdbar flushed in {}us", start.elapsed().as_micros());
         Ok(())
     }
 
@@ -213,3 +885,84 @@ impl Screen<EventBusCallback> {
         self.terminal.render_widget(widget, area)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_name() {
+        assert_eq!(Some("y".to_owned()), key_name(Key::Char('y')));
+        assert_eq!(Some("Enter".to_owned()), key_name(Key::Char('\n')));
+        assert_eq!(Some("Ctrl-c".to_owned()), key_name(Key::Ctrl('c')));
+        assert_eq!(Some("F1".to_owned()), key_name(Key::F(1)));
+        assert_eq!(None, key_name(Key::Insert));
+    }
+
+    #[test]
+    fn test_ui_channel_input_first() {
+        let (tx, rx) = ui_channel(16);
+        tx.send(UIEvent::Line(Line::fmt_raw("a"))).unwrap();
+        tx.send(UIEvent::Key(Key::Char('x'))).unwrap();
+        match rx.recv().unwrap() {
+            UIEvent::Key(Key::Char('x')) => (),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ui_channel_merge_lines() {
+        let (tx, rx) = ui_channel(16);
+        tx.send(UIEvent::Line(Line::fmt_raw("a"))).unwrap();
+        tx.send(UIEvent::Tick).unwrap();
+        tx.send(UIEvent::Lines(Lines::from(vec![
+            Line::fmt_raw("b"),
+            Line::fmt_raw("c"),
+        ])))
+        .unwrap();
+        match rx.recv().unwrap() {
+            UIEvent::Lines(lines) => assert_eq!(3, lines.into_vec().len()),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ui_channel_merge_numbered() {
+        let (tx, rx) = ui_channel(16);
+        tx.send(UIEvent::NumberedLines(5, Lines::from(vec![Line::fmt_raw("a\n")]))).unwrap();
+        tx.send(UIEvent::NumberedLines(6, Lines::from(vec![Line::fmt_raw("b\n")]))).unwrap();
+        // 行号不连续时不合并
+        tx.send(UIEvent::NumberedLines(1, Lines::from(vec![Line::fmt_raw("c\n")]))).unwrap();
+        match rx.recv().unwrap() {
+            UIEvent::NumberedLines(5, lines) => assert_eq!(2, lines.len()),
+            other => panic!("unexpected event {:?}", other),
+        }
+        match rx.recv().unwrap() {
+            UIEvent::NumberedLines(1, lines) => assert_eq!(1, lines.len()),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ui_channel_session_order() {
+        let (tx, rx) = ui_channel(16);
+        tx.send(UIEvent::NumberedLines(5, Lines::from(vec![Line::fmt_raw("a\n")]))).unwrap();
+        tx.send(UIEvent::SessionLines(1, 1, Lines::from(vec![Line::fmt_raw("x\n")]))).unwrap();
+        tx.send(UIEvent::NumberedLines(6, Lines::from(vec![Line::fmt_raw("b\n")]))).unwrap();
+        // 会话事件之后的文本不合并到之前的文本中
+        match rx.recv().unwrap() {
+            UIEvent::NumberedLines(5, lines) => assert_eq!(1, lines.len()),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(rx.recv().unwrap(), UIEvent::SessionLines(1, 1, _)));
+        assert!(matches!(rx.recv().unwrap(), UIEvent::NumberedLines(6, _)));
+    }
+
+    #[test]
+    fn test_ui_channel_drop_tick() {
+        let (tx, _rx) = ui_channel(1);
+        tx.send(UIEvent::Tick).unwrap();
+        // 通道已满，Tick被丢弃而不阻塞
+        tx.send(UIEvent::Tick).unwrap();
+    }
+}
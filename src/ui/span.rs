@@ -66,6 +66,34 @@ impl Span {
     }
 }
 
+impl<'lua> mlua::ToLua<'lua> for &Span {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("text", &self.content[..])?;
+        if let Some(fg) = self.style.fg {
+            table.set("fg", fg.description())?;
+        }
+        if let Some(bg) = self.style.bg {
+            table.set("bg", bg.description())?;
+        }
+        let mut modifier = self.style.add_modifier;
+        modifier.remove(self.style.sub_modifier);
+        if !modifier.is_empty() {
+            table.set("modifiers", modifier.bits())?;
+        }
+        if self.label != Label::None {
+            table.set("label", &self.label)?;
+        }
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+impl<'lua> mlua::ToLua<'lua> for Span {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        mlua::ToLua::to_lua(&self, lua)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,6 +60,8 @@ impl std::fmt::Display for Style {
                 Color::LightMagenta => write!(f, "95")?,
                 Color::LightCyan => write!(f, "96")?,
                 Color::White => write!(f, "97")?,
+                Color::Indexed(n) => write!(f, "38;5;{}", n)?,
+                Color::Rgb(r, g, b) => write!(f, "38;2;{};{};{}", r, g, b)?,
             }
             require_colon = true;
         }
@@ -85,6 +87,8 @@ impl std::fmt::Display for Style {
                 Color::LightMagenta => write!(f, "105")?,
                 Color::LightCyan => write!(f, "106")?,
                 Color::White => write!(f, "107")?,
+                Color::Indexed(n) => write!(f, "48;5;{}", n)?,
+                Color::Rgb(r, g, b) => write!(f, "48;2;{};{};{}", r, g, b)?,
             }
             require_colon = true;
         }
@@ -214,6 +218,37 @@ pub enum Color {
     LightMagenta,
     LightCyan,
     White,
+    // 256色中基础16色以外的颜色
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// 基础16色按SGR序号排列及对应的RGB值，与xterm默认调色板一致
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0x00, 0x00, 0x00)),
+    (Color::Red, (0xcd, 0x00, 0x00)),
+    (Color::Green, (0x00, 0xcd, 0x00)),
+    (Color::Yellow, (0xcd, 0xcd, 0x00)),
+    (Color::Blue, (0x00, 0x00, 0xee)),
+    (Color::Magenta, (0xcd, 0x00, 0xcd)),
+    (Color::Cyan, (0x00, 0xcd, 0xcd)),
+    (Color::Gray, (0xe5, 0xe5, 0xe5)),
+    (Color::DarkGray, (0x7f, 0x7f, 0x7f)),
+    (Color::LightRed, (0xff, 0x00, 0x00)),
+    (Color::LightGreen, (0x00, 0xff, 0x00)),
+    (Color::LightYellow, (0xff, 0xff, 0x00)),
+    (Color::LightBlue, (0x5c, 0x5c, 0xff)),
+    (Color::LightMagenta, (0xff, 0x00, 0xff)),
+    (Color::LightCyan, (0x00, 0xff, 0xff)),
+    (Color::White, (0xff, 0xff, 0xff)),
+];
+
+// 256色中6x6x6色块各分量的取值
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r0, g0, b0): (u8, u8, u8), (r1, g1, b1): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r0, r1) + d(g0, g1) + d(b0, b1)
 }
 
 impl Color {
@@ -224,8 +259,20 @@ impl Color {
         }
     }
 
+    /// 解析颜色名称，另支持#rrggbb及256色序号color0至color255
     pub fn from_str(name: impl AsRef<str>) -> Option<Self> {
-        let color = match name.as_ref() {
+        let name = name.as_ref();
+        if let Some(hex) = name.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return None;
+            }
+            let v = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Self::Rgb(v(0)?, v(2)?, v(4)?));
+        }
+        if let Some(n) = name.strip_prefix("color") {
+            return n.parse::<u8>().ok().map(Self::from_index);
+        }
+        let color = match name {
             "black" => Self::Black,
             "red" => Self::Red,
             "green" => Self::Green,
@@ -247,8 +294,70 @@ impl Color {
         Some(color)
     }
 
-    pub fn description(self) -> &'static str {
+    /// 256色序号对应的颜色，0至15为基础16色
+    pub fn from_index(n: u8) -> Self {
+        match ANSI16.get(n as usize) {
+            Some((color, _)) => *color,
+            None => Self::Indexed(n),
+        }
+    }
+
+    /// 颜色的RGB值，Reset返回None
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        let rgb = match self {
+            Self::Reset => return None,
+            Self::Indexed(n) if n < 16 => ANSI16[n as usize].1,
+            Self::Indexed(n) if n < 232 => {
+                let n = n - 16;
+                let level = |v: u8| CUBE_LEVELS[v as usize];
+                (level(n / 36), level(n / 6 % 6), level(n % 6))
+            }
+            Self::Indexed(n) => {
+                let v = 8 + (n - 232) * 10;
+                (v, v, v)
+            }
+            Self::Rgb(r, g, b) => (r, g, b),
+            basic => ANSI16.iter().find(|(c, _)| *c == basic)?.1,
+        };
+        Some(rgb)
+    }
+
+    /// 转换为最接近的256色，基础16色保持不变
+    pub fn to_indexed(self) -> Self {
+        let rgb = match self {
+            Self::Rgb(r, g, b) => (r, g, b),
+            other => return other,
+        };
+        let nearest = |v: u8| {
+            (0..6u8)
+                .min_by_key(|&i| (CUBE_LEVELS[i as usize] as i32 - v as i32).abs())
+                .unwrap()
+        };
+        let cube = Self::Indexed(16 + 36 * nearest(rgb.0) + 6 * nearest(rgb.1) + nearest(rgb.2));
+        let avg = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+        let gray = Self::Indexed(232 + (avg.saturating_sub(3) / 10).min(23) as u8);
+        [cube, gray]
+            .iter()
+            .copied()
+            .min_by_key(|c| distance(c.rgb().unwrap(), rgb))
+            .unwrap()
+    }
+
+    /// 转换为最接近的基础16色
+    pub fn to_ansi16(self) -> Self {
         match self {
+            Self::Indexed(n) if n < 16 => Self::from_index(n),
+            Self::Indexed(_) | Self::Rgb(..) => {
+                let rgb = self.rgb().unwrap();
+                ANSI16.iter().min_by_key(|(_, c)| distance(*c, rgb)).unwrap().0
+            }
+            other => other,
+        }
+    }
+
+    /// 颜色名称，可由from_str解析
+    pub fn description(self) -> String {
+        let name = match self {
             Color::Reset => "reset",
             Color::Black => "black",
             Color::Red => "red",
@@ -266,7 +375,10 @@ impl Color {
             Color::LightMagenta => "lightmagenta",
             Color::LightCyan => "lightcyan",
             Color::White => "white",
-        }
+            Color::Indexed(n) => return format!("color{}", n),
+            Color::Rgb(r, g, b) => return format!("#{:02x}{:02x}{:02x}", r, g, b),
+        };
+        name.to_owned()
     }
 }
 
@@ -299,5 +411,24 @@ mod tests {
             Style::default().add_modifier(Modifier::REVERSED)
         );
         println!("{}", s);
+        assert_eq!(
+            "\x1b[38;5;208;48;2;1;2;3m",
+            Style::default().fg(Color::Indexed(208)).bg(Color::Rgb(1, 2, 3)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_color_conversion() {
+        assert_eq!(Some(Color::Rgb(0xff, 0x87, 0x00)), Color::from_str("#ff8700"));
+        assert_eq!(Some(Color::Red), Color::from_str("color1"));
+        assert_eq!(Some(Color::Indexed(208)), Color::from_str("color208"));
+        assert_eq!(None, Color::from_str("#ff87"));
+        assert_eq!("#ff8700", Color::Rgb(0xff, 0x87, 0x00).description());
+        assert_eq!(Some((0xff, 0x87, 0x00)), Color::Indexed(208).rgb());
+        assert_eq!(Some((0x80, 0x80, 0x80)), Color::Indexed(244).rgb());
+        assert_eq!(Color::Indexed(208), Color::Rgb(0xfa, 0x88, 0x05).to_indexed());
+        assert_eq!(Color::Indexed(244), Color::Rgb(0x81, 0x7f, 0x80).to_indexed());
+        assert_eq!(Color::LightYellow, Color::Indexed(226).to_ansi16());
+        assert_eq!(Color::Blue, Color::Rgb(0, 0, 0xe0).to_ansi16());
     }
 }
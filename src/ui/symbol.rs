@@ -8,6 +8,9 @@ pub const BOTTOM_RIGHT: char = '┘';
 pub const ROUNDED_BOTTOM_RIGHT: char = '╯';
 pub const BOTTOM_LEFT: char = '└';
 pub const ROUNDED_BOTTOM_LEFT: char = '╰';
+pub const ASCII_VERTICAL: char = '|';
+pub const ASCII_HORIZONTAL: char = '-';
+pub const ASCII_CORNER: char = '+';
 
 #[cfg(test)]
 mod tests {
@@ -2,29 +2,109 @@ use crate::error::Result;
 use crate::proto::ansi::ClearCells;
 use crate::ui::buffer::BufferVec;
 use crate::ui::buffer::{Buffer, Cell};
+use crate::ui::caps::TermCaps;
 use crate::ui::layout::Rect;
+use crate::ui::theme::Theme;
 use crate::ui::widget::Widget;
+use lazy_static::lazy_static;
 use std::io::Write;
-use std::io::{self, Stdout};
+use std::io;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use termion::input::MouseTerminal;
-use termion::raw::{IntoRawMode, RawTerminal};
+use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
 use termion::terminal_size;
 
+// 关闭各类鼠标上报
+const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+// 开启及关闭括号粘贴模式，粘贴内容前后附加标记
+const PASTE_ON: &str = "\x1b[?2004h";
+const PASTE_OFF: &str = "\x1b[?2004l";
+
+// 终端是否处于原始模式及备用屏幕
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // 进入原始模式前的终端属性
+    static ref ORIG_TERMIOS: Mutex<Option<libc::termios>> = Mutex::new(None);
+}
+
+/// 恢复终端：关闭鼠标上报，退出备用屏幕，显示光标并恢复终端属性
+///
+/// 可在任意线程调用，仅在终端处于原始模式时生效，返回是否执行了恢复
+pub fn restore() -> bool {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return false;
+    }
+    let mut out = io::stdout();
+    let _ = write!(
+        out,
+        "{}{}{}{}{}",
+        MOUSE_OFF,
+        PASTE_OFF,
+        termion::style::Reset,
+        termion::screen::ToMainScreen,
+        termion::cursor::Show
+    );
+    let _ = out.flush();
+    let ios = ORIG_TERMIOS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(ios) = ios {
+        // 安全性：ios为进入原始模式前通过tcgetattr获取的有效属性
+        unsafe {
+            libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &ios);
+        }
+    }
+    true
+}
+
+/// 安装panic钩子，任意线程panic时先恢复终端，再显示panic信息
+///
+/// 标准错误通常已重定向至调试日志，因此在恢复后将信息输出到终端，
+/// 原有钩子仍会执行，信息同时写入日志
+pub fn install_panic_hook() {
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if restore() {
+            let mut out = io::stdout();
+            let _ = writeln!(out, "mudterm panicked: {}", info);
+            let _ = out.flush();
+        }
+        prev(info);
+    }));
+}
+
 /// wrapped termion's alternate screen with mouse support
+///
+/// 终端不支持鼠标时不开启鼠标上报
 pub struct Terminal {
-    out: AlternateScreen<MouseTerminal<RawTerminal<Stdout>>>,
+    out: AlternateScreen<Box<dyn Write>>,
+    caps: TermCaps,
     curr_buf: BufferVec,
     prev_buf: BufferVec,
     size: (u16, u16),
+    theme: Theme,
 }
 
 impl Terminal {
-    pub fn init() -> Result<Self> {
+    pub fn init(caps: TermCaps) -> Result<Self> {
+        // 保存原始终端属性，供异常时恢复
+        let mut ios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // 安全性：tcgetattr成功时ios已被完整初始化
+        if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, ios.as_mut_ptr()) } == 0 {
+            *ORIG_TERMIOS.lock().unwrap() = Some(unsafe { ios.assume_init() });
+        }
         let out = io::stdout().into_raw_mode()?;
-        let out = MouseTerminal::from(out);
-        let out = AlternateScreen::from(out);
+        let out: Box<dyn Write> = if caps.mouse {
+            Box::new(MouseTerminal::from(out))
+        } else {
+            Box::new(out)
+        };
+        let mut out = AlternateScreen::from(out);
+        write!(out, "{}", PASTE_ON)?;
         let (width, height) = terminal_size()?;
+        ACTIVE.store(true, Ordering::SeqCst);
         let rect = Rect {
             x: 1,
             y: 1,
@@ -33,25 +113,51 @@ impl Terminal {
         };
         Ok(Self {
             out,
+            caps,
             curr_buf: BufferVec::empty(rect),
             prev_buf: BufferVec::empty(rect),
             size: (width, height),
+            theme: Theme::default(),
         })
     }
 
+    pub fn caps(&self) -> &TermCaps {
+        &self.caps
+    }
+
     pub fn size(&self) -> (u16, u16) {
         self.size
     }
 
-    pub fn render_widget<W: Widget>(&mut self, widget: &mut W, area: Rect) -> Result<()> {
-        let curr_buffer = self.curr_buf_mut();
-        let mut subset = curr_buffer.subset(area)?;
-        widget.refresh_buffer(&mut subset)?;
+    /// 终端大小变化后按新尺寸重建缓冲区并清屏，下次刷新时全部重绘
+    pub fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.size = (width, height);
+        self.clear()
+    }
+
+    /// 重建缓冲区并清屏，下次刷新时全部重绘
+    pub fn clear(&mut self) -> Result<()> {
+        let rect = Rect {
+            x: 1,
+            y: 1,
+            width: self.size.0,
+            height: self.size.1,
+        };
+        self.curr_buf = BufferVec::empty(rect);
+        self.prev_buf = BufferVec::empty(rect);
+        write!(self.out, "{}", termion::clear::All)?;
         Ok(())
     }
 
-    fn curr_buf_mut(&mut self) -> &mut BufferVec {
-        &mut self.curr_buf
+    /// 设置渲染各组件使用的主题
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    pub fn render_widget<W: Widget>(&mut self, widget: &mut W, area: Rect) -> Result<()> {
+        let mut subset = self.curr_buf.subset(area)?;
+        widget.refresh_buffer(&mut subset, &self.theme)?;
+        Ok(())
     }
 
     /// 指定区域更新终端
@@ -72,13 +178,32 @@ impl Terminal {
         //     log::info!("{:?}", u);
         // }
         // log::info!();
-        draw_updates(&mut self.out, updates)?;
+        draw_updates(&mut self.out, updates, &self.caps)?;
         self.out.flush()?;
         std::mem::swap(&mut self.prev_buf, &mut self.curr_buf);
         self.curr_buf.reset();
         Ok(())
     }
 
+    /// 仅更新单个区域并移动光标，用于输入时的快速刷新
+    ///
+    /// 不交换双缓存，而是将该区域复制到上一帧缓存，其余区域保持不变，
+    /// 调用前需保证当前缓存的该区域已完整渲染
+    pub fn flush_area(&mut self, area: Rect, cursor: (u16, u16)) -> Result<()> {
+        let mut updates = vec![];
+        {
+            let curr_buf = self.curr_buf.subset(area)?;
+            let prev_buf = self.prev_buf.subset(area)?;
+            prev_buf.diff(&curr_buf, &mut updates);
+        }
+        draw_updates(&mut self.out, updates, &self.caps)?;
+        write!(self.out, "{}", termion::cursor::Goto(cursor.0, cursor.1))?;
+        self.out.flush()?;
+        self.prev_buf.copy_area(&self.curr_buf, area);
+        self.curr_buf.reset_area(area);
+        Ok(())
+    }
+
     pub fn set_cursor(&mut self, x: u16, y: u16) -> Result<()> {
         write!(self.out, "{}", termion::cursor::Goto(x, y))?;
         self.out.flush()?;
@@ -89,7 +214,7 @@ impl Terminal {
 /// 由于部分终端对宽字符渲染存在单元残留的问题，
 /// 这里尝试寻找连续的字符，并一次性擦除，再进行渲染
 /// 传入的updates数组需保证在连续的cell中如果纵坐标y一致，横坐标x单调递增
-fn draw_updates<W: Write>(out: &mut W, updates: Vec<(u16, u16, Cell)>) -> Result<()> {
+fn draw_updates<W: Write>(out: &mut W, updates: Vec<(u16, u16, Cell)>, caps: &TermCaps) -> Result<()> {
     let (mut line, mut start_x, mut start_y, mut next_x) = (Vec::<Cell>::new(), 0, 0, 0);
     for (x, y, cell) in updates
         .into_iter()
@@ -109,7 +234,7 @@ fn draw_updates<W: Write>(out: &mut W, updates: Vec<(u16, u16, Cell)>) -> Result
                 //执行写入
                 write!(out, "{}", termion::style::Reset)?;
                 for cell in line.drain(..) {
-                    write!(out, "{}{}", cell.style(), cell.symbol.ch)?;
+                    write!(out, "{}{}", caps.adapt_style(cell.style()), cell.symbol.ch)?;
                 }
             }
             // 设置新行
@@ -131,7 +256,7 @@ fn draw_updates<W: Write>(out: &mut W, updates: Vec<(u16, u16, Cell)>) -> Result
             )?;
             //执行写入
             for cell in line.drain(..) {
-                write!(out, "{}{}", cell.style(), cell.symbol.ch)?;
+                write!(out, "{}{}", caps.adapt_style(cell.style()), cell.symbol.ch)?;
             }
             // 设置新行
             start_x = x;
@@ -148,6 +273,13 @@ fn draw_updates<W: Write>(out: &mut W, updates: Vec<(u16, u16, Cell)>) -> Result
     Ok(())
 }
 
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+        restore();
+    }
+}
+
 impl Write for Terminal {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.out.write(buf)
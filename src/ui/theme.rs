@@ -0,0 +1,149 @@
+use crate::conf;
+use crate::ui::style::{Color, Style};
+use std::collections::HashMap;
+
+/// 样式角色，按层级继承
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Base,
+    Border,
+    CmdBar,
+    // 脚本模式下的命令行
+    Script,
+    Flow,
+    // 行号栏
+    Gutter,
+    // 弹出菜单
+    Menu,
+}
+
+impl Role {
+    pub fn parse(name: &str) -> Option<Self> {
+        let role = match name {
+            "base" => Role::Base,
+            "border" => Role::Border,
+            "cmdbar" => Role::CmdBar,
+            "script" => Role::Script,
+            "flow" => Role::Flow,
+            "gutter" => Role::Gutter,
+            "menu" => Role::Menu,
+            _ => return None,
+        };
+        Some(role)
+    }
+
+    pub fn parent(self) -> Option<Role> {
+        match self {
+            Role::Base => None,
+            Role::Border | Role::CmdBar | Role::Flow | Role::Menu => Some(Role::Base),
+            Role::Script => Some(Role::CmdBar),
+            Role::Gutter => Some(Role::Flow),
+        }
+    }
+}
+
+/// 界面主题，各组件从中解析样式
+///
+/// 角色未设置的属性继承自上级角色，组件自身的样式再叠加于主题之上
+#[derive(Debug, Clone)]
+pub struct Theme {
+    styles: HashMap<Role, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::empty()
+            .with(Role::Script, Style::default().bg(Color::Blue))
+            .with(Role::Gutter, Style::default().fg(Color::DarkGray))
+    }
+}
+
+impl Theme {
+    pub fn empty() -> Self {
+        Self {
+            styles: HashMap::new(),
+        }
+    }
+
+    /// 在默认主题上叠加配置
+    pub fn from_conf(config: &HashMap<String, conf::ThemeStyle>) -> Self {
+        let mut theme = Self::default();
+        for (name, ts) in config {
+            let role = match Role::parse(name) {
+                Some(role) => role,
+                None => {
+                    log::warn!("unknown theme role {}", name);
+                    continue;
+                }
+            };
+            let mut style = Style::default();
+            if let Some(fg) = Color::from_str(&ts.fg) {
+                style = style.fg(fg);
+            }
+            if let Some(bg) = Color::from_str(&ts.bg) {
+                style = style.bg(bg);
+            }
+            theme.set(role, style);
+        }
+        theme
+    }
+
+    pub fn with(mut self, role: Role, style: Style) -> Self {
+        self.set(role, style);
+        self
+    }
+
+    /// 叠加角色样式
+    pub fn set(&mut self, role: Role, style: Style) {
+        let entry = self.styles.entry(role).or_default();
+        *entry = entry.patch(style);
+    }
+
+    /// 解析角色的最终样式，自Base起逐级叠加
+    pub fn style(&self, role: Role) -> Style {
+        let base = match role.parent() {
+            Some(parent) => self.style(parent),
+            None => Style::default(),
+        };
+        match self.styles.get(&role) {
+            Some(style) => base.patch(*style),
+            None => base,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_cascade() {
+        let theme = Theme::default()
+            .with(Role::Base, Style::default().fg(Color::White).bg(Color::Black))
+            .with(Role::CmdBar, Style::default().fg(Color::Green));
+        assert_eq!(
+            Style::default().fg(Color::White).bg(Color::Black),
+            theme.style(Role::Border)
+        );
+        // 脚本模式继承命令行前景色，覆盖背景色
+        assert_eq!(
+            Style::default().fg(Color::Green).bg(Color::Blue),
+            theme.style(Role::Script)
+        );
+        assert_eq!(
+            Style::default().fg(Color::DarkGray).bg(Color::Black),
+            theme.style(Role::Gutter)
+        );
+
+        let mut config = HashMap::new();
+        config.insert(
+            "script".to_owned(),
+            conf::ThemeStyle {
+                fg: String::new(),
+                bg: "magenta".to_owned(),
+            },
+        );
+        let theme = Theme::from_conf(&config);
+        assert_eq!(Style::default().bg(Color::Magenta), theme.style(Role::Script));
+    }
+}
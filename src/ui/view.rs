@@ -0,0 +1,38 @@
+use crate::ui::line::Line;
+use std::sync::{Arc, RwLock};
+
+/// 主窗格当前可见的内容，按屏幕行保存折行后的文本
+///
+/// 由界面在每次刷新后更新，供脚本读取用户实际看到的内容
+#[derive(Debug, Clone, Default)]
+pub struct ScreenView(Arc<RwLock<Vec<Line>>>);
+
+impl ScreenView {
+    pub fn update(&self, rows: impl IntoIterator<Item = Line>) {
+        let mut inner = self.0.write().unwrap();
+        inner.clear();
+        inner.extend(rows);
+    }
+
+    /// 最下方的n行，未指定时返回所有行
+    pub fn rows(&self, n: Option<usize>) -> Vec<Line> {
+        let inner = self.0.read().unwrap();
+        let skip = n.map(|n| inner.len().saturating_sub(n)).unwrap_or(0);
+        inner[skip..].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_view_rows() {
+        let view = ScreenView::default();
+        assert!(view.rows(None).is_empty());
+        view.update(vec![Line::fmt_raw("a"), Line::fmt_raw("b"), Line::fmt_raw("c")]);
+        let rows: Vec<String> = view.rows(Some(2)).iter().map(|l| l.plain_text()).collect();
+        assert_eq!(vec!["b", "c"], rows);
+        assert_eq!(3, view.rows(Some(10)).len());
+    }
+}
@@ -3,12 +3,15 @@ use crate::ui::buffer::{Buffer, Symbol};
 use crate::ui::layout::Rect;
 use crate::ui::style::Style;
 use crate::ui::symbol::*;
+use crate::ui::theme::{Role, Theme};
 use crate::ui::widget::Widget;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Border {
     Rounded,
     Square,
+    // 终端不支持Unicode时使用
+    Ascii,
 }
 
 #[derive(Debug, Clone)]
@@ -44,9 +47,18 @@ impl Block {
         self
     }
 
+    /// 边框字符宽度，ASCII字符宽度始终为1
+    pub fn symbol_width(&self) -> u16 {
+        if self.cjk && self.border != Border::Ascii {
+            2
+        } else {
+            1
+        }
+    }
+
     pub fn inner_area(&self, area: Rect) -> Rect {
-        let cw = if self.cjk { 2 } else { 1 };
-        let width = if area.width & 1 == 1 && self.cjk {
+        let cw = self.symbol_width();
+        let width = if area.width & 1 == 1 && cw == 2 {
             // 由于边框字符宽度为2，如果区域宽度不为偶数，需要舍弃最后一列
             area.width - 2 * cw - 1
         } else {
@@ -61,7 +73,7 @@ impl Block {
     }
 
     pub fn outer_area(&self, area: Rect) -> Rect {
-        let width = if area.width & 1 == 1 && self.cjk {
+        let width = if area.width & 1 == 1 && self.symbol_width() == 2 {
             area.width - 1
         } else {
             area.width
@@ -76,10 +88,11 @@ impl Block {
 }
 
 impl Widget for Block {
-    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B) -> Result<()> {
+    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B, theme: &Theme) -> Result<()> {
         if buf.area().height < 2 {
             return Ok(());
         }
+        let style = theme.style(Role::Border).patch(self.style);
         let area = self.outer_area(*buf.area());
 
         let (top_left, top_right, bottom_left, bottom_right) = match self.border {
@@ -90,13 +103,18 @@ impl Widget for Block {
                 ROUNDED_BOTTOM_RIGHT,
             ),
             Border::Square => (TOP_LEFT, TOP_RIGHT, BOTTOM_LEFT, BOTTOM_RIGHT),
+            Border::Ascii => (ASCII_CORNER, ASCII_CORNER, ASCII_CORNER, ASCII_CORNER),
+        };
+        let (horizontal, vertical) = match self.border {
+            Border::Ascii => (ASCII_HORIZONTAL, ASCII_VERTICAL),
+            _ => (HORIZONTAL, VERTICAL),
         };
-        let sw: u16 = if self.cjk { 2 } else { 1 };
+        let sw = self.symbol_width();
         // right() - 1 to handle both even and odd width
         for y in vec![area.top(), area.bottom() - 1] {
             for x in (area.left() + sw..area.right() - sw).step_by(sw as usize) {
-                buf.get_mut(x, y).set_style(self.style).set_symbol(Symbol {
-                    ch: HORIZONTAL,
+                buf.get_mut(x, y).set_style(style).set_symbol(Symbol {
+                    ch: horizontal,
                     width: sw,
                     exists: false,
                 });
@@ -105,36 +123,36 @@ impl Widget for Block {
         // right() - 1 to handle both even and odd width
         for x in vec![area.left(), area.right() - sw] {
             for y in area.top() + 1..area.bottom() - 1 {
-                buf.get_mut(x, y).set_style(self.style).set_symbol(Symbol {
-                    ch: VERTICAL,
+                buf.get_mut(x, y).set_style(style).set_symbol(Symbol {
+                    ch: vertical,
                     width: sw,
                     exists: false,
                 });
             }
         }
         buf.get_mut(area.left(), area.top())
-            .set_style(self.style)
+            .set_style(style)
             .set_symbol(Symbol {
                 ch: top_left,
                 width: sw,
                 exists: false,
             });
         buf.get_mut(area.right() - sw, area.top())
-            .set_style(self.style)
+            .set_style(style)
             .set_symbol(Symbol {
                 ch: top_right,
                 width: sw,
                 exists: false,
             });
         buf.get_mut(area.left(), area.bottom() - 1)
-            .set_style(self.style)
+            .set_style(style)
             .set_symbol(Symbol {
                 ch: bottom_left,
                 width: sw,
                 exists: false,
             });
         buf.get_mut(area.right() - sw, area.bottom() - 1)
-            .set_style(self.style)
+            .set_style(style)
             .set_symbol(Symbol {
                 ch: bottom_right,
                 width: sw,
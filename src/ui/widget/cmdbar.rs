@@ -1,21 +1,59 @@
 use crate::error::Result;
+use crate::i18n;
 use crate::ui::buffer::Buffer;
 use crate::ui::layout::Rect;
-use crate::ui::style::{Color, Style};
-use crate::ui::widget::{Block, Widget};
+use crate::ui::line::Line;
+use crate::ui::theme::{Role, Theme};
+use crate::ui::widget::{Block, Border, Widget};
 use crate::ui::width::AppendWidthTab8;
 use crate::ui::UserOutput;
 use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// 重发命令的最短显示时长
+const FLASH_DURATION: Duration = Duration::from_millis(800);
 
-/// currently only support cjk mode
 #[derive(Debug)]
 pub struct CmdBar {
     cmd: UserOutput,
     block: Block,
-    style: Style,
     script_prefix: char,
     cjk: bool,
     hist: CmdHist,
+    // 脚本等待按键时显示的提示，此时不显示输入内容
+    prompt: Option<String>,
+    // 反向搜索历史命令的状态
+    search: Option<HistSearch>,
+    // 持久化历史命令的文件
+    hist_file: Option<PathBuf>,
+    // 最近出现的单词，用于Tab补全
+    words: WordIndex,
+    // 上次补全的状态，命令行被修改后失效
+    completion: Option<Completion>,
+    // 重发的上一条命令及开始显示的时刻，命令行为空时显示
+    flash: Option<(String, Instant)>,
+    // 服务器关闭回显时输入的是密码等敏感内容，不记录历史
+    secret: bool,
+}
+
+#[derive(Debug)]
+struct Completion {
+    // 被补全的单词在命令中的起始位置
+    start: usize,
+    candidates: Vec<String>,
+    idx: usize,
+    // 补全后的命令文本
+    text: String,
+}
+
+#[derive(Debug, Default)]
+struct HistSearch {
+    query: String,
+    // 当前匹配的历史命令位置
+    found: Option<usize>,
 }
 
 impl CmdBar {
@@ -23,17 +61,110 @@ impl CmdBar {
         Self {
             cmd: UserOutput::default(),
             block: Block::default().cjk(cjk),
-            style: Style::default(),
             // script_mode: false,
             script_prefix,
             cjk,
             hist: CmdHist::with_capacity(hist_size),
+            prompt: None,
+            search: None,
+            hist_file: None,
+            words: WordIndex::with_capacity(1000),
+            completion: None,
+            flash: None,
+            secret: false,
+        }
+    }
+
+    /// 从文件加载历史命令，之后输入的命令追加写入该文件
+    ///
+    /// 每条命令为一行JSON字符串，脚本命令带有脚本前缀，
+    /// 文件中的命令超出容量时仅保留最新的部分并重写文件，
+    /// 文件无法读取时记录警告，以空的历史记录启动
+    pub fn with_history_file(mut self, path: PathBuf) -> Self {
+        if let Err(e) = self.load_history(&path) {
+            log::warn!("load history file {} error {}", path.display(), e);
+            self.hist = CmdHist::with_capacity(self.hist.capacity);
+        }
+        self.hist_file = Some(path);
+        self
+    }
+
+    fn load_history(&mut self, path: &Path) -> Result<()> {
+        let mut total = 0;
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                match serde_json::from_str::<String>(&line) {
+                    Ok(text) => {
+                        let cmd = self.decode(text);
+                        self.hist.push(cmd);
+                        total += 1;
+                    }
+                    Err(e) => log::warn!("skip invalid history line {:?}: {}", line, e),
+                }
+            }
+        }
+        if total > self.hist.len() {
+            let mut content = String::new();
+            for cmd in &self.hist.cmds {
+                content.push_str(&serde_json::to_string(&self.encode(cmd))?);
+                content.push('\n');
+            }
+            fs::write(path, content)?;
         }
+        Ok(())
+    }
+
+    // 脚本命令以脚本前缀开头，输入时无法产生以前缀开头的普通命令
+    fn encode(&self, cmd: &UserOutput) -> String {
+        match cmd {
+            UserOutput::Cmd(s) => s.to_owned(),
+            UserOutput::Script(s) => format!("{}{}", self.script_prefix, s),
+        }
+    }
+
+    fn decode(&self, text: String) -> UserOutput {
+        match text.strip_prefix(self.script_prefix) {
+            Some(s) => UserOutput::Script(s.to_owned()),
+            None => UserOutput::Cmd(text),
+        }
+    }
+
+    // 追加写入历史文件，写入失败不影响输入
+    fn save_cmd(&self, cmd: &UserOutput) -> Result<()> {
+        if let Some(path) = self.hist_file.as_ref() {
+            if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            let mut opts = OpenOptions::new();
+            opts.create(true).append(true);
+            // 历史中可能包含敏感命令，仅允许本用户读写
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+            let mut file = opts.open(path)?;
+            writeln!(file, "{}", serde_json::to_string(&self.encode(cmd))?)?;
+        }
+        Ok(())
+    }
+
+    pub fn with_border(mut self, border: Border) -> Self {
+        self.block = self.block.border(border);
+        self
+    }
+
+    /// 切换歧义宽度字符的列宽，边框及光标位置随之变化
+    pub fn set_cjk(&mut self, cjk: bool) {
+        self.cjk = cjk;
+        self.block.cjk = cjk;
     }
 
-    pub fn cursor_pos(&self, area: Rect, cjk: bool) -> (u16, u16) {
-        let width = if cjk { 2 } else { 1 };
-        let offset = self.cmd.append_width(width, cjk) as u16;
+    pub fn cursor_pos(&self, area: Rect) -> (u16, u16) {
+        let width = self.block.symbol_width() as usize;
+        let offset = match (self.prompt.as_ref(), self.search_text()) {
+            (Some(prompt), _) => prompt.append_width(width, self.cjk) as u16,
+            (None, Some(text)) => text.append_width(width, self.cjk) as u16,
+            (None, None) => self.cmd.append_width(width, self.cjk) as u16,
+        };
         (area.left() + offset, area.top() + 1)
     }
 
@@ -41,10 +172,8 @@ impl CmdBar {
         if self.cmd.is_empty() && ch == self.script_prefix {
             if self.cmd.is_cmd() {
                 self.cmd = UserOutput::Script(String::new());
-                self.style = Style::default().bg(Color::Blue);
             } else {
                 self.cmd = UserOutput::Cmd(String::new());
-                self.style = Style::default();
             }
         } else {
             self.cmd.push(ch);
@@ -54,17 +183,29 @@ impl CmdBar {
     pub fn pop_char(&mut self) -> Option<char> {
         let ch = self.cmd.pop();
         if ch.is_some() && self.cmd.is_empty() && self.cmd.is_script() {
-            self.style = Style::default();
             self.cmd = UserOutput::Cmd(String::new());
         }
         ch
     }
 
+    /// 设置服务器是否关闭了回显，关闭时输入的命令不记录历史
+    pub fn set_secret(&mut self, secret: bool) {
+        self.secret = secret;
+    }
+
     pub fn take(&mut self) -> UserOutput {
         let cmd = std::mem::replace(&mut self.cmd, UserOutput::default());
-        // 每次都记录历史
+        // 以空格开头或关闭回显时输入的命令不记录历史
+        if self.secret || cmd.as_ref().starts_with(' ') {
+            return cmd;
+        }
+        // 每次都记录历史，与上一命令相同或为空时不写入文件
+        if !cmd.is_empty() && self.hist.last() != Some(&cmd) {
+            if let Err(e) = self.save_cmd(&cmd) {
+                log::warn!("save command history error {}", e);
+            }
+        }
         self.hist.push(cmd.clone());
-        self.style = Style::default();
         cmd
     }
 
@@ -72,6 +213,34 @@ impl CmdBar {
         self.cmd.clear();
     }
 
+    /// 设置或清除按键提示，输入内容保留
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt;
+    }
+
+    pub fn prompt(&self) -> Option<&str> {
+        self.prompt.as_deref()
+    }
+
+    /// 短暂显示重发的命令，不影响输入内容
+    pub fn flash(&mut self, cmd: String) {
+        self.flash = Some((cmd, Instant::now()));
+    }
+
+    /// 清除重发命令的显示，非强制时仅清除已显示足够时长的命令
+    pub fn expire_flash(&mut self, force: bool) {
+        if let Some((_, since)) = self.flash.as_ref() {
+            if force || since.elapsed() >= FLASH_DURATION {
+                self.flash = None;
+            }
+        }
+    }
+
+    /// 当前输入的文本
+    pub fn text(&self) -> &str {
+        self.cmd.as_ref()
+    }
+
     pub fn prev_cmd(&mut self) {
         if let Some(prev) = self.hist.prev() {
             self.cmd = prev.clone();
@@ -83,26 +252,190 @@ impl CmdBar {
             self.cmd = next.clone();
         }
     }
+
+    /// 将主窗格中的行加入补全的单词索引
+    pub fn index_line(&mut self, line: &Line) {
+        for span in line.spans() {
+            self.words.push_text(&span.content);
+        }
+    }
+
+    /// 以最近出现的单词补全光标前的单词，连续调用时依次替换为其他候选词
+    pub fn complete(&mut self) {
+        let text = self.cmd.as_ref().to_owned();
+        let (start, idx, candidates) = match self.completion.take().filter(|c| c.text == text) {
+            Some(c) => (c.start, (c.idx + 1) % c.candidates.len(), c.candidates),
+            None => {
+                let start = text
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map(|(i, c)| i + c.len_utf8())
+                    .unwrap_or(0);
+                let prefix = text[start..].to_lowercase();
+                if prefix.is_empty() {
+                    return;
+                }
+                let candidates = self.words.candidates(&prefix);
+                if candidates.is_empty() {
+                    return;
+                }
+                (start, 0, candidates)
+            }
+        };
+        while self.cmd.as_ref().len() > start {
+            self.cmd.pop();
+        }
+        for c in candidates[idx].chars() {
+            self.cmd.push(c);
+        }
+        self.completion = Some(Completion {
+            start,
+            candidates,
+            idx,
+            text: self.cmd.as_ref().to_owned(),
+        });
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// 开始反向搜索，搜索中再次调用时查找更早的匹配
+    pub fn search_prev(&mut self) {
+        match self.search.as_mut() {
+            None => self.search = Some(HistSearch::default()),
+            Some(search) => {
+                let before = search.found.unwrap_or(self.hist.len());
+                if let Some(idx) = self.hist.rfind(&search.query, before) {
+                    search.found = Some(idx);
+                }
+            }
+        }
+    }
+
+    /// 追加搜索字符，从最新的命令重新查找
+    pub fn search_push(&mut self, ch: char) {
+        if let Some(search) = self.search.as_mut() {
+            search.query.push(ch);
+            search.found = self.hist.rfind(&search.query, self.hist.len());
+        }
+    }
+
+    pub fn search_pop(&mut self) {
+        if let Some(search) = self.search.as_mut() {
+            search.query.pop();
+            search.found = if search.query.is_empty() {
+                None
+            } else {
+                self.hist.rfind(&search.query, self.hist.len())
+            };
+        }
+    }
+
+    /// 结束搜索，以匹配的命令填充命令行
+    pub fn accept_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            if let Some(cmd) = search.found.and_then(|idx| self.hist.cmds.get(idx)) {
+                self.cmd = cmd.clone();
+            }
+        }
+    }
+
+    /// 取消搜索，命令行保持不变
+    pub fn cancel_search(&mut self) {
+        self.search = None;
+    }
+
+    // 搜索时命令栏显示的文本
+    fn search_text(&self) -> Option<String> {
+        let search = self.search.as_ref()?;
+        let found = search
+            .found
+            .and_then(|idx| self.hist.cmds.get(idx))
+            .map(|cmd| self.encode(cmd))
+            .unwrap_or_default();
+        Some(i18n::trf("cmdbar.search", &[&search.query, &found]))
+    }
 }
 
 impl Widget for CmdBar {
-    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B) -> Result<()> {
-        self.block.refresh_buffer(buf)?;
+    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B, theme: &Theme) -> Result<()> {
+        self.block.refresh_buffer(buf, theme)?;
 
+        // 脚本模式、等待按键、搜索及显示重发命令时使用独立的样式
+        let search_text = self.search_text();
+        let flash = self.flash.as_ref().filter(|_| self.cmd.is_empty()).map(|(cmd, _)| cmd);
+        let style = if self.cmd.is_script() || self.prompt.is_some() || search_text.is_some() || flash.is_some()
+        {
+            theme.style(Role::Script)
+        } else {
+            theme.style(Role::CmdBar)
+        };
         let bararea = self.block.inner_area(*buf.area());
-        buf.set_style(bararea, self.style);
+        buf.set_style(bararea, style);
+        let text = match (self.prompt.as_ref(), search_text.as_ref(), flash) {
+            (Some(prompt), ..) => prompt.as_str(),
+            (None, Some(text), _) => text.as_str(),
+            (None, None, Some(cmd)) => cmd.as_str(),
+            (None, None, None) => self.cmd.as_ref(),
+        };
         buf.set_line_str(
             bararea.left(),
             bararea.top(),
-            &self.cmd,
+            text,
             bararea.right(),
-            self.style,
+            style,
             self.cjk,
         );
         Ok(())
     }
 }
 
+/// 最近出现的单词，用于Tab补全
+///
+/// 仅收录不短于3个字符的ASCII单词并转为小写，中文连续文本难以分词且极少作为命令参数
+#[derive(Debug)]
+pub struct WordIndex {
+    // 越近出现的单词越靠后
+    words: VecDeque<String>,
+    capacity: usize,
+}
+
+impl WordIndex {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            words: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push_text(&mut self, text: &str) {
+        for word in text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')) {
+            if word.len() < 3 {
+                continue;
+            }
+            let word = word.to_ascii_lowercase();
+            if let Some(idx) = self.words.iter().position(|w| *w == word) {
+                self.words.remove(idx);
+            } else if self.words.len() == self.capacity {
+                self.words.pop_front();
+            }
+            self.words.push_back(word);
+        }
+    }
+
+    /// 以prefix开头且更长的单词，越近出现的越靠前
+    pub fn candidates(&self, prefix: &str) -> Vec<String> {
+        self.words
+            .iter()
+            .rev()
+            .filter(|w| w.len() > prefix.len() && w.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 struct CmdHist {
     cmds: VecDeque<UserOutput>,
@@ -147,6 +480,17 @@ impl CmdHist {
         self.idx = 0;
     }
 
+    /// 在before之前查找包含query的最新命令
+    pub fn rfind(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        self.cmds
+            .iter()
+            .take(before)
+            .rposition(|cmd| cmd.as_ref().contains(query))
+    }
+
     pub fn push(&mut self, cmd: UserOutput) {
         if let Some(last) = self.last() {
             // 与上一命令完全相同，忽略
@@ -182,6 +526,7 @@ impl CmdHist {
 mod tests {
 
     use super::*;
+    use crate::testutil::TempDir;
 
     #[test]
     fn test_cmd_hist() {
@@ -203,4 +548,106 @@ mod tests {
         hist.push(UserOutput::Cmd("overflow".into()));
         assert_eq!(&UserOutput::Cmd("world".into()), hist.first().unwrap());
     }
+
+    #[test]
+    fn test_cmdbar_complete() {
+        let mut bar = CmdBar::new('.', true, 10);
+        bar.index_line(&Line::fmt_raw("张三(Zhang san)走了过来。"));
+        bar.index_line(&Line::fmt_raw("  一把钢刀(Blade)"));
+        bar.index_line(&Line::fmt_raw("李四(Zhao si)走了过来。"));
+        for c in "kill zh".chars() {
+            bar.push_char(c);
+        }
+        bar.complete();
+        assert_eq!("kill zhao", bar.text());
+        bar.complete();
+        assert_eq!("kill zhang", bar.text());
+        bar.complete();
+        assert_eq!("kill zhao", bar.text());
+        // 修改后重新补全
+        bar.push_char(' ');
+        bar.complete();
+        assert_eq!("kill zhao ", bar.text());
+        for c in "bl".chars() {
+            bar.push_char(c);
+        }
+        bar.complete();
+        assert_eq!("kill zhao blade", bar.text());
+    }
+
+    #[test]
+    fn test_cmd_hist_file_search() {
+        let tmp = TempDir::new("history");
+        let path = tmp.join("history.jsonl");
+        let mut bar = CmdBar::new('.', true, 3).with_history_file(path.clone());
+        for text in ["kill rat", "look", ".Send(\"kill dog\")", "kill rat"] {
+            for c in text.chars() {
+                bar.push_char(c);
+            }
+            bar.take();
+        }
+        // 重新加载时仅保留容量内最新的命令
+        let mut bar = CmdBar::new('.', true, 3).with_history_file(path.clone());
+        assert_eq!(3, bar.hist.len());
+        assert_eq!(3, fs::read_to_string(&path).unwrap().lines().count());
+        assert_eq!(&UserOutput::Script("Send(\"kill dog\")".into()), bar.hist.cmds.get(1).unwrap());
+
+        bar.search_prev();
+        for c in "kill".chars() {
+            bar.search_push(c);
+        }
+        assert_eq!(Some(2), bar.search.as_ref().unwrap().found);
+        bar.search_prev();
+        assert_eq!(Some(1), bar.search.as_ref().unwrap().found);
+        // 没有更早的匹配时保持不变
+        bar.search_prev();
+        assert_eq!(Some(1), bar.search.as_ref().unwrap().found);
+        bar.accept_search();
+        assert!(!bar.is_searching());
+        assert_eq!(UserOutput::Script("Send(\"kill dog\")".into()), bar.cmd);
+    }
+
+    #[test]
+    fn test_cmd_hist_file_invalid() {
+        let tmp = TempDir::new("history-invalid");
+        let path = tmp.join("history.jsonl");
+        fs::write(&path, b"\"look\"\n\xff\xfe\n").unwrap();
+        // 无法读取的文件不影响启动，历史记录为空
+        let mut bar = CmdBar::new('.', true, 10).with_history_file(path.clone());
+        assert_eq!(0, bar.hist.len());
+        for c in "n".chars() {
+            bar.push_char(c);
+        }
+        bar.take();
+        assert_eq!(1, bar.hist.len());
+        assert_eq!(0, CmdBar::new('.', true, 10).with_history_file(tmp.path().to_path_buf()).hist.len());
+    }
+
+    #[test]
+    fn test_cmd_hist_secret() {
+        let tmp = TempDir::new("history-secret");
+        let path = tmp.join("history.jsonl");
+        let mut bar = CmdBar::new('.', true, 10).with_history_file(path.clone());
+        fn input(bar: &mut CmdBar, text: &str) -> UserOutput {
+            for c in text.chars() {
+                bar.push_char(c);
+            }
+            bar.take()
+        }
+        input(&mut bar, "look");
+        // 以空格开头的命令照常发送但不记录
+        assert_eq!(UserOutput::Cmd(" tell zhao 密码".into()), input(&mut bar, " tell zhao 密码"));
+        bar.set_secret(true);
+        assert_eq!(UserOutput::Cmd("hunter2".into()), input(&mut bar, "hunter2"));
+        bar.set_secret(false);
+        input(&mut bar, "n");
+        assert_eq!(2, bar.hist.len());
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(vec!["\"look\"", "\"n\""], saved.lines().collect::<Vec<_>>());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        }
+    }
 }
@@ -1,17 +1,48 @@
 use crate::error::Result;
+use crate::i18n;
+use crate::proto::Label;
+use crate::runtime::marks::clock;
 use crate::ui::buffer::Buffer;
 use crate::ui::layout::Rect;
-use crate::ui::line::{Line, WrapLine};
+use crate::ui::line::{CompactStats, Line, WrapLine};
+use crate::ui::span::Span;
+use crate::ui::style::{Color, Modifier, Style};
+use crate::ui::theme::{Role, Theme};
 use crate::ui::widget::Widget;
-use std::collections::vec_deque::Iter;
 use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 行号栏宽度
+const GUTTER_WIDTH: u16 = 7;
+
+// 显示行及其行号，填充的空行没有行号
+type Rows = VecDeque<(Option<usize>, WrapLine)>;
 
 pub struct Flow {
     area: Rect,
     max_lines: usize,
-    history: VecDeque<Line>,
-    display: VecDeque<WrapLine>,
+    // 历史行及其在运行时回滚缓冲中的行号，界面自身的提示没有行号
+    history: VecDeque<(Option<usize>, Line)>,
+    // 跟随最新文本的显示行
+    display: Rows,
+    // 翻阅历史时上方窗格的显示行
+    scrolled: Rows,
     cjk: bool,
+    gutter: bool,
+    // 折行处添加连字符的ASCII串长度阈值，0表示不添加
+    hyphen_after: usize,
+    // 历史行压缩统计
+    stats: CompactStats,
+    // 向上翻阅的历史行数，0表示跟随最新的行
+    offset: usize,
+    // 翻阅历史时底部实时窗格的行数，0表示不分屏
+    live_rows: u16,
+    // 关闭时超出宽度的部分截断，不折行
+    wrap: bool,
+    // 新行开头添加本地时间
+    timestamps: bool,
+    // 翻阅历史时新行到达则回到最新的行
+    follow: bool,
 }
 
 impl Flow {
@@ -24,60 +55,106 @@ impl Flow {
             max_lines,
             history: VecDeque::new(),
             display: VecDeque::new(),
+            scrolled: VecDeque::new(),
             cjk,
+            gutter: false,
+            hyphen_after: 0,
+            stats: CompactStats::default(),
+            offset: 0,
+            live_rows: 0,
+            wrap: true,
+            timestamps: false,
+            follow: false,
         };
 
         for _ in 0..area.height {
-            flow.push_line(Line::fmt_raw(""));
+            flow.push_display(Line::fmt_raw(""), None);
         }
 
         flow
     }
 
-    /// 输入必须为单行
-    fn push_history(&mut self, line: Line) {
-        if let Some(last_line) = self.history.back_mut() {
+    /// 输入必须为单行，返回该行的行号及是否新增了历史行
+    ///
+    /// 行结束时进行压缩，长时间运行时减少历史行的内存占用
+    fn push_history(&mut self, mut line: Line, lineno: Option<usize>) -> (Option<usize>, bool) {
+        if let Some((last_lineno, last_line)) = self.history.back_mut() {
             if !last_line.ended() {
                 last_line.push_line(line);
-                return;
+                if last_line.ended() {
+                    self.stats += last_line.compact();
+                }
+                if last_lineno.is_none() {
+                    *last_lineno = lineno;
+                }
+                return (*last_lineno, false);
             }
         }
-        self.history.push_back(line);
+        if line.ended() {
+            self.stats += line.compact();
+        }
+        self.history.push_back((lineno, line));
         while self.history.len() > self.max_lines {
             self.history.pop_front();
         }
+        (lineno, true)
     }
 
-    pub fn push_line(&mut self, line: Line) {
-        self.push_history(line.clone());
+    // 文本区域宽度，开启行号栏时需扣除其宽度
+    fn text_width(&self) -> usize {
+        if self.gutter && self.area.width > GUTTER_WIDTH {
+            (self.area.width - GUTTER_WIDTH) as usize
+        } else {
+            self.area.width as usize
+        }
+    }
+
+    // 折行宽度，不折行时不限制宽度，绘制时截断
+    fn wrap_width(&self) -> usize {
+        if self.wrap {
+            self.text_width()
+        } else {
+            usize::MAX
+        }
+    }
+
+    fn push_display(&mut self, line: Line, lineno: Option<usize>) {
+        let mut display = std::mem::take(&mut self.display);
+        self.append_rows(&mut display, line, lineno, self.area.height as usize);
+        self.display = display;
+    }
+
+    // 将行折行后追加到显示行，超出高度时移除最早的行
+    fn append_rows(&self, display: &mut Rows, line: Line, lineno: Option<usize>, height: usize) {
+        let width = self.wrap_width();
         for span in line.into_spans() {
-            if let Some(last_line) = self.display.back_mut() {
+            if let Some((_, last_line)) = display.back_mut() {
                 if !last_line.ended() {
-                    last_line.push_span(span, self.area.width as usize, self.cjk);
+                    last_line.push_span(span, width, self.cjk, self.hyphen_after);
                 } else {
                     let line = Line::single(span);
-                    let wl = line.wrap(self.area.width as usize, self.cjk);
-                    self.display.push_back(wl);
+                    let wl = line.wrap_hyphen(width, self.cjk, self.hyphen_after);
+                    display.push_back((lineno, wl));
                 }
             } else {
                 let line = Line::single(span);
-                let wl = line.wrap(self.area.width as usize, self.cjk);
-                self.display.push_back(wl);
+                let wl = line.wrap_hyphen(width, self.cjk, self.hyphen_after);
+                display.push_back((lineno, wl));
             }
         }
-        let mut len: usize = self.display.iter().map(|wl| wl.0.len()).sum();
-        if len > self.area.height as usize {
+        let mut len: usize = display.iter().map(|(_, wl)| wl.0.len()).sum();
+        if len > height {
             'outer: loop {
-                let mut head = self.display.pop_front().unwrap();
+                let (_, mut head) = display.pop_front().unwrap();
                 if head.0.len() == 1 {
                     len -= 1;
-                    if len == self.area.height as usize {
+                    if len == height {
                         break 'outer;
                     }
                 } else {
                     while let Some(_) = head.0.pop() {
                         len -= 1;
-                        if len == self.area.height as usize {
+                        if len == height {
                             break 'outer;
                         }
                     }
@@ -86,12 +163,117 @@ impl Flow {
         }
     }
 
+    /// 历史行压缩统计
+    pub fn compact_stats(&self) -> CompactStats {
+        self.stats
+    }
+
+    /// 超长ASCII串折行时在行尾显示连字符
+    pub fn with_hyphen(mut self, hyphen_after: usize) -> Self {
+        self.hyphen_after = hyphen_after;
+        self
+    }
+
+    /// 翻阅历史时在底部保留显示最新文本的实时窗格
+    pub fn with_live_rows(mut self, live_rows: u16) -> Self {
+        self.live_rows = live_rows;
+        self
+    }
+
+    // 翻阅历史且空间足够时分屏，返回实时窗格的行数
+    fn split_rows(&self) -> Option<u16> {
+        if self.offset > 0 && self.live_rows > 0 && self.area.height >= self.live_rows + 3 {
+            Some(self.live_rows)
+        } else {
+            None
+        }
+    }
+
+    // 历史窗格的高度，分屏时扣除实时窗格及分隔线
+    fn history_height(&self) -> usize {
+        match self.split_rows() {
+            Some(live_rows) => (self.area.height - live_rows - 1) as usize,
+            None => self.area.height as usize,
+        }
+    }
+
+    /// 追加界面自身的行，行号栏中不显示行号
+    pub fn push_line(&mut self, line: Line) {
+        self.push_numbered_line(line, None);
+    }
+
+    /// 追加运行时输出的行，行号与运行时的回滚缓冲一致
+    pub fn push_numbered_line(&mut self, mut line: Line, lineno: Option<usize>) {
+        let returns = line.returns();
+        let starts = self.history.back().map(|(_, l)| l.ended()).unwrap_or(true);
+        if self.timestamps && starts && !returns {
+            line = stamp(line);
+        }
+        let (lineno, added) = self.push_history(line.clone(), lineno);
+        if self.follow && self.offset > 0 {
+            self.offset = 0;
+            self.redisplay_scrolled();
+        }
+        if self.offset > 0 {
+            if added {
+                self.offset += 1;
+            }
+            self.offset = self.offset.min(self.history.len().saturating_sub(1));
+        }
+        // 覆盖未结束的行时按历史重新填充显示区域
+        if returns {
+            self.redisplay();
+            return;
+        }
+        self.push_display(line, lineno);
+    }
+
+    /// 向上翻阅指定行数的历史
+    pub fn scroll_up(&mut self, lines: usize) {
+        let offset = (self.offset + lines).min(self.history.len().saturating_sub(1));
+        if offset != self.offset {
+            self.offset = offset;
+            self.redisplay_scrolled();
+        }
+    }
+
+    /// 向下翻阅，到达底部后恢复跟随最新的行
+    pub fn scroll_down(&mut self, lines: usize) {
+        let offset = self.offset.saturating_sub(lines);
+        if offset != self.offset {
+            self.offset = offset;
+            self.redisplay_scrolled();
+        }
+    }
+
+    /// 回到最新的行
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_down(self.offset);
+    }
+
+    /// 翻页的行数，保留一行上下文，分屏时按历史窗格的高度计算
+    pub fn page_size(&self) -> usize {
+        self.history_height().saturating_sub(1).max(1)
+    }
+
+    /// 最新的行之后未显示的历史行数
+    pub fn scroll_offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn push_lines(&mut self, lines: impl IntoIterator<Item = Line>) {
         for line in lines {
             self.push_line(line);
         }
     }
 
+    /// 追加运行时输出的连续多行，first为首行的行号
+    pub fn push_numbered_lines(&mut self, first: usize, lines: impl IntoIterator<Item = Line>) {
+        for (i, line) in lines.into_iter().enumerate() {
+            self.push_numbered_line(line, Some(first + i));
+        }
+    }
+
     pub fn reshape(&mut self, area: Rect) {
         // 更新区域
         self.area = area;
@@ -100,49 +282,263 @@ impl Flow {
         if self.max_lines < height {
             self.max_lines = height;
         }
-        // 重新填充历史文本
-        let lines = if self.history.len() < height {
-            let mut lines = Vec::with_capacity(height);
-            for _ in 0..height - self.history.len() {
-                lines.push(Line::fmt_raw(""));
-            }
-            lines.extend(self.history.drain(..));
-            lines
+        self.redisplay();
+    }
+
+    /// 切换行号栏的显示
+    pub fn toggle_gutter(&mut self) {
+        self.gutter = !self.gutter;
+        self.redisplay();
+    }
+
+    /// 开启或关闭折行，关闭时超出宽度的部分截断
+    pub fn set_wrap(&mut self, wrap: bool) {
+        if self.wrap != wrap {
+            self.wrap = wrap;
+            self.redisplay();
+        }
+    }
+
+    /// 开启或关闭时间戳，仅影响之后到达的行
+    pub fn set_timestamps(&mut self, timestamps: bool) {
+        self.timestamps = timestamps;
+    }
+
+    /// 开启或关闭翻阅历史时跟随新行
+    pub fn set_follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// 切换歧义宽度字符的列宽，按新宽度重新折行
+    pub fn set_cjk(&mut self, cjk: bool) {
+        self.cjk = cjk;
+        self.redisplay();
+    }
+
+    // 根据历史文本重新填充显示区域
+    fn redisplay(&mut self) {
+        self.display = self.fill_rows(self.history.len(), self.area.height as usize);
+        self.redisplay_scrolled();
+    }
+
+    // 翻阅时以偏移处的行为底部填充历史窗格
+    fn redisplay_scrolled(&mut self) {
+        self.scrolled = if self.offset > 0 {
+            self.fill_rows(self.history.len() - self.offset, self.history_height())
         } else {
-            self.history.drain(self.history.len() - height..).collect()
+            VecDeque::new()
         };
-        self.display.clear();
-        for line in lines {
-            self.push_line(line);
+    }
+
+    // 以历史中end之前的行填充指定高度，不足时在上方填充空行
+    fn fill_rows(&self, end: usize, height: usize) -> Rows {
+        let mut rows = VecDeque::new();
+        for _ in end..height {
+            self.append_rows(&mut rows, Line::fmt_raw(""), None, height);
+        }
+        let skip = end.saturating_sub(height);
+        for (lineno, line) in self.history.iter().take(end).skip(skip) {
+            self.append_rows(&mut rows, line.clone(), *lineno, height);
         }
+        rows
+    }
+
+    pub fn display_lines(&self) -> impl Iterator<Item = &WrapLine> {
+        self.display.iter().map(|(_, wl)| wl)
+    }
+
+    /// 折行后跟随最新文本的行，每项对应屏幕上的一行，不受翻阅历史影响
+    pub fn visible_rows(&self) -> impl Iterator<Item = &Line> {
+        self.display.iter().flat_map(|(_, wl)| wl.0.iter())
     }
 
-    pub fn display_lines(&self) -> Iter<'_, WrapLine> {
-        self.display.iter()
+    /// 翻阅历史时历史窗格的行
+    pub fn scrolled_rows(&self) -> impl Iterator<Item = &Line> {
+        self.scrolled.iter().flat_map(|(_, wl)| wl.0.iter())
+    }
+
+    // 绘制显示行，跳过前skip行，返回下一行的纵坐标
+    fn draw_rows<B: Buffer>(&self, buf: &mut B, theme: &Theme, rows: &Rows, skip: usize, mut y: u16) -> u16 {
+        let base = theme.style(Role::Flow);
+        let gutter_style = theme.style(Role::Gutter);
+        let gutter = self.gutter && buf.area().width > GUTTER_WIDTH;
+        let rows = rows
+            .iter()
+            .flat_map(|(lineno, wl)| wl.0.iter().enumerate().map(move |(i, l)| (i, lineno, l)))
+            .skip(skip);
+        for (i, lineno, l) in rows {
+            let mut x = buf.area().left();
+            if gutter {
+                if let (0, Some(lineno)) = (i, lineno) {
+                    let label = format!("{:>width$} ", lineno, width = GUTTER_WIDTH as usize - 1);
+                    buf.set_line_str(x, y, label, x + GUTTER_WIDTH, gutter_style, self.cjk);
+                }
+                x += GUTTER_WIDTH;
+            }
+            for span in l.spans() {
+                if let Some(pos) = buf.set_line_str(
+                    x,
+                    y,
+                    &span.content,
+                    buf.area().right(),
+                    base.patch(span.style),
+                    self.cjk,
+                ) {
+                    x = pos;
+                }
+            }
+            y += 1;
+        }
+        y
     }
 }
 
+// 在行首添加本地时间
+fn stamp(line: Line) -> Line {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let mut spans = vec![Span::new(
+        format!("{} ", clock(millis)),
+        Style::default().fg(Color::DarkGray),
+        Label::None,
+    )];
+    spans.extend(line.into_spans());
+    Line::new(spans)
+}
+
 impl Widget for Flow {
-    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B) -> Result<()> {
-        let mut y = buf.area().top();
-        for wl in &self.display {
-            for l in &wl.0 {
-                let mut x = buf.area().left();
-                for span in l.spans() {
-                    if let Some(pos) = buf.set_line_str(
-                        x,
-                        y,
-                        &span.content,
-                        buf.area().right(),
-                        span.style,
-                        self.cjk,
-                    ) {
-                        x = pos;
-                    }
+    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B, theme: &Theme) -> Result<()> {
+        let gutter_style = theme.style(Role::Gutter);
+        let area = *buf.area();
+        if self.offset == 0 {
+            self.draw_rows(buf, theme, &self.display, 0, area.top());
+            return Ok(());
+        }
+        let y = self.draw_rows(buf, theme, &self.scrolled, 0, area.top());
+        let label = format!(" {} +{} ", i18n::tr("ui.more"), self.offset);
+        let width = Line::fmt_raw(label.as_str()).display_width(self.cjk) as u16;
+        let style = gutter_style.add_modifier(Modifier::REVERSED);
+        match self.split_rows() {
+            // 分屏时以分隔线隔开历史窗格及底部的实时窗格
+            Some(live_rows) => {
+                let sep = "─".repeat(area.width as usize);
+                buf.set_line_str(area.left(), y, sep, area.right(), gutter_style, self.cjk);
+                if area.width >= width {
+                    buf.set_line_str(area.right() - width, y, label, area.right(), style, self.cjk);
+                }
+                let total: usize = self.display.iter().map(|(_, wl)| wl.0.len()).sum();
+                let skip = total.saturating_sub(live_rows as usize);
+                self.draw_rows(buf, theme, &self.display, skip, y + 1);
+            }
+            // 空间不足时在右下角提示其后的行数
+            None => {
+                if area.height > 0 && area.width >= width {
+                    buf.set_line_str(area.right() - width, area.bottom() - 1, label, area.right(), style, self.cjk);
                 }
-                y += 1;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_numbered_lines() {
+        let area = Rect::new(1, 1, 20, 4);
+        let mut flow = Flow::new(area, 10, true);
+        flow.push_numbered_lines(41, vec![Line::fmt_raw("a\n"), Line::fmt_raw("b")]);
+        // 续接未结束的行时沿用其行号
+        flow.push_numbered_lines(42, vec![Line::fmt_raw("c\n")]);
+        flow.push_line(Line::fmt_raw("note\n"));
+        let numbers: Vec<Option<usize>> = flow.history.iter().map(|(n, _)| *n).collect();
+        assert_eq!(vec![Some(41), Some(42), None], numbers);
+        flow.toggle_gutter();
+        let rows: Vec<Option<usize>> = flow.display.iter().map(|(n, _)| *n).collect();
+        assert_eq!(vec![None, Some(41), Some(42), None], rows);
+    }
+
+    #[test]
+    fn test_flow_scroll() {
+        let area = Rect::new(1, 1, 20, 2);
+        let mut flow = Flow::new(area, 10, true);
+        for i in 1..=5 {
+            flow.push_line(Line::fmt_raw(format!("line{}\n", i)));
+        }
+        let rows = |flow: &Flow| -> Vec<String> {
+            if flow.scroll_offset() > 0 {
+                flow.scrolled_rows().map(|l| l.plain_text().trim_end().to_owned()).collect()
+            } else {
+                flow.visible_rows().map(|l| l.plain_text().trim_end().to_owned()).collect()
+            }
+        };
+        flow.scroll_up(flow.page_size());
+        assert_eq!(vec!["line3", "line4"], rows(&flow));
+        // 翻阅时新行不改变显示内容
+        flow.push_line(Line::fmt_raw("line6\n"));
+        assert_eq!(vec!["line3", "line4"], rows(&flow));
+        assert_eq!(2, flow.scroll_offset());
+        flow.scroll_up(10);
+        assert_eq!(vec!["line1"], rows(&flow)[1..].to_vec());
+        flow.scroll_to_bottom();
+        assert_eq!(vec!["line5", "line6"], rows(&flow));
+        flow.push_line(Line::fmt_raw("line7\n"));
+        assert_eq!(vec!["line6", "line7"], rows(&flow));
+
+        // 分屏时历史窗格高度扣除实时窗格及分隔线
+        let area = Rect::new(1, 1, 20, 6);
+        let mut flow = Flow::new(area, 10, true).with_live_rows(2);
+        for i in 1..=8 {
+            flow.push_line(Line::fmt_raw(format!("line{}\n", i)));
+        }
+        // 未分屏时按整个窗格翻页
+        assert_eq!(5, flow.page_size());
+        flow.scroll_up(2);
+        assert_eq!(2, flow.page_size());
+        assert_eq!(vec!["line4", "line5", "line6"], rows(&flow));
+        flow.push_line(Line::fmt_raw("line9\n"));
+        assert_eq!(vec!["line4", "line5", "line6"], rows(&flow));
+        // 实时窗格继续显示新行
+        let live: Vec<String> = flow.visible_rows().map(|l| l.plain_text().trim_end().to_owned()).collect();
+        assert_eq!(vec!["line8", "line9"], live[live.len() - 2..].to_vec());
+    }
+
+    #[test]
+    fn test_flow_wrap_timestamps_follow() {
+        let area = Rect::new(1, 1, 6, 3);
+        let mut flow = Flow::new(area, 10, true);
+        flow.set_wrap(false);
+        flow.push_line(Line::fmt_raw("张三走了过来。\n"));
+        let rows: Vec<String> = flow.visible_rows().map(|l| l.plain_text()).collect();
+        assert_eq!(vec!["", "", "张三走了过来。"], rows);
+        flow.set_wrap(true);
+        assert_eq!(3, flow.visible_rows().count());
+
+        flow.set_timestamps(true);
+        flow.push_line(Line::fmt_raw("hp"));
+        flow.push_line(Line::fmt_raw("100\n"));
+        let (_, last) = flow.history.back().unwrap();
+        // 时间戳仅添加在行首
+        let text = last.plain_text();
+        assert_eq!(b':', text.as_bytes()[2]);
+        assert!(text.ends_with(" hp100"));
+
+        flow.set_follow(true);
+        flow.scroll_up(1);
+        flow.push_line(Line::fmt_raw("new\n"));
+        assert_eq!(0, flow.scroll_offset());
+    }
+
+    #[test]
+    fn test_flow_visible_rows() {
+        let area = Rect::new(1, 1, 6, 3);
+        let mut flow = Flow::new(area, 10, true);
+        flow.push_line(Line::fmt_raw("张三走了过来。"));
+        let rows: Vec<String> = flow.visible_rows().map(|l| l.plain_text()).collect();
+        assert_eq!(vec!["张三走", "了过来", "。"], rows);
+    }
+}
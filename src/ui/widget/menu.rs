@@ -0,0 +1,159 @@
+use crate::error::Result;
+use crate::ui::buffer::Buffer;
+use crate::ui::layout::Rect;
+use crate::ui::style::Modifier;
+use crate::ui::theme::{Role, Theme};
+use crate::ui::widget::{Block, Border, Widget};
+use crate::ui::width::AppendWidthTab8;
+use termion::event::Key;
+
+/// 菜单处理按键的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    Pending,
+    // 选中的序号，从0开始
+    Chosen(usize),
+    Cancel,
+}
+
+/// 居中弹出的编号菜单，数字键直接选择，或用方向键移动后回车选择，Esc取消
+#[derive(Debug, Clone)]
+pub struct Menu {
+    title: String,
+    items: Vec<String>,
+    selected: usize,
+    block: Block,
+    cjk: bool,
+}
+
+impl Menu {
+    pub fn new(title: impl Into<String>, items: Vec<String>, cjk: bool) -> Self {
+        Self {
+            title: title.into(),
+            items,
+            selected: 0,
+            block: Block::default().cjk(cjk),
+            cjk,
+        }
+    }
+
+    pub fn with_border(mut self, border: Border) -> Self {
+        self.block = self.block.border(border);
+        self
+    }
+
+    pub fn set_cjk(&mut self, cjk: bool) {
+        self.cjk = cjk;
+        self.block.cjk = cjk;
+    }
+
+    pub fn on_key(&mut self, key: Key) -> MenuAction {
+        match key {
+            Key::Char(c @ '1'..='9') => {
+                let idx = c as usize - '1' as usize;
+                if idx < self.items.len() {
+                    return MenuAction::Chosen(idx);
+                }
+            }
+            Key::Up if self.selected > 0 => self.selected -= 1,
+            Key::Down if self.selected + 1 < self.items.len() => self.selected += 1,
+            Key::Char('\n') if !self.items.is_empty() => return MenuAction::Chosen(self.selected),
+            Key::Esc => return MenuAction::Cancel,
+            _ => (),
+        }
+        MenuAction::Pending
+    }
+
+    fn item_text(&self, idx: usize) -> String {
+        format!("{}. {}", idx + 1, self.items[idx])
+    }
+
+    /// 在给定区域中居中显示所需的区域，超出时截断
+    pub fn area(&self, within: Rect) -> Rect {
+        let content = (0..self.items.len())
+            .map(|i| self.item_text(i).append_width(0, self.cjk))
+            .chain(std::iter::once(self.title.append_width(0, self.cjk)))
+            .max()
+            .unwrap_or(0) as u16;
+        // 两侧边框及各一列留白
+        let sw = self.block.symbol_width();
+        let mut width = content + 2 * sw + 2;
+        // 双宽边框时保持偶数宽度，避免舍弃最后一列
+        if sw == 2 && width & 1 == 1 {
+            width += 1;
+        }
+        let width = width.min(within.width);
+        // 上下边框及标题行
+        let height = (self.items.len() as u16 + 3).min(within.height);
+        Rect {
+            x: within.x + (within.width - width) / 2,
+            y: within.y + (within.height - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+impl Widget for Menu {
+    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B, theme: &Theme) -> Result<()> {
+        let area = *buf.area();
+        if area.height < 3 {
+            return Ok(());
+        }
+        let style = theme.style(Role::Menu);
+        buf.set_style(area, style);
+        self.block.refresh_buffer(buf, theme)?;
+        let inner = self.block.inner_area(area);
+        if inner.width < 2 {
+            return Ok(());
+        }
+        let (left, right) = (inner.left() + 1, inner.right() - 1);
+        buf.set_line_str(left, inner.top(), &self.title, right, style.add_modifier(Modifier::BOLD), self.cjk);
+        // 选中项超出可见行数时向下滚动
+        let rows = (inner.height - 1) as usize;
+        let first = (self.selected + 1).saturating_sub(rows);
+        for (row, idx) in (first..self.items.len()).take(rows).enumerate() {
+            let item_style = if idx == self.selected {
+                style.add_modifier(Modifier::REVERSED)
+            } else {
+                style
+            };
+            let y = inner.top() + 1 + row as u16;
+            buf.set_style(Rect::new(inner.left(), y, inner.width, 1), item_style);
+            buf.set_line_str(left, y, self.item_text(idx), right, item_style, self.cjk);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::buffer::BufferVec;
+
+    #[test]
+    fn test_menu_keys_and_render() {
+        let items = vec!["接受任务".to_owned(), "放弃".to_owned(), "询问详情".to_owned()];
+        let mut menu = Menu::new("选择", items, true);
+        assert_eq!(MenuAction::Chosen(1), menu.on_key(Key::Char('2')));
+        assert_eq!(MenuAction::Pending, menu.on_key(Key::Char('7')));
+        assert_eq!(MenuAction::Pending, menu.on_key(Key::Down));
+        assert_eq!(MenuAction::Pending, menu.on_key(Key::Down));
+        assert_eq!(MenuAction::Pending, menu.on_key(Key::Down));
+        assert_eq!(MenuAction::Chosen(2), menu.on_key(Key::Char('\n')));
+        assert_eq!(MenuAction::Cancel, menu.on_key(Key::Esc));
+
+        // "1. 接受任务"宽11，加边框及留白后补齐为偶数
+        let area = menu.area(Rect::new(1, 1, 80, 20));
+        assert_eq!(Rect::new(32, 8, 18, 6), area);
+        let mut buf = BufferVec::empty(area);
+        menu.refresh_buffer(&mut buf, &Theme::default()).unwrap();
+        let row: String = (area.left()..area.right())
+            .map(|x| buf.get(x, area.top() + 4))
+            .filter(|c| c.symbol.exists)
+            .map(|c| c.symbol.ch)
+            .collect();
+        assert_eq!("3. 询问详情", row.trim_matches(|c: char| c == ' ' || c == '│'));
+        assert!(buf.get(area.left() + 3, area.top() + 4).modifier.contains(Modifier::REVERSED));
+    }
+}
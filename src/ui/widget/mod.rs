@@ -1,14 +1,18 @@
 pub mod block;
 pub mod cmdbar;
 pub mod flow;
+pub mod menu;
 
 use crate::error::Result;
 use crate::ui::buffer::Buffer;
+use crate::ui::theme::Theme;
 
 pub use block::*;
 pub use cmdbar::*;
 pub use flow::*;
+pub use menu::*;
 
 pub trait Widget {
-    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B) -> Result<()>;
+    /// 刷新缓存，样式从主题中解析
+    fn refresh_buffer<B: Buffer>(&mut self, buf: &mut B, theme: &Theme) -> Result<()>;
 }
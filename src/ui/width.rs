@@ -48,7 +48,7 @@ impl AppendWidthTab8 for str {
                 w + if cjk {
                     c.width_cjk().unwrap_or(0)
                 } else {
-                    c.width_cjk().unwrap_or(0)
+                    c.width().unwrap_or(0)
                 }
             }
         })
@@ -111,5 +111,9 @@ mod tests {
             Span::new("\tworld", Style::default(), Label::None),
         ]);
         assert_eq!(13, s.append_width(0, true));
+        // 歧义宽度字符仅在cjk模式下占两列
+        let s = "·±中";
+        assert_eq!(6, s.append_width(0, true));
+        assert_eq!(4, s.append_width(0, false));
     }
 }
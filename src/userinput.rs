@@ -2,12 +2,18 @@ use crate::error::Result;
 use crate::event::Event;
 use crate::ui::UIEvent;
 use crossbeam_channel::Sender;
-use std::io;
+use encoding::codec::simpchinese::GB18030_ENCODING;
+use encoding::codec::tradchinese::BigFive2003Encoding;
+use encoding::{DecoderTrap, EncoderTrap, Encoding};
+use std::io::{self, Read};
 use termion::event::Event as TEvent;
 use termion::input::TermRead;
 
 pub fn subscribe_userinput(tx: Sender<Event>) -> Result<()> {
-    let stdin = io::stdin();
+    let choicetx = tx.clone();
+    let stdin = Utf8Reader::new(io::stdin()).on_ambiguous(move |choices| {
+        let _ = choicetx.send(Event::PasteChoice(choices));
+    });
     for evt in stdin.events() {
         match evt? {
             TEvent::Key(key) => {
@@ -23,7 +29,10 @@ pub fn subscribe_userinput(tx: Sender<Event>) -> Result<()> {
 }
 
 pub fn subscribe_userinput_for_ui(tx: Sender<UIEvent>) -> Result<()> {
-    let stdin = io::stdin();
+    let choicetx = tx.clone();
+    let stdin = Utf8Reader::new(io::stdin()).on_ambiguous(move |choices| {
+        let _ = choicetx.send(UIEvent::PasteChoice(choices));
+    });
     for evt in stdin.events() {
         match evt? {
             TEvent::Key(key) => {
@@ -37,3 +46,287 @@ pub fn subscribe_userinput_for_ui(tx: Sender<UIEvent>) -> Result<()> {
     }
     Ok(())
 }
+
+// U+FFFD的UTF-8编码
+const REPLACEMENT: &[u8] = "\u{fffd}".as_bytes();
+// 括号粘贴模式的起止标记
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// 无法确定编码的粘贴内容，按编码名称列出各候选文本
+pub type PasteChoices = Vec<(&'static str, String)>;
+
+/// 粘贴内容的解码结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paste {
+    Text(String),
+    Ambiguous(PasteChoices),
+}
+
+/// 解码粘贴的字节，合法UTF-8原样使用，否则尝试GBK及Big5
+///
+/// 常用汉字在两种编码中的字节范围大量重叠，仅有一种解码结果全部为常用字时采用该结果，
+/// 否则交由用户选择，均无法解码时替换非法字节
+pub fn decode_paste(bs: &[u8]) -> Paste {
+    if let Ok(s) = std::str::from_utf8(bs) {
+        return Paste::Text(s.to_owned());
+    }
+    let mut choices: PasteChoices = vec![];
+    if let Ok(s) = GB18030_ENCODING.decode(bs, DecoderTrap::Strict) {
+        choices.push(("GBK", s));
+    }
+    if let Ok(s) = BigFive2003Encoding.decode(bs, DecoderTrap::Strict) {
+        choices.push(("Big5", s));
+    }
+    let common: Vec<_> = choices.iter().filter(|(name, s)| is_common(name, s)).collect();
+    if common.len() == 1 {
+        return Paste::Text(common[0].1.to_owned());
+    }
+    match choices.len() {
+        0 => Paste::Text(String::from_utf8_lossy(bs).into_owned()),
+        1 => Paste::Text(choices.pop().unwrap().1),
+        _ => Paste::Ambiguous(choices),
+    }
+}
+
+// 非ASCII字符是否均为该编码的常用字符：GB2312符号及一级汉字，或Big5符号及常用字
+fn is_common(codec: &str, s: &str) -> bool {
+    let mut buf = [0u8; 4];
+    s.chars().filter(|c| !c.is_ascii()).all(|c| {
+        let c: &str = c.encode_utf8(&mut buf);
+        if codec == "GBK" {
+            match GB18030_ENCODING.encode(c, EncoderTrap::Strict).as_deref() {
+                Ok([lead, trail]) => {
+                    (0xa1..=0xa9).contains(lead) || (0xb0..=0xd7).contains(lead) && *trail >= 0xa1
+                }
+                _ => false,
+            }
+        } else {
+            match BigFive2003Encoding.encode(c, EncoderTrap::Strict).as_deref() {
+                Ok([lead, trail]) => (0xa140..=0xc67e).contains(&u16::from_be_bytes([*lead, *trail])),
+                _ => false,
+            }
+        }
+    })
+}
+
+/// 输入的UTF-8校验
+///
+/// 部分终端下输入法提交的多字节字符会被拆分到多次读取中，
+/// 不完整的字节序列将暂存至后续字节到达，非法字节替换为U+FFFD，
+/// 保证下游解析时不会因字节序列中断而出错。
+/// 括号粘贴模式下的内容整体解码，非UTF-8的GBK或Big5文本转换后输出
+pub struct Utf8Reader<R> {
+    inner: R,
+    // 已校验、待输出的字节
+    ready: Vec<u8>,
+    // 不完整的字节序列
+    pending: Vec<u8>,
+    // 正在粘贴的内容
+    paste: Option<Vec<u8>>,
+    on_ambiguous: Option<Box<dyn FnMut(PasteChoices) + Send>>,
+}
+
+impl<R: Read> Utf8Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            ready: Vec::new(),
+            pending: Vec::new(),
+            paste: None,
+            on_ambiguous: None,
+        }
+    }
+
+    /// 粘贴内容编码无法确定时的回调，未设置时按GBK解码
+    pub fn on_ambiguous(mut self, f: impl FnMut(PasteChoices) + Send + 'static) -> Self {
+        self.on_ambiguous = Some(Box::new(f));
+        self
+    }
+
+    // 分离粘贴内容，其余字节进行UTF-8校验
+    fn process(&mut self) {
+        loop {
+            match self.paste.as_mut() {
+                Some(paste) => match find(&self.pending, PASTE_END) {
+                    Some(pos) => {
+                        paste.extend_from_slice(&self.pending[..pos]);
+                        self.pending.drain(..pos + PASTE_END.len());
+                        let paste = self.paste.take().unwrap();
+                        self.finish_paste(&paste);
+                    }
+                    None => {
+                        // 保留可能是结束标记一部分的字节
+                        let keep = (PASTE_END.len() - 1).min(self.pending.len());
+                        let n = self.pending.len() - keep;
+                        paste.extend(self.pending.drain(..n));
+                        return;
+                    }
+                },
+                None => match find(&self.pending, PASTE_START) {
+                    Some(pos) => {
+                        let rest = self.pending.split_off(pos);
+                        self.validate();
+                        // 被粘贴打断的不完整序列
+                        if !self.pending.is_empty() {
+                            self.ready.extend_from_slice(REPLACEMENT);
+                        }
+                        self.pending = rest[PASTE_START.len()..].to_vec();
+                        self.paste = Some(Vec::new());
+                    }
+                    None => {
+                        self.validate();
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    fn finish_paste(&mut self, bs: &[u8]) {
+        let text = match decode_paste(bs) {
+            Paste::Text(text) => text,
+            Paste::Ambiguous(mut choices) => match self.on_ambiguous.as_mut() {
+                Some(f) => {
+                    f(choices);
+                    return;
+                }
+                None => choices.remove(0).1,
+            },
+        };
+        self.ready.extend_from_slice(text.as_bytes());
+    }
+
+    fn validate(&mut self) {
+        let mut start = 0;
+        loop {
+            match std::str::from_utf8(&self.pending[start..]) {
+                Ok(s) => {
+                    self.ready.extend_from_slice(s.as_bytes());
+                    self.pending.clear();
+                    return;
+                }
+                Err(e) => {
+                    let valid = start + e.valid_up_to();
+                    self.ready.extend_from_slice(&self.pending[start..valid]);
+                    match e.error_len() {
+                        // 序列不完整，等待后续字节
+                        None => {
+                            self.pending.drain(..valid);
+                            return;
+                        }
+                        Some(len) => {
+                            self.ready.extend_from_slice(REPLACEMENT);
+                            start = valid + len;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Utf8Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0u8; 1024];
+        while self.ready.is_empty() {
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                // 输入结束时丢弃不完整的序列
+                self.pending.clear();
+                return Ok(0);
+            }
+            self.pending.extend_from_slice(&chunk[..n]);
+            self.process();
+        }
+        let n = buf.len().min(self.ready.len());
+        buf[..n].copy_from_slice(&self.ready[..n]);
+        self.ready.drain(..n);
+        Ok(n)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use termion::event::Key;
+
+    // 按指定分块返回数据，模拟多次读取
+    struct ChunkReader(VecDeque<Vec<u8>>);
+
+    impl Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                None => Ok(0),
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+            }
+        }
+    }
+
+    fn keys(chunks: Vec<Vec<u8>>) -> Vec<Key> {
+        let reader = Utf8Reader::new(ChunkReader(chunks.into()));
+        reader.keys().map(|k| k.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_utf8_reader_split_input() {
+        let bs = "中文".as_bytes();
+        let chunks = vec![bs[..1].to_vec(), bs[1..4].to_vec(), bs[4..].to_vec(), b"a".to_vec()];
+        assert_eq!(
+            vec![Key::Char('中'), Key::Char('文'), Key::Char('a')],
+            keys(chunks)
+        );
+        // 非法字节及中断的序列被替换，不影响后续输入
+        let chunks = vec![vec![0xff, b'b'], vec![0xe4, 0xb8], b"\x1b".to_vec()];
+        assert_eq!(
+            vec![Key::Char('\u{fffd}'), Key::Char('b'), Key::Char('\u{fffd}'), Key::Esc],
+            keys(chunks)
+        );
+        // 单独的ESC仍可识别
+        assert_eq!(vec![Key::Esc, Key::Char('c')], keys(vec![b"\x1b".to_vec(), b"c".to_vec()]));
+    }
+
+    #[test]
+    fn test_utf8_reader_paste() {
+        let chars = |s: &str| s.chars().map(Key::Char).collect::<Vec<_>>();
+        // 跨越多次读取的GBK粘贴内容，按Big5解码时不全是常用字
+        let gbk = GB18030_ENCODING.encode("说中文", EncoderTrap::Strict).unwrap();
+        let chunks = vec![
+            b"a\x1b[200~".to_vec(),
+            gbk,
+            b"\x1b[20".to_vec(),
+            b"1~b".to_vec(),
+        ];
+        assert_eq!(chars("a说中文b"), keys(chunks));
+        // UTF-8粘贴内容原样输出
+        let chunks = vec![b"\x1b[200~".to_vec(), "中文".as_bytes().to_vec(), b"\x1b[201~".to_vec()];
+        assert_eq!(chars("中文"), keys(chunks));
+        let big5 = BigFive2003Encoding.encode("門派", EncoderTrap::Strict).unwrap();
+        assert_eq!(Paste::Text("門派".to_owned()), decode_paste(&big5));
+        // 两种编码均为常用字时交由回调选择
+        let bs = GB18030_ENCODING.encode("你好", EncoderTrap::Strict).unwrap();
+        match decode_paste(&bs) {
+            Paste::Ambiguous(choices) => {
+                assert_eq!(("GBK", "你好".to_owned()), choices[0]);
+                assert_eq!("Big5", choices[1].0);
+            }
+            other => panic!("unexpected paste {:?}", other),
+        }
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut chunks = vec![b"\x1b[200~".to_vec(), bs, b"\x1b[201~x".to_vec()];
+        let reader = Utf8Reader::new(ChunkReader(chunks.drain(..).collect())).on_ambiguous(move |c| {
+            tx.send(c).unwrap();
+        });
+        let ks: Vec<Key> = reader.keys().map(|k| k.unwrap()).collect();
+        assert_eq!(vec![Key::Char('x')], ks);
+        assert_eq!(2, rx.recv().unwrap().len());
+    }
+}
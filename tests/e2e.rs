@@ -0,0 +1,67 @@
+mod fake_mud;
+
+use fake_mud::{gbk, telnet, FakeMud, Session, Step, DO, OPT_ECHO, OPT_MXP, WILL};
+use mudterm::conf::Config;
+
+#[test]
+fn test_e2e_negotiate_and_trigger() {
+    let mut welcome = telnet(WILL, OPT_MXP);
+    welcome.extend(telnet(WILL, OPT_ECHO));
+    welcome.extend(gbk("\x1b[1;33m欢迎来到北大侠客行\x1b[2;37;0m\r\n"));
+    let mud = FakeMud::start(vec![vec![
+        Step::Send(welcome),
+        // 默认接受服务器提出的选项
+        Step::Expect(telnet(DO, OPT_MXP)),
+        Step::Expect(telnet(DO, OPT_ECHO)),
+        Step::Send(gbk("\x1b[1z<H2>客店</H2>\r\n")),
+        Step::Send(gbk("张三走了过来。\r\n")),
+        Step::Expect(b"hi zhangsan\n".to_vec()),
+    ]]);
+    let mut session = Session::connect(&mud.addr(), Config::default());
+    session.script(
+        r#"CreateTrigger("greet", "e2e", "^张三走了过来。$", 0, 1, function() Send("hi zhangsan") end)"#,
+    );
+    session.wait_line("张三走了过来。");
+    mud.join();
+    let lines = session.lines();
+    assert!(lines.contains(&"欢迎来到北大侠客行".to_owned()));
+    // MXP标签不显示
+    assert!(lines.contains(&"客店".to_owned()));
+}
+
+#[test]
+fn test_e2e_switch_codec() {
+    let mud = FakeMud::start(vec![vec![
+        Step::Send(gbk("请选择编码：\r\n")),
+        Step::Expect(b"utf8\n".to_vec()),
+        Step::Send("你好，世界\r\n".as_bytes().to_vec()),
+    ]]);
+    let mut session = Session::connect(&mud.addr(), Config::default());
+    session.wait_line("请选择编码：");
+    session.script(r#"SwitchCodec("utf8") Send("utf8")"#);
+    session.wait_line("你好，世界");
+    mud.join();
+}
+
+#[test]
+fn test_e2e_reconnect() {
+    let mud = FakeMud::start(vec![
+        vec![Step::Send(gbk("你被踢出了游戏。\r\n")), Step::Close],
+        vec![
+            Step::Send(gbk("李四走了过来。\r\n")),
+            Step::Expect(b"hi lisi\n".to_vec()),
+        ],
+    ]);
+    let mut session = Session::connect(&mud.addr(), Config::default());
+    session.script(
+        r#"CreateTrigger("greet", "e2e", "^李四走了过来。$", 0, 1, function() Send("hi lisi") end)"#,
+    );
+    session.wait_disconnected();
+    assert!(session.lines().contains(&"你被踢出了游戏。".to_owned()));
+    // 重新连接后触发器仍然有效
+    session.reconnect(&mud.addr());
+    session.wait_line("李四走了过来。");
+    let received = mud.join();
+    assert_eq!(2, received.len());
+    assert!(received[0].is_empty());
+}
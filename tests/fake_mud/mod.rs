@@ -0,0 +1,237 @@
+//! 端到端测试使用的模拟MUD服务器
+//!
+//! 服务器按脚本依次向客户端发送字节序列（ANSI、MXP、Telnet协商、GBK文本等），
+//! 并等待客户端发送指定内容，客户端一侧使用真实的Telnet线程及运行时处理
+#![allow(dead_code)]
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use encoding::{EncoderTrap, Encoding};
+use mudterm::app::server::{connect_world, start_from_mud_handle, start_to_mud_handle};
+use mudterm::conf::Config;
+use mudterm::event::Event;
+use mudterm::runtime::{Engine, EngineAction, RuntimeOutput};
+use mudterm::ui::UserOutput;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const IAC: u8 = 255;
+pub const WILL: u8 = 251;
+pub const WONT: u8 = 252;
+pub const DO: u8 = 253;
+pub const DONT: u8 = 254;
+pub const OPT_ECHO: u8 = 1;
+pub const OPT_MXP: u8 = 91;
+
+// 等待对端数据的最长时间
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 服务器脚本中的单个步骤
+#[derive(Debug, Clone)]
+pub enum Step {
+    // 向客户端发送字节
+    Send(Vec<u8>),
+    // 等待客户端发送的数据中出现指定字节，超时则测试失败
+    Expect(Vec<u8>),
+    // 关闭当前连接
+    Close,
+}
+
+/// 以GBK编码文本
+pub fn gbk(s: &str) -> Vec<u8> {
+    encoding::all::GBK.encode(s, EncoderTrap::Strict).unwrap()
+}
+
+/// Telnet协商命令，如IAC WILL MXP
+pub fn telnet(op: u8, opt: u8) -> Vec<u8> {
+    vec![IAC, op, opt]
+}
+
+/// 模拟服务器，每个会话脚本对应一次客户端连接
+pub struct FakeMud {
+    addr: SocketAddr,
+    handle: JoinHandle<Vec<Vec<u8>>>,
+}
+
+impl FakeMud {
+    pub fn start(sessions: Vec<Vec<Step>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            sessions
+                .into_iter()
+                .map(|steps| {
+                    let (stream, _) = listener.accept().unwrap();
+                    run_session(stream, steps)
+                })
+                .collect()
+        });
+        Self { addr, handle }
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// 等待所有会话结束，返回每个会话中客户端发送的全部数据
+    pub fn join(self) -> Vec<Vec<u8>> {
+        self.handle.join().expect("fake mud script failed")
+    }
+}
+
+fn run_session(mut stream: TcpStream, steps: Vec<Step>) -> Vec<u8> {
+    stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    let mut received = vec![];
+    // 已被Expect消费的数据长度
+    let mut consumed = 0;
+    for step in steps {
+        match step {
+            Step::Send(bs) => stream.write_all(&bs).unwrap(),
+            Step::Expect(bs) => {
+                let deadline = Instant::now() + WAIT_TIMEOUT;
+                loop {
+                    if let Some(pos) = find(&received[consumed..], &bs) {
+                        consumed += pos + bs.len();
+                        break;
+                    }
+                    assert!(
+                        Instant::now() < deadline,
+                        "expected {:?}, received {:?}",
+                        String::from_utf8_lossy(&bs),
+                        String::from_utf8_lossy(&received)
+                    );
+                    read_some(&mut stream, &mut received);
+                }
+            }
+            Step::Close => {
+                let _ = stream.shutdown(Shutdown::Both);
+                return received;
+            }
+        }
+    }
+    received
+}
+
+fn read_some(stream: &mut TcpStream, received: &mut Vec<u8>) {
+    let mut buf = [0u8; 1024];
+    match stream.read(&mut buf) {
+        Ok(n) => received.extend_from_slice(&buf[..n]),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => (),
+        Err(e) => panic!("fake mud read error {}", e),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// 连接模拟服务器的客户端，与standalone模式相同地将事件交给运行时处理
+pub struct Session {
+    config: Config,
+    engine: Engine,
+    // 保留发送端，连接线程退出后通道不会关闭
+    evttx: Sender<Event>,
+    evtrx: Receiver<Event>,
+    worldtx: Sender<Vec<u8>>,
+    // 输出到界面的文本
+    lines: Vec<String>,
+    disconnected: bool,
+}
+
+impl Session {
+    pub fn connect(addr: &str, config: Config) -> Self {
+        let mut engine = Engine::new(&config);
+        engine.init().unwrap();
+        let (evttx, evtrx) = unbounded();
+        let worldtx = open_world(addr, &config, evttx.clone());
+        Self {
+            config,
+            engine,
+            evttx,
+            evtrx,
+            worldtx,
+            lines: vec![],
+            disconnected: false,
+        }
+    }
+
+    /// 断开后重新连接，保留运行时状态
+    pub fn reconnect(&mut self, addr: &str) {
+        let (evttx, evtrx) = unbounded();
+        self.worldtx = open_world(addr, &self.config, evttx.clone());
+        self.evttx = evttx;
+        self.evtrx = evtrx;
+        self.disconnected = false;
+    }
+
+    /// 执行脚本，如创建触发器或切换编码
+    pub fn script(&mut self, script: &str) {
+        self.engine
+            .push(EngineAction::ExecuteUserOutput(UserOutput::Script(script.to_owned())));
+        self.flush();
+    }
+
+    /// 处理事件直到满足条件，超时则测试失败
+    pub fn wait_until(&mut self, mut cond: impl FnMut(&Session) -> bool) {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        while !cond(self) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let evt = self.evtrx.recv_timeout(timeout).unwrap_or_else(|_| {
+                panic!("condition not met, lines {:?}", self.lines);
+            });
+            self.on_event(evt);
+            self.flush();
+        }
+    }
+
+    /// 等待界面输出包含指定文本的行
+    pub fn wait_line(&mut self, text: &str) {
+        self.wait_until(|s| s.lines.iter().any(|l| l.contains(text)));
+    }
+
+    pub fn wait_disconnected(&mut self) {
+        self.wait_until(|s| s.disconnected);
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    fn on_event(&mut self, evt: Event) {
+        match evt {
+            Event::TelnetBytes(bs) => self.worldtx.send(bs).unwrap(),
+            Event::WorldBytes(_, bs) => self.engine.push(EngineAction::ParseWorldBytes(bs)),
+            Event::WorldProtocols(protocols) => {
+                self.engine.push(EngineAction::UpdateProtocols(protocols))
+            }
+            Event::WorldGmcp(bs) => self.engine.push(EngineAction::ReceiveGmcp(bs)),
+            Event::WorldMsdp(bs) => self.engine.push(EngineAction::ReceiveMsdp(bs)),
+            Event::WorldProbe(report) => self.engine.push(EngineAction::UpdateProbe(report)),
+            Event::WorldDisconnected(_) => self.disconnected = true,
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    fn flush(&mut self) {
+        for output in self.engine.apply() {
+            match output {
+                RuntimeOutput::ToServer(bs) => self.worldtx.send(bs).unwrap(),
+                RuntimeOutput::ToUI(_, lines, _) => {
+                    self.lines
+                        .extend(lines.into_vec().iter().map(|l| l.plain_text()));
+                }
+                // 其余输出仅与界面相关，新增的输出无需修改测试
+                _ => (),
+            }
+        }
+    }
+}
+
+fn open_world(addr: &str, config: &Config, evttx: Sender<Event>) -> Sender<Vec<u8>> {
+    let from_mud = connect_world(addr, Duration::from_secs(3)).unwrap();
+    let to_mud = from_mud.try_clone().unwrap();
+    start_from_mud_handle(evttx.clone(), from_mud, &config.protocol, 0);
+    start_to_mud_handle(evttx, to_mud, 0)
+}